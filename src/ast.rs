@@ -5,36 +5,42 @@ pub trait Locatable {
     fn loc(&self) -> &LoLocation;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AST {
     pub exprs: Vec<TopLevelExpr>,
     pub comments: Vec<Comment>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TopLevelExpr {
     FnDef(FnDefExpr),
     Include(IncludeExpr),
     Import(ImportExpr),
     GlobalDef(GlobalDefExpr),
     StructDef(StructDefExpr),
+    UnionDef(StructDefExpr),
     TypeDef(TypeDefExpr),
     ConstDef(ConstDefExpr),
+    ConstGroup(ConstGroupExpr),
+    FlagsDef(ConstGroupExpr),
     MemoryDef(MemoryDefExpr),
+    TableDef(TableDefExpr),
     StaticDataStore(StaticDataStoreExpr),
     ExportExistingFn(ExportExistingFnExpr),
     MacroDef(MacroDefExpr),
+    CustomSection(CustomSectionExpr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FnDefExpr {
     pub exported: bool,
     pub decl: FnDeclExpr,
     pub body: CodeBlockExpr,
+    pub doc: Option<String>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FnDeclExpr {
     pub fn_name: IdentExpr,
     pub fn_params: Vec<FnParam>,
@@ -42,17 +48,17 @@ pub struct FnDeclExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FnParam {
     pub param_name: String,
     pub param_type: FnParamType,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FnParamType {
     Self_,
-    SelfRef,
+    SelfRef { is_readonly: bool },
     Type { expr: TypeExpr },
 }
 
@@ -62,17 +68,18 @@ pub struct IncludeExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ImportExpr {
     pub module_name: String,
     pub items: Vec<ImportItem>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ImportItem {
     FnDecl(FnDeclExpr),
     Memory(MemoryDefExpr),
+    Table(TableImportExpr),
 }
 
 impl Locatable for ImportItem {
@@ -80,73 +87,134 @@ impl Locatable for ImportItem {
         match self {
             ImportItem::FnDecl(e) => &e.loc,
             ImportItem::Memory(e) => &e.loc,
+            ImportItem::Table(e) => &e.loc,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GlobalDefExpr {
     pub global_name: IdentExpr,
     pub expr: CodeExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructDefExpr {
+    pub attributes: Vec<AttributeExpr>,
     pub struct_name: IdentExpr,
     pub fields: Vec<StructDefField>,
+    pub doc: Option<String>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+// `@name` or `@name(arg, ...)`, args are kept as written
+#[derive(Debug, Clone)]
+pub struct AttributeExpr {
+    pub name: String,
+    pub args: Vec<String>,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
 pub struct StructDefField {
+    pub is_private: bool,
     pub field_name: String,
     pub field_type: TypeExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TypeDefExpr {
     pub type_name: IdentExpr,
+    pub is_distinct: bool,
     pub type_value: TypeExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConstDefExpr {
     pub const_name: IdentExpr,
     pub const_value: CodeExpr,
+    pub doc: Option<String>,
+    pub loc: LoLocation,
+}
+
+// `const group`, `const flags` or a `flags` type, values of members are optional
+#[derive(Debug, Clone)]
+pub struct ConstGroupExpr {
+    pub is_flags: bool,
+    pub group_name: IdentExpr,
+    pub members: Vec<ConstGroupMember>,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstGroupMember {
+    pub member_name: String,
+    pub value: Option<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MemoryDefExpr {
     pub exported: bool,
     pub min_pages: Option<u32>,
+    pub max_pages: Option<u32>,
     pub data_start: Option<u32>,
+    pub shared: bool,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct TableDefExpr {
+    pub exported: bool,
+    pub table_name: String,
+    pub fn_names: Vec<IdentExpr>,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableImportExpr {
+    pub table_name: String,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
 pub struct StaticDataStoreExpr {
     pub addr: CodeExpr,
     pub data: StaticDataStorePayload,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum StaticDataStorePayload {
     String { value: String },
+    Bytes { value: CodeExpr },
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomSectionExpr {
+    pub section_name: String,
+    pub data: CustomSectionPayload,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
+pub enum CustomSectionPayload {
+    String { value: String },
+    Embed { file_path: String },
+    Bytes { value: CodeExpr },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ExportExistingFnExpr {
     pub in_fn_name: IdentExpr,
     pub out_fn_name: String,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MacroDefExpr {
     pub macro_name: IdentExpr,
     pub macro_params: Vec<FnParam>,
@@ -156,7 +224,7 @@ pub struct MacroDefExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CodeBlockExpr {
     pub exprs: Vec<CodeExpr>,
     pub loc: LoLocation,
@@ -170,38 +238,56 @@ impl Locatable for TopLevelExpr {
             TopLevelExpr::Import(e) => &e.loc,
             TopLevelExpr::GlobalDef(e) => &e.loc,
             TopLevelExpr::StructDef(e) => &e.loc,
+            TopLevelExpr::UnionDef(e) => &e.loc,
             TopLevelExpr::TypeDef(e) => &e.loc,
             TopLevelExpr::ConstDef(e) => &e.loc,
+            TopLevelExpr::ConstGroup(e) => &e.loc,
+            TopLevelExpr::FlagsDef(e) => &e.loc,
             TopLevelExpr::MemoryDef(e) => &e.loc,
+            TopLevelExpr::TableDef(e) => &e.loc,
             TopLevelExpr::StaticDataStore(e) => &e.loc,
             TopLevelExpr::ExportExistingFn(e) => &e.loc,
             TopLevelExpr::MacroDef(e) => &e.loc,
+            TopLevelExpr::CustomSection(e) => &e.loc,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TypeExpr {
     Named {
         name: IdentExpr,
     },
     Pointer {
         pointee: Box<TypeExpr>,
+        is_readonly: bool,
     },
     SequencePointer {
         pointee: Box<TypeExpr>,
     },
+    Slice {
+        item_type: Box<TypeExpr>,
+    },
     Result {
         ok_type: Box<TypeExpr>,
         err_type: Box<TypeExpr>,
     },
+    // `E1 | E2` error type of a `Result`
+    ErrorUnion {
+        variants: Vec<TypeExpr>,
+    },
     Of {
         container_type: Box<TypeExpr>,
         item_type: Box<TypeExpr>,
     },
+    // inline `struct { ... }` field type
+    Struct {
+        fields: Vec<StructDefField>,
+        loc: LoLocation,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CodeExpr {
     // literals
     BoolLiteral(BoolLiteralExpr),
@@ -210,6 +296,7 @@ pub enum CodeExpr {
     StringLiteral(StringLiteralExpr),
     StructLiteral(StructLiteralExpr),
     ArrayLiteral(ArrayLiteralExpr),
+    ArrayRepeat(ArrayRepeatExpr),
 
     // variables
     Ident(IdentExpr),
@@ -221,14 +308,18 @@ pub enum CodeExpr {
     Cast(CastExpr),
     Assign(AssignExpr),
     FieldAccess(FieldAccessExpr),
+    Index(IndexExpr),
     PropagateError(PropagateErrorExpr),
     FnCall(FnCallExpr),
     MethodCall(MethodCallExpr),
     MacroFnCall(MacroFnCallExpr),
     MacroMethodCall(MacroMethodCallExpr),
+    MacroExpansion(MacroExpansionExpr),
     Dbg(DbgExpr),
     Sizeof(SizeofExpr),
     GetDataSize(GetDataSizeExpr),
+    Reflection(ReflectionExpr),
+    Bitcast(BitcastExpr),
 
     // control flow
     Return(ReturnExpr),
@@ -243,20 +334,20 @@ pub enum CodeExpr {
     Paren(ParenExpr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BoolLiteralExpr {
     pub value: bool,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CharLiteralExpr {
     pub repr: String,
     pub value: u32,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IntLiteralExpr {
     pub repr: String,
     pub value: u32,
@@ -264,7 +355,7 @@ pub struct IntLiteralExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StringLiteralExpr {
     pub repr: String,
     pub value: String,
@@ -272,20 +363,20 @@ pub struct StringLiteralExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ReturnExpr {
     pub expr: Option<Box<CodeExpr>>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IdentExpr {
     pub repr: String,
     pub parts: Vec<String>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InfixOpExpr {
     pub op_tag: InfixOpTag,
     pub lhs: Box<CodeExpr>,
@@ -293,14 +384,14 @@ pub struct InfixOpExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PrefixOpExpr {
     pub op_tag: PrefixOpTag,
     pub expr: Box<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IfExpr {
     pub cond: Box<CodeExpr>,
     pub then_block: Box<CodeBlockExpr>,
@@ -308,37 +399,37 @@ pub struct IfExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ElseBlock {
     None,
     Else(Box<CodeBlockExpr>),
     ElseIf(Box<CodeExpr>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LetExpr {
     pub local_name: String,
     pub value: Box<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LoopExpr {
     pub body: Box<CodeBlockExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BreakExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnreachableExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ForLoopExpr {
     pub counter: String,
     pub start: Box<CodeExpr>,
@@ -347,93 +438,115 @@ pub struct ForLoopExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ContinueExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DbgExpr {
     pub message: String,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DeferExpr {
+    pub captures: Vec<IdentExpr>, // copied when the defer is reached, see `defer with`
     pub expr: Box<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CastExpr {
     pub expr: Box<CodeExpr>,
     pub casted_to: TypeExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StructLiteralExpr {
     pub struct_name: IdentExpr,
     pub fields: Vec<StructLiteralField>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ArrayLiteralExpr {
     pub item_type: TypeExpr,
     pub items: Vec<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct ArrayRepeatExpr {
+    pub item: Box<CodeExpr>,
+    pub count: Box<CodeExpr>,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
 pub struct StructLiteralField {
     pub field_name: String,
     pub value: CodeExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AssignExpr {
     pub lhs: Box<CodeExpr>,
     pub rhs: Box<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FieldAccessExpr {
     pub lhs: Box<CodeExpr>,
     pub field_name: IdentExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct IndexExpr {
+    pub lhs: Box<CodeExpr>,
+    pub index: Box<CodeExpr>,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
+pub enum CatchBind {
+    Ident(String),
+    Fields(Vec<String>), // `catch { code, message } { ... }` destructures a struct error
+}
+
+#[derive(Debug, Clone)]
 pub struct CatchExpr {
     pub lhs: Box<CodeExpr>,
-    pub error_bind: String,
+    pub error_bind: CatchBind,
+    pub error_type: Option<TypeExpr>, // `catch err: E { ... }` handles one error union variant
     pub catch_body: CodeBlockExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PropagateErrorExpr {
     pub expr: Box<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParenExpr {
     pub expr: Box<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FnCallExpr {
     pub fn_name: IdentExpr,
     pub args: Vec<CodeExpr>,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MethodCallExpr {
     pub lhs: Box<CodeExpr>,
     pub field_name: IdentExpr,
@@ -441,7 +554,7 @@ pub struct MethodCallExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MacroFnCallExpr {
     pub fn_name: IdentExpr,
     pub type_args: Vec<TypeExpr>,
@@ -449,7 +562,7 @@ pub struct MacroFnCallExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MacroMethodCallExpr {
     pub lhs: Box<CodeExpr>,
     pub field_name: IdentExpr,
@@ -458,17 +571,44 @@ pub struct MacroMethodCallExpr {
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+// only produced by `--expand-macros`, `call` is the original macro call
+#[derive(Debug, Clone)]
+pub struct MacroExpansionExpr {
+    pub call: Box<CodeExpr>,
+    pub macro_loc: LoLocation,
+    pub body: CodeBlockExpr,
+    pub loc: LoLocation,
+}
+
+#[derive(Debug, Clone)]
 pub struct SizeofExpr {
     pub type_expr: TypeExpr,
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GetDataSizeExpr {
     pub loc: LoLocation,
 }
 
+// `@type_name(T)`, `@field_count(T)`, `@field_name(T, i)`, `@field_offset(T, i)`
+#[derive(Debug, Clone)]
+pub struct ReflectionExpr {
+    pub builtin: String,
+    pub type_expr: TypeExpr,
+    pub index: Option<Box<CodeExpr>>,
+    pub loc: LoLocation,
+}
+
+// `__bitcast<T, U>(value)`
+#[derive(Debug, Clone)]
+pub struct BitcastExpr {
+    pub from_type: TypeExpr,
+    pub to_type: TypeExpr,
+    pub expr: Box<CodeExpr>,
+    pub loc: LoLocation,
+}
+
 impl Locatable for CodeExpr {
     fn loc(&self) -> &LoLocation {
         match self {
@@ -477,6 +617,7 @@ impl Locatable for CodeExpr {
             CodeExpr::IntLiteral(e) => &e.loc,
             CodeExpr::StringLiteral(e) => &e.loc,
             CodeExpr::ArrayLiteral(e) => &e.loc,
+            CodeExpr::ArrayRepeat(e) => &e.loc,
             CodeExpr::Return(e) => &e.loc,
             CodeExpr::Ident(e) => &e.loc,
             CodeExpr::InfixOp(e) => &e.loc,
@@ -492,16 +633,20 @@ impl Locatable for CodeExpr {
             CodeExpr::StructLiteral(e) => &e.loc,
             CodeExpr::Assign(e) => &e.loc,
             CodeExpr::FieldAccess(e) => &e.loc,
+            CodeExpr::Index(e) => &e.loc,
             CodeExpr::Catch(e) => &e.loc,
             CodeExpr::Paren(e) => &e.loc,
             CodeExpr::FnCall(e) => &e.loc,
             CodeExpr::MethodCall(e) => &e.loc,
             CodeExpr::MacroFnCall(e) => &e.loc,
             CodeExpr::MacroMethodCall(e) => &e.loc,
+            CodeExpr::MacroExpansion(e) => &e.loc,
             CodeExpr::Sizeof(e) => &e.loc,
             CodeExpr::PropagateError(e) => &e.loc,
             CodeExpr::PrefixOp(e) => &e.loc,
             CodeExpr::GetDataSize(e) => &e.loc,
+            CodeExpr::Reflection(e) => &e.loc,
+            CodeExpr::Bitcast(e) => &e.loc,
             CodeExpr::Unreachable(e) => &e.loc,
         }
     }
@@ -0,0 +1,682 @@
+use crate::{ast::*, core::*, lexer::Comment, source_map::write_json_string};
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+// enum nodes carry their variant name in `kind`, struct fields keep their names,
+//   every node has a `loc` with byte offsets and 1-based line/col positions
+pub fn ast_to_json(ast: &AST) -> String {
+    let json = Json::Object(vec![
+        ("exprs", list(&ast.exprs, top_level_expr)),
+        ("comments", list(&ast.comments, comment)),
+    ]);
+
+    let mut out = String::new();
+    json.write(&mut out);
+    out.push('\n');
+    out
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => write!(out, "{value}").unwrap(),
+            Json::Number(value) => write!(out, "{value}").unwrap(),
+            Json::String(value) => write_json_string(out, value),
+            Json::Array(items) => {
+                out.push('[');
+                for (item, i) in items.iter().zip(0..) {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for ((key, value), i) in fields.iter().zip(0..) {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    write_json_string(out, key);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn node(kind: &str, loc: &LoLocation, mut fields: Vec<(&'static str, Json)>) -> Json {
+    fields.insert(0, ("kind", string(kind)));
+    fields.push(("loc", location(loc)));
+    Json::Object(fields)
+}
+
+fn object(loc: &LoLocation, mut fields: Vec<(&'static str, Json)>) -> Json {
+    fields.push(("loc", location(loc)));
+    Json::Object(fields)
+}
+
+fn location(loc: &LoLocation) -> Json {
+    let pos = loc.pos();
+    let end_pos = loc.end_pos();
+
+    Json::Object(vec![
+        ("file", string(loc.file_name().as_str())),
+        ("offset", Json::Number(loc.offset as u64)),
+        ("end_offset", Json::Number(loc.end_offset as u64)),
+        ("line", Json::Number(pos.line as u64)),
+        ("col", Json::Number(pos.col as u64)),
+        ("end_line", Json::Number(end_pos.line as u64)),
+        ("end_col", Json::Number(end_pos.col as u64)),
+    ])
+}
+
+fn string(value: &str) -> Json {
+    Json::String(String::from(value))
+}
+
+fn number(value: u32) -> Json {
+    Json::Number(value as u64)
+}
+
+fn list<T>(items: &[T], to_json: impl Fn(&T) -> Json) -> Json {
+    Json::Array(items.iter().map(to_json).collect())
+}
+
+fn optional<T>(value: &Option<T>, to_json: impl Fn(&T) -> Json) -> Json {
+    match value {
+        Some(value) => to_json(value),
+        None => Json::Null,
+    }
+}
+
+fn strings(values: &[String]) -> Json {
+    list(values, |value| string(value))
+}
+
+fn comment(comment: &Comment) -> Json {
+    object(
+        &comment.loc,
+        vec![
+            ("content", string(&comment.content)),
+            ("is_doc", Json::Bool(comment.is_doc)),
+        ],
+    )
+}
+
+fn top_level_expr(expr: &TopLevelExpr) -> Json {
+    match expr {
+        TopLevelExpr::FnDef(e) => node(
+            "FnDef",
+            &e.loc,
+            vec![
+                ("exported", Json::Bool(e.exported)),
+                ("decl", fn_decl(&e.decl)),
+                ("body", code_block(&e.body)),
+                ("doc", optional(&e.doc, |doc| string(doc))),
+            ],
+        ),
+        TopLevelExpr::Include(e) => {
+            node("Include", &e.loc, vec![("file_path", string(&e.file_path))])
+        }
+        TopLevelExpr::Import(e) => node(
+            "Import",
+            &e.loc,
+            vec![
+                ("module_name", string(&e.module_name)),
+                ("items", list(&e.items, import_item)),
+            ],
+        ),
+        TopLevelExpr::GlobalDef(e) => node(
+            "GlobalDef",
+            &e.loc,
+            vec![
+                ("global_name", ident(&e.global_name)),
+                ("expr", code_expr(&e.expr)),
+            ],
+        ),
+        TopLevelExpr::StructDef(e) => struct_def("StructDef", e),
+        TopLevelExpr::UnionDef(e) => struct_def("UnionDef", e),
+        TopLevelExpr::TypeDef(e) => node(
+            "TypeDef",
+            &e.loc,
+            vec![
+                ("type_name", ident(&e.type_name)),
+                ("is_distinct", Json::Bool(e.is_distinct)),
+                ("type_value", type_expr(&e.type_value)),
+            ],
+        ),
+        TopLevelExpr::ConstDef(e) => node(
+            "ConstDef",
+            &e.loc,
+            vec![
+                ("const_name", ident(&e.const_name)),
+                ("const_value", code_expr(&e.const_value)),
+                ("doc", optional(&e.doc, |doc| string(doc))),
+            ],
+        ),
+        TopLevelExpr::ConstGroup(e) => const_group("ConstGroup", e),
+        TopLevelExpr::FlagsDef(e) => const_group("FlagsDef", e),
+        TopLevelExpr::MemoryDef(e) => memory_def("MemoryDef", e),
+        TopLevelExpr::TableDef(e) => node(
+            "TableDef",
+            &e.loc,
+            vec![
+                ("exported", Json::Bool(e.exported)),
+                ("table_name", string(&e.table_name)),
+                ("fn_names", list(&e.fn_names, ident)),
+            ],
+        ),
+        TopLevelExpr::StaticDataStore(e) => {
+            let data = match &e.data {
+                StaticDataStorePayload::String { value } => {
+                    Json::Object(vec![("kind", string("String")), ("value", string(value))])
+                }
+                StaticDataStorePayload::Bytes { value } => {
+                    Json::Object(vec![("kind", string("Bytes")), ("value", code_expr(value))])
+                }
+            };
+            node(
+                "StaticDataStore",
+                &e.loc,
+                vec![("addr", code_expr(&e.addr)), ("data", data)],
+            )
+        }
+        TopLevelExpr::ExportExistingFn(e) => node(
+            "ExportExistingFn",
+            &e.loc,
+            vec![
+                ("in_fn_name", ident(&e.in_fn_name)),
+                ("out_fn_name", string(&e.out_fn_name)),
+            ],
+        ),
+        TopLevelExpr::MacroDef(e) => node(
+            "MacroDef",
+            &e.loc,
+            vec![
+                ("macro_name", ident(&e.macro_name)),
+                ("macro_params", list(&e.macro_params, fn_param)),
+                ("macro_type_params", strings(&e.macro_type_params)),
+                ("return_type", optional(&e.return_type, type_expr)),
+                ("body", code_block(&e.body)),
+            ],
+        ),
+        TopLevelExpr::CustomSection(e) => {
+            let data = match &e.data {
+                CustomSectionPayload::String { value } => {
+                    Json::Object(vec![("kind", string("String")), ("value", string(value))])
+                }
+                CustomSectionPayload::Embed { file_path } => Json::Object(vec![
+                    ("kind", string("Embed")),
+                    ("file_path", string(file_path)),
+                ]),
+                CustomSectionPayload::Bytes { value } => {
+                    Json::Object(vec![("kind", string("Bytes")), ("value", code_expr(value))])
+                }
+            };
+            node(
+                "CustomSection",
+                &e.loc,
+                vec![("section_name", string(&e.section_name)), ("data", data)],
+            )
+        }
+    }
+}
+
+fn import_item(item: &ImportItem) -> Json {
+    match item {
+        ImportItem::FnDecl(e) => {
+            let Json::Object(mut fields) = fn_decl(e) else {
+                unreachable!()
+            };
+            fields.insert(0, ("kind", string("FnDecl")));
+            Json::Object(fields)
+        }
+        ImportItem::Memory(e) => memory_def("Memory", e),
+        ImportItem::Table(e) => node("Table", &e.loc, vec![("table_name", string(&e.table_name))]),
+    }
+}
+
+fn fn_decl(decl: &FnDeclExpr) -> Json {
+    object(
+        &decl.loc,
+        vec![
+            ("fn_name", ident(&decl.fn_name)),
+            ("fn_params", list(&decl.fn_params, fn_param)),
+            ("return_type", optional(&decl.return_type, type_expr)),
+        ],
+    )
+}
+
+fn fn_param(param: &FnParam) -> Json {
+    let param_type = match &param.param_type {
+        FnParamType::Self_ => Json::Object(vec![("kind", string("Self"))]),
+        FnParamType::SelfRef { is_readonly } => Json::Object(vec![
+            ("kind", string("SelfRef")),
+            ("is_readonly", Json::Bool(*is_readonly)),
+        ]),
+        FnParamType::Type { expr } => {
+            Json::Object(vec![("kind", string("Type")), ("expr", type_expr(expr))])
+        }
+    };
+
+    object(
+        &param.loc,
+        vec![
+            ("param_name", string(&param.param_name)),
+            ("param_type", param_type),
+        ],
+    )
+}
+
+fn struct_def(kind: &str, e: &StructDefExpr) -> Json {
+    node(
+        kind,
+        &e.loc,
+        vec![
+            ("attributes", list(&e.attributes, attribute)),
+            ("struct_name", ident(&e.struct_name)),
+            ("fields", list(&e.fields, struct_def_field)),
+            ("doc", optional(&e.doc, |doc| string(doc))),
+        ],
+    )
+}
+
+fn attribute(attribute: &AttributeExpr) -> Json {
+    object(
+        &attribute.loc,
+        vec![
+            ("name", string(&attribute.name)),
+            ("args", strings(&attribute.args)),
+        ],
+    )
+}
+
+fn struct_def_field(field: &StructDefField) -> Json {
+    object(
+        &field.loc,
+        vec![
+            ("is_private", Json::Bool(field.is_private)),
+            ("field_name", string(&field.field_name)),
+            ("field_type", type_expr(&field.field_type)),
+        ],
+    )
+}
+
+fn const_group(kind: &str, e: &ConstGroupExpr) -> Json {
+    let member = |member: &ConstGroupMember| {
+        object(
+            &member.loc,
+            vec![
+                ("member_name", string(&member.member_name)),
+                ("value", optional(&member.value, code_expr)),
+            ],
+        )
+    };
+
+    node(
+        kind,
+        &e.loc,
+        vec![
+            ("is_flags", Json::Bool(e.is_flags)),
+            ("group_name", ident(&e.group_name)),
+            ("members", list(&e.members, member)),
+        ],
+    )
+}
+
+fn memory_def(kind: &str, e: &MemoryDefExpr) -> Json {
+    node(
+        kind,
+        &e.loc,
+        vec![
+            ("exported", Json::Bool(e.exported)),
+            ("min_pages", optional(&e.min_pages, |v| number(*v))),
+            ("max_pages", optional(&e.max_pages, |v| number(*v))),
+            ("data_start", optional(&e.data_start, |v| number(*v))),
+            ("shared", Json::Bool(e.shared)),
+        ],
+    )
+}
+
+fn ident(ident: &IdentExpr) -> Json {
+    object(
+        &ident.loc,
+        vec![
+            ("repr", string(&ident.repr)),
+            ("parts", strings(&ident.parts)),
+        ],
+    )
+}
+
+fn code_block(block: &CodeBlockExpr) -> Json {
+    object(&block.loc, vec![("exprs", list(&block.exprs, code_expr))])
+}
+
+fn type_expr(type_expr_: &TypeExpr) -> Json {
+    let (kind, fields) = match type_expr_ {
+        TypeExpr::Named { name } => ("Named", vec![("name", ident(name))]),
+        TypeExpr::Pointer {
+            pointee,
+            is_readonly,
+        } => (
+            "Pointer",
+            vec![
+                ("pointee", type_expr(pointee)),
+                ("is_readonly", Json::Bool(*is_readonly)),
+            ],
+        ),
+        TypeExpr::SequencePointer { pointee } => {
+            ("SequencePointer", vec![("pointee", type_expr(pointee))])
+        }
+        TypeExpr::Slice { item_type } => ("Slice", vec![("item_type", type_expr(item_type))]),
+        TypeExpr::Result { ok_type, err_type } => (
+            "Result",
+            vec![
+                ("ok_type", type_expr(ok_type)),
+                ("err_type", type_expr(err_type)),
+            ],
+        ),
+        TypeExpr::ErrorUnion { variants } => {
+            ("ErrorUnion", vec![("variants", list(variants, type_expr))])
+        }
+        TypeExpr::Of {
+            container_type,
+            item_type,
+        } => (
+            "Of",
+            vec![
+                ("container_type", type_expr(container_type)),
+                ("item_type", type_expr(item_type)),
+            ],
+        ),
+        TypeExpr::Struct { fields, loc } => {
+            return node(
+                "Struct",
+                loc,
+                vec![("fields", list(fields, struct_def_field))],
+            );
+        }
+    };
+
+    // type exprs other than inline structs have no location of their own
+    let mut fields = fields;
+    fields.insert(0, ("kind", string(kind)));
+    Json::Object(fields)
+}
+
+fn code_expr(expr: &CodeExpr) -> Json {
+    match expr {
+        CodeExpr::BoolLiteral(e) => {
+            node("BoolLiteral", &e.loc, vec![("value", Json::Bool(e.value))])
+        }
+        CodeExpr::CharLiteral(e) => node(
+            "CharLiteral",
+            &e.loc,
+            vec![("repr", string(&e.repr)), ("value", number(e.value))],
+        ),
+        CodeExpr::IntLiteral(e) => node(
+            "IntLiteral",
+            &e.loc,
+            vec![
+                ("repr", string(&e.repr)),
+                ("value", number(e.value)),
+                ("tag", optional(&e.tag, |tag| string(tag))),
+            ],
+        ),
+        CodeExpr::StringLiteral(e) => node(
+            "StringLiteral",
+            &e.loc,
+            vec![
+                ("repr", string(&e.repr)),
+                ("value", string(&e.value)),
+                ("zero_terminated", Json::Bool(e.zero_terminated)),
+            ],
+        ),
+        CodeExpr::StructLiteral(e) => {
+            let field = |field: &StructLiteralField| {
+                object(
+                    &field.loc,
+                    vec![
+                        ("field_name", string(&field.field_name)),
+                        ("value", code_expr(&field.value)),
+                    ],
+                )
+            };
+            node(
+                "StructLiteral",
+                &e.loc,
+                vec![
+                    ("struct_name", ident(&e.struct_name)),
+                    ("fields", list(&e.fields, field)),
+                ],
+            )
+        }
+        CodeExpr::ArrayLiteral(e) => node(
+            "ArrayLiteral",
+            &e.loc,
+            vec![
+                ("item_type", type_expr(&e.item_type)),
+                ("items", list(&e.items, code_expr)),
+            ],
+        ),
+        CodeExpr::ArrayRepeat(e) => node(
+            "ArrayRepeat",
+            &e.loc,
+            vec![("item", code_expr(&e.item)), ("count", code_expr(&e.count))],
+        ),
+        CodeExpr::Ident(e) => {
+            let Json::Object(mut fields) = ident(e) else {
+                unreachable!()
+            };
+            fields.insert(0, ("kind", string("Ident")));
+            Json::Object(fields)
+        }
+        CodeExpr::Let(e) => node(
+            "Let",
+            &e.loc,
+            vec![
+                ("local_name", string(&e.local_name)),
+                ("value", code_expr(&e.value)),
+            ],
+        ),
+        CodeExpr::InfixOp(e) => node(
+            "InfixOp",
+            &e.loc,
+            vec![
+                ("op", string(e.op_tag.to_str())),
+                ("lhs", code_expr(&e.lhs)),
+                ("rhs", code_expr(&e.rhs)),
+            ],
+        ),
+        CodeExpr::PrefixOp(e) => node(
+            "PrefixOp",
+            &e.loc,
+            vec![
+                ("op", string(e.op_tag.to_str())),
+                ("expr", code_expr(&e.expr)),
+            ],
+        ),
+        CodeExpr::Cast(e) => node(
+            "Cast",
+            &e.loc,
+            vec![
+                ("expr", code_expr(&e.expr)),
+                ("casted_to", type_expr(&e.casted_to)),
+            ],
+        ),
+        CodeExpr::Assign(e) => node(
+            "Assign",
+            &e.loc,
+            vec![("lhs", code_expr(&e.lhs)), ("rhs", code_expr(&e.rhs))],
+        ),
+        CodeExpr::FieldAccess(e) => node(
+            "FieldAccess",
+            &e.loc,
+            vec![
+                ("lhs", code_expr(&e.lhs)),
+                ("field_name", ident(&e.field_name)),
+            ],
+        ),
+        CodeExpr::Index(e) => node(
+            "Index",
+            &e.loc,
+            vec![("lhs", code_expr(&e.lhs)), ("index", code_expr(&e.index))],
+        ),
+        CodeExpr::PropagateError(e) => {
+            node("PropagateError", &e.loc, vec![("expr", code_expr(&e.expr))])
+        }
+        CodeExpr::FnCall(e) => node(
+            "FnCall",
+            &e.loc,
+            vec![
+                ("fn_name", ident(&e.fn_name)),
+                ("args", list(&e.args, code_expr)),
+            ],
+        ),
+        CodeExpr::MethodCall(e) => node(
+            "MethodCall",
+            &e.loc,
+            vec![
+                ("lhs", code_expr(&e.lhs)),
+                ("field_name", ident(&e.field_name)),
+                ("args", list(&e.args, code_expr)),
+            ],
+        ),
+        CodeExpr::MacroFnCall(e) => node(
+            "MacroFnCall",
+            &e.loc,
+            vec![
+                ("fn_name", ident(&e.fn_name)),
+                ("type_args", list(&e.type_args, type_expr)),
+                ("args", list(&e.args, code_expr)),
+            ],
+        ),
+        CodeExpr::MacroMethodCall(e) => node(
+            "MacroMethodCall",
+            &e.loc,
+            vec![
+                ("lhs", code_expr(&e.lhs)),
+                ("field_name", ident(&e.field_name)),
+                ("type_args", list(&e.type_args, type_expr)),
+                ("args", list(&e.args, code_expr)),
+            ],
+        ),
+        CodeExpr::MacroExpansion(e) => node(
+            "MacroExpansion",
+            &e.loc,
+            vec![
+                ("call", code_expr(&e.call)),
+                ("macro_loc", location(&e.macro_loc)),
+                ("body", code_block(&e.body)),
+            ],
+        ),
+        CodeExpr::Dbg(e) => node("Dbg", &e.loc, vec![("message", string(&e.message))]),
+        CodeExpr::Sizeof(e) => node(
+            "Sizeof",
+            &e.loc,
+            vec![("type_expr", type_expr(&e.type_expr))],
+        ),
+        CodeExpr::GetDataSize(e) => node("GetDataSize", &e.loc, vec![]),
+        CodeExpr::Reflection(e) => node(
+            "Reflection",
+            &e.loc,
+            vec![
+                ("builtin", string(&e.builtin)),
+                ("type_expr", type_expr(&e.type_expr)),
+                ("index", optional(&e.index, |index| code_expr(index))),
+            ],
+        ),
+        CodeExpr::Bitcast(e) => node(
+            "Bitcast",
+            &e.loc,
+            vec![
+                ("from_type", type_expr(&e.from_type)),
+                ("to_type", type_expr(&e.to_type)),
+                ("expr", code_expr(&e.expr)),
+            ],
+        ),
+        CodeExpr::Return(e) => node(
+            "Return",
+            &e.loc,
+            vec![("expr", optional(&e.expr, |expr| code_expr(expr)))],
+        ),
+        CodeExpr::If(e) => {
+            let else_block = match &e.else_block {
+                ElseBlock::None => Json::Null,
+                ElseBlock::Else(block) => {
+                    Json::Object(vec![("kind", string("Else")), ("block", code_block(block))])
+                }
+                ElseBlock::ElseIf(expr) => {
+                    Json::Object(vec![("kind", string("ElseIf")), ("expr", code_expr(expr))])
+                }
+            };
+            node(
+                "If",
+                &e.loc,
+                vec![
+                    ("cond", code_expr(&e.cond)),
+                    ("then_block", code_block(&e.then_block)),
+                    ("else_block", else_block),
+                ],
+            )
+        }
+        CodeExpr::Loop(e) => node("Loop", &e.loc, vec![("body", code_block(&e.body))]),
+        CodeExpr::Break(e) => node("Break", &e.loc, vec![]),
+        CodeExpr::Unreachable(e) => node("Unreachable", &e.loc, vec![]),
+        CodeExpr::ForLoop(e) => node(
+            "ForLoop",
+            &e.loc,
+            vec![
+                ("counter", string(&e.counter)),
+                ("start", code_expr(&e.start)),
+                ("end", code_expr(&e.end)),
+                ("body", code_block(&e.body)),
+            ],
+        ),
+        CodeExpr::Continue(e) => node("Continue", &e.loc, vec![]),
+        CodeExpr::Defer(e) => node(
+            "Defer",
+            &e.loc,
+            vec![
+                ("captures", list(&e.captures, ident)),
+                ("expr", code_expr(&e.expr)),
+            ],
+        ),
+        CodeExpr::Catch(e) => {
+            let error_bind = match &e.error_bind {
+                CatchBind::Ident(name) => {
+                    Json::Object(vec![("kind", string("Ident")), ("name", string(name))])
+                }
+                CatchBind::Fields(fields) => Json::Object(vec![
+                    ("kind", string("Fields")),
+                    ("fields", strings(fields)),
+                ]),
+            };
+            node(
+                "Catch",
+                &e.loc,
+                vec![
+                    ("lhs", code_expr(&e.lhs)),
+                    ("error_bind", error_bind),
+                    ("error_type", optional(&e.error_type, type_expr)),
+                    ("catch_body", code_block(&e.catch_body)),
+                ],
+            )
+        }
+        CodeExpr::Paren(e) => node("Paren", &e.loc, vec![("expr", code_expr(&e.expr))]),
+    }
+}
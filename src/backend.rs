@@ -0,0 +1,87 @@
+use crate::{code_generator::*, core::*, ir_generator::*, ir_printer::*, wasm::*};
+use alloc::{boxed::Box, format, string::String};
+use core::fmt::Write;
+
+// front-end of `--compile-v2` stops at the IR, everything after it is up to the backend
+pub trait Backend {
+    fn generate(
+        &self,
+        scope: LoScope,
+        features: &mut WasmFeatures,
+    ) -> Result<BackendOutput, LoError>;
+}
+
+pub enum BackendOutput {
+    // still optimized, validated and written by the CLI
+    Wasm(Box<WasmModule>),
+    Text(String),
+}
+
+pub const BACKEND_NAMES: &str = "wasm, wasm-gc, ir";
+
+pub fn get_backend(name: &str) -> Result<Box<dyn Backend>, String> {
+    match name {
+        "wasm" => Ok(Box::new(WasmBackend {
+            target: CodeGenTarget::Linear,
+        })),
+        "wasm-gc" => Ok(Box::new(WasmBackend {
+            target: CodeGenTarget::Gc,
+        })),
+        "ir" => Ok(Box::new(IrTextBackend)),
+        _ => Err(format!(
+            "Unknown backend: {name}, expected one of: {BACKEND_NAMES}"
+        )),
+    }
+}
+
+pub struct WasmBackend {
+    pub target: CodeGenTarget,
+}
+
+impl Backend for WasmBackend {
+    fn generate(
+        &self,
+        scope: LoScope,
+        features: &mut WasmFeatures,
+    ) -> Result<BackendOutput, LoError> {
+        if self.target == CodeGenTarget::Gc {
+            features.enable(WasmFeature::Gc);
+        }
+
+        let wasm_module = CodeGenerator::generate(scope, self.target)?;
+        Ok(BackendOutput::Wasm(Box::new(wasm_module)))
+    }
+}
+
+// prints with the `--emit-ir` printer, only `struct` lines are specific to this IR
+pub struct IrTextBackend;
+
+impl Backend for IrTextBackend {
+    fn generate(&self, scope: LoScope, _: &mut WasmFeatures) -> Result<BackendOutput, LoError> {
+        let mut out = String::new();
+
+        for struct_def in &scope.struct_defs {
+            writeln!(&mut out, "struct {} @ {}", struct_def.name, struct_def.loc).unwrap();
+            for field in &struct_def.fields {
+                writeln!(&mut out, "  {}: {}", field.name, field.type_).unwrap();
+            }
+            out.push('\n');
+        }
+
+        for fn_def in &scope.fn_defs {
+            if fn_def.exported {
+                out.push_str("export ");
+            }
+
+            let param_vars = fn_def.body.scope.vars.iter().take(fn_def.inputs.len());
+            let params = param_vars.map(|param| (&param.name, &param.type_));
+            print_ir_fn_header(&mut out, &fn_def.name, params, &fn_def.output, &fn_def.loc);
+            for expr in &fn_def.body.exprs {
+                print_ir_node(&mut out, &(), expr, 1);
+            }
+            out.push('\n');
+        }
+
+        Ok(BackendOutput::Text(out))
+    }
+}
@@ -0,0 +1,530 @@
+use crate::{core::Atom, ir::*, source_map::write_json_string, wasm::*};
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+// generated code uses these names, so params named like them get a `_` suffix
+const RESERVED_NAMES: &[&str] = &[
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    "wasm",
+    "view",
+    "readStr",
+    "writeStr",
+    "LoError",
+    "result",
+    "mem",
+    "err",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BindingsKind {
+    Js,
+    Dts,
+}
+
+impl BindingsKind {
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        match arg {
+            "js" => Ok(BindingsKind::Js),
+            "dts" => Ok(BindingsKind::Dts),
+            _ => Err(format!(
+                "Unknown bindings kind: {arg}, expected `js` or `dts`"
+            )),
+        }
+    }
+}
+
+struct ExportBinding<'a> {
+    name: &'a str,
+    params: Vec<(String, &'a LoType)>,
+    output: &'a LoType,
+    sret: bool,
+}
+
+#[derive(Default)]
+struct JsHelpers {
+    view: bool,
+    read_str: bool,
+    write_str: bool,
+}
+
+pub fn generate_bindings(ctx: &ModuleContext, kind: BindingsKind) -> Result<String, String> {
+    let mut bindings = Vec::new();
+    for fn_export in &ctx.fn_exports {
+        let fn_def = ctx.get_fn_def(&fn_export.in_name).unwrap(); // safe
+
+        let mut params = Vec::new();
+        for param in &fn_def.fn_params {
+            let mut name = param.name.clone();
+            if RESERVED_NAMES.contains(&name.as_str()) {
+                name.push('_');
+            }
+            params.push((name, &param.type_));
+        }
+
+        bindings.push(ExportBinding {
+            name: &fn_export.out_name,
+            params,
+            output: &fn_def.type_.output,
+            sret: fn_def.sret,
+        });
+    }
+
+    match kind {
+        BindingsKind::Js => generate_js(ctx, &bindings),
+        BindingsKind::Dts => generate_dts(ctx, &bindings),
+    }
+}
+
+fn generate_js(ctx: &ModuleContext, bindings: &[ExportBinding]) -> Result<String, String> {
+    let mut helpers = JsHelpers::default();
+    let mut methods = String::new();
+    for binding in bindings {
+        write_js_method(ctx, &mut methods, &mut helpers, binding).map_err(|message| {
+            format!("Cannot generate bindings for `{}`: {message}", binding.name)
+        })?;
+    }
+
+    let uses_memory = helpers.view || helpers.read_str || helpers.write_str;
+    if uses_memory && !has_memory_export(ctx) {
+        return Err(String::from(
+            "Cannot generate bindings: exports pass values through memory, \
+                which is not exported",
+        ));
+    }
+    if helpers.write_str && !has_alloc_export(ctx) {
+        return Err(String::from(
+            "Cannot generate bindings: str params are written to memory \
+                allocated by an exported `alloc(size: u32)` fn, which is missing",
+        ));
+    }
+
+    let mut out = String::new();
+    out.push_str("// generated by `lo --emit-bindings js`\n");
+    out.push_str("export class LoError extends Error {\n");
+    out.push_str("    constructor(code) {\n");
+    out.push_str("        super(`LO error: ${code}`);\n");
+    out.push_str("        this.code = code;\n");
+    out.push_str("    }\n");
+    out.push_str("}\n\n");
+
+    out.push_str("export async function load(source, imports = {}) {\n");
+    out.push_str("    const instantiated = await WebAssembly.instantiate(source, imports);\n");
+    out.push_str("    const wasm = (instantiated.instance ?? instantiated).exports;\n");
+    if helpers.view {
+        out.push_str("    const view = () => new DataView(wasm.memory.buffer);\n");
+    }
+    if helpers.read_str {
+        out.push_str("    const decoder = new TextDecoder();\n");
+        out.push_str("    const readStr = (ptr, size) =>\n");
+        out.push_str("        decoder.decode(new Uint8Array(wasm.memory.buffer, ptr, size));\n");
+    }
+    if helpers.write_str {
+        out.push_str("    const encoder = new TextEncoder();\n");
+        out.push_str("    const writeStr = (value) => {\n");
+        out.push_str("        const bytes = encoder.encode(value);\n");
+        out.push_str("        const ptr = wasm.alloc(bytes.length) >>> 0;\n");
+        out.push_str("        new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);\n");
+        out.push_str("        return [ptr, bytes.length];\n");
+        out.push_str("    };\n");
+    }
+    out.push_str("\n    return {\n");
+    out.push_str(&methods);
+    out.push_str("    };\n");
+    out.push_str("}\n");
+
+    Ok(out)
+}
+
+fn write_js_method(
+    ctx: &ModuleContext,
+    out: &mut String,
+    helpers: &mut JsHelpers,
+    binding: &ExportBinding,
+) -> Result<(), String> {
+    let mut args = Vec::new();
+    if binding.sret {
+        args.push(format!("{}", ctx.sret_buffer_ptr));
+    }
+    for (name, param_type) in &binding.params {
+        push_js_args(ctx, helpers, &mut args, name, param_type)?;
+    }
+
+    let param_names: Vec<&str> = binding
+        .params
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    writeln!(
+        out,
+        "        {}({}) {{",
+        js_key(binding.name),
+        param_names.join(", ")
+    )
+    .unwrap();
+
+    let (ok_type, err_type) = match binding.output {
+        LoType::Result { ok_type, err_type } => {
+            if err_type.to_wasm_type().is_none() {
+                return Err(format!("error type {err_type} is not supported"));
+            }
+            (&**ok_type, Some(&**err_type))
+        }
+        output => (output, None),
+    };
+
+    let mut leaves = Vec::new();
+    collect_leaf_types(ctx, binding.output, &mut leaves);
+
+    let call = format!("wasm{}({})", js_member(binding.name), args.join(", "));
+    if leaves.is_empty() || binding.sret {
+        writeln!(out, "            {call};").unwrap();
+    } else {
+        writeln!(out, "            const result = {call};").unwrap();
+    }
+
+    let mut components = Vec::new();
+    if binding.sret {
+        helpers.view = true;
+        out.push_str("            const mem = view();\n");
+
+        let mut sized_components = Vec::new();
+        let mut stats = EmitComponentStats::default();
+        binding
+            .output
+            .emit_sized_component_stats(ctx, &mut stats, &mut sized_components)?;
+        for component in sized_components {
+            let address = ctx.sret_buffer_ptr + component.byte_offset;
+            components.push(js_memory_component(&component.value_type, address)?);
+        }
+    } else if leaves.len() == 1 {
+        components.push(js_direct_component(&leaves[0], "result"));
+    } else {
+        for (leaf, i) in leaves.iter().zip(0..) {
+            components.push(js_direct_component(leaf, &format!("result[{i}]")));
+        }
+    }
+
+    let mut components = components.into_iter();
+    let ok_value = js_value(ctx, helpers, ok_type, &mut components)?;
+    if let Some(err_type) = err_type {
+        let err_value = js_value(ctx, helpers, err_type, &mut components)?;
+        writeln!(out, "            const err = {err_value};").unwrap();
+        out.push_str("            if (err) throw new LoError(err);\n");
+    }
+    if *ok_type != LoType::Void && *ok_type != LoType::Never {
+        writeln!(out, "            return {ok_value};").unwrap();
+    }
+
+    out.push_str("        },\n");
+
+    Ok(())
+}
+
+fn push_js_args(
+    ctx: &ModuleContext,
+    helpers: &mut JsHelpers,
+    args: &mut Vec<String>,
+    value: &str,
+    lo_type: &LoType,
+) -> Result<(), String> {
+    match lo_type {
+        LoType::Void | LoType::Never => {}
+        LoType::StructInstance { name } if name == "str" => {
+            helpers.write_str = true;
+            args.push(format!("...writeStr({value})"));
+        }
+        LoType::StructInstance { name } => {
+            let struct_def = ctx.get_struct_def(name).unwrap(); // safe
+            for field in &struct_def.fields {
+                let field_value = format!("{value}{}", js_member(&field.name));
+                push_js_args(ctx, helpers, args, &field_value, &field.value_type)?;
+            }
+        }
+        LoType::Tuple(items) => {
+            for (item_type, i) in items.iter().zip(0..) {
+                push_js_args(ctx, helpers, args, &format!("{value}[{i}]"), item_type)?;
+            }
+        }
+        LoType::Result { .. } | LoType::MacroTypeArg { .. } => {
+            return Err(format!("{lo_type} params are not supported"));
+        }
+        _ => args.push(String::from(value)),
+    }
+
+    Ok(())
+}
+
+fn js_value(
+    ctx: &ModuleContext,
+    helpers: &mut JsHelpers,
+    lo_type: &LoType,
+    components: &mut impl Iterator<Item = String>,
+) -> Result<String, String> {
+    Ok(match lo_type {
+        LoType::Void | LoType::Never => String::from("undefined"),
+        LoType::StructInstance { name } if name == "str" => {
+            helpers.read_str = true;
+            let ptr = components.next().unwrap(); // safe, str is two components
+            let size = components.next().unwrap();
+            format!("readStr({ptr}, {size})")
+        }
+        LoType::StructInstance { name } => {
+            let struct_def = ctx.get_struct_def(name).unwrap(); // safe
+            let mut fields = Vec::new();
+            for field in &struct_def.fields {
+                let field_value = js_value(ctx, helpers, &field.value_type, components)?;
+                fields.push(format!("{}: {field_value}", js_key(&field.name)));
+            }
+            if fields.is_empty() {
+                String::from("{}")
+            } else {
+                format!("{{ {} }}", fields.join(", "))
+            }
+        }
+        LoType::Tuple(items) => {
+            let mut values = Vec::new();
+            for item_type in items {
+                values.push(js_value(ctx, helpers, item_type, components)?);
+            }
+            format!("[{}]", values.join(", "))
+        }
+        LoType::Result { .. } | LoType::MacroTypeArg { .. } => {
+            return Err(format!("nested {lo_type} results are not supported"));
+        }
+        _ => components.next().unwrap(), // safe, one component per leaf
+    })
+}
+
+// wasm results come as signed numbers and i64 as signed bigints
+fn js_direct_component(lo_type: &LoType, value: &str) -> String {
+    match lo_type {
+        LoType::Bool => format!("{value} !== 0"),
+        LoType::U32 | LoType::Pointer { .. } => format!("{value} >>> 0"),
+        LoType::U64 => format!("BigInt.asUintN(64, {value})"),
+        _ => String::from(value),
+    }
+}
+
+fn js_memory_component(lo_type: &LoType, address: u32) -> Result<String, String> {
+    let getter = match lo_type {
+        LoType::Bool => return Ok(format!("mem.getUint8({address}) !== 0")),
+        LoType::U8 => "getUint8",
+        LoType::I8 => "getInt8",
+        LoType::U16 => "getUint16",
+        LoType::I16 => "getInt16",
+        LoType::U32 | LoType::Pointer { .. } => "getUint32",
+        LoType::I32 => "getInt32",
+        LoType::F32 => "getFloat32",
+        LoType::U64 => "getBigUint64",
+        LoType::I64 => "getBigInt64",
+        LoType::F64 => "getFloat64",
+        _ => return Err(format!("Cannot read {lo_type} from memory")),
+    };
+
+    if matches!(lo_type, LoType::U8 | LoType::I8) {
+        return Ok(format!("mem.{getter}({address})"));
+    }
+    Ok(format!("mem.{getter}({address}, true)"))
+}
+
+fn generate_dts(ctx: &ModuleContext, bindings: &[ExportBinding]) -> Result<String, String> {
+    let mut struct_names = Vec::new();
+    let mut methods = String::new();
+    for binding in bindings {
+        write!(methods, "    {}(", js_key(binding.name)).unwrap();
+        let mut params = Vec::new();
+        for (name, param_type) in &binding.params {
+            params.push(format!(
+                "{name}: {}",
+                ts_type(param_type, &mut struct_names)
+            ));
+        }
+        methods.push_str(&params.join(", "));
+
+        let output = match binding.output {
+            LoType::Result { ok_type, .. } => ok_type,
+            output => output,
+        };
+        writeln!(methods, "): {};", ts_type(output, &mut struct_names)).unwrap();
+    }
+
+    let mut out = String::new();
+    out.push_str("// generated by `lo --emit-bindings dts`\n");
+
+    // fields can add more structs to the list while it is being written
+    let mut i = 0;
+    while i < struct_names.len() {
+        let struct_def = ctx.get_struct_def(struct_names[i]).unwrap(); // safe
+        writeln!(out, "export interface {} {{", ts_name(&struct_def.name)).unwrap();
+        for field in &struct_def.fields {
+            let field_type = ts_type(&field.value_type, &mut struct_names);
+            writeln!(out, "    {}: {field_type};", js_key(&field.name)).unwrap();
+        }
+        out.push_str("}\n\n");
+        i += 1;
+    }
+
+    out.push_str("export interface Exports {\n");
+    out.push_str(&methods);
+    out.push_str("}\n\n");
+
+    out.push_str("export declare class LoError extends Error {\n");
+    out.push_str("    code: number | bigint | boolean;\n");
+    out.push_str("}\n\n");
+
+    out.push_str("export declare function load(\n");
+    out.push_str("    source: BufferSource | WebAssembly.Module,\n");
+    out.push_str("    imports?: WebAssembly.Imports,\n");
+    out.push_str("): Promise<Exports>;\n");
+
+    Ok(out)
+}
+
+fn ts_type(lo_type: &LoType, struct_names: &mut Vec<Atom>) -> String {
+    match lo_type {
+        LoType::Never => String::from("never"),
+        LoType::Void => String::from("void"),
+        LoType::Bool => String::from("boolean"),
+        LoType::U64 | LoType::I64 => String::from("bigint"),
+        LoType::ExternRef => String::from("unknown"),
+        LoType::StructInstance { name } if name == "str" => String::from("string"),
+        LoType::StructInstance { name } => {
+            if !struct_names.contains(name) {
+                struct_names.push(*name);
+            }
+            ts_name(name.as_str())
+        }
+        LoType::Tuple(items) => {
+            let mut item_types = Vec::new();
+            for item_type in items {
+                item_types.push(ts_type(item_type, struct_names));
+            }
+            format!("[{}]", item_types.join(", "))
+        }
+        LoType::Result { ok_type, .. } => ts_type(ok_type, struct_names),
+        LoType::MacroTypeArg { .. } => String::from("unknown"),
+        _ => String::from("number"),
+    }
+}
+
+fn ts_name(name: &str) -> String {
+    name.replace("::", "_")
+}
+
+fn collect_leaf_types(ctx: &ModuleContext, lo_type: &LoType, leaves: &mut Vec<LoType>) {
+    match lo_type {
+        LoType::Tuple(items) => {
+            for item_type in items {
+                collect_leaf_types(ctx, item_type, leaves);
+            }
+        }
+        LoType::StructInstance { name } => {
+            let struct_def = ctx.get_struct_def(name).unwrap(); // safe
+            for field in &struct_def.fields {
+                collect_leaf_types(ctx, &field.value_type, leaves);
+            }
+        }
+        LoType::Result { ok_type, err_type } => {
+            collect_leaf_types(ctx, ok_type, leaves);
+            collect_leaf_types(ctx, err_type, leaves);
+        }
+        _ => {
+            if lo_type.to_wasm_type().is_some() {
+                leaves.push(lo_type.clone());
+            }
+        }
+    }
+}
+
+fn has_memory_export(ctx: &ModuleContext) -> bool {
+    let wasm_module = ctx.wasm_module.borrow();
+    wasm_module
+        .exports
+        .iter()
+        .any(|export| export.export_type == WasmExportType::Mem && export.export_name == "memory")
+}
+
+fn has_alloc_export(ctx: &ModuleContext) -> bool {
+    ctx.fn_exports.iter().any(|fn_export| {
+        let fn_def = ctx.get_fn_def(&fn_export.in_name).unwrap(); // safe
+        fn_export.out_name == "alloc"
+            && !fn_def.sret
+            && fn_def.type_.inputs == [LoType::U32]
+            && matches!(fn_def.type_.output, LoType::U32 | LoType::Pointer { .. })
+    })
+}
+
+fn is_js_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_ascii_alphabetic() || first == '_' || first == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+// object literal key
+fn js_key(name: &str) -> String {
+    if is_js_identifier(name) {
+        return String::from(name);
+    }
+    let mut key = String::new();
+    write_json_string(&mut key, name);
+    key
+}
+
+// property access
+fn js_member(name: &str) -> String {
+    if is_js_identifier(name) {
+        return format!(".{name}");
+    }
+    let mut member = String::from("[");
+    write_json_string(&mut member, name);
+    member.push(']');
+    member
+}
@@ -1,62 +1,93 @@
-use crate::{ir_generator::*, wasm::*};
-use alloc::{string::String, vec::Vec};
+use crate::{core::*, ir_generator::*, wasm::*};
+use alloc::{format, string::String, vec::Vec};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum CodeGenTarget {
+    #[default]
+    Linear,
+    // experimental, structs and strings are gc managed instead of living in linear memory
+    Gc,
+}
 
 #[derive(Default)]
 pub struct CodeGenerator {
+    target: CodeGenTarget,
     wasm_module: WasmModule,
     ss: WasmScopeStack,
     fn_count: u32,
+    struct_names: Vec<String>, // indexed by gc type index
+    uses_str: bool,            // str array type goes right after struct types
+    fn_locals: Vec<WasmType>,  // all locals of the current fn, params included
 }
 
 impl CodeGenerator {
-    pub fn generate(scope: LoScope) -> WasmModule {
-        let mut generator = CodeGenerator::default();
+    pub fn generate(scope: LoScope, target: CodeGenTarget) -> Result<WasmModule, LoError> {
+        let mut generator = CodeGenerator {
+            target,
+            ..Default::default()
+        };
 
-        generator.generate_all(scope);
+        generator.generate_all(scope)?;
 
-        generator.wasm_module
+        Ok(generator.wasm_module)
     }
 
-    fn generate_all(&mut self, scope: LoScope) {
+    fn generate_all(&mut self, scope: LoScope) -> Result<(), LoError> {
+        self.generate_struct_types(&scope)?;
+
         let wasm_scope = self.make_scope(&scope);
         self.ss.push(wasm_scope);
 
         for fn_def in &scope.fn_defs {
+            let in_fn = |message| LoError {
+                message,
+                loc: fn_def.loc,
+            };
+
             let mut fn_type = WasmFnType {
                 inputs: Vec::new(),
                 outputs: Vec::new(),
             };
 
             for lo_type in &fn_def.inputs {
-                self.lower_type(&lo_type, &mut fn_type.inputs);
+                self.lower_type(lo_type, &mut fn_type.inputs)
+                    .map_err(in_fn)?;
             }
-            self.lower_type(&fn_def.output, &mut fn_type.outputs);
+            self.lower_type(&fn_def.output, &mut fn_type.outputs)
+                .map_err(in_fn)?;
 
             let mut fn_code = WasmFn {
                 locals: Vec::new(),
                 expr: WasmExpr { instrs: Vec::new() },
             };
 
-            for input_type in &fn_type.inputs {
+            // params come from the fn type, other locals are added when lowering `let`s
+            self.fn_locals.clear();
+            let mut wasm_scope = WasmScope::default();
+            for param in fn_def.body.scope.vars.iter().take(fn_def.inputs.len()) {
+                let var = self.add_var(&param.name, &param.type_).map_err(in_fn)?;
+                wasm_scope.vars.push(var);
+            }
+            self.ss.push(wasm_scope);
+
+            self.lower_exprs(&fn_def.body.exprs, &mut fn_code.expr.instrs)
+                .map_err(in_fn)?;
+            self.ss.pop();
+
+            for local_type in &self.fn_locals[fn_type.inputs.len()..] {
                 if let Some(locals) = fn_code.locals.last_mut() {
-                    if locals.value_type == *input_type {
+                    if locals.value_type == *local_type {
                         locals.count += 1;
                         continue;
                     }
                 }
 
                 fn_code.locals.push(WasmLocals {
-                    value_type: input_type.clone(),
+                    value_type: local_type.clone(),
                     count: 1,
                 });
             }
 
-            let wasm_scope = self.make_scope(&fn_def.body.scope);
-            self.ss.push(wasm_scope);
-
-            self.lower_exprs(&fn_def.body.exprs, &mut fn_code.expr.instrs);
-            self.ss.pop();
-
             let type_index = self.wasm_module.types.len() as u32;
             self.wasm_module.types.push(fn_type);
 
@@ -76,31 +107,136 @@ impl CodeGenerator {
             self.wasm_module.debug_fn_info.push(WasmDebugFnInfo {
                 fn_index,
                 fn_name: fn_def.name.clone(),
+                loc: fn_def.loc,
+                local_names: Vec::new(),
             });
         }
 
         self.ss.pop();
+
+        if self.uses_str {
+            self.wasm_module.gc_types.push(WasmGcType::Array {
+                item: WasmFieldType {
+                    storage_type: WasmStorageType::I8,
+                    mutable: false,
+                },
+            });
+        }
+
+        Ok(())
     }
 
-    fn lower_type(&mut self, lo_type: &LoType, wasm_types: &mut Vec<WasmType>) {
+    fn generate_struct_types(&mut self, scope: &LoScope) -> Result<(), LoError> {
+        if let (CodeGenTarget::Linear, Some(struct_def)) = (self.target, scope.struct_defs.first())
+        {
+            return Err(LoError {
+                message: format!(
+                    "Struct {} is only supported in `--compile-gc` mode for now",
+                    struct_def.name
+                ),
+                loc: struct_def.loc,
+            });
+        }
+
+        // all names are known upfront so struct fields can reference any struct
+        for struct_def in &scope.struct_defs {
+            self.struct_names.push(struct_def.name.clone());
+        }
+
+        for struct_def in &scope.struct_defs {
+            let mut fields = Vec::new();
+            for field in &struct_def.fields {
+                let mut field_types = Vec::new();
+                self.lower_type(&field.type_, &mut field_types)
+                    .map_err(|message| LoError {
+                        message,
+                        loc: struct_def.loc,
+                    })?;
+
+                for field_type in field_types {
+                    fields.push(WasmFieldType {
+                        storage_type: WasmStorageType::Value(field_type),
+                        mutable: true,
+                    });
+                }
+            }
+
+            self.wasm_module
+                .gc_types
+                .push(WasmGcType::Struct { fields });
+        }
+
+        Ok(())
+    }
+
+    fn lower_type(
+        &mut self,
+        lo_type: &LoType,
+        wasm_types: &mut Vec<WasmType>,
+    ) -> Result<(), String> {
         match lo_type {
             LoType::Never => {}
             LoType::Void => {}
             LoType::Bool => wasm_types.push(WasmType::I32),
             LoType::U32 => wasm_types.push(WasmType::I32),
+            LoType::Str | LoType::Struct { .. } => wasm_types.push(WasmType::Ref {
+                type_index: self.get_gc_type_index(lo_type)?,
+            }),
+        }
+
+        Ok(())
+    }
+
+    fn get_gc_type_index(&mut self, lo_type: &LoType) -> Result<u32, String> {
+        if self.target != CodeGenTarget::Gc {
+            return Err(format!(
+                "Type {lo_type} is only supported in `--compile-gc` mode for now"
+            ));
         }
+
+        match lo_type {
+            LoType::Str => {
+                self.uses_str = true;
+                Ok(self.struct_names.len() as u32)
+            }
+            LoType::Struct { name } => {
+                let type_index = self.struct_names.iter().position(|n| n == name).unwrap();
+                Ok(type_index as u32)
+            }
+            _ => Err(format!("Type {lo_type} is not a GC type")),
+        }
+    }
+
+    fn add_var(&mut self, name: &str, lo_type: &LoType) -> Result<WasmVar, String> {
+        let mut wasm_types = Vec::new();
+        self.lower_type(lo_type, &mut wasm_types)?;
+
+        let var = WasmVar {
+            name: String::from(name),
+            index: self.fn_locals.len() as u32,
+            count: wasm_types.len() as u32,
+        };
+        self.fn_locals.append(&mut wasm_types);
+
+        Ok(var)
     }
 
-    fn lower_exprs(&mut self, exprs: &Vec<LoExpr>, instrs: &mut Vec<WasmInstr>) {
+    fn lower_exprs(
+        &mut self,
+        exprs: &Vec<LoExpr>,
+        instrs: &mut Vec<WasmInstr>,
+    ) -> Result<(), String> {
         for expr in exprs {
-            self.lower(expr, instrs);
+            self.lower(expr, instrs)?;
         }
+
+        Ok(())
     }
 
-    fn lower(&mut self, expr: &LoExpr, instrs: &mut Vec<WasmInstr>) {
+    fn lower(&mut self, expr: &LoExpr, instrs: &mut Vec<WasmInstr>) -> Result<(), String> {
         match expr {
             LoExpr::Casted { expr, .. } => {
-                self.lower(expr, instrs);
+                self.lower(expr, instrs)?;
             }
             LoExpr::Void => {}
             LoExpr::Unreachable => {
@@ -112,12 +248,12 @@ impl CodeGenerator {
                 });
             }
             LoExpr::Return { expr } => {
-                self.lower(expr, instrs);
+                self.lower(expr, instrs)?;
                 instrs.push(WasmInstr::Return);
             }
             LoExpr::BinaryOp { kind, lhs, rhs } => {
-                self.lower(lhs, instrs);
-                self.lower(rhs, instrs);
+                self.lower(lhs, instrs)?;
+                self.lower(rhs, instrs)?;
                 instrs.push(WasmInstr::BinaryOp { kind: kind.clone() });
             }
             LoExpr::VarLoad { name, .. } => {
@@ -133,24 +269,84 @@ impl CodeGenerator {
                 then_block,
                 else_block,
             } => {
-                self.lower(cond, instrs);
+                self.lower(cond, instrs)?;
                 instrs.push(WasmInstr::BlockStart {
                     block_kind: WasmBlockKind::If,
                     block_type: WasmBlockType::NoOut,
                 });
-                self.lower_exprs(&then_block.exprs, instrs);
+                self.lower_block(then_block, instrs)?;
                 if let Some(else_block) = else_block {
                     instrs.push(WasmInstr::Else);
-                    self.lower_exprs(&else_block.exprs, instrs);
+                    self.lower_block(else_block, instrs)?;
                 }
                 instrs.push(WasmInstr::BlockEnd);
             }
             LoExpr::Call { fn_name, args, .. } => {
-                self.lower_exprs(args, instrs);
+                self.lower_exprs(args, instrs)?;
                 let fn_index = self.ss.get_fn_def(&fn_name).unwrap().index;
                 instrs.push(WasmInstr::Call { fn_index });
             }
+            LoExpr::Let { name, value } => {
+                self.lower(value, instrs)?;
+
+                // added after the value so it can still read a shadowed var
+                let var = self.add_var(name, &value.get_type())?;
+                for i in (0..var.count).rev() {
+                    instrs.push(WasmInstr::LocalSet {
+                        local_index: var.index + i,
+                    });
+                }
+                self.ss.top().vars.push(var);
+            }
+            LoExpr::StrConst { value } => {
+                let type_index = self.get_gc_type_index(&LoType::Str)?;
+                for byte in value.bytes() {
+                    instrs.push(WasmInstr::I32Const { value: byte as i32 });
+                }
+                instrs.push(WasmInstr::ArrayNewFixed {
+                    type_index,
+                    len: value.len() as u32,
+                });
+            }
+            LoExpr::StrLen { lhs } => {
+                self.lower(lhs, instrs)?;
+                instrs.push(WasmInstr::ArrayLen);
+            }
+            LoExpr::StructNew { fields, .. } => {
+                self.lower_exprs(fields, instrs)?;
+                instrs.push(WasmInstr::StructNew {
+                    type_index: self.get_gc_type_index(&expr.get_type())?,
+                });
+            }
+            LoExpr::FieldLoad {
+                lhs,
+                struct_name,
+                field_index,
+                ..
+            } => {
+                self.lower(lhs, instrs)?;
+                instrs.push(WasmInstr::StructGet {
+                    type_index: self.get_gc_type_index(&LoType::Struct {
+                        name: struct_name.clone(),
+                    })?,
+                    field_index: *field_index,
+                });
+            }
         }
+
+        Ok(())
+    }
+
+    fn lower_block(
+        &mut self,
+        block: &CodeBlock,
+        instrs: &mut Vec<WasmInstr>,
+    ) -> Result<(), String> {
+        self.ss.push(WasmScope::default());
+        let result = self.lower_exprs(&block.exprs, instrs);
+        self.ss.pop();
+
+        result
     }
 
     fn make_scope(&mut self, scope: &LoScope) -> WasmScope {
@@ -163,19 +359,10 @@ impl CodeGenerator {
             self.fn_count += 1;
         }
 
-        let mut vars = Vec::new();
-        for lo_var in &scope.vars {
-            let mut wasm_types = Vec::new();
-            self.lower_type(&lo_var.type_, &mut wasm_types);
-
-            vars.push(WasmVar {
-                name: lo_var.name.clone(),
-                index: vars.len() as u32,
-                count: wasm_types.len() as u32,
-            });
+        WasmScope {
+            vars: Vec::new(),
+            fn_defs,
         }
-
-        WasmScope { vars, fn_defs }
     }
 }
 
@@ -210,9 +397,14 @@ impl WasmScopeStack {
         self.scopes.pop().unwrap()
     }
 
+    fn top(&mut self) -> &mut WasmScope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    // later vars shadow earlier ones of the same scope
     fn get_var(&self, name: &str) -> Option<&WasmVar> {
         for scope in self.scopes.iter().rev() {
-            for var in &scope.vars {
+            for var in scope.vars.iter().rev() {
                 if var.name == *name {
                     return Some(var);
                 }
@@ -1,14 +1,30 @@
-use alloc::{format, rc::Rc, string::String, vec, vec::Vec};
-use core::{cell::RefCell, ffi::CStr, str};
+use alloc::{
+    alloc::{GlobalAlloc, Layout},
+    boxed::Box,
+    format,
+    string::String,
+    vec,
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    str,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 #[derive(Default, PartialEq)]
 pub enum CompilerMode {
     #[default]
     Compile,
     CompileV2,
+    CompileGc,
     Inspect,
     PrettyPrint,
+    ExpandMacros,
+    Doc,
     Eval,
+    EmitIr,
+    EmitAst,
 }
 
 #[derive(PartialEq)]
@@ -45,59 +61,429 @@ impl From<LoError> for String {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+// line and column resolved from a location's offset, only needed for diagnostics
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct LoPosition {
-    pub offset: usize,
     pub line: usize,
     pub col: usize,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+// byte range into one of the registered source files
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct LoLocation {
-    pub file_name: Rc<str>,
-
-    pub pos: LoPosition,
-    pub end_pos: LoPosition,
+    pub file_id: u32,
+    pub offset: u32,
+    pub end_offset: u32,
 }
 
 impl LoLocation {
     pub fn internal() -> Self {
         LoLocation {
-            file_name: "<internal>".into(),
-            pos: LoPosition {
-                offset: 0,
-                line: 1,
-                col: 1,
-            },
-            end_pos: LoPosition {
-                offset: 0,
-                line: 1,
-                col: 1,
-            },
+            file_id: INTERNAL_FILE_ID,
+            offset: 0,
+            end_offset: 0,
         }
     }
+
+    pub fn file_name(&self) -> Atom {
+        SourceFile::name(self.file_id)
+    }
+
+    pub fn pos(&self) -> LoPosition {
+        SourceFile::position(self.file_id, self.offset, *COLUMN_UNIT.borrow())
+    }
+
+    pub fn end_pos(&self) -> LoPosition {
+        SourceFile::position(self.file_id, self.end_offset, *COLUMN_UNIT.borrow())
+    }
+
+    // independent of `--columns`, used where the format defines the unit (like source maps)
+    pub fn utf16_pos(&self) -> LoPosition {
+        SourceFile::position(self.file_id, self.offset, ColumnUnit::Utf16)
+    }
 }
 
 impl core::fmt::Display for LoLocation {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{}:{}:{}", self.file_name, self.pos.line, self.pos.col)
+        let pos = self.pos();
+        write!(f, "{}:{}:{}", self.file_name(), pos.line, pos.col)
+    }
+}
+
+const INTERNAL_FILE_ID: u32 = u32::MAX;
+
+// what `LoPosition::col` counts, editors speaking LSP expect UTF-16 code units
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColumnUnit {
+    Chars { tab_width: usize }, // tabs advance to the next multiple of `tab_width`
+    Utf16,
+}
+
+impl ColumnUnit {
+    pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+    pub fn parse(arg: &str, tab_width: usize) -> Result<Self, String> {
+        match arg {
+            "chars" => Ok(ColumnUnit::Chars { tab_width }),
+            "utf16" => Ok(ColumnUnit::Utf16),
+            _ => Err(format!(
+                "Unknown column unit: {arg}, expected `chars` or `utf16`"
+            )),
+        }
+    }
+
+    pub fn set(self) {
+        *COLUMN_UNIT.borrow_mut() = self;
+    }
+}
+
+#[thread_local]
+static COLUMN_UNIT: RefCell<ColumnUnit> = RefCell::new(ColumnUnit::Chars {
+    tab_width: ColumnUnit::DEFAULT_TAB_WIDTH,
+});
+
+pub struct SourceFile {
+    name: Atom,
+    source: Box<str>,
+    line_starts: Vec<u32>, // byte offsets
+}
+
+#[thread_local]
+static SOURCE_FILES: RefCell<Vec<SourceFile>> = RefCell::new(Vec::new());
+
+impl SourceFile {
+    // every lexed source gets an id, even if a file with the same name was added before
+    pub fn add(name: &str, source: &str) -> u32 {
+        let mut line_starts = vec![0];
+        for (offset, byte) in source.bytes().enumerate() {
+            if byte == b'\n' {
+                line_starts.push(offset as u32 + 1);
+            }
+        }
+
+        let mut files = SOURCE_FILES.borrow_mut();
+        files.push(SourceFile {
+            name: Atom::intern(name),
+            source: source.into(),
+            line_starts,
+        });
+        files.len() as u32 - 1
+    }
+
+    fn name(file_id: u32) -> Atom {
+        match SOURCE_FILES.borrow().get(file_id as usize) {
+            Some(file) => file.name,
+            None => Atom::intern("<internal>"),
+        }
+    }
+
+    fn position(file_id: u32, offset: u32, unit: ColumnUnit) -> LoPosition {
+        let files = SOURCE_FILES.borrow();
+        let Some(file) = files.get(file_id as usize) else {
+            return LoPosition { line: 1, col: 1 };
+        };
+
+        let line = file.line_starts.partition_point(|start| *start <= offset);
+        let line_start = file.line_starts[line - 1] as usize;
+        let line_end = (offset as usize).min(file.source.len());
+
+        let mut col = 1;
+        for char in file.source[line_start..line_end].chars() {
+            col += match unit {
+                ColumnUnit::Chars { tab_width } if char == '\t' => {
+                    tab_width - (col - 1) % tab_width
+                }
+                ColumnUnit::Chars { .. } => 1,
+                ColumnUnit::Utf16 => char.len_utf16(),
+            };
+        }
+
+        LoPosition { line, col }
     }
 }
 
+// interned identifier, comparing and copying it doesn't touch the string,
+//   ordering is by the interning order, not alphabetical
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Atom(u32);
+
+#[derive(Default)]
+struct Interner {
+    slots: Vec<u32>, // indices into `names` by name hash, `EMPTY_SLOT` when free
+    names: Vec<&'static str>,
+}
+
+const EMPTY_SLOT: u32 = u32::MAX;
+
+impl Interner {
+    // FNV-1a
+    fn hash(name: &str) -> usize {
+        let mut hash = 0x811c9dc5u32;
+        for byte in name.bytes() {
+            hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+        }
+        hash as usize
+    }
+
+    // slot of `name` or the free slot where it would go
+    fn find_slot(&self, name: &str) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut slot = Interner::hash(name) & mask;
+        loop {
+            let index = self.slots[slot];
+            if index == EMPTY_SLOT || self.names[index as usize] == name {
+                return slot;
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Atom> {
+        if self.slots.is_empty() {
+            return None;
+        }
+
+        let index = self.slots[self.find_slot(name)];
+        if index == EMPTY_SLOT {
+            return None;
+        }
+
+        Some(Atom(index))
+    }
+
+    fn insert(&mut self, name: &'static str) -> Atom {
+        if self.names.len() * 2 >= self.slots.len() {
+            self.slots = vec![EMPTY_SLOT; (self.slots.len() * 2).max(256)];
+            for (index, name) in self.names.iter().enumerate() {
+                let slot = self.find_slot(name);
+                self.slots[slot] = index as u32;
+            }
+        }
+
+        let atom = Atom(self.names.len() as u32);
+        let slot = self.find_slot(name);
+        self.slots[slot] = atom.0;
+        self.names.push(name);
+        atom
+    }
+}
+
+#[thread_local]
+static INTERNER: RefCell<Option<Interner>> = RefCell::new(None);
+
+impl Atom {
+    pub fn intern(name: &str) -> Atom {
+        if let Some(atom) = Atom::lookup(name) {
+            return atom;
+        }
+
+        let mut interner = INTERNER.borrow_mut();
+        let interner = interner.get_or_insert_with(Interner::default);
+
+        // interned names live until the compiler exits
+        interner.insert(Box::leak(Box::from(name)))
+    }
+
+    // doesn't intern the name, so unknown names can be looked up without growing the interner
+    pub fn lookup(name: &str) -> Option<Atom> {
+        INTERNER.borrow().as_ref()?.lookup(name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.borrow().as_ref().unwrap().names[self.0 as usize]
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(name: &str) -> Self {
+        Atom::intern(name)
+    }
+}
+
+impl From<&String> for Atom {
+    fn from(name: &String) -> Self {
+        Atom::intern(name)
+    }
+}
+
+impl From<Atom> for String {
+    fn from(atom: Atom) -> Self {
+        String::from(atom.as_str())
+    }
+}
+
+impl From<&Atom> for Atom {
+    fn from(atom: &Atom) -> Self {
+        *atom
+    }
+}
+
+impl PartialEq<str> for Atom {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Atom {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Atom {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<Atom> for String {
+    fn eq(&self, other: &Atom) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl core::ops::Deref for Atom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl core::fmt::Display for Atom {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl core::fmt::Debug for Atom {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// hash map keyed by atoms, iterates in insertion order
+#[derive(Clone)]
+pub struct AtomMap<V> {
+    entries: Vec<(Atom, V)>,
+    slots: Vec<u32>, // indices into `entries` by atom hash, `EMPTY_SLOT` when free
+}
+
+impl<V> Default for AtomMap<V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            slots: Vec::new(),
+        }
+    }
+}
+
+impl<V> AtomMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // slot of `key` or the free slot where it would go
+    fn find_slot(&self, key: Atom) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut slot = key.0.wrapping_mul(0x9e3779b9) as usize & mask;
+        loop {
+            let index = self.slots[slot];
+            if index == EMPTY_SLOT || self.entries[index as usize].0 == key {
+                return slot;
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    fn find_index(&self, key: Atom) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+
+        let index = self.slots[self.find_slot(key)];
+        if index == EMPTY_SLOT {
+            return None;
+        }
+
+        Some(index as usize)
+    }
+
+    pub fn get(&self, key: Atom) -> Option<&V> {
+        let index = self.find_index(key)?;
+        Some(&self.entries[index].1)
+    }
+
+    // for names that come straight from tokens, names that were never interned aren't in any map
+    pub fn get_by_name(&self, name: &str) -> Option<&V> {
+        self.get(Atom::lookup(name)?)
+    }
+
+    pub fn insert(&mut self, key: Atom, value: V) -> Option<V> {
+        if let Some(index) = self.find_index(key) {
+            return Some(core::mem::replace(&mut self.entries[index].1, value));
+        }
+
+        if self.entries.len() * 2 >= self.slots.len() {
+            self.slots = vec![EMPTY_SLOT; (self.slots.len() * 2).max(8)];
+            for index in 0..self.entries.len() {
+                let slot = self.find_slot(self.entries[index].0);
+                self.slots[slot] = index as u32;
+            }
+        }
+
+        let slot = self.find_slot(key);
+        self.slots[slot] = self.entries.len() as u32;
+        self.entries.push((key, value));
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Atom, &V)> {
+        self.entries.iter().map(|(key, value)| (*key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.iter_mut().map(|(_, value)| value)
+    }
+}
+
+impl<V> core::ops::Index<Atom> for AtomMap<V> {
+    type Output = V;
+
+    fn index(&self, key: Atom) -> &V {
+        self.get(key).unwrap()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
 const CWD_PREOPEN_FD: u32 = 3;
 
+#[cfg(target_arch = "wasm32")]
 pub struct WasiArgs {
     size: usize,
     argv: Vec<*mut u8>,
     _argv_buf: Vec<u8>,
 }
 
+#[cfg(target_arch = "wasm32")]
 impl WasiArgs {
     pub fn load() -> Result<Self, wasi::Errno> {
         let (argv_size, argv_buf_size) = unsafe { wasi::args_sizes_get() }?;
 
-        let mut argv = vec![core::ptr::null::<u8>() as *mut u8; argv_size];
-        let mut _argv_buf = vec![0u8; argv_buf_size];
+        let mut argv = alloc::vec![core::ptr::null::<u8>() as *mut u8; argv_size];
+        let mut _argv_buf = alloc::vec![0u8; argv_buf_size];
         if argv_size != 0 {
             unsafe { wasi::args_get(argv.as_mut_ptr() as *mut *mut u8, _argv_buf.as_mut_ptr()) }?;
         }
@@ -118,16 +504,155 @@ impl WasiArgs {
             return None;
         }
 
-        unsafe { CStr::from_ptr(self.argv[index] as *const i8).to_str().ok() }
+        unsafe {
+            core::ffi::CStr::from_ptr(self.argv[index] as *const i8)
+                .to_str()
+                .ok()
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct WasiArgs {
+    args: Vec<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WasiArgs {
+    pub fn load() -> Result<Self, wasi::Errno> {
+        Ok(Self {
+            args: std::env::args().collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.args.get(index).map(String::as_str)
+    }
+}
+
+pub enum CliArg<'a> {
+    Flag(&'a str),
+    Positional(&'a str),
+}
+
+// flags can come in any order, everything after `--` is positional
+pub struct ArgParser<'a> {
+    args: Vec<&'a str>,
+    index: usize,
+    positional_only: bool,
+    usage: &'a str,
+}
+
+impl<'a> ArgParser<'a> {
+    pub fn new(args: Vec<&'a str>, usage: &'a str) -> Self {
+        Self {
+            args,
+            index: 0,
+            positional_only: false,
+            usage,
+        }
+    }
+
+    pub fn next(&mut self) -> Option<CliArg<'a>> {
+        loop {
+            let arg = *self.args.get(self.index)?;
+            self.index += 1;
+
+            if self.positional_only || arg == "-" || !arg.starts_with('-') {
+                return Some(CliArg::Positional(arg));
+            }
+
+            if arg == "--" {
+                self.positional_only = true;
+                continue;
+            }
+
+            return Some(CliArg::Flag(arg));
+        }
+    }
+
+    pub fn value(&mut self, flag: &str, value_name: &str) -> Result<&'a str, String> {
+        let Some(value) = self.args.get(self.index) else {
+            return Err(self.error(format!("Missing {value_name} after {flag}")));
+        };
+        self.index += 1;
+
+        Ok(value)
+    }
+
+    pub fn rest(&mut self) -> Vec<&'a str> {
+        let rest = self.args[self.index..].to_vec();
+        self.index = self.args.len();
+        rest
+    }
+
+    // the full usage scrolls the error away, it is printed by `--help` instead
+    pub fn error(&self, message: impl AsRef<str>) -> String {
+        format!("{}\nRun with --help for usage", message.as_ref())
+    }
+
+    // suggests the closest flag mentioned in the usage
+    pub fn unknown_flag(&self, flag: &str) -> String {
+        let known_flags = self
+            .usage
+            .split_whitespace()
+            .map(|word| word.trim_matches(|c| matches!(c, ',' | '`' | '(' | ')')))
+            .filter(|word| word.starts_with('-') && word.len() > 2 && !word.contains('<'));
+
+        let closest = known_flags
+            .map(|known_flag| (edit_distance(flag, known_flag), known_flag))
+            .min_by_key(|(distance, _)| *distance);
+
+        match closest {
+            Some((distance, known_flag)) if distance <= 2 => self.error(format!(
+                "Unknown option: {flag}, did you mean {known_flag}?"
+            )),
+            _ => self.error(format!("Unknown option: {flag}")),
+        }
     }
 }
 
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut prev_row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.bytes().enumerate() {
+        let mut row = Vec::with_capacity(b.len() + 1);
+        row.push(i + 1);
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = prev_row[j] + (a_char != *b_char) as usize;
+            row.push(substitution.min(prev_row[j + 1] + 1).min(row[j] + 1));
+        }
+        prev_row = row;
+    }
+
+    prev_row[b.len()]
+}
+
+#[cfg(target_arch = "wasm32")]
 pub fn proc_exit(exit_code: u32) -> ! {
+    stderr_flush();
+
+    if cfg!(feature = "browser") {
+        core::arch::wasm32::unreachable();
+    }
+
     unsafe { wasi::proc_exit(exit_code) };
     unreachable!(); // needed for typesystem
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub fn proc_exit(exit_code: u32) -> ! {
+    stderr_flush();
+    std::process::exit(exit_code as i32)
+}
+
 /// Hack for https://github.com/microsoft/vscode-wasm/issues/161
+#[cfg(target_arch = "wasm32")]
 pub fn unlock_fs() -> Result<(), wasi::Errno> {
     use alloc::alloc::*;
 
@@ -140,6 +665,7 @@ pub fn unlock_fs() -> Result<(), wasi::Errno> {
     Ok(())
 }
 
+#[cfg(target_arch = "wasm32")]
 static mut FS_UNLOCKED: bool = false;
 
 pub fn file_read_utf8(file_path: &str) -> Result<String, String> {
@@ -152,7 +678,14 @@ pub fn file_read_utf8(file_path: &str) -> Result<String, String> {
     return Ok(chars);
 }
 
+#[cfg(target_arch = "wasm32")]
 pub fn file_read(file_path: &str) -> Result<Vec<u8>, String> {
+    if cfg!(feature = "browser") {
+        return Err(format!(
+            "Cannot read {file_path}, there is no file system in the browser"
+        ));
+    }
+
     if file_path == "<stdin>" {
         return fd_read_all(wasi::FD_STDIN)
             .map_err(|err| format!("Cannot read <stdin>: error code = {err}"));
@@ -175,10 +708,203 @@ pub fn file_read(file_path: &str) -> Result<Vec<u8>, String> {
     return Ok(bytes);
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub fn file_read(file_path: &str) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    if file_path == "<stdin>" {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|err| format!("Cannot read <stdin>: {err}"))?;
+        return Ok(bytes);
+    }
+
+    std::fs::read(file_path).map_err(|err| format!("Cannot read file {file_path}: {err}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+type FileHandle = u32;
+
+#[cfg(target_arch = "wasm32")]
+fn file_create(file_path: &str) -> Result<FileHandle, String> {
+    if cfg!(feature = "browser") {
+        return Err(format!(
+            "Cannot write {file_path}, there is no file system in the browser"
+        ));
+    }
+
+    if unsafe { !FS_UNLOCKED } {
+        unlock_fs().map_err(|err| format!("Error unlocking fs: error code = {err}"))?;
+        unsafe { FS_UNLOCKED = true };
+    }
+
+    let oflags = wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC;
+    unsafe {
+        wasi::path_open(
+            CWD_PREOPEN_FD,
+            1,
+            file_path,
+            oflags,
+            wasi::RIGHTS_FD_WRITE,
+            0,
+            0,
+        )
+    }
+    .map_err(|err| format!("Cannot create file {file_path}: error code = {err}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn file_put(fd: &mut FileHandle, mut bytes: &[u8]) -> Result<(), String> {
+    // writes can be partial
+    while !bytes.is_empty() {
+        let out_vec = [wasi::Ciovec {
+            buf: bytes.as_ptr(),
+            buf_len: bytes.len(),
+        }];
+        let written = unsafe { wasi::fd_write(*fd, &out_vec) }
+            .map_err(|err| format!("error code = {err}"))?;
+        bytes = &bytes[written..];
+    }
+
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn file_close(fd: FileHandle, file_path: &str) -> Result<(), String> {
+    unsafe { wasi::fd_close(fd) }
+        .map_err(|err| format!("Cannot close file {file_path}: error code = {err}"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type FileHandle = std::fs::File;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_create(file_path: &str) -> Result<FileHandle, String> {
+    std::fs::File::create(file_path).map_err(|err| format!("Cannot create file {file_path}: {err}"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_put(file: &mut FileHandle, bytes: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    file.write_all(bytes).map_err(|err| format!("{err}"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_close(file: FileHandle, _file_path: &str) -> Result<(), String> {
+    drop(file);
+    Ok(())
+}
+
+pub fn file_write(file_path: &str, bytes: &[u8]) -> Result<(), String> {
+    let mut file = file_create(file_path)?;
+    file_put(&mut file, bytes).map_err(|err| format!("Cannot write file {file_path}: {err}"))?;
+    file_close(file, file_path)
+}
+
+// buffered writer to <stdout> or a file, lets big outputs be written as they are produced
+pub struct OutputStream {
+    file: Option<(String, FileHandle)>, // <stdout> if `None`
+    buffer: Vec<u8>,
+    flushed_len: usize,
+    error: Option<String>, // first write error, reported by `finish`
+}
+
+const OUTPUT_STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+impl OutputStream {
+    pub fn stdout() -> Self {
+        Self {
+            file: None,
+            buffer: Vec::with_capacity(OUTPUT_STREAM_BUFFER_SIZE),
+            flushed_len: 0,
+            error: None,
+        }
+    }
+
+    pub fn create(file_path: &str) -> Result<Self, String> {
+        let file = file_create(file_path)?;
+        Ok(Self {
+            file: Some((String::from(file_path), file)),
+            ..Self::stdout()
+        })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        if self.buffer.len() + bytes.len() > OUTPUT_STREAM_BUFFER_SIZE {
+            self.flush();
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // count of bytes written so far
+    pub fn len(&self) -> usize {
+        self.flushed_len + self.buffer.len()
+    }
+
+    fn flush(&mut self) {
+        self.flushed_len += self.buffer.len();
+
+        let Some((file_path, file)) = &mut self.file else {
+            stdout_write_bytes(&self.buffer);
+            self.buffer.clear();
+            return;
+        };
+
+        if self.error.is_none() {
+            if let Err(err) = file_put(file, &self.buffer) {
+                self.error = Some(format!("Cannot write file {file_path}: {err}"));
+            }
+        }
+        self.buffer.clear();
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.flush();
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        if let Some((file_path, file)) = self.file.take() {
+            file_close(file, &file_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn file_exists(file_path: &str) -> bool {
+    if cfg!(feature = "browser") {
+        return false;
+    }
+
+    if unsafe { !FS_UNLOCKED } {
+        if unlock_fs().is_err() {
+            return false;
+        }
+        unsafe { FS_UNLOCKED = true };
+    }
+
+    let Ok(fd) = fd_open(file_path) else {
+        return false;
+    };
+    let _ = unsafe { wasi::fd_close(fd) };
+    true
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn file_exists(file_path: &str) -> bool {
+    std::path::Path::new(file_path).is_file()
+}
+
+#[cfg(target_arch = "wasm32")]
 fn fd_open(file_path: &str) -> Result<u32, wasi::Errno> {
     unsafe { wasi::path_open(CWD_PREOPEN_FD, 1, &file_path, 0, 264240830, 268435455, 0) }
 }
 
+#[cfg(target_arch = "wasm32")]
 fn fd_read_all(fd: u32) -> Result<Vec<u8>, String> {
     let mut output = Vec::<u8>::new();
     let mut chunk = [0; 256];
@@ -211,6 +937,55 @@ fn fd_read_all(fd: u32) -> Result<Vec<u8>, String> {
     Ok(output)
 }
 
+#[thread_local]
+static STDIN_BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+
+// returns `None` when <stdin> is exhausted
+pub fn stdin_read_line() -> Option<String> {
+    stderr_flush();
+
+    let buffer = &mut *STDIN_BUFFER.borrow_mut();
+    let mut chunk = [0; 256];
+
+    loop {
+        if let Some(newline_index) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line = String::from_utf8_lossy(&buffer[..newline_index]).into();
+            buffer.drain(..=newline_index);
+            return Some(line);
+        }
+
+        let nread = stdin_read(&mut chunk);
+
+        if nread == 0 {
+            if buffer.is_empty() {
+                return None;
+            }
+
+            let line = String::from_utf8_lossy(buffer).into();
+            buffer.clear();
+            return Some(line);
+        }
+
+        buffer.extend(&chunk[0..nread]);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn stdin_read(chunk: &mut [u8]) -> usize {
+    let in_vec = [wasi::Iovec {
+        buf: chunk.as_mut_ptr(),
+        buf_len: chunk.len(),
+    }];
+    unsafe { wasi::fd_read(wasi::FD_STDIN, &in_vec) }.unwrap_or(0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn stdin_read(chunk: &mut [u8]) -> usize {
+    use std::io::Read;
+
+    std::io::stdin().read(chunk).unwrap_or(0)
+}
+
 pub fn stdout_writeln(message: impl AsRef<str>) {
     stdout_write(message);
     stdout_write("\n");
@@ -227,7 +1002,7 @@ pub fn stdout_enable_bufferring() {
 pub fn stdout_disable_bufferring() {
     if let Some(buffer) = &mut *STDOUT_BUFFER.borrow_mut() {
         if !buffer.is_empty() {
-            fputs(wasi::FD_STDOUT, &buffer);
+            stdout_put(buffer);
             buffer.clear();
         }
     }
@@ -235,32 +1010,74 @@ pub fn stdout_disable_bufferring() {
 }
 
 pub fn stdout_write(message: impl AsRef<str>) {
-    let message_bytes = message.as_ref().as_bytes();
+    stdout_write_bytes(message.as_ref().as_bytes());
+}
 
+pub fn stdout_write_bytes(message_bytes: &[u8]) {
     let Some(buffer) = &mut *STDOUT_BUFFER.borrow_mut() else {
-        fputs(wasi::FD_STDOUT, message_bytes);
+        stdout_put(message_bytes);
         return;
     };
 
     if buffer.len() + message_bytes.len() > STDOUT_BUFFER_SIZE {
         if !buffer.is_empty() {
-            fputs(wasi::FD_STDOUT, &buffer);
+            stdout_put(buffer);
             buffer.clear();
         }
     }
 
     if message_bytes.len() >= STDOUT_BUFFER_SIZE {
-        fputs(wasi::FD_STDOUT, message_bytes);
+        stdout_put(message_bytes);
     } else {
         buffer.extend_from_slice(message_bytes);
     }
 }
 
+// pending diagnostics always go first so they never end up in the middle of the output
+fn stdout_put(message_bytes: &[u8]) {
+    stderr_flush();
+    fputs(wasi::FD_STDOUT, message_bytes);
+}
+
+#[thread_local]
+static STDERR_BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+const STDERR_BUFFER_SIZE: usize = 4096;
+
+// messages are kept whole, the buffer is written out on `stderr_flush`, before any <stdout>
+//   output, before reading <stdin>, on exit or once it grows past `STDERR_BUFFER_SIZE`
 pub fn stderr_write(message: impl AsRef<str>) {
-    fputs(wasi::FD_STDERR, message.as_ref().as_bytes());
+    let buffer_len = {
+        let mut buffer = STDERR_BUFFER.borrow_mut();
+        buffer.extend_from_slice(message.as_ref().as_bytes());
+        buffer.len()
+    };
+
+    if buffer_len >= STDERR_BUFFER_SIZE {
+        stderr_flush();
+    }
 }
 
+pub fn stderr_flush() {
+    let buffer = STDERR_BUFFER.take();
+    if !buffer.is_empty() {
+        fputs(wasi::FD_STDERR, &buffer);
+    }
+}
+
+// there is no WASI in the browser build, <stderr> is collected for the host instead
+#[cfg(target_arch = "wasm32")]
+#[thread_local]
+pub static CAPTURED_STDERR: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+
+#[cfg(target_arch = "wasm32")]
 pub fn fputs(fd: u32, message: &[u8]) {
+    if cfg!(feature = "browser") {
+        if fd == wasi::FD_STDERR {
+            CAPTURED_STDERR.borrow_mut().extend_from_slice(message);
+        }
+        return;
+    }
+
     let out_vec = [wasi::Ciovec {
         buf: message.as_ptr(),
         buf_len: message.len(),
@@ -269,24 +1086,137 @@ pub fn fputs(fd: u32, message: &[u8]) {
     unsafe { wasi::fd_write(fd, &out_vec) }.unwrap();
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub fn fputs(fd: u32, message: &[u8]) {
+    use std::io::Write;
+
+    match fd {
+        wasi::FD_STDOUT => std::io::stdout().write_all(message).unwrap(),
+        wasi::FD_STDERR => std::io::stderr().write_all(message).unwrap(),
+        _ => panic!("Cannot write to fd {fd} in native build"),
+    }
+}
+
+// same check as `isatty` of wasi-libc: a character device that can't seek
+#[cfg(target_arch = "wasm32")]
+pub fn is_terminal(fd: u32) -> bool {
+    if cfg!(feature = "browser") {
+        return false;
+    }
+
+    let Ok(fdstat) = (unsafe { wasi::fd_fdstat_get(fd) }) else {
+        return false;
+    };
+
+    fdstat.fs_filetype == wasi::FILETYPE_CHARACTER_DEVICE
+        && fdstat.fs_rights_base & (wasi::RIGHTS_FD_SEEK | wasi::RIGHTS_FD_TELL) == 0
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn is_terminal(fd: u32) -> bool {
+    use std::io::IsTerminal;
+
+    match fd {
+        wasi::FD_STDOUT => std::io::stdout().is_terminal(),
+        wasi::FD_STDERR => std::io::stderr().is_terminal(),
+        _ => false,
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn env_var(name: &str) -> Option<String> {
+    if cfg!(feature = "browser") {
+        return None;
+    }
+
+    let (environ_size, environ_buf_size) = unsafe { wasi::environ_sizes_get() }.ok()?;
+
+    let mut environ = alloc::vec![core::ptr::null_mut::<u8>(); environ_size];
+    let mut environ_buf = alloc::vec![0u8; environ_buf_size];
+    if environ_size != 0 {
+        unsafe { wasi::environ_get(environ.as_mut_ptr(), environ_buf.as_mut_ptr()) }.ok()?;
+    }
+
+    // entries are `<name>=<value>\0` one after another
+    environ_buf.split(|byte| *byte == 0).find_map(|entry| {
+        let (entry_name, value) = str::from_utf8(entry).ok()?.split_once('=')?;
+        (entry_name == name).then(|| String::from(value))
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+// `LO_COLOR` is either `always`, `never` or `auto` (default)
+pub fn stderr_colors_enabled() -> bool {
+    match env_var("LO_COLOR").as_deref() {
+        Some("always") => true,
+        Some("never") => false,
+        _ => is_terminal(wasi::FD_STDERR),
+    }
+}
+
+pub fn stderr_write_diagnostic(diagnostic: impl AsRef<str>) {
+    let diagnostic = diagnostic.as_ref();
+    if !stderr_colors_enabled() {
+        return stderr_write(diagnostic);
+    }
+
+    stderr_write(highlight_diagnostic(diagnostic));
+}
+
+// `<loc> - <message>` with bold location and red message (yellow for warnings),
+//   only the first line is highlighted
+fn highlight_diagnostic(diagnostic: &str) -> String {
+    let (first_line, rest) = match diagnostic.split_once('\n') {
+        Some((first_line, rest)) => (first_line, Some(rest)),
+        None => (diagnostic, None),
+    };
+
+    let Some((loc, message)) = first_line.split_once(" - ") else {
+        return String::from(diagnostic);
+    };
+
+    let color = if message.starts_with("Warning: ") {
+        "33"
+    } else {
+        "31"
+    };
+
+    let mut output = format!("\x1b[1m{loc}\x1b[0m - \x1b[{color}m{message}\x1b[0m");
+    if let Some(rest) = rest {
+        output.push('\n');
+        output.push_str(rest);
+    }
+    output
+}
+
 #[allow(dead_code)]
 pub fn debug(msg: String) {
-    unsafe {
-        wasi::fd_write(
-            wasi::FD_STDERR,
-            &[
-                wasi::Ciovec {
-                    buf: msg.as_ptr(),
-                    buf_len: msg.as_bytes().len(),
-                },
-                wasi::Ciovec {
-                    buf: "\n".as_ptr(),
-                    buf_len: 1,
-                },
-            ],
-        )
-        .unwrap();
+    stderr_write(msg + "\n");
+}
+
+// non-relative includes that don't exist in the working directory
+//   are looked up in `LO_INCLUDE_PATH` (`:` separated dirs)
+pub fn resolve_include_path(file_path: &str, relative_to: &str) -> String {
+    if file_path.starts_with('.') || file_path == "<stdin>" || file_exists(file_path) {
+        return resolve_path(file_path, relative_to);
+    }
+
+    let Some(include_path) = env_var("LO_INCLUDE_PATH") else {
+        return String::from(file_path);
+    };
+
+    for include_dir in include_path.split(':').filter(|dir| !dir.is_empty()) {
+        let candidate = format!("{}/{file_path}", include_dir.trim_end_matches('/'));
+        if file_exists(&candidate) {
+            return candidate;
+        }
     }
+
+    String::from(file_path)
 }
 
 pub fn resolve_path(file_path: &str, relative_to: &str) -> String {
@@ -342,16 +1272,38 @@ pub struct RangeDisplay<'a>(pub &'a LoLocation);
 
 impl<'a> core::fmt::Display for RangeDisplay<'a> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let sl = self.0.pos.line;
-        let sc = self.0.pos.col;
-        let el = self.0.end_pos.line;
-        let ec = self.0.end_pos.col;
+        let LoPosition { line: sl, col: sc } = self.0.pos();
+        let LoPosition { line: el, col: ec } = self.0.end_pos();
 
         write!(f, "{sl}:{sc}-{el}:{ec}")?;
         Ok(())
     }
 }
 
+// appends doc comment to inspect hover text, escaped for JSON
+pub struct DocDisplay<'a>(pub &'a Option<String>);
+
+impl<'a> core::fmt::Display for DocDisplay<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Some(doc) = self.0 else {
+            return Ok(());
+        };
+
+        f.write_str("\\n\\n")?;
+        for char in doc.chars() {
+            match char {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\t' => f.write_str("\\t")?,
+                _ => write!(f, "{char}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Default)]
 pub struct LoErrorManager {
     errors: Vec<LoError>,
@@ -368,9 +1320,191 @@ impl LoErrorManager {
         }
 
         for error in &self.errors {
-            stderr_write(format!("{error}\n"));
+            stderr_write_diagnostic(format!("{error}\n"));
         }
 
         Err(format!(""))
     }
 }
+
+static ALLOC_COUNT: AtomicUsize = AtomicUsize::new(0);
+static ALLOC_BYTES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// wraps the global allocator to count allocations for `--timings`
+pub struct CountingAllocator<A> {
+    inner: A,
+}
+
+impl<A> CountingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    fn count_alloc(size: usize) {
+        ALLOC_COUNT.fetch_add(1, Ordering::Relaxed);
+        ALLOC_BYTES.fetch_add(size, Ordering::Relaxed);
+        let live_bytes = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_LIVE_BYTES.fetch_max(live_bytes, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count_alloc(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count_alloc(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        Self::count_alloc(new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn clock_now_ns() -> u64 {
+    if cfg!(feature = "browser") {
+        return 0;
+    }
+
+    unsafe { wasi::clock_time_get(wasi::CLOCKID_MONOTONIC, 1) }.unwrap_or(0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clock_now_ns() -> u64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_nanos() as u64
+}
+
+#[derive(Clone, Copy, Default)]
+struct PhaseStats {
+    time_ns: u64,
+    alloc_count: usize,
+    alloc_bytes: usize,
+}
+
+impl PhaseStats {
+    fn now() -> Self {
+        Self {
+            time_ns: clock_now_ns(),
+            alloc_count: ALLOC_COUNT.load(Ordering::Relaxed),
+            alloc_bytes: ALLOC_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    fn add(&mut self, other: PhaseStats) {
+        self.time_ns += other.time_ns;
+        self.alloc_count += other.alloc_count;
+        self.alloc_bytes += other.alloc_bytes;
+    }
+
+    fn since(&self, start: &PhaseStats) -> PhaseStats {
+        PhaseStats {
+            time_ns: self.time_ns - start.time_ns,
+            alloc_count: self.alloc_count - start.alloc_count,
+            alloc_bytes: self.alloc_bytes - start.alloc_bytes,
+        }
+    }
+
+    fn minus(&self, other: &PhaseStats) -> PhaseStats {
+        PhaseStats {
+            time_ns: self.time_ns.saturating_sub(other.time_ns),
+            alloc_count: self.alloc_count.saturating_sub(other.alloc_count),
+            alloc_bytes: self.alloc_bytes.saturating_sub(other.alloc_bytes),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Timings {
+    // totals of each phase in order of the first run, nested phases are excluded from the outer one
+    phases: Vec<(&'static str, PhaseStats)>,
+    // stats at the start of each running phase and totals of its nested phases
+    running: Vec<(PhaseStats, PhaseStats)>,
+}
+
+#[thread_local]
+static TIMINGS: RefCell<Option<Timings>> = RefCell::new(None);
+
+pub fn timings_enable() {
+    *TIMINGS.borrow_mut() = Some(Timings::default());
+}
+
+pub fn measure_phase<T>(phase: &'static str, run: impl FnOnce() -> T) -> T {
+    if TIMINGS.borrow().is_none() {
+        return run();
+    }
+
+    if let Some(timings) = &mut *TIMINGS.borrow_mut() {
+        timings
+            .running
+            .push((PhaseStats::now(), PhaseStats::default()));
+    }
+
+    let result = run();
+
+    if let Some(timings) = &mut *TIMINGS.borrow_mut() {
+        let (start, nested) = timings.running.pop().unwrap();
+        let total = PhaseStats::now().since(&start);
+        if let Some((_, parent_nested)) = timings.running.last_mut() {
+            parent_nested.add(total);
+        }
+
+        let own = total.minus(&nested);
+        match timings.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, stats)) => stats.add(own),
+            None => timings.phases.push((phase, own)),
+        }
+    }
+
+    result
+}
+
+pub fn timings_print() {
+    let Some(timings) = &*TIMINGS.borrow() else {
+        return;
+    };
+
+    let format_line = |phase: &str, stats: &PhaseStats| {
+        format!(
+            "{phase:<10} {:>9}.{:03}ms {:>10} {:>14}\n",
+            stats.time_ns / 1_000_000,
+            stats.time_ns / 1_000 % 1_000,
+            stats.alloc_count,
+            stats.alloc_bytes,
+        )
+    };
+
+    let mut report = format!(
+        "{:<10} {:>15} {:>10} {:>14}\n",
+        "phase", "time", "allocs", "alloc bytes"
+    );
+    let mut total = PhaseStats::default();
+    for (phase, stats) in &timings.phases {
+        report.push_str(&format_line(phase, stats));
+        total.add(*stats);
+    }
+    report.push_str(&format_line("total", &total));
+    report.push_str(&format!(
+        "peak memory: {} bytes\n",
+        PEAK_LIVE_BYTES.load(Ordering::Relaxed)
+    ));
+
+    stderr_write(report);
+}
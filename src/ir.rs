@@ -1,34 +1,207 @@
-use crate::{core::*, parser::*, wasm::*};
-use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec, vec::Vec};
+use crate::{core::*, mangling::*, parser::*, wasm::*};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    rc::Rc,
+    string::String,
+    vec,
+    vec::Vec,
+};
 use core::cell::RefCell;
 
+pub const DATA_LAYOUT_GLOBALS: [&str; 2] = ["__data_end", "__heap_base"];
+pub const HEAP_BASE_ALIGNMENT: u32 = 16;
+pub const SHADOW_STACK_SIZE: u32 = 65_536;
+pub const STACK_FRAME_ALIGNMENT: u32 = 16;
+pub const MAX_FN_RESULT_COMPONENTS: u32 = 4;
+pub const MAX_MACRO_EXPANSION_DEPTH: u32 = 64;
+// parsing and every later pass recurse once per level, these keep the compiler
+//   within its wasm stack (1 MiB by default)
+pub const MAX_EXPR_NESTING_DEPTH: u32 = 256;
+pub const MAX_INCLUDE_DEPTH: u32 = 128;
+
+// can be disabled per file with `#pragma allow <name>`
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoWarning {
+    UnreachableCode,
+    UnusedParam,
+    UnusedResult,
+    WasiAbi,
+}
+
+impl LoWarning {
+    pub fn from_name(name: &str) -> Option<LoWarning> {
+        match name {
+            "unreachable_code" => Some(LoWarning::UnreachableCode),
+            "unused_param" => Some(LoWarning::UnusedParam),
+            "unused_result" => Some(LoWarning::UnusedResult),
+            "wasi_abi" => Some(LoWarning::WasiAbi),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ModuleContext<'a> {
     pub mode: CompilerMode,
     pub wasm_module: RefCell<WasmModule>,
-    pub fn_defs: BTreeMap<String, FnDef>,
+    pub fn_defs: AtomMap<FnDef>,
     pub fn_bodies: RefCell<Vec<FnBody>>,
     pub fn_exports: Vec<FnExport>,
+    pub export_names: BTreeMap<String, LoLocation>, // of every export, to report duplicates
     pub memories: BTreeMap<String, u32>,
-    pub struct_defs: Vec<StructDef>,
-    pub globals: BTreeMap<String, GlobalDef>,
+    pub tables: Vec<TableDef>,
+    pub struct_defs: RefCell<Vec<Rc<StructDef>>>, // slice structs are registered on first use
+    pub struct_def_indices: RefCell<AtomMap<usize>>, // indices of `struct_defs` by name
+    pub globals: AtomMap<GlobalDef>,
     pub indicies_of_data_size_globals: Vec<usize>,
     pub imported_fns_count: u32,
     pub data_size: RefCell<u32>,
+    pub data_start_override: Option<u32>,
+    pub data_layout_globals: RefCell<BTreeMap<String, u32>>,
+    pub export_data_layout_globals: bool,
+    pub stack_pointer_global: RefCell<Option<u32>>,
+    pub features: WasmFeatures,
+    pub checks: RuntimeChecks,
+    pub max_fn_result_components: u32,
+    pub sret_buffer_size: u32,
+    pub sret_buffer_ptr: u32,
     pub string_pool: RefCell<BTreeMap<String, u32>>,
-    pub constants: RefCell<BTreeMap<String, ConstDef>>,
-    pub included_modules: BTreeMap<String, u32>,
+    pub string_bytes_saved: RefCell<u32>,
+    pub fn_names: Vec<Atom>, // indexed by own fn index
+    pub fn_local_names: BTreeMap<u32, Vec<WasmLocalName>>,
+    pub emit_source_locs: bool,
+    pub unmangled_exports: bool, // fns are exported by their source names, see `mangle_name`
+    pub constants: RefCell<AtomMap<ConstDef>>,
+    pub included_modules: AtomMap<u32>,
+    pub include_depth: u32, // of the file being parsed, the root file is at 1
     pub macros: BTreeMap<String, MacroDef>,
+    pub alloc_fn: Option<String>, // marked with `@alloc`, called by `__alloc`
+    pub free_fn: Option<String>,  // marked with `@free`, called by `__free`
+    pub start_fn: Option<String>, // marked with `@start`, called on instantiation
+    pub flags_types: BTreeSet<Atom>, // structs declared with `flags`
+    pub union_types: BTreeSet<Atom>, // structs with all fields at offset 0
+    pub distinct_types: AtomMap<LoType>, // `type <name> = distinct <inner type>`
     pub type_scope: LoTypeScope<'a>,
+    pub warnings: RefCell<Vec<LoError>>,
+    pub allowed_warnings: BTreeSet<(Atom, LoWarning)>, // by file name
+    pub error_ids: RefCell<BTreeMap<String, u32>>,     // discriminants of error union variants
+    pub inspect_range: Option<InspectRange>,           // fn bodies outside of it are not compiled
 }
 
 impl<'a> ModuleContext<'a> {
-    pub fn get_struct_def(&self, struct_name: &str) -> Option<&StructDef> {
-        self.struct_defs.iter().find(|s| s.name == struct_name)
+    pub fn get_fn_def(&self, fn_name: &str) -> Option<&FnDef> {
+        self.fn_defs.get_by_name(fn_name)
     }
 
-    pub fn get_struct_def_mut(&mut self, struct_name: &str) -> Option<&mut StructDef> {
-        self.struct_defs.iter_mut().find(|s| s.name == struct_name)
+    pub fn warn(&self, warning: LoWarning, loc: LoLocation, message: &str) {
+        if self.allowed_warnings.contains(&(loc.file_name(), warning)) {
+            return;
+        }
+
+        self.warnings.borrow_mut().push(LoError {
+            message: format!("Warning: {message}"),
+            loc,
+        });
+    }
+
+    pub fn get_struct_def(&self, struct_name: impl Into<Atom>) -> Option<Rc<StructDef>> {
+        let struct_index = *self.struct_def_indices.borrow().get(struct_name.into())?;
+        Some(self.struct_defs.borrow()[struct_index].clone())
+    }
+
+    pub fn insert_struct_def(&self, struct_def: StructDef) {
+        let mut struct_defs = self.struct_defs.borrow_mut();
+        self.struct_def_indices
+            .borrow_mut()
+            .insert(Atom::intern(&struct_def.name), struct_defs.len());
+        struct_defs.push(Rc::new(struct_def));
+    }
+
+    // `&[T]` is a struct of `data: *&T` and `size: u32`, defined on first use
+    pub fn get_slice_type(&self, item_type: LoType) -> LoType {
+        let name = Atom::intern(&format!("&[{item_type}]"));
+        if self.get_struct_def(name).is_none() {
+            self.insert_struct_def(StructDef {
+                name: String::from(name.as_str()),
+                fields: vec![
+                    StructField {
+                        name: String::from("data"),
+                        is_private: false,
+                        value_type: LoType::Pointer {
+                            pointee: Box::new(item_type),
+                            is_readonly: false,
+                        },
+                        field_index: 0,
+                        byte_offset: 0,
+                        loc: LoLocation::internal(),
+                    },
+                    StructField {
+                        name: String::from("size"),
+                        is_private: false,
+                        value_type: LoType::U32,
+                        field_index: 1,
+                        byte_offset: 4,
+                        loc: LoLocation::internal(),
+                    },
+                ],
+                layout: StructLayout::default(),
+                byte_length: 8,
+                fully_defined: true,
+                doc: None,
+                loc: LoLocation::internal(),
+            });
+        }
+
+        LoType::StructInstance { name }
+    }
+
+    pub fn is_flags_type(&self, lo_type: &LoType) -> bool {
+        let LoType::StructInstance { name } = lo_type else {
+            return false;
+        };
+        self.flags_types.contains(name)
+    }
+
+    // every error type gets a module wide discriminant so error unions convert without remapping
+    pub fn get_error_id(&self, err_type: &LoType) -> u32 {
+        let mut error_ids = self.error_ids.borrow_mut();
+        let next_id = error_ids.len() as u32 + 1; // 0 means no error
+        *error_ids.entry(format!("{err_type}")).or_insert(next_id)
+    }
+
+    pub fn is_union_type(&self, lo_type: &LoType) -> bool {
+        let LoType::StructInstance { name } = lo_type else {
+            return false;
+        };
+        self.union_types.contains(name)
+    }
+
+    pub fn get_distinct_inner_type(&self, lo_type: &LoType) -> Option<LoType> {
+        let LoType::StructInstance { name } = lo_type else {
+            return None;
+        };
+        self.distinct_types.get(*name).cloned()
+    }
+
+    pub fn get_slice_item_type(&self, lo_type: &LoType) -> Option<LoType> {
+        let LoType::StructInstance { name } = lo_type else {
+            return None;
+        };
+        if !name.as_str().starts_with("&[") {
+            return None;
+        }
+
+        let struct_def = self.get_struct_def(*name)?;
+        let LoType::Pointer {
+            pointee: item_type, ..
+        } = &struct_def.fields[0].value_type
+        else {
+            return None;
+        };
+
+        Some(*item_type.clone())
     }
 
     pub fn insert_fn_type(&self, fn_type: WasmFnType) -> u32 {
@@ -43,11 +216,29 @@ impl<'a> ModuleContext<'a> {
         wasm_module.types.len() as u32 - 1
     }
 
-    pub fn append_data(&self, bytes: Vec<u8>) -> u32 {
-        let bytes_ptr = *self.data_size.borrow();
-        let bytes_len = bytes.len() as u32;
+    pub fn append_data(&self, mut bytes: Vec<u8>, align: u32) -> u32 {
+        let data_size = *self.data_size.borrow();
+        let bytes_ptr = data_size.next_multiple_of(align);
+
+        *self.data_size.borrow_mut() = bytes_ptr + bytes.len() as u32;
+
+        // extend previous segment (padding included) if it ends right where the new data starts
+        let mut wasm_module = self.wasm_module.borrow_mut();
+        if let Some(WasmData::Active {
+            offset,
+            bytes: prev_bytes,
+        }) = wasm_module.datas.last_mut()
+        {
+            if let [WasmInstr::I32Const { value }] = offset.instrs[..] {
+                if value as u32 + prev_bytes.len() as u32 == data_size {
+                    prev_bytes.resize((bytes_ptr - value as u32) as usize, 0);
+                    prev_bytes.append(&mut bytes);
+                    return bytes_ptr;
+                }
+            }
+        }
 
-        self.wasm_module.borrow_mut().datas.push(WasmData::Active {
+        wasm_module.datas.push(WasmData::Active {
             offset: WasmExpr {
                 instrs: vec![WasmInstr::I32Const {
                     value: bytes_ptr as i32,
@@ -56,13 +247,210 @@ impl<'a> ModuleContext<'a> {
             bytes,
         });
 
-        *self.data_size.borrow_mut() += bytes_len;
-
         bytes_ptr
     }
 
+    // space for data that is only written at runtime, so no segment is emitted
+    pub fn reserve_data(&self, size: u32, align: u32) -> u32 {
+        let ptr = self.data_size.borrow().next_multiple_of(align);
+        *self.data_size.borrow_mut() = ptr + size;
+        ptr
+    }
+
+    // reuses bytes of already pooled strings when `value` is contained in one of them
+    //   or when it starts with the tail of the string at the end of the data
+    pub fn append_string(&self, value: &str) -> u32 {
+        let data_size = *self.data_size.borrow();
+
+        let mut tail_overlap = 0;
+        for (pooled_value, pooled_ptr) in self.string_pool.borrow().iter() {
+            if let Some(offset) = pooled_value.find(value) {
+                *self.string_bytes_saved.borrow_mut() += value.len() as u32;
+                return pooled_ptr + offset as u32;
+            }
+
+            if pooled_ptr + pooled_value.len() as u32 == data_size {
+                tail_overlap = (1..=value.len().min(pooled_value.len()))
+                    .rev()
+                    .find(|len| pooled_value.as_bytes().ends_with(&value.as_bytes()[..*len]))
+                    .unwrap_or(0);
+            }
+        }
+
+        *self.string_bytes_saved.borrow_mut() += tail_overlap as u32;
+        self.append_data(value.as_bytes()[tail_overlap..].to_vec(), 1) - tail_overlap as u32
+    }
+
+    // `__data_end` and `__heap_base` are created on first use, values are set in `finalize`
+    pub fn get_data_layout_global(&self, global_name: &str) -> Option<u32> {
+        if !DATA_LAYOUT_GLOBALS.contains(&global_name) {
+            return None;
+        }
+
+        if let Some(global_index) = self.data_layout_globals.borrow().get(global_name) {
+            return Some(*global_index);
+        }
+
+        let mut wasm_module = self.wasm_module.borrow_mut();
+        let global_index = wasm_module.globals.len() as u32;
+        wasm_module.globals.push(WasmGlobal {
+            kind: WasmGlobalKind {
+                value_type: WasmType::I32,
+                mutable: false,
+            },
+            initial_value: WasmExpr {
+                instrs: vec![WasmInstr::I32Const { value: 0 }], // stub
+            },
+        });
+
+        self.data_layout_globals
+            .borrow_mut()
+            .insert(String::from(global_name), global_index);
+
+        Some(global_index)
+    }
+
+    // shadow stack lives between static data and `__heap_base`, growing down
+    pub fn get_stack_pointer_global(&self) -> u32 {
+        if let Some(global_index) = *self.stack_pointer_global.borrow() {
+            return global_index;
+        }
+
+        let mut wasm_module = self.wasm_module.borrow_mut();
+        let global_index = wasm_module.globals.len() as u32;
+        wasm_module.globals.push(WasmGlobal {
+            kind: WasmGlobalKind {
+                value_type: WasmType::I32,
+                mutable: true,
+            },
+            initial_value: WasmExpr {
+                instrs: vec![WasmInstr::I32Const { value: 0 }], // stub
+            },
+        });
+
+        *self.stack_pointer_global.borrow_mut() = Some(global_index);
+
+        global_index
+    }
+
+    // results with more components are returned through a pointer passed as the first param
+    pub fn uses_sret(&self, output: &LoType) -> bool {
+        output.emit_components(self, &mut vec![]) > self.max_fn_result_components
+    }
+
+    pub fn get_export_name(&self, fn_name: &str) -> String {
+        if self.unmangled_exports {
+            return String::from(fn_name);
+        }
+
+        mangle_name(fn_name)
+    }
+
     pub fn get_loc_module_index(&self, loc: &LoLocation) -> u32 {
-        *self.included_modules.get(&loc.file_name as &str).unwrap() // safe
+        *self.included_modules.get(loc.file_name()).unwrap() // safe
+    }
+}
+
+// `<file>[:<first line>-<last line>]` of `--inspect-range`, lines are 1-based and inclusive
+pub struct InspectRange {
+    pub file_name: Atom,
+    pub lines: Option<(usize, usize)>,
+}
+
+impl InspectRange {
+    pub fn parse(range: &str) -> Result<Self, String> {
+        let invalid_range = || {
+            format!("Invalid inspect range: {range}, expected <file>[:<first line>-<last line>]")
+        };
+
+        let mut file_name = range;
+        let mut lines = None;
+        if let Some((path, line_range)) = range.rsplit_once(':') {
+            let Some((first, last)) = line_range.split_once('-') else {
+                return Err(invalid_range());
+            };
+            let (Ok(first), Ok(last)) = (first.parse::<usize>(), last.parse::<usize>()) else {
+                return Err(invalid_range());
+            };
+            if first == 0 || last < first {
+                return Err(invalid_range());
+            }
+
+            file_name = path;
+            lines = Some((first, last));
+        }
+
+        let file_name = resolve_include_path(file_name, &LoLocation::internal().file_name());
+
+        Ok(Self {
+            file_name: Atom::intern(&file_name),
+            lines,
+        })
+    }
+
+    // `start` and `end` are the first and the last token of the checked code
+    pub fn overlaps(&self, start: &LoLocation, end: &LoLocation) -> bool {
+        if start.file_name() != self.file_name {
+            return false;
+        }
+
+        let Some((first, last)) = self.lines else {
+            return true;
+        };
+
+        start.pos().line <= last && end.end_pos().line >= first
+    }
+}
+
+// checks inserted into the generated code, failed ones call the `panic` fn of the program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuntimeCheck {
+    DivByZero,
+    Bounds,
+}
+
+impl RuntimeCheck {
+    pub const ALL: [RuntimeCheck; 2] = [RuntimeCheck::DivByZero, RuntimeCheck::Bounds];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RuntimeCheck::DivByZero => "div-by-zero",
+            RuntimeCheck::Bounds => "bounds",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        RuntimeCheck::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RuntimeChecks {
+    enabled: u32, // bit per `RuntimeCheck`
+}
+
+impl RuntimeChecks {
+    pub fn has(&self, check: RuntimeCheck) -> bool {
+        self.enabled & (1 << check as u32) != 0
+    }
+
+    pub fn enable(&mut self, check: RuntimeCheck) {
+        self.enabled |= 1 << check as u32;
+    }
+
+    // comma separated check names
+    pub fn apply_list(&mut self, list: &str) -> Result<(), String> {
+        for name in list.split(',') {
+            let Some(check) = RuntimeCheck::parse(name) else {
+                let known = RuntimeCheck::ALL.map(|c| c.name()).join(", ");
+                return Err(format!(
+                    "Unknown runtime check: {name}, expected one of: {known}"
+                ));
+            };
+            self.enable(check);
+        }
+
+        Ok(())
     }
 }
 
@@ -74,10 +462,36 @@ pub struct LoFnType {
 
 pub struct FnContext<'a> {
     pub module: &'a ModuleContext<'a>,
+    pub fn_name: &'a str,
     pub lo_fn_type: &'a LoFnType,
     pub locals_last_index: u32,
     pub non_arg_wasm_locals: Vec<WasmType>,
     pub defers: Vec<LoInstr>,
+    pub local_names: Vec<WasmLocalName>,
+    pub address_taken_locals: Vec<String>,
+    pub stack_frame_local: Option<u32>, // followed by a local with previous stack pointer
+    pub stack_frame_size: u32,
+    pub macro_depth: u32,
+    pub expr_depth: u32, // of the IR tree being built, see `MAX_EXPR_NESTING_DEPTH`
+    pub loop_breaks: Vec<bool>, // one per enclosing loop, set once it's broken out of
+}
+
+impl<'a> FnContext<'a> {
+    // multi-component locals (like structs) get a name per component: `p.0`, `p.1`, ...
+    pub fn add_local_name(&mut self, name: &str, local_index: u32, comp_count: u32) {
+        for comp_index in 0..comp_count {
+            let name = if comp_count == 1 {
+                String::from(name)
+            } else {
+                format!("{name}.{comp_index}")
+            };
+
+            self.local_names.push(WasmLocalName {
+                local_index: local_index + comp_index,
+                name,
+            });
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -86,6 +500,7 @@ pub enum LoBlockKind {
     Block,
     Loop,
     ForLoop,
+    Macro, // macro expansion root, emits no wasm block and hides the caller's scope
 }
 
 impl Default for LoBlockKind {
@@ -97,46 +512,70 @@ impl Default for LoBlockKind {
 #[derive(Default)]
 pub struct Block<'a> {
     pub block_kind: LoBlockKind,
-    pub locals: BTreeMap<String, LocalDef>,
-    pub macro_args: Option<BTreeMap<String, LoInstr>>,
+    pub locals: AtomMap<LocalDef>,
+    pub macro_args: Option<AtomMap<LoInstr>>,
     pub type_scope: Option<LoTypeScope<'a>>,
     pub parent: Option<&'a Block<'a>>,
+    pub scope_parent: Option<&'a Block<'a>>, // closest ancestor that has names to resolve
 }
 
 impl<'a> Block<'a> {
     pub fn child_of(ctx: &'a ModuleContext, parent: &'a Block<'a>) -> Block<'a> {
         Block {
             parent: Some(parent),
+            scope_parent: parent.scope_link(),
             type_scope: Some(LoTypeScope::default().with_parent(ctx, parent)),
             ..Default::default()
         }
     }
 
+    // the parent is borrowed while its children live so it can't gain locals,
+    //   empty blocks are skipped to keep lookups in deeply nested code short
+    fn scope_link(&'a self) -> Option<&'a Block<'a>> {
+        if self.locals.is_empty()
+            && self.macro_args.is_none()
+            && self.block_kind != LoBlockKind::Macro
+        {
+            return self.scope_parent;
+        }
+
+        Some(self)
+    }
+
     pub fn of_kind(mut self, block_type: LoBlockKind) -> Self {
         self.block_kind = block_type;
         self
     }
 
     pub fn get_local(&self, local_name: &str) -> Option<&LocalDef> {
+        self.find_local(Atom::lookup(local_name)?)
+    }
+
+    fn find_local(&self, local_name: Atom) -> Option<&LocalDef> {
         if let Some(local_def) = self.locals.get(local_name) {
             return Some(local_def);
         }
 
-        if let Some(parent) = self.parent {
-            return parent.get_local(local_name);
+        if self.block_kind == LoBlockKind::Macro {
+            return None;
+        }
+
+        if let Some(parent) = self.scope_parent {
+            return parent.find_local(local_name);
         }
 
         None
     }
 
     pub fn get_own_local(&self, local_name: &str) -> Option<&LocalDef> {
+        let local_name = Atom::lookup(local_name)?;
         if let Some(local_def) = self.locals.get(local_name) {
             return Some(local_def);
         }
 
         if self.block_kind == LoBlockKind::Function {
-            if let Some(parent) = self.parent {
-                return parent.get_local(local_name);
+            if let Some(parent) = self.scope_parent {
+                return parent.find_local(local_name);
             }
         }
 
@@ -144,14 +583,22 @@ impl<'a> Block<'a> {
     }
 
     pub fn get_macro_arg(&self, arg_name: &str) -> Option<&LoInstr> {
+        self.find_macro_arg(Atom::lookup(arg_name)?)
+    }
+
+    fn find_macro_arg(&self, arg_name: Atom) -> Option<&LoInstr> {
         if let Some(macro_args) = &self.macro_args {
             if let Some(macro_value) = macro_args.get(arg_name) {
                 return Some(macro_value);
             }
         }
 
-        if let Some(parent) = self.parent {
-            return parent.get_macro_arg(arg_name);
+        if self.block_kind == LoBlockKind::Macro {
+            return None;
+        }
+
+        if let Some(parent) = self.scope_parent {
+            return parent.find_macro_arg(arg_name);
         }
 
         return None;
@@ -166,14 +613,18 @@ pub struct BlockContext<'a, 'b> {
 
 #[derive(Default)]
 pub struct LoTypeScope<'a> {
-    pub types: BTreeMap<String, LoType>,
+    pub types: AtomMap<LoType>,
     pub parent: Option<&'a LoTypeScope<'a>>,
 }
 
 impl<'a> LoTypeScope<'a> {
     pub fn with_parent(mut self, ctx: &'a ModuleContext, parent: &'a Block<'a>) -> Self {
         if let Some(parent) = &parent.type_scope {
-            self.parent = Some(parent);
+            // same as with block locals, scopes without own types are skipped
+            self.parent = match parent.types.is_empty() {
+                true => parent.parent,
+                false => Some(parent),
+            };
         } else {
             self.parent = Some(&ctx.type_scope);
         }
@@ -182,19 +633,23 @@ impl<'a> LoTypeScope<'a> {
     }
 
     pub fn get(&self, name: &str) -> Option<&LoType> {
+        self.get_atom(Atom::lookup(name)?)
+    }
+
+    fn get_atom(&self, name: Atom) -> Option<&LoType> {
         if let Some(type_) = self.types.get(name) {
             return Some(type_);
         }
 
         if let Some(parent) = &self.parent {
-            return parent.get(name);
+            return parent.get_atom(name);
         }
 
         None
     }
 
-    pub fn insert(&mut self, name: String, type_: LoType) {
-        self.types.insert(name, type_);
+    pub fn insert(&mut self, name: impl Into<Atom>, type_: LoType) {
+        self.types.insert(name.into(), type_);
     }
 }
 
@@ -213,40 +668,82 @@ pub enum LoType {
     U64,
     I64,
     F64,
-    Pointer(Box<LoType>),
+    ExternRef, // opaque host value, only lives in locals, globals and fn params/results
+    Pointer {
+        pointee: Box<LoType>,
+        is_readonly: bool, // `&readonly T`, can't be written through
+    },
     Tuple(Vec<LoType>),
     StructInstance {
-        name: String,
+        name: Atom,
     },
     Result {
         ok_type: Box<LoType>,
         err_type: Box<LoType>,
     },
+    // `E1 | E2` error of a `Result`, stored as the variant's payload and its discriminant
+    ErrorUnion {
+        variants: Vec<LoType>,
+    },
     MacroTypeArg {
         name: String,
     },
 }
 
 impl LoType {
+    // `&T` can be passed where `&readonly T` is expected, but not the other way around
+    pub fn is_assignable_to(&self, wanted: &LoType) -> bool {
+        if let (
+            LoType::Pointer { pointee, .. },
+            LoType::Pointer {
+                pointee: wanted_pointee,
+                is_readonly: true,
+            },
+        ) = (self, wanted)
+        {
+            return pointee == wanted_pointee;
+        }
+
+        self == wanted
+    }
+
+    pub fn error_union_repr() -> LoType {
+        LoType::Tuple(vec![LoType::U32, LoType::U32])
+    }
+
     pub fn deref_rec(&self) -> &LoType {
         match self {
-            LoType::Pointer(pointee) => pointee.deref_rec(),
+            LoType::Pointer { pointee, .. } => pointee.deref_rec(),
             other => other,
         }
     }
 
-    pub fn resolve_macro_type_args(&self, type_scope: &LoTypeScope) -> Result<LoType, LoError> {
+    pub fn resolve_macro_type_args(
+        &self,
+        ctx: &ModuleContext,
+        type_scope: &LoTypeScope,
+    ) -> Result<LoType, LoError> {
         Ok(match self {
-            Self::Pointer(pointee) => {
-                Self::Pointer(Box::new(pointee.resolve_macro_type_args(type_scope)?))
-            }
+            Self::Pointer {
+                pointee,
+                is_readonly,
+            } => Self::Pointer {
+                pointee: Box::new(pointee.resolve_macro_type_args(ctx, type_scope)?),
+                is_readonly: *is_readonly,
+            },
             Self::Tuple(items) => {
                 let mut resolved_items = Vec::new();
                 for item in items {
-                    resolved_items.push(item.resolve_macro_type_args(type_scope)?);
+                    resolved_items.push(item.resolve_macro_type_args(ctx, type_scope)?);
                 }
                 Self::Tuple(resolved_items)
             }
+            Self::StructInstance { .. } => {
+                let Some(item_type) = ctx.get_slice_item_type(self) else {
+                    return Ok(self.clone());
+                };
+                ctx.get_slice_type(item_type.resolve_macro_type_args(ctx, type_scope)?)
+            }
             Self::MacroTypeArg { name } => {
                 if let Some(t) = type_scope.get(name) {
                     return Ok(t.clone());
@@ -274,7 +771,15 @@ impl core::fmt::Display for LoType {
             LoType::U64 => f.write_str("u64"),
             LoType::I64 => f.write_str("i64"),
             LoType::F64 => f.write_str("f64"),
-            LoType::Pointer(pointee) => f.write_fmt(format_args!("&{pointee}")),
+            LoType::ExternRef => f.write_str("externref"),
+            LoType::Pointer {
+                pointee,
+                is_readonly: false,
+            } => f.write_fmt(format_args!("&{pointee}")),
+            LoType::Pointer {
+                pointee,
+                is_readonly: true,
+            } => f.write_fmt(format_args!("&readonly {pointee}")),
             LoType::Tuple(types) => {
                 f.write_str("(")?;
                 let mut types_iter = types.iter();
@@ -287,10 +792,20 @@ impl core::fmt::Display for LoType {
                 }
                 f.write_str(")")
             }
-            LoType::StructInstance { name } => f.write_str(name),
+            LoType::StructInstance { name } => f.write_str(name.as_str()),
             LoType::Result { ok_type, err_type } => {
                 f.write_fmt(format_args!("Result<{ok_type}, {err_type}>"))
             }
+            LoType::ErrorUnion { variants } => {
+                let mut variants_iter = variants.iter();
+                if let Some(variant) = variants_iter.next() {
+                    f.write_fmt(format_args!("{variant}"))?;
+                }
+                for variant in variants_iter {
+                    f.write_fmt(format_args!(" | {variant}"))?;
+                }
+                Ok(())
+            }
             LoType::MacroTypeArg { name } => f.write_str(name),
         }
     }
@@ -311,10 +826,11 @@ impl LoType {
     pub fn to_wasm_type(&self) -> Option<WasmType> {
         Some(match self {
             LoType::Bool | LoType::U8 | LoType::I8 | LoType::U16 => WasmType::I32,
-            LoType::I16 | LoType::U32 | LoType::I32 | LoType::Pointer(_) => WasmType::I32,
+            LoType::I16 | LoType::U32 | LoType::I32 | LoType::Pointer { .. } => WasmType::I32,
             LoType::F32 => WasmType::F32,
             LoType::U64 | LoType::I64 => WasmType::I64,
             LoType::F64 => WasmType::F64,
+            LoType::ExternRef => WasmType::ExternRef,
             _ => return None,
         })
     }
@@ -331,8 +847,11 @@ impl LoType {
             LoType::Void => {}
             LoType::Bool | LoType::U8 | LoType::I8 => byte_len = Some(1),
             LoType::U16 | LoType::I16 => byte_len = Some(2),
-            LoType::U32 | LoType::I32 | LoType::F32 | LoType::Pointer(_) => byte_len = Some(4),
+            LoType::U32 | LoType::I32 | LoType::F32 | LoType::Pointer { .. } => byte_len = Some(4),
             LoType::U64 | LoType::I64 | LoType::F64 => byte_len = Some(8),
+            LoType::ExternRef => {
+                return Err(format!("Cannot place {self} in memory"));
+            }
             LoType::Tuple(types) => {
                 for lo_type in types {
                     lo_type.emit_sized_component_stats(ctx, stats, components)?;
@@ -342,16 +861,23 @@ impl LoType {
                 // safe, validation is done when creating StructInstance
                 let struct_def = ctx.get_struct_def(name).unwrap();
 
+                // offsets are relative to the struct start so padding matches the definition
+                let struct_start = stats.byte_length;
                 for field in &struct_def.fields {
+                    stats.byte_length = struct_start + field.byte_offset;
                     field
                         .value_type
                         .emit_sized_component_stats(ctx, stats, components)?;
                 }
+                stats.byte_length = struct_start + struct_def.byte_length;
             }
             LoType::Result { ok_type, err_type } => {
                 ok_type.emit_sized_component_stats(ctx, stats, components)?;
                 err_type.emit_sized_component_stats(ctx, stats, components)?;
             }
+            LoType::ErrorUnion { .. } => {
+                LoType::error_union_repr().emit_sized_component_stats(ctx, stats, components)?;
+            }
             LoType::MacroTypeArg { name } => {
                 return Err(format!("Cannot get size of macro arg: {name}"));
             }
@@ -401,6 +927,9 @@ impl LoType {
                 let err_count = err_type.emit_components(ctx, components);
                 ok_count + err_count
             }
+            LoType::ErrorUnion { .. } => {
+                LoType::error_union_repr().emit_components(ctx, components)
+            }
             _ => unreachable!(),
         }
     }
@@ -412,6 +941,37 @@ impl LoType {
         Ok(stats)
     }
 
+    // alignment of the largest primitive component, raised by `@align` of structs
+    pub fn get_alignment(&self, ctx: &ModuleContext) -> Result<u32, String> {
+        match self {
+            LoType::Tuple(types) => {
+                let mut alignment = 1;
+                for lo_type in types {
+                    alignment = alignment.max(lo_type.get_alignment(ctx)?);
+                }
+                Ok(alignment)
+            }
+            LoType::StructInstance { name } => {
+                // safe, validation is done when creating StructInstance
+                let struct_def = ctx.get_struct_def(name).unwrap();
+                if struct_def.layout == StructLayout::Packed {
+                    return Ok(1);
+                }
+
+                let mut alignment = struct_def.layout.min_alignment();
+                for field in &struct_def.fields {
+                    alignment = alignment.max(field.value_type.get_alignment(ctx)?);
+                }
+                Ok(alignment)
+            }
+            LoType::Result { ok_type, err_type } => Ok(ok_type
+                .get_alignment(ctx)?
+                .max(err_type.get_alignment(ctx)?)),
+            LoType::ErrorUnion { .. } => LoType::error_union_repr().get_alignment(ctx),
+            _ => Ok(self.sized_comp_stats(ctx)?.byte_length.max(1)),
+        }
+    }
+
     pub fn to_load_kind(&self) -> Result<WasmLoadKind, String> {
         match self {
             LoType::Bool => return Ok(WasmLoadKind::I32U8),
@@ -425,12 +985,23 @@ impl LoType {
             LoType::U64 => return Ok(WasmLoadKind::I64),
             LoType::I64 => return Ok(WasmLoadKind::I64),
             LoType::F64 => return Ok(WasmLoadKind::F64),
-            LoType::Pointer(_) => return Ok(WasmLoadKind::I32),
+            LoType::Pointer { .. } => return Ok(WasmLoadKind::I32),
             _ => {}
         };
         return Err(format!("Unsupported type for load: {self:?}"));
     }
 
+    // atomics only zero extend so signed types narrower than 32 bits are not supported
+    pub fn to_atomic_kind(&self) -> Result<WasmStoreKind, String> {
+        match self {
+            LoType::U8 => Ok(WasmStoreKind::I32U8),
+            LoType::U16 => Ok(WasmStoreKind::I32U16),
+            LoType::U32 | LoType::I32 | LoType::Pointer { .. } => Ok(WasmStoreKind::I32),
+            LoType::U64 | LoType::I64 => Ok(WasmStoreKind::I64),
+            _ => Err(format!("Unsupported type for atomic access: {self}")),
+        }
+    }
+
     pub fn get_default_value(&self, ctx: &ModuleContext) -> LoInstr {
         match self {
             LoType::Never => LoInstr::Unreachable,
@@ -446,9 +1017,8 @@ impl LoType {
             LoType::U64 => LoInstr::U64Const { value: 0 },
             LoType::I64 => LoInstr::I64Const { value: 0 },
             LoType::F64 => LoInstr::F64Const { value: 0.0 },
-            LoType::Pointer(pointee) => {
-                LoInstr::U32Const { value: 0 }.casted(LoType::Pointer(pointee.clone()))
-            }
+            LoType::ExternRef => LoInstr::ExternRefNull,
+            LoType::Pointer { .. } => LoInstr::U32Const { value: 0 }.casted(self.clone()),
             LoType::Tuple(types) => {
                 let mut values = Vec::new();
                 for item_type in types {
@@ -469,6 +1039,9 @@ impl LoType {
                     err_type.get_default_value(ctx),
                 ],
             },
+            LoType::ErrorUnion { .. } => LoType::error_union_repr()
+                .get_default_value(ctx)
+                .casted(self.clone()),
             LoType::MacroTypeArg { .. } => unreachable!(),
         }
     }
@@ -478,6 +1051,7 @@ pub struct LocalDef {
     pub index: u32,
     pub value_type: LoType,
     pub loc: LoLocation,
+    pub stack_offset: Option<u32>, // offset in the stack frame for address-taken locals
 }
 
 pub struct GlobalDef {
@@ -489,13 +1063,14 @@ pub struct GlobalDef {
 
 pub struct ConstDef {
     pub value: LoInstr,
+    pub doc: Option<String>,
     pub loc: LoLocation,
 }
 
 pub struct FnBody {
     pub fn_index: u32,
     pub type_index: u32,
-    pub locals: BTreeMap<String, LocalDef>,
+    pub locals: AtomMap<LocalDef>,
     pub locals_last_index: u32,
     pub body: LoTokenStream,
 }
@@ -505,17 +1080,61 @@ pub struct FnExport {
     pub out_name: String,
 }
 
+pub struct TableDef {
+    pub name: String,
+    pub fn_names: Vec<String>, // placed at the start of the table in this order
+    pub exported: bool,
+    pub imported: bool, // imported tables come first in the index space
+}
+
 #[derive(Clone)]
 pub struct StructDef {
     pub name: String,
     pub fields: Vec<StructField>,
+    pub layout: StructLayout,
+    pub byte_length: u32,    // includes trailing padding
     pub fully_defined: bool, // used for self-reference checks
+    pub doc: Option<String>,
     pub loc: LoLocation,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum StructLayout {
+    // fields are placed back-to-back, set by `@packed`
+    Packed,
+    // fields are placed at multiples of their alignment, `@align(<align>)` raises
+    // the alignment of the struct itself
+    Aligned { align: u32 },
+}
+
+// natural alignment, padding is added the same way C compilers do
+impl Default for StructLayout {
+    fn default() -> Self {
+        StructLayout::Aligned { align: 1 }
+    }
+}
+
+impl StructLayout {
+    // offset of the next field given the end of the previous one
+    pub fn field_offset(&self, prev_end: u32, field_alignment: u32) -> u32 {
+        match self {
+            StructLayout::Packed => prev_end,
+            StructLayout::Aligned { .. } => prev_end.next_multiple_of(field_alignment),
+        }
+    }
+
+    pub fn min_alignment(&self) -> u32 {
+        match self {
+            StructLayout::Packed => 1,
+            StructLayout::Aligned { align } => *align,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct StructField {
     pub name: String,
+    pub is_private: bool, // only accessible in the defining file and methods of the struct
     pub value_type: LoType,
     pub field_index: u32,
     pub byte_offset: u32,
@@ -524,6 +1143,9 @@ pub struct StructField {
 
 impl core::fmt::Display for StructField {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_private {
+            write!(f, "private ")?;
+        }
         write!(f, "{}: {}", self.name, self.value_type)
     }
 }
@@ -535,6 +1157,8 @@ pub struct FnDef {
     pub fn_params: Vec<FnParam>,
     pub type_index: u32,
     pub type_: LoFnType,
+    pub sret: bool,
+    pub doc: Option<String>,
     pub loc: LoLocation,
 }
 
@@ -644,6 +1268,16 @@ pub enum LoInstr {
         source: Box<LoInstr>,
         num_bytes: Box<LoInstr>,
     },
+    ExternRefNull,
+    ExternRefIsNull {
+        value: Box<LoInstr>,
+    },
+    // atomic memory instr applied to `args`, built by `__atomic_*` intrinsics
+    Atomic {
+        instr: WasmInstr,
+        args: Vec<LoInstr>,
+        value_type: LoType,
+    },
     Load {
         kind: LoType,
         align: u32,
@@ -651,7 +1285,7 @@ pub enum LoInstr {
         address_instr: Box<LoInstr>,
     },
     StructLoad {
-        struct_name: String,
+        struct_name: Atom,
         address_instr: Box<LoInstr>,
         address_local_index: u32,
         base_byte_offset: u32,
@@ -668,7 +1302,7 @@ pub enum LoInstr {
         global_index: u32,
     },
     StructGet {
-        struct_name: String,
+        struct_name: Atom,
         base_index: u32,
         primitive_gets: Vec<LoInstr>,
     },
@@ -727,6 +1361,10 @@ pub enum LoInstr {
         return_type: LoType,
         args: Vec<LoInstr>,
     },
+    ReturnCall {
+        fn_index: u32,
+        args: Vec<LoInstr>,
+    },
     MultiValueEmit {
         values: Vec<LoInstr>,
     },
@@ -734,6 +1372,9 @@ pub enum LoInstr {
         value_type: LoType,
         expr: Box<LoInstr>,
     },
+    SourceLoc {
+        loc: LoLocation,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -777,19 +1418,21 @@ impl LoInstr {
                 LoType::Tuple(types)
             }
             LoInstr::StructLoad { struct_name, .. } | LoInstr::StructGet { struct_name, .. } => {
-                LoType::StructInstance {
-                    name: struct_name.clone(),
-                }
+                LoType::StructInstance { name: *struct_name }
             }
 
             // type-checked in the complier:
             LoInstr::Casted { value_type, .. } => value_type.clone(),
             LoInstr::Set { .. } => LoType::Void,
+            LoInstr::SourceLoc { .. } => LoType::Void,
             LoInstr::Drop { .. } => LoType::Void,
-            LoInstr::Return { .. } => LoType::Never,
+            LoInstr::Return { .. } | LoInstr::ReturnCall { .. } => LoType::Never,
             LoInstr::MemorySize => LoType::I32,
             LoInstr::MemoryGrow { .. } => LoType::I32,
             LoInstr::MemoryCopy { .. } => LoType::Void,
+            LoInstr::ExternRefNull => LoType::ExternRef,
+            LoInstr::ExternRefIsNull { .. } => LoType::Bool,
+            LoInstr::Atomic { value_type, .. } => value_type.clone(),
 
             LoInstr::BinaryOp { kind, lhs, .. } => match kind {
                 WasmBinaryOpKind::I32_EQ
@@ -881,6 +1524,13 @@ pub fn lower_expr(out: &mut Vec<WasmInstr>, expr: &LoInstr) {
             lower_expr(out, num_bytes);
             out.push(WasmInstr::MemoryGrow);
         }
+        LoInstr::ExternRefNull => out.push(WasmInstr::RefNull {
+            ref_type: WasmType::ExternRef,
+        }),
+        LoInstr::ExternRefIsNull { value } => {
+            lower_expr(out, value);
+            out.push(WasmInstr::RefIsNull);
+        }
         LoInstr::MemoryCopy {
             destination,
             source,
@@ -891,6 +1541,10 @@ pub fn lower_expr(out: &mut Vec<WasmInstr>, expr: &LoInstr) {
             lower_expr(out, num_bytes);
             out.push(WasmInstr::MemoryCopy);
         }
+        LoInstr::Atomic { instr, args, .. } => {
+            lower_exprs(out, args);
+            out.push(instr.clone());
+        }
         LoInstr::Load {
             kind,
             align,
@@ -1025,11 +1679,18 @@ pub fn lower_expr(out: &mut Vec<WasmInstr>, expr: &LoInstr) {
                 fn_index: *fn_index,
             });
         }
+        LoInstr::ReturnCall { fn_index, args } => {
+            lower_exprs(out, args);
+            out.push(WasmInstr::ReturnCall {
+                fn_index: *fn_index,
+            });
+        }
         LoInstr::MultiValueEmit { values } => {
             lower_exprs(out, values);
         }
         LoInstr::Casted { expr, .. } => {
             lower_expr(out, expr);
         }
+        LoInstr::SourceLoc { loc } => out.push(WasmInstr::SourceLoc { loc: *loc }),
     }
 }
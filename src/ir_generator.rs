@@ -7,6 +7,8 @@ pub enum LoType {
     Void,
     Bool,
     U32,
+    Str,
+    Struct { name: String },
 }
 
 impl core::fmt::Display for LoType {
@@ -16,6 +18,8 @@ impl core::fmt::Display for LoType {
             LoType::Void => f.write_str("void"),
             LoType::Bool => f.write_str("bool"),
             LoType::U32 => f.write_str("u32"),
+            LoType::Str => f.write_str("str"),
+            LoType::Struct { name } => f.write_str(name),
         }
     }
 }
@@ -59,6 +63,26 @@ pub enum LoExpr {
         args: Vec<LoExpr>,
         return_type: LoType,
     },
+    Let {
+        name: String,
+        value: Box<LoExpr>,
+    },
+    StrConst {
+        value: String,
+    },
+    StrLen {
+        lhs: Box<LoExpr>,
+    },
+    StructNew {
+        struct_name: String,
+        fields: Vec<LoExpr>, // in the order of definition
+    },
+    FieldLoad {
+        lhs: Box<LoExpr>,
+        struct_name: String,
+        field_index: u32,
+        field_type: LoType,
+    },
 }
 
 impl LoExpr {
@@ -74,6 +98,13 @@ impl LoExpr {
             LoExpr::VarLoad { var_type, .. } => var_type.clone(),
             LoExpr::If { .. } => LoType::Void,
             LoExpr::Call { return_type, .. } => return_type.clone(),
+            LoExpr::Let { .. } => LoType::Void,
+            LoExpr::StrConst { .. } => LoType::Str,
+            LoExpr::StrLen { .. } => LoType::U32,
+            LoExpr::StructNew { struct_name, .. } => LoType::Struct {
+                name: struct_name.clone(),
+            },
+            LoExpr::FieldLoad { field_type, .. } => field_type.clone(),
         }
     }
 }
@@ -82,6 +113,7 @@ impl LoExpr {
 pub struct LoScope {
     pub vars: Vec<LoVar>,
     pub fn_defs: Vec<LoFnDef>,
+    pub struct_defs: Vec<LoStructDef>,
 }
 
 pub struct LoVar {
@@ -89,12 +121,19 @@ pub struct LoVar {
     pub type_: LoType,
 }
 
+pub struct LoStructDef {
+    pub name: String,
+    pub fields: Vec<LoVar>,
+    pub loc: LoLocation,
+}
+
 pub struct LoFnDef {
     pub name: String,
     pub inputs: Vec<LoType>,
     pub output: LoType,
     pub exported: bool,
     pub body: CodeBlock,
+    pub loc: LoLocation,
 }
 
 #[derive(Default)]
@@ -139,6 +178,18 @@ impl LoScopeStack {
         None
     }
 
+    fn get_struct_def(&self, name: &str) -> Option<&LoStructDef> {
+        for scope in self.scopes.iter().rev() {
+            for struct_def in &scope.struct_defs {
+                if struct_def.name == *name {
+                    return Some(struct_def);
+                }
+            }
+        }
+
+        None
+    }
+
     fn get_fn_def_mut(&mut self, name: &str) -> Option<&mut LoFnDef> {
         for scope in self.scopes.iter_mut().rev() {
             for var in &mut scope.fn_defs {
@@ -174,19 +225,57 @@ impl IRGenerator {
                 TopLevelExpr::FnDef(fn_def) => self.process_fn_def(fn_def)?,
                 TopLevelExpr::Import(_) => return Err(LoError::todo(file!(), line!())),
                 TopLevelExpr::GlobalDef(_) => return Err(LoError::todo(file!(), line!())),
-                TopLevelExpr::StructDef(_) => return Err(LoError::todo(file!(), line!())),
+                TopLevelExpr::StructDef(struct_def) => self.process_struct_def(struct_def)?,
                 TopLevelExpr::TypeDef(_) => return Err(LoError::todo(file!(), line!())),
                 TopLevelExpr::ConstDef(_) => return Err(LoError::todo(file!(), line!())),
+                TopLevelExpr::ConstGroup(_) => return Err(LoError::todo(file!(), line!())),
+                TopLevelExpr::UnionDef(_) => return Err(LoError::todo(file!(), line!())),
+                TopLevelExpr::FlagsDef(_) => return Err(LoError::todo(file!(), line!())),
                 TopLevelExpr::MemoryDef(_) => return Err(LoError::todo(file!(), line!())),
+                TopLevelExpr::TableDef(_) => return Err(LoError::todo(file!(), line!())),
                 TopLevelExpr::StaticDataStore(_) => return Err(LoError::todo(file!(), line!())),
                 TopLevelExpr::ExportExistingFn(_) => return Err(LoError::todo(file!(), line!())),
                 TopLevelExpr::MacroDef(_) => return Err(LoError::todo(file!(), line!())),
+                TopLevelExpr::CustomSection(_) => return Err(LoError::todo(file!(), line!())),
             }
         }
 
         Ok(())
     }
 
+    fn process_struct_def(&mut self, struct_def: &StructDefExpr) -> Result<(), LoError> {
+        let struct_name = &struct_def.struct_name;
+        if self.ss.get_struct_def(&struct_name.repr).is_some() {
+            return Err(LoError {
+                message: format!("Cannot redefine type {}", struct_name.repr),
+                loc: struct_name.loc,
+            });
+        }
+
+        let mut fields = Vec::<LoVar>::new();
+        for field in &struct_def.fields {
+            if fields.iter().any(|f| f.name == field.field_name) {
+                return Err(LoError {
+                    message: format!("Duplicate struct field name: {}", field.field_name),
+                    loc: field.loc,
+                });
+            }
+
+            fields.push(LoVar {
+                name: field.field_name.clone(),
+                type_: self.build_type(&field.field_type)?,
+            });
+        }
+
+        self.ss.top().struct_defs.push(LoStructDef {
+            name: struct_name.repr.clone(),
+            fields,
+            loc: struct_def.loc,
+        });
+
+        Ok(())
+    }
+
     fn process_fn_def(&mut self, fn_def: &FnDefExpr) -> Result<(), LoError> {
         let return_type = match &fn_def.decl.return_type {
             Some(return_type) => self.build_type(return_type)?,
@@ -204,7 +293,7 @@ impl IRGenerator {
                             "Duplicate function parameter name: {}",
                             fn_param.param_name
                         ),
-                        loc: fn_param.loc.clone(),
+                        loc: fn_param.loc,
                     });
                     continue;
                 }
@@ -231,6 +320,7 @@ impl IRGenerator {
             output: return_type,
             exported: fn_def.exported,
             body: CodeBlock::default(),
+            loc: fn_def.loc,
         });
 
         self.ss.push(scope);
@@ -248,17 +338,30 @@ impl IRGenerator {
 
     fn build_type(&mut self, type_expr: &TypeExpr) -> Result<LoType, LoError> {
         match type_expr {
-            TypeExpr::Named { name } => {
-                if name.repr == "u32" {
-                    return Ok(LoType::U32);
-                }
+            TypeExpr::Named { name } => match name.repr.as_str() {
+                "u32" => Ok(LoType::U32),
+                "bool" => Ok(LoType::Bool),
+                "str" => Ok(LoType::Str),
+                _ => {
+                    if self.ss.get_struct_def(&name.repr).is_some() {
+                        return Ok(LoType::Struct {
+                            name: name.repr.clone(),
+                        });
+                    }
 
-                Err(LoError::todo(file!(), line!()))
-            }
+                    Err(LoError {
+                        message: format!("Unknown type: {}", name.repr),
+                        loc: name.loc,
+                    })
+                }
+            },
             TypeExpr::Pointer { .. } => Err(LoError::todo(file!(), line!())),
             TypeExpr::SequencePointer { .. } => Err(LoError::todo(file!(), line!())),
+            TypeExpr::Slice { .. } => Err(LoError::todo(file!(), line!())),
             TypeExpr::Result { .. } => Err(LoError::todo(file!(), line!())),
+            TypeExpr::ErrorUnion { .. } => Err(LoError::todo(file!(), line!())),
             TypeExpr::Of { .. } => Err(LoError::todo(file!(), line!())),
+            TypeExpr::Struct { .. } => Err(LoError::todo(file!(), line!())),
         }
     }
 
@@ -290,7 +393,7 @@ impl IRGenerator {
                 let Some(var) = self.ss.get_var(&var_load.repr) else {
                     return Err(LoError {
                         message: format!("Cannot read unknown variable: {}", var_load.repr),
-                        loc: var_load.loc.clone(),
+                        loc: var_load.loc,
                     });
                 };
 
@@ -315,7 +418,7 @@ impl IRGenerator {
                         message: format!(
                             "Operands have different types: {lhs_type} and {rhs_type}"
                         ),
-                        loc: loc.clone(),
+                        loc: *loc,
                     });
                 }
 
@@ -329,7 +432,7 @@ impl IRGenerator {
                         message: format!(
                             "Operator {op} is not applicable to operands of type {type_}",
                         ),
-                        loc: loc.clone(),
+                        loc: *loc,
                     }
                 }
 
@@ -389,6 +492,7 @@ impl IRGenerator {
                     | InfixOpTag::ShiftRightAssign
                     | InfixOpTag::Cast
                     | InfixOpTag::FieldAccess
+                    | InfixOpTag::Index
                     | InfixOpTag::Catch
                     | InfixOpTag::ErrorPropagation => {
                         return Err(LoError::unreachable(file!(), line!()))
@@ -463,7 +567,7 @@ impl IRGenerator {
                 let Some(fn_def) = self.ss.get_fn_def(&fn_name.repr) else {
                     return Err(LoError {
                         message: format!("Trying to call unknown function {}", fn_name.repr),
-                        loc: loc.clone(),
+                        loc: *loc,
                     });
                 };
 
@@ -474,7 +578,7 @@ impl IRGenerator {
                             ListDisplay(&arg_types),
                             ListDisplay(&fn_def.inputs)
                         ),
-                        loc: loc.clone(),
+                        loc: *loc,
                     });
                 }
 
@@ -487,29 +591,165 @@ impl IRGenerator {
             CodeExpr::Unreachable(UnreachableExpr { .. }) => Ok(LoExpr::Unreachable),
 
             CodeExpr::BoolLiteral(_) => Err(LoError::todo(file!(), line!())),
-            CodeExpr::Let(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::Let(LetExpr {
+                local_name, value, ..
+            }) => {
+                let lo_value = self.build_code_expr(value)?;
+
+                self.ss.top().vars.push(LoVar {
+                    name: local_name.clone(),
+                    type_: lo_value.get_type(),
+                });
+
+                Ok(LoExpr::Let {
+                    name: local_name.clone(),
+                    value: Box::new(lo_value),
+                })
+            }
             CodeExpr::Loop(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::Break(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::ForLoop(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::Continue(_) => Err(LoError::todo(file!(), line!())),
-            CodeExpr::StringLiteral(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::StringLiteral(StringLiteralExpr {
+                value,
+                zero_terminated,
+                loc,
+                ..
+            }) => {
+                if *zero_terminated {
+                    return Err(LoError {
+                        message: String::from("Zero terminated strings are not supported"),
+                        loc: *loc,
+                    });
+                }
+
+                Ok(LoExpr::StrConst {
+                    value: value.clone(),
+                })
+            }
             CodeExpr::Dbg(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::Defer(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::Cast(_) => Err(LoError::todo(file!(), line!())),
-            CodeExpr::StructLiteral(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::Index(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::StructLiteral(StructLiteralExpr {
+                struct_name,
+                fields,
+                ..
+            }) => {
+                let Some(struct_def) = self.ss.get_struct_def(&struct_name.repr) else {
+                    return Err(LoError {
+                        message: format!("Unknown struct: {}", struct_name.repr),
+                        loc: struct_name.loc,
+                    });
+                };
+                let struct_fields = struct_def
+                    .fields
+                    .iter()
+                    .map(|f| (f.name.clone(), f.type_.clone()))
+                    .collect::<Vec<_>>();
+
+                let mut lo_fields = Vec::new();
+                for field in fields {
+                    let Some((field_name, field_type)) = struct_fields.get(lo_fields.len()) else {
+                        return Err(LoError {
+                            message: String::from("Excess field values"),
+                            loc: field.loc,
+                        });
+                    };
+
+                    if field.field_name != *field_name {
+                        return Err(LoError {
+                            message: format!("Unexpected field name, expecting: `{field_name}`"),
+                            loc: field.loc,
+                        });
+                    }
+
+                    let lo_value = self.build_code_expr(&field.value)?;
+                    let value_type = lo_value.get_type();
+                    if value_type != *field_type {
+                        return Err(LoError {
+                            message: format!(
+                                "Invalid type for field {}.{field_name}, expected: {field_type}, got: {value_type}",
+                                struct_name.repr
+                            ),
+                            loc: field.loc,
+                        });
+                    }
+                    lo_fields.push(lo_value);
+                }
+
+                if lo_fields.len() < struct_fields.len() {
+                    let missing_fields = struct_fields
+                        .iter()
+                        .skip(lo_fields.len())
+                        .map(|(name, _)| name)
+                        .collect::<Vec<_>>();
+
+                    return Err(LoError {
+                        message: format!("Missing struct fields: {}", ListDisplay(&missing_fields)),
+                        loc: struct_name.loc,
+                    });
+                }
+
+                Ok(LoExpr::StructNew {
+                    struct_name: struct_name.repr.clone(),
+                    fields: lo_fields,
+                })
+            }
             CodeExpr::Assign(_) => Err(LoError::todo(file!(), line!())),
-            CodeExpr::FieldAccess(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::FieldAccess(FieldAccessExpr {
+                lhs, field_name, ..
+            }) => {
+                let lo_lhs = self.build_code_expr(lhs)?;
+
+                match lo_lhs.get_type() {
+                    LoType::Str if field_name.repr == "size" => Ok(LoExpr::StrLen {
+                        lhs: Box::new(lo_lhs),
+                    }),
+                    LoType::Struct { name } => {
+                        let struct_def = self.ss.get_struct_def(&name).unwrap(); // safe
+                        let Some(field_index) = struct_def
+                            .fields
+                            .iter()
+                            .position(|f| f.name == field_name.repr)
+                        else {
+                            return Err(LoError {
+                                message: format!(
+                                    "Unknown field {} in struct {name}",
+                                    field_name.repr
+                                ),
+                                loc: field_name.loc,
+                            });
+                        };
+
+                        Ok(LoExpr::FieldLoad {
+                            field_type: struct_def.fields[field_index].type_.clone(),
+                            lhs: Box::new(lo_lhs),
+                            struct_name: name,
+                            field_index: field_index as u32,
+                        })
+                    }
+                    lhs_type => Err(LoError {
+                        message: format!("Unknown field {} in type {lhs_type}", field_name.repr),
+                        loc: field_name.loc,
+                    }),
+                }
+            }
             CodeExpr::Catch(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::Paren(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::MethodCall(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::MacroFnCall(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::MacroMethodCall(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::MacroExpansion(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::Sizeof(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::PropagateError(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::CharLiteral(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::PrefixOp(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::ArrayLiteral(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::ArrayRepeat(_) => Err(LoError::todo(file!(), line!())),
             CodeExpr::GetDataSize(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::Reflection(_) => Err(LoError::todo(file!(), line!())),
+            CodeExpr::Bitcast(_) => Err(LoError::todo(file!(), line!())),
         }
     }
 }
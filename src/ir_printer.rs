@@ -0,0 +1,335 @@
+use crate::{core::*, ir::*, ir_generator::*};
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::{Display, Write};
+
+// one node per line, children are indented by two spaces:
+//   `<kind>[ <attr>]...: <type>`, `?` stands for untyped compiler internals
+//   (shared by `--emit-ir` and the `ir` backend of `--compile-v2`)
+pub trait IrTextNode<C> {
+    fn describe<'a>(&'a self, ctx: &C) -> IrNodeText<'a, Self>;
+
+    // `None` for compiler internals without a lo type
+    fn type_text(&self, ctx: &C) -> Option<String>;
+}
+
+pub enum IrNodeText<'a, T: ?Sized> {
+    Node {
+        kind: String,
+        children: Vec<&'a T>,
+    },
+    // branches are grouped under `then` and `else` lines without a type
+    If {
+        cond: &'a T,
+        then_branch: Vec<&'a T>,
+        else_branch: Option<Vec<&'a T>>,
+    },
+}
+
+pub fn print_fn_ir(
+    ctx: &ModuleContext,
+    fn_name: &str,
+    fn_def: &FnDef,
+    exprs: &[LoInstr],
+) -> String {
+    let mut out = String::new();
+
+    let params = fn_def.fn_params.iter().map(|p| (&p.name, &p.type_));
+    print_ir_fn_header(&mut out, fn_name, params, &fn_def.type_.output, &fn_def.loc);
+    for expr in exprs {
+        print_ir_node(&mut out, ctx, expr, 1);
+    }
+    out.push('\n');
+
+    out
+}
+
+pub fn print_ir_fn_header(
+    out: &mut String,
+    fn_name: &str,
+    params: impl Iterator<Item = (impl Display, impl Display)>,
+    output: &impl Display,
+    loc: &LoLocation,
+) {
+    let mut params_text = String::new();
+    for ((name, type_), i) in params.zip(0..) {
+        if i != 0 {
+            params_text.push_str(", ");
+        }
+        write!(&mut params_text, "{name}: {type_}").unwrap();
+    }
+    writeln!(out, "fn {fn_name}({params_text}): {output} @ {loc}").unwrap();
+}
+
+pub fn print_ir_node<C, T: IrTextNode<C>>(out: &mut String, ctx: &C, node: &T, depth: usize) {
+    let (kind, children) = match node.describe(ctx) {
+        IrNodeText::Node { kind, children } => (kind, children),
+        IrNodeText::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            print_node_line(out, ctx, node, depth, "if");
+            print_ir_node(out, ctx, cond, depth + 1);
+            print_indent(out, depth + 1);
+            out.push_str("then\n");
+            for expr in then_branch {
+                print_ir_node(out, ctx, expr, depth + 2);
+            }
+            if let Some(else_branch) = else_branch {
+                print_indent(out, depth + 1);
+                out.push_str("else\n");
+                for expr in else_branch {
+                    print_ir_node(out, ctx, expr, depth + 2);
+                }
+            }
+            return;
+        }
+    };
+
+    print_node_line(out, ctx, node, depth, &kind);
+    for child in children {
+        print_ir_node(out, ctx, child, depth + 1);
+    }
+}
+
+fn print_node_line<C, T: IrTextNode<C>>(
+    out: &mut String,
+    ctx: &C,
+    node: &T,
+    depth: usize,
+    kind: &str,
+) {
+    print_indent(out, depth);
+    out.push_str(kind);
+    match node.type_text(ctx) {
+        Some(type_text) => writeln!(out, ": {type_text}").unwrap(),
+        None => out.push_str(": ?\n"),
+    }
+}
+
+fn print_indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+impl<'m> IrTextNode<ModuleContext<'m>> for LoInstr {
+    fn describe<'a>(&'a self, ctx: &ModuleContext) -> IrNodeText<'a, Self> {
+        describe_instr(ctx, self)
+    }
+
+    fn type_text(&self, ctx: &ModuleContext) -> Option<String> {
+        if !is_typed(ctx, self) {
+            return None;
+        }
+        Some(format!("{}", self.get_type(ctx)))
+    }
+}
+
+fn describe_instr<'a>(ctx: &ModuleContext, instr: &'a LoInstr) -> IrNodeText<'a, LoInstr> {
+    let (kind, children): (String, Vec<&LoInstr>) = match instr {
+        LoInstr::NoInstr => (String::from("nop"), vec![]),
+        LoInstr::Unreachable => (String::from("unreachable"), vec![]),
+        LoInstr::Drop { value, drop_count } => (format!("drop {drop_count}"), vec![&**value]),
+        LoInstr::BinaryOp { kind, lhs, rhs } => {
+            (format!("binary_op {kind:?}"), vec![&**lhs, &**rhs])
+        }
+        LoInstr::MemorySize => (String::from("memory_size"), vec![]),
+        LoInstr::MemoryGrow { num_bytes } => (String::from("memory_grow"), vec![&**num_bytes]),
+        LoInstr::MemoryCopy {
+            destination,
+            source,
+            num_bytes,
+        } => (
+            String::from("memory_copy"),
+            vec![&**destination, &**source, &**num_bytes],
+        ),
+        LoInstr::ExternRefNull => (String::from("externref_null"), vec![]),
+        LoInstr::ExternRefIsNull { value } => (String::from("externref_is_null"), vec![&**value]),
+        LoInstr::Atomic {
+            instr: atomic_instr,
+            args,
+            ..
+        } => {
+            // immediates of the wasm instr are left out, only its name is kept
+            let instr_name = format!("{atomic_instr:?}");
+            let instr_name = instr_name.split_whitespace().next().unwrap_or_default();
+            (format!("atomic {instr_name}"), args.iter().collect())
+        }
+        LoInstr::Load {
+            kind,
+            align,
+            offset,
+            address_instr,
+        } => (
+            format!("load {kind} align={align} offset={offset}"),
+            vec![&**address_instr],
+        ),
+        LoInstr::StructLoad {
+            struct_name,
+            address_instr,
+            address_local_index,
+            base_byte_offset,
+            primitive_loads,
+        } => {
+            let kind = format!(
+                "struct_load {struct_name} address_local={address_local_index} \
+                    offset={base_byte_offset}"
+            );
+            let mut children = vec![&**address_instr];
+            children.extend(primitive_loads);
+            (kind, children)
+        }
+        LoInstr::LocalGet { local_index, .. } | LoInstr::UntypedLocalGet { local_index } => {
+            (format!("local_get {local_index}"), vec![])
+        }
+        LoInstr::GlobalGet { global_index } => (format!("global_get {global_index}"), vec![]),
+        LoInstr::StructGet {
+            struct_name,
+            base_index,
+            primitive_gets,
+        } => (
+            format!("struct_get {struct_name} base={base_index}"),
+            primitive_gets.iter().collect(),
+        ),
+        LoInstr::I32Const { value } => (format!("i32_const {value}"), vec![]),
+        LoInstr::U32Const { value } => (format!("u32_const {value}"), vec![]),
+        LoInstr::U64Const { value } => (format!("u64_const {value}"), vec![]),
+        LoInstr::I64Const { value } => (format!("i64_const {value}"), vec![]),
+        LoInstr::F32Const { value } => (format!("f32_const {value}"), vec![]),
+        LoInstr::F64Const { value } => (format!("f64_const {value}"), vec![]),
+        LoInstr::I64FromI32Unsigned { expr } => {
+            (String::from("i64_from_i32_unsigned"), vec![&**expr])
+        }
+        LoInstr::I64FromI32Signed { expr } => (String::from("i64_from_i32_signed"), vec![&**expr]),
+        LoInstr::I32FromI64 { expr } => (String::from("i32_from_i64"), vec![&**expr]),
+        LoInstr::Set { bind } => match bind {
+            LoSetBind::Local { index } => (format!("local_set {index}"), vec![]),
+            LoSetBind::Global { index } => (format!("global_set {index}"), vec![]),
+            LoSetBind::Memory {
+                align,
+                offset,
+                kind,
+                address_instr,
+                value_local_index,
+            } => (
+                format!(
+                    "store {kind:?} align={align} offset={offset} value_local={value_local_index}"
+                ),
+                vec![&**address_instr],
+            ),
+        },
+        LoInstr::Return { value } => (String::from("return"), vec![&**value]),
+        LoInstr::Block { body, .. } => (String::from("block"), body.iter().collect()),
+        LoInstr::Loop { body, .. } => (String::from("loop"), body.iter().collect()),
+        LoInstr::If {
+            cond,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            return IrNodeText::If {
+                cond,
+                then_branch: then_branch.iter().collect(),
+                else_branch: else_branch.as_ref().map(|branch| branch.iter().collect()),
+            };
+        }
+        LoInstr::Branch { label_index } => (format!("branch {label_index}"), vec![]),
+        LoInstr::Call { fn_index, args, .. } | LoInstr::ReturnCall { fn_index, args } => {
+            let call_kind = match instr {
+                LoInstr::ReturnCall { .. } => "return_call",
+                _ => "call",
+            };
+            (
+                format!("{call_kind} {}", get_fn_name(ctx, *fn_index)),
+                args.iter().collect(),
+            )
+        }
+        LoInstr::MultiValueEmit { values } => {
+            (String::from("multi_value_emit"), values.iter().collect())
+        }
+        LoInstr::Casted { expr, .. } => (String::from("casted"), vec![&**expr]),
+        LoInstr::SourceLoc { loc } => (format!("source_loc {loc}"), vec![]),
+    };
+
+    IrNodeText::Node { kind, children }
+}
+
+// `LoInstr::get_type` only works for what the parser type-checks,
+//   internal locals and globals (like the stack pointer) have no lo type
+fn is_typed(ctx: &ModuleContext, instr: &LoInstr) -> bool {
+    match instr {
+        LoInstr::UntypedLocalGet { .. } => false,
+        LoInstr::GlobalGet { global_index } => ctx
+            .globals
+            .values()
+            .any(|global| global.index == *global_index),
+        LoInstr::BinaryOp { lhs, .. } => is_typed(ctx, lhs),
+        LoInstr::MultiValueEmit { values } => values.iter().all(|value| is_typed(ctx, value)),
+        _ => true,
+    }
+}
+
+fn get_fn_name(ctx: &ModuleContext, fn_index: u32) -> String {
+    for (fn_name, fn_def) in ctx.fn_defs.iter() {
+        if fn_def.get_absolute_index(ctx) == fn_index {
+            return String::from(fn_name.as_str());
+        }
+    }
+
+    format!("#{fn_index}")
+}
+
+impl IrTextNode<()> for LoExpr {
+    fn describe<'a>(&'a self, _: &()) -> IrNodeText<'a, Self> {
+        let (kind, children): (String, Vec<&LoExpr>) = match self {
+            LoExpr::Casted { expr, .. } => (String::from("casted"), vec![&**expr]),
+            LoExpr::Void => (String::from("void"), vec![]),
+            LoExpr::Unreachable => (String::from("unreachable"), vec![]),
+            LoExpr::U32Const { value } => (format!("u32_const {value}"), vec![]),
+            LoExpr::Return { expr } => (String::from("return"), vec![&**expr]),
+            LoExpr::BinaryOp { kind, lhs, rhs } => {
+                (format!("binary_op {kind:?}"), vec![&**lhs, &**rhs])
+            }
+            LoExpr::VarLoad { name, .. } => (format!("var_load {name}"), vec![]),
+            LoExpr::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                return IrNodeText::If {
+                    cond,
+                    then_branch: then_block.exprs.iter().collect(),
+                    else_branch: else_block
+                        .as_ref()
+                        .map(|block| block.exprs.iter().collect()),
+                };
+            }
+            LoExpr::Call { fn_name, args, .. } => {
+                (format!("call {fn_name}"), args.iter().collect())
+            }
+            LoExpr::Let { name, value } => (format!("let {name}"), vec![&**value]),
+            LoExpr::StrConst { value } => (format!("str_const {value:?}"), vec![]),
+            LoExpr::StrLen { lhs } => (String::from("str_len"), vec![&**lhs]),
+            LoExpr::StructNew {
+                struct_name,
+                fields,
+            } => (format!("struct_new {struct_name}"), fields.iter().collect()),
+            LoExpr::FieldLoad {
+                lhs,
+                struct_name,
+                field_index,
+                ..
+            } => (
+                format!("field_load {struct_name}.{field_index}"),
+                vec![&**lhs],
+            ),
+        };
+
+        IrNodeText::Node { kind, children }
+    }
+
+    fn type_text(&self, _: &()) -> Option<String> {
+        Some(format!("{}", self.get_type()))
+    }
+}
@@ -1,5 +1,6 @@
 use crate::core::*;
-use alloc::{format, rc::Rc, string::String, vec::Vec};
+use alloc::{format, string::String, vec::Vec};
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum LoTokenType {
@@ -15,61 +16,101 @@ pub enum LoTokenType {
 #[derive(Debug, Clone)]
 pub struct LoToken {
     pub type_: LoTokenType,
-    pub value: String,
+    pub value: Atom, // token text as it appears in the source
     pub loc: LoLocation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Comment {
     pub content: String,
+    pub is_doc: bool,
     pub loc: LoLocation,
 }
 
-pub struct Lexer {
-    file_name: Rc<str>,
+impl Comment {
+    // collects `///` comments placed on the lines right above `loc`,
+    //   `comments` are sorted by offset so only the ones right before `loc` are visited
+    pub fn get_doc(comments: &[Comment], loc: &LoLocation) -> Option<String> {
+        let mut doc_lines = Vec::new();
+        let mut expected_line = loc.pos().line;
+
+        let comments_before = comments.partition_point(|c| c.loc.offset < loc.offset);
+        for comment in comments[..comments_before].iter().rev() {
+            if !comment.is_doc || comment.loc.pos().line + 1 != expected_line {
+                break;
+            }
+
+            let line = comment.content[3..].trim_end();
+            doc_lines.push(line.strip_prefix(' ').unwrap_or(line));
+            expected_line = comment.loc.pos().line;
+        }
+
+        if doc_lines.is_empty() {
+            return None;
+        }
+
+        doc_lines.reverse();
+        Some(doc_lines.join("\n"))
+    }
+}
+
+// `#pragma <name> <args>` line, applies to the whole file
+#[derive(Debug, Clone)]
+pub struct Pragma {
+    pub name: String,
+    pub args: Vec<String>,
+    pub loc: LoLocation,
+}
+
+pub struct Lexer<'a> {
+    file_id: u32,
+    source: &'a str,
     chars: Vec<char>,
     index: usize,
-    line: usize,
-    col: usize,
-    was_newline: bool,
+    byte_index: usize, // byte offset of `chars[index]` in `source`
     comments: Vec<Comment>,
+    pragmas: Vec<Pragma>,
 }
 
 pub struct Tokens {
     pub tokens: Vec<LoToken>,
     pub end_loc: LoLocation,
     pub comments: Vec<Comment>,
+    pub pragmas: Vec<Pragma>,
 }
 
-impl Lexer {
+impl<'a> Lexer<'a> {
     pub fn lex(file_name: &str, chars: &str) -> Result<Tokens, LoError> {
-        let mut lexer = Lexer {
-            file_name: file_name.into(),
-            chars: chars.chars().collect::<Vec<_>>(),
-            index: 0,
-            line: 1,
-            col: 1,
-            was_newline: false,
-            comments: Vec::new(),
-        };
+        measure_phase("lex", || {
+            let mut lexer = Lexer {
+                file_id: SourceFile::add(file_name, chars),
+                source: chars,
+                chars: chars.chars().collect::<Vec<_>>(),
+                index: 0,
+                byte_index: 0,
+                comments: Vec::new(),
+                pragmas: Vec::new(),
+            };
 
-        let tokens = lexer.lex_file()?;
+            let tokens = lexer.lex_file()?;
 
-        Ok(Tokens {
-            tokens,
-            end_loc: lexer.loc(),
-            comments: lexer.comments,
+            Ok(Tokens {
+                tokens,
+                end_loc: lexer.loc(),
+                comments: lexer.comments,
+                pragmas: lexer.pragmas,
+            })
         })
     }
 
     fn lex_file(&mut self) -> Result<Vec<LoToken>, LoError> {
         let mut tokens = Vec::new();
 
-        self.skip_space();
+        self.skip_space()?;
 
         while self.index < self.chars.len() {
             tokens.push(self.lex_token()?);
-            self.skip_space();
+            self.skip_space()?;
         }
 
         Ok(tokens)
@@ -87,8 +128,7 @@ impl Lexer {
         if char.is_numeric() {
             return self.lex_int_literal();
         }
-        // NOTE: must be after int because is_symbol_char matches digits
-        if is_symbol_char(char) {
+        if is_symbol_start_char(char) {
             return self.lex_symbol();
         }
         if is_delim_char(char) {
@@ -111,11 +151,19 @@ impl Lexer {
             self.next_char();
         }
 
-        loc.end_pos = self.pos();
+        loc.end_offset = self.byte_index as u32;
+
+        // differently encoded but equal names must intern to the same atom
+        let text = &self.source[loc.offset as usize..self.byte_index];
+        let value = if text.is_ascii() {
+            Atom::intern(text)
+        } else {
+            Atom::intern(&text.nfc().collect::<String>())
+        };
 
         Ok(LoToken {
             type_: LoTokenType::Symbol,
-            value: self.chars[loc.pos.offset..self.index].iter().collect(),
+            value,
             loc,
         })
     }
@@ -151,11 +199,11 @@ impl Lexer {
         }
         self.next_char(); // skip end quote
 
-        loc.end_pos = self.pos();
+        loc.end_offset = self.byte_index as u32;
 
         Ok(LoToken {
             type_: LoTokenType::CharLiteral,
-            value: self.chars[loc.pos.offset..self.index].iter().collect(),
+            value: self.text_from(loc.offset),
             loc,
         })
     }
@@ -192,23 +240,36 @@ impl Lexer {
             self.next_char();
         }
 
-        loc.end_pos = self.pos();
+        loc.end_offset = self.byte_index as u32;
+
+        let text = &self.source[loc.offset as usize..self.byte_index];
+        if Lexer::try_parse_int_literal_value(text).is_none() {
+            return Err(LoError {
+                message: format!("Integer literal does not fit into 64 bits: {text}"),
+                loc,
+            });
+        }
 
         Ok(LoToken {
             type_: LoTokenType::IntLiteral,
-            value: self.chars[loc.pos.offset..self.index].iter().collect(),
+            value: self.text_from(loc.offset),
             loc,
         })
     }
 
+    // int literals are checked to fit into u64 while lexing
     pub fn parse_int_literal_value(int_literal: &str) -> u64 {
+        Lexer::try_parse_int_literal_value(int_literal).unwrap()
+    }
+
+    fn try_parse_int_literal_value(int_literal: &str) -> Option<u64> {
         let int_literal = int_literal.replace("_", "");
 
-        if int_literal.starts_with("0x") {
-            return u64::from_str_radix(&int_literal[2..], 16).unwrap();
+        if let Some(hex_digits) = int_literal.strip_prefix("0x") {
+            return u64::from_str_radix(hex_digits, 16).ok();
         }
 
-        int_literal.parse().unwrap()
+        int_literal.parse().ok()
     }
 
     fn lex_string(&mut self) -> Result<LoToken, LoError> {
@@ -238,11 +299,11 @@ impl Lexer {
 
         self.next_char(); // skip end quote
 
-        loc.end_pos = self.pos();
+        loc.end_offset = self.byte_index as u32;
 
         Ok(LoToken {
             type_: LoTokenType::StringLiteral,
-            value: self.chars[loc.pos.offset..self.index].iter().collect(),
+            value: self.text_from(loc.offset),
             loc,
         })
     }
@@ -286,37 +347,38 @@ impl Lexer {
 
         Ok(LoToken {
             type_: LoTokenType::Delim,
-            value: self.chars[loc.pos.offset].into(),
+            value: self.text_from(loc.offset),
             loc,
         })
     }
 
     fn lex_operator(&mut self) -> Result<LoToken, LoError> {
         let mut loc = self.loc();
-        let mut value = String::new();
+        let start = self.byte_index;
 
         loop {
-            value.push(self.current_char()?);
+            let char = self.current_char()?;
+            let value = &self.source[start..self.byte_index + char.len_utf8()];
 
             let mut is_start_of_operator = false;
             for operator in OPERATORS {
-                if operator.starts_with(&value) {
+                if operator.starts_with(value) {
                     is_start_of_operator = true;
                     break;
                 }
             }
 
             if !is_start_of_operator {
-                value.pop();
                 break;
             };
 
             self.next_char();
         }
 
+        let value = &self.source[start..self.byte_index];
         let mut matched_fully = false;
         for operator in OPERATORS {
-            if operator == &value {
+            if *operator == value {
                 matched_fully = true;
                 break;
             }
@@ -329,79 +391,115 @@ impl Lexer {
             });
         };
 
-        loc.end_pos = self.pos();
+        loc.end_offset = self.byte_index as u32;
 
         Ok(LoToken {
             type_: LoTokenType::Operator,
-            value,
+            value: Atom::intern(value),
             loc,
         })
     }
 
-    fn skip_space(&mut self) {
+    fn skip_space(&mut self) -> Result<(), LoError> {
         while self.current_char().map(is_space_char).unwrap_or(false) {
             self.next_char();
         }
 
-        if self.current_char() == Ok('/') && self.peek_next_char() == Ok('/') {
+        let rest = &self.source[self.byte_index..];
+        let is_shebang = self.byte_index == 0 && rest.starts_with("#!");
+        let is_pragma = rest.starts_with("#pragma");
+
+        if rest.starts_with("//") || is_shebang || is_pragma {
+            // shebang and pragma lines are kept as comments so that pretty printing preserves them
             let comment = self.lex_comment();
+            if is_pragma {
+                let mut words = comment.content["#pragma".len()..].split_whitespace();
+                self.pragmas.push(Pragma {
+                    name: String::from(words.next().unwrap_or("")),
+                    args: words.map(String::from).collect(),
+                    loc: comment.loc,
+                });
+            }
             self.comments.push(comment);
-            self.skip_space();
+            return self.skip_space();
         }
+
+        if rest.starts_with("/*") {
+            let comment = self.lex_block_comment()?;
+            self.comments.push(comment);
+            return self.skip_space();
+        }
+
+        Ok(())
     }
 
+    // lexes till the end of the line
     fn lex_comment(&mut self) -> Comment {
         let mut loc = self.loc();
 
-        self.next_char(); /* `/` */
-        self.next_char(); /* `/` */
-
-        loop {
+        while self.current_char().map(|c| c != '\n').unwrap_or(false) {
             self.next_char();
-
-            let Ok(char) = self.current_char() else {
-                break;
-            };
-
-            if char == '\n' {
-                break;
-            }
         }
 
-        loc.end_pos = self.pos();
+        loc.end_offset = self.byte_index as u32;
+
+        let content = String::from(&self.source[loc.offset as usize..self.byte_index]);
+        let is_doc = content.starts_with("///") && !content.starts_with("////");
 
         Comment {
-            content: self.chars[loc.pos.offset..self.index].iter().collect(),
+            content,
+            is_doc,
             loc,
         }
     }
 
-    fn next_char(&mut self) {
-        self.index += 1;
+    // block comments nest, so code containing them can be commented out
+    fn lex_block_comment(&mut self) -> Result<Comment, LoError> {
+        let mut loc = self.loc();
+        let mut depth = 0;
 
-        let Ok(char) = self.current_char() else {
-            return;
-        };
+        loop {
+            let rest = &self.source[self.byte_index..];
+            if rest.starts_with("/*") {
+                depth += 1;
+            } else if rest.starts_with("*/") {
+                depth -= 1;
+            } else if rest.is_empty() {
+                return Err(LoError {
+                    message: String::from("Unterminated block comment"),
+                    loc,
+                });
+            } else {
+                self.next_char();
+                continue;
+            }
 
-        self.col += 1;
+            self.next_char();
+            self.next_char();
 
-        if char == '\n' {
-            // NOTE(edge case): when first character is encountered
-            //  was_newline is not true but rather undefined,
-            //  thus we don't bump the line count
-            if self.index == 0 || self.was_newline {
-                self.line += 1;
+            if depth == 0 {
+                break;
             }
-            self.was_newline = true;
-            return;
         }
 
-        if self.was_newline {
-            self.line += 1;
-            self.col = 1;
-            self.was_newline = false;
-            return;
+        loc.end_offset = self.byte_index as u32;
+
+        Ok(Comment {
+            content: String::from(&self.source[loc.offset as usize..self.byte_index]),
+            is_doc: false,
+            loc,
+        })
+    }
+
+    fn text_from(&self, start: u32) -> Atom {
+        Atom::intern(&self.source[start as usize..self.byte_index])
+    }
+
+    fn next_char(&mut self) {
+        if let Some(char) = self.chars.get(self.index) {
+            self.byte_index += char.len_utf8();
         }
+        self.index += 1;
     }
 
     fn current_char(&mut self) -> Result<char, LoError> {
@@ -427,18 +525,9 @@ impl Lexer {
 
     fn loc(&self) -> LoLocation {
         LoLocation {
-            file_name: self.file_name.clone(),
-
-            pos: self.pos(),
-            end_pos: self.pos(),
-        }
-    }
-
-    fn pos(&self) -> LoPosition {
-        LoPosition {
-            offset: self.index,
-            line: self.line,
-            col: self.col,
+            file_id: self.file_id,
+            offset: self.byte_index as u32,
+            end_offset: self.byte_index as u32,
         }
     }
 }
@@ -450,14 +539,24 @@ fn is_space_char(c: char) -> bool {
     }
 }
 
+// identifiers follow UAX-31 with `_` allowed at the start
+fn is_symbol_start_char(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c)
+}
+
 fn is_symbol_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '_'
+    unicode_ident::is_xid_continue(c)
 }
 
 fn is_delim_char(c: char) -> bool {
     "(){}[],;".contains(c)
 }
 
+// type names that can follow an int literal, like `255u8`
+pub const INT_LITERAL_SUFFIXES: [&str; 10] = [
+    "u8", "i8", "u16", "i16", "u32", "i32", "f32", "u64", "i64", "f64",
+];
+
 static OPERATORS: &[&str] = &[
     "=",   // Assignment
     "==",  // Equality comparison
@@ -508,7 +607,7 @@ fn is_operator_start_char(c: char) -> bool {
     return false;
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InfixOpTag {
     Equal,
     NotEqual,
@@ -542,6 +641,7 @@ pub enum InfixOpTag {
 
     Cast,
     FieldAccess,
+    Index,
     Catch,
 
     ErrorPropagation,
@@ -579,6 +679,7 @@ impl InfixOpTag {
             InfixOpTag::ShiftRightAssign => ">>=",
             InfixOpTag::Cast => "as",
             InfixOpTag::FieldAccess => ".",
+            InfixOpTag::Index => "[",
             InfixOpTag::Catch => "catch",
             InfixOpTag::ErrorPropagation => "?",
         }
@@ -599,6 +700,7 @@ impl InfixOp {
             "catch" => (Catch, OpInfo { bp: 13, assoc: L }),
 
             "." => (FieldAccess, OpInfo { bp: 12, assoc: L }),
+            "[" => (Index, OpInfo { bp: 12, assoc: L }),
 
             "?" => (
                 ErrorPropagation,
@@ -650,10 +752,11 @@ impl InfixOp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PrefixOpTag {
     Not,
     Dereference,
+    AddressOf,
     Positive,
     Negative,
 }
@@ -663,6 +766,7 @@ impl PrefixOpTag {
         match self {
             PrefixOpTag::Not => "!",
             PrefixOpTag::Dereference => "*",
+            PrefixOpTag::AddressOf => "&",
             PrefixOpTag::Positive => "+",
             PrefixOpTag::Negative => "-",
         }
@@ -682,6 +786,7 @@ impl PrefixOp {
         let (tag, info) = match token.value.as_str() {
             "!" => (Not, OpInfo { bp: 8, assoc: L }),
             "*" => (Dereference, OpInfo { bp: 8, assoc: L }),
+            "&" => (AddressOf, OpInfo { bp: 8, assoc: L }),
             "+" => (Positive, OpInfo { bp: 8, assoc: L }),
             "-" => (Negative, OpInfo { bp: 8, assoc: L }),
             _ => return Option::None,
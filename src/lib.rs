@@ -1,27 +1,43 @@
-#![no_std]
-#![feature(alloc_error_handler, thread_local)]
+#![cfg_attr(target_arch = "wasm32", no_std)]
+#![feature(alloc_error_handler, thread_local, core_intrinsics)]
+#![allow(internal_features)]
+#![cfg_attr(feature = "browser", allow(dead_code))] // most of the CLI is not reachable from `compile`
 
 extern crate alloc;
 
 mod ast;
+mod ast_json;
+mod backend;
+mod bindings;
 mod code_generator;
 mod core;
 mod ir;
 mod ir_generator;
+mod ir_printer;
 mod lexer;
+mod macro_expander;
+mod mangling;
 mod parser;
 mod parser_v2;
 mod printer;
+mod sha256;
+mod source_map;
+mod wasi_abi;
 mod wasm;
 mod wasm_eval;
+mod wasm_multivalue;
+mod wasm_optimizer;
+mod wasm_report;
+mod wasm_validator;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm_target {
+    use crate::core::CountingAllocator;
     use lol_alloc::{FreeListAllocator, LockedAllocator};
 
     #[global_allocator]
-    static ALLOCATOR: LockedAllocator<FreeListAllocator> =
-        LockedAllocator::new(FreeListAllocator::new());
+    static ALLOCATOR: CountingAllocator<LockedAllocator<FreeListAllocator>> =
+        CountingAllocator::new(LockedAllocator::new(FreeListAllocator::new()));
 
     #[alloc_error_handler]
     fn oom(_: core::alloc::Layout) -> ! {
@@ -31,78 +47,526 @@ mod wasm_target {
     #[panic_handler]
     fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
         crate::core::stderr_write(alloc::format!("{info}\n"));
+        crate::core::stderr_flush();
         core::arch::wasm32::unreachable();
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+#[global_allocator]
+static ALLOCATOR: core::CountingAllocator<std::alloc::System> =
+    core::CountingAllocator::new(std::alloc::System);
+
 static USAGE: &str = "\
-Usage: lo <file> [mode]
+Usage: lo <file> [mode] [options]
+  arguments can come in any order, everything after `--` is treated as <file>
   where [mode] is either:
     --compile-v2 (temporary)
+    --compile-gc (experimental)
+      same front-end as `--compile-v2`, but structs and strings are garbage
+      collected wasm gc objects instead of living in linear memory
+      (same as `--compile-v2 --backend wasm-gc`)
     --inspect
     --pretty-print
+    --expand-macros
+      same as `--pretty-print`, but macro calls are replaced by their expansions
+      marked with the macro call and definition location
+    --doc
+    --emit-ast
+      prints the syntax tree with locations and comments as JSON
+    --emit-ir
+      prints the typed IR of each fn right before it is lowered to wasm,
+      one node per line with statement locations
     --eval (experimental)
-  No [mode] means compilation to wasm\
+  No [mode] means compilation to wasm
+  -h, --help
+    prints this message
+  --backend <wasm|wasm-gc|ir>
+    code generator that consumes the IR of `--compile-v2` (default: wasm),
+    `ir` prints the IR as text instead of generating a wasm module
+  --inspect-range <file>[:<first line>-<last line>]
+    only compiles fn bodies in the range with `--inspect`, other fns are checked
+    by signature alone, which is much faster on large include graphs
+  --validate
+    checks generated wasm module before output or evaluation
+  -O0, -O1, -O2
+    optimization level (default: -O1),
+    -O0 keeps direct translation of the source for debugging
+  --strip
+    omits debug custom sections (like fn names) from the output
+  --release
+    same as `-O2 --strip`, optimization level can still be overridden
+  --source-map <file>
+    writes source map of the output to <file> and references it from the output
+  -o <file>
+    writes the output to <file> instead of <stdout>
+  --force
+    writes the wasm module to <stdout> even if it is a terminal
+  --emit-hash
+    prints SHA-256 of the generated wasm module instead of the module itself
+  --emit-bindings <js|dts>
+    prints a JS loader (`js`) or TypeScript declarations (`dts`) for the exported fns
+    instead of the module, str params need an exported `alloc(size: u32)` fn
+  --data-start <address>
+    places static data (like strings) starting at <address> instead of 0,
+    takes precedence over `data_start` of the memory
+  --export-heap-base
+    exports `__heap_base` and `__data_end` globals
+    (readable from the source without the export, they are created on first use)
+  --unmangled-exports
+    exports fns like `Vec::push` by their source names instead of mangled ones
+    (names of plain fns like `main` are never mangled)
+  --features <feature>[,<feature>]...
+    enables wasm features (bulk-memory, multi-value, reference-types, simd,
+    tail-call, threads, gc), `-<feature>` disables one of the defaults
+    (default: bulk-memory,multi-value)
+  --checks <check>[,<check>]...
+    inserts runtime checks (div-by-zero, bounds) that call `panic(message: str): never`
+    of the program with the source location instead of trapping
+  --no-multivalue
+    same as `--features -multi-value`, multi-component fn results
+    are returned through a pointer for engines without the proposal
+  --data-report
+    prints size of the data section and bytes saved by string pooling to <stderr>
+  --report
+    prints sizes of fn bodies, the data section report, a call graph
+    and bytes retained by each export to <stderr>
+  --timings
+    prints time and allocations spent in each compiler phase to <stderr>
+  --columns <chars|utf16>
+    unit of columns in diagnostics and `--inspect` ranges (default: chars),
+    editors using LSP positions need `utf16`
+  --tab-width <n>
+    columns a tab advances to the next multiple of with `--columns chars` (default: 4)
+  --eval options:
+    --import <module>::<name>=<trap|noop|<module>::<fn>>
+      binds fn import to a trap, a no-op or a host fn
+      (<name> can be `*` to match any fn of the module)
+    --dump-memory <start>:<len>
+      prints memory range to <stderr> after evaluation or on trap
+    --dump-globals
+      prints globals to <stderr> after evaluation or on trap
+    --debug
+      pauses before the first instruction, reads debugger commands from <stdin>
+      (type `help` when paused to list them)
+    --invoke <fn> [args]...
+      calls exported <fn> with [args] instead of `_start` or `main`
+    --bench
+      calls every exported `bench_*` fn instead of `_start` or `main`, printing
+      executed instruction count and call count of each fn
+    --profile
+      prints instructions executed in each fn and its call count to <stderr>
+      after evaluation or on trap, most expensive fns first\
 ";
 
+#[cfg(feature = "browser")]
+mod browser_api {
+    use crate::{core::*, parser, wasm_optimizer::*};
+    use alloc::{string::String, vec::Vec};
+    use core::cell::RefCell;
+
+    // output of the last `compile` call, kept until the next one
+    #[thread_local]
+    static OUTPUT: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+    #[thread_local]
+    static OUTPUT_SLICE: RefCell<[u32; 2]> = RefCell::new([0, 0]);
+
+    #[no_mangle]
+    pub extern "C" fn alloc(size: usize) -> *mut u8 {
+        let mut buffer = Vec::<u8>::with_capacity(size);
+        let ptr = buffer.as_mut_ptr();
+        core::mem::forget(buffer);
+        ptr
+    }
+
+    // takes ownership of the source allocated with `alloc(source_len)`, returns address of
+    //   `[ptr, len]` of the wasm module or of the error message if it doesn't start with `\0asm`
+    #[no_mangle]
+    pub extern "C" fn compile(source_ptr: *mut u8, source_len: usize) -> *const u32 {
+        let source = unsafe { Vec::from_raw_parts(source_ptr, source_len, source_len) };
+
+        let output = compile_source(source).unwrap_or_else(|err_message| {
+            stderr_flush();
+            let mut output = CAPTURED_STDERR.take();
+            output.extend_from_slice(err_message.as_bytes());
+            output.push(b'\n');
+            output
+        });
+        stderr_flush();
+        CAPTURED_STDERR.borrow_mut().clear(); // warnings are not reported on success
+
+        let mut output_slice = OUTPUT_SLICE.borrow_mut();
+        *output_slice = [output.as_ptr() as u32, output.len() as u32];
+        *OUTPUT.borrow_mut() = output;
+        output_slice.as_ptr()
+    }
+
+    fn compile_source(source: Vec<u8>) -> Result<Vec<u8>, String> {
+        let Ok(source) = String::from_utf8(source) else {
+            return Err(String::from("Source is not valid UTF-8"));
+        };
+
+        let ctx = &mut parser::init(CompilerMode::Compile);
+        parser::parse_file_contents(ctx, String::from("<input>"), &source)?;
+        parser::finalize(ctx)?;
+        optimize_module(&mut ctx.wasm_module.borrow_mut(), OptLevel::default());
+
+        let mut binary = Vec::new();
+        ctx.wasm_module.take().dump(&mut binary);
+        Ok(binary)
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "browser")))]
+pub use wasi_api::_start;
+
+#[cfg(not(feature = "browser"))]
 mod wasi_api {
     use crate::{
-        code_generator::*, core::*, ir_generator::*, lexer::*, parser, parser_v2::*, printer::*,
-        wasm_eval::*, USAGE,
+        ast_json::*,
+        backend::*,
+        bindings::*,
+        core::*,
+        ir::{InspectRange, RuntimeChecks},
+        ir_generator::*,
+        lexer::*,
+        macro_expander::*,
+        parser,
+        parser_v2::*,
+        printer::*,
+        sha256::*,
+        source_map::*,
+        wasm::*,
+        wasm_eval::*,
+        wasm_multivalue::*,
+        wasm_optimizer::*,
+        wasm_report::*,
+        wasm_validator::*,
+        USAGE,
     };
     use alloc::{format, rc::Rc, string::String, vec::Vec};
 
-    #[no_mangle]
+    // native builds call this from `main` instead, `_start` is taken by the C runtime there
+    #[cfg_attr(target_arch = "wasm32", no_mangle)]
     pub extern "C" fn _start() {
-        start().unwrap_or_else(|err_message| {
+        let result = start();
+        timings_print();
+
+        result.unwrap_or_else(|err_message| {
             stdout_disable_bufferring();
 
-            stderr_write(err_message);
+            stderr_write_diagnostic(err_message);
             stderr_write("\n");
             proc_exit(1);
         });
 
         stdout_disable_bufferring();
+        stderr_flush();
+    }
+
+    // the module is streamed to its destination, only the hash needs the whole binary
+    fn write_output(
+        wasm_module: &WasmModule,
+        emit_hash: bool,
+        output_path: Option<&str>,
+        force: bool,
+    ) -> Result<Vec<WasmSourceLoc>, String> {
+        if emit_hash {
+            let mut binary = Vec::new();
+            let source_locs = wasm_module.dump(&mut binary);
+            let hash_line = format!("{}\n", sha256_hex(&binary));
+            match output_path {
+                Some(output_path) => file_write(output_path, hash_line.as_bytes())?,
+                None => stdout_write(hash_line),
+            }
+            return Ok(source_locs);
+        }
+
+        let mut output = match output_path {
+            Some(output_path) => OutputStream::create(output_path)?,
+            None => {
+                if !force && is_terminal(wasi::FD_STDOUT) {
+                    return Err(String::from(
+                        "Refusing to write the wasm module to a terminal, \
+                        use `-o <file>`, redirect <stdout> or pass --force",
+                    ));
+                }
+                OutputStream::stdout()
+            }
+        };
+        let source_locs = wasm_module.dump(&mut output);
+        output.finish()?;
+        Ok(source_locs)
     }
 
     fn start() -> Result<(), String> {
         let args = WasiArgs::load().unwrap();
-        if args.len() < 2 {
-            return Err(format!("{}", USAGE));
-        }
+        let mut arg_parser =
+            ArgParser::new((1..args.len()).filter_map(|i| args.get(i)).collect(), USAGE);
+
+        let mut file_name = None;
+        let mut mode_flag = None;
+        let mut eval_only_flag = None;
+        let mut backend_name = None;
+        let mut inspect_range = None;
+        let mut unmangled_exports = false;
+        let mut validate = false;
+        let mut release = false;
+        let mut strip = false;
+        let mut emit_hash = false;
+        let mut force = false;
+        let mut output_path = None;
+        let mut data_report = false;
+        let mut report = false;
+        let mut export_heap_base = false;
+        let mut opt_level = None;
+        let mut source_map_path = None;
+        let mut bindings_kind = None;
+        let mut data_start = None;
+        let mut features = WasmFeatures::default();
+        let mut checks = RuntimeChecks::default();
+        let mut column_unit = "chars";
+        let mut tab_width = None;
+        let mut eval_options = EvalOptions::default();
+
+        while let Some(arg) = arg_parser.next() {
+            let flag = match arg {
+                CliArg::Positional(path) => {
+                    if let Some(file_name) = file_name {
+                        return Err(arg_parser.error(format!(
+                            "Unexpected argument: {path}, input file is already {file_name}"
+                        )));
+                    }
+                    file_name = Some(path);
+                    continue;
+                }
+                CliArg::Flag(flag) => flag,
+            };
 
-        let mut file_name = args.get(1).unwrap();
-        if file_name == "-i" {
-            file_name = "<stdin>";
+            match flag {
+                "-h" | "--help" => {
+                    stdout_write(USAGE);
+                    return Ok(());
+                }
+                "-i" => {
+                    if let Some(file_name) = file_name {
+                        return Err(arg_parser.error(format!(
+                            "Unexpected argument: -i, input file is already {file_name}"
+                        )));
+                    }
+                    file_name = Some("<stdin>");
+                }
+                "--compile-v2" | "--compile-gc" | "--inspect" | "--pretty-print"
+                | "--expand-macros" | "--doc" | "--emit-ast" | "--emit-ir" | "--eval" => {
+                    if let Some(mode_flag) = mode_flag {
+                        if mode_flag != flag {
+                            return Err(arg_parser.error(format!(
+                                "Conflicting compiler modes: {mode_flag} and {flag}"
+                            )));
+                        }
+                    }
+                    mode_flag = Some(flag);
+                }
+                "--backend" => backend_name = Some(arg_parser.value(flag, "backend name")?),
+                "--inspect-range" => {
+                    let range = arg_parser.value(flag, "inspect range")?;
+                    inspect_range = Some(InspectRange::parse(range)?);
+                }
+                "--validate" => validate = true,
+                "--release" => release = true,
+                "--strip" => strip = true,
+                "--emit-hash" => emit_hash = true,
+                "--force" => force = true,
+                "-o" => output_path = Some(arg_parser.value(flag, "output file")?),
+                "--data-report" => data_report = true,
+                "--report" => report = true,
+                "--timings" => timings_enable(),
+                "--columns" => column_unit = arg_parser.value(flag, "column unit")?,
+                "--tab-width" => {
+                    let width = arg_parser.value(flag, "tab width")?;
+                    let Some(width) = width.parse::<usize>().ok().filter(|w| *w != 0) else {
+                        return Err(format!("Invalid tab width: {width}"));
+                    };
+                    tab_width = Some(width);
+                }
+                "--export-heap-base" => export_heap_base = true,
+                "--unmangled-exports" => unmangled_exports = true,
+                "--source-map" => {
+                    source_map_path = Some(arg_parser.value(flag, "source map file")?);
+                }
+                "--emit-bindings" => {
+                    let kind = arg_parser.value(flag, "bindings kind")?;
+                    bindings_kind = Some(BindingsKind::parse(kind)?);
+                }
+                "--data-start" => {
+                    let address = arg_parser.value(flag, "data start address")?;
+                    let Ok(address) = address.parse::<u32>() else {
+                        return Err(format!("Invalid data start address: {address}"));
+                    };
+                    data_start = Some(address);
+                }
+                "--features" => {
+                    features.apply_list(arg_parser.value(flag, "wasm feature list")?)?;
+                }
+                "--no-multivalue" => features.disable(WasmFeature::MultiValue),
+                "--checks" => {
+                    checks.apply_list(arg_parser.value(flag, "runtime check list")?)?;
+                }
+                "--import" => {
+                    let binding = arg_parser.value(flag, "import binding")?;
+                    eval_options
+                        .import_bindings
+                        .push(ImportBinding::parse(binding)?);
+                    eval_only_flag = Some(flag);
+                }
+                "--dump-memory" => {
+                    let range = arg_parser.value(flag, "memory range")?;
+                    eval_options.memory_dumps.push(MemoryRange::parse(range)?);
+                    eval_only_flag = Some(flag);
+                }
+                "--dump-globals" => {
+                    eval_options.dump_globals = true;
+                    eval_only_flag = Some(flag);
+                }
+                "--bench" => {
+                    eval_options.bench = true;
+                    eval_only_flag = Some(flag);
+                }
+                "--profile" => {
+                    eval_options.profile = true;
+                    eval_only_flag = Some(flag);
+                }
+                "--debug" => {
+                    eval_options.debug = true;
+                    eval_only_flag = Some(flag);
+                }
+                "--invoke" => {
+                    let fn_name = arg_parser.value(flag, "function to invoke")?;
+                    let invoke_args = arg_parser.rest();
+
+                    eval_options.invoke = Some(EvalInvoke {
+                        fn_name: fn_name.into(),
+                        args: invoke_args.into_iter().map(String::from).collect(),
+                    });
+                    eval_only_flag = Some(flag);
+                }
+                _ => {
+                    let Some(level) = OptLevel::parse(flag) else {
+                        return Err(arg_parser.unknown_flag(flag));
+                    };
+                    opt_level = Some(level);
+                }
+            }
         }
 
-        let compiler_mode = match args.get(2) {
+        let Some(file_name) = file_name else {
+            if args.len() <= 1 {
+                return Err(String::from(USAGE));
+            }
+            return Err(arg_parser.error("Missing input file"));
+        };
+
+        let compiler_mode = match mode_flag {
             None => CompilerMode::Compile,
             Some("--compile-v2") => CompilerMode::CompileV2,
+            Some("--compile-gc") => CompilerMode::CompileGc,
             Some("--inspect") => CompilerMode::Inspect,
             Some("--pretty-print") => CompilerMode::PrettyPrint,
-            Some("--eval") => CompilerMode::Eval,
-            Some(unknown_mode) => {
-                return Err(format!("Unknown compiler mode: {unknown_mode}\n{}", USAGE));
-            }
+            Some("--expand-macros") => CompilerMode::ExpandMacros,
+            Some("--doc") => CompilerMode::Doc,
+            Some("--emit-ast") => CompilerMode::EmitAst,
+            Some("--emit-ir") => CompilerMode::EmitIr,
+            Some(_) => CompilerMode::Eval,
         };
 
-        if compiler_mode == CompilerMode::CompileV2 {
+        if let Some(eval_only_flag) = eval_only_flag {
+            if compiler_mode != CompilerMode::Eval {
+                return Err(arg_parser.error(format!(
+                    "Option {eval_only_flag} is only available with --eval"
+                )));
+            }
+        }
+
+        if inspect_range.is_some() && compiler_mode != CompilerMode::Inspect {
+            return Err(arg_parser.error("Option --inspect-range is only available with --inspect"));
+        }
+
+        if backend_name.is_some() && compiler_mode != CompilerMode::CompileV2 {
+            return Err(arg_parser.error("Option --backend is only available with --compile-v2"));
+        }
+
+        let columns = ColumnUnit::parse(
+            column_unit,
+            tab_width.unwrap_or(ColumnUnit::DEFAULT_TAB_WIDTH),
+        )?;
+        if tab_width.is_some() && columns == ColumnUnit::Utf16 {
+            return Err(arg_parser.error("--tab-width only applies to `--columns chars`"));
+        }
+        columns.set();
+
+        if eval_options.debug && file_name == "<stdin>" {
+            return Err(String::from(
+                "--debug reads commands from <stdin>, source must be a file",
+            ));
+        }
+
+        let strip = release || strip;
+        let opt_level = opt_level.unwrap_or(if release {
+            OptLevel::O2
+        } else {
+            OptLevel::default()
+        });
+
+        if compiler_mode == CompilerMode::CompileV2 || compiler_mode == CompilerMode::CompileGc {
             let mut files = Vec::new();
-            parse_file_and_deps(&mut files, file_name, &LoLocation::internal())?;
+            measure_phase("parse", || {
+                parse_file_and_deps(&mut files, file_name, &LoLocation::internal())
+            })?;
 
-            let mut ir_generator = IRGenerator::default();
-            for file in files.iter().rev() {
-                ir_generator.process_file(file)?;
-            }
-            ir_generator.errors.print_all()?;
-            let lo_ir = ir_generator.generate_ir()?;
+            let lo_ir = measure_phase("ir gen", || {
+                let mut ir_generator = IRGenerator::default();
+                for file in files.iter().rev() {
+                    ir_generator.process_file(file)?;
+                }
+                ir_generator.errors.print_all()?;
+                Ok::<_, String>(ir_generator.generate_ir()?)
+            })?;
 
-            let wasm_module = CodeGenerator::generate(lo_ir);
+            let backend = match compiler_mode {
+                CompilerMode::CompileGc => get_backend("wasm-gc")?,
+                _ => get_backend(backend_name.unwrap_or("wasm"))?,
+            };
 
-            let mut binary = Vec::new();
-            wasm_module.dump(&mut binary);
-            fputs(wasi::FD_STDOUT, binary.as_slice());
+            let output = measure_phase("codegen", || backend.generate(lo_ir, &mut features))?;
+            let mut wasm_module = match output {
+                BackendOutput::Wasm(wasm_module) => *wasm_module,
+                BackendOutput::Text(text) => {
+                    match output_path {
+                        Some(output_path) => file_write(output_path, text.as_bytes())?,
+                        None => stdout_write(text),
+                    }
+                    return Ok(());
+                }
+            };
+            measure_phase("optimize", || {
+                optimize_module(&mut wasm_module, opt_level);
+                if !features.has(WasmFeature::MultiValue) {
+                    remove_multivalue(&mut wasm_module)?;
+                }
+                Ok::<_, String>(())
+            })?;
+            if validate {
+                measure_phase("validate", || {
+                    WasmValidator::validate(&wasm_module, features).map_err(|err| err.message)
+                })?;
+            }
+            if strip {
+                wasm_module.debug_fn_info.clear();
+            }
+
+            measure_phase("emit", || {
+                write_output(&wasm_module, emit_hash, output_path, force).map(|_| ())
+            })?;
 
             return Ok(());
         }
@@ -118,26 +582,144 @@ mod wasi_api {
             return Ok(());
         };
 
-        if compiler_mode == CompilerMode::Inspect {
+        if compiler_mode == CompilerMode::ExpandMacros {
+            let mut files = Vec::new();
+            parse_file_and_deps(&mut files, file_name, &LoLocation::internal())?;
+            let ast = MacroExpander::expand(files)?;
+
+            Printer::print(Rc::new(ast));
+
+            return Ok(());
+        };
+
+        if compiler_mode == CompilerMode::EmitAst {
+            let chars = file_read_utf8(file_name)?;
+            let tokens = Lexer::lex(file_name, &chars)?;
+            let ast = ParserV2::parse(tokens)?;
+
+            stdout_write(ast_to_json(&ast));
+
+            return Ok(());
+        };
+
+        if compiler_mode == CompilerMode::Doc {
+            let chars = file_read_utf8(file_name)?;
+            let tokens = Lexer::lex(file_name, &chars)?;
+            let ast = ParserV2::parse(tokens)?;
+
+            Printer::print_docs(Rc::new(ast));
+
+            return Ok(());
+        };
+
+        if compiler_mode == CompilerMode::Inspect || compiler_mode == CompilerMode::EmitIr {
             stdout_enable_bufferring();
         }
 
         let ctx = &mut parser::init(compiler_mode);
+        // the debugger resolves `<file>:<line>` breakpoints by statement locations
+        ctx.emit_source_locs =
+            source_map_path.is_some() || ctx.mode == CompilerMode::EmitIr || eval_options.debug;
+        ctx.export_data_layout_globals = export_heap_base;
+        ctx.unmangled_exports = unmangled_exports;
+        ctx.features = features;
+        ctx.checks = checks;
+        ctx.inspect_range = inspect_range;
+        if !features.has(WasmFeature::MultiValue) {
+            ctx.max_fn_result_components = 1;
+        }
+        if let Some(data_start) = data_start {
+            ctx.data_start_override = Some(data_start);
+            *ctx.data_size.borrow_mut() = data_start;
+        }
 
-        parser::parse_file(ctx, file_name, &LoLocation::internal())?;
+        measure_phase("parse", || {
+            parser::parse_file(ctx, file_name, &LoLocation::internal())
+        })?;
 
-        parser::finalize(ctx)?;
+        measure_phase("finalize", || parser::finalize(ctx))?;
+
+        if ctx.mode != CompilerMode::Inspect {
+            for warning in ctx.warnings.borrow().iter() {
+                stderr_write_diagnostic(format!("{warning}\n"));
+            }
+        }
+
+        if ctx.mode == CompilerMode::Compile || ctx.mode == CompilerMode::Eval {
+            measure_phase("optimize", || {
+                optimize_module(&mut ctx.wasm_module.borrow_mut(), opt_level);
+                if !features.has(WasmFeature::MultiValue) {
+                    remove_multivalue(&mut ctx.wasm_module.borrow_mut())?;
+                }
+                Ok::<_, String>(())
+            })?;
+        }
+
+        if validate && (ctx.mode == CompilerMode::Compile || ctx.mode == CompilerMode::Eval) {
+            measure_phase("validate", || {
+                WasmValidator::validate(&ctx.wasm_module.borrow(), features)
+                    .map_err(|err| err.message)
+            })?;
+        }
 
         if ctx.mode == CompilerMode::Compile {
-            let mut binary = Vec::new();
-            ctx.wasm_module.take().dump(&mut binary);
-            fputs(wasi::FD_STDOUT, binary.as_slice());
+            if data_report {
+                let wasm_module = ctx.wasm_module.borrow();
+                let data_bytes = wasm_module.datas.iter().fold(0, |size, data| {
+                    let WasmData::Active { bytes, .. } = data;
+                    size + bytes.len()
+                });
+                stderr_write(format!(
+                    "data section: {data_bytes} bytes in {} segments, \
+                    {} bytes saved by string pooling\n",
+                    wasm_module.datas.len(),
+                    ctx.string_bytes_saved.borrow(),
+                ));
+            }
+
+            if report {
+                let string_bytes_saved = *ctx.string_bytes_saved.borrow();
+                stderr_write(generate_report(
+                    &ctx.wasm_module.borrow(),
+                    string_bytes_saved,
+                ));
+            }
+
+            if let Some(bindings_kind) = bindings_kind {
+                stdout_write(generate_bindings(ctx, bindings_kind)?);
+                return Ok(());
+            }
+
+            let mut wasm_module = ctx.wasm_module.take();
+            if strip {
+                wasm_module.debug_fn_info.clear();
+            }
+
+            if let Some(source_map_path) = source_map_path {
+                wasm_module.source_map_url = Some(String::from(source_map_path));
+            }
+
+            measure_phase("emit", || {
+                let source_locs = write_output(&wasm_module, emit_hash, output_path, force)?;
+                if let Some(source_map_path) = source_map_path {
+                    let source_map = generate_source_map(&source_locs);
+                    file_write(source_map_path, source_map.as_bytes())?;
+                }
+                Ok::<_, String>(())
+            })?;
         }
 
         if ctx.mode == CompilerMode::Eval {
             let wasm_module = ctx.wasm_module.take();
 
-            WasmEval::eval(wasm_module).map_err(|err| err.message)?;
+            // warnings go before any output of the program
+            stderr_flush();
+            let exit_code = measure_phase("eval", || WasmEval::eval(wasm_module, eval_options))
+                .map_err(|err| err.message)?;
+            if exit_code != 0 {
+                timings_print();
+                proc_exit(exit_code);
+            }
         }
 
         return Ok(());
@@ -0,0 +1,441 @@
+use crate::{ast::*, core::*, ir::MAX_MACRO_EXPANSION_DEPTH, lexer::*, parser_v2::FileInfo};
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+
+// source level expansion used by `--expand-macros`, args are substituted
+//   as written, same as the compiler evaluates them at every use
+pub struct MacroExpander {
+    macros: BTreeMap<String, MacroDefExpr>,
+    local_types: BTreeMap<String, String>, // type names of the current fn's locals, if known
+}
+
+impl MacroExpander {
+    // returns the AST of the first file with macro calls replaced by their expansions,
+    //   macros can be defined in any of the files
+    pub fn expand(mut files: Vec<FileInfo>) -> Result<AST, LoError> {
+        let mut expander = MacroExpander {
+            macros: BTreeMap::new(),
+            local_types: BTreeMap::new(),
+        };
+        for file in &files {
+            for expr in &file.ast.exprs {
+                if let TopLevelExpr::MacroDef(macro_def) = expr {
+                    let macro_name = macro_def.macro_name.repr.clone();
+                    expander.macros.insert(macro_name, macro_def.clone());
+                }
+            }
+        }
+
+        let mut ast = files.remove(0).ast;
+        for expr in &mut ast.exprs {
+            match expr {
+                TopLevelExpr::FnDef(fn_def) => {
+                    expander.local_types.clear();
+                    for param in &fn_def.decl.fn_params {
+                        if let FnParamType::Type {
+                            expr: TypeExpr::Named { name },
+                        } = &param.param_type
+                        {
+                            let param_name = param.param_name.clone();
+                            expander.local_types.insert(param_name, name.repr.clone());
+                        }
+                    }
+                    expander.expand_block(&mut fn_def.body, 0)?
+                }
+                TopLevelExpr::GlobalDef(global_def) => {
+                    expander.expand_expr(&mut global_def.expr, 0)?
+                }
+                TopLevelExpr::ConstDef(const_def) => {
+                    expander.expand_expr(&mut const_def.const_value, 0)?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(ast)
+    }
+
+    fn expand_block(&mut self, block: &mut CodeBlockExpr, depth: u32) -> Result<(), LoError> {
+        for expr in &mut block.exprs {
+            self.expand_expr(expr, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn expand_expr(&mut self, expr: &mut CodeExpr, depth: u32) -> Result<(), LoError> {
+        let macro_def = match expr {
+            CodeExpr::MacroFnCall(call) => self.macros.get(&call.fn_name.repr),
+            // without a known receiver type the method has to be unambiguous
+            CodeExpr::MacroMethodCall(call) => {
+                if let Some(type_name) = self.guess_type(&call.lhs) {
+                    let macro_name = format!("{type_name}::{}", call.field_name.repr);
+                    self.macros.get(&macro_name)
+                } else {
+                    self.find_method_macro(&call.field_name.repr)
+                }
+            }
+            _ => None,
+        };
+
+        let Some(macro_def) = macro_def.cloned() else {
+            for_each_child(expr, &mut |child| self.expand_expr(child, depth))?;
+
+            if let CodeExpr::Let(LetExpr {
+                local_name, value, ..
+            }) = expr
+            {
+                match self.guess_type(value) {
+                    Some(type_name) => self.local_types.insert(local_name.clone(), type_name),
+                    None => self.local_types.remove(local_name),
+                };
+            }
+
+            return Ok(());
+        };
+
+        let call = expr.clone();
+        let (type_args, mut args) = match expr {
+            CodeExpr::MacroFnCall(call) => (&call.type_args, call.args.clone()),
+            CodeExpr::MacroMethodCall(call) => {
+                let mut args = Vec::new();
+                args.push((*call.lhs).clone());
+                args.extend(call.args.iter().cloned());
+                (&call.type_args, args)
+            }
+            _ => unreachable!(),
+        };
+        let macro_name = &macro_def.macro_name.repr;
+        let loc = call.loc();
+
+        if depth >= MAX_MACRO_EXPANSION_DEPTH {
+            return Err(LoError {
+                message: format!(
+                    "Macro expansion depth limit ({MAX_MACRO_EXPANSION_DEPTH}) exceeded while expanding {macro_name}!"
+                ),
+                loc: *loc,
+            });
+        }
+
+        if type_args.len() != macro_def.macro_type_params.len() {
+            return Err(LoError {
+                message: format!(
+                    "Invalid number of type params, expected {}, got {}",
+                    macro_def.macro_type_params.len(),
+                    type_args.len()
+                ),
+                loc: *loc,
+            });
+        }
+
+        if args.len() != macro_def.macro_params.len() {
+            return Err(LoError {
+                message: format!(
+                    "Invalid number of args for `{macro_name}!`, expected {}, got {}",
+                    macro_def.macro_params.len(),
+                    args.len()
+                ),
+                loc: *loc,
+            });
+        }
+
+        // args belong to the caller so they are expanded before substitution
+        let mut values = BTreeMap::new();
+        for (param, arg) in macro_def.macro_params.iter().zip(&mut args) {
+            self.expand_expr(arg, depth)?;
+            values.insert(param.param_name.clone(), as_operand(arg.clone()));
+        }
+
+        let mut types = BTreeMap::new();
+        for (name, type_arg) in macro_def.macro_type_params.iter().zip(type_args) {
+            types.insert(name.clone(), type_arg.clone());
+        }
+
+        let mut body = macro_def.body.clone();
+        // macro locals don't leak into the caller
+        let caller_local_types = self.local_types.clone();
+        for body_expr in &mut body.exprs {
+            substitute(body_expr, &values, &types)?;
+            self.expand_expr(body_expr, depth + 1)?;
+        }
+        self.local_types = caller_local_types;
+
+        *expr = CodeExpr::MacroExpansion(MacroExpansionExpr {
+            loc: *loc,
+            call: Box::new(call),
+            macro_loc: macro_def.loc,
+            body,
+        });
+
+        Ok(())
+    }
+
+    fn find_method_macro(&self, method_name: &str) -> Option<&MacroDefExpr> {
+        let suffix = format!("::{method_name}");
+        let mut candidates = self.macros.values().filter(|macro_def| {
+            macro_def.macro_name.repr.ends_with(&suffix)
+                && macro_def.macro_params.first().is_some_and(|p| {
+                    matches!(
+                        p.param_type,
+                        FnParamType::Self_ | FnParamType::SelfRef { .. }
+                    )
+                })
+        });
+
+        match (candidates.next(), candidates.next()) {
+            (Some(macro_def), None) => Some(macro_def),
+            _ => None,
+        }
+    }
+
+    fn guess_type(&self, expr: &CodeExpr) -> Option<String> {
+        match expr {
+            CodeExpr::BoolLiteral(_) => Some(String::from("bool")),
+            CodeExpr::StringLiteral(_) => Some(String::from("str")),
+            CodeExpr::IntLiteral(IntLiteralExpr { tag, .. }) => {
+                Some(tag.clone().unwrap_or(String::from("u32")))
+            }
+            CodeExpr::PrefixOp(PrefixOpExpr {
+                op_tag: PrefixOpTag::Positive | PrefixOpTag::Negative,
+                expr,
+                ..
+            }) => match self.guess_type(expr)?.as_str() {
+                "u64" | "i64" => Some(String::from("i64")),
+                _ => Some(String::from("i32")),
+            },
+            CodeExpr::Cast(CastExpr {
+                casted_to: TypeExpr::Named { name },
+                ..
+            }) => Some(name.repr.clone()),
+            CodeExpr::StructLiteral(StructLiteralExpr { struct_name, .. }) => {
+                Some(struct_name.repr.clone())
+            }
+            CodeExpr::Ident(ident) => self.local_types.get(&ident.repr).cloned(),
+            CodeExpr::Paren(ParenExpr { expr, .. }) => self.guess_type(expr),
+            CodeExpr::InfixOp(InfixOpExpr {
+                op_tag, lhs, rhs, ..
+            }) => match op_tag {
+                InfixOpTag::Equal
+                | InfixOpTag::NotEqual
+                | InfixOpTag::Less
+                | InfixOpTag::Greater
+                | InfixOpTag::LessEqual
+                | InfixOpTag::GreaterEqual => Some(String::from("bool")),
+                _ => self.guess_type(lhs).or_else(|| self.guess_type(rhs)),
+            },
+            _ => None,
+        }
+    }
+}
+
+fn substitute(
+    expr: &mut CodeExpr,
+    values: &BTreeMap<String, CodeExpr>,
+    types: &BTreeMap<String, TypeExpr>,
+) -> Result<(), LoError> {
+    if let CodeExpr::Ident(ident) = expr {
+        if let Some(value) = values.get(&ident.repr) {
+            *expr = value.clone();
+            return Ok(());
+        }
+    }
+
+    match expr {
+        CodeExpr::Cast(CastExpr { casted_to, .. }) => substitute_type(casted_to, types),
+        CodeExpr::Bitcast(BitcastExpr {
+            from_type, to_type, ..
+        }) => {
+            substitute_type(from_type, types);
+            substitute_type(to_type, types);
+        }
+        CodeExpr::Sizeof(SizeofExpr { type_expr, .. })
+        | CodeExpr::Reflection(ReflectionExpr { type_expr, .. }) => {
+            substitute_type(type_expr, types)
+        }
+        CodeExpr::ArrayLiteral(ArrayLiteralExpr { item_type, .. }) => {
+            substitute_type(item_type, types)
+        }
+        CodeExpr::Catch(CatchExpr {
+            error_type: Some(error_type),
+            ..
+        }) => substitute_type(error_type, types),
+        CodeExpr::MacroFnCall(MacroFnCallExpr { type_args, .. })
+        | CodeExpr::MacroMethodCall(MacroMethodCallExpr { type_args, .. }) => {
+            for type_arg in type_args {
+                substitute_type(type_arg, types);
+            }
+        }
+        _ => {}
+    }
+
+    for_each_child(expr, &mut |child| substitute(child, values, types))
+}
+
+fn substitute_type(type_expr: &mut TypeExpr, types: &BTreeMap<String, TypeExpr>) {
+    match type_expr {
+        TypeExpr::Named { name } => {
+            if let Some(type_arg) = types.get(&name.repr) {
+                *type_expr = type_arg.clone();
+            }
+        }
+        TypeExpr::Pointer { pointee, .. } | TypeExpr::SequencePointer { pointee } => {
+            substitute_type(pointee, types)
+        }
+        TypeExpr::Slice { item_type } => substitute_type(item_type, types),
+        TypeExpr::Result { ok_type, err_type } => {
+            substitute_type(ok_type, types);
+            substitute_type(err_type, types);
+        }
+        TypeExpr::ErrorUnion { variants } => {
+            for variant in variants {
+                substitute_type(variant, types);
+            }
+        }
+        TypeExpr::Of {
+            container_type,
+            item_type,
+        } => {
+            substitute_type(container_type, types);
+            substitute_type(item_type, types);
+        }
+        TypeExpr::Struct { fields, .. } => {
+            for field in fields {
+                substitute_type(&mut field.field_type, types);
+            }
+        }
+    }
+}
+
+// substituted args keep their meaning when used as an operand
+fn as_operand(expr: CodeExpr) -> CodeExpr {
+    match expr {
+        CodeExpr::BoolLiteral(_)
+        | CodeExpr::CharLiteral(_)
+        | CodeExpr::IntLiteral(_)
+        | CodeExpr::StringLiteral(_)
+        | CodeExpr::Ident(_)
+        | CodeExpr::Paren(_)
+        | CodeExpr::FnCall(_)
+        | CodeExpr::MethodCall(_)
+        | CodeExpr::FieldAccess(_)
+        | CodeExpr::Index(_)
+        | CodeExpr::MacroExpansion(_) => expr,
+        _ => CodeExpr::Paren(ParenExpr {
+            loc: *expr.loc(),
+            expr: Box::new(expr),
+        }),
+    }
+}
+
+// expansions are final, so their bodies are not visited
+fn for_each_child(
+    expr: &mut CodeExpr,
+    f: &mut dyn FnMut(&mut CodeExpr) -> Result<(), LoError>,
+) -> Result<(), LoError> {
+    match expr {
+        CodeExpr::BoolLiteral(_)
+        | CodeExpr::CharLiteral(_)
+        | CodeExpr::IntLiteral(_)
+        | CodeExpr::StringLiteral(_)
+        | CodeExpr::Ident(_)
+        | CodeExpr::MacroExpansion(_)
+        | CodeExpr::Dbg(_)
+        | CodeExpr::Sizeof(_)
+        | CodeExpr::GetDataSize(_)
+        | CodeExpr::Break(_)
+        | CodeExpr::Continue(_)
+        | CodeExpr::Unreachable(_) => {}
+        CodeExpr::StructLiteral(StructLiteralExpr { fields, .. }) => {
+            for field in fields {
+                f(&mut field.value)?;
+            }
+        }
+        CodeExpr::ArrayLiteral(ArrayLiteralExpr { items, .. }) => {
+            for item in items {
+                f(item)?;
+            }
+        }
+        CodeExpr::ArrayRepeat(ArrayRepeatExpr { item, count, .. }) => {
+            f(item)?;
+            f(count)?;
+        }
+        CodeExpr::Let(LetExpr { value: expr, .. })
+        | CodeExpr::PrefixOp(PrefixOpExpr { expr, .. })
+        | CodeExpr::Cast(CastExpr { expr, .. })
+        | CodeExpr::Bitcast(BitcastExpr { expr, .. })
+        | CodeExpr::FieldAccess(FieldAccessExpr { lhs: expr, .. })
+        | CodeExpr::PropagateError(PropagateErrorExpr { expr, .. })
+        | CodeExpr::Defer(DeferExpr { expr, .. })
+        | CodeExpr::Paren(ParenExpr { expr, .. }) => f(expr)?,
+        CodeExpr::InfixOp(InfixOpExpr { lhs, rhs, .. })
+        | CodeExpr::Assign(AssignExpr { lhs, rhs, .. })
+        | CodeExpr::Index(IndexExpr {
+            lhs, index: rhs, ..
+        }) => {
+            f(lhs)?;
+            f(rhs)?;
+        }
+        CodeExpr::FnCall(FnCallExpr { args, .. })
+        | CodeExpr::MacroFnCall(MacroFnCallExpr { args, .. }) => {
+            for arg in args {
+                f(arg)?;
+            }
+        }
+        CodeExpr::MethodCall(MethodCallExpr { lhs, args, .. })
+        | CodeExpr::MacroMethodCall(MacroMethodCallExpr { lhs, args, .. }) => {
+            f(lhs)?;
+            for arg in args {
+                f(arg)?;
+            }
+        }
+        CodeExpr::Return(ReturnExpr { expr, .. })
+        | CodeExpr::Reflection(ReflectionExpr { index: expr, .. }) => {
+            if let Some(expr) = expr {
+                f(expr)?;
+            }
+        }
+        CodeExpr::If(IfExpr {
+            cond,
+            then_block,
+            else_block,
+            ..
+        }) => {
+            f(cond)?;
+            for expr in &mut then_block.exprs {
+                f(expr)?;
+            }
+            match else_block {
+                ElseBlock::None => {}
+                ElseBlock::Else(block) => {
+                    for expr in &mut block.exprs {
+                        f(expr)?;
+                    }
+                }
+                ElseBlock::ElseIf(expr) => f(expr)?,
+            }
+        }
+        CodeExpr::Loop(LoopExpr { body, .. }) => {
+            for expr in &mut body.exprs {
+                f(expr)?;
+            }
+        }
+        CodeExpr::ForLoop(ForLoopExpr {
+            start, end, body, ..
+        }) => {
+            f(start)?;
+            f(end)?;
+            for expr in &mut body.exprs {
+                f(expr)?;
+            }
+        }
+        CodeExpr::Catch(CatchExpr {
+            lhs, catch_body, ..
+        }) => {
+            f(lhs)?;
+            for expr in &mut catch_body.exprs {
+                f(expr)?;
+            }
+        }
+    }
+
+    Ok(())
+}
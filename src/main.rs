@@ -0,0 +1,3 @@
+fn main() {
+    lo::_start();
+}
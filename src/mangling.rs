@@ -0,0 +1,76 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+const MANGLED_PREFIX: &str = "_L";
+
+// plain identifiers are kept as is, other names become `_L` followed by their `::` separated
+//   segments, each prefixed with its byte length, bytes other than `[A-Za-z0-9_]`
+//   (and leading digits) are escaped as `$<hex>`, so `Vec::push` is `_L3Vec4push`
+//   and `&[u8]::len` is `_L11$26$5Bu8$5D3len`
+pub fn mangle_name(name: &str) -> String {
+    if is_plain_identifier(name) && !name.starts_with(MANGLED_PREFIX) {
+        return String::from(name);
+    }
+
+    let mut mangled = String::from(MANGLED_PREFIX);
+    for segment in split_segments(name) {
+        let escaped = escape_segment(segment);
+        write!(&mut mangled, "{}{escaped}", escaped.len()).unwrap();
+    }
+
+    mangled
+}
+
+fn is_plain_identifier(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    let Some(first) = bytes.next() else {
+        return false;
+    };
+
+    (first.is_ascii_alphabetic() || first == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+// `::` inside of type arguments (like `Result<a::B, E>::unwrap`) doesn't start a segment
+fn split_segments(name: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut depth = 0;
+    let mut segment_start = 0;
+
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'<' | b'[' | b'(' => depth += 1,
+            b'>' | b']' | b')' => depth -= 1,
+            b':' if depth == 0 && bytes.get(i + 1) == Some(&b':') => {
+                segments.push(&name[segment_start..i]);
+                segment_start = i + 2;
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    segments.push(&name[segment_start..]);
+
+    segments
+}
+
+fn escape_segment(segment: &str) -> String {
+    let mut escaped = String::new();
+
+    for (byte, i) in segment.bytes().zip(0..) {
+        let is_kept =
+            byte.is_ascii_alphabetic() || byte == b'_' || (byte.is_ascii_digit() && i != 0);
+
+        if is_kept {
+            escaped.push(byte as char);
+        } else {
+            write!(&mut escaped, "${byte:02X}").unwrap();
+        }
+    }
+
+    escaped
+}
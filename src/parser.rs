@@ -1,5 +1,9 @@
-use crate::{core::*, ir::*, lexer::*, wasm::*};
-use alloc::{boxed::Box, collections::BTreeMap, format, str, string::String, vec, vec::Vec};
+use crate::{
+    core::*, ir::*, ir_printer::*, lexer::*, wasi_abi::*, wasm::*, wasm_validator::MAX_PAGES,
+};
+use alloc::{
+    boxed::Box, collections::BTreeMap, format, rc::Rc, str, string::String, vec, vec::Vec,
+};
 use LoTokenType::*;
 
 const RECEIVER_PARAM_NAME: &str = "self";
@@ -7,6 +11,7 @@ const RECEIVER_PARAM_NAME: &str = "self";
 pub fn init<'a>(mode: CompilerMode) -> ModuleContext<'a> {
     let mut ctx = ModuleContext::default();
     ctx.mode = mode;
+    ctx.max_fn_result_components = MAX_FN_RESULT_COMPONENTS;
 
     if ctx.mode == CompilerMode::Inspect {
         stdout_writeln("[");
@@ -20,18 +25,26 @@ pub fn parse_file(
     file_path: &str,
     loc: &LoLocation,
 ) -> Result<u32, LoError> {
-    let file_path = resolve_path(file_path, &loc.file_name);
+    let file_path = resolve_include_path(file_path, &loc.file_name());
 
-    if let Some(file_index) = ctx.included_modules.get(&file_path) {
+    if let Some(file_index) = ctx.included_modules.get_by_name(&file_path) {
         return Ok(*file_index);
     }
 
-    let chars = file_read_utf8(&file_path).map_err(|message| LoError {
-        message,
-        loc: loc.clone(),
-    })?;
+    if ctx.include_depth >= MAX_INCLUDE_DEPTH {
+        return Err(LoError {
+            message: format!(
+                "Include depth limit ({MAX_INCLUDE_DEPTH}) exceeded while including {file_path}"
+            ),
+            loc: *loc,
+        });
+    }
+
+    let chars = file_read_utf8(&file_path).map_err(|message| LoError { message, loc: *loc })?;
 
+    ctx.include_depth += 1;
     let file_index = parse_file_contents(ctx, file_path, &chars)?;
+    ctx.include_depth -= 1;
 
     return Ok(file_index);
 }
@@ -42,7 +55,10 @@ pub fn parse_file_contents(
     chars: &str,
 ) -> Result<u32, LoError> {
     let tokens = Lexer::lex(&file_path, &chars)?;
+    apply_pragmas(ctx, &tokens.pragmas)?;
+    let comments = tokens.comments;
     let mut tokens = LoTokenStream::new(tokens.tokens, tokens.end_loc);
+    tokens.comments = comments;
 
     let file_index = ctx.included_modules.len() as u32;
     if ctx.mode == CompilerMode::Inspect {
@@ -52,13 +68,46 @@ pub fn parse_file_contents(
                 \"path\": \"{file_path}\" }}, "
         ));
     }
-    ctx.included_modules.insert(file_path, file_index);
+    ctx.included_modules
+        .insert(Atom::intern(&file_path), file_index);
 
     parse_file_tokens(ctx, &mut tokens)?;
 
     return Ok(file_index);
 }
 
+fn apply_pragmas(ctx: &mut ModuleContext, pragmas: &[Pragma]) -> Result<(), LoError> {
+    for pragma in pragmas {
+        if pragma.name != "allow" {
+            return Err(LoError {
+                message: format!("Unknown pragma: {}", pragma.name),
+                loc: pragma.loc,
+            });
+        }
+
+        if pragma.args.is_empty() {
+            return Err(LoError {
+                message: String::from("Expected warning names after `#pragma allow`"),
+                loc: pragma.loc,
+            });
+        }
+
+        for warning_name in &pragma.args {
+            let Some(warning) = LoWarning::from_name(warning_name) else {
+                return Err(LoError {
+                    message: format!("Unknown warning: {warning_name}"),
+                    loc: pragma.loc,
+                });
+            };
+
+            ctx.allowed_warnings
+                .insert((pragma.loc.file_name(), warning));
+        }
+    }
+
+    Ok(())
+}
+
 fn parse_file_tokens(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Result<(), LoError> {
     while tokens.peek().is_some() {
         parse_top_level_expr(ctx, tokens)?;
@@ -68,7 +117,7 @@ fn parse_file_tokens(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Res
     if let Some(unexpected) = tokens.peek() {
         return Err(LoError {
             message: format!("Unexpected token on top level: {}", unexpected.value),
-            loc: unexpected.loc.clone(),
+            loc: unexpected.loc,
         });
     }
 
@@ -76,9 +125,19 @@ fn parse_file_tokens(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Res
 }
 
 pub fn finalize(ctx: &mut ModuleContext) -> Result<(), LoError> {
+    let needs_allocator = ctx.alloc_fn.is_none() && ctx.free_fn.is_none();
+    if needs_allocator && !ctx.memories.is_empty() && uses_alloc_intrinsics(ctx) {
+        let tokens = Lexer::lex("<internal>", BUMP_ALLOCATOR)?;
+        let mut tokens = LoTokenStream::new(tokens.tokens, tokens.end_loc);
+        parse_file_tokens(ctx, &mut tokens)?;
+
+        ctx.alloc_fn = Some(String::from("__bump_alloc"));
+        ctx.free_fn = Some(String::from("__bump_free"));
+    }
+
     // push function exports
     for fn_export in &ctx.fn_exports {
-        let fn_def = ctx.fn_defs.get(&fn_export.in_name).unwrap(); // safe
+        let fn_def = ctx.get_fn_def(&fn_export.in_name).unwrap(); // safe
 
         ctx.wasm_module.borrow_mut().exports.push(WasmExport {
             export_type: WasmExportType::Func,
@@ -87,81 +146,64 @@ pub fn finalize(ctx: &mut ModuleContext) -> Result<(), LoError> {
         });
     }
 
-    // push function codes
-    for mut fn_body in ctx.fn_bodies.take() {
-        let fn_def = ctx
-            .fn_defs
-            .values()
-            .find(|fd| fd.local && fd.fn_index == fn_body.fn_index)
-            .unwrap();
-
-        let mut fn_ctx = FnContext {
-            module: &ctx,
-            lo_fn_type: &fn_def.type_,
-            locals_last_index: fn_body.locals_last_index,
-            non_arg_wasm_locals: vec![],
-            defers: vec![],
-        };
-
-        let locals_block = Block {
-            locals: fn_body.locals,
-            ..Default::default()
-        };
-
-        let mut block_ctx = BlockContext {
-            module: &ctx,
-            fn_ctx: &mut fn_ctx,
-            block: Block::child_of(ctx, &locals_block).of_kind(LoBlockKind::Function),
-        };
-
-        let mut contents = parse_block_contents(&mut block_ctx, &mut fn_body.body, LoType::Void)?;
-
-        if !contents.has_return && !contents.has_never {
-            if let Some(mut values) = get_deferred(&mut block_ctx) {
-                contents.exprs.append(&mut values);
-            };
-
-            let return_type = &fn_def.type_.output;
+    if let Some(start_fn) = &ctx.start_fn {
+        let fn_def = ctx.get_fn_def(start_fn).unwrap(); // safe, checked on parse
+        ctx.wasm_module.borrow_mut().start = Some(fn_def.get_absolute_index(ctx));
+    }
 
-            match return_type {
-                LoType::Void => {}
-                LoType::Never => {
-                    return Err(LoError {
-                        message: format!("This function terminates but is marked as `never`"),
-                        loc: fn_def.loc.clone(),
-                    });
-                }
-                _ => {
-                    return Err(LoError {
-                        message: format!("Missing return expression"),
-                        loc: fn_def.loc.clone(),
-                    });
-                }
-            }
+    // tables are sized to fit their fns, which are placed by an element segment
+    let imported_tables = ctx.tables.iter().filter(|t| t.imported).count() as u32;
+    let defined_tables = ctx.tables.iter().filter(|t| !t.imported);
+    for (table_def, table_index) in defined_tables.zip(imported_tables..) {
+        let mut fn_indices = vec![];
+        for fn_name in &table_def.fn_names {
+            let fn_def = ctx.get_fn_def(fn_name).unwrap(); // safe, checked on parse
+            fn_indices.push(fn_def.get_absolute_index(ctx));
         }
 
-        let mut locals = Vec::<WasmLocals>::new();
-        for local_type in &block_ctx.fn_ctx.non_arg_wasm_locals {
-            if let Some(wasm_locals) = locals.last_mut() {
-                if wasm_locals.value_type == *local_type {
-                    wasm_locals.count += 1;
-                    continue;
-                }
-            }
-            locals.push(WasmLocals {
-                count: 1,
-                value_type: local_type.clone(),
+        let mut wasm_module = ctx.wasm_module.borrow_mut();
+        wasm_module.tables.push(WasmLimits {
+            min: fn_indices.len() as u32,
+            max: None,
+            shared: false,
+        });
+        if table_def.exported {
+            wasm_module.exports.push(WasmExport {
+                export_type: WasmExportType::Table,
+                export_name: table_def.name.clone(),
+                exported_item_index: table_index,
+            });
+        }
+        if !fn_indices.is_empty() {
+            wasm_module.elements.push(WasmElement::Active {
+                table_index,
+                offset: WasmExpr {
+                    instrs: vec![WasmInstr::I32Const { value: 0 }],
+                },
+                fn_indices,
             });
         }
+    }
 
-        let mut instrs = vec![];
-        lower_exprs(&mut instrs, &contents.exprs);
+    // sret results are written here by callees and read back right after the call
+    if ctx.sret_buffer_size != 0 {
+        ctx.sret_buffer_ptr = ctx.reserve_data(ctx.sret_buffer_size, 8);
+    }
 
-        ctx.wasm_module.borrow_mut().codes.push(WasmFn {
-            locals,
-            expr: WasmExpr { instrs },
-        });
+    // push function codes
+    let mut fn_local_names = BTreeMap::new();
+    for fn_body in ctx.fn_bodies.take() {
+        // tooling only needs bodies in the inspected range, signatures are checked on parse
+        if !should_compile_fn_body(ctx, &fn_body) {
+            continue;
+        }
+
+        let fn_index = fn_body.fn_index;
+        let (wasm_fn, local_names) = compile_fn_body(ctx, fn_body)?;
+        ctx.wasm_module.borrow_mut().codes.push(wasm_fn);
+        fn_local_names.insert(fn_index, local_names);
     }
+    ctx.fn_local_names = fn_local_names;
 
     if ctx.mode != CompilerMode::Inspect {
         // put __DATA_SIZE__ value into all globals that contain it
@@ -180,6 +222,54 @@ pub fn finalize(ctx: &mut ModuleContext) -> Result<(), LoError> {
                 },
             );
         }
+
+        if ctx.export_data_layout_globals {
+            for global_name in DATA_LAYOUT_GLOBALS {
+                if let Some(loc) = ctx.export_names.get(global_name) {
+                    return Err(LoError {
+                        message: format!(
+                            "Duplicate export name: {global_name}, \
+                            also exported by --export-heap-base"
+                        ),
+                        loc: *loc,
+                    });
+                }
+
+                let global_index = ctx.get_data_layout_global(global_name).unwrap();
+
+                ctx.wasm_module.borrow_mut().exports.push(WasmExport {
+                    export_type: WasmExportType::Global,
+                    export_name: String::from(global_name),
+                    exported_item_index: global_index,
+                });
+            }
+        }
+
+        let data_end = *ctx.data_size.borrow();
+        let mut heap_base = data_end.next_multiple_of(HEAP_BASE_ALIGNMENT);
+
+        if let Some(global_index) = *ctx.stack_pointer_global.borrow() {
+            heap_base += SHADOW_STACK_SIZE;
+
+            ctx.wasm_module.borrow_mut().globals[global_index as usize].initial_value = WasmExpr {
+                instrs: vec![WasmInstr::I32Const {
+                    value: heap_base as i32,
+                }],
+            };
+        }
+
+        for (global_name, global_index) in ctx.data_layout_globals.borrow().iter() {
+            let value = match global_name.as_str() {
+                "__heap_base" => heap_base,
+                _ => data_end,
+            };
+
+            ctx.wasm_module.borrow_mut().globals[*global_index as usize].initial_value = WasmExpr {
+                instrs: vec![WasmInstr::I32Const {
+                    value: value as i32,
+                }],
+            };
+        }
     }
 
     if ctx.mode == CompilerMode::Compile || ctx.mode == CompilerMode::Eval {
@@ -195,27 +285,244 @@ pub fn finalize(ctx: &mut ModuleContext) -> Result<(), LoError> {
     Ok(())
 }
 
-// TODO: add local names (requires sizable refactoring to achieve)
+fn should_compile_fn_body(ctx: &ModuleContext, fn_body: &FnBody) -> bool {
+    let Some(inspect_range) = &ctx.inspect_range else {
+        return true;
+    };
+
+    let fn_name = ctx.fn_names[fn_body.fn_index as usize];
+    let fn_def = &ctx.fn_defs[fn_name];
+    inspect_range.overlaps(&fn_def.loc, &fn_body.body.terminal_token.loc)
+}
+
+fn compile_fn_body(
+    ctx: &ModuleContext,
+    mut fn_body: FnBody,
+) -> Result<(WasmFn, Vec<WasmLocalName>), LoError> {
+    let fn_name = ctx.fn_names[fn_body.fn_index as usize];
+    let fn_def = &ctx.fn_defs[fn_name];
+
+    let mut fn_ctx = FnContext {
+        module: ctx,
+        fn_name: fn_name.as_str(),
+        lo_fn_type: &fn_def.type_,
+        locals_last_index: fn_body.locals_last_index,
+        non_arg_wasm_locals: vec![],
+        defers: vec![],
+        local_names: vec![],
+        address_taken_locals: collect_address_taken_names(&fn_body.body),
+        stack_frame_local: None,
+        stack_frame_size: 0,
+        macro_depth: 0,
+        expr_depth: 0,
+        loop_breaks: vec![],
+    };
+
+    if fn_def.sret {
+        fn_ctx.add_local_name("<sret>", 0, 1);
+    }
+    for (param_name, param) in fn_body.locals.iter() {
+        let comp_count = param.value_type.emit_components(ctx, &mut vec![]);
+        fn_ctx.add_local_name(param_name.as_str(), param.index, comp_count);
+    }
+
+    if !fn_ctx.address_taken_locals.is_empty() {
+        let stack_frame_local = fn_ctx.locals_last_index;
+        fn_ctx.non_arg_wasm_locals.push(WasmType::I32);
+        fn_ctx.non_arg_wasm_locals.push(WasmType::I32);
+        fn_ctx.locals_last_index += 2;
+        fn_ctx.add_local_name("<stack frame>", stack_frame_local, 1);
+        fn_ctx.add_local_name("<prev stack pointer>", stack_frame_local + 1, 1);
+        fn_ctx.stack_frame_local = Some(stack_frame_local);
+
+        // goes first so it runs after all user defers
+        fn_ctx.defers.push(
+            LoInstr::MultiValueEmit {
+                values: vec![
+                    LoInstr::UntypedLocalGet {
+                        local_index: stack_frame_local + 1,
+                    },
+                    LoInstr::Set {
+                        bind: LoSetBind::Global {
+                            index: ctx.get_stack_pointer_global(),
+                        },
+                    },
+                ],
+            }
+            .casted(LoType::Void),
+        );
+    }
+
+    warn_about_unused_params(ctx, fn_def, &fn_body.body);
+
+    let locals_block = Block {
+        locals: fn_body.locals,
+        ..Default::default()
+    };
+
+    let mut block_ctx = BlockContext {
+        module: ctx,
+        fn_ctx: &mut fn_ctx,
+        block: Block::child_of(ctx, &locals_block).of_kind(LoBlockKind::Function),
+    };
+
+    let mut contents = parse_block_contents(&mut block_ctx, &mut fn_body.body, LoType::Void)?;
+
+    if !contents.has_return && !contents.has_never {
+        if let Some(mut values) = get_deferred(&mut block_ctx) {
+            contents.exprs.append(&mut values);
+        };
+
+        let return_type = &fn_def.type_.output;
+
+        match return_type {
+            LoType::Void => {}
+            LoType::Never => {
+                return Err(LoError {
+                    message: format!("This function terminates but is marked as `never`"),
+                    loc: fn_def.loc,
+                });
+            }
+            _ => {
+                return Err(LoError {
+                    message: format!("Missing return expression"),
+                    loc: fn_def.loc,
+                });
+            }
+        }
+    }
+
+    let mut locals = Vec::<WasmLocals>::new();
+    for local_type in &block_ctx.fn_ctx.non_arg_wasm_locals {
+        if let Some(wasm_locals) = locals.last_mut() {
+            if wasm_locals.value_type == *local_type {
+                wasm_locals.count += 1;
+                continue;
+            }
+        }
+        locals.push(WasmLocals {
+            count: 1,
+            value_type: local_type.clone(),
+        });
+    }
+
+    if let Some(stack_frame_local) = block_ctx.fn_ctx.stack_frame_local {
+        let stack_pointer_global = ctx.get_stack_pointer_global();
+        let frame_size = block_ctx.fn_ctx.stack_frame_size;
+
+        let prologue = LoInstr::MultiValueEmit {
+            values: vec![
+                LoInstr::GlobalGet {
+                    global_index: stack_pointer_global,
+                },
+                LoInstr::Set {
+                    bind: LoSetBind::Local {
+                        index: stack_frame_local + 1,
+                    },
+                },
+                LoInstr::BinaryOp {
+                    kind: WasmBinaryOpKind::I32_SUB,
+                    lhs: Box::new(LoInstr::UntypedLocalGet {
+                        local_index: stack_frame_local + 1,
+                    }),
+                    rhs: Box::new(LoInstr::U32Const {
+                        value: frame_size.next_multiple_of(STACK_FRAME_ALIGNMENT),
+                    }),
+                },
+                LoInstr::Set {
+                    bind: LoSetBind::Local {
+                        index: stack_frame_local,
+                    },
+                },
+                LoInstr::UntypedLocalGet {
+                    local_index: stack_frame_local,
+                },
+                LoInstr::Set {
+                    bind: LoSetBind::Global {
+                        index: stack_pointer_global,
+                    },
+                },
+            ],
+        };
+        contents.exprs.insert(0, prologue.casted(LoType::Void));
+    }
+
+    if ctx.start_fn.as_deref() == Some(fn_name.as_str()) {
+        contents.exprs.insert(0, compile_start_guard(ctx));
+    }
+
+    if ctx.mode == CompilerMode::EmitIr {
+        stdout_write(print_fn_ir(ctx, fn_name.as_str(), fn_def, &contents.exprs));
+    }
+
+    let mut instrs = vec![];
+    lower_exprs(&mut instrs, &contents.exprs);
+
+    let mut local_names = core::mem::take(&mut block_ctx.fn_ctx.local_names);
+    local_names.sort_by_key(|local_name| local_name.local_index);
+
+    let wasm_fn = WasmFn {
+        locals,
+        expr: WasmExpr { instrs },
+    };
+    Ok((wasm_fn, local_names))
+}
+
+// used when no `@alloc` and `@free` fns are defined, memory is never reused
+const BUMP_ALLOCATOR: &str = "
+global __bump_alloc::NEXT = 0;
+
+fn __bump_alloc(size: u32, align: u32): &void {
+    if __bump_alloc::NEXT == 0 {
+        // skips `__heap_base` itself as it can be 0 which is null
+        __bump_alloc::NEXT = __heap_base + 1;
+    };
+    let ptr = (__bump_alloc::NEXT + (align - 1)) & (0 - align);
+    let end = ptr + size;
+    let memory_end = __memory_size() as u32 * 65536;
+    if end > memory_end {
+        let pages_needed = ((end - memory_end) + 65535) / 65536;
+        if __memory_grow(pages_needed) == -1 {
+            unreachable;
+        };
+    };
+    __bump_alloc::NEXT = end;
+    return ptr as &void;
+};
+
+fn __bump_free(_ptr: &void) {};
+";
+
+fn uses_alloc_intrinsics(ctx: &ModuleContext) -> bool {
+    let is_alloc_intrinsic =
+        |t: &LoToken| t.type_ == Symbol && (t.value == "__alloc" || t.value == "__free");
+
+    let fn_bodies = ctx.fn_bodies.borrow();
+    let mut bodies = fn_bodies
+        .iter()
+        .map(|fn_body| &fn_body.body)
+        .chain(ctx.macros.values().map(|macro_def| &macro_def.body));
+
+    bodies.any(|body| body.tokens.iter().any(is_alloc_intrinsic))
+}
+
 fn write_debug_info(ctx: &mut ModuleContext) -> Result<(), LoError> {
     use crate::wasm::*;
 
     let mut wasm_module = ctx.wasm_module.borrow_mut();
 
     let first_own_fn_index = ctx.imported_fns_count;
-    let own_fns_count = wasm_module.functions.len() as u32;
 
     /* function names */
     {
-        for fn_index in first_own_fn_index..first_own_fn_index + own_fns_count {
-            let (fn_name, _) = ctx
-                .fn_defs
-                .iter()
-                .find(|(_, v)| v.get_absolute_index(ctx) == fn_index)
-                .unwrap();
+        for (fn_name, i) in ctx.fn_names.iter().zip(0..) {
+            let fn_def = &ctx.fn_defs[*fn_name]; // every own fn has a def
 
             wasm_module.debug_fn_info.push(WasmDebugFnInfo {
-                fn_index,
-                fn_name: fn_name.clone(),
+                fn_index: first_own_fn_index + i,
+                fn_name: String::from(fn_name.as_str()),
+                loc: fn_def.loc,
+                local_names: ctx.fn_local_names.remove(&i).unwrap_or_default(),
             })
         }
     }
@@ -231,8 +538,50 @@ fn parse_top_level_expr(
         return Ok(());
     }
 
+    let doc = tokens.get_doc_comment();
+
+    let TopLevelAttributes {
+        struct_layout,
+        allocator_role,
+        is_start,
+        derives,
+    } = parse_top_level_attributes(tokens)?;
+    if struct_layout.is_some() && !tokens.next_is(Symbol, "struct")? {
+        return Err(LoError {
+            message: String::from("Layout attributes are only supported on structs"),
+            loc: *tokens.loc(),
+        });
+    }
+    if !derives.is_empty() && !tokens.next_is(Symbol, "struct")? {
+        return Err(LoError {
+            message: String::from("Derive attributes are only supported on structs"),
+            loc: *tokens.loc(),
+        });
+    }
+    if allocator_role.is_some() && !tokens.next_is(Symbol, "fn")? {
+        return Err(LoError {
+            message: String::from("Allocator attributes are only supported on fns"),
+            loc: *tokens.loc(),
+        });
+    }
+    if is_start && !tokens.next_is(Symbol, "fn")? {
+        return Err(LoError {
+            message: String::from("Start attribute is only supported on fns"),
+            loc: *tokens.loc(),
+        });
+    }
+
     if let Some(_) = tokens.eat(Symbol, "fn")? {
-        return parse_fn_def(ctx, tokens, false);
+        parse_fn_def(ctx, tokens, false, doc)?;
+
+        if let Some(allocator_role) = allocator_role {
+            register_allocator_fn(ctx, allocator_role)?;
+        }
+        if is_start {
+            register_start_fn(ctx)?;
+        }
+
+        return Ok(());
     }
 
     if let Some(_) = tokens.eat(Symbol, "macro")? {
@@ -244,7 +593,7 @@ fn parse_top_level_expr(
         let Some(WasmType::I32) = offset.get_type(ctx).to_wasm_type() else {
             return Err(LoError {
                 message: format!("Invalid memory offset"),
-                loc: tokens.loc().clone(),
+                loc: *tokens.loc(),
             });
         };
 
@@ -254,6 +603,7 @@ fn parse_top_level_expr(
             let value = Lexer::unescape_string(&data.value);
             value.as_bytes().iter().map(|b| *b).collect()
         } else {
+            tokens.expect(Delim, "[")?;
             parse_const_sequence(ctx, tokens)?.1
         };
 
@@ -269,17 +619,71 @@ fn parse_top_level_expr(
     }
 
     if let Some(_) = tokens.eat(Symbol, "memory")? {
-        parse_memory(ctx, tokens)?;
+        parse_memory(ctx, tokens, false)?;
+        return Ok(());
+    }
+
+    if tokens.eat(Symbol, "table")?.is_some() {
+        return parse_table(ctx, tokens, false);
+    }
+
+    if tokens.eat(Symbol, "custom_section")?.is_some() {
+        let section_name = tokens.expect_any(StringLiteral)?.clone();
+        let name = Lexer::unescape_string(&section_name.value);
+        if name == "name" || name == "sourceMappingURL" {
+            return Err(LoError {
+                message: format!("Custom section `{name}` is reserved by the compiler"),
+                loc: section_name.loc,
+            });
+        }
+
+        tokens.expect(Operator, "=")?;
+
+        let bytes = if let Some(data) = tokens.eat_any(StringLiteral)? {
+            Lexer::unescape_string(&data.value).into_bytes()
+        } else if tokens.eat(Operator, "@")?.is_some() {
+            tokens.expect(Symbol, "embed")?;
+            let file_path = tokens.expect_any(StringLiteral)?.clone();
+            let path = resolve_path(
+                &Lexer::unescape_string(&file_path.value),
+                &file_path.loc.file_name(),
+            );
+            file_read(&path).map_err(|message| LoError {
+                message,
+                loc: file_path.loc,
+            })?
+        } else {
+            let loc = tokens.expect(Delim, "[")?.loc;
+            let (item_type, bytes) = parse_const_sequence(ctx, tokens)?;
+            if item_type != LoType::U8 {
+                return Err(LoError {
+                    message: String::from("Custom section data must be a sequence of u8"),
+                    loc,
+                });
+            }
+            bytes
+        };
+
+        ctx.wasm_module
+            .borrow_mut()
+            .custom_sections
+            .push(WasmCustomSection { name, bytes });
+
         return Ok(());
     }
 
     if let Some(_) = tokens.eat(Symbol, "export")? {
         if let Some(_) = tokens.eat(Symbol, "fn")? {
-            return parse_fn_def(ctx, tokens, true);
+            return parse_fn_def(ctx, tokens, true, doc);
+        }
+
+        if tokens.eat(Symbol, "table")?.is_some() {
+            return parse_table(ctx, tokens, true);
         }
 
-        if let Some(_) = tokens.eat(Symbol, "memory")? {
-            let (memory_index, _) = parse_memory(ctx, tokens)?;
+        if let Some(memory_token) = tokens.eat(Symbol, "memory")?.cloned() {
+            let (memory_index, _) = parse_memory(ctx, tokens, false)?;
+            register_export_name(ctx, "memory", &memory_token.loc)?;
 
             ctx.wasm_module.borrow_mut().exports.push(WasmExport {
                 export_type: WasmExportType::Mem,
@@ -293,7 +697,7 @@ fn parse_top_level_expr(
         if let Some(_) = tokens.eat(Symbol, "existing")? {
             tokens.expect(Symbol, "fn")?;
             let in_name = parse_nested_symbol(tokens)?;
-            if let None = ctx.fn_defs.get(&in_name.value) {
+            if ctx.get_fn_def(&in_name.value).is_none() {
                 return Err(LoError {
                     message: format!("Cannot export unknown function {}", in_name.value),
                     loc: in_name.loc,
@@ -301,11 +705,12 @@ fn parse_top_level_expr(
             }
 
             tokens.expect(Symbol, "as")?;
-            let out_name = tokens.expect_any(StringLiteral)?;
-            let out_name = Lexer::unescape_string(&out_name.value);
+            let out_name_token = tokens.expect_any(StringLiteral)?.clone();
+            let out_name = Lexer::unescape_string(&out_name_token.value);
+            register_export_name(ctx, &out_name, &out_name_token.loc)?;
 
             ctx.fn_exports.push(FnExport {
-                in_name: in_name.value,
+                in_name: String::from(in_name.value),
                 out_name,
             });
 
@@ -321,7 +726,7 @@ fn parse_top_level_expr(
         tokens.expect(Delim, "{")?;
         while let None = tokens.eat(Delim, "}")? {
             if let Some(_) = tokens.eat(Symbol, "memory")? {
-                let (_, limits) = parse_memory(ctx, tokens)?;
+                let (_, limits) = parse_memory(ctx, tokens, true)?;
                 tokens.expect(LoTokenType::Delim, ";")?;
 
                 ctx.wasm_module.borrow_mut().imports.push(WasmImport {
@@ -333,17 +738,59 @@ fn parse_top_level_expr(
                 continue;
             }
 
+            if tokens.eat(Symbol, "table")?.is_some() {
+                let table_name = tokens.expect_any(Symbol)?.clone();
+                check_new_table(ctx, &table_name)?;
+                tokens.expect(Operator, ":")?;
+                let table_type = tokens.expect_any(Symbol)?.clone();
+                if table_type.value != "funcref" {
+                    return Err(LoError {
+                        message: format!(
+                            "Unsupported table type: {}, expected funcref",
+                            table_type.value
+                        ),
+                        loc: table_type.loc,
+                    });
+                }
+                tokens.expect(LoTokenType::Delim, ";")?;
+
+                ctx.tables.push(TableDef {
+                    name: String::from(table_name.value),
+                    fn_names: vec![],
+                    exported: false,
+                    imported: true,
+                });
+                ctx.wasm_module.borrow_mut().imports.push(WasmImport {
+                    module_name: module_name.clone(),
+                    item_name: String::from(table_name.value),
+                    item_desc: WasmImportDesc::Table(WasmLimits {
+                        min: 0,
+                        max: None,
+                        shared: false,
+                    }),
+                });
+
+                continue;
+            }
+
+            let doc = tokens.get_doc_comment();
             tokens.expect(Symbol, "fn")?;
             let fn_decl = parse_fn_decl(ctx, tokens)?;
             tokens.expect(LoTokenType::Delim, ";")?;
 
-            if ctx.fn_defs.contains_key(&fn_decl.fn_name) {
+            if ctx.get_fn_def(&fn_decl.fn_name).is_some() {
                 return Err(LoError {
                     message: format!("Cannot redefine function: {}", fn_decl.fn_name),
                     loc: fn_decl.loc,
                 });
             }
 
+            if module_name == WASI_MODULE_NAME {
+                if let Some(message) = check_wasi_import(&fn_decl.method_name, &fn_decl.wasm_type) {
+                    ctx.warn(LoWarning::WasiAbi, fn_decl.loc, &message);
+                }
+            }
+
             let type_index = ctx.insert_fn_type(fn_decl.wasm_type);
 
             let fn_index = ctx.imported_fns_count;
@@ -355,9 +802,11 @@ fn parse_top_level_expr(
                 fn_params: fn_decl.fn_params,
                 type_index,
                 type_: fn_decl.lo_type,
+                sret: false,
+                doc,
                 loc: fn_decl.loc,
             };
-            ctx.fn_defs.insert(fn_decl.fn_name.clone(), fn_def);
+            ctx.fn_defs.insert(Atom::intern(&fn_decl.fn_name), fn_def);
             ctx.wasm_module.borrow_mut().imports.push(WasmImport {
                 module_name: module_name.clone(),
                 item_name: fn_decl.method_name,
@@ -373,7 +822,7 @@ fn parse_top_level_expr(
         let global_name = parse_nested_symbol(tokens)?;
         tokens.expect(Operator, "=")?;
 
-        let global_index = ctx.globals.len();
+        let global_index = ctx.wasm_module.borrow().globals.len();
 
         let global_value: LoInstr;
         if let Some(_) = tokens.eat(LoTokenType::Operator, "@")? {
@@ -391,11 +840,16 @@ fn parse_top_level_expr(
                 message: format!(
                     "Unsupported top level type: {lo_type}, only primitives are supported"
                 ),
-                loc: global_name.loc.clone(),
+                loc: global_name.loc,
             });
         };
 
-        if ctx.globals.contains_key(&global_name.value) {
+        if ctx.globals.get_by_name(&global_name.value).is_some()
+            || ctx
+                .data_layout_globals
+                .borrow()
+                .contains_key(global_name.value.as_str())
+        {
             return Err(LoError {
                 message: format!("Cannot redefine global: {}", global_name.value),
                 loc: global_name.loc,
@@ -416,7 +870,7 @@ fn parse_top_level_expr(
         }
 
         ctx.globals.insert(
-            global_name.value.clone(),
+            Atom::intern(&global_name.value),
             GlobalDef {
                 index: global_index as u32,
                 mutable,
@@ -442,95 +896,87 @@ fn parse_top_level_expr(
     if let Some(_) = tokens.eat(Symbol, "struct")? {
         let struct_name = parse_nested_symbol(tokens)?;
 
-        if let Some(_) = ctx.type_scope.get(&struct_name.value) {
-            return Err(LoError {
-                message: format!("Cannot redefine type {}", struct_name.value),
-                loc: struct_name.loc,
-            });
+        let layout = struct_layout.unwrap_or_default();
+        parse_struct_def(ctx, tokens, struct_name.clone(), layout, doc, false)?;
+
+        for derive in &derives {
+            derive_struct_fns(ctx, &struct_name.value, derive)?;
         }
 
-        // declare not fully defined struct to use in self-references
-        ctx.struct_defs.push(StructDef {
-            name: struct_name.value.clone(),
-            fields: vec![],
-            fully_defined: false,
-            loc: struct_name.loc,
-        });
+        return Ok(());
+    }
 
-        ctx.type_scope.insert(
-            struct_name.value.clone(),
-            LoType::StructInstance {
-                name: struct_name.value.clone(),
-            },
-        );
+    if tokens.eat(Symbol, "union")?.is_some() {
+        let union_name = parse_nested_symbol(tokens)?;
 
-        let mut field_index = 0;
-        let mut byte_offset = 0;
-        let mut struct_fields = Vec::<StructField>::new();
+        parse_struct_def(
+            ctx,
+            tokens,
+            union_name.clone(),
+            StructLayout::default(),
+            doc,
+            true,
+        )?;
+        ctx.union_types.insert(Atom::intern(&union_name.value));
 
-        tokens.expect(Delim, "{")?;
-        while let None = tokens.eat(Delim, "}")? {
-            let field_name = tokens.expect_any(Symbol)?.clone();
-            tokens.expect(Operator, ":")?;
-            let field_type_loc = tokens.loc().clone();
-            let field_type = parse_const_lo_type(ctx, tokens)?;
-            if !tokens.next_is(Delim, "}")? {
-                tokens.expect(Delim, ",")?;
-            }
+        return Ok(());
+    }
 
-            if struct_fields
-                .iter()
-                .find(|f| f.name == field_name.value)
-                .is_some()
-            {
-                return Err(LoError {
-                    message: format!(
-                        "Found duplicate struct field name: '{}' of struct {}",
-                        field_name.value, struct_name.value,
-                    ),
-                    loc: field_name.loc,
-                });
-            }
+    // `flags <name> { ... }` is a struct of `bits: u32` with members defined like `const flags`
+    if tokens.eat(Symbol, "flags")?.is_some() {
+        let flags_name = parse_nested_symbol(tokens)?;
 
-            let mut stats = EmitComponentStats::default();
-            field_type
-                .emit_sized_component_stats(ctx, &mut stats, &mut vec![])
-                .map_err(|err| LoError {
-                    message: err,
-                    loc: field_type_loc,
-                })?;
-
-            struct_fields.push(StructField {
-                name: field_name.value,
-                value_type: field_type,
-                field_index,
-                byte_offset,
-                loc: field_name.loc,
+        if ctx.type_scope.get(&flags_name.value).is_some() {
+            return Err(LoError {
+                message: format!("Cannot redefine type {}", flags_name.value),
+                loc: flags_name.loc,
             });
-
-            field_index += stats.count;
-            byte_offset += stats.byte_length;
         }
 
-        let struct_def = ctx.get_struct_def_mut(&struct_name.value).unwrap();
-        struct_def.fields.append(&mut struct_fields);
-        struct_def.fully_defined = true;
+        ctx.insert_struct_def(StructDef {
+            name: String::from(flags_name.value),
+            fields: vec![StructField {
+                name: String::from("bits"),
+                is_private: false,
+                value_type: LoType::U32,
+                field_index: 0,
+                byte_offset: 0,
+                loc: flags_name.loc,
+            }],
+            layout: StructLayout::default(),
+            byte_length: 4,
+            fully_defined: true,
+            doc,
+            loc: flags_name.loc,
+        });
+
+        let flags_type = LoType::StructInstance {
+            name: Atom::intern(&flags_name.value),
+        };
+        ctx.type_scope.insert(flags_name.value, flags_type.clone());
+        ctx.flags_types.insert(Atom::intern(&flags_name.value));
 
-        return Ok(());
+        return parse_const_group(ctx, tokens, flags_name, true, Some(flags_type));
     }
 
     if let Some(_) = tokens.eat(Symbol, "type")?.cloned() {
         let type_alias = parse_nested_symbol(tokens)?;
         tokens.expect(Operator, "=")?;
+        let is_distinct = tokens.eat(Symbol, "distinct")?.is_some();
+        let actual_type_loc = *tokens.loc();
         let actual_type = parse_const_lo_type(ctx, tokens)?;
 
         if let Some(_) = ctx.type_scope.get(&type_alias.value) {
             return Err(LoError {
                 message: format!("Cannot redefine type: {}", type_alias.value),
-                loc: type_alias.loc.clone(),
+                loc: type_alias.loc,
             });
         }
 
+        if is_distinct {
+            return define_distinct_type(ctx, type_alias, actual_type, actual_type_loc, doc);
+        }
+
         ctx.type_scope.insert(type_alias.value, actual_type);
 
         return Ok(());
@@ -538,39 +984,18 @@ fn parse_top_level_expr(
 
     if let Some(_) = tokens.eat(Symbol, "const")?.cloned() {
         let const_name = parse_nested_symbol(tokens)?;
-        tokens.expect(Operator, "=")?;
-        let const_value = parse_const_expr(ctx, tokens, 0)?;
-
-        if ctx.constants.borrow().contains_key(&const_name.value) {
-            return Err(LoError {
-                message: format!("Duplicate constant: {}", const_name.value),
-                loc: const_name.loc.clone(),
-            });
-        }
 
-        if ctx.mode == CompilerMode::Inspect {
-            let source_index = ctx.get_loc_module_index(&const_name.loc);
-            let source_range = RangeDisplay(&const_name.loc);
-
-            let const_name = &const_name.value;
-            let const_type = const_value.get_type(ctx);
-
-            stdout_writeln(format!(
-                "{{ \"type\": \"info\", \
-                    \"hover\": \"const {const_name}: {const_type}\", \
-                    \"loc\": \"{source_index}/{source_range}\" }}, ",
-            ));
+        let is_group = const_name.value == "group" || const_name.value == "flags";
+        if is_group && tokens.next_is_any(Symbol)? {
+            let group_name = parse_nested_symbol(tokens)?;
+            let is_flags = const_name.value == "flags";
+            return parse_const_group(ctx, tokens, group_name, is_flags, None);
         }
 
-        ctx.constants.borrow_mut().insert(
-            const_name.value,
-            ConstDef {
-                value: const_value,
-                loc: const_name.loc,
-            },
-        );
+        tokens.expect(Operator, "=")?;
+        let const_value = parse_const_expr(ctx, tokens, 0)?;
 
-        return Ok(());
+        return define_const(ctx, const_name, const_value, doc);
     }
 
     if let Some(_) = tokens.eat(Symbol, "include")?.cloned() {
@@ -598,28 +1023,142 @@ fn parse_top_level_expr(
     let unexpected = tokens.peek().unwrap();
     return Err(LoError {
         message: format!("Unexpected top level token: {}", unexpected.value),
-        loc: unexpected.loc.clone(),
+        loc: unexpected.loc,
+    });
+}
+
+// `table <name> = [<fn>, ...]`, fns are referenced by index so they must be known already
+fn parse_table(
+    ctx: &mut ModuleContext,
+    tokens: &mut LoTokenStream,
+    exported: bool,
+) -> Result<(), LoError> {
+    let table_name = tokens.expect_any(Symbol)?.clone();
+    check_new_table(ctx, &table_name)?;
+    if exported {
+        register_export_name(ctx, &table_name.value, &table_name.loc)?;
+    }
+
+    tokens.expect(Operator, "=")?;
+
+    let mut fn_names = vec![];
+    tokens.expect(Delim, "[")?;
+    while tokens.eat(Delim, "]")?.is_none() {
+        let fn_name = parse_nested_symbol(tokens)?;
+        if ctx.get_fn_def(&fn_name.value).is_none() {
+            return Err(LoError {
+                message: format!(
+                    "Cannot put unknown function {} into table {}",
+                    fn_name.value, table_name.value
+                ),
+                loc: fn_name.loc,
+            });
+        }
+        fn_names.push(String::from(fn_name.value));
+
+        if !tokens.next_is(Delim, "]")? {
+            tokens.expect(Delim, ",")?;
+        }
+    }
+
+    ctx.tables.push(TableDef {
+        name: String::from(table_name.value),
+        fn_names,
+        exported,
+        imported: false,
     });
+
+    Ok(())
+}
+
+// names are valid UTF-8 already, string literals have no byte escapes
+fn register_export_name(
+    ctx: &mut ModuleContext,
+    export_name: &str,
+    loc: &LoLocation,
+) -> Result<(), LoError> {
+    if export_name.is_empty() {
+        return Err(LoError {
+            message: String::from("Export name cannot be empty"),
+            loc: *loc,
+        });
+    }
+
+    if let Some(prev_loc) = ctx.export_names.get(export_name) {
+        return Err(LoError {
+            message: format!(
+                "Duplicate export name: {export_name}, already exported at {prev_loc}"
+            ),
+            loc: *loc,
+        });
+    }
+
+    ctx.export_names.insert(String::from(export_name), *loc);
+    Ok(())
+}
+
+fn check_new_table(ctx: &ModuleContext, table_name: &LoToken) -> Result<(), LoError> {
+    if ctx.tables.iter().any(|t| t.name == table_name.value) {
+        return Err(LoError {
+            message: format!("Duplicate table definition: {}", table_name.value),
+            loc: table_name.loc,
+        });
+    }
+    if !ctx.tables.is_empty() {
+        ctx.features
+            .require(WasmFeature::ReferenceTypes, "Multiple tables")
+            .map_err(|message| LoError {
+                message,
+                loc: table_name.loc,
+            })?;
+    }
+
+    Ok(())
 }
 
 fn parse_memory(
     ctx: &mut ModuleContext,
     tokens: &mut LoTokenStream,
+    imported: bool,
 ) -> Result<(u32, WasmLimits), LoError> {
     let memory_name = String::from("memory");
     if ctx.memories.contains_key(&memory_name) {
         return Err(LoError {
             message: format!("Duplicate memory definition: {memory_name}"),
-            loc: tokens.loc().clone(),
+            loc: *tokens.loc(),
         });
     }
 
-    let mut memory_limits = WasmLimits { min: 0, max: None };
+    let memory_loc = *tokens.loc();
+    let mut memory_limits = WasmLimits {
+        min: 0,
+        max: None,
+        shared: false,
+    };
 
+    let mut seen_props = Vec::<Atom>::new();
     tokens.expect(Delim, "{")?;
     while let None = tokens.eat(Delim, "}")? {
         let prop = tokens.expect_any(Symbol)?.clone();
-        tokens.expect(Operator, ":")?;
+        if !MEMORY_PROPS.contains(&prop.value.as_str()) {
+            return Err(LoError {
+                message: unknown_memory_prop_message(&prop.value),
+                loc: prop.loc,
+            });
+        }
+        if seen_props.contains(&prop.value) {
+            return Err(LoError {
+                message: format!("Duplicate memory property: {}", prop.value),
+                loc: prop.loc,
+            });
+        }
+        seen_props.push(prop.value);
+
+        // bare `shared` is the same as `shared: true`
+        let is_bare_flag = prop.value == "shared" && !tokens.next_is(Operator, ":")?;
+        if !is_bare_flag {
+            tokens.expect(Operator, ":")?;
+        }
 
         match prop.value.as_str() {
             "min_pages" => {
@@ -632,13 +1171,31 @@ fn parse_memory(
             }
             "data_start" => {
                 let value = parse_u32_literal(tokens.expect_any(IntLiteral)?)?;
-                *ctx.data_size.borrow_mut() = value;
+                *ctx.data_size.borrow_mut() = ctx.data_start_override.unwrap_or(value);
             }
             _ => {
-                return Err(LoError {
-                    message: format!("Invalid memory property"),
-                    loc: prop.loc,
-                });
+                memory_limits.shared = is_bare_flag || {
+                    let value = tokens.expect_any(Symbol)?.clone();
+                    match value.value.as_str() {
+                        "true" => true,
+                        "false" => false,
+                        _ => {
+                            return Err(LoError {
+                                message: format!("Expected true or false, got: {}", value.value),
+                                loc: value.loc,
+                            });
+                        }
+                    }
+                };
+
+                if memory_limits.shared {
+                    ctx.features
+                        .require(WasmFeature::Threads, "Shared memory")
+                        .map_err(|message| LoError {
+                            message,
+                            loc: prop.loc,
+                        })?;
+                }
             }
         }
 
@@ -647,25 +1204,99 @@ fn parse_memory(
         }
     }
 
+    if memory_limits.min > MAX_PAGES {
+        return Err(LoError {
+            message: format!("Memory min_pages is larger than {MAX_PAGES}"),
+            loc: memory_loc,
+        });
+    }
+    if let Some(max) = memory_limits.max {
+        if max > MAX_PAGES {
+            return Err(LoError {
+                message: format!("Memory max_pages is larger than {MAX_PAGES}"),
+                loc: memory_loc,
+            });
+        }
+        if max < memory_limits.min {
+            return Err(LoError {
+                message: format!(
+                    "Memory min_pages ({}) is larger than max_pages ({max})",
+                    memory_limits.min
+                ),
+                loc: memory_loc,
+            });
+        }
+    }
+
+    if memory_limits.shared && memory_limits.max.is_none() {
+        return Err(LoError {
+            message: String::from("Shared memory must have max_pages"),
+            loc: memory_loc,
+        });
+    }
+
     let memory_index = ctx.wasm_module.borrow().memories.len() as u32;
-    ctx.wasm_module
-        .borrow_mut()
-        .memories
-        .push(memory_limits.clone());
+    // imported memory only goes to the import section
+    if !imported {
+        ctx.wasm_module
+            .borrow_mut()
+            .memories
+            .push(memory_limits.clone());
+    }
     ctx.memories.insert(memory_name.clone(), memory_index);
 
     Ok((memory_index, memory_limits))
 }
 
+pub const MEMORY_PROPS: [&str; 4] = ["min_pages", "max_pages", "data_start", "shared"];
+
+pub fn unknown_memory_prop_message(prop_name: &str) -> String {
+    let closest = MEMORY_PROPS
+        .iter()
+        .map(|known_prop| (edit_distance(prop_name, known_prop), known_prop))
+        .min_by_key(|(distance, _)| *distance);
+
+    match closest {
+        Some((distance, known_prop)) if distance <= 2 => {
+            format!("Unknown memory property: {prop_name}, did you mean {known_prop}?")
+        }
+        _ => format!(
+            "Unknown memory property: {prop_name}, expected one of: {}",
+            MEMORY_PROPS.join(", ")
+        ),
+    }
+}
+
 fn parse_fn_def(
     ctx: &mut ModuleContext,
     tokens: &mut LoTokenStream,
     exported: bool,
+    doc: Option<String>,
 ) -> Result<(), LoError> {
-    let fn_decl = parse_fn_decl(ctx, tokens)?;
+    let mut fn_decl = parse_fn_decl(ctx, tokens)?;
     let body = collect_block_tokens(tokens)?;
 
-    if ctx.fn_defs.contains_key(&fn_decl.fn_name) {
+    let sret = ctx.uses_sret(&fn_decl.lo_type.output);
+    if sret {
+        for local in fn_decl.locals.values_mut() {
+            local.index += 1;
+        }
+        fn_decl.wasm_type.inputs.insert(0, WasmType::I32);
+        fn_decl.wasm_type.outputs.clear();
+
+        let map_err = |message| LoError {
+            message,
+            loc: fn_decl.loc,
+        };
+        let output_size = fn_decl
+            .lo_type
+            .output
+            .sized_comp_stats(ctx)
+            .map_err(map_err)?;
+        ctx.sret_buffer_size = ctx.sret_buffer_size.max(output_size.byte_length);
+    }
+
+    if ctx.get_fn_def(&fn_decl.fn_name).is_some() {
         return Err(LoError {
             message: format!("Cannot redefine function: {}", fn_decl.fn_name),
             loc: fn_decl.loc,
@@ -673,9 +1304,12 @@ fn parse_fn_def(
     }
 
     if exported {
+        let out_name = ctx.get_export_name(&fn_decl.fn_name);
+        register_export_name(ctx, &out_name, &fn_decl.loc)?;
+
         ctx.fn_exports.push(FnExport {
             in_name: fn_decl.fn_name.clone(),
-            out_name: fn_decl.fn_name.clone(),
+            out_name,
         });
     }
 
@@ -684,15 +1318,19 @@ fn parse_fn_def(
     ctx.wasm_module.borrow_mut().functions.push(type_index);
 
     let fn_index = ctx.wasm_module.borrow_mut().functions.len() as u32 - 1;
+    let fn_name = Atom::intern(&fn_decl.fn_name);
+    ctx.fn_names.push(fn_name);
 
     ctx.fn_defs.insert(
-        fn_decl.fn_name,
+        fn_name,
         FnDef {
             local: true,
             fn_index,
             fn_params: fn_decl.fn_params,
             type_index,
             type_: fn_decl.lo_type,
+            sret,
+            doc,
             loc: fn_decl.loc,
         },
     );
@@ -712,7 +1350,7 @@ fn parse_macro_def(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Resul
     let macro_name = parse_nested_symbol(tokens)?;
     tokens.expect(Operator, "!")?;
 
-    if ctx.macros.contains_key(&macro_name.value) {
+    if ctx.macros.contains_key(macro_name.value.as_str()) {
         return Err(LoError {
             message: format!("Cannot redefine macro: {}", macro_name.value),
             loc: macro_name.loc,
@@ -729,10 +1367,10 @@ fn parse_macro_def(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Resul
                 tokens.expect(Delim, ",")?;
             }
 
-            if get_type_by_name(ctx, &ctx.type_scope, &p_name, false).is_ok() {
+            if get_type_by_name(ctx, &ctx.type_scope, &p_name).is_ok() {
                 return Err(LoError {
                     message: format!("Type parameter shadows existing type: {}", p_name.value),
-                    loc: p_name.loc.clone(),
+                    loc: p_name.loc,
                 });
             }
 
@@ -740,12 +1378,12 @@ fn parse_macro_def(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Resul
                 if *param == p_name.value {
                     return Err(LoError {
                         message: format!("Found duplicate type parameter: {}", p_name.value),
-                        loc: p_name.loc.clone(),
+                        loc: p_name.loc,
                     });
                 }
             }
 
-            type_params.push(p_name.value);
+            type_params.push(String::from(p_name.value));
         }
     }
 
@@ -755,7 +1393,7 @@ fn parse_macro_def(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Resul
     };
     for type_param in &type_params {
         new_type_scope.insert(
-            type_param.clone(),
+            type_param,
             LoType::MacroTypeArg {
                 name: type_param.clone(),
             },
@@ -771,7 +1409,7 @@ fn parse_macro_def(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Resul
     let body = collect_block_tokens(tokens)?;
 
     ctx.macros.insert(
-        macro_name.value.clone(),
+        String::from(macro_name.value),
         MacroDef {
             receiver_type,
             method_name,
@@ -793,7 +1431,7 @@ struct FnDecl {
     fn_params: Vec<FnParam>,
     lo_type: LoFnType,
     wasm_type: WasmFnType,
-    locals: BTreeMap<String, LocalDef>,
+    locals: AtomMap<LocalDef>,
 }
 
 fn parse_fn_decl(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Result<FnDecl, LoError> {
@@ -803,10 +1441,10 @@ fn parse_fn_decl(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Result<
     let params = parse_fn_params(ctx, &ctx.type_scope, tokens, &receiver_type)?;
 
     let mut fn_decl = FnDecl {
-        fn_name: fn_name.value.clone(),
+        fn_name: String::from(fn_name.value),
         fn_params: params.clone(),
         method_name,
-        loc: fn_name.loc.clone(),
+        loc: fn_name.loc,
         lo_type: LoFnType {
             inputs: vec![],
             output: LoType::Void,
@@ -815,7 +1453,7 @@ fn parse_fn_decl(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Result<
             inputs: vec![],
             outputs: vec![],
         },
-        locals: BTreeMap::new(),
+        locals: AtomMap::new(),
     };
 
     for param in params {
@@ -823,8 +1461,9 @@ fn parse_fn_decl(ctx: &mut ModuleContext, tokens: &mut LoTokenStream) -> Result<
             index: fn_decl.wasm_type.inputs.len() as u32,
             value_type: param.type_.clone(),
             loc: param.loc,
+            stack_offset: None,
         };
-        fn_decl.locals.insert(param.name, local_def);
+        fn_decl.locals.insert(Atom::intern(&param.name), local_def);
 
         param
             .type_
@@ -857,6 +1496,13 @@ fn parse_fn_params(
 
     if let Some(receiver_type) = &receiver_type {
         if let Some(self_token) = tokens.eat(Symbol, RECEIVER_PARAM_NAME)?.cloned() {
+            if ctx.is_union_type(receiver_type) {
+                return Err(LoError {
+                    message: format!("Union {receiver_type} can only be used through a pointer"),
+                    loc: self_token.loc,
+                });
+            }
+
             if !tokens.next_is(Delim, ")")? {
                 tokens.expect(Delim, ",")?;
             }
@@ -867,6 +1513,7 @@ fn parse_fn_params(
                 loc: self_token.loc,
             });
         } else if let Some(_) = tokens.eat(Operator, "&")? {
+            let is_readonly = tokens.eat(Symbol, "readonly")?.is_some();
             let self_token = tokens.expect(Symbol, RECEIVER_PARAM_NAME)?.clone();
             if !tokens.next_is(Delim, ")")? {
                 tokens.expect(Delim, ",")?;
@@ -874,7 +1521,10 @@ fn parse_fn_params(
 
             params.push(FnParam {
                 name: String::from(RECEIVER_PARAM_NAME),
-                type_: LoType::Pointer(Box::new(receiver_type.clone())),
+                type_: LoType::Pointer {
+                    pointee: Box::new(receiver_type.clone()),
+                    is_readonly,
+                },
                 loc: self_token.loc,
             });
         };
@@ -895,13 +1545,13 @@ fn parse_fn_params(
                         "Found function param with conflicting name: {}",
                         p_name.value
                     ),
-                    loc: p_name.loc.clone(),
+                    loc: p_name.loc,
                 });
             }
         }
 
         params.push(FnParam {
-            name: p_name.value,
+            name: String::from(p_name.value),
             type_: p_type,
             loc: p_name.loc,
         });
@@ -913,10 +1563,9 @@ fn parse_fn_params(
 fn parse_block(
     ctx: &mut BlockContext,
     tokens: &mut LoTokenStream,
-) -> Result<Vec<LoInstr>, LoError> {
+) -> Result<BlockContents, LoError> {
     let mut block_tokens = collect_block_tokens(tokens)?;
-    let contents = parse_block_contents(ctx, &mut block_tokens, LoType::Void)?;
-    Ok(contents.exprs)
+    parse_block_contents(ctx, &mut block_tokens, LoType::Void)
 }
 
 fn collect_block_tokens(tokens: &mut LoTokenStream) -> Result<LoTokenStream, LoError> {
@@ -945,11 +1594,15 @@ fn collect_block_tokens(tokens: &mut LoTokenStream) -> Result<LoTokenStream, LoE
     Ok(output)
 }
 
+// each nested expr is a level of recursion here and in every later pass over the IR
 fn parse_expr(
     ctx: &mut BlockContext,
     tokens: &mut LoTokenStream,
     min_bp: u32,
 ) -> Result<LoInstr, LoError> {
+    let outer_depth = ctx.fn_ctx.expr_depth;
+
+    enter_nested_expr(ctx, tokens)?;
     let mut primary = parse_primary(ctx, tokens)?;
 
     while tokens.peek().is_some() {
@@ -966,9 +1619,26 @@ fn parse_expr(
         primary = parse_postfix(ctx, tokens, primary, op)?;
     }
 
+    ctx.fn_ctx.expr_depth = outer_depth;
+
     Ok(primary)
 }
 
+fn enter_nested_expr(ctx: &mut BlockContext, tokens: &LoTokenStream) -> Result<(), LoError> {
+    if ctx.fn_ctx.expr_depth >= MAX_EXPR_NESTING_DEPTH {
+        return Err(LoError {
+            message: format!(
+                "Expression is too deeply nested, \
+                    nesting depth limit ({MAX_EXPR_NESTING_DEPTH}) exceeded"
+            ),
+            loc: tokens.peek().unwrap_or(&tokens.terminal_token).loc,
+        });
+    }
+
+    ctx.fn_ctx.expr_depth += 1;
+    Ok(())
+}
+
 fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<LoInstr, LoError> {
     if tokens.next_is_any(IntLiteral)? {
         return parse_const_int(tokens);
@@ -988,10 +1658,14 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
 
     if let Some(_) = tokens.eat(Delim, "[")? {
         let (item_type, bytes) = parse_const_sequence(ctx.module, tokens)?;
-        let bytes_ptr = ctx.module.append_data(bytes);
+        let align = item_type.get_alignment(ctx.module).unwrap(); // sequence types are sized
+        let bytes_ptr = ctx.module.append_data(bytes, align);
 
         return Ok(
-            LoInstr::U32Const { value: bytes_ptr }.casted(LoType::Pointer(Box::new(item_type)))
+            LoInstr::U32Const { value: bytes_ptr }.casted(LoType::Pointer {
+                pointee: Box::new(item_type),
+                is_readonly: false,
+            }),
         );
     }
 
@@ -1014,16 +1688,17 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
     }
 
     if let Some(return_token) = tokens.eat(Symbol, "return")?.cloned() {
-        let mut value = if tokens.next_is(Delim, ";")? {
+        let value = if tokens.next_is(Delim, ";")? {
             LoInstr::NoInstr
         } else {
             parse_expr(ctx, tokens, 0)?
         };
 
-        let return_type = value.get_type(ctx.module);
         let expected_return_type = &ctx.fn_ctx.lo_fn_type.output;
+        let value = coerce_int_literal(value, expected_return_type, &return_token.loc)?;
+        let return_type = value.get_type(ctx.module);
 
-        if return_type != *expected_return_type {
+        if !return_type.is_assignable_to(expected_return_type) {
             return Err(LoError {
                 message: format!(
                     "Invalid return type, \
@@ -1033,34 +1708,38 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             });
         }
 
-        if let Some(mut values) = get_deferred(ctx) {
-            values.insert(0, value);
-            value = LoInstr::MultiValueEmit { values }.casted(LoType::Void);
-        }
+        return compile_return(ctx, value, &return_token.loc);
+    }
 
-        return Ok(LoInstr::Return {
-            value: Box::new(value),
-        });
+    if tokens.eat(Operator, "@")?.is_some() {
+        return parse_reflection_builtin(ctx, tokens);
     }
 
     if let Some(t) = tokens.eat(Symbol, "sizeof")?.cloned() {
-        let value_type = parse_lo_type(ctx, tokens)?;
+        // no value is created, so unions can be used directly
+        let value_type = parse_referenced_lo_type(ctx, tokens)?;
 
         return Ok(LoInstr::U32Const {
             value: value_type
                 .sized_comp_stats(&ctx.module)
                 .map_err(|err| LoError {
                     message: err,
-                    loc: t.loc.clone(),
+                    loc: t.loc,
                 })?
                 .byte_length as u32,
         });
     }
 
     if let Some(_) = tokens.eat(Symbol, "defer")? {
+        if tokens.eat(Symbol, "with")?.is_some() {
+            return parse_defer_with_captures(ctx, tokens);
+        }
+
+        // locals are read when deferred exprs run, at fn exit
+        let expr_loc = tokens.peek().unwrap_or(&tokens.terminal_token).loc;
         let deffered_expr = parse_expr(ctx, tokens, 0)?;
 
-        ctx.fn_ctx.defers.push(deffered_expr);
+        push_deferred(ctx, deffered_expr, expr_loc)?;
 
         return Ok(LoInstr::NoInstr);
     }
@@ -1095,6 +1774,45 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         });
     }
 
+    if let Some(instr) = parse_atomic_intrinsic(ctx, tokens)? {
+        return Ok(instr);
+    }
+
+    if let Some(instr) = parse_alloc_intrinsic(ctx, tokens)? {
+        return Ok(instr);
+    }
+
+    if let Some(t) = tokens.eat(Symbol, "__externref_null")?.cloned() {
+        tokens.expect(Delim, "(")?;
+        tokens.expect(Delim, ")")?;
+        require_reference_types(ctx.module, &t)?;
+        return Ok(LoInstr::ExternRefNull);
+    }
+
+    if let Some(t) = tokens.eat(Symbol, "__externref_is_null")?.cloned() {
+        tokens.expect(Delim, "(")?;
+        let value = parse_expr(ctx, tokens, 0)?;
+        tokens.eat(Delim, ",")?; // optional
+        tokens.expect(Delim, ")")?;
+        require_reference_types(ctx.module, &t)?;
+
+        let value_type = value.get_type(ctx.module);
+        if value_type != LoType::ExternRef {
+            return Err(LoError {
+                message: format!(
+                    "Invalid arguments for {}, got [{value_type}], expected [{}]",
+                    t.value,
+                    LoType::ExternRef
+                ),
+                loc: t.loc,
+            });
+        }
+
+        return Ok(LoInstr::ExternRefIsNull {
+            value: Box::new(value),
+        });
+    }
+
     if let Some(t) = tokens.eat(Symbol, "__memory_copy")?.cloned() {
         tokens.expect(Delim, "(")?;
 
@@ -1112,6 +1830,14 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
 
         tokens.expect(Delim, ")")?;
 
+        ctx.module
+            .features
+            .require(WasmFeature::BulkMemory, &t.value)
+            .map_err(|message| LoError {
+                message,
+                loc: t.loc,
+            })?;
+
         if destination_type != LoType::U32
             || source_type != LoType::U32
             || num_bytes_type != LoType::U32
@@ -1134,8 +1860,34 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         });
     }
 
+    if let Some(t) = tokens.eat(Symbol, "__bitcast")?.cloned() {
+        tokens.expect(Operator, "<")?;
+        let from_type = parse_lo_type(ctx, tokens)?;
+        tokens.expect(Delim, ",")?;
+        let to_type = parse_lo_type(ctx, tokens)?;
+        tokens.expect(Operator, ">")?;
+
+        tokens.expect(Delim, "(")?;
+        let value = parse_expr(ctx, tokens, 0)?;
+        tokens.eat(Delim, ",")?; // optional
+        tokens.expect(Delim, ")")?;
+
+        let value_type = value.get_type(ctx.module);
+        if value_type != from_type {
+            return Err(LoError {
+                message: format!(
+                    "Invalid arguments for {}, got [{value_type}], expected [{from_type}]",
+                    t.value,
+                ),
+                loc: t.loc,
+            });
+        }
+
+        return build_bitcast(ctx.module, value, to_type, &t.loc);
+    }
+
     if let Some(t) = tokens.eat(Symbol, "__debug_typeof")?.cloned() {
-        let loc = tokens.peek().unwrap_or(&t).loc.clone();
+        let loc = tokens.peek().unwrap_or(&t).loc;
 
         let expr = parse_expr(ctx, tokens, 0)?;
         let expr_type = expr.get_type(ctx.module);
@@ -1156,9 +1908,21 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         return parse_const_str(ctx.module, tokens, debug_mesage);
     }
 
-    if let Some(_) = tokens.eat(Symbol, "if")? {
+    if let Some(if_token) = tokens.eat(Symbol, "if")?.cloned() {
+        let cond_loc = tokens.peek().unwrap_or(&if_token).loc;
         let cond = parse_expr(ctx, tokens, 0)?;
 
+        let cond_type = cond.get_type(ctx.module);
+        if let LoType::Pointer { .. } = cond_type {
+            return Err(LoError {
+                message: format!(
+                    "Pointer `{cond_type}` cannot be used as a condition, \
+                    cast it to `u32` to check the address"
+                ),
+                loc: cond_loc,
+            });
+        }
+
         let then_branch = parse_block(
             &mut BlockContext {
                 module: ctx.module,
@@ -1169,6 +1933,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         )?;
 
         let mut else_branch = None;
+        let mut else_has_never = false;
         if let Some(_) = tokens.eat(Symbol, "else")? {
             let else_ctx = &mut BlockContext {
                 module: ctx.module,
@@ -1176,18 +1941,29 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
                 block: Block::child_of(ctx.module, &ctx.block),
             };
             if tokens.next_is(Symbol, "if")? {
-                else_branch = Some(vec![parse_expr(else_ctx, tokens, 0)?]);
+                let else_if = parse_expr(else_ctx, tokens, 0)?;
+                else_has_never = else_if.get_type(else_ctx.module) == LoType::Never;
+                else_branch = Some(vec![else_if]);
             } else {
-                else_branch = Some(parse_block(else_ctx, tokens)?)
+                let else_contents = parse_block(else_ctx, tokens)?;
+                else_has_never = else_contents.has_never;
+                else_branch = Some(else_contents.exprs);
             }
         }
 
-        return Ok(LoInstr::If {
+        let if_instr = LoInstr::If {
             block_type: LoBlockType::void(),
             cond: Box::new(cond),
-            then_branch,
+            then_branch: then_branch.exprs,
             else_branch,
-        });
+        };
+
+        // code after the `if` is unreachable when none of the branches terminate
+        if then_branch.has_never && else_has_never {
+            return Ok(if_instr.casted(LoType::Never));
+        }
+
+        return Ok(if_instr);
     }
 
     if let Some(_) = tokens.eat(Symbol, "loop")? {
@@ -1197,18 +1973,27 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             block: Block::child_of(ctx.module, &ctx.block).of_kind(LoBlockKind::Loop),
         };
 
-        let mut body = parse_block(&mut ctx, tokens)?;
+        ctx.fn_ctx.loop_breaks.push(false);
+        let mut body = parse_block(&mut ctx, tokens)?.exprs;
+        let has_break = ctx.fn_ctx.loop_breaks.pop().unwrap();
 
         let implicit_continue = LoInstr::Branch { label_index: 0 };
         body.push(implicit_continue);
 
-        return Ok(LoInstr::Block {
+        let loop_instr = LoInstr::Block {
             block_type: LoBlockType::void(),
             body: vec![LoInstr::Loop {
                 block_type: LoBlockType::void(),
                 body,
             }],
-        });
+        };
+
+        // a loop can only be exited with `break` (`return` leaves the whole fn)
+        if !has_break {
+            return Ok(loop_instr.casted(LoType::Never));
+        }
+
+        return Ok(loop_instr);
     }
 
     if let Some(for_loop) = tokens.eat(Symbol, "for")?.cloned() {
@@ -1224,6 +2009,11 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         tokens.expect(Operator, "..")?;
         let end_count = parse_expr(counter_ctx, tokens, 0)?;
 
+        let end_type = end_count.get_type(counter_ctx.module);
+        let start_count = coerce_int_literal(start_count, &end_type, &for_loop.loc)?;
+        let start_type = start_count.get_type(counter_ctx.module);
+        let end_count = coerce_int_literal(end_count, &start_type, &for_loop.loc)?;
+
         let counter_type = start_count.get_type(counter_ctx.module);
         if end_count.get_type(counter_ctx.module) != counter_type {
             return Err(LoError {
@@ -1296,7 +2086,9 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             fn_ctx: counter_ctx.fn_ctx,
             block: Block::child_of(ctx.module, &counter_ctx.block).of_kind(LoBlockKind::ForLoop),
         };
-        let loop_body = parse_block(loop_body_ctx, tokens)?;
+        loop_body_ctx.fn_ctx.loop_breaks.push(false);
+        let loop_body = parse_block(loop_body_ctx, tokens)?.exprs;
+        loop_body_ctx.fn_ctx.loop_breaks.pop();
 
         let instrs = vec![
             init_instr,
@@ -1328,7 +2120,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             if current_block.block_kind == LoBlockKind::Function {
                 return Err(LoError {
                     message: format!("Cannot break outside of a loop"),
-                    loc: break_token.loc.clone(),
+                    loc: break_token.loc,
                 });
             }
 
@@ -1341,11 +2133,17 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
                 break;
             }
 
+            if current_block.block_kind != LoBlockKind::Macro {
+                label_index += 1;
+            }
             current_block = current_block.parent.unwrap();
-            label_index += 1;
         }
 
-        return Ok(LoInstr::Branch { label_index });
+        if let Some(has_break) = ctx.fn_ctx.loop_breaks.last_mut() {
+            *has_break = true;
+        }
+
+        return Ok(LoInstr::Branch { label_index }.casted(LoType::Never));
     }
 
     if let Some(continue_token) = tokens.eat(Symbol, "continue")? {
@@ -1356,7 +2154,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             if current_block.block_kind == LoBlockKind::Function {
                 return Err(LoError {
                     message: format!("Cannot continue outside of a loop"),
-                    loc: continue_token.loc.clone(),
+                    loc: continue_token.loc,
                 });
             }
 
@@ -1368,11 +2166,13 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
                 break;
             }
 
+            if current_block.block_kind != LoBlockKind::Macro {
+                label_index += 1;
+            }
             current_block = current_block.parent.unwrap();
-            label_index += 1;
         }
 
-        return Ok(LoInstr::Branch { label_index });
+        return Ok(LoInstr::Branch { label_index }.casted(LoType::Never));
     }
 
     if let Some(_) = tokens.eat(Symbol, "let")?.cloned() {
@@ -1390,13 +2190,22 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             });
         }
 
-        if let Some(_) = ctx.module.globals.get(&local_name.value) {
+        if ctx.module.globals.get_by_name(&local_name.value).is_some() {
             return Err(LoError {
                 message: format!("Local name collides with global: {}", local_name.value),
-                loc: local_name.loc.clone(),
+                loc: local_name.loc,
             });
         };
 
+        if ctx
+            .fn_ctx
+            .address_taken_locals
+            .iter()
+            .any(|l| *l == local_name.value)
+        {
+            return define_stack_local(ctx, &local_name, value, value_type);
+        }
+
         return define_local(ctx, &local_name, value, value_type);
     }
 
@@ -1413,7 +2222,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             let LoType::Result { ok_type, err_type } = &ctx.fn_ctx.lo_fn_type.output else {
                 return Err(LoError {
                     message: format!("Cannot infer Result type from function's return type",),
-                    loc: ok_token.loc.clone(),
+                    loc: ok_token.loc,
                 });
             };
 
@@ -1428,19 +2237,20 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         };
         tokens.expect(Delim, ")")?;
 
+        let ok_value = coerce_int_literal(ok_value, &expected_ok_type, &ok_token.loc)?;
         let ok_value_type = ok_value.get_type(ctx.module);
-        if ok_value_type != expected_ok_type {
+        if !ok_value_type.is_assignable_to(&expected_ok_type) {
             return Err(LoError {
                 message: format!(
                     "Invalid Ok type: {}, expected: {}",
                     ok_value_type, expected_ok_type
                 ),
-                loc: ok_token.loc.clone(),
+                loc: ok_token.loc,
             });
         };
 
         return Ok(LoInstr::MultiValueEmit {
-            values: vec![ok_value, LoInstr::I32Const { value: 0 }],
+            values: vec![ok_value, expected_err_type.get_default_value(ctx.module)],
         }
         .casted(LoType::Result {
             ok_type: Box::new(expected_ok_type),
@@ -1461,7 +2271,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             let LoType::Result { ok_type, err_type } = &ctx.fn_ctx.lo_fn_type.output else {
                 return Err(LoError {
                     message: format!("Cannot infer Result type from function's return type",),
-                    loc: err_token.loc.clone(),
+                    loc: err_token.loc,
                 });
             };
 
@@ -1474,16 +2284,17 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         tokens.expect(Delim, ")")?;
 
         let err_value_type = err_value.get_type(ctx.module);
-        if err_value_type != expected_err_type {
+        if !is_error_convertible(&err_value_type, &expected_err_type) {
             return Err(LoError {
                 message: format!(
                     "Invalid Err type: {}, expected: {}",
                     err_value_type, expected_err_type
                 ),
-                loc: err_token.loc.clone(),
+                loc: err_token.loc,
             });
         };
 
+        let err_value = convert_error(ctx.module, err_value, &err_value_type, &expected_err_type);
         return Ok(LoInstr::MultiValueEmit {
             values: vec![expected_ok_type.get_default_value(ctx.module), err_value],
         }
@@ -1500,9 +2311,20 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
 
             match op.tag {
                 PrefixOpTag::Not => {
+                    let value = parse_expr(ctx, tokens, min_bp)?;
+                    let value_type = value.get_type(ctx.module);
+                    if value_type != LoType::Bool {
+                        return Err(LoError {
+                            message: format!(
+                                "Operator `!` is only supported on bool, got {value_type}"
+                            ),
+                            loc: op.token.loc,
+                        });
+                    }
+
                     return Ok(LoInstr::BinaryOp {
                         kind: WasmBinaryOpKind::I32_EQ,
-                        lhs: Box::new(parse_expr(ctx, tokens, min_bp)?),
+                        lhs: Box::new(value),
                         rhs: Box::new(LoInstr::U32Const { value: 0 }),
                     });
                 }
@@ -1511,6 +2333,9 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
                     return cast_to_signed(value, &op.token.loc);
                 }
                 PrefixOpTag::Negative => {
+                    if tokens.next_is_any(IntLiteral)? {
+                        return parse_negated_const_int(tokens);
+                    }
                     let value = parse_expr(ctx, tokens, min_bp + 1)?;
                     return negate(value, &op.token.loc);
                 }
@@ -1518,7 +2343,11 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
                     let pointer = Box::new(parse_expr(ctx, tokens, min_bp)?);
                     let pointer_type = pointer.get_type(ctx.module);
 
-                    let LoType::Pointer(pointee_type) = pointer_type else {
+                    let LoType::Pointer {
+                        pointee: pointee_type,
+                        ..
+                    } = pointer_type
+                    else {
                         return Err(LoError {
                             message: format!("Cannot dereference {pointer_type:?}"),
                             loc: op.token.loc,
@@ -1530,20 +2359,46 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
                         loc: op.token.loc,
                     });
                 }
+                PrefixOpTag::AddressOf => {
+                    let local_name = tokens.expect_any(Symbol)?.clone();
+                    let Some(local) = ctx.block.get_local(&local_name.value) else {
+                        return Err(LoError {
+                            message: format!("Cannot take address of: {}", local_name.value),
+                            loc: local_name.loc,
+                        });
+                    };
+
+                    let Some(stack_offset) = local.stack_offset else {
+                        return Err(LoError {
+                            message: format!(
+                                "Cannot take address of: {}, only `let` locals are addressable",
+                                local_name.value
+                            ),
+                            loc: local_name.loc,
+                        });
+                    };
+
+                    return Ok(compile_stack_local_address(ctx, stack_offset).casted(
+                        LoType::Pointer {
+                            pointee: Box::new(local.value_type.clone()),
+                            is_readonly: false,
+                        },
+                    ));
+                }
             }
         }
     }
 
     if let Some(_) = tokens.eat(Operator, ".")? {
         let struct_name = parse_nested_symbol(tokens)?;
-        let Some(struct_def) = ctx.module.get_struct_def(&struct_name.value) else {
+        let Some(struct_def) = ctx.module.get_struct_def(struct_name.value) else {
             return Err(LoError {
                 message: format!("Can not create unknown struct: {}", struct_name.value),
                 loc: struct_name.loc,
             });
         };
 
-        return parse_struct_literal(ctx, tokens, struct_name, struct_def);
+        return parse_struct_literal(ctx, tokens, struct_name, &struct_def);
     }
 
     let value = parse_nested_symbol(tokens)?;
@@ -1558,6 +2413,16 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
     }
 
     if let Some(local) = ctx.block.get_local(&value.value) {
+        if let Some(stack_offset) = local.stack_offset {
+            let value_type = local.value_type.clone();
+            return compile_stack_local_get(ctx, stack_offset, &value_type).map_err(|message| {
+                LoError {
+                    message,
+                    loc: value.loc,
+                }
+            });
+        }
+
         if ctx.module.mode == CompilerMode::Inspect {
             let source_index = ctx.module.get_loc_module_index(&value.loc);
             let source_range = RangeDisplay(&value.loc);
@@ -1583,7 +2448,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         });
     };
 
-    if let Some(const_def) = ctx.module.constants.borrow().get(&value.value) {
+    if let Some(const_def) = ctx.module.constants.borrow().get_by_name(&value.value) {
         if ctx.module.mode == CompilerMode::Inspect {
             let source_index = ctx.module.get_loc_module_index(&value.loc);
             let source_range = RangeDisplay(&value.loc);
@@ -1592,11 +2457,12 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
 
             let const_name = &value.value;
             let const_type = const_def.value.get_type(ctx.module);
+            let doc = DocDisplay(&const_def.doc);
 
             stdout_writeln(format!(
                 "{{ \"type\": \"info\", \
                     \"link\": \"{target_index}/{target_range}\", \
-                    \"hover\": \"const {const_name}: {const_type}\", \
+                    \"hover\": \"const {const_name}: {const_type}{doc}\", \
                     \"loc\": \"{source_index}/{source_range}\" }}, ",
             ));
         }
@@ -1604,7 +2470,7 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         return Ok(const_def.value.clone());
     }
 
-    if let Some(global) = ctx.module.globals.get(&value.value) {
+    if let Some(global) = ctx.module.globals.get_by_name(&value.value) {
         if ctx.module.mode == CompilerMode::Inspect {
             let source_index = ctx.module.get_loc_module_index(&value.loc);
             let source_range = RangeDisplay(&value.loc);
@@ -1627,13 +2493,17 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
         });
     };
 
-    if let Some(fn_def) = ctx.module.fn_defs.get(&value.value) {
+    if let Some(global_index) = ctx.module.get_data_layout_global(&value.value) {
+        return Ok(LoInstr::GlobalGet { global_index }.casted(LoType::U32));
+    }
+
+    if let Some(fn_def) = ctx.module.get_fn_def(&value.value) {
         let mut args = vec![];
         parse_fn_call_args(ctx, tokens, &mut args)?;
         typecheck_fn_call_args(
             ctx.module,
             &fn_def.type_.inputs,
-            &args,
+            &mut args,
             &value.value,
             &value.loc,
         )?;
@@ -1647,19 +2517,19 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
             let fn_name = &value.value;
             let params = ListDisplay(&fn_def.fn_params);
             let return_type = &fn_def.type_.output;
+            let doc = DocDisplay(&fn_def.doc);
 
             stdout_writeln(format!(
                 "{{ \"type\": \"info\", \
                     \"link\": \"{target_index}/{target_range}\", \
-                    \"hover\": \"fn {fn_name}({params}): {return_type}\", \
+                    \"hover\": \"fn {fn_name}({params}): {return_type}{doc}\", \
                     \"loc\": \"{source_index}/{source_range}\" }}, ",
             ));
         }
 
-        return Ok(LoInstr::Call {
-            fn_index: fn_def.get_absolute_index(ctx.module),
-            return_type: fn_def.type_.output.clone(),
-            args,
+        return compile_fn_call(ctx, fn_def, args).map_err(|message| LoError {
+            message,
+            loc: value.loc,
         });
     }
 
@@ -1669,18 +2539,113 @@ fn parse_primary(ctx: &mut BlockContext, tokens: &mut LoTokenStream) -> Result<L
     });
 }
 
+// `defer with (a, b) <expr>` copies `a` and `b` into hidden locals when the defer is reached,
+//   the deferred expr reads the copies instead of the values at fn exit
+fn parse_defer_with_captures(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+) -> Result<LoInstr, LoError> {
+    let mut snapshots = vec![];
+    let mut captured_locals = AtomMap::<LocalDef>::new();
+
+    tokens.expect(Delim, "(")?;
+    while tokens.eat(Delim, ")")?.is_none() {
+        let local_name = tokens.expect_any(Symbol)?.clone();
+        if !tokens.next_is(Delim, ")")? {
+            tokens.expect(Delim, ",")?;
+        }
+
+        if captured_locals.get_by_name(&local_name.value).is_some() {
+            return Err(LoError {
+                message: format!("Duplicate defer capture: {}", local_name.value),
+                loc: local_name.loc,
+            });
+        }
+
+        let Some(local) = ctx.block.get_local(&local_name.value) else {
+            return Err(LoError {
+                message: format!("Only locals can be captured by defer: {}", local_name.value),
+                loc: local_name.loc,
+            });
+        };
+        let value_type = local.value_type.clone();
+        let value = match local.stack_offset {
+            Some(stack_offset) => compile_stack_local_get(ctx, stack_offset, &value_type),
+            None => compile_local_get(ctx.module, local.index, &value_type),
+        };
+        let map_err = |message| LoError {
+            message,
+            loc: local_name.loc,
+        };
+        let value = value.map_err(map_err)?;
+
+        let local_index = ctx.fn_ctx.locals_last_index;
+        let comp_count =
+            value_type.emit_components(ctx.module, &mut ctx.fn_ctx.non_arg_wasm_locals);
+        ctx.fn_ctx.locals_last_index += comp_count;
+        ctx.fn_ctx.add_local_name(
+            &format!("<defer {}>", local_name.value),
+            local_index,
+            comp_count,
+        );
+
+        let bind_instr =
+            compile_local_get(ctx.module, local_index, &value_type).map_err(map_err)?;
+        snapshots.push(compile_set(ctx, value, bind_instr, &local_name.loc)?);
+
+        captured_locals.insert(
+            Atom::intern(&local_name.value),
+            LocalDef {
+                index: local_index,
+                value_type,
+                loc: local_name.loc,
+                stack_offset: None,
+            },
+        );
+    }
+
+    let defer_ctx = &mut BlockContext {
+        module: ctx.module,
+        fn_ctx: ctx.fn_ctx,
+        block: Block {
+            locals: captured_locals,
+            ..Block::child_of(ctx.module, &ctx.block)
+        },
+    };
+    let expr_loc = tokens.peek().unwrap_or(&tokens.terminal_token).loc;
+    let deffered_expr = parse_expr(defer_ctx, tokens, 0)?;
+
+    push_deferred(defer_ctx, deffered_expr, expr_loc)?;
+
+    Ok(LoInstr::MultiValueEmit { values: snapshots }.casted(LoType::Void))
+}
+
 fn parse_struct_literal(
     ctx: &mut BlockContext,
     tokens: &mut LoTokenStream,
     struct_name: LoToken,
     struct_def: &StructDef,
 ) -> Result<LoInstr, LoError> {
+    if ctx
+        .module
+        .union_types
+        .contains(&Atom::intern(&struct_name.value))
+    {
+        return Err(LoError {
+            message: format!(
+                "Union {} can only be used through a pointer",
+                struct_name.value
+            ),
+            loc: struct_name.loc,
+        });
+    }
+
     let mut values = vec![];
     tokens.expect(Delim, "{")?;
     while let None = tokens.eat(Delim, "}")? {
         let field_name = tokens.expect_any(Symbol)?.clone();
         tokens.expect(Operator, ":")?;
-        let field_value_loc = tokens.loc().clone();
+        let field_value_loc = *tokens.loc();
         let field_value = parse_expr(ctx, tokens, 0)?;
 
         if !tokens.next_is(Delim, "}")? {
@@ -1701,9 +2666,12 @@ fn parse_struct_literal(
                 loc: field_name.loc,
             });
         }
+        check_field_visibility(ctx, &struct_name.value, struct_field, &field_name.loc)?;
 
+        let field_value =
+            coerce_int_literal(field_value, &struct_field.value_type, &field_value_loc)?;
         let field_value_type = field_value.get_type(ctx.module);
-        if field_value_type != struct_field.value_type {
+        if !field_value_type.is_assignable_to(&struct_field.value_type) {
             return Err(LoError {
                 message: format!(
                     "Invalid type for field {}.{}, expected: {}, got: {}",
@@ -1732,13 +2700,54 @@ fn parse_struct_literal(
 
     return Ok(
         LoInstr::MultiValueEmit { values }.casted(LoType::StructInstance {
-            name: struct_name.value,
+            name: Atom::intern(&struct_name.value),
         }),
     );
 }
 
+// private fields can be used in the file that defines the struct and in its methods,
+//   so the struct stays in control of their invariants
+fn check_field_visibility(
+    ctx: &BlockContext,
+    struct_name: &str,
+    field: &StructField,
+    access_loc: &LoLocation,
+) -> Result<(), LoError> {
+    if !field.is_private || access_loc.file_name() == field.loc.file_name() {
+        return Ok(());
+    }
+
+    let fn_name = ctx.fn_ctx.fn_name;
+    if fn_name.starts_with(struct_name) && fn_name[struct_name.len()..].starts_with("::") {
+        return Ok(());
+    }
+
+    Err(LoError {
+        message: format!(
+            "Field `{}` of struct {struct_name} is private, use methods of {struct_name} instead",
+            field.name
+        ),
+        loc: *access_loc,
+    })
+}
+
 fn cast_to_signed(value: LoInstr, loc: &LoLocation) -> Result<LoInstr, LoError> {
     match value {
+        // suffixed `u32` and `i32` literals
+        LoInstr::Casted {
+            value_type: LoType::U32 | LoType::I32,
+            expr,
+        } if matches!(*expr, LoInstr::U32Const { .. } | LoInstr::I32Const { .. }) => {
+            Ok(cast_to_signed(*expr, loc)?.casted(LoType::I32))
+        }
+        // suffixed `u64` and `i64` literals
+        LoInstr::Casted {
+            value_type: LoType::U64 | LoType::I64,
+            expr,
+        } if matches!(*expr, LoInstr::U64Const { .. } | LoInstr::I64Const { .. }) => {
+            Ok(cast_to_signed(*expr, loc)?.casted(LoType::I64))
+        }
+
         LoInstr::U32Const { value } => {
             return Ok(LoInstr::I32Const {
                 value: value as i32,
@@ -1752,28 +2761,38 @@ fn cast_to_signed(value: LoInstr, loc: &LoLocation) -> Result<LoInstr, LoError>
         _ => {
             return Err(LoError {
                 message: format!("Cannot cast this expression to signed integer"),
-                loc: loc.clone(),
+                loc: *loc,
             });
         }
     }
 }
 
 fn negate(value: LoInstr, loc: &LoLocation) -> Result<LoInstr, LoError> {
+    if let Some(int) = get_bare_int_literal(&value) {
+        if let Some(negated) = build_bare_int_literal(-int, true) {
+            return Ok(negated);
+        }
+    }
+
     match value {
-        LoInstr::U32Const { value } => {
-            return Ok(LoInstr::I32Const {
-                value: -(value as i32),
-            })
+        // suffixed `u32` and `i32` literals
+        LoInstr::Casted {
+            value_type: LoType::U32 | LoType::I32,
+            expr,
+        } if matches!(*expr, LoInstr::U32Const { .. } | LoInstr::I32Const { .. }) => {
+            Ok(negate(*expr, loc)?.casted(LoType::I32))
         }
-        LoInstr::U64Const { value } => {
-            return Ok(LoInstr::I64Const {
-                value: -(value as i64),
-            })
+        // suffixed `u64` and `i64` literals
+        LoInstr::Casted {
+            value_type: LoType::U64 | LoType::I64,
+            expr,
+        } if matches!(*expr, LoInstr::U64Const { .. } | LoInstr::I64Const { .. }) => {
+            Ok(negate(*expr, loc)?.casted(LoType::I64))
         }
         _ => {
             return Err(LoError {
                 message: format!("Cannot negate this expression"),
-                loc: loc.clone(),
+                loc: *loc,
             });
         }
     }
@@ -1788,7 +2807,7 @@ fn define_local(
     if ctx.block.get_own_local(&local_name.value).is_some() {
         return Err(LoError {
             message: format!("Duplicate local definition: {}", local_name.value),
-            loc: local_name.loc.clone(),
+            loc: local_name.loc,
         });
     }
 
@@ -1808,13 +2827,16 @@ fn define_local(
     let local_index = ctx.fn_ctx.locals_last_index;
     let comp_count = value_type.emit_components(&ctx.module, &mut ctx.fn_ctx.non_arg_wasm_locals);
     ctx.fn_ctx.locals_last_index += comp_count;
+    ctx.fn_ctx
+        .add_local_name(&local_name.value, local_index, comp_count);
 
     ctx.block.locals.insert(
-        local_name.value.clone(),
+        Atom::intern(&local_name.value),
         LocalDef {
             index: local_index,
             value_type,
-            loc: local_name.loc.clone(),
+            loc: local_name.loc,
+            stack_offset: None,
         },
     );
 
@@ -1826,6 +2848,124 @@ fn define_local(
     return compile_set(ctx, value, bind_instr, &local_name.loc);
 }
 
+// address-taken locals live in the fn's shadow stack frame instead of wasm locals
+fn define_stack_local(
+    ctx: &mut BlockContext,
+    local_name: &LoToken,
+    value: LoInstr,
+    value_type: LoType,
+) -> Result<LoInstr, LoError> {
+    if ctx.block.get_own_local(&local_name.value).is_some() {
+        return Err(LoError {
+            message: format!("Duplicate local definition: {}", local_name.value),
+            loc: local_name.loc,
+        });
+    }
+
+    let map_err = |message| LoError {
+        message,
+        loc: local_name.loc,
+    };
+    let byte_length = value_type
+        .sized_comp_stats(ctx.module)
+        .map_err(map_err)?
+        .byte_length;
+    let alignment = value_type.get_alignment(ctx.module).map_err(map_err)?;
+
+    let stack_offset = ctx.fn_ctx.stack_frame_size.next_multiple_of(alignment);
+    ctx.fn_ctx.stack_frame_size = stack_offset + byte_length;
+
+    let bind_instr = compile_stack_local_get(ctx, stack_offset, &value_type).map_err(map_err)?;
+
+    ctx.block.locals.insert(
+        Atom::intern(&local_name.value),
+        LocalDef {
+            index: ctx.fn_ctx.stack_frame_local.unwrap(), // set up for address-taken locals
+            value_type,
+            loc: local_name.loc,
+            stack_offset: Some(stack_offset),
+        },
+    );
+
+    compile_set(ctx, value, bind_instr, &local_name.loc)
+}
+
+fn compile_stack_local_address(ctx: &BlockContext, stack_offset: u32) -> LoInstr {
+    LoInstr::BinaryOp {
+        kind: WasmBinaryOpKind::I32_ADD,
+        lhs: Box::new(LoInstr::UntypedLocalGet {
+            local_index: ctx.fn_ctx.stack_frame_local.unwrap(), // safe
+        }),
+        rhs: Box::new(LoInstr::U32Const {
+            value: stack_offset,
+        }),
+    }
+}
+
+fn compile_stack_local_get(
+    ctx: &mut BlockContext,
+    stack_offset: u32,
+    value_type: &LoType,
+) -> Result<LoInstr, String> {
+    let address_instr = LoInstr::UntypedLocalGet {
+        local_index: ctx.fn_ctx.stack_frame_local.unwrap(), // safe
+    };
+    compile_load(ctx, value_type, &address_instr, stack_offset)
+}
+
+// names used with prefix `&` in the fn body, for example `&value`,
+//   `&` is binary when it follows an operand (a symbol, a literal or a closing delimiter)
+// a param is used if its name appears anywhere in the body, `self` and `_`-prefixed
+//   params are never reported
+fn warn_about_unused_params(ctx: &ModuleContext, fn_def: &FnDef, body: &LoTokenStream) {
+    for param in &fn_def.fn_params {
+        if param.name == "self" || param.name.starts_with('_') {
+            continue;
+        }
+
+        let is_used = body
+            .tokens
+            .iter()
+            .any(|token| token.type_ == Symbol && token.value == param.name);
+        if !is_used {
+            ctx.warn(
+                LoWarning::UnusedParam,
+                param.loc,
+                &format!("Unused parameter: {}", param.name),
+            );
+        }
+    }
+}
+
+fn collect_address_taken_names(body: &LoTokenStream) -> Vec<String> {
+    let mut names = Vec::<String>::new();
+
+    for (i, token) in body.tokens.iter().enumerate() {
+        if token.type_ != Operator || token.value != "&" {
+            continue;
+        }
+
+        if let Some(prev) = i.checked_sub(1).map(|i| &body.tokens[i]) {
+            let follows_operand = match prev.type_ {
+                Symbol | IntLiteral | CharLiteral | StringLiteral => true,
+                Delim => prev.value == ")" || prev.value == "]",
+                _ => false,
+            };
+            if follows_operand {
+                continue;
+            }
+        }
+
+        if let Some(next) = body.tokens.get(i + 1) {
+            if next.type_ == Symbol && !names.iter().any(|n| *n == next.value) {
+                names.push(String::from(next.value));
+            }
+        }
+    }
+
+    names
+}
+
 fn parse_macro_call(
     ctx: &mut BlockContext,
     tokens: &mut LoTokenStream,
@@ -1836,13 +2976,13 @@ fn parse_macro_call(
         let receiver_type = receiver.get_type(ctx.module);
         get_fn_name_from_method(&receiver_type, &macro_token.value)
     } else {
-        macro_token.value.clone()
+        String::from(macro_token.value)
     };
 
     let Some(macro_def) = ctx.module.macros.get(&macro_name) else {
         return Err(LoError {
             message: format!("Unknown macro: {}", macro_name),
-            loc: macro_token.loc.clone(),
+            loc: macro_token.loc,
         });
     };
 
@@ -1851,7 +2991,8 @@ fn parse_macro_call(
 
         if let Some(_) = tokens.eat(Operator, "<")? {
             while let None = tokens.eat(Operator, ">")? {
-                let macro_arg = parse_lo_type(ctx, tokens)?;
+                // checked where the macro body creates a value of the type
+                let macro_arg = parse_referenced_lo_type(ctx, tokens)?;
                 type_args.push(macro_arg);
                 if !tokens.next_is(Operator, ">")? {
                     tokens.expect(Delim, ",")?;
@@ -1866,18 +3007,20 @@ fn parse_macro_call(
                     macro_def.type_params.len(),
                     type_args.len()
                 ),
-                loc: macro_token.loc.clone(),
+                loc: macro_token.loc,
             });
         }
 
         let mut type_scope = LoTypeScope::default();
         for (name, value) in macro_def.type_params.iter().zip(type_args) {
-            type_scope.insert(name.clone(), value.clone());
+            type_scope.insert(name, value.clone());
         }
 
         type_scope
     };
-    let return_type = macro_def.return_type.resolve_macro_type_args(&type_scope)?;
+    let return_type = macro_def
+        .return_type
+        .resolve_macro_type_args(ctx.module, &type_scope)?;
 
     let macro_args = {
         let mut args = vec![];
@@ -1888,31 +3031,58 @@ fn parse_macro_call(
 
         let mut params = Vec::new();
         for param in &macro_def.params {
-            params.push(param.type_.resolve_macro_type_args(&type_scope)?);
+            params.push(
+                param
+                    .type_
+                    .resolve_macro_type_args(ctx.module, &type_scope)?,
+            );
         }
-        typecheck_fn_call_args(ctx.module, &params, &args, &macro_name, &macro_token.loc)?;
+        typecheck_fn_call_args(
+            ctx.module,
+            &params,
+            &mut args,
+            &macro_name,
+            &macro_token.loc,
+        )?;
 
-        let mut macro_args = BTreeMap::new();
+        let mut macro_args = AtomMap::new();
         for (param, value) in macro_def.params.iter().zip(args) {
-            macro_args.insert(param.name.clone(), value.clone());
+            macro_args.insert(Atom::intern(&param.name), value.clone());
         }
 
         macro_args
     };
 
+    if ctx.fn_ctx.macro_depth >= MAX_MACRO_EXPANSION_DEPTH {
+        return Err(LoError {
+            message: format!(
+                "Macro expansion depth limit ({MAX_MACRO_EXPANSION_DEPTH}) exceeded while expanding {macro_name}!"
+            ),
+            loc: macro_token.loc,
+        });
+    }
+
+    // the body only sees its own args and type params, caller's locals are hidden
     let macro_ctx = &mut BlockContext {
         module: ctx.module,
         fn_ctx: ctx.fn_ctx,
         block: Block {
+            block_kind: LoBlockKind::Macro,
             parent: Some(&ctx.block),
-            type_scope: Some(type_scope.with_parent(ctx.module, &ctx.block)),
+            type_scope: Some(LoTypeScope {
+                types: type_scope.types,
+                parent: Some(&ctx.module.type_scope),
+            }),
             macro_args: Some(macro_args),
             ..Default::default()
         },
     };
 
-    let exprs =
-        parse_block_contents(macro_ctx, &mut macro_def.body.clone(), return_type.clone())?.exprs;
+    macro_ctx.fn_ctx.macro_depth += 1;
+    let contents =
+        parse_block_contents(macro_ctx, &mut macro_def.body.clone(), return_type.clone());
+    macro_ctx.fn_ctx.macro_depth -= 1;
+    let exprs = contents?.exprs;
 
     if ctx.module.mode == CompilerMode::Inspect {
         let source_index = ctx.module.get_loc_module_index(&macro_token.loc);
@@ -1953,18 +3123,31 @@ fn parse_block_contents(
         has_return: false,
     };
 
+    let mut unreachable_loc: Option<LoLocation> = None;
+
     while tokens.peek().is_some() {
-        let expr_loc = tokens.peek().unwrap().loc.clone();
+        let expr_loc = tokens.peek().unwrap().loc;
         let expr = parse_expr(ctx, tokens, 0)?;
-        tokens.expect(Delim, ";")?;
+        let end_offset = tokens.expect(Delim, ";")?.loc.end_offset;
+
+        if contents.has_never {
+            let loc = unreachable_loc.get_or_insert(expr_loc);
+            loc.end_offset = end_offset;
+        }
 
         let expr_type = expr.get_type(ctx.module);
         if expr_type == LoType::Never {
             contents.has_never = true;
-            if let LoInstr::Return { .. } = &expr {
+            if let LoInstr::Return { .. } | LoInstr::ReturnCall { .. } = &expr {
                 contents.has_return = true;
             }
         } else if expr_type != LoType::Void {
+            if let LoType::Result { .. } = expr_type {
+                if expr_type != expected_type {
+                    return Err(unhandled_result_error(&expr_type, expr_loc));
+                }
+            }
+
             if expr_type != expected_type {
                 return Err(LoError {
                     message: format!("Expression resolved to `{expr_type}`, but block expected `{expected_type}`"),
@@ -1984,20 +3167,28 @@ fn parse_block_contents(
             resolved_type = expr_type;
         }
 
+        if ctx.module.emit_source_locs {
+            contents.exprs.push(LoInstr::SourceLoc { loc: expr_loc });
+        }
         contents.exprs.push(expr);
     }
 
+    if let Some(loc) = unreachable_loc {
+        ctx.module
+            .warn(LoWarning::UnreachableCode, loc, "Unreachable code");
+    }
+
     if let Some(t) = tokens.peek() {
         return Err(LoError {
             message: format!("Unexpected token at the end of block: {t:?}"),
-            loc: t.loc.clone(),
+            loc: t.loc,
         });
     }
 
     if !contents.has_never && resolved_type != expected_type {
         return Err(LoError {
             message: format!("Block resolved to {resolved_type} but {expected_type} was expected"),
-            loc: tokens.terminal_token.loc.clone(),
+            loc: tokens.terminal_token.loc,
         });
     }
 
@@ -2035,11 +3226,7 @@ fn parse_postfix(
         | InfixOpTag::ShiftRight => {
             let lhs = primary;
             let rhs = parse_expr(ctx, tokens, min_bp)?;
-            LoInstr::BinaryOp {
-                kind: get_binary_op(ctx.module, &op, &lhs, &rhs)?,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
-            }
+            build_binary_op(ctx, &op, lhs, rhs)?
         }
         InfixOpTag::And | InfixOpTag::Or => {
             let lhs = primary;
@@ -2065,26 +3252,23 @@ fn parse_postfix(
             let lhs = primary;
             let rhs = parse_expr(ctx, tokens, min_bp)?;
 
-            let value = LoInstr::BinaryOp {
-                kind: get_binary_op(ctx.module, &op, &lhs, &rhs)?,
-                lhs: Box::new(lhs.clone()),
-                rhs: Box::new(rhs),
-            };
+            let value = build_binary_op(ctx, &op, lhs.clone(), rhs)?;
 
             compile_set(ctx, value, lhs, &op.token.loc)?
         }
         InfixOpTag::Assign => {
             let value = parse_expr(ctx, tokens, min_bp)?;
-            let value_type = value.get_type(ctx.module);
             let bind_type = primary.get_type(ctx.module);
+            let value = coerce_int_literal(value, &bind_type, &op.token.loc)?;
+            let value_type = value.get_type(ctx.module);
 
-            if value_type != bind_type {
+            if !value_type.is_assignable_to(&bind_type) {
                 return Err(LoError {
                     message: format!(
                         "Invalid types for '{}', needed {bind_type}, got {value_type}",
                         op.token.value
                     ),
-                    loc: op.token.loc.clone(),
+                    loc: op.token.loc,
                 });
             }
             compile_set(ctx, value, primary, &op.token.loc)?
@@ -2104,8 +3288,20 @@ fn parse_postfix(
                 let method_name = field_or_method_name;
                 let receiver_type = primary.get_type(ctx.module);
 
+                if let Some(item_type) = ctx.module.get_slice_item_type(&receiver_type) {
+                    return parse_slice_method_call(ctx, tokens, primary, item_type, method_name);
+                }
+
+                if method_name.value == "has" && ctx.module.is_flags_type(&receiver_type) {
+                    return parse_flags_has_call(ctx, tokens, primary, method_name);
+                }
+
+                if let LoType::Result { .. } = receiver_type {
+                    return parse_result_method_call(ctx, tokens, primary, method_name);
+                }
+
                 let fn_name = get_fn_name_from_method(&receiver_type, &method_name.value);
-                let Some(fn_def) = ctx.module.fn_defs.get(&fn_name) else {
+                let Some(fn_def) = ctx.module.get_fn_def(&fn_name) else {
                     return Err(LoError {
                         message: format!("Unknown function: {fn_name}"),
                         loc: method_name.loc,
@@ -2117,7 +3313,7 @@ fn parse_postfix(
                 typecheck_fn_call_args(
                     ctx.module,
                     &fn_def.type_.inputs,
-                    &args,
+                    &mut args,
                     &fn_name,
                     &method_name.loc,
                 )?;
@@ -2130,19 +3326,19 @@ fn parse_postfix(
 
                     let params = ListDisplay(&fn_def.fn_params);
                     let return_type = &fn_def.type_.output;
+                    let doc = DocDisplay(&fn_def.doc);
 
                     stdout_writeln(format!(
                         "{{ \"type\": \"info\", \
                             \"link\": \"{target_index}/{target_range}\", \
-                            \"hover\": \"fn {fn_name}({params}): {return_type}\", \
+                            \"hover\": \"fn {fn_name}({params}): {return_type}{doc}\", \
                             \"loc\": \"{source_index}/{source_range}\" }}, ",
                     ));
                 }
 
-                return Ok(LoInstr::Call {
-                    fn_index: fn_def.get_absolute_index(ctx.module),
-                    return_type: fn_def.type_.output.clone(),
-                    args,
+                return compile_fn_call(ctx, fn_def, args).map_err(|message| LoError {
+                    message,
+                    loc: method_name.loc,
                 });
             }
 
@@ -2168,6 +3364,7 @@ fn parse_postfix(
                         loc: field_name.loc,
                     });
                 };
+                check_field_visibility(ctx, struct_name.as_str(), field, &field_name.loc)?;
 
                 if ctx.module.mode == CompilerMode::Inspect {
                     let source_index = ctx.module.get_loc_module_index(&field_name.loc);
@@ -2177,11 +3374,12 @@ fn parse_postfix(
 
                     let field_name = &field_name.value;
                     let field_type = &field.value_type;
+                    let field_offset = field.byte_offset;
 
                     stdout_writeln(format!(
                         "{{ \"type\": \"info\", \
                             \"link\": \"{target_index}/{target_range}\", \
-                            \"hover\": \"{struct_name}\\n{field_name}: {field_type}\", \
+                            \"hover\": \"{struct_name}\\n{field_name}: {field_type} // offset {field_offset}\", \
                             \"loc\": \"{source_index}/{source_range}\" }}, ",
                     ));
                 }
@@ -2220,6 +3418,7 @@ fn parse_postfix(
                         loc: field_name.loc,
                     });
                 };
+                check_field_visibility(ctx, struct_name.as_str(), field, &field_name.loc)?;
 
                 if ctx.module.mode == CompilerMode::Inspect {
                     let source_index = ctx.module.get_loc_module_index(&field_name.loc);
@@ -2229,11 +3428,12 @@ fn parse_postfix(
 
                     let field_name = &field_name.value;
                     let field_type = &field.value_type;
+                    let field_offset = field.byte_offset;
 
                     stdout_writeln(format!(
                         "{{ \"type\": \"info\", \
                             \"link\": \"{target_index}/{target_range}\", \
-                            \"hover\": \"{struct_name}\\n{field_name}: {field_type}\", \
+                            \"hover\": \"{struct_name}\\n{field_name}: {field_type} // offset {field_offset}\", \
                             \"loc\": \"{source_index}/{source_range}\" }}, ",
                     ));
                 }
@@ -2251,7 +3451,11 @@ fn parse_postfix(
             }
 
             let primary_type = primary.get_type(ctx.module);
-            if let LoType::Pointer(pointee_type) = &primary_type {
+            if let LoType::Pointer {
+                pointee: pointee_type,
+                ..
+            } = &primary_type
+            {
                 if let LoType::StructInstance { name: struct_name } = pointee_type.as_ref() {
                     let struct_def = ctx.module.get_struct_def(struct_name).unwrap();
                     let Some(field) = struct_def
@@ -2264,9 +3468,10 @@ fn parse_postfix(
                                 "Unknown field {} in struct {struct_name}",
                                 field_name.value
                             ),
-                            loc: field_name.loc.clone(),
+                            loc: field_name.loc,
                         });
                     };
+                    check_field_visibility(ctx, struct_name.as_str(), field, &field_name.loc)?;
 
                     if ctx.module.mode == CompilerMode::Inspect {
                         let source_index = ctx.module.get_loc_module_index(&field_name.loc);
@@ -2276,11 +3481,12 @@ fn parse_postfix(
 
                         let field_name = &field_name.value;
                         let field_type = &field.value_type;
+                        let field_offset = field.byte_offset;
 
                         stdout_writeln(format!(
                             "{{ \"type\": \"info\", \
                                 \"link\": \"{target_index}/{target_range}\", \
-                                \"hover\": \"{struct_name}\\n{field_name}: {field_type}\", \
+                                \"hover\": \"{struct_name}\\n{field_name}: {field_type} // offset {field_offset}\", \
                                 \"loc\": \"{source_index}/{source_range}\" }}, ",
                         ));
                     }
@@ -2288,7 +3494,7 @@ fn parse_postfix(
                     return compile_load(ctx, &field.value_type, &primary, field.byte_offset)
                         .map_err(|e| LoError {
                             message: e,
-                            loc: op.token.loc.clone(),
+                            loc: op.token.loc,
                         });
                 };
             };
@@ -2301,108 +3507,670 @@ fn parse_postfix(
                 loc: field_name.loc,
             });
         }
+        InfixOpTag::Index => {
+            let index = parse_expr(ctx, tokens, 0)?;
+            tokens.expect(Delim, "]")?;
+
+            let primary_type = primary.get_type(ctx.module);
+            let str_type = LoType::StructInstance {
+                name: Atom::intern("str"),
+            };
+
+            // `str` has the same layout as `&[u8]` so its bytes are indexed the same way
+            let item_type = if primary_type == str_type {
+                Some(LoType::U8)
+            } else {
+                ctx.module.get_slice_item_type(&primary_type)
+            };
+            let Some(item_type) = item_type else {
+                return Err(LoError {
+                    message: format!("Trying to index non slice: {primary_type}"),
+                    loc: op.token.loc,
+                });
+            };
+
+            let index_type = index.get_type(ctx.module);
+            if index_type != LoType::U32 {
+                return Err(LoError {
+                    message: format!("Slice index must be u32, got {index_type}"),
+                    loc: op.token.loc,
+                });
+            }
+
+            let address = if ctx.module.checks.has(RuntimeCheck::Bounds) {
+                compile_checked_slice_index(ctx, primary, &item_type, index, &op.token.loc)?
+            } else {
+                // only `data` is kept on the stack
+                let data = LoInstr::Drop {
+                    value: Box::new(primary),
+                    drop_count: 1,
+                };
+                get_slice_item_address(ctx, data, &item_type, index, &op.token.loc)?
+            };
+            compile_load(ctx, &item_type, &address, 0).map_err(|message| LoError {
+                message,
+                loc: op.token.loc,
+            })?
+        }
         InfixOpTag::Catch => parse_catch(ctx, tokens, primary, op, false)?,
         InfixOpTag::ErrorPropagation => parse_catch(ctx, tokens, primary, op, true)?,
     })
 }
 
-fn parse_catch(
+// `.len()` and `.slice(start, end)` are built into every `&[T]`
+fn parse_slice_method_call(
     ctx: &mut BlockContext,
     tokens: &mut LoTokenStream,
-    primary: LoInstr,
-    op: InfixOp,
-    rethrow: bool,
+    slice: LoInstr,
+    item_type: LoType,
+    method_name: LoToken,
 ) -> Result<LoInstr, LoError> {
-    let primary_type = primary.get_type(ctx.module);
-    let LoType::Result {
-        ok_type: caught_ok_type,
-        err_type,
-        ..
-    } = primary_type
-    else {
-        return Err(LoError {
-            message: format!(
-                "Trying to catch an error from the expression of type: {primary_type}",
-            ),
-            loc: op.token.loc,
-        });
-    };
+    let slice_type = slice.get_type(ctx.module);
 
-    let catch_ctx = &mut BlockContext {
-        module: ctx.module,
-        fn_ctx: ctx.fn_ctx,
-        block: Block::child_of(ctx.module, &ctx.block),
-    };
+    let mut args = vec![];
+    parse_fn_call_args(ctx, tokens, &mut args)?;
 
-    let error_bind = if !rethrow {
-        let mut error_bind = tokens.expect_any(Symbol)?.clone();
-        if error_bind.value == "_" {
-            error_bind.value = "<ignored error>".into(); // make sure it's not accesible
+    let params = match method_name.value.as_str() {
+        "len" => vec![],
+        "slice" => vec![LoType::U32, LoType::U32],
+        _ => {
+            return Err(LoError {
+                message: format!("Unknown slice method: {}", method_name.value),
+                loc: method_name.loc,
+            })
         }
-        error_bind
-    } else {
-        op.token // `?` becomes the error bind, will also be hoverable
     };
-
-    let bind_err_instr = define_local(
-        catch_ctx,
-        &error_bind,
-        LoInstr::NoInstr, // pop error value from the stack
-        *err_type.clone(),
-    )?;
-
-    let catch_body = if !rethrow {
-        let mut catch_block = collect_block_tokens(tokens)?;
-        parse_block_contents(catch_ctx, &mut catch_block, *caught_ok_type.clone())?.exprs
-    } else {
-        assert_fn_can_throw(catch_ctx.fn_ctx, &err_type, &error_bind.loc)?;
-
-        let LoType::Result {
-            ok_type: fn_ok_type,
-            ..
-        } = &catch_ctx.fn_ctx.lo_fn_type.output
-        else {
-            return Err(LoError::unreachable(file!(), line!()));
+    let fn_name = format!("{slice_type}::{}", method_name.value);
+    typecheck_fn_call_args(ctx.module, &params, &mut args, &fn_name, &method_name.loc)?;
+
+    // `len` keeps the size in a temp local
+    if method_name.value == "len" {
+        let temp_local_index = alloc_temp_i32_local(ctx);
+        let get_temp = LoInstr::UntypedLocalGet {
+            local_index: temp_local_index,
+        };
+        let set_temp = LoInstr::Set {
+            bind: LoSetBind::Local {
+                index: temp_local_index,
+            },
         };
 
-        let mut return_value = LoInstr::MultiValueEmit {
+        return Ok(LoInstr::MultiValueEmit {
             values: vec![
-                fn_ok_type.get_default_value(ctx.module),
-                LoInstr::LocalGet {
-                    local_index: catch_ctx
-                        .block
-                        .get_own_local(&error_bind.value)
-                        .unwrap()
-                        .index,
-                    value_type: *err_type.clone(),
+                slice,
+                set_temp,
+                LoInstr::Drop {
+                    value: Box::new(LoInstr::NoInstr),
+                    drop_count: 1,
                 },
+                get_temp,
             ],
-        };
-
-        // TODO: this is duplicated like 3 times, should wrap in a function
-        if let Some(mut values) = get_deferred(catch_ctx) {
-            values.insert(0, return_value);
-            return_value = LoInstr::MultiValueEmit { values }.casted(LoType::Void);
         }
+        .casted(LoType::U32));
+    }
 
-        vec![LoInstr::Return {
-            value: Box::new(return_value),
-        }]
-    };
+    let end = args.pop().unwrap(); // safe, typechecked above
+    let start = args.pop().unwrap();
 
-    let error_value = compile_local_get(
-        ctx.module,
-        catch_ctx
-            .block
-            .get_own_local(&error_bind.value)
-            .unwrap() // safe
-            .index,
-        &err_type,
-    )
-    .unwrap(); // safe
+    // the slice is evaluated before the args
+    let (mut values, get_data, get_len) = store_slice_parts(ctx, slice);
 
-    let mut bind_ok_instr = LoInstr::NoInstr;
-    let mut ok_value = LoInstr::NoInstr;
+    let start_local_index = alloc_temp_i32_local(ctx);
+    let get_start = LoInstr::UntypedLocalGet {
+        local_index: start_local_index,
+    };
+    values.push(start);
+    values.push(LoInstr::Set {
+        bind: LoSetBind::Local {
+            index: start_local_index,
+        },
+    });
+
+    let end = if ctx.module.checks.has(RuntimeCheck::Bounds) {
+        let end_local_index = alloc_temp_i32_local(ctx);
+        let get_end = LoInstr::UntypedLocalGet {
+            local_index: end_local_index,
+        };
+        values.push(end);
+        values.push(LoInstr::Set {
+            bind: LoSetBind::Local {
+                index: end_local_index,
+            },
+        });
+
+        let message = format!("{} - Slice range out of bounds", method_name.loc);
+        let panic_call = compile_panic_call(ctx, message, &method_name.loc)?;
+        values.push(LoInstr::If {
+            block_type: LoBlockType::void(),
+            cond: Box::new(LoInstr::BinaryOp {
+                kind: WasmBinaryOpKind::I32_OR,
+                lhs: Box::new(LoInstr::BinaryOp {
+                    kind: WasmBinaryOpKind::I32_GT_U,
+                    lhs: Box::new(get_start.clone()),
+                    rhs: Box::new(get_end.clone()),
+                }),
+                rhs: Box::new(LoInstr::BinaryOp {
+                    kind: WasmBinaryOpKind::I32_GT_U,
+                    lhs: Box::new(get_end.clone()),
+                    rhs: Box::new(get_len),
+                }),
+            }),
+            then_branch: vec![panic_call],
+            else_branch: None,
+        });
+
+        get_end
+    } else {
+        end
+    };
+
+    values.push(get_slice_item_address(
+        ctx,
+        get_data,
+        &item_type,
+        get_start.clone(),
+        &method_name.loc,
+    )?);
+    values.push(LoInstr::BinaryOp {
+        kind: WasmBinaryOpKind::I32_SUB,
+        lhs: Box::new(end),
+        rhs: Box::new(get_start),
+    });
+
+    Ok(LoInstr::MultiValueEmit { values }.casted(slice_type))
+}
+
+fn alloc_temp_i32_local(ctx: &mut BlockContext) -> u32 {
+    let local_index = ctx.fn_ctx.locals_last_index;
+    ctx.fn_ctx.non_arg_wasm_locals.push(WasmType::I32);
+    ctx.fn_ctx.locals_last_index += 1;
+    local_index
+}
+
+// evaluates `slice` and keeps its `data` and `len` in temp locals,
+//   returns the code doing that and the getters of both
+fn store_slice_parts(ctx: &mut BlockContext, slice: LoInstr) -> (Vec<LoInstr>, LoInstr, LoInstr) {
+    let data_local_index = alloc_temp_i32_local(ctx);
+    let len_local_index = alloc_temp_i32_local(ctx);
+
+    let values = vec![
+        slice,
+        LoInstr::Set {
+            bind: LoSetBind::Local {
+                index: len_local_index,
+            },
+        },
+        LoInstr::Set {
+            bind: LoSetBind::Local {
+                index: data_local_index,
+            },
+        },
+    ];
+    let get_data = LoInstr::UntypedLocalGet {
+        local_index: data_local_index,
+    };
+    let get_len = LoInstr::UntypedLocalGet {
+        local_index: len_local_index,
+    };
+
+    (values, get_data, get_len)
+}
+
+// `slice[index]` with the index checked against the slice length
+fn compile_checked_slice_index(
+    ctx: &mut BlockContext,
+    slice: LoInstr,
+    item_type: &LoType,
+    index: LoInstr,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    let (mut values, get_data, get_len) = store_slice_parts(ctx, slice);
+
+    let index_local_index = alloc_temp_i32_local(ctx);
+    let get_index = LoInstr::UntypedLocalGet {
+        local_index: index_local_index,
+    };
+    values.push(index);
+    values.push(LoInstr::Set {
+        bind: LoSetBind::Local {
+            index: index_local_index,
+        },
+    });
+
+    let panic_call = compile_panic_call(ctx, format!("{loc} - Index out of bounds"), loc)?;
+    values.push(LoInstr::If {
+        block_type: LoBlockType::void(),
+        cond: Box::new(LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_GE_U,
+            lhs: Box::new(get_index.clone()),
+            rhs: Box::new(get_len),
+        }),
+        then_branch: vec![panic_call],
+        else_branch: None,
+    });
+
+    let address = get_slice_item_address(ctx, get_data, item_type, get_index, loc)?;
+    let address_type = address.get_type(ctx.module);
+    values.push(address);
+
+    Ok(LoInstr::MultiValueEmit { values }.casted(address_type))
+}
+
+// `flags.has(other)` is built into every flags type and compiles to `(flags & other) == other`
+fn parse_flags_has_call(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+    flags: LoInstr,
+    method_name: LoToken,
+) -> Result<LoInstr, LoError> {
+    let flags_type = flags.get_type(ctx.module);
+
+    let mut args = vec![];
+    parse_fn_call_args(ctx, tokens, &mut args)?;
+
+    let fn_name = format!("{flags_type}::has");
+    let params = vec![flags_type.clone()];
+    typecheck_fn_call_args(ctx.module, &params, &mut args, &fn_name, &method_name.loc)?;
+    let other = args.pop().unwrap(); // safe, typechecked above
+
+    let temp_local_index = ctx.fn_ctx.locals_last_index;
+    ctx.fn_ctx.non_arg_wasm_locals.push(WasmType::I32);
+    ctx.fn_ctx.locals_last_index += 1;
+
+    let get_temp = LoInstr::UntypedLocalGet {
+        local_index: temp_local_index,
+    };
+    let set_temp = LoInstr::Set {
+        bind: LoSetBind::Local {
+            index: temp_local_index,
+        },
+    };
+
+    // `other` is evaluated once and kept in the temp local for the comparison
+    Ok(LoInstr::BinaryOp {
+        kind: WasmBinaryOpKind::I32_EQ,
+        lhs: Box::new(LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_AND,
+            lhs: Box::new(flags),
+            rhs: Box::new(
+                LoInstr::MultiValueEmit {
+                    values: vec![other, set_temp, get_temp.clone()],
+                }
+                .casted(LoType::U32),
+            ),
+        }),
+        rhs: Box::new(get_temp),
+    })
+}
+
+// `.is_ok()`, `.unwrap()` and `.unwrap_or(default)` are built into every `Result<T, E>`
+fn parse_result_method_call(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+    result: LoInstr,
+    method_name: LoToken,
+) -> Result<LoInstr, LoError> {
+    let result_type = result.get_type(ctx.module);
+    let LoType::Result { ok_type, err_type } = &result_type else {
+        return Err(LoError::unreachable(file!(), line!()));
+    };
+
+    let mut args = vec![];
+    parse_fn_call_args(ctx, tokens, &mut args)?;
+
+    let params = match method_name.value.as_str() {
+        "is_ok" | "unwrap" => vec![],
+        "unwrap_or" => vec![*ok_type.clone()],
+        _ => {
+            return Err(LoError {
+                message: format!("Unknown Result method: {}", method_name.value),
+                loc: method_name.loc,
+            })
+        }
+    };
+    let fn_name = format!("{result_type}::{}", method_name.value);
+    typecheck_fn_call_args(ctx.module, &params, &mut args, &fn_name, &method_name.loc)?;
+
+    let result_ctx = &mut BlockContext {
+        module: ctx.module,
+        fn_ctx: ctx.fn_ctx,
+        block: Block::child_of(ctx.module, &ctx.block),
+    };
+
+    // error is on top of the stack, ok value is popped after it
+    let mut locals = vec![];
+    for (local_name, local_type) in [("<err>", err_type), ("<ok>", ok_type)] {
+        let bind = define_local(
+            result_ctx,
+            &LoToken {
+                value: local_name.into(),
+                ..method_name.clone()
+            },
+            LoInstr::NoInstr,
+            *local_type.clone(),
+        )?;
+        let local_index = result_ctx.block.get_own_local(local_name).unwrap().index; // safe
+        locals.push((bind, local_index));
+    }
+    let (bind_ok, ok_local_index) = locals.pop().unwrap(); // safe
+    let (bind_err, err_local_index) = locals.pop().unwrap(); // safe
+    let ok_value = compile_local_get(ctx.module, ok_local_index, ok_type).unwrap(); // safe
+    let err_value = compile_error_flag(ctx.module, err_local_index, err_type);
+
+    if method_name.value == "is_ok" {
+        return Ok(LoInstr::MultiValueEmit {
+            values: vec![
+                result,
+                bind_err,
+                bind_ok,
+                LoInstr::BinaryOp {
+                    kind: WasmBinaryOpKind::I32_EQ,
+                    lhs: Box::new(err_value),
+                    rhs: Box::new(LoInstr::U32Const { value: 0 }),
+                },
+            ],
+        }
+        .casted(LoType::Bool));
+    }
+
+    // `unwrap` traps, calling `panic(message: str): never` with the location if it is defined
+    let mut on_error = vec![];
+    if let Some(default) = args.pop() {
+        on_error.push(default);
+    } else {
+        if ctx.module.get_fn_def("panic").is_some() {
+            let loc = &method_name.loc;
+            on_error.push(compile_panic_call(
+                ctx,
+                format!("{loc} - Unwrapped an error"),
+                loc,
+            )?);
+        }
+        on_error.push(LoInstr::Unreachable);
+    }
+
+    Ok(LoInstr::MultiValueEmit {
+        values: vec![
+            result,
+            bind_err,
+            bind_ok,
+            LoInstr::If {
+                block_type: LoBlockType::in_out(ctx.module, &[], ok_type),
+                cond: Box::new(err_value),
+                then_branch: on_error,
+                else_branch: Some(vec![ok_value]),
+            },
+        ],
+    }
+    .casted(*ok_type.clone()))
+}
+
+// `data` is the pointer to the first item
+fn get_slice_item_address(
+    ctx: &BlockContext,
+    data: LoInstr,
+    item_type: &LoType,
+    index: LoInstr,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    let item_size = item_type
+        .sized_comp_stats(ctx.module)
+        .map_err(|message| LoError { message, loc: *loc })?
+        .byte_length;
+
+    Ok(LoInstr::BinaryOp {
+        kind: WasmBinaryOpKind::I32_ADD,
+        lhs: Box::new(data),
+        rhs: Box::new(LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_MUL,
+            lhs: Box::new(index),
+            rhs: Box::new(LoInstr::U32Const { value: item_size }),
+        }),
+    }
+    .casted(LoType::Pointer {
+        pointee: Box::new(item_type.clone()),
+        is_readonly: false,
+    }))
+}
+
+// `@type_name(T)`, `@field_count(T)`, `@field_name(T, i)` and `@field_offset(T, i)`,
+//   non-const `i` reads the field info from a table placed in data
+fn parse_reflection_builtin(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+) -> Result<LoInstr, LoError> {
+    let builtin = tokens.expect_any(Symbol)?.clone();
+    let has_index = match builtin.value.as_str() {
+        "type_name" | "field_count" => false,
+        "field_name" | "field_offset" => true,
+        _ => {
+            return Err(LoError {
+                message: format!("Unknown builtin: @{}", builtin.value),
+                loc: builtin.loc,
+            });
+        }
+    };
+
+    tokens.expect(Delim, "(")?;
+    let lo_type = parse_lo_type(ctx, tokens)?;
+
+    // strings and field tables are placed in data
+    let check_memory = || {
+        if ctx.module.memories.is_empty() && ctx.module.mode != CompilerMode::Inspect {
+            return Err(LoError {
+                message: format!("Cannot use @{} with no memories defined", builtin.value),
+                loc: builtin.loc,
+            });
+        }
+        Ok(())
+    };
+
+    if builtin.value == "type_name" {
+        tokens.expect(Delim, ")")?;
+        check_memory()?;
+        return Ok(compile_str_const(ctx.module, format!("{lo_type}")));
+    }
+
+    let struct_def = match &lo_type {
+        LoType::StructInstance { name } => ctx.module.get_struct_def(name),
+        _ => None,
+    };
+    let Some(struct_def) = struct_def else {
+        return Err(LoError {
+            message: format!("@{} expects a struct type, got {lo_type}", builtin.value),
+            loc: builtin.loc,
+        });
+    };
+
+    if !has_index {
+        tokens.expect(Delim, ")")?;
+        return Ok(LoInstr::U32Const {
+            value: struct_def.fields.len() as u32,
+        });
+    }
+
+    tokens.expect(Delim, ",")?;
+    let index_loc = *tokens.loc();
+    let index = parse_expr(ctx, tokens, 0)?;
+    tokens.expect(Delim, ")")?;
+
+    let index_type = index.get_type(ctx.module);
+    if index_type != LoType::U32 {
+        return Err(LoError {
+            message: format!("Field index must be u32, got {index_type}"),
+            loc: index_loc,
+        });
+    }
+
+    if let LoInstr::U32Const { value } = index {
+        let Some(field) = struct_def.fields.get(value as usize) else {
+            return Err(LoError {
+                message: format!("Field index out of range for {lo_type}: {value}"),
+                loc: index_loc,
+            });
+        };
+
+        if builtin.value == "field_name" {
+            check_memory()?;
+            return Ok(compile_str_const(ctx.module, field.name.clone()));
+        }
+
+        return Ok(LoInstr::U32Const {
+            value: field.byte_offset,
+        });
+    }
+
+    check_memory()?;
+
+    let mut table = Vec::new();
+    let item_type = if builtin.value == "field_name" {
+        for field in &struct_def.fields {
+            let name_ptr = get_pooled_string_ptr(ctx.module, field.name.clone());
+            table.extend_from_slice(&name_ptr.to_le_bytes());
+            table.extend_from_slice(&(field.name.len() as u32).to_le_bytes());
+        }
+        LoType::StructInstance {
+            name: Atom::intern("str"),
+        }
+    } else {
+        for field in &struct_def.fields {
+            table.extend_from_slice(&field.byte_offset.to_le_bytes());
+        }
+        LoType::U32
+    };
+    let entry_size = table.len() as u32 / struct_def.fields.len().max(1) as u32;
+    let table_ptr = ctx.module.append_data(table, 4);
+
+    let address = LoInstr::BinaryOp {
+        kind: WasmBinaryOpKind::I32_ADD,
+        lhs: Box::new(LoInstr::U32Const { value: table_ptr }),
+        rhs: Box::new(LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_MUL,
+            lhs: Box::new(index),
+            rhs: Box::new(LoInstr::U32Const { value: entry_size }),
+        }),
+    };
+
+    compile_load(ctx, &item_type, &address, 0).map_err(|message| LoError {
+        message,
+        loc: index_loc,
+    })
+}
+
+fn parse_catch(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+    primary: LoInstr,
+    op: InfixOp,
+    rethrow: bool,
+) -> Result<LoInstr, LoError> {
+    let primary_type = primary.get_type(ctx.module);
+    let LoType::Result {
+        ok_type: caught_ok_type,
+        err_type,
+        ..
+    } = primary_type
+    else {
+        return Err(LoError {
+            message: format!(
+                "Trying to catch an error from the expression of type: {primary_type}",
+            ),
+            loc: op.token.loc,
+        });
+    };
+
+    let catch_ctx = &mut BlockContext {
+        module: ctx.module,
+        fn_ctx: ctx.fn_ctx,
+        block: Block::child_of(ctx.module, &ctx.block),
+    };
+
+    let mut field_binds = None;
+    let error_bind = if !rethrow && tokens.next_is(Delim, "{")? {
+        let (pattern_token, fields) = parse_catch_pattern(tokens)?;
+        field_binds = Some(fields);
+        LoToken {
+            value: "<err>".into(),
+            ..pattern_token
+        }
+    } else if !rethrow {
+        let mut error_bind = tokens.expect_any(Symbol)?.clone();
+        if error_bind.value == "_" {
+            error_bind.value = "<ignored error>".into(); // make sure it's not accesible
+        }
+        error_bind
+    } else {
+        op.token // `?` becomes the error bind, will also be hoverable
+    };
+
+    let catches_by_type = !rethrow && tokens.next_is(Operator, ":")?;
+    let err_local = if catches_by_type {
+        LoToken {
+            value: "<err>".into(),
+            ..error_bind.clone()
+        }
+    } else {
+        error_bind.clone()
+    };
+
+    let bind_err_instr = define_local(
+        catch_ctx,
+        &err_local,
+        LoInstr::NoInstr, // pop error value from the stack
+        *err_type.clone(),
+    )?;
+    let err_local_index = catch_ctx
+        .block
+        .get_own_local(&err_local.value)
+        .unwrap()
+        .index; // safe
+
+    let catch_body = if catches_by_type {
+        let clauses = parse_catch_clauses(
+            catch_ctx,
+            tokens,
+            (&err_type, err_local_index),
+            &caught_ok_type,
+            error_bind.clone(),
+        )?;
+        build_catch_clauses(ctx.module, clauses, &caught_ok_type)
+    } else if !rethrow {
+        let mut catch_body = vec![];
+        if let Some(field_binds) = field_binds {
+            let err_value = (&*err_type, err_local_index, &error_bind.loc);
+            catch_body = bind_error_fields(catch_ctx, err_value, field_binds)?;
+        }
+        let mut catch_block = collect_block_tokens(tokens)?;
+        catch_body.extend(
+            parse_block_contents(catch_ctx, &mut catch_block, *caught_ok_type.clone())?.exprs,
+        );
+        catch_body
+    } else {
+        assert_fn_can_throw(catch_ctx.fn_ctx, &err_type, &error_bind.loc)?;
+
+        let LoType::Result {
+            ok_type: fn_ok_type,
+            err_type: fn_err_type,
+        } = &catch_ctx.fn_ctx.lo_fn_type.output
+        else {
+            return Err(LoError::unreachable(file!(), line!()));
+        };
+
+        let error_value = compile_local_get(ctx.module, err_local_index, &err_type).unwrap(); // safe
+        let return_value = LoInstr::MultiValueEmit {
+            values: vec![
+                fn_ok_type.get_default_value(ctx.module),
+                convert_error(ctx.module, error_value, &err_type, fn_err_type),
+            ],
+        };
+
+        vec![compile_return(catch_ctx, return_value, &error_bind.loc)?]
+    };
+
+    let error_flag = compile_error_flag(ctx.module, err_local_index, &err_type);
+
+    let mut bind_ok_instr = LoInstr::NoInstr;
+    let mut ok_value = LoInstr::NoInstr;
 
     if *caught_ok_type != LoType::Void {
         let tmp_ok_local_name = "<ok>";
@@ -2434,7 +4202,7 @@ fn parse_catch(
             bind_ok_instr,
             LoInstr::If {
                 block_type: LoBlockType::in_out(ctx.module, &[], &caught_ok_type),
-                cond: Box::new(error_value), // error_value == 0 means no error
+                cond: Box::new(error_flag),
                 then_branch: catch_body,
                 else_branch: Some(vec![ok_value]),
             },
@@ -2443,24 +4211,261 @@ fn parse_catch(
     .casted(*caught_ok_type.clone()))
 }
 
-fn assert_fn_can_throw(
-    ctx: &FnContext,
-    error_type: &LoType,
-    throw_loc: &LoLocation,
-) -> Result<(), LoError> {
-    let LoType::Result { err_type, .. } = &ctx.lo_fn_type.output else {
-        return Err(LoError {
-            message: format!(
+// `catch { code, message } { ... }` binds fields of a struct error as locals
+fn parse_catch_pattern(tokens: &mut LoTokenStream) -> Result<(LoToken, Vec<LoToken>), LoError> {
+    let pattern_token = tokens.expect(Delim, "{")?.clone();
+    let mut fields = vec![];
+    while tokens.eat(Delim, "}")?.is_none() {
+        fields.push(tokens.expect_any(Symbol)?.clone());
+        if !tokens.next_is(Delim, "}")? {
+            tokens.expect(Delim, ",")?;
+        }
+    }
+    Ok((pattern_token, fields))
+}
+
+fn bind_error_fields(
+    ctx: &mut BlockContext,
+    (err_type, err_local_index, pattern_loc): (&LoType, u32, &LoLocation),
+    field_binds: Vec<LoToken>,
+) -> Result<Vec<LoInstr>, LoError> {
+    let LoType::StructInstance { name: struct_name } = err_type else {
+        return Err(LoError {
+            message: format!("Cannot destructure error of type {err_type}, it is not a struct"),
+            loc: *pattern_loc,
+        });
+    };
+    let struct_def = ctx.module.get_struct_def(struct_name).unwrap(); // safe
+
+    let mut binds = vec![];
+    for field_bind in field_binds {
+        let Some(field) = struct_def
+            .fields
+            .iter()
+            .find(|f| f.name == field_bind.value)
+        else {
+            return Err(LoError {
+                message: format!("Unknown field {} in struct {struct_name}", field_bind.value),
+                loc: field_bind.loc,
+            });
+        };
+        check_field_visibility(ctx, struct_name.as_str(), field, &field_bind.loc)?;
+
+        let field_index = err_local_index + field.field_index;
+        let field_value = compile_local_get(ctx.module, field_index, &field.value_type).unwrap(); // safe
+        binds.push(define_local(
+            ctx,
+            &field_bind,
+            field_value,
+            field.value_type.clone(),
+        )?);
+    }
+    Ok(binds)
+}
+
+struct CatchClause {
+    cond: Option<LoInstr>, // `None` for the untyped clause
+    body: Vec<LoInstr>,
+}
+
+// `catch err: E { ... } catch err: F { ... }` branches by the variant of an error union,
+//   an untyped clause at the end catches the remaining variants as the whole union
+fn parse_catch_clauses(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+    (err_type, err_local_index): (&LoType, u32),
+    ok_type: &LoType,
+    first_bind: LoToken,
+) -> Result<Vec<CatchClause>, LoError> {
+    let LoType::ErrorUnion { variants } = err_type else {
+        return Err(LoError {
+            message: format!("Cannot catch by error type, {err_type} is not an error union"),
+            loc: first_bind.loc,
+        });
+    };
+
+    let mut clauses = vec![];
+    let mut uncaught_variants = variants.clone();
+    let mut error_bind = first_bind;
+    loop {
+        let mut caught_type = None;
+        if tokens.eat(Operator, ":")?.is_some() {
+            let type_loc = tokens.peek().unwrap_or(&tokens.terminal_token).loc;
+            let variant = parse_lo_type(ctx, tokens)?;
+            let Some(variant_index) = uncaught_variants.iter().position(|v| *v == variant) else {
+                let message = if variants.contains(&variant) {
+                    format!("Error type {variant} is already caught")
+                } else {
+                    format!("Error type {variant} is not a variant of {err_type}")
+                };
+                return Err(LoError {
+                    message,
+                    loc: type_loc,
+                });
+            };
+            uncaught_variants.remove(variant_index);
+            caught_type = Some(variant);
+        }
+
+        let clause_ctx = &mut BlockContext {
+            module: ctx.module,
+            fn_ctx: ctx.fn_ctx,
+            block: Block::child_of(ctx.module, &ctx.block),
+        };
+
+        let (cond, bind_value, bind_type) = if let Some(variant) = caught_type {
+            let cond = LoInstr::BinaryOp {
+                kind: WasmBinaryOpKind::I32_EQ,
+                lhs: Box::new(compile_error_flag(ctx.module, err_local_index, err_type)),
+                rhs: Box::new(LoInstr::U32Const {
+                    value: ctx.module.get_error_id(&variant),
+                }),
+            };
+            let mut payload = LoInstr::NoInstr;
+            if variant.emit_components(ctx.module, &mut vec![]) != 0 {
+                payload = LoInstr::UntypedLocalGet {
+                    local_index: err_local_index,
+                }
+                .casted(variant.clone());
+            }
+            (Some(cond), payload, variant)
+        } else {
+            let error_value = compile_local_get(ctx.module, err_local_index, err_type).unwrap(); // safe
+            (None, error_value, err_type.clone())
+        };
+
+        if error_bind.value == "_" {
+            error_bind.value = "<ignored error>".into(); // make sure it's not accesible
+        }
+        let mut body = vec![define_local(
+            clause_ctx,
+            &error_bind,
+            bind_value,
+            bind_type,
+        )?];
+        let mut clause_block = collect_block_tokens(tokens)?;
+        body.extend(parse_block_contents(clause_ctx, &mut clause_block, ok_type.clone())?.exprs);
+
+        let is_last = cond.is_none() || !tokens.peek().is_some_and(|t| t.is(Symbol, "catch"));
+        clauses.push(CatchClause { cond, body });
+        if is_last {
+            break;
+        }
+
+        tokens.next(); // skip `catch`
+        error_bind = tokens.expect_any(Symbol)?.clone();
+    }
+
+    if clauses.last().is_some_and(|c| c.cond.is_some()) && !uncaught_variants.is_empty() {
+        return Err(LoError {
+            message: format!(
+                "Unhandled error types: {}",
+                LoType::ErrorUnion {
+                    variants: uncaught_variants
+                }
+            ),
+            loc: error_bind.loc,
+        });
+    }
+
+    Ok(clauses)
+}
+
+// clauses are checked in order, the last one handles whatever is left
+fn build_catch_clauses(
+    ctx: &ModuleContext,
+    mut clauses: Vec<CatchClause>,
+    ok_type: &LoType,
+) -> Vec<LoInstr> {
+    let mut branch = clauses.pop().unwrap().body; // safe, there is at least one clause
+    while let Some(clause) = clauses.pop() {
+        branch = vec![LoInstr::If {
+            block_type: LoBlockType::in_out(ctx, &[], ok_type),
+            cond: Box::new(clause.cond.unwrap()), // safe, only the last clause can be untyped
+            then_branch: clause.body,
+            else_branch: Some(branch),
+        }];
+    }
+    branch
+}
+
+// non-zero if there is an error, error unions keep it in the discriminant
+//   and errors of multiple components in the first one
+fn compile_error_flag(ctx: &ModuleContext, err_local_index: u32, err_type: &LoType) -> LoInstr {
+    if let LoType::ErrorUnion { .. } = err_type {
+        return LoInstr::UntypedLocalGet {
+            local_index: err_local_index + 1,
+        };
+    }
+
+    let mut components = vec![];
+    err_type.emit_components(ctx, &mut components);
+    if components.len() > 1 && components[0] == WasmType::I32 {
+        return LoInstr::UntypedLocalGet {
+            local_index: err_local_index,
+        };
+    }
+    compile_local_get(ctx, err_local_index, err_type).unwrap() // safe
+}
+
+// error union variants and subsets of the union convert to it
+fn is_error_convertible(err_type: &LoType, wanted: &LoType) -> bool {
+    if err_type.is_assignable_to(wanted) {
+        return true;
+    }
+    let LoType::ErrorUnion { variants } = wanted else {
+        return false;
+    };
+    match err_type {
+        LoType::ErrorUnion {
+            variants: err_variants,
+        } => err_variants.iter().all(|v| variants.contains(v)),
+        _ => variants.contains(err_type),
+    }
+}
+
+// discriminants are module wide, so only single variants need tagging
+fn convert_error(
+    ctx: &ModuleContext,
+    value: LoInstr,
+    err_type: &LoType,
+    wanted: &LoType,
+) -> LoInstr {
+    if err_type.is_assignable_to(wanted) {
+        return value;
+    }
+    if let LoType::ErrorUnion { .. } = err_type {
+        return value.casted(wanted.clone());
+    }
+
+    let mut values = vec![value];
+    if err_type.emit_components(ctx, &mut vec![]) == 0 {
+        values.push(LoInstr::U32Const { value: 0 });
+    }
+    values.push(LoInstr::U32Const {
+        value: ctx.get_error_id(err_type),
+    });
+    LoInstr::MultiValueEmit { values }.casted(wanted.clone())
+}
+
+fn assert_fn_can_throw(
+    ctx: &FnContext,
+    error_type: &LoType,
+    throw_loc: &LoLocation,
+) -> Result<(), LoError> {
+    let LoType::Result { err_type, .. } = &ctx.lo_fn_type.output else {
+        return Err(LoError {
+            message: format!(
                 "Cannot throw {error_type}, function can only return {output}",
                 output = ctx.lo_fn_type.output,
             ),
-            loc: throw_loc.clone(),
+            loc: *throw_loc,
         });
     };
-    if *error_type != **err_type {
+    if !is_error_convertible(error_type, err_type) {
         return Err(LoError {
             message: format!("Invalid throw type, expected {err_type}, got {error_type}",),
-            loc: throw_loc.clone(),
+            loc: *throw_loc,
         });
     }
 
@@ -2482,6 +4487,232 @@ fn get_op_additional_to_assign(op: &InfixOpTag) -> Result<InfixOpTag, LoError> {
     }
 }
 
+fn build_binary_op(
+    ctx: &mut BlockContext,
+    op: &InfixOp,
+    lhs: LoInstr,
+    rhs: LoInstr,
+) -> Result<LoInstr, LoError> {
+    if let Some(value) = fold_bare_int_literals(&op.tag, &lhs, &rhs, &op.token.loc)? {
+        return Ok(value);
+    }
+
+    let rhs = coerce_int_literal(rhs, &lhs.get_type(ctx.module), &op.token.loc)?;
+    let lhs = coerce_int_literal(lhs, &rhs.get_type(ctx.module), &op.token.loc)?;
+    let mut rhs = rhs;
+
+    if matches!(op.tag, InfixOpTag::Equal | InfixOpTag::NotEqual) {
+        let str_type = LoType::StructInstance {
+            name: Atom::intern("str"),
+        };
+        if lhs.get_type(ctx.module) == str_type && rhs.get_type(ctx.module) == str_type {
+            return compile_str_equals_call(ctx, op, lhs, rhs);
+        }
+    }
+
+    // flags only combine and compare with the same flags type, others fall through to an error
+    let lhs_type = lhs.get_type(ctx.module);
+    if ctx.module.is_flags_type(&lhs_type) && rhs.get_type(ctx.module) == lhs_type {
+        let kind = match op.tag {
+            InfixOpTag::BitOr => Some(WasmBinaryOpKind::I32_OR),
+            InfixOpTag::BitAnd => Some(WasmBinaryOpKind::I32_AND),
+            InfixOpTag::Equal => Some(WasmBinaryOpKind::I32_EQ),
+            InfixOpTag::NotEqual => Some(WasmBinaryOpKind::I32_NE),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            return Ok(LoInstr::BinaryOp {
+                kind,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            });
+        }
+    }
+
+    // distinct types compute as their inner type, but only with the same distinct type
+    if let Some(inner_type) = ctx.module.get_distinct_inner_type(&lhs_type) {
+        if rhs.get_type(ctx.module) == lhs_type {
+            let lhs = lhs.casted(inner_type.clone());
+            let rhs = rhs.casted(inner_type);
+            let value = build_binary_op(ctx, op, lhs, rhs)?;
+
+            let is_comparison = matches!(
+                op.tag,
+                InfixOpTag::Equal
+                    | InfixOpTag::NotEqual
+                    | InfixOpTag::Less
+                    | InfixOpTag::Greater
+                    | InfixOpTag::LessEqual
+                    | InfixOpTag::GreaterEqual
+            );
+            if is_comparison {
+                return Ok(value);
+            }
+            return Ok(value.casted(lhs_type));
+        }
+    }
+
+    let kind = get_binary_op(ctx.module, op, &lhs, &rhs)?;
+    let value_type = lhs.get_type(ctx.module);
+
+    let is_int_division = matches!(
+        kind,
+        WasmBinaryOpKind::I32_DIV_S
+            | WasmBinaryOpKind::I32_DIV_U
+            | WasmBinaryOpKind::I32_REM_S
+            | WasmBinaryOpKind::I32_REM_U
+            | WasmBinaryOpKind::I64_DIV_S
+            | WasmBinaryOpKind::I64_DIV_U
+            | WasmBinaryOpKind::I64_REM_S
+            | WasmBinaryOpKind::I64_REM_U
+    );
+    if is_int_division && ctx.module.checks.has(RuntimeCheck::DivByZero) {
+        rhs = guard_div_by_zero(ctx, rhs, &op.token.loc)?;
+    }
+
+    let value = LoInstr::BinaryOp {
+        kind,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    };
+
+    // results of these can go out of range of 8 and 16 bit types, so they are wrapped back
+    match op.tag {
+        InfixOpTag::Add
+        | InfixOpTag::Sub
+        | InfixOpTag::Mul
+        | InfixOpTag::Div
+        | InfixOpTag::ShiftLeft => Ok(wrap_packed_int(value, value_type)),
+        _ => Ok(value),
+    }
+}
+
+// evaluates divisor once and panics if it is zero
+fn guard_div_by_zero(
+    ctx: &mut BlockContext,
+    divisor: LoInstr,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    let divisor_type = divisor.get_type(ctx.module);
+    let Some(wasm_type) = divisor_type.to_wasm_type() else {
+        return Err(LoError::unreachable(file!(), line!()));
+    };
+
+    let panic_call = compile_panic_call(ctx, format!("{loc} - Division by zero"), loc)?;
+
+    let divisor_local_index = ctx.fn_ctx.locals_last_index;
+    ctx.fn_ctx.non_arg_wasm_locals.push(wasm_type.clone());
+    ctx.fn_ctx.locals_last_index += 1;
+
+    let get_divisor = LoInstr::UntypedLocalGet {
+        local_index: divisor_local_index,
+    };
+    let zero = match wasm_type {
+        WasmType::I64 => LoInstr::I64Const { value: 0 },
+        _ => LoInstr::U32Const { value: 0 },
+    };
+    let eq_kind = match wasm_type {
+        WasmType::I64 => WasmBinaryOpKind::I64_EQ,
+        _ => WasmBinaryOpKind::I32_EQ,
+    };
+
+    Ok(LoInstr::MultiValueEmit {
+        values: vec![
+            divisor,
+            LoInstr::Set {
+                bind: LoSetBind::Local {
+                    index: divisor_local_index,
+                },
+            },
+            LoInstr::If {
+                block_type: LoBlockType::void(),
+                cond: Box::new(LoInstr::BinaryOp {
+                    kind: eq_kind,
+                    lhs: Box::new(get_divisor.clone()),
+                    rhs: Box::new(zero),
+                }),
+                then_branch: vec![panic_call],
+                else_branch: None,
+            },
+            get_divisor,
+        ],
+    }
+    .casted(divisor_type))
+}
+
+fn compile_panic_call(
+    ctx: &mut BlockContext,
+    message: String,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    let str_type = LoType::StructInstance {
+        name: Atom::intern("str"),
+    };
+    let Some(fn_def) = ctx.module.get_fn_def("panic") else {
+        return Err(LoError {
+            message: String::from("Runtime checks require a `panic(message: str): never` fn"),
+            loc: *loc,
+        });
+    };
+    if fn_def.type_.inputs != [str_type] || fn_def.type_.output != LoType::Never {
+        return Err(LoError {
+            message: String::from(
+                "Runtime checks require `panic` fn to be `panic(message: str): never`",
+            ),
+            loc: *loc,
+        });
+    }
+
+    let fn_def = fn_def.clone();
+    let message = compile_str_const(ctx.module, message);
+    compile_fn_call(ctx, &fn_def, vec![message]).map_err(|message| LoError { message, loc: *loc })
+}
+
+// `==` and `!=` on `str` compare bytes using `str::equals` from std
+fn compile_str_equals_call(
+    ctx: &mut BlockContext,
+    op: &InfixOp,
+    lhs: LoInstr,
+    rhs: LoInstr,
+) -> Result<LoInstr, LoError> {
+    let str_type = LoType::StructInstance {
+        name: Atom::intern("str"),
+    };
+    let fn_def = match ctx.module.get_fn_def("str::equals") {
+        Some(fn_def)
+            if fn_def.type_.inputs == [str_type.clone(), str_type]
+                && fn_def.type_.output == LoType::Bool =>
+        {
+            fn_def.clone()
+        }
+        _ => {
+            return Err(LoError {
+                message: format!(
+                    "Operator `{}` on str requires a `str::equals(self, other: str): bool` fn",
+                    op.token.value
+                ),
+                loc: op.token.loc,
+            })
+        }
+    };
+
+    let equals = compile_fn_call(ctx, &fn_def, vec![lhs, rhs]).map_err(|message| LoError {
+        message,
+        loc: op.token.loc,
+    })?;
+
+    if op.tag == InfixOpTag::NotEqual {
+        return Ok(LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_EQ,
+            lhs: Box::new(equals),
+            rhs: Box::new(LoInstr::U32Const { value: 0 }),
+        }
+        .casted(LoType::Bool));
+    }
+
+    Ok(equals)
+}
+
 fn get_binary_op(
     ctx: &ModuleContext,
     op: &InfixOp,
@@ -2497,7 +4728,7 @@ fn get_binary_op(
                 "Operands of `{}` have incompatible types: {} and {}",
                 op.token.value, lhs_type, rhs_type
             ),
-            loc: op.token.loc.clone(),
+            loc: op.token.loc,
         });
     }
 
@@ -2565,39 +4796,27 @@ fn get_binary_op(
             operand_type => return err_incompatible_op(op, operand_type),
         },
         InfixOpTag::Add => match lhs_type {
-            LoType::Bool
-            | LoType::I8
-            | LoType::U8
-            | LoType::I16
-            | LoType::U16
-            | LoType::I32
-            | LoType::U32 => WasmBinaryOpKind::I32_ADD,
+            LoType::I8 | LoType::U8 | LoType::I16 | LoType::U16 | LoType::I32 | LoType::U32 => {
+                WasmBinaryOpKind::I32_ADD
+            }
             LoType::I64 | LoType::U64 => WasmBinaryOpKind::I64_ADD,
             LoType::F32 => WasmBinaryOpKind::F32_ADD,
             LoType::F64 => WasmBinaryOpKind::F64_ADD,
             operand_type => return err_incompatible_op(op, operand_type),
         },
         InfixOpTag::Sub => match lhs_type {
-            LoType::Bool
-            | LoType::I8
-            | LoType::U8
-            | LoType::I16
-            | LoType::U16
-            | LoType::I32
-            | LoType::U32 => WasmBinaryOpKind::I32_SUB,
+            LoType::I8 | LoType::U8 | LoType::I16 | LoType::U16 | LoType::I32 | LoType::U32 => {
+                WasmBinaryOpKind::I32_SUB
+            }
             LoType::I64 | LoType::U64 => WasmBinaryOpKind::I64_SUB,
             LoType::F32 => WasmBinaryOpKind::F32_SUB,
             LoType::F64 => WasmBinaryOpKind::F64_SUB,
             operand_type => return err_incompatible_op(op, operand_type),
         },
         InfixOpTag::Mul => match lhs_type {
-            LoType::Bool
-            | LoType::I8
-            | LoType::U8
-            | LoType::I16
-            | LoType::U16
-            | LoType::I32
-            | LoType::U32 => WasmBinaryOpKind::I32_MUL,
+            LoType::I8 | LoType::U8 | LoType::I16 | LoType::U16 | LoType::I32 | LoType::U32 => {
+                WasmBinaryOpKind::I32_MUL
+            }
             LoType::I64 | LoType::U64 => WasmBinaryOpKind::I64_MUL,
             LoType::F32 => WasmBinaryOpKind::F32_MUL,
             LoType::F64 => WasmBinaryOpKind::F64_MUL,
@@ -2605,7 +4824,7 @@ fn get_binary_op(
         },
         InfixOpTag::Div => match lhs_type {
             LoType::I8 | LoType::I16 | LoType::I32 => WasmBinaryOpKind::I32_DIV_S,
-            LoType::Bool | LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_DIV_U,
+            LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_DIV_U,
             LoType::I64 => WasmBinaryOpKind::I64_DIV_S,
             LoType::U64 => WasmBinaryOpKind::I64_DIV_U,
             LoType::F32 => WasmBinaryOpKind::F32_DIV,
@@ -2614,21 +4833,21 @@ fn get_binary_op(
         },
         InfixOpTag::Mod => match lhs_type {
             LoType::I8 | LoType::I16 | LoType::I32 => WasmBinaryOpKind::I32_REM_S,
-            LoType::Bool | LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_REM_U,
+            LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_REM_U,
             LoType::I64 => WasmBinaryOpKind::I64_REM_S,
             LoType::U64 => WasmBinaryOpKind::I64_REM_U,
             operand_type => return err_incompatible_op(op, operand_type),
         },
         InfixOpTag::ShiftLeft => match lhs_type {
             LoType::I8 | LoType::I16 | LoType::I32 => WasmBinaryOpKind::I32_SHL,
-            LoType::Bool | LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_SHL,
+            LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_SHL,
             LoType::I64 => WasmBinaryOpKind::I64_SHL,
             LoType::U64 => WasmBinaryOpKind::I64_SHL,
             operand_type => return err_incompatible_op(op, operand_type),
         },
         InfixOpTag::ShiftRight => match lhs_type {
             LoType::I8 | LoType::I16 | LoType::I32 => WasmBinaryOpKind::I32_SHR_S,
-            LoType::Bool | LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_SHR_U,
+            LoType::U8 | LoType::U16 | LoType::U32 => WasmBinaryOpKind::I32_SHR_U,
             LoType::I64 => WasmBinaryOpKind::I64_SHR_S,
             LoType::U64 => WasmBinaryOpKind::I64_SHR_U,
             operand_type => return err_incompatible_op(op, operand_type),
@@ -2687,11 +4906,57 @@ fn err_incompatible_op<T>(op: &InfixOp, operand_type: LoType) -> Result<T, LoErr
             "Operator `{}` is incompatible with operands of type {}",
             op.token.value, operand_type
         ),
-        loc: op.token.loc.clone(),
+        loc: op.token.loc,
     })
 }
 
 // TODO: support all numeric types
+// unlike `as` no conversion is ever done, the bytes are reinterpreted as is
+fn build_bitcast(
+    ctx: &ModuleContext,
+    value: LoInstr,
+    wanted_type: LoType,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    let actual_type = value.get_type(ctx);
+    let map_err = |message| LoError { message, loc: *loc };
+
+    let actual_size = actual_type
+        .sized_comp_stats(ctx)
+        .map_err(map_err)?
+        .byte_length;
+    let wanted_size = wanted_type
+        .sized_comp_stats(ctx)
+        .map_err(map_err)?
+        .byte_length;
+    if actual_size != wanted_size {
+        return Err(LoError {
+            message: format!(
+                "Cannot bitcast `{actual_type}` to `{wanted_type}`: \
+                sizes differ ({actual_size} and {wanted_size} bytes)"
+            ),
+            loc: *loc,
+        });
+    }
+
+    let mut actual_wasm_types = vec![];
+    actual_type.emit_components(ctx, &mut actual_wasm_types);
+
+    let mut wanted_wasm_types = vec![];
+    wanted_type.emit_components(ctx, &mut wanted_wasm_types);
+
+    if actual_wasm_types != wanted_wasm_types {
+        return Err(LoError {
+            message: format!(
+                "Cannot bitcast `{actual_type}` to `{wanted_type}`: wasm components differ"
+            ),
+            loc: *loc,
+        });
+    }
+
+    Ok(value.casted(wanted_type))
+}
+
 fn build_cast(
     ctx: &ModuleContext,
     value: LoInstr,
@@ -2700,14 +4965,60 @@ fn build_cast(
 ) -> Result<LoInstr, LoError> {
     let actual_type = value.get_type(ctx);
 
+    let wanted_int_type = ctx.get_distinct_inner_type(&wanted_type);
+    check_int_const_range(
+        &value,
+        wanted_int_type.as_ref().unwrap_or(&wanted_type),
+        loc,
+    )?;
+
+    // distinct types are casted as their inner types
+    let actual_inner_type = ctx.get_distinct_inner_type(&actual_type);
+    let wanted_inner_type = ctx.get_distinct_inner_type(&wanted_type);
+    if actual_inner_type.is_some() || wanted_inner_type.is_some() {
+        let value = match actual_inner_type {
+            Some(inner_type) => value.casted(inner_type),
+            None => value,
+        };
+        let inner_type = wanted_inner_type.unwrap_or(wanted_type.clone());
+        let value = build_cast(ctx, value, inner_type, loc).map_err(|_| LoError {
+            message: format!("`{actual_type}` cannot be casted to `{wanted_type}`"),
+            loc: *loc,
+        })?;
+        return Ok(value.casted(wanted_type));
+    }
+
+    // pointers are raw addresses, so the only primitive they convert from and to is `u32`
+    //   (structs wrapping a pointer are still casted by their components below)
+    let actual_is_pointer = matches!(actual_type, LoType::Pointer { .. });
+    let wanted_is_pointer = matches!(wanted_type, LoType::Pointer { .. });
+    let other_type = if actual_is_pointer {
+        &wanted_type
+    } else {
+        &actual_type
+    };
+    if actual_is_pointer != wanted_is_pointer && other_type.to_wasm_type().is_some() {
+        if *other_type != LoType::U32 {
+            return Err(LoError {
+                message: format!(
+                    "`{actual_type}` cannot be casted to `{wanted_type}`, \
+                    pointers can only be casted from and to `u32`"
+                ),
+                loc: *loc,
+            });
+        }
+
+        return Ok(value.casted(wanted_type));
+    }
+
     if wanted_type == LoType::I64 {
-        if actual_type == LoType::I32 {
+        if matches!(actual_type, LoType::I8 | LoType::I16 | LoType::I32) {
             return Ok(LoInstr::I64FromI32Signed {
                 expr: Box::new(value),
             });
         }
 
-        if actual_type == LoType::U32 {
+        if matches!(actual_type, LoType::U8 | LoType::U16 | LoType::U32) {
             return Ok(LoInstr::I64FromI32Unsigned {
                 expr: Box::new(value),
             });
@@ -2715,14 +5026,14 @@ fn build_cast(
     }
 
     if wanted_type == LoType::U64 {
-        if actual_type == LoType::I32 {
+        if matches!(actual_type, LoType::I8 | LoType::I16 | LoType::I32) {
             return Ok(LoInstr::I64FromI32Signed {
                 expr: Box::new(value),
             }
             .casted(wanted_type));
         }
 
-        if actual_type == LoType::U8 || actual_type == LoType::U32 {
+        if matches!(actual_type, LoType::U8 | LoType::U16 | LoType::U32) {
             return Ok(LoInstr::I64FromI32Unsigned {
                 expr: Box::new(value),
             }
@@ -2730,7 +5041,57 @@ fn build_cast(
         }
     }
 
-    if wanted_type == LoType::I32 {
+    if wanted_type == LoType::Bool && actual_type != LoType::Bool {
+        if let Some(value) = get_const_u32(&value) {
+            return Ok(LoInstr::U32Const {
+                value: (value != 0) as u32,
+            }
+            .casted(wanted_type));
+        }
+
+        let (kind, zero) = match actual_type.to_wasm_type() {
+            Some(WasmType::I32) => (WasmBinaryOpKind::I32_NE, LoInstr::U32Const { value: 0 }),
+            Some(WasmType::I64) => (WasmBinaryOpKind::I64_NE, LoInstr::I64Const { value: 0 }),
+            _ => {
+                return Err(LoError {
+                    message: format!("`{actual_type}` cannot be casted to `{wanted_type}`"),
+                    loc: *loc,
+                })
+            }
+        };
+
+        // any non zero value becomes `true`, so bools are always 0 or 1
+        return Ok(LoInstr::BinaryOp {
+            kind,
+            lhs: Box::new(value),
+            rhs: Box::new(zero),
+        });
+    }
+
+    if let Some((wanted_bits, wanted_signed)) = get_packed_int_info(&wanted_type) {
+        if actual_type == LoType::I64 || actual_type == LoType::U64 {
+            let value = LoInstr::I32FromI64 {
+                expr: Box::new(value),
+            };
+            return Ok(wrap_packed_int(value, wanted_type));
+        }
+
+        let fits = match actual_type {
+            LoType::Bool => true,
+            _ => match get_packed_int_info(&actual_type) {
+                Some((actual_bits, false)) => {
+                    actual_bits < wanted_bits || (actual_bits == wanted_bits && !wanted_signed)
+                }
+                Some((actual_bits, true)) => wanted_signed && actual_bits <= wanted_bits,
+                None => false,
+            },
+        };
+        if !fits && actual_type.to_wasm_type() == Some(WasmType::I32) {
+            return Ok(wrap_packed_int(value, wanted_type));
+        }
+    }
+
+    if wanted_type == LoType::I32 {
         if actual_type == LoType::I64 || actual_type == LoType::U64 {
             return Ok(LoInstr::I32FromI64 {
                 expr: Box::new(value),
@@ -2756,493 +5117,1593 @@ fn build_cast(
     if actual_wasm_types != wanted_wasm_types {
         return Err(LoError {
             message: format!("`{}` cannot be casted to `{}`", actual_type, wanted_type),
-            loc: loc.clone(),
+            loc: *loc,
         });
     }
 
     Ok(value.casted(wanted_type))
 }
 
-fn parse_fn_call_args(
-    ctx: &mut BlockContext,
-    tokens: &mut LoTokenStream,
-    args: &mut Vec<LoInstr>,
-) -> Result<(), LoError> {
-    tokens.expect(Delim, "(")?;
-    while let None = tokens.eat(Delim, ")")? {
-        args.push(parse_expr(ctx, tokens, 0)?);
+fn get_const_u32(value: &LoInstr) -> Option<u32> {
+    match value {
+        LoInstr::U32Const { value } => Some(*value),
+        LoInstr::I32Const { value } => Some(*value as u32),
+        LoInstr::Casted { expr, .. } => get_const_u32(expr),
+        _ => None,
+    }
+}
+
+// bit width and signedness of integer types narrower than 32 bits
+fn get_packed_int_info(lo_type: &LoType) -> Option<(u32, bool)> {
+    match lo_type {
+        LoType::U8 => Some((8, false)),
+        LoType::I8 => Some((8, true)),
+        LoType::U16 => Some((16, false)),
+        LoType::I16 => Some((16, true)),
+        _ => None,
+    }
+}
+
+// packed ints are kept zero or sign extended to i32, so loads, stores and comparisons agree
+fn wrap_packed_int(value: LoInstr, value_type: LoType) -> LoInstr {
+    let Some((bits, signed)) = get_packed_int_info(&value_type) else {
+        return value.casted(value_type);
+    };
+
+    if let Some(const_value) = get_const_u32(&value) {
+        let shift = 32 - bits;
+        let value = if signed {
+            (((const_value << shift) as i32) >> shift) as u32
+        } else {
+            (const_value << shift) >> shift
+        };
+        return LoInstr::U32Const { value }.casted(value_type);
+    }
+
+    if !signed {
+        return LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_AND,
+            lhs: Box::new(value),
+            rhs: Box::new(LoInstr::U32Const {
+                value: (1 << bits) - 1,
+            }),
+        }
+        .casted(value_type);
+    }
+
+    let shift = LoInstr::U32Const { value: 32 - bits };
+    LoInstr::BinaryOp {
+        kind: WasmBinaryOpKind::I32_SHR_S,
+        lhs: Box::new(LoInstr::BinaryOp {
+            kind: WasmBinaryOpKind::I32_SHL,
+            lhs: Box::new(value),
+            rhs: Box::new(shift.clone()),
+        }),
+        rhs: Box::new(shift),
+    }
+    .casted(value_type)
+}
+
+fn parse_fn_call_args(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+    args: &mut Vec<LoInstr>,
+) -> Result<(), LoError> {
+    tokens.expect(Delim, "(")?;
+    while let None = tokens.eat(Delim, ")")? {
+        args.push(parse_expr(ctx, tokens, 0)?);
+
+        if !tokens.next_is(Delim, ")")? {
+            tokens.expect(Delim, ",")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn typecheck_fn_call_args(
+    ctx: &ModuleContext,
+    params: &Vec<LoType>,
+    args: &mut Vec<LoInstr>,
+    fn_name: &str,
+    fn_call_loc: &LoLocation,
+) -> Result<(), LoError> {
+    if args.len() == params.len() {
+        for (arg, param) in args.iter_mut().zip(params) {
+            let value = core::mem::replace(arg, LoInstr::NoInstr);
+            *arg = coerce_int_literal(value, param, fn_call_loc)?;
+        }
+    }
+
+    let mut arg_types = vec![];
+    for arg in args {
+        arg_types.push(arg.get_type(ctx));
+    }
+
+    let args_match = arg_types.len() == params.len()
+        && arg_types
+            .iter()
+            .zip(params)
+            .all(|(a, p)| a.is_assignable_to(p));
+    if !args_match {
+        return Err(LoError {
+            message: format!(
+                "Invalid arguments for `{}` call: [{}], expected: [{}]",
+                fn_name,
+                ListDisplay(&arg_types),
+                ListDisplay(params)
+            ),
+            loc: *fn_call_loc,
+        });
+    }
+
+    Ok(())
+}
+
+fn parse_const_expr(
+    ctx: &mut ModuleContext,
+    tokens: &mut LoTokenStream,
+    min_bp: u32,
+) -> Result<LoInstr, LoError> {
+    let mut primary = parse_const_primary(ctx, tokens)?;
+
+    while tokens.peek().is_some() {
+        let op_symbol = tokens.peek().unwrap().clone();
+        let Some(op) = InfixOp::parse(op_symbol) else {
+            break;
+        };
+
+        if op.info.bp < min_bp {
+            break;
+        }
+
+        tokens.next(); // skip operator
+        primary = parse_const_postfix(ctx, tokens, primary, op)?;
+    }
+
+    Ok(primary)
+}
+
+fn parse_const_primary(
+    ctx: &mut ModuleContext,
+    tokens: &mut LoTokenStream,
+) -> Result<LoInstr, LoError> {
+    if tokens.next_is_any(IntLiteral)? {
+        return parse_const_int(tokens);
+    }
+
+    if let Some(value) = tokens.eat_any(CharLiteral)? {
+        return Ok(LoInstr::U32Const {
+            value: Lexer::parse_char_literal_value(&value.value),
+        }
+        .casted(LoType::U8));
+    }
+
+    if let Some(value) = tokens.eat_any(StringLiteral)? {
+        let value = Lexer::unescape_string(&value.value);
+        return parse_const_str(ctx, tokens, value);
+    }
+
+    if let Some(_) = tokens.eat(Delim, "[")? {
+        let (item_type, bytes) = parse_const_sequence(ctx, tokens)?;
+        let align = item_type.get_alignment(ctx).unwrap(); // sequence types are sized
+        let bytes_ptr = ctx.append_data(bytes, align);
+
+        return Ok(
+            LoInstr::U32Const { value: bytes_ptr }.casted(LoType::Pointer {
+                pointee: Box::new(item_type),
+                is_readonly: false,
+            }),
+        );
+    }
+
+    if let Some(_) = tokens.eat(Symbol, "true")? {
+        return Ok(LoInstr::U32Const { value: 1 }.casted(LoType::Bool));
+    }
+
+    if let Some(t) = tokens.eat(Symbol, "__externref_null")?.cloned() {
+        tokens.expect(Delim, "(")?;
+        tokens.expect(Delim, ")")?;
+        require_reference_types(ctx, &t)?;
+        return Ok(LoInstr::ExternRefNull);
+    }
+
+    if let Some(_) = tokens.eat(Symbol, "false")? {
+        return Ok(LoInstr::U32Const { value: 0 }.casted(LoType::Bool));
+    }
+
+    if let Some(token) = tokens.peek().cloned() {
+        if let Some(op) = PrefixOp::parse(token) {
+            let min_bp = op.info.get_min_bp_for_next();
+            tokens.next(); // skip operator
+
+            match op.tag {
+                PrefixOpTag::Positive => {
+                    let value = parse_const_expr(ctx, tokens, min_bp + 1)?;
+                    return cast_to_signed(value, &op.token.loc);
+                }
+                PrefixOpTag::Negative => {
+                    if tokens.next_is_any(IntLiteral)? {
+                        return parse_negated_const_int(tokens);
+                    }
+                    let value = parse_const_expr(ctx, tokens, min_bp + 1)?;
+                    return negate(value, &op.token.loc);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let value = parse_nested_symbol(tokens)?;
+
+    if let Some(const_def) = ctx.constants.borrow().get_by_name(&value.value) {
+        return Ok(const_def.value.clone());
+    }
+
+    let Some(global) = ctx.globals.get_by_name(&value.value) else {
+        return Err(LoError {
+            message: format!("Reading unknown variable in const context: {}", value.value),
+            loc: value.loc,
+        });
+    };
+
+    return Ok(LoInstr::GlobalGet {
+        global_index: global.index,
+    });
+}
+
+fn parse_const_postfix(
+    ctx: &ModuleContext,
+    tokens: &mut LoTokenStream,
+    primary: LoInstr,
+    op: InfixOp,
+) -> Result<LoInstr, LoError> {
+    let _min_bp = op.info.get_min_bp_for_next();
+
+    Ok(match op.tag {
+        InfixOpTag::Cast => {
+            let cast_type = parse_const_lo_type(ctx, tokens)?;
+
+            build_cast(ctx, primary, cast_type, &op.token.loc)?
+        }
+        _ => {
+            return Err(LoError {
+                message: format!("Unsupported operator in const context: {}", op.token.value),
+                loc: op.token.loc,
+            });
+        }
+    })
+}
+
+fn parse_const_lo_type(ctx: &ModuleContext, tokens: &mut LoTokenStream) -> Result<LoType, LoError> {
+    parse_lo_type_(ctx, &ctx.type_scope, tokens, false)
+}
+
+fn parse_lo_type(ctx: &BlockContext, tokens: &mut LoTokenStream) -> Result<LoType, LoError> {
+    if let Some(type_scope) = &ctx.block.type_scope {
+        parse_lo_type_(ctx.module, &type_scope, tokens, false)
+    } else {
+        parse_const_lo_type(ctx.module, tokens)
+    }
+}
+
+fn parse_referenced_lo_type(
+    ctx: &BlockContext,
+    tokens: &mut LoTokenStream,
+) -> Result<LoType, LoError> {
+    let type_scope = ctx.block.type_scope.as_ref();
+    let type_scope = type_scope.unwrap_or(&ctx.module.type_scope);
+    parse_lo_type_(ctx.module, type_scope, tokens, true)
+}
+
+// unions are only allowed when `is_referenced` as their values can't be held in locals
+// variants are limited to a single `i32` component so every union has the same layout
+fn parse_error_union(
+    ctx: &ModuleContext,
+    type_scope: &LoTypeScope,
+    tokens: &mut LoTokenStream,
+    (first_variant, first_loc): (LoType, LoLocation),
+) -> Result<LoType, LoError> {
+    let mut variants = vec![first_variant];
+    let mut variant_locs = vec![first_loc];
+    while tokens.eat(Operator, "|")?.is_some() {
+        variant_locs.push(tokens.peek().unwrap_or(&tokens.terminal_token).loc);
+        variants.push(parse_lo_type_(ctx, type_scope, tokens, false)?);
+    }
+
+    for (index, (variant, loc)) in variants.iter().zip(variant_locs).enumerate() {
+        if variants[..index].contains(variant) {
+            return Err(LoError {
+                message: format!("Duplicate error union variant: {variant}"),
+                loc,
+            });
+        }
+
+        let mut components = vec![];
+        variant.emit_components(ctx, &mut components);
+        if components.len() > 1 || components.iter().any(|c| *c != WasmType::I32) {
+            return Err(LoError {
+                message: format!(
+                    "Error union variant {variant} must have at most one 32-bit integer component"
+                ),
+                loc,
+            });
+        }
+
+        ctx.get_error_id(variant);
+    }
+
+    Ok(LoType::ErrorUnion { variants })
+}
+
+fn parse_lo_type_(
+    ctx: &ModuleContext,
+    type_scope: &LoTypeScope,
+    tokens: &mut LoTokenStream,
+    is_referenced: bool,
+) -> Result<LoType, LoError> {
+    let primary = parse_lo_type_primary(ctx, type_scope, tokens, is_referenced)?;
+
+    if let Some(_) = tokens.eat(Symbol, "of")? {
+        // TODO: attach as metadata and use in type equality check
+        parse_lo_type_(ctx, type_scope, tokens, false)?;
+
+        return Ok(primary);
+    }
+
+    return Ok(primary);
+}
+
+fn parse_lo_type_primary(
+    ctx: &ModuleContext,
+    type_scope: &LoTypeScope,
+    tokens: &mut LoTokenStream,
+    is_referenced: bool,
+) -> Result<LoType, LoError> {
+    if let Some(_) = tokens.eat(Delim, "(")? {
+        let type_ = parse_lo_type_(ctx, type_scope, tokens, is_referenced)?;
+        tokens.expect(Delim, ")")?;
+        return Ok(type_);
+    }
+
+    if let Some(_) = tokens.eat(Operator, "&")? {
+        if tokens.eat(Delim, "[")?.is_some() {
+            let item_type = parse_lo_type_(ctx, type_scope, tokens, false)?;
+            tokens.expect(Delim, "]")?;
+            return Ok(ctx.get_slice_type(item_type));
+        }
+
+        let is_readonly = tokens.eat(Symbol, "readonly")?.is_some();
+        let pointee = parse_lo_type_primary(ctx, type_scope, tokens, true)?;
+        return Ok(LoType::Pointer {
+            pointee: Box::new(pointee),
+            is_readonly,
+        });
+    }
+
+    if let Some(_) = tokens.eat(Operator, "*&")? {
+        let pointee = parse_lo_type_primary(ctx, type_scope, tokens, true)?;
+        return Ok(LoType::Pointer {
+            pointee: Box::new(pointee),
+            is_readonly: false,
+        });
+    }
+
+    if let Some(_) = tokens.eat(Symbol, "Result")? {
+        tokens.expect(Operator, "<")?;
+        let ok_type = parse_lo_type_(ctx, type_scope, tokens, false)?;
+        tokens.expect(Delim, ",")?;
+        let err_loc = tokens.peek().unwrap_or(&tokens.terminal_token).loc;
+        let mut err_type = parse_lo_type_(ctx, type_scope, tokens, false)?;
+        if tokens.next_is(Operator, "|")? {
+            err_type = parse_error_union(ctx, type_scope, tokens, (err_type, err_loc))?;
+        }
+        tokens.expect(Operator, ">")?;
+
+        return Ok(LoType::Result {
+            ok_type: Box::new(ok_type),
+            err_type: Box::new(err_type),
+        });
+    }
+
+    let token = parse_nested_symbol(tokens)?;
+    let lo_type = get_type_by_name(ctx, type_scope, &token)?;
+
+    if !is_referenced && ctx.is_union_type(&lo_type) {
+        return Err(LoError {
+            message: format!("Union {lo_type} can only be used through a pointer"),
+            loc: token.loc,
+        });
+    }
+
+    Ok(lo_type)
+}
+
+fn get_type_by_name(
+    ctx: &ModuleContext,
+    type_scope: &LoTypeScope,
+    token: &LoToken,
+) -> Result<LoType, LoError> {
+    match token.value.as_str() {
+        "never" => Ok(LoType::Never),
+        "void" => Ok(LoType::Void),
+        "bool" => Ok(LoType::Bool),
+        "u8" => Ok(LoType::U8),
+        "i8" => Ok(LoType::I8),
+        "u16" => Ok(LoType::U16),
+        "i16" => Ok(LoType::I16),
+        "u32" => Ok(LoType::U32),
+        "i32" => Ok(LoType::I32),
+        "f32" => Ok(LoType::F32),
+        "u64" => Ok(LoType::U64),
+        "i64" => Ok(LoType::I64),
+        "f64" => Ok(LoType::F64),
+        "externref" => {
+            require_reference_types(ctx, token)?;
+            Ok(LoType::ExternRef)
+        }
+        _ => {
+            let Some(type_) = type_scope.get(&token.value) else {
+                return Err(LoError {
+                    message: format!("Unknown type: {}", token.value),
+                    loc: token.loc,
+                });
+            };
+
+            let mut is_type_alias = true;
+
+            if let LoType::StructInstance { name } = type_ {
+                let struct_def = ctx.get_struct_def(name).unwrap(); // safe because of if let
+
+                if *name == *token.value {
+                    is_type_alias = false;
+
+                    if ctx.mode == CompilerMode::Inspect {
+                        let source_index = ctx.get_loc_module_index(&token.loc);
+                        let source_range = RangeDisplay(&token.loc);
+                        let target_index = ctx.get_loc_module_index(&struct_def.loc);
+                        let target_range = RangeDisplay(&struct_def.loc);
+
+                        let fields = ListDisplay(&struct_def.fields);
+                        let size = struct_def.byte_length;
+                        let align = type_.get_alignment(ctx).map_err(|message| LoError {
+                            message,
+                            loc: token.loc,
+                        })?;
+                        let doc = DocDisplay(&struct_def.doc);
+
+                        stdout_writeln(format!(
+                            "{{ \"type\": \"info\", \
+                                \"link\": \"{target_index}/{target_range}\", \
+                                \"hover\": \"struct {name} {{ {fields} }}\\n// size {size}, align {align}{doc}\", \
+                                \"loc\": \"{source_index}/{source_range}\" }}, ",
+                        ));
+                    }
+                }
+            }
+
+            if ctx.mode == CompilerMode::Inspect && is_type_alias {
+                let source_index = ctx.get_loc_module_index(&token.loc);
+                let source_range = RangeDisplay(&token.loc);
+
+                let type_name = &token.value;
+
+                // TODO: add links
+                stdout_writeln(format!(
+                    "{{ \"type\": \"info\", \
+                        \"hover\": \"type {type_name} = {type_}\", \
+                        \"loc\": \"{source_index}/{source_range}\" }}, ",
+                ));
+            }
+
+            return Ok(type_.clone());
+        }
+    }
+}
+
+fn parse_const_str(
+    ctx: &ModuleContext,
+    tokens: &mut LoTokenStream,
+    mut value: String,
+) -> Result<LoInstr, LoError> {
+    if ctx.memories.len() == 0 && ctx.mode != CompilerMode::Inspect {
+        return Err(LoError {
+            message: format!("Cannot use strings with no memories defined"),
+            loc: *tokens.loc(),
+        });
+    }
+
+    let is_null_terminated = tokens.eat(IntLiteral, "0")?.is_some();
+    if is_null_terminated {
+        value.push('\0');
+        let string_ptr = get_pooled_string_ptr(ctx, value);
+        return Ok(
+            LoInstr::U32Const { value: string_ptr }.casted(LoType::Pointer {
+                pointee: Box::new(LoType::U8),
+                is_readonly: false,
+            }),
+        );
+    }
+
+    Ok(compile_str_const(ctx, value))
+}
+
+fn compile_str_const(ctx: &ModuleContext, value: String) -> LoInstr {
+    let string_len = value.as_bytes().len() as u32;
+    let string_ptr = get_pooled_string_ptr(ctx, value);
+
+    LoInstr::MultiValueEmit {
+        values: vec![
+            LoInstr::U32Const { value: string_ptr },
+            LoInstr::U32Const { value: string_len },
+        ],
+    }
+    .casted(LoType::StructInstance {
+        name: Atom::intern("str"),
+    })
+}
+
+fn get_pooled_string_ptr(ctx: &ModuleContext, value: String) -> u32 {
+    let string_ptr = ctx.string_pool.borrow().get(&value).cloned();
+    match string_ptr {
+        Some(string_ptr) => string_ptr,
+        None => {
+            let new_string_ptr = ctx.append_string(&value);
+            ctx.string_pool.borrow_mut().insert(value, new_string_ptr);
+            new_string_ptr
+        }
+    }
+}
+
+// TODO: support sequences of any type
+fn parse_const_sequence(
+    ctx: &ModuleContext,
+    tokens: &mut LoTokenStream,
+) -> Result<(LoType, Vec<u8>), LoError> {
+    // `[<byte>; <count>]` repeats the byte
+    if tokens.next_is_any(IntLiteral)? {
+        let byte = parse_u8_literal(tokens.expect_any(IntLiteral)?)?;
+        tokens.eat(Symbol, "u8")?;
+        tokens.expect(Delim, ";")?;
+        let count = parse_u32_literal(tokens.expect_any(IntLiteral)?)?;
+        tokens.expect(Delim, "]")?;
+
+        return Ok((LoType::U8, vec![byte; count as usize]));
+    }
+
+    let item_type = parse_const_lo_type(ctx, tokens)?;
+    if item_type != LoType::U8
+        && item_type
+            != (LoType::StructInstance {
+                name: Atom::intern("str"),
+            })
+    {
+        return Err(LoError {
+            message: format!("Unsupported sequence element type: {}", item_type),
+            loc: *tokens.loc(),
+        });
+    }
+
+    tokens.expect(Delim, "]")?;
+
+    let mut bytes = vec![];
+
+    tokens.expect(Delim, "[")?;
+    while let None = tokens.eat(Delim, "]")? {
+        if item_type == LoType::U8 {
+            let byte = tokens.expect_any(IntLiteral)?;
+            bytes.push(parse_u8_literal(byte)?);
+            tokens.eat(Symbol, "u8")?;
+        } else if item_type
+            == (LoType::StructInstance {
+                name: Atom::intern("str"),
+            })
+        {
+            let value = tokens.expect_any(StringLiteral)?;
+            let value = Lexer::unescape_string(&value.value);
+            let len = value.len();
+            let ptr = ctx.append_data(value.into_bytes(), 1);
+
+            bytes.extend_from_slice(&ptr.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+        } else {
+            unreachable!()
+        }
 
-        if !tokens.next_is(Delim, ")")? {
+        if !tokens.next_is(Delim, "]")? {
             tokens.expect(Delim, ",")?;
         }
     }
 
-    Ok(())
+    return Ok((item_type, bytes));
 }
 
-fn typecheck_fn_call_args(
-    ctx: &ModuleContext,
-    params: &Vec<LoType>,
-    args: &Vec<LoInstr>,
-    fn_name: &str,
-    fn_call_loc: &LoLocation,
-) -> Result<(), LoError> {
-    let mut arg_types = vec![];
-    for arg in args {
-        arg_types.push(arg.get_type(ctx));
-    }
-
-    if arg_types != *params {
-        return Err(LoError {
-            message: format!(
-                "Invalid arguments for `{}` call: [{}], expected: [{}]",
-                fn_name,
-                ListDisplay(&arg_types),
-                ListDisplay(params)
-            ),
-            loc: fn_call_loc.clone(),
-        });
+fn parse_nested_symbol(tokens: &mut LoTokenStream) -> Result<LoToken, LoError> {
+    let mut nested_symbol = tokens.expect_any(Symbol)?.clone();
+    while let Some(_) = tokens.eat(Operator, "::")? {
+        let path_part = tokens.expect_any(Symbol)?;
+        nested_symbol.value =
+            Atom::intern(&format!("{}::{}", nested_symbol.value, path_part.value));
+        nested_symbol.loc.end_offset = path_part.loc.end_offset;
     }
-
-    Ok(())
+    Ok(nested_symbol)
 }
 
-fn parse_const_expr(
-    ctx: &mut ModuleContext,
-    tokens: &mut LoTokenStream,
-    min_bp: u32,
-) -> Result<LoInstr, LoError> {
-    let mut primary = parse_const_primary(ctx, tokens)?;
+fn extract_method_receiver_and_name(
+    ctx: &ModuleContext,
+    token: &LoToken,
+) -> Result<(Option<LoType>, String), LoError> {
+    Ok(match token.value.rsplitn(2, "::").collect::<Vec<_>>()[..] {
+        [method_name, receiver_name] => {
+            let mut token = LoToken {
+                type_: LoTokenType::Symbol,
+                value: Atom::intern(receiver_name),
+                loc: token.loc,
+            };
 
-    while tokens.peek().is_some() {
-        let op_symbol = tokens.peek().unwrap().clone();
-        let Some(op) = InfixOp::parse(op_symbol) else {
-            break;
-        };
+            // TODO: correct `end_offset` info is lost during creation of nested_symbol
+            token.loc.end_offset = token.loc.offset;
 
-        if op.info.bp < min_bp {
-            break;
+            (
+                Some(get_type_by_name(ctx, &ctx.type_scope, &token)?),
+                String::from(method_name),
+            )
         }
+        [fn_name] => (None, String::from(fn_name)),
+        _ => unreachable!(),
+    })
+}
 
-        tokens.next(); // skip operator
-        primary = parse_const_postfix(ctx, tokens, primary, op)?;
+fn parse_const_int(tokens: &mut LoTokenStream) -> Result<LoInstr, LoError> {
+    let int_literal = tokens.expect_any(IntLiteral)?.clone();
+    let (literal_type, has_suffix) = parse_int_literal_suffix(tokens)?;
+
+    // bare literals are range checked once their type is known, see `coerce_int_literal`
+    if !has_suffix {
+        let value = Lexer::parse_int_literal_value(&int_literal.value);
+        return Ok(build_bare_int_literal(value as i128, false).unwrap());
     }
 
-    Ok(primary)
+    let value = parse_int_literal(&int_literal, &literal_type)?;
+
+    Ok(match literal_type {
+        // wrapped to be told apart from bare literals
+        LoType::U32 => LoInstr::U32Const {
+            value: value as u32,
+        }
+        .casted(LoType::U32),
+        LoType::I32 => LoInstr::I32Const {
+            value: value as i32,
+        }
+        .casted(LoType::I32),
+        LoType::U64 => LoInstr::U64Const { value }.casted(LoType::U64),
+        LoType::I64 => LoInstr::I64Const {
+            value: value as i64,
+        }
+        .casted(LoType::I64),
+        LoType::F32 => LoInstr::F32Const {
+            value: value as f32,
+        },
+        LoType::F64 => LoInstr::F64Const {
+            value: value as f64,
+        },
+        // values of smaller ints are held in i32 like everywhere else
+        _ => LoInstr::U32Const {
+            value: value as u32,
+        }
+        .casted(literal_type),
+    })
 }
 
-fn parse_const_primary(
-    ctx: &mut ModuleContext,
-    tokens: &mut LoTokenStream,
-) -> Result<LoInstr, LoError> {
-    if tokens.next_is_any(IntLiteral)? {
-        return parse_const_int(tokens);
-    }
+// `-<literal>` is folded as a whole, so the minimum of signed types can be written
+fn parse_negated_const_int(tokens: &mut LoTokenStream) -> Result<LoInstr, LoError> {
+    let int_literal = tokens.expect_any(IntLiteral)?.clone();
+    let (literal_type, has_suffix) = parse_int_literal_suffix(tokens)?;
+
+    // negated unsigned literals are signed, `-5u64` is an i64,
+    //   bare ones reaching the range check don't fit into i32 already
+    let value_type = match literal_type {
+        _ if !has_suffix => LoType::I64,
+        LoType::U32 => LoType::I32,
+        LoType::U64 => LoType::I64,
+        _ => literal_type,
+    };
 
-    if let Some(value) = tokens.eat_any(CharLiteral)? {
-        return Ok(LoInstr::U32Const {
-            value: Lexer::parse_char_literal_value(&value.value),
+    let value = -(Lexer::parse_int_literal_value(&int_literal.value) as i128);
+    if !has_suffix {
+        if let Some(value) = build_bare_int_literal(value, true) {
+            return Ok(value);
         }
-        .casted(LoType::U8));
     }
 
-    if let Some(value) = tokens.eat_any(StringLiteral)? {
-        let value = Lexer::unescape_string(&value.value);
-        return parse_const_str(ctx, tokens, value);
+    if let Some((min, max)) = get_int_range(&value_type) {
+        if value < min {
+            return Err(LoError {
+                message: format!(
+                    "Integer literal -{} is out of range for {value_type}, allowed range is {min}..={max}",
+                    int_literal.value
+                ),
+                loc: int_literal.loc,
+            });
+        }
     }
 
-    if let Some(_) = tokens.eat(Delim, "[")? {
-        let (item_type, bytes) = parse_const_sequence(ctx, tokens)?;
-        let bytes_ptr = ctx.append_data(bytes);
+    Ok(match value_type {
+        LoType::I32 => LoInstr::I32Const {
+            value: value as i32,
+        }
+        .casted(LoType::I32),
+        LoType::I64 => LoInstr::I64Const {
+            value: value as i64,
+        }
+        .casted(LoType::I64),
+        LoType::F32 => LoInstr::F32Const {
+            value: value as f32,
+        },
+        LoType::F64 => LoInstr::F64Const {
+            value: value as f64,
+        },
+        // smaller ints are held sign extended
+        _ => LoInstr::I32Const {
+            value: value as i32,
+        }
+        .casted(value_type),
+    })
+}
 
-        return Ok(
-            LoInstr::U32Const { value: bytes_ptr }.casted(LoType::Pointer(Box::new(item_type)))
-        );
+// returns literal's type and whether it was explicitly specified
+fn parse_int_literal_suffix(tokens: &mut LoTokenStream) -> Result<(LoType, bool), LoError> {
+    for suffix in INT_LITERAL_SUFFIXES {
+        if tokens.eat(Symbol, suffix)?.is_some() {
+            let literal_type = match suffix {
+                "u8" => LoType::U8,
+                "i8" => LoType::I8,
+                "u16" => LoType::U16,
+                "i16" => LoType::I16,
+                "i32" => LoType::I32,
+                "f32" => LoType::F32,
+                "u64" => LoType::U64,
+                "i64" => LoType::I64,
+                "f64" => LoType::F64,
+                _ => LoType::U32,
+            };
+            return Ok((literal_type, true));
+        }
     }
 
-    if let Some(_) = tokens.eat(Symbol, "true")? {
-        return Ok(LoInstr::U32Const { value: 1 }.casted(LoType::Bool));
-    }
+    Ok((LoType::U32, false))
+}
 
-    if let Some(_) = tokens.eat(Symbol, "false")? {
-        return Ok(LoInstr::U32Const { value: 0 }.casted(LoType::Bool));
-    }
+#[derive(Clone, Copy, PartialEq)]
+enum AllocatorRole {
+    Alloc,
+    Free,
+}
 
-    if let Some(token) = tokens.peek().cloned() {
-        if let Some(op) = PrefixOp::parse(token) {
-            let min_bp = op.info.get_min_bp_for_next();
-            tokens.next(); // skip operator
+#[derive(Default)]
+struct TopLevelAttributes {
+    struct_layout: Option<StructLayout>,
+    allocator_role: Option<AllocatorRole>,
+    is_start: bool,
+    derives: Vec<LoToken>,
+}
 
-            match op.tag {
-                PrefixOpTag::Positive => {
-                    let value = parse_const_expr(ctx, tokens, min_bp + 1)?;
-                    return cast_to_signed(value, &op.token.loc);
-                }
-                PrefixOpTag::Negative => {
-                    let value = parse_const_expr(ctx, tokens, min_bp + 1)?;
-                    return negate(value, &op.token.loc);
+// `@packed`, `@align(<align>)` or `@derive(<name>, ...)` before a struct definition,
+//   `@alloc`, `@free` or `@start` before a fn definition
+fn parse_top_level_attributes(tokens: &mut LoTokenStream) -> Result<TopLevelAttributes, LoError> {
+    let mut attributes = TopLevelAttributes::default();
+    while tokens.eat(Operator, "@")?.is_some() {
+        let attribute = tokens.expect_any(Symbol)?.clone();
+
+        let role = match attribute.value.as_str() {
+            "alloc" => Some(AllocatorRole::Alloc),
+            "free" => Some(AllocatorRole::Free),
+            _ => None,
+        };
+        if let Some(role) = role {
+            if attributes.allocator_role.is_some() {
+                return Err(LoError {
+                    message: String::from("Allocator role is already specified"),
+                    loc: attribute.loc,
+                });
+            }
+            attributes.allocator_role = Some(role);
+            continue;
+        }
+
+        if attribute.value == "start" {
+            attributes.is_start = true;
+            continue;
+        }
+
+        if attribute.value == "derive" {
+            tokens.expect(Delim, "(")?;
+            while tokens.eat(Delim, ")")?.is_none() {
+                attributes.derives.push(tokens.expect_any(Symbol)?.clone());
+                if !tokens.next_is(Delim, ")")? {
+                    tokens.expect(Delim, ",")?;
                 }
-                _ => {}
             }
+            continue;
         }
-    }
 
-    let value = parse_nested_symbol(tokens)?;
+        let layout = &mut attributes.struct_layout;
+        if layout.is_some() {
+            return Err(LoError {
+                message: String::from("Struct layout is already specified"),
+                loc: attribute.loc,
+            });
+        }
 
-    if let Some(const_def) = ctx.constants.borrow().get(&value.value) {
-        return Ok(const_def.value.clone());
+        match attribute.value.as_str() {
+            "packed" => *layout = Some(StructLayout::Packed),
+            "align" => {
+                tokens.expect(Delim, "(")?;
+                let align_token = tokens.expect_any(IntLiteral)?.clone();
+                tokens.expect(Delim, ")")?;
+
+                let align = parse_u32_literal(&align_token)?;
+                if !align.is_power_of_two() {
+                    return Err(LoError {
+                        message: format!("Struct alignment must be a power of two, got {align}"),
+                        loc: align_token.loc,
+                    });
+                }
+                *layout = Some(StructLayout::Aligned { align });
+            }
+            unknown => {
+                return Err(LoError {
+                    message: format!("Unknown attribute: @{unknown}"),
+                    loc: attribute.loc,
+                });
+            }
+        }
     }
 
-    let Some(global) = ctx.globals.get(&value.value) else {
+    Ok(attributes)
+}
+
+fn parse_struct_def(
+    ctx: &mut ModuleContext,
+    tokens: &mut LoTokenStream,
+    struct_name: LoToken,
+    layout: StructLayout,
+    doc: Option<String>,
+    is_union: bool,
+) -> Result<(), LoError> {
+    if let Some(_) = ctx.type_scope.get(&struct_name.value) {
         return Err(LoError {
-            message: format!("Reading unknown variable in const context: {}", value.value),
-            loc: value.loc,
+            message: format!("Cannot redefine type {}", struct_name.value),
+            loc: struct_name.loc,
         });
-    };
+    }
 
-    return Ok(LoInstr::GlobalGet {
-        global_index: global.index,
+    // declare not fully defined struct to use in self-references
+    ctx.insert_struct_def(StructDef {
+        name: String::from(struct_name.value),
+        fields: vec![],
+        layout,
+        byte_length: 0,
+        fully_defined: false,
+        doc,
+        loc: struct_name.loc,
     });
-}
 
-fn parse_const_postfix(
-    ctx: &ModuleContext,
-    tokens: &mut LoTokenStream,
-    primary: LoInstr,
-    op: InfixOp,
-) -> Result<LoInstr, LoError> {
-    let _min_bp = op.info.get_min_bp_for_next();
+    let name = Atom::intern(&struct_name.value);
+    ctx.type_scope.insert(name, LoType::StructInstance { name });
 
-    Ok(match op.tag {
-        InfixOpTag::Cast => {
-            let cast_type = parse_const_lo_type(ctx, tokens)?;
+    let mut field_index = 0;
+    let mut byte_offset = 0;
+    let mut alignment = layout.min_alignment();
+    let mut struct_fields = Vec::<StructField>::new();
 
-            build_cast(ctx, primary, cast_type, &op.token.loc)?
+    tokens.expect(Delim, "{")?;
+    while let None = tokens.eat(Delim, "}")? {
+        let is_private = tokens.eat(Symbol, "private")?.is_some();
+        let field_name = tokens.expect_any(Symbol)?.clone();
+        tokens.expect(Operator, ":")?;
+        let field_type_loc = *tokens.loc();
+        let field_type = if tokens.eat(Symbol, "struct")?.is_some() {
+            // inline struct types are defined as `<struct name>::<field name>`
+            let nested_name = LoToken {
+                value: Atom::intern(&format!("{}::{}", struct_name.value, field_name.value)),
+                ..field_name.clone()
+            };
+            parse_struct_def(ctx, tokens, nested_name.clone(), layout, None, false)?;
+            LoType::StructInstance {
+                name: Atom::intern(&nested_name.value),
+            }
+        } else {
+            parse_const_lo_type(ctx, tokens)?
+        };
+        if !tokens.next_is(Delim, "}")? {
+            tokens.expect(Delim, ",")?;
         }
-        _ => {
+
+        if let Some(recursive_struct) = find_by_value_partial_struct(ctx, &field_type) {
             return Err(LoError {
-                message: format!("Unsupported operator in const context: {}", op.token.value),
-                loc: op.token.loc,
+                message: format!(
+                    "Recursive type needs indirection: field `{}` of struct {} contains {} by value",
+                    field_name.value, struct_name.value, recursive_struct,
+                ),
+                loc: field_name.loc,
             });
         }
-    })
-}
-
-fn parse_const_lo_type(ctx: &ModuleContext, tokens: &mut LoTokenStream) -> Result<LoType, LoError> {
-    parse_lo_type_(ctx, &ctx.type_scope, tokens, false)
-}
-
-fn parse_lo_type(ctx: &BlockContext, tokens: &mut LoTokenStream) -> Result<LoType, LoError> {
-    if let Some(type_scope) = &ctx.block.type_scope {
-        parse_lo_type_(ctx.module, &type_scope, tokens, false)
-    } else {
-        parse_const_lo_type(ctx.module, tokens)
-    }
-}
 
-fn parse_lo_type_(
-    ctx: &ModuleContext,
-    type_scope: &LoTypeScope,
-    tokens: &mut LoTokenStream,
-    is_referenced: bool,
-) -> Result<LoType, LoError> {
-    let primary = parse_lo_type_primary(ctx, type_scope, tokens, is_referenced)?;
+        if struct_fields
+            .iter()
+            .find(|f| f.name == field_name.value)
+            .is_some()
+        {
+            return Err(LoError {
+                message: format!(
+                    "Found duplicate struct field name: '{}' of struct {}",
+                    field_name.value, struct_name.value,
+                ),
+                loc: field_name.loc,
+            });
+        }
 
-    if let Some(_) = tokens.eat(Symbol, "of")? {
-        // TODO: attach as metadata and use in type equality check
-        parse_lo_type_(ctx, type_scope, tokens, is_referenced)?;
+        let map_err = |message| LoError {
+            message,
+            loc: field_type_loc,
+        };
+        let mut stats = EmitComponentStats::default();
+        field_type
+            .emit_sized_component_stats(ctx, &mut stats, &mut vec![])
+            .map_err(map_err)?;
+        let field_alignment = field_type.get_alignment(ctx).map_err(map_err)?;
+
+        // all fields of a union start at 0, `byte_offset` tracks the end of the largest one
+        let field_offset = if is_union {
+            0
+        } else {
+            layout.field_offset(byte_offset, field_alignment)
+        };
+        alignment = alignment.max(field_alignment);
+
+        struct_fields.push(StructField {
+            name: String::from(field_name.value),
+            is_private,
+            value_type: field_type,
+            field_index,
+            byte_offset: field_offset,
+            loc: field_name.loc,
+        });
 
-        return Ok(primary);
+        if !is_union {
+            field_index += stats.count;
+        }
+        byte_offset = byte_offset.max(field_offset + stats.byte_length);
     }
 
-    return Ok(primary);
+    let struct_index = ctx.struct_def_indices.borrow()[name]; // safe, declared above
+    let mut struct_defs = ctx.struct_defs.borrow_mut();
+    let struct_def = Rc::make_mut(&mut struct_defs[struct_index]);
+    struct_def.fields.append(&mut struct_fields);
+    struct_def.byte_length = layout.field_offset(byte_offset, alignment);
+    struct_def.fully_defined = true;
+    drop(struct_defs);
+
+    Ok(())
 }
 
-fn parse_lo_type_primary(
-    ctx: &ModuleContext,
-    type_scope: &LoTypeScope,
-    tokens: &mut LoTokenStream,
-    is_referenced: bool,
-) -> Result<LoType, LoError> {
-    if let Some(_) = tokens.eat(Delim, "(")? {
-        let type_ = parse_lo_type_(ctx, type_scope, tokens, false)?;
-        tokens.expect(Delim, ")")?;
-        return Ok(type_);
+// only the struct being defined is partially defined, so finding one means it contains itself,
+//   pointers don't count as their size doesn't depend on the pointee
+fn find_by_value_partial_struct(ctx: &ModuleContext, lo_type: &LoType) -> Option<Atom> {
+    match lo_type {
+        LoType::StructInstance { name } => {
+            let struct_def = ctx.get_struct_def(name)?;
+            if struct_def.fully_defined {
+                return None;
+            }
+            Some(*name)
+        }
+        LoType::Tuple(types) => types
+            .iter()
+            .find_map(|lo_type| find_by_value_partial_struct(ctx, lo_type)),
+        LoType::Result { ok_type, err_type } => find_by_value_partial_struct(ctx, ok_type)
+            .or_else(|| find_by_value_partial_struct(ctx, err_type)),
+        _ => None,
     }
+}
 
-    if let Some(_) = tokens.eat(Operator, "&")? {
-        let pointee = parse_lo_type_primary(ctx, type_scope, tokens, true)?;
-        return Ok(LoType::Pointer(Box::new(pointee)));
-    }
+// `type <name> = distinct <T>` is a struct of `value: T`,
+//   it only converts to and from `T` with explicit casts
+fn define_distinct_type(
+    ctx: &mut ModuleContext,
+    type_name: LoToken,
+    inner_type: LoType,
+    inner_type_loc: LoLocation,
+    doc: Option<String>,
+) -> Result<(), LoError> {
+    let map_err = |message| LoError {
+        message,
+        loc: inner_type_loc,
+    };
+    let mut stats = EmitComponentStats::default();
+    inner_type
+        .emit_sized_component_stats(ctx, &mut stats, &mut vec![])
+        .map_err(map_err)?;
+    let alignment = inner_type.get_alignment(ctx).map_err(map_err)?;
+
+    let layout = StructLayout::default();
+    ctx.insert_struct_def(StructDef {
+        name: String::from(type_name.value),
+        fields: vec![StructField {
+            name: String::from("value"),
+            is_private: false,
+            value_type: inner_type.clone(),
+            field_index: 0,
+            byte_offset: 0,
+            loc: type_name.loc,
+        }],
+        layout,
+        byte_length: layout.field_offset(stats.byte_length, alignment),
+        fully_defined: true,
+        doc,
+        loc: type_name.loc,
+    });
 
-    if let Some(_) = tokens.eat(Operator, "*&")? {
-        let pointee = parse_lo_type_primary(ctx, type_scope, tokens, true)?;
-        return Ok(LoType::Pointer(Box::new(pointee)));
-    }
+    let name = Atom::intern(&type_name.value);
+    ctx.type_scope.insert(name, LoType::StructInstance { name });
+    ctx.distinct_types.insert(name, inner_type);
 
-    if let Some(_) = tokens.eat(Symbol, "Result")? {
-        tokens.expect(Operator, "<")?;
-        let ok_type = parse_lo_type_(ctx, type_scope, tokens, false)?;
-        tokens.expect(Delim, ",")?;
-        let err_type = parse_lo_type_(ctx, type_scope, tokens, false)?;
-        tokens.expect(Operator, ">")?;
+    Ok(())
+}
 
-        return Ok(LoType::Result {
-            ok_type: Box::new(ok_type),
-            err_type: Box::new(err_type),
+fn define_const(
+    ctx: &mut ModuleContext,
+    const_name: LoToken,
+    const_value: LoInstr,
+    doc: Option<String>,
+) -> Result<(), LoError> {
+    if ctx
+        .constants
+        .borrow()
+        .get_by_name(&const_name.value)
+        .is_some()
+    {
+        return Err(LoError {
+            message: format!("Duplicate constant: {}", const_name.value),
+            loc: const_name.loc,
         });
     }
 
-    let token = parse_nested_symbol(tokens)?;
-    get_type_by_name(ctx, type_scope, &token, is_referenced)
+    if ctx.mode == CompilerMode::Inspect {
+        let source_index = ctx.get_loc_module_index(&const_name.loc);
+        let source_range = RangeDisplay(&const_name.loc);
+
+        let const_name = &const_name.value;
+        let const_type = const_value.get_type(ctx);
+        let doc = DocDisplay(&doc);
+
+        stdout_writeln(format!(
+            "{{ \"type\": \"info\", \
+                \"hover\": \"const {const_name}: {const_type}{doc}\", \
+                \"loc\": \"{source_index}/{source_range}\" }}, ",
+        ));
+    }
+
+    ctx.constants.borrow_mut().insert(
+        Atom::intern(&const_name.value),
+        ConstDef {
+            value: const_value,
+            doc,
+            loc: const_name.loc,
+        },
+    );
+
+    Ok(())
 }
 
-fn get_type_by_name(
-    ctx: &ModuleContext,
-    type_scope: &LoTypeScope,
-    token: &LoToken,
-    is_referenced: bool,
-) -> Result<LoType, LoError> {
-    match token.value.as_str() {
-        "never" => Ok(LoType::Never),
-        "void" => Ok(LoType::Void),
-        "bool" => Ok(LoType::Bool),
-        "u8" => Ok(LoType::U8),
-        "i8" => Ok(LoType::I8),
-        "u16" => Ok(LoType::U16),
-        "i16" => Ok(LoType::I16),
-        "u32" => Ok(LoType::U32),
-        "i32" => Ok(LoType::I32),
-        "f32" => Ok(LoType::F32),
-        "u64" => Ok(LoType::U64),
-        "i64" => Ok(LoType::I64),
-        "f64" => Ok(LoType::F64),
-        _ => {
-            let Some(type_) = type_scope.get(&token.value) else {
-                return Err(LoError {
-                    message: format!("Unknown type: {}", token.value),
-                    loc: token.loc.clone(),
-                });
-            };
+// `const group <name> { A, B = 5, C }` defines `<name>::A = 0`, `<name>::B = 5`, `<name>::C = 6`,
+//   `const flags` starts at 1 and continues with the next power of two instead,
+//   members of a `flags` type declaration are casted to that type
+fn parse_const_group(
+    ctx: &mut ModuleContext,
+    tokens: &mut LoTokenStream,
+    group_name: LoToken,
+    is_flags: bool,
+    member_type: Option<LoType>,
+) -> Result<(), LoError> {
+    let group_kind = if member_type.is_some() {
+        "flags"
+    } else {
+        "const group"
+    };
 
-            let mut is_type_alias = true;
+    let mut next_value = Some(if is_flags { 1 } else { 0 });
+    let mut members = Vec::<(String, u32)>::new();
 
-            if let LoType::StructInstance { name } = type_ {
-                let struct_def = ctx.get_struct_def(name).unwrap(); // safe because of if let
-                if !struct_def.fully_defined && !is_referenced {
+    tokens.expect(Delim, "{")?;
+    while tokens.eat(Delim, "}")?.is_none() {
+        let doc = tokens.get_doc_comment();
+        let mut member = tokens.expect_any(Symbol)?.clone();
+
+        let value = if tokens.eat(Operator, "=")?.is_some() {
+            let value_loc = *tokens.loc();
+            let value = parse_const_expr(ctx, tokens, 0)?;
+            let value_type = value.get_type(ctx);
+            match get_const_u32(&value) {
+                Some(value) if value_type == LoType::U32 => value,
+                _ => {
                     return Err(LoError {
-                        message: format!("Cannot use partially defined struct: {name}"),
-                        loc: token.loc.clone(),
+                        message: if member_type.is_some() {
+                            format!("Flags values must be u32 constants, got {value_type}")
+                        } else {
+                            format!("Const group values must be u32 constants, got {value_type}")
+                        },
+                        loc: value_loc,
                     });
                 }
+            }
+        } else {
+            let Some(value) = next_value else {
+                return Err(LoError {
+                    message: format!(
+                        "Value of {}::{} overflows u32",
+                        group_name.value, member.value
+                    ),
+                    loc: member.loc,
+                });
+            };
+            value
+        };
 
-                if *name == token.value {
-                    is_type_alias = false;
+        if let Some((other, _)) = members.iter().find(|(_, v)| *v == value) {
+            return Err(LoError {
+                message: format!(
+                    "Duplicate value in {group_kind} {}: {} and {} are both {value}",
+                    group_name.value, other, member.value
+                ),
+                loc: member.loc,
+            });
+        }
+        members.push((String::from(member.value), value));
 
-                    if ctx.mode == CompilerMode::Inspect {
-                        let source_index = ctx.get_loc_module_index(&token.loc);
-                        let source_range = RangeDisplay(&token.loc);
-                        let target_index = ctx.get_loc_module_index(&struct_def.loc);
-                        let target_range = RangeDisplay(&struct_def.loc);
+        next_value = if !is_flags {
+            value.checked_add(1)
+        } else if value == 0 {
+            Some(1)
+        } else {
+            1u32.checked_shl(32 - value.leading_zeros())
+        };
 
-                        let fields = ListDisplay(&struct_def.fields);
+        member.value = Atom::intern(&format!("{}::{}", group_name.value, member.value));
+        let mut const_value = LoInstr::U32Const { value };
+        if let Some(member_type) = &member_type {
+            const_value = const_value.casted(member_type.clone());
+        }
+        define_const(ctx, member, const_value, doc)?;
 
-                        stdout_writeln(format!(
-                            "{{ \"type\": \"info\", \
-                                \"link\": \"{target_index}/{target_range}\", \
-                                \"hover\": \"struct {name} {{ {fields} }}\", \
-                                \"loc\": \"{source_index}/{source_range}\" }}, ",
-                        ));
+        if !tokens.next_is(Delim, "}")? {
+            tokens.expect(Delim, ",")?;
+        }
+    }
+
+    Ok(())
+}
+
+// generates fns for `@derive(...)` as source and parses it like the rest of the file,
+//   tokens get the location of the derive name so errors and inspections point there
+fn derive_struct_fns(
+    ctx: &mut ModuleContext,
+    struct_name: &str,
+    derive: &LoToken,
+) -> Result<(), LoError> {
+    let struct_def = ctx.get_struct_def(struct_name).unwrap(); // safe, just defined
+
+    let unsupported_field = |field: &StructField| LoError {
+        message: format!(
+            "Cannot derive {} for {struct_name}, field `{}` has unsupported type {}",
+            derive.value, field.name, field.value_type
+        ),
+        loc: derive.loc,
+    };
+
+    let mut source = String::new();
+    match derive.value.as_str() {
+        "Equals" => {
+            let mut cond = String::from("true");
+            for field in &struct_def.fields {
+                let name = &field.name;
+                match &field.value_type {
+                    LoType::StructInstance { name: type_name } if type_name != "str" => {
+                        cond += &format!(" && self.{name}.equals(other.{name})")
+                    }
+                    LoType::Tuple(_) | LoType::Result { .. } | LoType::ExternRef => {
+                        return Err(unsupported_field(field));
                     }
+                    _ => cond += &format!(" && (self.{name} == other.{name})"),
                 }
             }
 
-            if ctx.mode == CompilerMode::Inspect && is_type_alias {
-                let source_index = ctx.get_loc_module_index(&token.loc);
-                let source_range = RangeDisplay(&token.loc);
-
-                let type_name = &token.value;
+            // structs without fields are always equal
+            let other = if struct_def.fields.is_empty() {
+                "_other"
+            } else {
+                "other"
+            };
+            source += &format!(
+                "fn {struct_name}::equals(self, {other}: {struct_name}): bool {{ return {cond}; }};"
+            );
+        }
+        // `to_string` and `write` follow the conversions of `u32` and `u64` in std
+        "ToStr" => {
+            let mut body = format!("output.push_str(\"{struct_name} {{\");");
+            for (field, index) in struct_def.fields.iter().zip(0..) {
+                let name = &field.name;
+                let separator = if index == 0 { " " } else { ", " };
+                body += &format!("output.push_str(\"{separator}{name}: \");");
+                body += &match &field.value_type {
+                    LoType::Bool => format!(
+                        "if self.{name} {{ output.push_str(\"true\"); }} \
+                            else {{ output.push_str(\"false\"); }};"
+                    ),
+                    LoType::U8 | LoType::U16 | LoType::U32 | LoType::Pointer { .. } => {
+                        format!("output = u32::write(self.{name} as u32, output);")
+                    }
+                    LoType::U64 => format!("output = u64::write(self.{name}, output);"),
+                    LoType::StructInstance { name: type_name } if type_name == "str" => format!(
+                        "output.push('\"'); output.push_str(self.{name}); output.push('\"');"
+                    ),
+                    LoType::StructInstance { name: type_name } => {
+                        format!("output = {type_name}::write(self.{name}, output);")
+                    }
+                    _ => return Err(unsupported_field(field)),
+                };
+            }
+            if struct_def.fields.is_empty() {
+                body += "output.push_str(\"}\");";
+            } else {
+                body += "output.push_str(\" }\");";
+            }
 
-                // TODO: add links
-                stdout_writeln(format!(
-                    "{{ \"type\": \"info\", \
-                        \"hover\": \"type {type_name} = {type_}\", \
-                        \"loc\": \"{source_index}/{source_range}\" }}, ",
-                ));
+            source += &format!(
+                "fn {struct_name}::to_string(self): String {{ \
+                    return {struct_name}::write(self, String::new(32)); }};\
+                fn {struct_name}::write(self, output: String): String {{ {body} return output; }};"
+            );
+        }
+        "Default" => {
+            let mut fields = String::new();
+            for field in &struct_def.fields {
+                let name = &field.name;
+                let value = match &field.value_type {
+                    LoType::Bool => String::from("false"),
+                    LoType::U8
+                    | LoType::I8
+                    | LoType::U16
+                    | LoType::I16
+                    | LoType::U32
+                    | LoType::I32
+                    | LoType::U64
+                    | LoType::I64
+                    | LoType::Pointer { .. } => format!("0 as {}", field.value_type),
+                    LoType::StructInstance { name: type_name } if type_name == "str" => {
+                        String::from("\"\"")
+                    }
+                    LoType::StructInstance { name: type_name } => format!("{type_name}::default()"),
+                    _ => return Err(unsupported_field(field)),
+                };
+                fields += &format!("{name}: {value}, ");
             }
 
-            return Ok(type_.clone());
+            source += &format!(
+                "fn {struct_name}::default(): {struct_name} {{ \
+                    return .{struct_name} {{ {fields} }}; }};"
+            );
+        }
+        unknown => {
+            return Err(LoError {
+                message: format!(
+                    "Unknown derive: {unknown}, expected one of: Equals, ToStr, Default"
+                ),
+                loc: derive.loc,
+            });
         }
     }
+
+    let mut lexed = Lexer::lex(&derive.loc.file_name(), &source)?;
+    for token in &mut lexed.tokens {
+        token.loc = derive.loc;
+    }
+    let mut tokens = LoTokenStream::new(lexed.tokens, derive.loc);
+    parse_file_tokens(ctx, &mut tokens)
 }
 
-fn parse_const_str(
-    ctx: &ModuleContext,
-    tokens: &mut LoTokenStream,
-    mut value: String,
-) -> Result<LoInstr, LoError> {
-    if ctx.memories.len() == 0 && ctx.mode != CompilerMode::Inspect {
+// the fn that was just defined becomes the target of `__alloc` or `__free`
+fn register_allocator_fn(ctx: &mut ModuleContext, role: AllocatorRole) -> Result<(), LoError> {
+    let fn_name = *ctx.fn_names.last().unwrap(); // safe, fn was just defined
+    let fn_def = &ctx.fn_defs[fn_name];
+
+    let void_ptr = LoType::Pointer {
+        pointee: Box::new(LoType::Void),
+        is_readonly: false,
+    };
+    let (attribute, signature, matches, registered) = match role {
+        AllocatorRole::Alloc => (
+            "alloc",
+            "fn(size: u32, align: u32): &void",
+            fn_def.type_.inputs == [LoType::U32, LoType::U32] && fn_def.type_.output == void_ptr,
+            &mut ctx.alloc_fn,
+        ),
+        AllocatorRole::Free => (
+            "free",
+            "fn(ptr: &void)",
+            fn_def.type_.inputs == [void_ptr] && fn_def.type_.output == LoType::Void,
+            &mut ctx.free_fn,
+        ),
+    };
+
+    if !matches {
         return Err(LoError {
-            message: format!("Cannot use strings with no memories defined"),
-            loc: tokens.loc().clone(),
+            message: format!("`@{attribute}` fn must have signature `{signature}`"),
+            loc: fn_def.loc,
         });
     }
 
-    let is_null_terminated = tokens.eat(IntLiteral, "0")?.is_some();
-    if is_null_terminated {
-        value.push('\0');
+    if let Some(existing) = registered {
+        return Err(LoError {
+            message: format!("`@{attribute}` fn is already defined: {existing}"),
+            loc: fn_def.loc,
+        });
     }
 
-    let string_len = value.as_bytes().len() as u32;
+    *registered = Some(String::from(fn_name.as_str()));
 
-    let string_ptr = ctx.string_pool.borrow().get(&value).cloned();
-    let string_ptr = match string_ptr {
-        Some(string_ptr) => string_ptr,
-        None => {
-            let new_string_ptr = ctx.append_data(value.clone().into_bytes());
-            ctx.string_pool.borrow_mut().insert(value, new_string_ptr);
-            new_string_ptr
-        }
-    };
+    Ok(())
+}
 
-    if is_null_terminated {
-        return Ok(
-            LoInstr::U32Const { value: string_ptr }.casted(LoType::Pointer(Box::new(LoType::U8)))
-        );
-    }
+// the fn that was just defined is called on instantiation, before any export
+fn register_start_fn(ctx: &mut ModuleContext) -> Result<(), LoError> {
+    let fn_name = *ctx.fn_names.last().unwrap(); // safe, fn was just defined
+    let fn_def = &ctx.fn_defs[fn_name];
 
-    Ok(LoInstr::MultiValueEmit {
-        values: vec![
-            LoInstr::U32Const { value: string_ptr },
-            LoInstr::U32Const { value: string_len },
-        ],
+    if !fn_def.type_.inputs.is_empty() || fn_def.type_.output != LoType::Void {
+        return Err(LoError {
+            message: String::from("`@start` fn must have signature `fn()`"),
+            loc: fn_def.loc,
+        });
     }
-    .casted(LoType::StructInstance {
-        name: format!("str"),
-    }))
-}
 
-// TODO: support sequences of any type
-fn parse_const_sequence(
-    ctx: &ModuleContext,
-    tokens: &mut LoTokenStream,
-) -> Result<(LoType, Vec<u8>), LoError> {
-    let item_type = parse_const_lo_type(ctx, tokens)?;
-    if item_type != LoType::U8
-        && item_type
-            != (LoType::StructInstance {
-                name: format!("str"),
-            })
-    {
+    if let Some(existing) = &ctx.start_fn {
         return Err(LoError {
-            message: format!("Unsupported sequence element type: {}", item_type),
-            loc: tokens.loc().clone(),
+            message: format!("`@start` fn is already defined: {existing}"),
+            loc: fn_def.loc,
         });
     }
 
-    tokens.expect(Delim, "]")?;
+    ctx.start_fn = Some(String::from(fn_name.as_str()));
 
-    let mut bytes = vec![];
+    Ok(())
+}
 
-    tokens.expect(Delim, "[")?;
-    while let None = tokens.eat(Delim, "]")? {
-        if item_type == LoType::U8 {
-            let byte = tokens.expect_any(IntLiteral)?;
-            bytes.push(parse_u8_literal(byte)?);
-        } else if item_type
-            == (LoType::StructInstance {
-                name: format!("str"),
-            })
-        {
-            let value = tokens.expect_any(StringLiteral)?;
-            let value = Lexer::unescape_string(&value.value);
-            let len = value.len();
-            let ptr = ctx.append_data(value.into_bytes());
+// `@start` fns can also be called directly, later calls return right away:
+//   `if __initialized { return; }; __initialized = true;`
+fn compile_start_guard(ctx: &ModuleContext) -> LoInstr {
+    let mut wasm_module = ctx.wasm_module.borrow_mut();
+    let guard_global = wasm_module.globals.len() as u32;
+    wasm_module.globals.push(WasmGlobal {
+        kind: WasmGlobalKind {
+            value_type: WasmType::I32,
+            mutable: true,
+        },
+        initial_value: WasmExpr {
+            instrs: vec![WasmInstr::I32Const { value: 0 }],
+        },
+    });
+
+    LoInstr::MultiValueEmit {
+        values: vec![
+            LoInstr::If {
+                block_type: LoBlockType::void(),
+                cond: Box::new(LoInstr::GlobalGet {
+                    global_index: guard_global,
+                }),
+                then_branch: vec![LoInstr::Return {
+                    value: Box::new(LoInstr::NoInstr),
+                }],
+                else_branch: None,
+            },
+            LoInstr::U32Const { value: 1 },
+            LoInstr::Set {
+                bind: LoSetBind::Global {
+                    index: guard_global,
+                },
+            },
+        ],
+    }
+    .casted(LoType::Void)
+}
 
-            bytes.extend_from_slice(&ptr.to_le_bytes());
-            bytes.extend_from_slice(&len.to_le_bytes());
-        } else {
-            unreachable!()
-        }
+// bare int literals take the type they are used as, `x = 0` where `x: i64` or `takes_u8(5)`
+fn coerce_int_literal(
+    value: LoInstr,
+    wanted_type: &LoType,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    let Some(int) = get_bare_int_literal(&value) else {
+        return Ok(value);
+    };
 
-        if !tokens.next_is(Delim, "]")? {
-            tokens.expect(Delim, ",")?;
+    // wrapped like suffixed literals, so the type sticks
+    let coerced = match wanted_type {
+        LoType::U32 => LoInstr::U32Const { value: int as u32 },
+        LoType::I32 => LoInstr::I32Const { value: int as i32 },
+        LoType::U64 => LoInstr::U64Const { value: int as u64 },
+        LoType::I64 => LoInstr::I64Const { value: int as i64 },
+        LoType::F32 => return Ok(LoInstr::F32Const { value: int as f32 }),
+        LoType::F64 => return Ok(LoInstr::F64Const { value: int as f64 }),
+        // values of smaller ints are held in i32 like everywhere else
+        LoType::U8 | LoType::I8 | LoType::U16 | LoType::I16 => {
+            LoInstr::U32Const { value: int as u32 }
         }
-    }
+        _ => return Ok(value),
+    };
 
-    return Ok((item_type, bytes));
+    check_int_const_range(&value, wanted_type, loc)?;
+
+    Ok(coerced.casted(wanted_type.clone()))
 }
 
-fn parse_nested_symbol(tokens: &mut LoTokenStream) -> Result<LoToken, LoError> {
-    let mut nested_symbol = tokens.expect_any(Symbol)?.clone();
-    while let Some(_) = tokens.eat(Operator, "::")? {
-        let path_part = tokens.expect_any(Symbol)?;
-        nested_symbol.value += "::";
-        nested_symbol.value += path_part.value.as_str();
-        nested_symbol.loc.end_pos = path_part.loc.end_pos.clone();
+fn get_bare_int_literal(value: &LoInstr) -> Option<i128> {
+    match value {
+        LoInstr::U32Const { value } => Some(*value as i128),
+        LoInstr::I32Const { value } => Some(*value as i128),
+        LoInstr::U64Const { value } => Some(*value as i128),
+        LoInstr::I64Const { value } => Some(*value as i128),
+        _ => None,
     }
-    Ok(nested_symbol)
 }
 
-fn extract_method_receiver_and_name(
-    ctx: &ModuleContext,
-    token: &LoToken,
-) -> Result<(Option<LoType>, String), LoError> {
-    Ok(match token.value.rsplitn(2, "::").collect::<Vec<_>>()[..] {
-        [method_name, receiver_name] => {
-            let mut token = LoToken {
-                type_: LoTokenType::Symbol,
-                value: String::from(receiver_name),
-                loc: token.loc.clone(),
-            };
+// bare literals are u32 (i32 when negative or signed) or u64 (i64) if they don't fit
+fn build_bare_int_literal(value: i128, is_signed: bool) -> Option<LoInstr> {
+    if !is_signed && value >= 0 {
+        return Some(match value {
+            0..=0xFFFF_FFFF => LoInstr::U32Const {
+                value: value as u32,
+            },
+            _ => LoInstr::U64Const {
+                value: u64::try_from(value).ok()?,
+            },
+        });
+    }
 
-            // TODO: correct `end_pos` info is lost during creation of nested_symbol
-            token.loc.end_pos = token.loc.pos.clone();
+    if let Ok(value) = i32::try_from(value) {
+        return Some(LoInstr::I32Const { value });
+    }
 
-            (
-                Some(get_type_by_name(ctx, &ctx.type_scope, &token, false)?),
-                String::from(method_name),
-            )
-        }
-        [fn_name] => (None, String::from(fn_name)),
-        _ => unreachable!(),
+    Some(LoInstr::I64Const {
+        value: i64::try_from(value).ok()?,
     })
 }
 
-fn parse_const_int(tokens: &mut LoTokenStream) -> Result<LoInstr, LoError> {
-    let int_literal = tokens.expect_any(IntLiteral)?.clone();
+// arithmetic on bare literals is done at compile time, so `takes_i64(1 + 2)` infers the type too
+fn fold_bare_int_literals(
+    op: &InfixOpTag,
+    lhs: &LoInstr,
+    rhs: &LoInstr,
+    loc: &LoLocation,
+) -> Result<Option<LoInstr>, LoError> {
+    // `+` makes a literal signed, the result is signed too
+    let is_signed = [lhs, rhs]
+        .iter()
+        .any(|value| matches!(value, LoInstr::I32Const { .. } | LoInstr::I64Const { .. }));
+    let (Some(lhs), Some(rhs)) = (get_bare_int_literal(lhs), get_bare_int_literal(rhs)) else {
+        return Ok(None);
+    };
 
-    if let Some(_) = tokens.eat(Symbol, "i64")? {
-        return Ok(LoInstr::I64Const {
-            value: parse_i64_literal(&int_literal)?,
+    if matches!(op, InfixOpTag::Div | InfixOpTag::Mod) && rhs == 0 {
+        return Err(LoError {
+            message: String::from("Division by zero in constant expression"),
+            loc: *loc,
         });
     }
 
-    if let Some(_) = tokens.eat(Symbol, "u64")? {
-        return Ok(LoInstr::U64Const {
-            value: parse_u64_literal(&int_literal)?,
+    // 64 bit operands can't overflow i128, except for `mul` which is checked
+    let value = match op {
+        InfixOpTag::Add => Some(lhs + rhs),
+        InfixOpTag::Sub => Some(lhs - rhs),
+        InfixOpTag::Mul => lhs.checked_mul(rhs),
+        InfixOpTag::Div => Some(lhs / rhs),
+        InfixOpTag::Mod => Some(lhs % rhs),
+        InfixOpTag::BitAnd => Some(lhs & rhs),
+        InfixOpTag::BitOr => Some(lhs | rhs),
+        _ => return Ok(None),
+    };
+
+    match value.and_then(|value| build_bare_int_literal(value, is_signed)) {
+        Some(value) => Ok(Some(value)),
+        None => Err(LoError {
+            message: String::from("Integer literal arithmetic overflows"),
+            loc: *loc,
+        }),
+    }
+}
+
+// literals (possibly negated) can't be silently truncated, `300 as u8` is an error
+fn check_int_const_range(value: &LoInstr, type_: &LoType, loc: &LoLocation) -> Result<(), LoError> {
+    let value = match value {
+        LoInstr::Casted {
+            value_type: LoType::U32,
+            expr,
+        } if matches!(**expr, LoInstr::U32Const { .. }) => {
+            return check_int_const_range(expr, type_, loc);
+        }
+        LoInstr::Casted {
+            value_type: LoType::I32,
+            expr,
+        } if matches!(**expr, LoInstr::I32Const { .. }) => {
+            return check_int_const_range(expr, type_, loc);
+        }
+        LoInstr::Casted {
+            value_type: LoType::U64 | LoType::I64,
+            expr,
+        } if matches!(**expr, LoInstr::U64Const { .. } | LoInstr::I64Const { .. }) => {
+            return check_int_const_range(expr, type_, loc);
+        }
+        LoInstr::U32Const { value } => *value as i128,
+        LoInstr::I32Const { value } => *value as i128,
+        LoInstr::U64Const { value } => *value as i128,
+        LoInstr::I64Const { value } => *value as i128,
+        _ => return Ok(()),
+    };
+    let Some((min, max)) = get_int_range(type_) else {
+        return Ok(());
+    };
+
+    if value < min || value > max {
+        return Err(LoError {
+            message: format!(
+                "Integer literal {value} is out of range for {type_}, allowed range is {min}..={max}"
+            ),
+            loc: *loc,
         });
     }
 
-    return Ok(LoInstr::U32Const {
-        value: parse_u32_literal(&int_literal)?,
-    });
+    Ok(())
+}
+
+fn get_int_range(type_: &LoType) -> Option<(i128, i128)> {
+    Some(match type_ {
+        LoType::U8 => (0, u8::MAX as i128),
+        LoType::I8 => (i8::MIN as i128, i8::MAX as i128),
+        LoType::U16 => (0, u16::MAX as i128),
+        LoType::I16 => (i16::MIN as i128, i16::MAX as i128),
+        LoType::U32 => (0, u32::MAX as i128),
+        LoType::I32 => (i32::MIN as i128, i32::MAX as i128),
+        LoType::U64 => (0, u64::MAX as i128),
+        LoType::I64 => (i64::MIN as i128, i64::MAX as i128),
+        _ => return None,
+    })
 }
 
 fn parse_u8_literal(int: &LoToken) -> Result<u8, LoError> {
-    Ok(Lexer::parse_int_literal_value(&int.value) as u8)
+    Ok(parse_int_literal(int, &LoType::U8)? as u8)
 }
 
 fn parse_u32_literal(int: &LoToken) -> Result<u32, LoError> {
-    Ok(Lexer::parse_int_literal_value(&int.value) as u32)
+    Ok(parse_int_literal(int, &LoType::U32)? as u32)
 }
 
-fn parse_i64_literal(int: &LoToken) -> Result<i64, LoError> {
-    Ok(Lexer::parse_int_literal_value(&int.value) as i64)
-}
+// checks that the literal fits into `type_`, literals are never negative
+fn parse_int_literal(int: &LoToken, type_: &LoType) -> Result<u64, LoError> {
+    let (min, max) = get_int_range(type_).unwrap_or((0, u64::MAX as i128));
+
+    let value = Lexer::parse_int_literal_value(&int.value);
+    if value as i128 > max {
+        return Err(LoError {
+            message: format!(
+                "Integer literal {} is out of range for {type_}, allowed range is {min}..={max}",
+                int.value
+            ),
+            loc: int.loc,
+        });
+    }
 
-fn parse_u64_literal(int: &LoToken) -> Result<u64, LoError> {
-    Ok(Lexer::parse_int_literal_value(&int.value))
+    Ok(value)
 }
 
 fn get_fn_name_from_method(receiver_type: &LoType, method_name: &str) -> String {
@@ -3250,6 +6711,134 @@ fn get_fn_name_from_method(receiver_type: &LoType, method_name: &str) -> String
     format!("{resolved_receiver_type}::{method_name}")
 }
 
+fn compile_fn_call(
+    ctx: &mut BlockContext,
+    fn_def: &FnDef,
+    mut args: Vec<LoInstr>,
+) -> Result<LoInstr, String> {
+    if !fn_def.sret {
+        return Ok(LoInstr::Call {
+            fn_index: fn_def.get_absolute_index(ctx.module),
+            return_type: fn_def.type_.output.clone(),
+            args,
+        });
+    }
+
+    let sret_buffer_ptr = LoInstr::U32Const {
+        value: ctx.module.sret_buffer_ptr,
+    };
+    args.insert(0, sret_buffer_ptr.clone());
+
+    let call = LoInstr::Call {
+        fn_index: fn_def.get_absolute_index(ctx.module),
+        return_type: LoType::Void,
+        args,
+    };
+    let result = compile_load(ctx, &fn_def.type_.output, &sret_buffer_ptr, 0)?;
+
+    Ok(LoInstr::MultiValueEmit {
+        values: vec![call, result],
+    }
+    .casted(fn_def.type_.output.clone()))
+}
+
+// deferred exprs run after the return value is computed, sret stores go last
+fn compile_return(
+    ctx: &mut BlockContext,
+    value: LoInstr,
+    loc: &LoLocation,
+) -> Result<LoInstr, LoError> {
+    if let Some(tail_call) = get_tail_call(ctx, &value) {
+        return Ok(tail_call);
+    }
+
+    let mut value = value;
+    if let Some(mut values) = get_deferred(ctx) {
+        values.insert(0, value);
+        value = LoInstr::MultiValueEmit { values }.casted(LoType::Void);
+    }
+
+    let output = &ctx.fn_ctx.lo_fn_type.output;
+    if ctx.module.uses_sret(output) {
+        let sret_ptr = LoInstr::UntypedLocalGet { local_index: 0 };
+        let bind_instr = compile_load(ctx, output, &sret_ptr, 0)
+            .map_err(|message| LoError { message, loc: *loc })?;
+        value = compile_set(ctx, value, bind_instr, loc)?;
+    }
+
+    Ok(LoInstr::Return {
+        value: Box::new(value),
+    })
+}
+
+// returned call can reuse the caller's frame unless something has to run after it,
+//   sret callees store the result to the same buffer the caller would copy it to
+fn get_tail_call(ctx: &BlockContext, value: &LoInstr) -> Option<LoInstr> {
+    if !ctx.module.features.has(WasmFeature::TailCall) || !ctx.fn_ctx.defers.is_empty() {
+        return None;
+    }
+
+    let output = &ctx.fn_ctx.lo_fn_type.output;
+    let sret = ctx.module.uses_sret(output);
+    let call = match value {
+        LoInstr::Call { return_type, .. } if !sret && return_type == output => value,
+        // sret call is followed by a load of the result from the buffer
+        LoInstr::Casted { value_type, expr } if sret && value_type == output => match &**expr {
+            LoInstr::MultiValueEmit { values } => values.first()?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let LoInstr::Call { fn_index, args, .. } = call else {
+        return None;
+    };
+    Some(LoInstr::ReturnCall {
+        fn_index: *fn_index,
+        args: args.clone(),
+    })
+}
+
+// deferred exprs have no one to return a value to, so a value of one is dropped
+//   with a warning unless it's discarded explicitly with `defer let _ = ...`
+fn push_deferred(ctx: &mut BlockContext, expr: LoInstr, loc: LoLocation) -> Result<(), LoError> {
+    let expr_type = expr.get_type(ctx.module);
+    if expr_type == LoType::Void || expr_type == LoType::Never {
+        ctx.fn_ctx.defers.push(expr);
+        return Ok(());
+    }
+
+    if let LoType::Result { .. } = expr_type {
+        return Err(unhandled_result_error(&expr_type, loc));
+    }
+
+    ctx.module.warn(
+        LoWarning::UnusedResult,
+        loc,
+        &format!("Unused result of type {expr_type}, discard it with `let _ = ...`"),
+    );
+
+    let drop_count = expr_type.emit_components(ctx.module, &mut vec![]);
+    ctx.fn_ctx.defers.push(LoInstr::Drop {
+        value: Box::new(expr),
+        drop_count,
+    });
+
+    Ok(())
+}
+
+// errors can't go unnoticed, a `Result` that is neither caught, propagated with `?`
+//   nor discarded with `let _ =` is rejected wherever its value would be lost
+fn unhandled_result_error(result_type: &LoType, loc: LoLocation) -> LoError {
+    LoError {
+        message: format!(
+            "Unhandled {result_type}, `catch` the error, propagate it with `?` \
+                or discard it with `let _ =`"
+        ),
+        loc,
+    }
+}
+
 fn get_deferred(ctx: &mut BlockContext) -> Option<Vec<LoInstr>> {
     if ctx.fn_ctx.defers.len() == 0 {
         return None;
@@ -3261,6 +6850,229 @@ fn get_deferred(ctx: &mut BlockContext) -> Option<Vec<LoInstr>> {
     Some(deferred)
 }
 
+fn require_reference_types(ctx: &ModuleContext, token: &LoToken) -> Result<(), LoError> {
+    ctx.features
+        .require(WasmFeature::ReferenceTypes, &token.value)
+        .map_err(|message| LoError {
+            message,
+            loc: token.loc,
+        })
+}
+
+// `__alloc(size, align)` and `__free(ptr)` call the `@alloc` and `@free` fns,
+//   a bump allocator is generated in `finalize` if none are defined
+fn parse_alloc_intrinsic(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+) -> Result<Option<LoInstr>, LoError> {
+    let (attribute, fn_name) = if tokens.next_is(Symbol, "__alloc")? {
+        ("alloc", &ctx.module.alloc_fn)
+    } else if tokens.next_is(Symbol, "__free")? {
+        ("free", &ctx.module.free_fn)
+    } else {
+        return Ok(None);
+    };
+    let t = tokens.next().unwrap().clone();
+
+    let Some(fn_def) = fn_name
+        .as_ref()
+        .and_then(|name| ctx.module.get_fn_def(name))
+    else {
+        let message = if ctx.module.memories.is_empty() {
+            format!("{} requires a memory", t.value)
+        } else {
+            format!("{} requires a `@{attribute}` fn", t.value)
+        };
+        return Err(LoError {
+            message,
+            loc: t.loc,
+        });
+    };
+    let fn_def = fn_def.clone();
+
+    let mut args = vec![];
+    parse_fn_call_args(ctx, tokens, &mut args)?;
+    typecheck_fn_call_args(
+        ctx.module,
+        &fn_def.type_.inputs,
+        &mut args,
+        &t.value,
+        &t.loc,
+    )?;
+
+    let call = compile_fn_call(ctx, &fn_def, args).map_err(|message| LoError {
+        message,
+        loc: t.loc,
+    })?;
+
+    Ok(Some(call))
+}
+
+fn parse_atomic_intrinsic(
+    ctx: &mut BlockContext,
+    tokens: &mut LoTokenStream,
+) -> Result<Option<LoInstr>, LoError> {
+    let Some(t) = tokens.peek() else {
+        return Ok(None);
+    };
+    if t.type_ != Symbol
+        || !(t.value.starts_with("__atomic_") || t.value.starts_with("__memory_atomic_"))
+    {
+        return Ok(None);
+    }
+    let t = tokens.next().unwrap().clone();
+
+    ctx.module
+        .features
+        .require(WasmFeature::Threads, &t.value)
+        .map_err(|message| LoError {
+            message,
+            loc: t.loc,
+        })?;
+
+    tokens.expect(Delim, "(")?;
+    let mut args = vec![];
+    while tokens.eat(Delim, ")")?.is_none() {
+        args.push(parse_expr(ctx, tokens, 0)?);
+        if !tokens.next_is(Delim, ")")? {
+            tokens.expect(Delim, ",")?;
+        }
+    }
+    let arg_types: Vec<_> = args.iter().map(|arg| arg.get_type(ctx.module)).collect();
+
+    let invalid_args = |expected: &Vec<LoType>| LoError {
+        message: format!(
+            "Invalid arguments for {}, got [{}], expected [{}]",
+            t.value,
+            ListDisplay(&arg_types),
+            ListDisplay(expected),
+        ),
+        loc: t.loc,
+    };
+
+    if t.value == "__atomic_fence" {
+        if !args.is_empty() {
+            return Err(invalid_args(&vec![]));
+        }
+
+        return Ok(Some(LoInstr::Atomic {
+            instr: WasmInstr::AtomicFence,
+            args,
+            value_type: LoType::Void,
+        }));
+    }
+
+    // the rest take a pointer to the accessed value first
+    let Some(LoType::Pointer {
+        pointee: value_type,
+        ..
+    }) = arg_types.first()
+    else {
+        return Err(LoError {
+            message: format!("{} expects a pointer as the first argument", t.value),
+            loc: t.loc,
+        });
+    };
+    // only loads and waits accept `&readonly T`
+    let pointer_type = LoType::Pointer {
+        pointee: value_type.clone(),
+        is_readonly: false,
+    };
+    let readonly_pointer_type = LoType::Pointer {
+        pointee: value_type.clone(),
+        is_readonly: true,
+    };
+    let value_type = *value_type.clone();
+    let kind = value_type.to_atomic_kind().map_err(|message| LoError {
+        message,
+        loc: t.loc,
+    })?;
+
+    let (instr, expected_args, result_type) = match t.value.as_str() {
+        "__atomic_load" => (
+            WasmInstr::AtomicLoad {
+                kind: WasmLoadKind::from_store_kind(&kind),
+                offset: 0,
+            },
+            vec![readonly_pointer_type],
+            value_type,
+        ),
+        "__atomic_store" => (
+            WasmInstr::AtomicStore { kind, offset: 0 },
+            vec![pointer_type, value_type],
+            LoType::Void,
+        ),
+        "__atomic_add" | "__atomic_sub" | "__atomic_and" | "__atomic_or" | "__atomic_xor"
+        | "__atomic_xchg" => {
+            let op = match t.value.as_str() {
+                "__atomic_add" => WasmAtomicRmwOp::Add,
+                "__atomic_sub" => WasmAtomicRmwOp::Sub,
+                "__atomic_and" => WasmAtomicRmwOp::And,
+                "__atomic_or" => WasmAtomicRmwOp::Or,
+                "__atomic_xor" => WasmAtomicRmwOp::Xor,
+                _ => WasmAtomicRmwOp::Xchg,
+            };
+            (
+                WasmInstr::AtomicRmw {
+                    op,
+                    kind,
+                    offset: 0,
+                },
+                vec![pointer_type, value_type.clone()],
+                value_type,
+            )
+        }
+        "__atomic_cmpxchg" => (
+            WasmInstr::AtomicCmpxchg { kind, offset: 0 },
+            vec![pointer_type, value_type.clone(), value_type.clone()],
+            value_type,
+        ),
+        "__memory_atomic_wait" => {
+            let wait_type = match kind {
+                WasmStoreKind::I32 => WasmType::I32,
+                WasmStoreKind::I64 => WasmType::I64,
+                _ => {
+                    return Err(LoError {
+                        message: format!("Cannot wait on {value_type} value"),
+                        loc: t.loc,
+                    });
+                }
+            };
+            (
+                WasmInstr::MemoryAtomicWait {
+                    value_type: wait_type,
+                    offset: 0,
+                },
+                vec![readonly_pointer_type, value_type, LoType::I64],
+                LoType::U32,
+            )
+        }
+        "__memory_atomic_notify" => (
+            WasmInstr::MemoryAtomicNotify { offset: 0 },
+            vec![pointer_type, LoType::U32],
+            LoType::U32,
+        ),
+        _ => {
+            return Err(LoError {
+                message: format!("Unknown atomic intrinsic: {}", t.value),
+                loc: t.loc,
+            });
+        }
+    };
+
+    let args_match = arg_types.len() == expected_args.len()
+        && (arg_types.iter().zip(&expected_args)).all(|(a, e)| a.is_assignable_to(e));
+    if !args_match {
+        return Err(invalid_args(&expected_args));
+    }
+
+    Ok(Some(LoInstr::Atomic {
+        instr,
+        args,
+        value_type: result_type,
+    }))
+}
+
 fn compile_load(
     ctx: &mut BlockContext,
     value_type: &LoType,
@@ -3276,7 +7088,22 @@ fn compile_load(
         });
     }
 
-    if let LoType::Tuple(item_types) = value_type {
+    // results are laid out like a tuple of ok and err values
+    let result_item_types;
+    let item_types = match value_type {
+        LoType::Tuple(item_types) => Some(item_types),
+        LoType::ErrorUnion { .. } => {
+            result_item_types = vec![LoType::U32, LoType::U32];
+            Some(&result_item_types)
+        }
+        LoType::Result { ok_type, err_type } => {
+            result_item_types = vec![*ok_type.clone(), *err_type.clone()];
+            Some(&result_item_types)
+        }
+        _ => None,
+    };
+
+    if let Some(item_types) = item_types {
         let mut item_gets = vec![];
         let mut item_byte_offset = 0;
         for item_type in item_types {
@@ -3295,6 +7122,11 @@ fn compile_load(
     let LoType::StructInstance { name } = value_type else {
         return Err(format!("Unsupported type for compile_load: {value_type:?}"));
     };
+    if ctx.module.is_union_type(value_type) {
+        return Err(format!(
+            "Union {value_type} can only be used through a pointer"
+        ));
+    }
 
     let mut components = vec![];
     let mut stats = EmitComponentStats {
@@ -3321,7 +7153,7 @@ fn compile_load(
     }
 
     Ok(LoInstr::StructLoad {
-        struct_name: name.clone(),
+        struct_name: *name,
         address_instr: Box::new(address_instr.clone()),
         address_local_index,
         base_byte_offset,
@@ -3334,6 +7166,11 @@ fn compile_local_get(
     base_index: u32,
     value_type: &LoType,
 ) -> Result<LoInstr, String> {
+    if let LoType::ErrorUnion { .. } = value_type {
+        let repr_get = compile_local_get(ctx, base_index, &LoType::error_union_repr())?;
+        return Ok(repr_get.casted(value_type.clone()));
+    }
+
     if let LoType::Tuple(item_types) = value_type {
         let mut item_gets = vec![];
         for (item_index, item_type) in (0..).zip(item_types) {
@@ -3364,7 +7201,7 @@ fn compile_local_get(
     }
 
     Ok(LoInstr::StructGet {
-        struct_name: name.clone(),
+        struct_name: *name,
         base_index,
         primitive_gets,
     })
@@ -3377,16 +7214,33 @@ fn compile_set(
     loc: &LoLocation,
 ) -> Result<LoInstr, LoError> {
     let mut values = vec![];
-    compile_set_binds(&mut values, ctx, bind_instr, None).map_err(|message| LoError {
-        message,
-        loc: loc.clone(),
-    })?;
+    compile_set_binds(&mut values, ctx, bind_instr, None)
+        .map_err(|message| LoError { message, loc: *loc })?;
     values.push(value_instr);
     values.reverse();
 
     Ok(LoInstr::MultiValueEmit { values }.casted(LoType::Void))
 }
 
+fn check_writable_address(ctx: &ModuleContext, address_instr: &LoInstr) -> Result<(), String> {
+    // compiler generated addresses, like the sret pointer, are always writable
+    if let LoInstr::UntypedLocalGet { .. } = address_instr {
+        return Ok(());
+    }
+
+    let address_type = address_instr.get_type(ctx);
+    if let LoType::Pointer {
+        is_readonly: true, ..
+    } = address_type
+    {
+        return Err(format!(
+            "Cannot write through read-only pointer `{address_type}`"
+        ));
+    }
+
+    Ok(())
+}
+
 fn compile_set_binds(
     output: &mut Vec<LoInstr>,
     ctx: &mut BlockContext,
@@ -3420,7 +7274,10 @@ fn compile_set_binds(
 
             let address_instr = match address_index {
                 Some(local_index) => Box::new(LoInstr::UntypedLocalGet { local_index }),
-                None => address_instr,
+                None => {
+                    check_writable_address(ctx.module, &address_instr)?;
+                    address_instr
+                }
             };
 
             output.push(LoInstr::Set {
@@ -3439,6 +7296,8 @@ fn compile_set_binds(
             address_local_index,
             ..
         } => {
+            check_writable_address(ctx.module, &address_instr)?;
+
             let mut values = vec![];
 
             for value in primitive_loads {
@@ -3482,6 +7341,7 @@ pub struct LoTokenStream {
     pub tokens: Vec<LoToken>,
     pub index: usize,
     pub terminal_token: LoToken,
+    pub comments: Vec<Comment>,
 }
 
 impl LoTokenStream {
@@ -3494,9 +7354,15 @@ impl LoTokenStream {
                 value: "<EOF>".into(),
                 loc: end_location,
             },
+            comments: vec![],
         }
     }
 
+    pub fn get_doc_comment(&self) -> Option<String> {
+        let token = self.peek()?;
+        Comment::get_doc(&self.comments, &token.loc)
+    }
+
     pub fn expect_any(&mut self, type_: LoTokenType) -> Result<&LoToken, LoError> {
         match self.peek() {
             Some(token) if token.is_any(type_) => Ok(self.next().unwrap()),
@@ -3504,7 +7370,7 @@ impl LoTokenStream {
                 let unexpected = other.unwrap_or(&self.terminal_token);
                 Err(LoError {
                     message: format!("Unexpected token '{}', wanted {type_:?}", unexpected.value),
-                    loc: unexpected.loc.clone(),
+                    loc: unexpected.loc,
                 })
             }
         }
@@ -3517,7 +7383,7 @@ impl LoTokenStream {
                 let unexpected = other.unwrap_or(&self.terminal_token);
                 Err(LoError {
                     message: format!("Unexpected token '{}', wanted '{value}'", unexpected.value),
-                    loc: unexpected.loc.clone(),
+                    loc: unexpected.loc,
                 })
             }
         }
@@ -3582,7 +7448,7 @@ impl LoTokenStream {
     fn err_eof<T>(&self, message: String) -> Result<T, LoError> {
         Err(LoError {
             message,
-            loc: self.terminal_token.loc.clone(),
+            loc: self.terminal_token.loc,
         })
     }
 }
@@ -1,4 +1,10 @@
-use crate::{ast::*, core::*, lexer::*};
+use crate::{
+    ast::*,
+    core::*,
+    ir::MAX_EXPR_NESTING_DEPTH,
+    lexer::*,
+    parser::{unknown_memory_prop_message, MEMORY_PROPS},
+};
 use alloc::{boxed::Box, format, string::String, vec::Vec};
 
 use LoTokenType::*;
@@ -14,7 +20,7 @@ pub fn parse_file_and_deps(
     file_name: &str,
     loc: &LoLocation,
 ) -> Result<(), LoError> {
-    let file_path = resolve_path(file_name, &loc.file_name);
+    let file_path = resolve_include_path(file_name, &loc.file_name());
 
     for file in files.iter() {
         // file already parsed, skip
@@ -23,10 +29,7 @@ pub fn parse_file_and_deps(
         }
     }
 
-    let chars = file_read_utf8(&file_path).map_err(|message| LoError {
-        message,
-        loc: loc.clone(),
-    })?;
+    let chars = file_read_utf8(&file_path).map_err(|message| LoError { message, loc: *loc })?;
     let tokens = Lexer::lex(&file_path, &chars)?;
     let ast = ParserV2::parse(tokens)?;
 
@@ -38,7 +41,7 @@ pub fn parse_file_and_deps(
     }
 
     files.push(FileInfo {
-        path: file_path.into(),
+        path: file_path,
         ast,
     });
 
@@ -53,10 +56,25 @@ pub fn parse_file_and_deps(
     Ok(())
 }
 
+fn attach_doc_comments(ast: &mut AST) {
+    for expr in &mut ast.exprs {
+        let doc = Comment::get_doc(&ast.comments, expr.loc());
+
+        match expr {
+            TopLevelExpr::FnDef(fn_def) => fn_def.doc = doc,
+            TopLevelExpr::StructDef(struct_def) => struct_def.doc = doc,
+            TopLevelExpr::UnionDef(union_def) => union_def.doc = doc,
+            TopLevelExpr::ConstDef(const_def) => const_def.doc = doc,
+            _ => {}
+        }
+    }
+}
+
 pub struct ParserV2 {
     pub tokens: Vec<LoToken>,
     pub tokens_processed: usize,
     pub terminal_token: LoToken,
+    pub expr_depth: u32, // same limit as the main parser, the AST is walked recursively too
 }
 
 impl ParserV2 {
@@ -69,6 +87,7 @@ impl ParserV2 {
                 value: "<EOF>".into(),
                 loc: tokens.end_loc,
             },
+            expr_depth: 0,
         };
 
         let mut ast = AST {
@@ -77,6 +96,7 @@ impl ParserV2 {
         };
 
         parser.parse_file(&mut ast)?;
+        attach_doc_comments(&mut ast);
 
         Ok(ast)
     }
@@ -95,7 +115,7 @@ impl ParserV2 {
                     "Unexpected top level token: {}, EOF expected",
                     unexpected.value
                 ),
-                loc: unexpected.loc.clone(),
+                loc: unexpected.loc,
             });
         }
 
@@ -103,8 +123,16 @@ impl ParserV2 {
     }
 
     fn parse_top_level_expr(&mut self) -> Result<TopLevelExpr, LoError> {
+        if self.current().is(Operator, "@") {
+            let loc = self.current().loc;
+            let attributes = self.parse_attributes()?;
+            self.expect(Symbol, "struct")?;
+            let struct_def = self.parse_struct_def(attributes, loc)?;
+            return Ok(TopLevelExpr::StructDef(struct_def));
+        }
+
         if let Some(_) = self.eat(Symbol, "export")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             if let Some(_) = self.eat(Symbol, "fn")? {
                 let fn_def = self.parse_fn_def(true, loc)?;
@@ -116,19 +144,24 @@ impl ParserV2 {
                 return Ok(TopLevelExpr::MemoryDef(memory_def));
             }
 
+            if self.eat(Symbol, "table")?.is_some() {
+                let table_def = self.parse_table_def(true, loc)?;
+                return Ok(TopLevelExpr::TableDef(table_def));
+            }
+
             if let Some(_) = self.eat(Symbol, "existing")? {
-                let mut loc = self.prev().loc.clone();
+                let mut loc = self.prev().loc;
 
                 self.expect(Symbol, "fn")?;
                 let in_fn_name = self.parse_ident()?;
                 self.expect(Symbol, "as")?;
                 let out_fn_name = self.expect_any(StringLiteral)?.clone();
 
-                loc.end_pos = self.prev().loc.end_pos.clone();
+                loc.end_offset = self.prev().loc.end_offset;
 
                 return Ok(TopLevelExpr::ExportExistingFn(ExportExistingFnExpr {
                     in_fn_name,
-                    out_fn_name: out_fn_name.value,
+                    out_fn_name: String::from(out_fn_name.value),
                     loc,
                 }));
             }
@@ -136,39 +169,46 @@ impl ParserV2 {
             let unexpected = self.current();
             return Err(LoError {
                 message: format!("Unexpected exportable: {:?}", unexpected.value),
-                loc: unexpected.loc.clone(),
+                loc: unexpected.loc,
             });
         }
 
         if let Some(_) = self.eat(Symbol, "fn")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             let fn_def = self.parse_fn_def(false, loc)?;
             return Ok(TopLevelExpr::FnDef(fn_def));
         }
 
         if let Some(_) = self.eat(Symbol, "memory")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             let memory_def = self.parse_memory_def(false, loc)?;
             return Ok(TopLevelExpr::MemoryDef(memory_def));
         }
 
+        if self.eat(Symbol, "table")?.is_some() {
+            let loc = self.prev().loc;
+
+            let table_def = self.parse_table_def(false, loc)?;
+            return Ok(TopLevelExpr::TableDef(table_def));
+        }
+
         if let Some(_) = self.eat(Symbol, "include")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let file_path = self.expect_any(StringLiteral)?.clone();
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::Include(IncludeExpr {
-                file_path: file_path.value,
+                file_path: String::from(file_path.value),
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Symbol, "import")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             self.expect(Symbol, "from")?;
             let module_name = self.expect_any(StringLiteral)?.clone();
@@ -182,23 +222,23 @@ impl ParserV2 {
                 self.expect(Delim, ";")?;
             }
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::Import(ImportExpr {
-                module_name: module_name.value,
+                module_name: String::from(module_name.value),
                 items,
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Symbol, "global")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let global_name = self.parse_ident()?;
             self.expect(Operator, "=")?;
             let expr = self.parse_code_expr(0)?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::GlobalDef(GlobalDefExpr {
                 global_name,
@@ -208,93 +248,124 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Symbol, "struct")? {
-            let mut loc = self.prev().loc.clone();
-
-            let struct_name = self.parse_ident()?;
-
-            let mut fields = Vec::new();
-
-            self.expect(Delim, "{")?;
-            while let None = self.eat(Delim, "}")? {
-                let mut field_loc = self.current().loc.clone();
-
-                let field_name = self.expect_any(Symbol)?.clone();
-                self.expect(Operator, ":")?;
-                let field_type = self.parse_type_expr()?;
-
-                field_loc.end_pos = self.prev().loc.end_pos.clone();
-
-                fields.push(StructDefField {
-                    field_name: field_name.value,
-                    field_type,
-                    loc: field_loc,
-                });
-
-                if !self.current().is(Delim, "}") {
-                    self.expect(Delim, ",")?;
-                }
-            }
+            let loc = self.prev().loc;
+            let struct_def = self.parse_struct_def(Vec::new(), loc)?;
+            return Ok(TopLevelExpr::StructDef(struct_def));
+        }
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+        if self.eat(Symbol, "union")?.is_some() {
+            let loc = self.prev().loc;
+            let union_def = self.parse_struct_def(Vec::new(), loc)?;
+            return Ok(TopLevelExpr::UnionDef(union_def));
+        }
 
-            return Ok(TopLevelExpr::StructDef(StructDefExpr {
-                struct_name,
-                fields,
-                loc,
-            }));
+        if self.eat(Symbol, "flags")?.is_some() {
+            let loc = self.prev().loc;
+            let flags_def = self.parse_const_group(true, loc)?;
+            return Ok(TopLevelExpr::FlagsDef(flags_def));
         }
 
         if let Some(_) = self.eat(Symbol, "type")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let type_name = self.parse_ident()?;
             self.expect(Operator, "=")?;
+            let is_distinct = self.eat(Symbol, "distinct")?.is_some();
             let type_value = self.parse_type_expr()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::TypeDef(TypeDefExpr {
                 type_name,
+                is_distinct,
                 type_value,
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Symbol, "const")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let const_name = self.parse_ident()?;
+
+            let is_group = const_name.repr == "group" || const_name.repr == "flags";
+            if is_group && self.current().is_any(Symbol) {
+                let is_flags = const_name.repr == "flags";
+                let group = self.parse_const_group(is_flags, loc)?;
+                return Ok(TopLevelExpr::ConstGroup(group));
+            }
+
             self.expect(Operator, "=")?;
             let const_value = self.parse_code_expr(0)?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::ConstDef(ConstDefExpr {
                 const_name,
                 const_value,
+                doc: None,
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Operator, "*")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             // can't use `parse_code_expr` as that will capture `=` token
             let addr = self.parse_code_expr_primary()?;
             self.expect(Operator, "=")?;
-            let chars = self.expect_any(StringLiteral)?.clone();
+            let data = if let Some(chars) = self.eat_any(StringLiteral)? {
+                StaticDataStorePayload::String {
+                    value: String::from(chars.value),
+                }
+            } else {
+                StaticDataStorePayload::Bytes {
+                    value: self.parse_code_expr_primary()?,
+                }
+            };
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::StaticDataStore(StaticDataStoreExpr {
                 addr,
-                data: StaticDataStorePayload::String { value: chars.value },
+                data,
+                loc,
+            }));
+        }
+
+        if self.eat(Symbol, "custom_section")?.is_some() {
+            let mut loc = self.prev().loc;
+
+            let section_name = self.expect_any(StringLiteral)?.clone();
+            self.expect(Operator, "=")?;
+
+            let data = if let Some(value) = self.eat_any(StringLiteral)? {
+                CustomSectionPayload::String {
+                    value: String::from(value.value),
+                }
+            } else if self.eat(Operator, "@")?.is_some() {
+                self.expect(Symbol, "embed")?;
+                let file_path = self.expect_any(StringLiteral)?.clone();
+                CustomSectionPayload::Embed {
+                    file_path: String::from(file_path.value),
+                }
+            } else {
+                CustomSectionPayload::Bytes {
+                    value: self.parse_code_expr_primary()?,
+                }
+            };
+
+            loc.end_offset = self.prev().loc.end_offset;
+
+            return Ok(TopLevelExpr::CustomSection(CustomSectionExpr {
+                section_name: String::from(section_name.value),
+                data,
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Symbol, "macro")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let macro_name = self.parse_ident()?;
             self.expect(Operator, "!")?;
@@ -303,7 +374,7 @@ impl ParserV2 {
             if let Some(_) = self.eat(Operator, "<")? {
                 while let None = self.eat(Operator, ">")? {
                     let type_param = self.expect_any(Symbol)?;
-                    macro_type_params.push(type_param.value.clone());
+                    macro_type_params.push(String::from(type_param.value));
 
                     if !self.current().is(Operator, ">") {
                         self.expect(Delim, ",")?;
@@ -321,7 +392,7 @@ impl ParserV2 {
 
             let body = self.parse_code_block_expr()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(TopLevelExpr::MacroDef(MacroDefExpr {
                 macro_name,
@@ -336,7 +407,7 @@ impl ParserV2 {
         let unexpected = self.current();
         return Err(LoError {
             message: format!("Unexpected top level token: {:?}", unexpected.value),
-            loc: unexpected.loc.clone(),
+            loc: unexpected.loc,
         });
     }
 
@@ -344,12 +415,13 @@ impl ParserV2 {
         let decl = self.parse_fn_decl()?;
         let body = self.parse_code_block_expr()?;
 
-        loc.end_pos = self.prev().loc.end_pos.clone();
+        loc.end_offset = self.prev().loc.end_offset;
 
         Ok(FnDefExpr {
             exported,
             decl,
             body,
+            doc: None,
             loc,
         })
     }
@@ -362,32 +434,77 @@ impl ParserV2 {
         self.expect(Delim, "{")?;
 
         let mut min_pages = None;
-        if let Some(_) = self.eat(Symbol, "min_pages")? {
-            self.expect(Operator, ":")?;
-            let int = self.expect_any(IntLiteral)?;
-            let int_value = Lexer::parse_int_literal_value(&int.value) as u32;
-            self.expect(Delim, ",")?;
+        let mut max_pages = None;
+        let mut data_start = None;
+        let mut shared = false;
+        while self.eat(Delim, "}")?.is_none() {
+            let prop = self.expect_any(Symbol)?.clone();
+            if !MEMORY_PROPS.contains(&prop.value.as_str()) {
+                return Err(LoError {
+                    message: unknown_memory_prop_message(&prop.value),
+                    loc: prop.loc,
+                });
+            }
 
-            min_pages = Some(int_value);
-        }
+            // bare `shared` is the same as `shared: true`
+            if prop.value == "shared" && self.eat(Operator, ":")?.is_none() {
+                shared = true;
+            } else if prop.value == "shared" {
+                shared = self.expect_any(Symbol)?.value == "true";
+            } else {
+                self.expect(Operator, ":")?;
+                let int = self.expect_any(IntLiteral)?;
+                let int_value = Lexer::parse_int_literal_value(&int.value) as u32;
 
-        let mut data_start = None;
-        if let Some(_) = self.eat(Symbol, "data_start")? {
-            self.expect(Operator, ":")?;
-            let int = self.expect_any(IntLiteral)?;
-            let int_value = Lexer::parse_int_literal_value(&int.value) as u32;
-            self.eat(Delim, ",")?;
+                match prop.value.as_str() {
+                    "min_pages" => min_pages = Some(int_value),
+                    "max_pages" => max_pages = Some(int_value),
+                    _ => data_start = Some(int_value),
+                }
+            }
 
-            data_start = Some(int_value);
+            if self.eat(Delim, ",")?.is_none() {
+                self.expect(Delim, "}")?;
+                break;
+            }
         }
-        self.expect(Delim, "}")?;
 
-        loc.end_pos = self.prev().loc.end_pos.clone();
+        loc.end_offset = self.prev().loc.end_offset;
 
         Ok(MemoryDefExpr {
             exported,
             min_pages,
+            max_pages,
             data_start,
+            shared,
+            loc,
+        })
+    }
+
+    fn parse_table_def(
+        &mut self,
+        exported: bool,
+        mut loc: LoLocation,
+    ) -> Result<TableDefExpr, LoError> {
+        let table_name = self.expect_any(Symbol)?.clone();
+        self.expect(Operator, "=")?;
+
+        self.expect(Delim, "[")?;
+        let mut fn_names = Vec::new();
+        while self.eat(Delim, "]")?.is_none() {
+            fn_names.push(self.parse_ident()?);
+
+            if !self.current().is(Delim, "]") {
+                self.expect(Delim, ",")?;
+            }
+        }
+
+        loc.end_offset = self.prev().loc.end_offset;
+
+        Ok(TableDefExpr {
+            exported,
+            table_name: String::from(table_name.value),
+            fn_names,
             loc,
         })
     }
@@ -399,23 +516,36 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Symbol, "memory")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
             let memory_def = self.parse_memory_def(false, loc)?;
             return Ok(ImportItem::Memory(memory_def));
         }
 
+        if self.eat(Symbol, "table")?.is_some() {
+            let mut loc = self.prev().loc;
+            let table_name = self.expect_any(Symbol)?.clone();
+            self.expect(Operator, ":")?;
+            self.expect(Symbol, "funcref")?;
+            loc.end_offset = self.prev().loc.end_offset;
+
+            return Ok(ImportItem::Table(TableImportExpr {
+                table_name: String::from(table_name.value),
+                loc,
+            }));
+        }
+
         let unexpected = self.current();
         return Err(LoError {
             message: format!(
                 "Unexpected token in importable item: {:?}",
                 unexpected.value
             ),
-            loc: unexpected.loc.clone(),
+            loc: unexpected.loc,
         });
     }
 
     fn parse_fn_decl(&mut self) -> Result<FnDeclExpr, LoError> {
-        let mut loc = self.prev().loc.clone();
+        let mut loc = self.prev().loc;
 
         let fn_name = self.parse_ident()?;
         let fn_params = self.parse_fn_params()?;
@@ -426,7 +556,7 @@ impl ParserV2 {
             None
         };
 
-        loc.end_pos = self.prev().loc.end_pos.clone();
+        loc.end_offset = self.prev().loc.end_offset;
 
         Ok(FnDeclExpr {
             fn_name,
@@ -442,17 +572,18 @@ impl ParserV2 {
         let _ = self.expect(Delim, "(")?;
 
         while let None = self.eat(Delim, ")")? {
-            let mut loc = self.current().loc.clone();
+            let mut loc = self.current().loc;
 
             let mut p_type = FnParamType::Self_;
             if let Some(_) = self.eat(Operator, "&")? {
-                p_type = FnParamType::SelfRef;
+                let is_readonly = self.eat(Symbol, "readonly")?.is_some();
+                p_type = FnParamType::SelfRef { is_readonly };
             }
 
             let p_name = self.expect_any(Symbol)?.clone();
 
             if p_name.value != "self" {
-                if let FnParamType::SelfRef = p_type {
+                if let FnParamType::SelfRef { .. } = p_type {
                     return Err(LoError {
                         message: format!(
                             "Only `self` param can be preceded by the reference operator"
@@ -467,14 +598,14 @@ impl ParserV2 {
                 };
             }
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             if !self.current().is(Delim, ")") {
                 self.expect(Delim, ",")?;
             }
 
             params.push(FnParam {
-                param_name: p_name.value,
+                param_name: String::from(p_name.value),
                 param_type: p_type,
                 loc,
             });
@@ -500,8 +631,17 @@ impl ParserV2 {
 
     fn parse_type_expr_primary(&mut self) -> Result<TypeExpr, LoError> {
         if let Some(_) = self.eat(Operator, "&")? {
+            if self.eat(Delim, "[")?.is_some() {
+                let item_type = Box::new(self.parse_type_expr()?);
+                self.expect(Delim, "]")?;
+
+                return Ok(TypeExpr::Slice { item_type });
+            }
+
+            let is_readonly = self.eat(Symbol, "readonly")?.is_some();
             return Ok(TypeExpr::Pointer {
                 pointee: Box::new(self.parse_type_expr()?),
+                is_readonly,
             });
         }
 
@@ -511,11 +651,26 @@ impl ParserV2 {
             });
         }
 
+        if self.eat(Symbol, "struct")?.is_some() {
+            let mut loc = self.prev().loc;
+            let fields = self.parse_struct_def_fields()?;
+            loc.end_offset = self.prev().loc.end_offset;
+
+            return Ok(TypeExpr::Struct { fields, loc });
+        }
+
         if let Some(_) = self.eat(Symbol, "Result")? {
             self.expect(Operator, "<")?;
             let ok_type = Box::new(self.parse_type_expr()?);
             self.expect(Delim, ",")?;
-            let err_type = Box::new(self.parse_type_expr()?);
+            let mut err_type = Box::new(self.parse_type_expr()?);
+            if self.eat(Operator, "|")?.is_some() {
+                let mut variants = Vec::from([*err_type, self.parse_type_expr()?]);
+                while self.eat(Operator, "|")?.is_some() {
+                    variants.push(self.parse_type_expr()?);
+                }
+                err_type = Box::new(TypeExpr::ErrorUnion { variants });
+            }
             self.expect(Operator, ">")?;
 
             return Ok(TypeExpr::Result { ok_type, err_type });
@@ -530,7 +685,7 @@ impl ParserV2 {
 
         let mut code_block = CodeBlockExpr {
             exprs: Vec::new(),
-            loc: self.prev().loc.clone(),
+            loc: self.prev().loc,
         };
 
         while let None = self.eat(Delim, "}")? {
@@ -541,12 +696,15 @@ impl ParserV2 {
         }
 
         // close curly pos
-        code_block.loc.end_pos = self.prev().loc.end_pos.clone();
+        code_block.loc.end_offset = self.prev().loc.end_offset;
 
         return Ok(code_block);
     }
 
     fn parse_code_expr(&mut self, min_bp: u32) -> Result<CodeExpr, LoError> {
+        let outer_depth = self.expr_depth;
+
+        self.enter_nested_expr()?;
         let mut primary = self.parse_code_expr_primary()?;
 
         while self.peek().is_some() {
@@ -563,25 +721,42 @@ impl ParserV2 {
             primary = self.parse_code_expr_postfix(primary, op)?;
         }
 
+        self.expr_depth = outer_depth;
+
         Ok(primary)
     }
 
+    fn enter_nested_expr(&mut self) -> Result<(), LoError> {
+        if self.expr_depth >= MAX_EXPR_NESTING_DEPTH {
+            return Err(LoError {
+                message: format!(
+                    "Expression is too deeply nested, \
+                        nesting depth limit ({MAX_EXPR_NESTING_DEPTH}) exceeded"
+                ),
+                loc: self.current().loc,
+            });
+        }
+
+        self.expr_depth += 1;
+        Ok(())
+    }
+
     fn parse_code_expr_primary(&mut self) -> Result<CodeExpr, LoError> {
         if let Some(_) = self.eat(Symbol, "return")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let mut expr = None;
             if !self.current().is(Delim, ";") {
                 expr = Some(Box::new(self.parse_code_expr(0)?));
             }
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Return(ReturnExpr { expr, loc }));
         };
 
         if let Some(_) = self.eat(Symbol, "if")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let expr = Box::new(self.parse_code_expr(0)?);
             let then_block = Box::new(self.parse_code_block_expr()?);
@@ -596,7 +771,7 @@ impl ParserV2 {
                 }
             }
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::If(IfExpr {
                 cond: expr,
@@ -607,36 +782,39 @@ impl ParserV2 {
         };
 
         if let Some(_) = self.eat(Symbol, "true")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             return Ok(CodeExpr::BoolLiteral(BoolLiteralExpr { value: true, loc }));
         }
 
         if let Some(_) = self.eat(Symbol, "false")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             return Ok(CodeExpr::BoolLiteral(BoolLiteralExpr { value: false, loc }));
         }
 
         if let Some(char) = self.eat_any(CharLiteral)?.cloned() {
             return Ok(CodeExpr::CharLiteral(CharLiteralExpr {
-                repr: char.value.clone(),
+                repr: String::from(char.value),
                 value: Lexer::parse_char_literal_value(&char.value) as u32,
-                loc: char.loc.clone(),
+                loc: char.loc,
             }));
         };
 
         if let Some(int) = self.eat_any(IntLiteral)?.cloned() {
             let mut tag = None;
-            if let Some(_) = self.eat(Symbol, "u64")? {
-                tag = Some(String::from("u64"));
+            for suffix in INT_LITERAL_SUFFIXES {
+                if self.eat(Symbol, suffix)?.is_some() {
+                    tag = Some(String::from(suffix));
+                    break;
+                }
             }
 
             return Ok(CodeExpr::IntLiteral(IntLiteralExpr {
-                repr: int.value.clone(),
+                repr: String::from(int.value),
                 value: Lexer::parse_int_literal_value(&int.value) as u32,
                 tag,
-                loc: int.loc.clone(),
+                loc: int.loc,
             }));
         };
 
@@ -648,45 +826,45 @@ impl ParserV2 {
 
             return Ok(CodeExpr::StringLiteral(StringLiteralExpr {
                 value: Lexer::unescape_string(&string.value),
-                repr: string.value,
+                repr: String::from(string.value),
                 zero_terminated,
                 loc: string.loc,
             }));
         };
 
         if let Some(_) = self.eat(Delim, "(")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let expr = Box::new(self.parse_code_expr(0)?);
             self.expect(Delim, ")")?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Paren(ParenExpr { expr, loc }));
         };
 
         if let Some(_) = self.eat(Symbol, "let")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let local_name = self.expect_any(Symbol)?.clone();
             self.expect(Operator, "=")?;
             let value = self.parse_code_expr(0)?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Let(LetExpr {
-                local_name: local_name.value,
+                local_name: String::from(local_name.value),
                 value: Box::new(value),
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Symbol, "loop")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let body = self.parse_code_block_expr()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Loop(LoopExpr {
                 body: Box::new(body),
@@ -695,13 +873,13 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Symbol, "break")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             return Ok(CodeExpr::Break(BreakExpr { loc }));
         }
 
         if let Some(_) = self.eat(Symbol, "for")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let counter = self.expect_any(Symbol)?.clone();
             self.expect(Symbol, "in")?;
@@ -710,10 +888,10 @@ impl ParserV2 {
             let end = self.parse_code_expr(0)?;
             let body = self.parse_code_block_expr()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::ForLoop(ForLoopExpr {
-                counter: counter.value,
+                counter: String::from(counter.value),
                 start: Box::new(start),
                 end: Box::new(end),
                 body: Box::new(body),
@@ -722,38 +900,51 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Symbol, "continue")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             return Ok(CodeExpr::Continue(ContinueExpr { loc }));
         }
 
         if let Some(_) = self.eat(Symbol, "dbg")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let message = self.expect_any(StringLiteral)?.clone();
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Dbg(DbgExpr {
-                message: message.value,
+                message: String::from(message.value),
                 loc,
             }));
         }
 
         if let Some(_) = self.eat(Symbol, "unreachable")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
 
             return Ok(CodeExpr::Unreachable(UnreachableExpr { loc }));
         }
 
         if let Some(_) = self.eat(Symbol, "defer")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
+
+            let mut captures = Vec::new();
+            if self.eat(Symbol, "with")?.is_some() {
+                self.expect(Delim, "(")?;
+                while self.eat(Delim, ")")?.is_none() {
+                    captures.push(self.parse_ident()?);
+
+                    if !self.current().is(Delim, ")") {
+                        self.expect(Delim, ",")?;
+                    }
+                }
+            }
 
             let expr = self.parse_code_expr(0)?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Defer(DeferExpr {
+                captures,
                 expr: Box::new(expr),
                 loc,
             }));
@@ -763,18 +954,19 @@ impl ParserV2 {
             if let Some(op) = PrefixOp::parse(token) {
                 self.next(); // skip operator
 
-                let mut loc = self.prev().loc.clone();
+                let mut loc = self.prev().loc;
 
                 let min_bp = op.info.get_min_bp_for_next();
 
                 match op.tag {
                     PrefixOpTag::Dereference
+                    | PrefixOpTag::AddressOf
                     | PrefixOpTag::Not
                     | PrefixOpTag::Positive
                     | PrefixOpTag::Negative => {
                         let expr = Box::new(self.parse_code_expr(min_bp)?);
 
-                        loc.end_pos = self.prev().loc.end_pos.clone();
+                        loc.end_offset = self.prev().loc.end_offset;
 
                         return Ok(CodeExpr::PrefixOp(PrefixOpExpr {
                             expr,
@@ -787,17 +979,28 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Symbol, "sizeof")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
             let type_expr = self.parse_type_expr()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::Sizeof(SizeofExpr { type_expr, loc }));
         };
 
         if let Some(_) = self.eat(Delim, "[")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
+
+            if self.current().is_any(IntLiteral) {
+                let item = Box::new(self.parse_code_expr(0)?);
+                self.expect(Delim, ";")?;
+                let count = Box::new(self.parse_code_expr(0)?);
+                self.expect(Delim, "]")?;
+
+                loc.end_offset = self.prev().loc.end_offset;
+
+                return Ok(CodeExpr::ArrayRepeat(ArrayRepeatExpr { item, count, loc }));
+            }
 
             let item_type = self.parse_type_expr()?;
             self.expect(Delim, "]")?;
@@ -813,7 +1016,7 @@ impl ParserV2 {
                 }
             }
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::ArrayLiteral(ArrayLiteralExpr {
                 item_type,
@@ -823,17 +1026,35 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Operator, "@")? {
-            let mut loc = self.prev().loc.clone();
+            let mut loc = self.prev().loc;
 
-            self.expect(Symbol, "data_size")?;
+            let builtin = self.expect_any(Symbol)?.value;
+            if builtin == "data_size" {
+                loc.end_offset = self.prev().loc.end_offset;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+                return Ok(CodeExpr::GetDataSize(GetDataSizeExpr { loc }));
+            }
 
-            return Ok(CodeExpr::GetDataSize(GetDataSizeExpr { loc }));
+            self.expect(Delim, "(")?;
+            let type_expr = self.parse_type_expr()?;
+            let mut index = None;
+            if self.eat(Delim, ",")?.is_some() {
+                index = Some(Box::new(self.parse_code_expr(0)?));
+            }
+            self.expect(Delim, ")")?;
+
+            loc.end_offset = self.prev().loc.end_offset;
+
+            return Ok(CodeExpr::Reflection(ReflectionExpr {
+                builtin: String::from(builtin),
+                type_expr,
+                index,
+                loc,
+            }));
         }
 
         if let Some(_) = self.eat(Operator, ".")? {
-            let loc = self.prev().loc.clone();
+            let loc = self.prev().loc;
             let struct_name = self.parse_ident()?;
             let struct_literal = self.parse_struct_literal(struct_name, loc)?;
             return Ok(CodeExpr::StructLiteral(struct_literal));
@@ -841,12 +1062,36 @@ impl ParserV2 {
 
         let ident = self.parse_ident()?;
 
+        if ident.repr == "__bitcast" {
+            let mut loc = ident.loc;
+
+            self.expect(Operator, "<")?;
+            let from_type = self.parse_type_expr()?;
+            self.expect(Delim, ",")?;
+            let to_type = self.parse_type_expr()?;
+            self.expect(Operator, ">")?;
+
+            self.expect(Delim, "(")?;
+            let expr = Box::new(self.parse_code_expr(0)?);
+            self.eat(Delim, ",")?; // optional
+            self.expect(Delim, ")")?;
+
+            loc.end_offset = self.prev().loc.end_offset;
+
+            return Ok(CodeExpr::Bitcast(BitcastExpr {
+                from_type,
+                to_type,
+                expr,
+                loc,
+            }));
+        }
+
         if self.current().is(Delim, "(") {
-            let mut loc = ident.loc.clone();
+            let mut loc = ident.loc;
 
             let args = self.parse_fn_args()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::FnCall(FnCallExpr {
                 fn_name: ident,
@@ -856,12 +1101,12 @@ impl ParserV2 {
         }
 
         if let Some(_) = self.eat(Operator, "!")? {
-            let mut loc = ident.loc.clone();
+            let mut loc = ident.loc;
 
             let type_args = self.parse_macro_type_args()?;
             let args = self.parse_fn_args()?;
 
-            loc.end_pos = self.prev().loc.end_pos.clone();
+            loc.end_offset = self.prev().loc.end_offset;
 
             return Ok(CodeExpr::MacroFnCall(MacroFnCallExpr {
                 fn_name: ident,
@@ -878,12 +1123,12 @@ impl ParserV2 {
         let mut ident = IdentExpr {
             repr: String::new(),
             parts: Vec::new(),
-            loc: self.current().loc.clone(),
+            loc: self.current().loc,
         };
 
         loop {
             let ident_part = self.expect_any(Symbol)?;
-            ident.parts.push(ident_part.value.clone());
+            ident.parts.push(String::from(ident_part.value));
             ident.repr += ident_part.value.as_str();
 
             if let Some(_) = self.eat(Operator, "::")? {
@@ -894,11 +1139,132 @@ impl ParserV2 {
             break;
         }
 
-        ident.loc.end_pos = self.prev().loc.end_pos.clone();
+        ident.loc.end_offset = self.prev().loc.end_offset;
 
         Ok(ident)
     }
 
+    fn parse_const_group(
+        &mut self,
+        is_flags: bool,
+        mut loc: LoLocation,
+    ) -> Result<ConstGroupExpr, LoError> {
+        let group_name = self.parse_ident()?;
+
+        let mut members = Vec::new();
+        self.expect(Delim, "{")?;
+        while self.eat(Delim, "}")?.is_none() {
+            let member_name = self.expect_any(Symbol)?;
+            let mut member_loc = member_name.loc;
+            let member_name = member_name.value;
+
+            let mut value = None;
+            if self.eat(Operator, "=")?.is_some() {
+                value = Some(self.parse_code_expr(0)?);
+            }
+            member_loc.end_offset = self.prev().loc.end_offset;
+
+            members.push(ConstGroupMember {
+                member_name: String::from(member_name),
+                value,
+                loc: member_loc,
+            });
+
+            if !self.current().is(Delim, "}") {
+                self.expect(Delim, ",")?;
+            }
+        }
+
+        loc.end_offset = self.prev().loc.end_offset;
+
+        Ok(ConstGroupExpr {
+            is_flags,
+            group_name,
+            members,
+            loc,
+        })
+    }
+
+    fn parse_struct_def(
+        &mut self,
+        attributes: Vec<AttributeExpr>,
+        mut loc: LoLocation,
+    ) -> Result<StructDefExpr, LoError> {
+        let struct_name = self.parse_ident()?;
+        let fields = self.parse_struct_def_fields()?;
+
+        loc.end_offset = self.prev().loc.end_offset;
+
+        Ok(StructDefExpr {
+            attributes,
+            struct_name,
+            fields,
+            doc: None,
+            loc,
+        })
+    }
+
+    fn parse_struct_def_fields(&mut self) -> Result<Vec<StructDefField>, LoError> {
+        let mut fields = Vec::new();
+
+        self.expect(Delim, "{")?;
+        while let None = self.eat(Delim, "}")? {
+            let mut field_loc = self.current().loc;
+
+            let is_private = self.eat(Symbol, "private")?.is_some();
+            let field_name = self.expect_any(Symbol)?.clone();
+            self.expect(Operator, ":")?;
+            let field_type = self.parse_type_expr()?;
+
+            field_loc.end_offset = self.prev().loc.end_offset;
+
+            fields.push(StructDefField {
+                is_private,
+                field_name: String::from(field_name.value),
+                field_type,
+                loc: field_loc,
+            });
+
+            if !self.current().is(Delim, "}") {
+                self.expect(Delim, ",")?;
+            }
+        }
+
+        Ok(fields)
+    }
+
+    fn parse_attributes(&mut self) -> Result<Vec<AttributeExpr>, LoError> {
+        let mut attributes = Vec::new();
+        while self.eat(Operator, "@")?.is_some() {
+            let mut loc = self.prev().loc;
+            let name = self.expect_any(Symbol)?.value;
+
+            let mut args = Vec::new();
+            if self.eat(Delim, "(")?.is_some() {
+                while self.eat(Delim, ")")?.is_none() {
+                    let arg = match self.eat_any(IntLiteral)? {
+                        Some(int) => int.value,
+                        None => self.expect_any(Symbol)?.value,
+                    };
+                    args.push(String::from(arg));
+
+                    if !self.current().is(Delim, ")") {
+                        self.expect(Delim, ",")?;
+                    }
+                }
+            }
+
+            loc.end_offset = self.prev().loc.end_offset;
+            attributes.push(AttributeExpr {
+                name: String::from(name),
+                args,
+                loc,
+            });
+        }
+
+        Ok(attributes)
+    }
+
     fn parse_struct_literal(
         &mut self,
         ident: IdentExpr,
@@ -908,16 +1274,16 @@ impl ParserV2 {
 
         self.expect(Delim, "{")?;
         while let None = self.eat(Delim, "}")? {
-            let mut field_loc = self.current().loc.clone();
+            let mut field_loc = self.current().loc;
 
             let field_name = self.expect_any(Symbol)?.clone();
             self.expect(Operator, ":")?;
             let value = self.parse_code_expr(0)?;
 
-            field_loc.end_pos = self.prev().loc.end_pos.clone();
+            field_loc.end_offset = self.prev().loc.end_offset;
 
             fields.push(StructLiteralField {
-                field_name: field_name.value,
+                field_name: String::from(field_name.value),
                 value,
                 loc: field_loc,
             });
@@ -927,7 +1293,7 @@ impl ParserV2 {
             }
         }
 
-        loc.end_pos = self.prev().loc.end_pos.clone();
+        loc.end_offset = self.prev().loc.end_offset;
 
         return Ok(StructLiteralExpr {
             struct_name: ident,
@@ -1005,8 +1371,8 @@ impl ParserV2 {
                 let lhs = primary;
                 let rhs = self.parse_code_expr(min_bp)?;
 
-                let mut loc = lhs.loc().clone();
-                loc.end_pos = rhs.loc().end_pos.clone();
+                let mut loc = *lhs.loc();
+                loc.end_offset = rhs.loc().end_offset;
 
                 Ok(CodeExpr::InfixOp(InfixOpExpr {
                     op_tag: op.tag,
@@ -1016,11 +1382,11 @@ impl ParserV2 {
                 }))
             }
             InfixOpTag::Cast => {
-                let mut loc = primary.loc().clone();
+                let mut loc = *primary.loc();
 
                 let casted_to = self.parse_type_expr()?;
 
-                loc.end_pos = self.prev().loc.end_pos.clone();
+                loc.end_offset = self.prev().loc.end_offset;
 
                 Ok(CodeExpr::Cast(CastExpr {
                     expr: Box::new(primary),
@@ -1029,14 +1395,14 @@ impl ParserV2 {
                 }))
             }
             InfixOpTag::FieldAccess => {
-                let mut loc = primary.loc().clone();
+                let mut loc = *primary.loc();
 
                 let field_name = self.parse_ident()?;
 
                 if self.current().is(Delim, "(") {
                     let args = self.parse_fn_args()?;
 
-                    loc.end_pos = self.prev().loc.end_pos.clone();
+                    loc.end_offset = self.prev().loc.end_offset;
 
                     return Ok(CodeExpr::MethodCall(MethodCallExpr {
                         lhs: Box::new(primary),
@@ -1050,7 +1416,7 @@ impl ParserV2 {
                     let type_args = self.parse_macro_type_args()?;
                     let args = self.parse_fn_args()?;
 
-                    loc.end_pos = self.prev().loc.end_pos.clone();
+                    loc.end_offset = self.prev().loc.end_offset;
 
                     return Ok(CodeExpr::MacroMethodCall(MacroMethodCallExpr {
                         lhs: Box::new(primary),
@@ -1061,7 +1427,7 @@ impl ParserV2 {
                     }));
                 }
 
-                loc.end_pos = self.prev().loc.end_pos.clone();
+                loc.end_offset = self.prev().loc.end_offset;
 
                 Ok(CodeExpr::FieldAccess(FieldAccessExpr {
                     lhs: Box::new(primary),
@@ -1069,13 +1435,27 @@ impl ParserV2 {
                     loc,
                 }))
             }
+            InfixOpTag::Index => {
+                let mut loc = *primary.loc();
+
+                let index = self.parse_code_expr(0)?;
+                self.expect(Delim, "]")?;
+
+                loc.end_offset = self.prev().loc.end_offset;
+
+                Ok(CodeExpr::Index(IndexExpr {
+                    lhs: Box::new(primary),
+                    index: Box::new(index),
+                    loc,
+                }))
+            }
             InfixOpTag::Assign => {
-                let mut loc = primary.loc().clone();
+                let mut loc = *primary.loc();
 
                 // TODO: validate that this is a proper lhs for assignment
                 let value = self.parse_code_expr(min_bp)?;
 
-                loc.end_pos = self.prev().loc.end_pos.clone();
+                loc.end_offset = self.prev().loc.end_offset;
 
                 Ok(CodeExpr::Assign(AssignExpr {
                     lhs: Box::new(primary),
@@ -1084,23 +1464,39 @@ impl ParserV2 {
                 }))
             }
             InfixOpTag::Catch => {
-                let mut loc = primary.loc().clone();
-
-                let error_bind = self.expect_any(Symbol)?.clone();
+                let mut loc = *primary.loc();
+
+                let error_bind = if self.eat(Delim, "{")?.is_some() {
+                    let mut fields = Vec::new();
+                    while self.eat(Delim, "}")?.is_none() {
+                        fields.push(String::from(self.expect_any(Symbol)?.value));
+                        if !self.current().is(Delim, "}") {
+                            self.expect(Delim, ",")?;
+                        }
+                    }
+                    CatchBind::Fields(fields)
+                } else {
+                    CatchBind::Ident(String::from(self.expect_any(Symbol)?.value))
+                };
+                let mut error_type = None;
+                if self.eat(Operator, ":")?.is_some() {
+                    error_type = Some(self.parse_type_expr()?);
+                }
                 let catch_body = self.parse_code_block_expr()?;
 
-                loc.end_pos = self.prev().loc.end_pos.clone();
+                loc.end_offset = self.prev().loc.end_offset;
 
                 Ok(CodeExpr::Catch(CatchExpr {
                     lhs: Box::new(primary),
-                    error_bind: error_bind.value,
+                    error_bind,
+                    error_type,
                     catch_body,
                     loc,
                 }))
             }
             InfixOpTag::ErrorPropagation => {
-                let mut loc = primary.loc().clone();
-                loc.end_pos = self.prev().loc.end_pos.clone();
+                let mut loc = *primary.loc();
+                loc.end_offset = self.prev().loc.end_offset;
 
                 Ok(CodeExpr::PropagateError(PropagateErrorExpr {
                     expr: Box::new(primary),
@@ -1119,7 +1515,7 @@ impl ParserV2 {
                 let unexpected = other.unwrap_or(&self.terminal_token);
                 Err(LoError {
                     message: format!("Unexpected token '{}', wanted {type_:?}", unexpected.value),
-                    loc: unexpected.loc.clone(),
+                    loc: unexpected.loc,
                 })
             }
         }
@@ -1132,7 +1528,7 @@ impl ParserV2 {
                 let unexpected = other.unwrap_or(&self.terminal_token);
                 Err(LoError {
                     message: format!("Unexpected token '{}', wanted '{value}'", unexpected.value),
-                    loc: unexpected.loc.clone(),
+                    loc: unexpected.loc,
                 })
             }
         }
@@ -1,12 +1,13 @@
 use core::usize;
 
 use crate::{ast::*, core::*};
-use alloc::{rc::Rc, string::ToString, vec::Vec};
+use alloc::{format, rc::Rc, string::ToString, vec::Vec};
 
 pub struct Printer {
     ast: Rc<AST>,
     indent: usize,
     comments_printed: usize,
+    expansion_depth: usize, // expanded code comes from other places, so comments are skipped
 }
 
 impl Printer {
@@ -15,6 +16,7 @@ impl Printer {
             ast,
             indent: 0,
             comments_printed: 0,
+            expansion_depth: 0,
         };
 
         stdout_enable_bufferring();
@@ -22,15 +24,116 @@ impl Printer {
         stdout_disable_bufferring();
     }
 
+    pub fn print_docs(ast: Rc<AST>) {
+        let mut printer = Printer {
+            // comments are not a part of the docs
+            comments_printed: ast.comments.len(),
+            ast,
+            indent: 0,
+            expansion_depth: 0,
+        };
+
+        stdout_enable_bufferring();
+        printer.print_docs_file();
+        stdout_disable_bufferring();
+    }
+
+    fn print_docs_file(&mut self) {
+        for expr in self.ast.clone().exprs.iter() {
+            let (kind, name, doc) = match expr {
+                // fns are a part of the public API only when exported
+                TopLevelExpr::FnDef(fn_def) if !fn_def.exported => continue,
+                TopLevelExpr::FnDef(fn_def) => ("fn", &fn_def.decl.fn_name, &fn_def.doc),
+                TopLevelExpr::StructDef(struct_def) => {
+                    ("struct", &struct_def.struct_name, &struct_def.doc)
+                }
+                TopLevelExpr::UnionDef(union_def) => {
+                    ("union", &union_def.struct_name, &union_def.doc)
+                }
+                TopLevelExpr::ConstDef(const_def) => {
+                    ("const", &const_def.const_name, &const_def.doc)
+                }
+                _ => continue,
+            };
+
+            stdout_write("## ");
+            stdout_write(kind);
+            stdout_write(" ");
+            stdout_writeln(&name.repr);
+            stdout_writeln("");
+
+            stdout_writeln("```lo");
+            match expr {
+                TopLevelExpr::FnDef(FnDefExpr { exported, decl, .. }) => {
+                    if *exported {
+                        stdout_write("export ");
+                    }
+                    self.print_fn_decl(decl);
+                    stdout_writeln(";");
+                }
+                TopLevelExpr::StructDef(StructDefExpr {
+                    attributes,
+                    struct_name,
+                    fields,
+                    ..
+                })
+                | TopLevelExpr::UnionDef(StructDefExpr {
+                    attributes,
+                    struct_name,
+                    fields,
+                    ..
+                }) => {
+                    self.print_attributes(attributes);
+                    stdout_write(kind);
+                    stdout_write(" ");
+                    stdout_write(&struct_name.repr);
+                    stdout_writeln(" {");
+                    self.indent += 1;
+                    for field in fields {
+                        self.print_indent();
+                        if field.is_private {
+                            stdout_write("private ");
+                        }
+                        stdout_write(&field.field_name);
+                        stdout_write(": ");
+                        self.print_type_expr(&field.field_type);
+                        stdout_writeln(",");
+                    }
+                    self.indent -= 1;
+                    stdout_writeln("};");
+                }
+                TopLevelExpr::ConstDef(ConstDefExpr {
+                    const_name,
+                    const_value,
+                    ..
+                }) => {
+                    stdout_write("const ");
+                    stdout_write(&const_name.repr);
+                    stdout_write(" = ");
+                    self.print_code_expr(const_value);
+                    stdout_writeln(";");
+                }
+                _ => {}
+            }
+            stdout_writeln("```");
+            stdout_writeln("");
+
+            if let Some(doc) = doc {
+                stdout_writeln(doc);
+                stdout_writeln("");
+            }
+        }
+    }
+
     // TODO: print all function declarations first in C mode
     fn print_file(&mut self) {
         for (expr, i) in self.ast.clone().exprs.iter().zip(0..) {
-            self.print_comments_before_pos(expr.loc().pos.offset);
+            self.print_comments_before_pos(expr.loc().offset);
             self.print_top_level_expr(expr, i);
         }
 
         // print the rest of the comments
-        self.print_comments_before_pos(usize::MAX);
+        self.print_comments_before_pos(u32::MAX);
     }
 
     fn print_top_level_expr(&mut self, expr: &TopLevelExpr, expr_index: usize) {
@@ -39,6 +142,7 @@ impl Printer {
                 exported,
                 decl,
                 body,
+                doc: _,
                 loc: _,
             }) => {
                 if *exported {
@@ -69,11 +173,16 @@ impl Printer {
                 self.indent += 1;
 
                 for (item, i) in items.iter().zip(0..) {
-                    self.print_comments_before_pos(item.loc().pos.offset);
+                    self.print_comments_before_pos(item.loc().offset);
                     self.print_indent();
                     match item {
                         ImportItem::FnDecl(decl) => self.print_fn_decl(decl),
                         ImportItem::Memory(memory_def) => self.print_memory_def(memory_def),
+                        ImportItem::Table(table_import) => {
+                            stdout_write("table ");
+                            stdout_write(&table_import.table_name);
+                            stdout_write(": funcref");
+                        }
                     }
                     stdout_writeln(";");
                     if i != items.len() - 1 {
@@ -82,7 +191,7 @@ impl Printer {
                 }
 
                 // print the rest of the comments
-                self.print_comments_before_pos(loc.end_pos.offset);
+                self.print_comments_before_pos(loc.end_offset);
 
                 self.indent -= 1;
                 self.print_indent();
@@ -100,44 +209,45 @@ impl Printer {
                 stdout_writeln(";");
             }
             TopLevelExpr::StructDef(StructDefExpr {
+                attributes,
                 struct_name,
                 fields,
+                doc: _,
                 loc,
             }) => {
+                self.print_attributes(attributes);
                 stdout_write("struct ");
                 stdout_write(&struct_name.repr);
 
-                if fields.len() == 0 {
-                    stdout_writeln(" {};");
-                } else {
-                    stdout_writeln(" {");
-                    self.indent += 1;
-                    for field in fields {
-                        self.print_comments_before_pos(field.loc.pos.offset);
-                        self.print_indent();
-                        stdout_write(&field.field_name);
-                        stdout_write(": ");
-                        self.print_type_expr(&field.field_type);
-                        stdout_writeln(",");
-                    }
-
-                    // print the rest of the comments
-                    self.print_comments_before_pos(loc.end_pos.offset);
-
-                    self.indent -= 1;
-                    self.print_indent();
+                stdout_write(" ");
+                self.print_struct_def_fields(fields, loc);
+                stdout_writeln(";");
+            }
+            TopLevelExpr::UnionDef(StructDefExpr {
+                struct_name,
+                fields,
+                loc,
+                ..
+            }) => {
+                stdout_write("union ");
+                stdout_write(&struct_name.repr);
 
-                    stdout_writeln("};");
-                }
+                stdout_write(" ");
+                self.print_struct_def_fields(fields, loc);
+                stdout_writeln(";");
             }
             TopLevelExpr::TypeDef(TypeDefExpr {
                 type_name,
+                is_distinct,
                 type_value,
                 loc: _,
             }) => {
                 stdout_write("type ");
                 stdout_write(&type_name.repr);
                 stdout_write(" = ");
+                if *is_distinct {
+                    stdout_write("distinct ");
+                }
                 self.print_type_expr(type_value);
                 stdout_writeln(";");
 
@@ -148,6 +258,7 @@ impl Printer {
             TopLevelExpr::ConstDef(ConstDefExpr {
                 const_name,
                 const_value,
+                doc: _,
                 loc: _,
             }) => {
                 stdout_write("const ");
@@ -160,10 +271,42 @@ impl Printer {
                     return;
                 }
             }
+            TopLevelExpr::ConstGroup(const_group) => {
+                if const_group.is_flags {
+                    stdout_write("const flags ");
+                } else {
+                    stdout_write("const group ");
+                }
+                self.print_const_group(const_group);
+            }
+            TopLevelExpr::FlagsDef(flags_def) => {
+                stdout_write("flags ");
+                self.print_const_group(flags_def);
+            }
             TopLevelExpr::MemoryDef(memory_def) => {
                 self.print_memory_def(memory_def);
                 stdout_writeln(";");
             }
+            TopLevelExpr::TableDef(TableDefExpr {
+                exported,
+                table_name,
+                fn_names,
+                loc: _,
+            }) => {
+                if *exported {
+                    stdout_write("export ");
+                }
+                stdout_write("table ");
+                stdout_write(table_name);
+                stdout_write(" = [");
+                for (fn_name, i) in fn_names.iter().zip(0..) {
+                    if i != 0 {
+                        stdout_write(", ");
+                    }
+                    stdout_write(&fn_name.repr);
+                }
+                stdout_writeln("];");
+            }
             TopLevelExpr::StaticDataStore(StaticDataStoreExpr { addr, data, loc: _ }) => {
                 stdout_write("*");
                 self.print_code_expr(addr);
@@ -172,8 +315,31 @@ impl Printer {
                     StaticDataStorePayload::String { value } => {
                         stdout_write(value);
                     }
+                    StaticDataStorePayload::Bytes { value } => self.print_code_expr(value),
+                }
+                stdout_writeln(";");
+            }
+            TopLevelExpr::CustomSection(CustomSectionExpr {
+                section_name,
+                data,
+                loc: _,
+            }) => {
+                stdout_write("custom_section ");
+                stdout_write(section_name);
+                stdout_write(" = ");
+                match data {
+                    CustomSectionPayload::String { value } => stdout_write(value),
+                    CustomSectionPayload::Embed { file_path } => {
+                        stdout_write("@embed ");
+                        stdout_write(file_path);
+                    }
+                    CustomSectionPayload::Bytes { value } => self.print_code_expr(value),
                 }
                 stdout_writeln(";");
+
+                if let Some(TopLevelExpr::CustomSection(_)) = self.ast.exprs.get(expr_index + 1) {
+                    return;
+                }
             }
             TopLevelExpr::ExportExistingFn(ExportExistingFnExpr {
                 in_fn_name,
@@ -248,8 +414,11 @@ impl Printer {
                 FnParamType::Self_ => {
                     stdout_write(&fn_param.param_name);
                 }
-                FnParamType::SelfRef => {
+                FnParamType::SelfRef { is_readonly } => {
                     stdout_write("&");
+                    if *is_readonly {
+                        stdout_write("readonly ");
+                    }
                     stdout_write(&fn_param.param_name);
                 }
                 FnParamType::Type { expr } => {
@@ -262,12 +431,44 @@ impl Printer {
         stdout_write(")");
     }
 
+    fn print_const_group(
+        &mut self,
+        ConstGroupExpr {
+            is_flags: _,
+            group_name,
+            members,
+            loc,
+        }: &ConstGroupExpr,
+    ) {
+        stdout_write(&group_name.repr);
+        stdout_writeln(" {");
+        self.indent += 1;
+        for member in members {
+            self.print_comments_before_pos(member.loc.offset);
+            self.print_indent();
+            stdout_write(&member.member_name);
+            if let Some(value) = &member.value {
+                stdout_write(" = ");
+                self.print_code_expr(value);
+            }
+            stdout_writeln(",");
+        }
+
+        // print the rest of the comments
+        self.print_comments_before_pos(loc.end_offset);
+
+        self.indent -= 1;
+        stdout_writeln("};");
+    }
+
     fn print_memory_def(
         &mut self,
         MemoryDefExpr {
             exported,
             min_pages,
+            max_pages,
             data_start,
+            shared,
             loc: _,
         }: &MemoryDefExpr,
     ) {
@@ -282,27 +483,61 @@ impl Printer {
             stdout_write(min_pages.to_string());
             stdout_writeln(",");
         }
+        if let Some(max_pages) = max_pages {
+            self.print_indent();
+            stdout_write("max_pages: ");
+            stdout_write(max_pages.to_string());
+            stdout_writeln(",");
+        }
         if let Some(data_start) = data_start {
             self.print_indent();
             stdout_write("data_start: ");
             stdout_write(data_start.to_string());
             stdout_writeln(",");
         }
+        if *shared {
+            self.print_indent();
+            stdout_writeln("shared: true,");
+        }
         self.indent -= 1;
         self.print_indent();
         stdout_write("}");
     }
 
+    fn print_attributes(&mut self, attributes: &[AttributeExpr]) {
+        for attribute in attributes {
+            stdout_write("@");
+            stdout_write(&attribute.name);
+            if !attribute.args.is_empty() {
+                stdout_write("(");
+                stdout_write(attribute.args.join(", "));
+                stdout_write(")");
+            }
+            stdout_write(" ");
+        }
+    }
+
     fn print_type_expr(&mut self, type_expr: &TypeExpr) {
         match type_expr {
-            TypeExpr::Pointer { pointee } => {
+            TypeExpr::Pointer {
+                pointee,
+                is_readonly,
+            } => {
                 stdout_write("&");
+                if *is_readonly {
+                    stdout_write("readonly ");
+                }
                 self.print_type_expr(pointee);
             }
             TypeExpr::SequencePointer { pointee } => {
                 stdout_write("*&");
                 self.print_type_expr(pointee);
             }
+            TypeExpr::Slice { item_type } => {
+                stdout_write("&[");
+                self.print_type_expr(item_type);
+                stdout_write("]");
+            }
             TypeExpr::Named { name } => {
                 stdout_write(&name.repr);
             }
@@ -313,6 +548,14 @@ impl Printer {
                 self.print_type_expr(err_type);
                 stdout_write(">");
             }
+            TypeExpr::ErrorUnion { variants } => {
+                for (i, variant) in variants.iter().enumerate() {
+                    if i > 0 {
+                        stdout_write(" | ");
+                    }
+                    self.print_type_expr(variant);
+                }
+            }
             TypeExpr::Of {
                 container_type,
                 item_type,
@@ -321,23 +564,58 @@ impl Printer {
                 stdout_write(" of ");
                 self.print_type_expr(item_type);
             }
+            TypeExpr::Struct { fields, loc } => {
+                stdout_write("struct ");
+                self.print_struct_def_fields(fields, loc);
+            }
+        }
+    }
+
+    fn print_struct_def_fields(&mut self, fields: &Vec<StructDefField>, loc: &LoLocation) {
+        if fields.is_empty() {
+            stdout_write("{}");
+            return;
+        }
+
+        stdout_writeln("{");
+        self.indent += 1;
+        for field in fields {
+            self.print_comments_before_pos(field.loc.offset);
+            self.print_indent();
+            if field.is_private {
+                stdout_write("private ");
+            }
+            stdout_write(&field.field_name);
+            stdout_write(": ");
+            self.print_type_expr(&field.field_type);
+            stdout_writeln(",");
         }
+
+        // print the rest of the comments
+        self.print_comments_before_pos(loc.end_offset);
+
+        self.indent -= 1;
+        self.print_indent();
+        stdout_write("}");
     }
 
     fn print_code_block_expr(&mut self, code_block: &CodeBlockExpr) {
         stdout_writeln("{");
+        self.print_code_block_contents(code_block);
+    }
 
+    fn print_code_block_contents(&mut self, code_block: &CodeBlockExpr) {
         self.indent += 1;
 
         for expr in &code_block.exprs {
-            self.print_comments_before_pos(expr.loc().pos.offset);
+            self.print_comments_before_pos(expr.loc().offset);
             self.print_indent();
             self.print_code_expr(expr);
             stdout_writeln(";");
         }
 
         // print the rest of the comments
-        self.print_comments_before_pos(code_block.loc.end_pos.offset);
+        self.print_comments_before_pos(code_block.loc.end_offset);
 
         self.indent -= 1;
 
@@ -394,17 +672,28 @@ impl Printer {
                 stdout_writeln("[");
                 self.indent += 1;
                 for item in items {
-                    self.print_comments_before_pos(item.loc().pos.offset);
+                    self.print_comments_before_pos(item.loc().offset);
                     self.print_indent();
                     self.print_code_expr(item);
                     stdout_writeln(",");
                 }
                 // print the rest of the comments
-                self.print_comments_before_pos(loc.end_pos.offset);
+                self.print_comments_before_pos(loc.end_offset);
                 self.indent -= 1;
                 self.print_indent();
                 stdout_write("]");
             }
+            CodeExpr::ArrayRepeat(ArrayRepeatExpr {
+                item,
+                count,
+                loc: _,
+            }) => {
+                stdout_write("[");
+                self.print_code_expr(item);
+                stdout_write("; ");
+                self.print_code_expr(count);
+                stdout_write("]");
+            }
 
             CodeExpr::Ident(IdentExpr {
                 repr,
@@ -511,8 +800,22 @@ impl Printer {
                 stdout_write("dbg ");
                 stdout_write(message);
             }
-            CodeExpr::Defer(DeferExpr { expr, loc: _ }) => {
+            CodeExpr::Defer(DeferExpr {
+                captures,
+                expr,
+                loc: _,
+            }) => {
                 stdout_write("defer ");
+                if !captures.is_empty() {
+                    stdout_write("with (");
+                    for (i, capture) in captures.iter().enumerate() {
+                        if i != 0 {
+                            stdout_write(", ");
+                        }
+                        stdout_write(&capture.repr);
+                    }
+                    stdout_write(") ");
+                }
                 self.print_code_expr(expr);
             }
             CodeExpr::Cast(CastExpr {
@@ -534,7 +837,7 @@ impl Printer {
                 stdout_writeln(" {");
                 self.indent += 1;
                 for field in fields {
-                    self.print_comments_before_pos(field.loc.pos.offset);
+                    self.print_comments_before_pos(field.loc.offset);
                     self.print_indent();
                     stdout_write(&field.field_name);
                     stdout_write(": ");
@@ -543,7 +846,7 @@ impl Printer {
                 }
 
                 // print the rest of the comments
-                self.print_comments_before_pos(loc.end_pos.offset);
+                self.print_comments_before_pos(loc.end_offset);
 
                 self.indent -= 1;
                 self.print_indent();
@@ -555,6 +858,12 @@ impl Printer {
                 stdout_write(" = ");
                 self.print_code_expr(rhs);
             }
+            CodeExpr::Index(IndexExpr { lhs, index, loc: _ }) => {
+                self.print_code_expr(lhs);
+                stdout_write("[");
+                self.print_code_expr(index);
+                stdout_write("]");
+            }
             CodeExpr::FieldAccess(FieldAccessExpr {
                 lhs,
                 field_name,
@@ -567,12 +876,24 @@ impl Printer {
             CodeExpr::Catch(CatchExpr {
                 lhs,
                 error_bind,
+                error_type,
                 catch_body,
                 loc: _,
             }) => {
                 self.print_code_expr(lhs);
                 stdout_write(" catch ");
-                stdout_write(error_bind);
+                match error_bind {
+                    CatchBind::Ident(name) => stdout_write(name),
+                    CatchBind::Fields(fields) => {
+                        stdout_write("{ ");
+                        stdout_write(fields.join(", "));
+                        stdout_write(" }");
+                    }
+                }
+                if let Some(error_type) = error_type {
+                    stdout_write(": ");
+                    self.print_type_expr(error_type);
+                }
                 stdout_write(" ");
                 self.print_code_block_expr(catch_body);
             }
@@ -626,6 +947,25 @@ impl Printer {
                 self.print_type_args(type_args);
                 self.print_args(args);
             }
+            CodeExpr::MacroExpansion(MacroExpansionExpr {
+                call,
+                macro_loc,
+                body,
+                loc: _,
+            }) => {
+                stdout_writeln("{");
+                self.expansion_depth += 1;
+
+                self.indent += 1;
+                self.print_indent();
+                stdout_write("// expanded from ");
+                self.print_code_expr(call);
+                stdout_writeln(format!(", defined at {macro_loc}"));
+                self.indent -= 1;
+
+                self.print_code_block_contents(body);
+                self.expansion_depth -= 1;
+            }
             CodeExpr::Sizeof(SizeofExpr { type_expr, loc: _ }) => {
                 stdout_write("sizeof ");
                 self.print_type_expr(type_expr);
@@ -637,6 +977,36 @@ impl Printer {
             CodeExpr::GetDataSize(GetDataSizeExpr { loc: _ }) => {
                 stdout_write("@data_size");
             }
+            CodeExpr::Reflection(ReflectionExpr {
+                builtin,
+                type_expr,
+                index,
+                loc: _,
+            }) => {
+                stdout_write("@");
+                stdout_write(builtin);
+                stdout_write("(");
+                self.print_type_expr(type_expr);
+                if let Some(index) = index {
+                    stdout_write(", ");
+                    self.print_code_expr(index);
+                }
+                stdout_write(")");
+            }
+            CodeExpr::Bitcast(BitcastExpr {
+                from_type,
+                to_type,
+                expr,
+                loc: _,
+            }) => {
+                stdout_write("__bitcast<");
+                self.print_type_expr(from_type);
+                stdout_write(", ");
+                self.print_type_expr(to_type);
+                stdout_write(">(");
+                self.print_code_expr(expr);
+                stdout_write(")");
+            }
         }
     }
 
@@ -668,10 +1038,14 @@ impl Printer {
         stdout_write(">");
     }
 
-    fn print_comments_before_pos(&mut self, offset: usize) {
+    fn print_comments_before_pos(&mut self, offset: u32) {
+        if self.expansion_depth > 0 {
+            return;
+        }
+
         while self.comments_printed < self.ast.comments.len() {
             let comment = &self.ast.comments[self.comments_printed];
-            if comment.loc.end_pos.offset > offset {
+            if comment.loc.end_offset > offset {
                 break;
             }
 
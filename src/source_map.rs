@@ -0,0 +1,108 @@
+use crate::{core::*, wasm::*};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// source map v3 for a wasm module where the generated column is the byte offset
+//   of the instr in the module (there is only one generated line),
+//   sources are embedded so the map works no matter where it is served from
+pub fn generate_source_map(source_locs: &[WasmSourceLoc]) -> String {
+    let mut sources = Vec::<&str>::new();
+    let mut mappings = String::new();
+
+    // every segment field is encoded relative to the previous segment
+    let mut prev_segment = [0i64; 4];
+    for source_loc in source_locs {
+        let loc = &source_loc.loc;
+
+        let file_name = loc.file_name().as_str();
+        let pos = loc.utf16_pos();
+
+        let source_index = match sources.iter().position(|s| *s == file_name) {
+            Some(source_index) => source_index,
+            None => {
+                sources.push(file_name);
+                sources.len() - 1
+            }
+        };
+
+        let segment = [
+            source_loc.code_offset as i64,
+            source_index as i64,
+            pos.line as i64 - 1,
+            pos.col as i64 - 1,
+        ];
+
+        if !mappings.is_empty() {
+            mappings.push(',');
+        }
+        for (value, prev_value) in segment.iter().zip(prev_segment) {
+            write_vlq(&mut mappings, value - prev_value);
+        }
+
+        prev_segment = segment;
+    }
+
+    let mut output = String::from("{\"version\":3,\"sources\":[");
+    for (source, i) in sources.iter().zip(0..) {
+        if i != 0 {
+            output.push(',');
+        }
+        write_json_string(&mut output, source);
+    }
+
+    output.push_str("],\"sourcesContent\":[");
+    for (source, i) in sources.iter().zip(0..) {
+        if i != 0 {
+            output.push(',');
+        }
+        match file_read_utf8(source) {
+            Ok(source_content) => write_json_string(&mut output, &source_content),
+            Err(_) => output.push_str("null"),
+        }
+    }
+
+    write!(&mut output, "],\"names\":[],\"mappings\":\"{mappings}\"}}").unwrap();
+
+    output
+}
+
+fn write_vlq(out: &mut String, value: i64) {
+    // sign is stored in the lowest bit
+    let mut value = if value < 0 {
+        ((-value as u64) << 1) | 1
+    } else {
+        (value as u64) << 1
+    };
+
+    loop {
+        let mut digit = (value & 0b11111) as usize;
+        value >>= 5;
+        if value > 0 {
+            digit |= 0b100000; // continuation bit
+        }
+
+        out.push(BASE64_CHARS[digit] as char);
+
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+pub fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,110 @@
+use crate::wasm::*;
+use alloc::{format, string::String, vec::Vec};
+
+pub const WASI_MODULE_NAME: &str = "wasi_snapshot_preview1";
+
+const I32: WasmType = WasmType::I32;
+const I64: WasmType = WasmType::I64;
+
+// lowered signatures of every preview1 fn: name, params, results,
+//   pointers, lengths, flags and errnos are all i32, offsets, timestamps and rights are i64
+const WASI_FNS: &[(&str, &[WasmType], &[WasmType])] = &[
+    ("args_get", &[I32, I32], &[I32]),
+    ("args_sizes_get", &[I32, I32], &[I32]),
+    ("environ_get", &[I32, I32], &[I32]),
+    ("environ_sizes_get", &[I32, I32], &[I32]),
+    ("clock_res_get", &[I32, I32], &[I32]),
+    ("clock_time_get", &[I32, I64, I32], &[I32]),
+    ("fd_advise", &[I32, I64, I64, I32], &[I32]),
+    ("fd_allocate", &[I32, I64, I64], &[I32]),
+    ("fd_close", &[I32], &[I32]),
+    ("fd_datasync", &[I32], &[I32]),
+    ("fd_fdstat_get", &[I32, I32], &[I32]),
+    ("fd_fdstat_set_flags", &[I32, I32], &[I32]),
+    ("fd_fdstat_set_rights", &[I32, I64, I64], &[I32]),
+    ("fd_filestat_get", &[I32, I32], &[I32]),
+    ("fd_filestat_set_size", &[I32, I64], &[I32]),
+    ("fd_filestat_set_times", &[I32, I64, I64, I32], &[I32]),
+    ("fd_pread", &[I32, I32, I32, I64, I32], &[I32]),
+    ("fd_prestat_get", &[I32, I32], &[I32]),
+    ("fd_prestat_dir_name", &[I32, I32, I32], &[I32]),
+    ("fd_pwrite", &[I32, I32, I32, I64, I32], &[I32]),
+    ("fd_read", &[I32, I32, I32, I32], &[I32]),
+    ("fd_readdir", &[I32, I32, I32, I64, I32], &[I32]),
+    ("fd_renumber", &[I32, I32], &[I32]),
+    ("fd_seek", &[I32, I64, I32, I32], &[I32]),
+    ("fd_sync", &[I32], &[I32]),
+    ("fd_tell", &[I32, I32], &[I32]),
+    ("fd_write", &[I32, I32, I32, I32], &[I32]),
+    ("path_create_directory", &[I32, I32, I32], &[I32]),
+    ("path_filestat_get", &[I32, I32, I32, I32, I32], &[I32]),
+    (
+        "path_filestat_set_times",
+        &[I32, I32, I32, I32, I64, I64, I32],
+        &[I32],
+    ),
+    ("path_link", &[I32, I32, I32, I32, I32, I32, I32], &[I32]),
+    (
+        "path_open",
+        &[I32, I32, I32, I32, I32, I64, I64, I32, I32],
+        &[I32],
+    ),
+    ("path_readlink", &[I32, I32, I32, I32, I32, I32], &[I32]),
+    ("path_remove_directory", &[I32, I32, I32], &[I32]),
+    ("path_rename", &[I32, I32, I32, I32, I32, I32], &[I32]),
+    ("path_symlink", &[I32, I32, I32, I32, I32], &[I32]),
+    ("path_unlink_file", &[I32, I32, I32], &[I32]),
+    ("poll_oneoff", &[I32, I32, I32, I32], &[I32]),
+    ("proc_exit", &[I32], &[]),
+    ("proc_raise", &[I32], &[I32]),
+    ("sched_yield", &[], &[I32]),
+    ("random_get", &[I32, I32], &[I32]),
+    ("sock_accept", &[I32, I32, I32], &[I32]),
+    ("sock_recv", &[I32, I32, I32, I32, I32, I32], &[I32]),
+    ("sock_send", &[I32, I32, I32, I32, I32], &[I32]),
+    ("sock_shutdown", &[I32, I32], &[I32]),
+];
+
+// `None` if the import matches the ABI, otherwise a message describing the mismatch
+pub fn check_wasi_import(fn_name: &str, fn_type: &WasmFnType) -> Option<String> {
+    let Some((_, inputs, outputs)) = WASI_FNS.iter().find(|(name, ..)| *name == fn_name) else {
+        return Some(format!("Unknown WASI function: {fn_name}"));
+    };
+
+    if fn_type.inputs == *inputs && fn_type.outputs == *outputs {
+        return None;
+    }
+
+    Some(format!(
+        "Signature of {fn_name} doesn't match the WASI ABI, \
+            expected {}, got {}",
+        format_signature(inputs, outputs),
+        format_signature(&fn_type.inputs, &fn_type.outputs),
+    ))
+}
+
+// `(i32, i64) -> i32`, fns without results end with `-> ()`
+fn format_signature(inputs: &[WasmType], outputs: &[WasmType]) -> String {
+    let inputs = format_types(inputs);
+    if outputs.len() == 1 {
+        return format!("({inputs}) -> {}", format_types(outputs));
+    }
+
+    format!("({inputs}) -> ({})", format_types(outputs))
+}
+
+fn format_types(types: &[WasmType]) -> String {
+    let type_names: Vec<_> = types
+        .iter()
+        .map(|type_| match type_ {
+            WasmType::I32 => "i32",
+            WasmType::I64 => "i64",
+            WasmType::F32 => "f32",
+            WasmType::F64 => "f64",
+            WasmType::ExternRef => "externref",
+            WasmType::Ref { .. } => "ref",
+        })
+        .collect();
+
+    type_names.join(", ")
+}
@@ -1,16 +1,26 @@
-use ::alloc::{string::String, vec::Vec};
+use crate::{
+    core::{LoLocation, OutputStream},
+    mangling::mangle_name,
+};
+use ::alloc::{format, string::String, vec::Vec};
 
 #[derive(Default, Clone, Debug, PartialEq, PartialOrd)]
 pub struct WasmModule {
+    pub gc_types: Vec<WasmGcType>,
     pub types: Vec<WasmFnType>,
     pub imports: Vec<WasmImport>,
     pub functions: Vec<u32>,
+    pub tables: Vec<WasmLimits>, // all tables hold funcrefs
     pub memories: Vec<WasmLimits>,
     pub globals: Vec<WasmGlobal>,
     pub exports: Vec<WasmExport>,
+    pub start: Option<u32>, // fn index, called on instantiation
+    pub elements: Vec<WasmElement>,
     pub codes: Vec<WasmFn>,
     pub datas: Vec<WasmData>,
     pub debug_fn_info: Vec<WasmDebugFnInfo>,
+    pub custom_sections: Vec<WasmCustomSection>,
+    pub source_map_url: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -19,6 +29,36 @@ pub struct WasmFnType {
     pub outputs: Vec<WasmType>,
 }
 
+// gc proposal types are encoded before fn types, so they are indexed directly
+//   by `WasmType::Ref` and gc instrs while fn type indices are shifted on output
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum WasmGcType {
+    Struct { fields: Vec<WasmFieldType> },
+    Array { item: WasmFieldType },
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct WasmFieldType {
+    pub storage_type: WasmStorageType,
+    pub mutable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum WasmStorageType {
+    I8,
+    Value(WasmType),
+}
+
+impl WasmStorageType {
+    // packed values are extended to i32 on the stack
+    pub fn unpacked(&self) -> WasmType {
+        match self {
+            WasmStorageType::I8 => WasmType::I32,
+            WasmStorageType::Value(value_type) => value_type.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct WasmImport {
     pub module_name: String,
@@ -29,6 +69,7 @@ pub struct WasmImport {
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum WasmImportDesc {
     Func { type_index: u32 },
+    Table(WasmLimits), // of funcrefs
     Memory(WasmLimits),
 }
 
@@ -49,9 +90,10 @@ pub struct WasmExpr {
     pub instrs: Vec<WasmInstr>,
 }
 
+// not all instructions are emitted by the compiler, but all are supported by the evaluator
 #[repr(u8)]
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, dead_code)]
 pub enum WasmBinaryOpKind {
     I32_EQ = 0x46,
     I32_NE = 0x47,
@@ -98,9 +140,12 @@ pub enum WasmBinaryOpKind {
     I32_REM_U = 0x70,
     I32_AND = 0x71,
     I32_OR = 0x72,
+    I32_XOR = 0x73,
     I32_SHL = 0x74,
     I32_SHR_S = 0x75,
     I32_SHR_U = 0x76,
+    I32_ROTL = 0x77,
+    I32_ROTR = 0x78,
 
     I64_ADD = 0x7C,
     I64_SUB = 0x7D,
@@ -111,23 +156,93 @@ pub enum WasmBinaryOpKind {
     I64_REM_U = 0x82,
     I64_AND = 0x83,
     I64_OR = 0x84,
+    I64_XOR = 0x85,
     I64_SHL = 0x86,
     I64_SHR_S = 0x87,
     I64_SHR_U = 0x88,
+    I64_ROTL = 0x89,
+    I64_ROTR = 0x8A,
 
     F32_ADD = 0x92,
     F32_SUB = 0x93,
     F32_MUL = 0x94,
     F32_DIV = 0x95,
+    F32_MIN = 0x96,
+    F32_MAX = 0x97,
+    F32_COPYSIGN = 0x98,
 
     F64_ADD = 0xA0,
     F64_SUB = 0xA1,
     F64_MUL = 0xA2,
     F64_DIV = 0xA3,
+    F64_MIN = 0xA4,
+    F64_MAX = 0xA5,
+    F64_COPYSIGN = 0xA6,
 }
 
+// single operand numeric instructions, including conversions
 #[repr(u8)]
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[allow(non_camel_case_types, dead_code)]
+pub enum WasmUnaryOpKind {
+    I32_EQZ = 0x45,
+    I64_EQZ = 0x50,
+
+    I32_CLZ = 0x67,
+    I32_CTZ = 0x68,
+    I32_POPCNT = 0x69,
+
+    I64_CLZ = 0x79,
+    I64_CTZ = 0x7A,
+    I64_POPCNT = 0x7B,
+
+    F32_ABS = 0x8B,
+    F32_NEG = 0x8C,
+    F32_CEIL = 0x8D,
+    F32_FLOOR = 0x8E,
+    F32_TRUNC = 0x8F,
+    F32_NEAREST = 0x90,
+    F32_SQRT = 0x91,
+
+    F64_ABS = 0x99,
+    F64_NEG = 0x9A,
+    F64_CEIL = 0x9B,
+    F64_FLOOR = 0x9C,
+    F64_TRUNC = 0x9D,
+    F64_NEAREST = 0x9E,
+    F64_SQRT = 0x9F,
+
+    I32_TRUNC_F32_S = 0xA8,
+    I32_TRUNC_F32_U = 0xA9,
+    I32_TRUNC_F64_S = 0xAA,
+    I32_TRUNC_F64_U = 0xAB,
+
+    I64_TRUNC_F32_S = 0xAE,
+    I64_TRUNC_F32_U = 0xAF,
+    I64_TRUNC_F64_S = 0xB0,
+    I64_TRUNC_F64_U = 0xB1,
+
+    F32_CONVERT_I32_S = 0xB2,
+    F32_CONVERT_I32_U = 0xB3,
+    F32_CONVERT_I64_S = 0xB4,
+    F32_CONVERT_I64_U = 0xB5,
+    F32_DEMOTE_F64 = 0xB6,
+
+    F64_CONVERT_I32_S = 0xB7,
+    F64_CONVERT_I32_U = 0xB8,
+    F64_CONVERT_I64_S = 0xB9,
+    F64_CONVERT_I64_U = 0xBA,
+    F64_PROMOTE_F32 = 0xBB,
+
+    I32_REINTERPRET_F32 = 0xBC,
+    I64_REINTERPRET_F64 = 0xBD,
+    F32_REINTERPRET_I32 = 0xBE,
+    F64_REINTERPRET_I64 = 0xBF,
+}
+
+#[repr(u8)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[allow(dead_code)]
 pub enum WasmLoadKind {
     I32 = 0x28,
     I64 = 0x29,
@@ -137,6 +252,12 @@ pub enum WasmLoadKind {
     I32U8 = 0x2D,
     I32I16 = 0x2E,
     I32U16 = 0x2F,
+    I64I8 = 0x30,
+    I64U8 = 0x31,
+    I64I16 = 0x32,
+    I64U16 = 0x33,
+    I64I32 = 0x34,
+    I64U32 = 0x35,
 }
 
 #[repr(u8)]
@@ -148,6 +269,45 @@ pub enum WasmStoreKind {
     F64 = 0x39,
     I32U8 = 0x3A,
     I32U16 = 0x3B,
+    I64U8 = 0x3C,
+    I64U16 = 0x3D,
+    I64U32 = 0x3E,
+}
+
+impl WasmStoreKind {
+    // position within each group of atomic opcodes, `None` for kinds without atomic variants
+    pub fn atomic_index(&self) -> Option<u8> {
+        match self {
+            WasmStoreKind::I32 => Some(0),
+            WasmStoreKind::I64 => Some(1),
+            WasmStoreKind::I32U8 => Some(2),
+            WasmStoreKind::I32U16 => Some(3),
+            WasmStoreKind::I64U8 => Some(4),
+            WasmStoreKind::I64U16 => Some(5),
+            WasmStoreKind::I64U32 => Some(6),
+            WasmStoreKind::F32 | WasmStoreKind::F64 => None,
+        }
+    }
+
+    // log2 of the access size, atomic accesses must use exactly this alignment
+    pub fn natural_align(&self) -> u32 {
+        match self {
+            WasmStoreKind::I32U8 | WasmStoreKind::I64U8 => 0,
+            WasmStoreKind::I32U16 | WasmStoreKind::I64U16 => 1,
+            WasmStoreKind::I32 | WasmStoreKind::F32 | WasmStoreKind::I64U32 => 2,
+            WasmStoreKind::I64 | WasmStoreKind::F64 => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum WasmAtomicRmwOp {
+    Add = 0x1E,
+    Sub = 0x25,
+    And = 0x2C,
+    Or = 0x33,
+    Xor = 0x3A,
+    Xchg = 0x41,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -157,6 +317,23 @@ pub enum WasmBlockType {
     InOut { type_index: u32 },
 }
 
+impl WasmLoadKind {
+    // zero extending load of the same width
+    pub fn from_store_kind(kind: &WasmStoreKind) -> Self {
+        match kind {
+            WasmStoreKind::I32 => Self::I32,
+            WasmStoreKind::I64 => Self::I64,
+            WasmStoreKind::F32 => Self::F32,
+            WasmStoreKind::F64 => Self::F64,
+            WasmStoreKind::I32U8 => Self::I32U8,
+            WasmStoreKind::I32U16 => Self::I32U16,
+            WasmStoreKind::I64U8 => Self::I64U8,
+            WasmStoreKind::I64U16 => Self::I64U16,
+            WasmStoreKind::I64U32 => Self::I64U32,
+        }
+    }
+}
+
 impl WasmStoreKind {
     pub fn from_load_kind(kind: &WasmLoadKind) -> Self {
         match kind {
@@ -168,6 +345,12 @@ impl WasmStoreKind {
             WasmLoadKind::I32U8 => Self::I32U8,
             WasmLoadKind::I32I16 => Self::I32U16,
             WasmLoadKind::I32U16 => Self::I32U16,
+            WasmLoadKind::I64I8 => Self::I64U8,
+            WasmLoadKind::I64U8 => Self::I64U8,
+            WasmLoadKind::I64I16 => Self::I64U16,
+            WasmLoadKind::I64U16 => Self::I64U16,
+            WasmLoadKind::I64I32 => Self::I64U32,
+            WasmLoadKind::I64U32 => Self::I64U32,
         }
     }
 }
@@ -179,9 +362,39 @@ pub enum WasmInstr {
     BinaryOp {
         kind: WasmBinaryOpKind,
     },
+    #[allow(dead_code)]
+    UnaryOp {
+        kind: WasmUnaryOpKind,
+    },
     MemorySize,
     MemoryGrow,
     MemoryCopy,
+    // threads proposal, atomic accesses are always naturally aligned
+    AtomicLoad {
+        kind: WasmLoadKind,
+        offset: u32,
+    },
+    AtomicStore {
+        kind: WasmStoreKind,
+        offset: u32,
+    },
+    AtomicRmw {
+        op: WasmAtomicRmwOp,
+        kind: WasmStoreKind,
+        offset: u32,
+    },
+    AtomicCmpxchg {
+        kind: WasmStoreKind,
+        offset: u32,
+    },
+    MemoryAtomicWait {
+        value_type: WasmType,
+        offset: u32,
+    },
+    MemoryAtomicNotify {
+        offset: u32,
+    },
+    AtomicFence,
     I32Const {
         value: i32,
     },
@@ -206,6 +419,9 @@ pub enum WasmInstr {
     LocalSet {
         local_index: u32,
     },
+    LocalTee {
+        local_index: u32,
+    },
     GlobalSet {
         global_index: u32,
     },
@@ -232,6 +448,32 @@ pub enum WasmInstr {
     Call {
         fn_index: u32,
     },
+    // tail call proposal, returns whatever the callee returns
+    ReturnCall {
+        fn_index: u32,
+    },
+    // reference types proposal
+    RefNull {
+        ref_type: WasmType,
+    },
+    RefIsNull,
+    // gc proposal, type indices point into `gc_types`
+    StructNew {
+        type_index: u32,
+    },
+    StructGet {
+        type_index: u32,
+        field_index: u32,
+    },
+    ArrayNewFixed {
+        type_index: u32,
+        len: u32,
+    },
+    ArrayLen,
+    // not an instr, marks start of the source statement for source maps
+    SourceLoc {
+        loc: LoLocation,
+    },
 }
 
 #[repr(u8)]
@@ -242,19 +484,21 @@ pub enum WasmBlockKind {
     If = 0x04,
 }
 
-#[repr(u8)]
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum WasmType {
-    I32 = 0x7F,
-    I64 = 0x7E,
-    F32 = 0x7D,
-    F64 = 0x7C,
+    I32,
+    I64,
+    F32,
+    F64,
+    ExternRef,
+    Ref { type_index: u32 }, // nullable reference to one of `gc_types`
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct WasmLimits {
     pub min: u32,
     pub max: Option<u32>,
+    pub shared: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -280,7 +524,9 @@ pub struct WasmExport {
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum WasmExportType {
     Func = 0x00,
+    Table = 0x01,
     Mem = 0x02,
+    Global = 0x03,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -288,68 +534,343 @@ pub enum WasmData {
     Active { offset: WasmExpr, bytes: Vec<u8> },
 }
 
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum WasmElement {
+    Active {
+        table_index: u32,
+        offset: WasmExpr,
+        fn_indices: Vec<u32>,
+    },
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct WasmDebugFnInfo {
     pub fn_index: u32,
     pub fn_name: String,
+    pub loc: LoLocation,
+    pub local_names: Vec<WasmLocalName>,
 }
 
-impl WasmModule {
-    pub fn dump(&self, output: &mut Vec<u8>) {
-        self.dump_using_buffer(output, &mut Vec::new());
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct WasmCustomSection {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct WasmSourceLoc {
+    pub code_offset: u32,
+    pub loc: LoLocation,
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct WasmLocalName {
+    pub local_index: u32,
+    pub name: String,
+}
+
+// post-MVP proposals the generated code may depend on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WasmFeature {
+    BulkMemory,
+    MultiValue,
+    ReferenceTypes,
+    Simd,
+    TailCall,
+    Threads,
+    Gc,
+}
+
+impl WasmFeature {
+    pub const ALL: [WasmFeature; 7] = [
+        WasmFeature::BulkMemory,
+        WasmFeature::MultiValue,
+        WasmFeature::ReferenceTypes,
+        WasmFeature::Simd,
+        WasmFeature::TailCall,
+        WasmFeature::Threads,
+        WasmFeature::Gc,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WasmFeature::BulkMemory => "bulk-memory",
+            WasmFeature::MultiValue => "multi-value",
+            WasmFeature::ReferenceTypes => "reference-types",
+            WasmFeature::Simd => "simd",
+            WasmFeature::TailCall => "tail-call",
+            WasmFeature::Threads => "threads",
+            WasmFeature::Gc => "gc",
+        }
     }
 
-    pub fn dump_using_buffer(&self, output: &mut Vec<u8>, section_buffer: &mut Vec<u8>) {
-        write_magic_and_version(output);
+    pub fn parse(name: &str) -> Option<Self> {
+        WasmFeature::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
 
-        self.write_type_section(section_buffer);
-        write_section(output, section_buffer, 0x01);
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WasmFeatures {
+    enabled: u32, // bit per `WasmFeature`
+}
 
-        self.write_import_section(section_buffer);
-        write_section(output, section_buffer, 0x02);
+impl Default for WasmFeatures {
+    // what the compiler emitted before features were configurable
+    fn default() -> Self {
+        let mut features = WasmFeatures { enabled: 0 };
+        features.enable(WasmFeature::BulkMemory);
+        features.enable(WasmFeature::MultiValue);
+        features
+    }
+}
+
+impl WasmFeatures {
+    pub fn has(&self, feature: WasmFeature) -> bool {
+        self.enabled & (1 << feature as u32) != 0
+    }
+
+    pub fn enable(&mut self, feature: WasmFeature) {
+        self.enabled |= 1 << feature as u32;
+    }
+
+    pub fn disable(&mut self, feature: WasmFeature) {
+        self.enabled &= !(1 << feature as u32);
+    }
+
+    // comma separated feature names, `-<name>` disables a feature enabled by default
+    pub fn apply_list(&mut self, list: &str) -> Result<(), String> {
+        for item in list.split(',') {
+            let (enable, name) = match item.strip_prefix('-') {
+                Some(name) => (false, name),
+                None => (true, item),
+            };
+
+            let Some(feature) = WasmFeature::parse(name) else {
+                let known = WasmFeature::ALL.map(|f| f.name()).join(", ");
+                return Err(format!(
+                    "Unknown wasm feature: {name}, expected one of: {known}"
+                ));
+            };
+
+            if enable {
+                self.enable(feature);
+            } else {
+                self.disable(feature);
+            }
+        }
+
+        Ok(())
+    }
 
-        self.write_function_section(section_buffer);
-        write_section(output, section_buffer, 0x03);
+    pub fn require(&self, feature: WasmFeature, what: &str) -> Result<(), String> {
+        if self.has(feature) {
+            return Ok(());
+        }
 
-        self.write_memory_section(section_buffer);
-        write_section(output, section_buffer, 0x05);
+        let name = feature.name();
+        Err(format!(
+            "{what} requires wasm feature `{name}`, enable it with `--features {name}`"
+        ))
+    }
+}
 
-        self.write_global_section(section_buffer);
-        write_section(output, section_buffer, 0x06);
+// destination of the binary encoder
+pub trait WasmSink {
+    fn write_bytes(&mut self, bytes: &[u8]);
+    fn position(&self) -> usize;
+}
 
-        self.write_export_section(section_buffer);
-        write_section(output, section_buffer, 0x07);
+impl WasmSink for Vec<u8> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
 
-        self.write_code_section(section_buffer);
-        write_section(output, section_buffer, 0x0A);
+    fn position(&self) -> usize {
+        self.len()
+    }
+}
+
+impl WasmSink for OutputStream {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write(bytes);
+    }
+
+    fn position(&self) -> usize {
+        self.len()
+    }
+}
 
-        self.write_data_section(section_buffer);
-        write_section(output, section_buffer, 0x0B);
+// measures encoded size without keeping the bytes
+#[derive(Default)]
+struct ByteCounter(usize);
+
+impl WasmSink for ByteCounter {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+
+    fn position(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy)]
+enum WasmSection<'a> {
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    Data,
+    Names,
+    Custom { name: &'a str, bytes: &'a [u8] },
+}
+
+impl WasmModule {
+    // sections are measured before they are written so the module can be streamed
+    //   to the output without ever being held in memory as a whole,
+    //   returns offsets of `SourceLoc` markers in the output
+    pub fn dump(&self, out: &mut impl WasmSink) -> Vec<WasmSourceLoc> {
+        write_magic_and_version(out);
+
+        self.write_sized_section(out, WasmSection::Type);
+        self.write_sized_section(out, WasmSection::Import);
+        self.write_sized_section(out, WasmSection::Function);
+        if !self.tables.is_empty() {
+            self.write_sized_section(out, WasmSection::Table);
+        }
+        self.write_sized_section(out, WasmSection::Memory);
+        self.write_sized_section(out, WasmSection::Global);
+        self.write_sized_section(out, WasmSection::Export);
+        if self.start.is_some() {
+            self.write_sized_section(out, WasmSection::Start);
+        }
+        if !self.elements.is_empty() {
+            self.write_sized_section(out, WasmSection::Element);
+        }
+
+        let mut source_locs = Vec::new();
+        self.write_code_section(out, &mut source_locs);
+
+        self.write_sized_section(out, WasmSection::Data);
 
         if self.debug_fn_info.len() > 0 {
-            self.write_custom_section(section_buffer);
-            write_section(output, section_buffer, 0x00);
+            self.write_sized_section(out, WasmSection::Names);
         }
+
+        for custom_section in &self.custom_sections {
+            self.write_sized_section(
+                out,
+                WasmSection::Custom {
+                    name: &custom_section.name,
+                    bytes: &custom_section.bytes,
+                },
+            );
+        }
+
+        if let Some(source_map_url) = &self.source_map_url {
+            let mut url_bytes = Vec::new();
+            write_u32(&mut url_bytes, source_map_url.len() as u32);
+            write_all(&mut url_bytes, source_map_url.as_bytes());
+            self.write_sized_section(
+                out,
+                WasmSection::Custom {
+                    name: "sourceMappingURL",
+                    bytes: &url_bytes,
+                },
+            );
+        }
+
+        source_locs
+    }
+
+    fn write_sized_section(&self, out: &mut impl WasmSink, section: WasmSection) {
+        let mut section_size = ByteCounter::default();
+        self.write_section_contents(&mut section_size, section);
+
+        let section_code = match section {
+            WasmSection::Type => 0x01,
+            WasmSection::Import => 0x02,
+            WasmSection::Function => 0x03,
+            WasmSection::Table => 0x04,
+            WasmSection::Memory => 0x05,
+            WasmSection::Global => 0x06,
+            WasmSection::Export => 0x07,
+            WasmSection::Start => 0x08,
+            WasmSection::Element => 0x09,
+            WasmSection::Data => 0x0B,
+            WasmSection::Names | WasmSection::Custom { .. } => 0x00,
+        };
+        write_u8(out, section_code);
+        write_u32(out, section_size.0 as u32);
+        self.write_section_contents(out, section);
     }
 
-    fn write_type_section(&self, out: &mut Vec<u8>) {
-        write_u32(out, self.types.len() as u32);
+    fn write_section_contents(&self, out: &mut impl WasmSink, section: WasmSection) {
+        match section {
+            WasmSection::Type => self.write_type_section(out),
+            WasmSection::Import => self.write_import_section(out),
+            WasmSection::Function => self.write_function_section(out),
+            WasmSection::Table => self.write_table_section(out),
+            WasmSection::Memory => self.write_memory_section(out),
+            WasmSection::Global => self.write_global_section(out),
+            WasmSection::Export => self.write_export_section(out),
+            WasmSection::Start => write_u32(out, self.start.unwrap()), // safe, checked on dump
+            WasmSection::Element => self.write_element_section(out),
+            WasmSection::Data => self.write_data_section(out),
+            WasmSection::Names => self.write_custom_section(out),
+            WasmSection::Custom { name, bytes } => {
+                write_u32(out, name.len() as u32);
+                write_all(out, name.as_bytes());
+                write_all(out, bytes);
+            }
+        }
+    }
+
+    fn write_type_section(&self, out: &mut impl WasmSink) {
+        if self.gc_types.is_empty() {
+            write_u32(out, self.types.len() as u32);
+        } else {
+            // gc types share a single recursion group so they can reference each other
+            write_u32(out, 1 + self.types.len() as u32);
+            write_u8(out, 0x4E); // rec
+            write_u32(out, self.gc_types.len() as u32);
+            for gc_type in &self.gc_types {
+                match gc_type {
+                    WasmGcType::Struct { fields } => {
+                        write_u8(out, 0x5F); // struct type
+                        write_u32(out, fields.len() as u32);
+                        for field in fields {
+                            write_field_type(out, field);
+                        }
+                    }
+                    WasmGcType::Array { item } => {
+                        write_u8(out, 0x5E); // array type
+                        write_field_type(out, item);
+                    }
+                }
+            }
+        }
+
         for fn_type in &self.types {
             write_u8(out, 0x60); // func type
 
             write_u32(out, fn_type.inputs.len() as u32);
             for fn_input in &fn_type.inputs {
-                write_u8(out, fn_input.clone() as u8);
+                write_value_type(out, fn_input);
             }
 
             write_u32(out, fn_type.outputs.len() as u32);
             for fn_output in &fn_type.outputs {
-                write_u8(out, fn_output.clone() as u8);
+                write_value_type(out, fn_output);
             }
         }
     }
 
-    fn write_import_section(&self, out: &mut Vec<u8>) {
+    fn write_import_section(&self, out: &mut impl WasmSink) {
         write_u32(out, self.imports.len() as u32);
         for import in &self.imports {
             write_u32(out, import.module_name.len() as u32);
@@ -361,7 +882,12 @@ impl WasmModule {
             match import.item_desc {
                 WasmImportDesc::Func { type_index } => {
                     write_u8(out, 0x00); // fn
-                    write_u32(out, type_index);
+                    write_u32(out, self.gc_types.len() as u32 + type_index);
+                }
+                WasmImportDesc::Table(ref table) => {
+                    write_u8(out, 0x01); // table
+                    write_u8(out, 0x70); // funcref
+                    write_memory_limits(out, table);
                 }
                 WasmImportDesc::Memory(ref memory) => {
                     write_u8(out, 0x02); // memory
@@ -371,24 +897,32 @@ impl WasmModule {
         }
     }
 
-    fn write_function_section(&self, out: &mut Vec<u8>) {
+    fn write_function_section(&self, out: &mut impl WasmSink) {
         write_u32(out, self.functions.len() as u32);
         for type_index in &self.functions {
-            write_u32(out, *type_index);
+            write_u32(out, self.gc_types.len() as u32 + *type_index);
+        }
+    }
+
+    fn write_table_section(&self, out: &mut impl WasmSink) {
+        write_u32(out, self.tables.len() as u32);
+        for table in &self.tables {
+            write_u8(out, 0x70); // funcref
+            write_memory_limits(out, table);
         }
     }
 
-    fn write_memory_section(&self, out: &mut Vec<u8>) {
+    fn write_memory_section(&self, out: &mut impl WasmSink) {
         write_u32(out, self.memories.len() as u32);
         for memory in &self.memories {
             write_memory_limits(out, memory);
         }
     }
 
-    fn write_global_section(&self, out: &mut Vec<u8>) {
+    fn write_global_section(&self, out: &mut impl WasmSink) {
         write_u32(out, self.globals.len() as u32);
         for global in &self.globals {
-            write_u8(out, global.kind.value_type.clone() as u8);
+            write_value_type(out, &global.kind.value_type);
 
             if global.kind.mutable {
                 write_u8(out, 0x01);
@@ -396,11 +930,11 @@ impl WasmModule {
                 write_u8(out, 0x00);
             }
 
-            write_expr(out, &global.initial_value);
+            write_expr(out, &global.initial_value, self.gc_types.len() as u32);
         }
     }
 
-    fn write_export_section(&self, out: &mut Vec<u8>) {
+    fn write_export_section(&self, out: &mut impl WasmSink) {
         write_u32(out, self.exports.len() as u32);
         for export in &self.exports {
             write_u32(out, export.export_name.len() as u32);
@@ -412,38 +946,102 @@ impl WasmModule {
         }
     }
 
-    fn write_code_section(&self, out: &mut Vec<u8>) {
-        let mut fn_section = Vec::new();
+    fn write_element_section(&self, out: &mut impl WasmSink) {
+        write_u32(out, self.elements.len() as u32);
+        for element in &self.elements {
+            let WasmElement::Active {
+                table_index,
+                offset,
+                fn_indices,
+            } = element;
+
+            // the short form only targets table 0
+            if *table_index == 0 {
+                write_u32(out, 0);
+            } else {
+                write_u32(out, 2);
+                write_u32(out, *table_index);
+            }
+            write_expr(out, offset, self.gc_types.len() as u32);
+            if *table_index != 0 {
+                write_u8(out, 0x00); // elemkind funcref
+            }
 
-        write_u32(out, self.codes.len() as u32);
-        for fn_code in &self.codes {
-            write_u32(&mut fn_section, fn_code.locals.len() as u32);
-            for locals_of_some_type in &fn_code.locals {
-                write_u32(&mut fn_section, locals_of_some_type.count as u32);
-                write_u8(
-                    &mut fn_section,
-                    locals_of_some_type.value_type.clone() as u8,
-                );
+            write_u32(out, fn_indices.len() as u32);
+            for fn_index in fn_indices {
+                write_u32(out, *fn_index);
             }
-            write_expr(&mut fn_section, &fn_code.expr);
+        }
+    }
 
-            write_u32(out, fn_section.len() as u32);
-            out.append(&mut fn_section);
+    // fn bodies are measured first, the section size is derived from their sizes
+    // encoded sizes of `codes`, without the size prefix each body has in the code section
+    pub fn fn_body_sizes(&self) -> Vec<u32> {
+        let mut fn_body_sizes = Vec::with_capacity(self.codes.len());
+        for fn_code in &self.codes {
+            let mut fn_body_size = ByteCounter::default();
+            self.write_fn_body(&mut fn_body_size, fn_code, None);
+            fn_body_sizes.push(fn_body_size.0 as u32);
         }
+
+        fn_body_sizes
     }
 
-    fn write_data_section(&self, out: &mut Vec<u8>) {
+    fn write_code_section(&self, out: &mut impl WasmSink, source_locs: &mut Vec<WasmSourceLoc>) {
+        let fn_body_sizes = self.fn_body_sizes();
+        let mut section_size = ByteCounter::default();
+        write_u32(&mut section_size, self.codes.len() as u32);
+        for fn_body_size in &fn_body_sizes {
+            write_u32(&mut section_size, *fn_body_size);
+            section_size.0 += *fn_body_size as usize;
+        }
+
+        write_u8(out, 0x0A);
+        write_u32(out, section_size.0 as u32);
+        write_u32(out, self.codes.len() as u32);
+        for (fn_code, fn_body_size) in self.codes.iter().zip(fn_body_sizes) {
+            write_u32(out, fn_body_size);
+            self.write_fn_body(out, fn_code, Some(&mut *source_locs));
+        }
+    }
+
+    fn write_fn_body(
+        &self,
+        out: &mut impl WasmSink,
+        fn_code: &WasmFn,
+        mut source_locs: Option<&mut Vec<WasmSourceLoc>>,
+    ) {
+        write_u32(out, fn_code.locals.len() as u32);
+        for locals_of_some_type in &fn_code.locals {
+            write_u32(out, locals_of_some_type.count);
+            write_value_type(out, &locals_of_some_type.value_type);
+        }
+        for instr in &fn_code.expr.instrs {
+            if let WasmInstr::SourceLoc { loc } = instr {
+                if let Some(source_locs) = &mut source_locs {
+                    source_locs.push(WasmSourceLoc {
+                        code_offset: out.position() as u32,
+                        loc: *loc,
+                    });
+                }
+            }
+            write_instr(out, instr, self.gc_types.len() as u32);
+        }
+        write_u8(out, 0x0B); // end
+    }
+
+    fn write_data_section(&self, out: &mut impl WasmSink) {
         write_u32(out, self.datas.len() as u32);
         for data in self.datas.iter() {
             let WasmData::Active { offset, bytes } = data;
             write_u32(out, 0);
-            write_expr(out, offset);
+            write_expr(out, offset, self.gc_types.len() as u32);
             write_u32(out, bytes.len() as u32);
             write_all(out, bytes);
         }
     }
 
-    fn write_custom_section(&self, out: &mut Vec<u8>) {
+    fn write_custom_section(&self, out: &mut impl WasmSink) {
         let section_name = "name";
         write_u32(out, section_name.len() as u32);
         write_all(out, section_name.as_bytes());
@@ -453,22 +1051,45 @@ impl WasmModule {
             let mut subsection_buf = Vec::new();
             write_u32(&mut subsection_buf, self.debug_fn_info.len() as u32);
             for fn_name in &self.debug_fn_info {
+                let mangled_name = mangle_name(&fn_name.fn_name);
                 write_u32(&mut subsection_buf, fn_name.fn_index);
-                write_u32(&mut subsection_buf, fn_name.fn_name.len() as u32);
-                write_all(&mut subsection_buf, fn_name.fn_name.as_bytes());
+                write_u32(&mut subsection_buf, mangled_name.len() as u32);
+                write_all(&mut subsection_buf, mangled_name.as_bytes());
             }
             write_section(out, &mut subsection_buf, 1);
         }
+
+        /* local names */
+        {
+            let fns_with_locals = self
+                .debug_fn_info
+                .iter()
+                .filter(|fn_info| !fn_info.local_names.is_empty());
+
+            let mut subsection_buf = Vec::new();
+            write_u32(&mut subsection_buf, fns_with_locals.clone().count() as u32);
+            for fn_info in fns_with_locals {
+                write_u32(&mut subsection_buf, fn_info.fn_index);
+                write_u32(&mut subsection_buf, fn_info.local_names.len() as u32);
+                for local_name in &fn_info.local_names {
+                    write_u32(&mut subsection_buf, local_name.local_index);
+                    write_u32(&mut subsection_buf, local_name.name.len() as u32);
+                    write_all(&mut subsection_buf, local_name.name.as_bytes());
+                }
+            }
+            write_section(out, &mut subsection_buf, 2);
+        }
     }
 }
 
-pub fn write_section(out: &mut Vec<u8>, section: &mut Vec<u8>, section_code: u8) {
+pub fn write_section(out: &mut impl WasmSink, section: &mut Vec<u8>, section_code: u8) {
     write_u8(out, section_code);
     write_u32(out, section.len() as u32);
-    out.append(section);
+    out.write_bytes(section);
+    section.clear();
 }
 
-fn write_magic_and_version(out: &mut Vec<u8>) {
+fn write_magic_and_version(out: &mut impl WasmSink) {
     // wasm magic number
     write_all(out, b"\0asm");
 
@@ -476,15 +1097,16 @@ fn write_magic_and_version(out: &mut Vec<u8>) {
     write_all(out, &[0x01, 0x00, 0x00, 0x00]);
 }
 
-fn write_expr(out: &mut Vec<u8>, expr: &WasmExpr) {
+fn write_expr(out: &mut impl WasmSink, expr: &WasmExpr, fn_types_offset: u32) {
     for instr in &expr.instrs {
-        write_instr(out, instr);
+        write_instr(out, instr, fn_types_offset);
     }
 
     write_u8(out, 0x0B); // end
 }
 
-fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
+// `fn_types_offset` is the number of gc types encoded before fn types
+fn write_instr(out: &mut impl WasmSink, instr: &WasmInstr, fn_types_offset: u32) {
     match instr {
         WasmInstr::Unreachable => {
             write_u8(out, 0x00);
@@ -492,6 +1114,9 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
         WasmInstr::BinaryOp { kind } => {
             write_u8(out, kind.clone() as u8);
         }
+        WasmInstr::UnaryOp { kind } => {
+            write_u8(out, kind.clone() as u8);
+        }
         WasmInstr::MemorySize => {
             write_u8(out, 0x3F);
             write_u8(out, 0x00);
@@ -515,6 +1140,32 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
             write_u32(out, *align);
             write_u32(out, *offset);
         }
+        WasmInstr::AtomicLoad { kind, offset } => {
+            let kind = WasmStoreKind::from_load_kind(kind);
+            write_atomic_instr(out, 0x10 + kind.atomic_index().unwrap(), &kind, *offset);
+        }
+        WasmInstr::AtomicStore { kind, offset } => {
+            write_atomic_instr(out, 0x17 + kind.atomic_index().unwrap(), kind, *offset);
+        }
+        WasmInstr::AtomicRmw { op, kind, offset } => {
+            let opcode = op.clone() as u8 + kind.atomic_index().unwrap();
+            write_atomic_instr(out, opcode, kind, *offset);
+        }
+        WasmInstr::AtomicCmpxchg { kind, offset } => {
+            write_atomic_instr(out, 0x48 + kind.atomic_index().unwrap(), kind, *offset);
+        }
+        WasmInstr::MemoryAtomicWait { value_type, offset } => match value_type {
+            WasmType::I64 => write_atomic_instr(out, 0x02, &WasmStoreKind::I64, *offset),
+            _ => write_atomic_instr(out, 0x01, &WasmStoreKind::I32, *offset),
+        },
+        WasmInstr::MemoryAtomicNotify { offset } => {
+            write_atomic_instr(out, 0x00, &WasmStoreKind::I32, *offset);
+        }
+        WasmInstr::AtomicFence => {
+            write_u8(out, 0xFE);
+            write_u32(out, 0x03);
+            write_u8(out, 0x00);
+        }
         WasmInstr::I32Const { value } => {
             write_u8(out, 0x41);
             write_i32(out, *value);
@@ -525,11 +1176,11 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
         }
         WasmInstr::F32Const { value } => {
             write_u8(out, 0x43);
-            out.extend_from_slice(&value.to_le_bytes());
+            write_all(out, &value.to_le_bytes());
         }
         WasmInstr::F64Const { value } => {
             write_u8(out, 0x44);
-            out.extend_from_slice(&value.to_le_bytes());
+            write_all(out, &value.to_le_bytes());
         }
         WasmInstr::I64ExtendI32s => {
             write_u8(out, 0xac);
@@ -552,6 +1203,11 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
             write_u8(out, 0x21);
             write_u32(out, *local_index);
         }
+        WasmInstr::SourceLoc { .. } => {}
+        WasmInstr::LocalTee { local_index } => {
+            write_u8(out, 0x22);
+            write_u32(out, *local_index);
+        }
         WasmInstr::GlobalSet { global_index } => {
             write_u8(out, 0x24);
             write_u32(out, *global_index);
@@ -575,6 +1231,37 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
             write_u8(out, 0x10);
             write_u32(out, *fn_index);
         }
+        WasmInstr::ReturnCall { fn_index } => {
+            write_u8(out, 0x12);
+            write_u32(out, *fn_index);
+        }
+        WasmInstr::RefNull { ref_type } => {
+            write_u8(out, 0xD0);
+            write_heap_type(out, ref_type);
+        }
+        WasmInstr::RefIsNull => {
+            write_u8(out, 0xD1);
+        }
+        WasmInstr::StructNew { type_index } => {
+            write_gc_instr(out, 0x00);
+            write_u32(out, *type_index);
+        }
+        WasmInstr::StructGet {
+            type_index,
+            field_index,
+        } => {
+            write_gc_instr(out, 0x02);
+            write_u32(out, *type_index);
+            write_u32(out, *field_index);
+        }
+        WasmInstr::ArrayNewFixed { type_index, len } => {
+            write_gc_instr(out, 0x08);
+            write_u32(out, *type_index);
+            write_u32(out, *len);
+        }
+        WasmInstr::ArrayLen => {
+            write_gc_instr(out, 0x0F);
+        }
         WasmInstr::BlockStart {
             block_kind,
             block_type,
@@ -585,10 +1272,10 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
                     write_u8(out, 0x40); // no value
                 }
                 WasmBlockType::SingleOut { wasm_type } => {
-                    write_u8(out, wasm_type.clone() as u8);
+                    write_value_type(out, wasm_type);
                 }
                 WasmBlockType::InOut { type_index } => {
-                    write_i32(out, *type_index as i32);
+                    write_i32(out, (fn_types_offset + *type_index) as i32);
                 }
             }
         }
@@ -605,9 +1292,53 @@ fn write_instr(out: &mut Vec<u8>, instr: &WasmInstr) {
     }
 }
 
-fn write_memory_limits(out: &mut Vec<u8>, memory: &WasmLimits) {
+fn write_atomic_instr(out: &mut impl WasmSink, opcode: u8, kind: &WasmStoreKind, offset: u32) {
+    write_u8(out, 0xFE);
+    write_u32(out, opcode as u32);
+    write_u32(out, kind.natural_align());
+    write_u32(out, offset);
+}
+
+fn write_gc_instr(out: &mut impl WasmSink, opcode: u8) {
+    write_u8(out, 0xFB);
+    write_u32(out, opcode as u32);
+}
+
+fn write_value_type(out: &mut impl WasmSink, value_type: &WasmType) {
+    match value_type {
+        WasmType::I32 => write_u8(out, 0x7F),
+        WasmType::I64 => write_u8(out, 0x7E),
+        WasmType::F32 => write_u8(out, 0x7D),
+        WasmType::F64 => write_u8(out, 0x7C),
+        WasmType::ExternRef => write_u8(out, 0x6F),
+        WasmType::Ref { .. } => {
+            write_u8(out, 0x63); // ref null
+            write_heap_type(out, value_type);
+        }
+    }
+}
+
+// for nullable reference types
+fn write_heap_type(out: &mut impl WasmSink, ref_type: &WasmType) {
+    match ref_type {
+        WasmType::ExternRef => write_u8(out, 0x6F),
+        WasmType::Ref { type_index } => write_i32(out, *type_index as i32),
+        _ => unreachable!(),
+    }
+}
+
+fn write_field_type(out: &mut impl WasmSink, field: &WasmFieldType) {
+    match &field.storage_type {
+        WasmStorageType::I8 => write_u8(out, 0x78),
+        WasmStorageType::Value(value_type) => write_value_type(out, value_type),
+    }
+    write_u8(out, field.mutable as u8);
+}
+
+fn write_memory_limits(out: &mut impl WasmSink, memory: &WasmLimits) {
     if let Some(memory_max) = memory.max {
-        write_u8(out, 0x01);
+        // shared memories must have max size
+        write_u8(out, if memory.shared { 0x03 } else { 0x01 });
         write_u32(out, memory.min as u32);
         write_u32(out, memory_max as u32);
     } else {
@@ -616,44 +1347,56 @@ fn write_memory_limits(out: &mut Vec<u8>, memory: &WasmLimits) {
     }
 }
 
-fn write_u8(out: &mut Vec<u8>, value: u8) {
-    out.push(value);
+fn write_u8(out: &mut impl WasmSink, value: u8) {
+    out.write_bytes(&[value]);
 }
 
-pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+pub fn write_u32(out: &mut impl WasmSink, value: u32) {
     leb128_write_unsigned(out, value as u64);
 }
 
-fn write_i32(out: &mut Vec<u8>, value: i32) {
+fn write_i32(out: &mut impl WasmSink, value: i32) {
     leb128_write_signed(out, value as i64);
 }
 
-fn write_i64(out: &mut Vec<u8>, value: i64) {
+fn write_i64(out: &mut impl WasmSink, value: i64) {
     leb128_write_signed(out, value);
 }
 
-pub fn write_all(out: &mut Vec<u8>, value: &[u8]) {
-    out.extend_from_slice(value);
+pub fn write_all(out: &mut impl WasmSink, value: &[u8]) {
+    out.write_bytes(value);
 }
 
 // LEB128
 
 const CONTINUATION_BIT: u8 = 1 << 7;
 
-fn leb128_write_signed(output: &mut Vec<u8>, mut val: i64) {
-    while val != 0 && val != -1 {
+fn leb128_write_signed(output: &mut impl WasmSink, mut val: i64) {
+    loop {
         let byte = (val as u8) & !CONTINUATION_BIT;
         val >>= 7;
-        output.push(byte | CONTINUATION_BIT);
+
+        // the sign bit of the last byte has to match the sign of the value
+        let sign_bit_set = byte & (1 << 6) != 0;
+        if (val == 0 && !sign_bit_set) || (val == -1 && sign_bit_set) {
+            output.write_bytes(&[byte]);
+            return;
+        }
+
+        output.write_bytes(&[byte | CONTINUATION_BIT]);
     }
-    output.push((val as u8) & !CONTINUATION_BIT);
 }
 
-fn leb128_write_unsigned(output: &mut Vec<u8>, mut val: u64) {
-    while val > 0 {
+fn leb128_write_unsigned(output: &mut impl WasmSink, mut val: u64) {
+    loop {
         let byte = (val as u8) & !CONTINUATION_BIT;
         val >>= 7;
-        output.push(byte | CONTINUATION_BIT);
+
+        if val == 0 {
+            output.write_bytes(&[byte]);
+            return;
+        }
+
+        output.write_bytes(&[byte | CONTINUATION_BIT]);
     }
-    output.push((val as u8) & !CONTINUATION_BIT);
 }
@@ -1,51 +1,297 @@
 use crate::{core::*, wasm::*};
-use alloc::{
-    alloc::{alloc, dealloc, Layout},
-    format, str,
-    string::String,
-    vec,
-    vec::Vec,
-};
+#[cfg(target_arch = "wasm32")]
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::{format, str, string::String, vec, vec::Vec};
 
 const PAGE_SIZE: usize = 65_536;
+const MAX_PAGES: usize = 65_536;
 
 #[derive(Debug)]
 pub struct EvalError {
     pub message: String,
 }
 
+#[derive(Default)]
+pub struct EvalOptions {
+    pub import_bindings: Vec<ImportBinding>,
+    pub invoke: Option<EvalInvoke>,
+    pub memory_dumps: Vec<MemoryRange>,
+    pub dump_globals: bool,
+    pub debug: bool,
+    pub bench: bool,
+    pub profile: bool,
+}
+
+pub struct MemoryRange {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl MemoryRange {
+    // parses `<start>:<len>`, both can be either decimal or `0x` prefixed hex
+    pub fn parse(range: &str) -> Result<Self, String> {
+        fn parse_number(number: &str) -> Option<usize> {
+            if let Some(hex) = number.strip_prefix("0x") {
+                return usize::from_str_radix(hex, 16).ok();
+            }
+            number.parse().ok()
+        }
+
+        let invalid_range = || format!("Invalid memory range: {range}, expected <start>:<len>");
+
+        let Some((start, len)) = range.split_once(':') else {
+            return Err(invalid_range());
+        };
+
+        Ok(Self {
+            start: parse_number(start).ok_or_else(invalid_range)?,
+            len: parse_number(len).ok_or_else(invalid_range)?,
+        })
+    }
+}
+
+pub struct EvalInvoke {
+    pub fn_name: String,
+    pub args: Vec<String>,
+}
+
+pub struct ImportBinding {
+    pub module_name: String,
+    pub item_name: String, // `*` matches any fn of the module
+    pub target: ImportTarget,
+}
+
+pub enum ImportTarget {
+    HostFn {
+        module_name: String,
+        fn_name: String,
+    },
+    Trap,
+    Noop,
+}
+
+impl ImportBinding {
+    // parses `<module>::<name>=<trap|noop|<module>::<fn>>`
+    pub fn parse(binding: &str) -> Result<Self, String> {
+        let invalid_binding = || {
+            format!(
+                "Invalid import binding: {binding}, \
+                expected <module>::<name>=<trap|noop|<module>::<fn>>"
+            )
+        };
+
+        let Some((import, target)) = binding.split_once('=') else {
+            return Err(invalid_binding());
+        };
+
+        let Some((module_name, item_name)) = import.split_once("::") else {
+            return Err(invalid_binding());
+        };
+
+        let target = match target {
+            "trap" => ImportTarget::Trap,
+            "noop" => ImportTarget::Noop,
+            _ => {
+                let Some((module_name, fn_name)) = target.split_once("::") else {
+                    return Err(invalid_binding());
+                };
+
+                ImportTarget::HostFn {
+                    module_name: module_name.into(),
+                    fn_name: fn_name.into(),
+                }
+            }
+        };
+
+        Ok(Self {
+            module_name: module_name.into(),
+            item_name: item_name.into(),
+            target,
+        })
+    }
+
+    fn matches(&self, import: &WasmImport) -> bool {
+        self.module_name == import.module_name
+            && (self.item_name == "*" || self.item_name == import.item_name)
+    }
+}
+
+enum HostFn {
+    Builtin {
+        full_name: String,
+    },
+    Trap {
+        full_name: String,
+    },
+    Noop {
+        inputs_len: usize,
+        outputs: Vec<WasmType>,
+    },
+}
+
 #[derive(Default)]
 pub struct WasmEval {
     wasm_module: WasmModule,
+    options: EvalOptions,
     fn_imports_len: usize,
     globals: Vec<WasmValue>,
     stack: Vec<WasmValue>,
     call_stack: Vec<CallFrame>,
     memory: LinearMemory,
-    host_fns: Vec<String>,
-    jump_tables: Vec<(u32, JumpTable)>,
+    host_fns: Vec<HostFn>,
+    fns: Vec<EvalFn>, // indexed by fn index minus imported fns count
+    debug_fn_indices: Vec<Option<usize>>, // indexed by fn index, points into `debug_fn_info`
+    debugger: Option<Debugger>,
+    counters: Option<EvalCounters>, // collected for `--bench` and `--profile`
 }
 
 impl WasmEval {
-    pub fn eval(wasm_module: WasmModule) -> Result<(), EvalError> {
+    // returns exit code for the process
+    pub fn eval(wasm_module: WasmModule, options: EvalOptions) -> Result<u32, EvalError> {
         let mut eval = WasmEval {
             wasm_module,
+            options,
             ..Default::default()
         };
 
-        eval.init_module()?;
-        eval.eval_main()?;
+        for (fn_info, i) in eval.wasm_module.debug_fn_info.iter().zip(0..) {
+            let fn_index = fn_info.fn_index as usize;
+            if fn_index >= eval.debug_fn_indices.len() {
+                eval.debug_fn_indices.resize(fn_index + 1, None);
+            }
+            eval.debug_fn_indices[fn_index] = Some(i);
+        }
+
+        if eval.options.profile {
+            eval.counters = Some(EvalCounters::default());
+        }
 
-        Ok(())
+        if eval.options.debug {
+            eval.debugger = Some(Debugger {
+                breakpoints: Vec::new(),
+                mode: DebuggerMode::Step,
+            });
+        }
+
+        let result = eval.run();
+        eval.dump_state();
+
+        result
+    }
+
+    fn run(&mut self) -> Result<u32, EvalError> {
+        self.init_module()?;
+
+        if let Some(invoke) = self.options.invoke.take() {
+            self.eval_invoke(&invoke)?;
+            return Ok(0);
+        }
+
+        if self.options.bench {
+            self.eval_benches()?;
+            return Ok(0);
+        }
+
+        self.eval_main()
+    }
+
+    fn dump_state(&self) {
+        use core::fmt::Write;
+
+        let mut output = String::new();
+
+        if self.options.dump_globals {
+            writeln!(&mut output, "globals:").unwrap();
+            output.push_str(&format_values(&self.globals));
+        }
+
+        for range in &self.options.memory_dumps {
+            let start = range.start.min(self.memory.bytes.len());
+            let end = (range.start + range.len).min(self.memory.bytes.len());
+
+            writeln!(&mut output, "memory {start:#010x}..{end:#010x}:").unwrap();
+            for line_start in (start..end).step_by(16) {
+                let line = &self.memory.bytes[line_start..(line_start + 16).min(end)];
+
+                write!(&mut output, "  {line_start:08x}:").unwrap();
+                for i in 0..16 {
+                    match line.get(i) {
+                        Some(byte) => write!(&mut output, " {byte:02x}").unwrap(),
+                        None => write!(&mut output, "   ").unwrap(),
+                    }
+                }
+
+                write!(&mut output, "  ").unwrap();
+                for byte in line {
+                    let char = if byte.is_ascii_graphic() || *byte == b' ' {
+                        *byte as char
+                    } else {
+                        '.'
+                    };
+                    write!(&mut output, "{char}").unwrap();
+                }
+                writeln!(&mut output).unwrap();
+            }
+        }
+
+        if let Some(counters) = &self.counters {
+            output.push_str(&self.format_profile(counters));
+        }
+
+        if !output.is_empty() {
+            stderr_write(output);
+        }
+    }
+
+    // fns sorted by instrs executed in their own body, then by call count
+    fn format_profile(&self, counters: &EvalCounters) -> String {
+        use core::fmt::Write;
+
+        let mut fn_stats = Vec::new();
+        for (fn_index, (calls, instrs)) in counters.fn_stats.iter().enumerate() {
+            if *calls != 0 || *instrs != 0 {
+                fn_stats.push((*instrs, *calls, fn_index as u32));
+            }
+        }
+        fn_stats.sort_by(|lhs, rhs| rhs.cmp(lhs));
+
+        let mut output = format!("profile: {} instrs\n", counters.instrs);
+        let total_instrs = counters.instrs.max(1) as f64;
+        let instrs_width = digit_count(fn_stats.first().map_or(0, |(instrs, _, _)| *instrs));
+        let calls_width = digit_count(
+            fn_stats
+                .iter()
+                .map(|(_, calls, _)| *calls)
+                .max()
+                .unwrap_or(0),
+        );
+        for (instrs, calls, fn_index) in fn_stats {
+            let share = instrs as f64 * 100.0 / total_instrs;
+            write!(
+                &mut output,
+                "  {instrs:>instrs_width$} {share:>5.1}% {calls:>calls_width$} calls  {}",
+                self.get_fn_display_name(fn_index)
+            )
+            .unwrap();
+            if let Some(fn_info) = self.get_debug_fn_info(fn_index) {
+                write!(&mut output, " at {}", fn_info.loc).unwrap();
+            }
+            writeln!(&mut output).unwrap();
+        }
+
+        output
     }
 
     // TODO: add module verify step
     fn init_module(&mut self) -> Result<(), EvalError> {
+        if !self.wasm_module.gc_types.is_empty() {
+            return Err(EvalError {
+                message: String::from("GC types are not supported by the evaluator"),
+            });
+        }
+
         for global in unsafe_borrow(&self.wasm_module.globals) {
-            self.eval_expr(
-                &global.initial_value,
-                &JumpTable::for_expr(&global.initial_value),
-            )?;
+            self.eval_code(&EvalCode::decode(&global.initial_value))?;
             let initial_value = self.stack.pop().unwrap();
             self.globals.push(initial_value);
         }
@@ -59,7 +305,7 @@ impl WasmEval {
             for data in unsafe_borrow(&self.wasm_module.datas) {
                 match data {
                     WasmData::Active { offset, bytes } => {
-                        self.eval_expr(offset, &JumpTable::for_expr(offset))?;
+                        self.eval_code(&EvalCode::decode(offset))?;
                         let offset = self.pop_i32() as usize;
 
                         self.memory.bytes[offset..offset + bytes.len()].copy_from_slice(&bytes);
@@ -71,15 +317,48 @@ impl WasmEval {
         'import_loop: for (import, i) in self.wasm_module.imports.iter().zip(0..) {
             if let WasmImportDesc::Func { type_index } = import.item_desc {
                 let fn_type = &self.wasm_module.types[type_index as usize];
+                let full_name = format!("{}::{}", import.module_name, import.item_name);
+
+                let mut host_module_name = &import.module_name;
+                let mut host_fn_name = &import.item_name;
+
+                let binding = self
+                    .options
+                    .import_bindings
+                    .iter()
+                    .find(|b| b.matches(import));
+                match binding.map(|b| &b.target) {
+                    Some(ImportTarget::Trap) => {
+                        self.host_fns.push(HostFn::Trap { full_name });
+                        self.fn_imports_len += 1;
+                        continue 'import_loop;
+                    }
+                    Some(ImportTarget::Noop) => {
+                        self.host_fns.push(HostFn::Noop {
+                            inputs_len: fn_type.inputs.len(),
+                            outputs: fn_type.outputs.clone(),
+                        });
+                        self.fn_imports_len += 1;
+                        continue 'import_loop;
+                    }
+                    Some(ImportTarget::HostFn {
+                        module_name,
+                        fn_name,
+                    }) => {
+                        host_module_name = module_name;
+                        host_fn_name = fn_name;
+                    }
+                    None => {}
+                }
 
                 for host_fn in &SUPPORTED_HOST_FNS {
-                    if import.module_name == host_fn.module_name
-                        && import.item_name == host_fn.fn_name
+                    if *host_module_name == host_fn.module_name
+                        && *host_fn_name == host_fn.fn_name
                         && &fn_type.inputs[..] == host_fn.fn_inputs
                         && &fn_type.outputs[..] == host_fn.fn_outputs
                     {
-                        let full_name = format!("{}::{}", import.module_name, import.item_name);
-                        self.host_fns.push(full_name);
+                        let full_name = format!("{host_module_name}::{host_fn_name}");
+                        self.host_fns.push(HostFn::Builtin { full_name });
                         self.fn_imports_len += 1;
                         continue 'import_loop;
                     }
@@ -98,19 +377,39 @@ impl WasmEval {
             });
         }
 
-        for (fn_code, i) in self.wasm_module.codes.iter().zip(0..) {
-            let fn_index = (self.fn_imports_len + i) as u32;
-            let jump_table = JumpTable::for_expr(&fn_code.expr);
-            self.jump_tables.push((fn_index, jump_table));
+        for (fn_code, type_index) in self
+            .wasm_module
+            .codes
+            .iter()
+            .zip(&self.wasm_module.functions)
+        {
+            let fn_type = &self.wasm_module.types[*type_index as usize];
+
+            let mut locals = Vec::new();
+            for local in &fn_code.locals {
+                for _ in 0..local.count {
+                    locals.push(WasmValue::default_for_type(&local.value_type));
+                }
+            }
+
+            self.fns.push(EvalFn {
+                inputs_len: fn_type.inputs.len(),
+                locals,
+                code: EvalCode::decode(&fn_code.expr),
+            });
+        }
+
+        if let Some(fn_index) = self.wasm_module.start {
+            self.call_fn(fn_index)?;
         }
 
         Ok(())
     }
 
-    fn eval_main(&mut self) -> Result<(), EvalError> {
+    fn eval_main(&mut self) -> Result<u32, EvalError> {
         if let Some(fn_index) = self.get_exported_fn_index("_start") {
             self.call_fn(fn_index)?;
-            return Ok(());
+            return Ok(0);
         };
 
         let Some(fn_index) = self.get_exported_fn_index("main") else {
@@ -120,7 +419,107 @@ impl WasmEval {
         };
 
         self.call_fn(fn_index)?;
+        let values = self.print_results("main", fn_index)?;
+
+        // `main` returning i32 (or a Result, whose error code goes last) sets the exit code
+        let exit_code = match &values[..] {
+            [.., WasmValue::I32 { value }] => *value as u32,
+            _ => 0,
+        };
+
+        Ok(exit_code)
+    }
+
+    fn eval_invoke(&mut self, invoke: &EvalInvoke) -> Result<(), EvalError> {
+        let fn_name = &invoke.fn_name;
+        let Some(fn_index) = self.get_exported_fn_index(fn_name) else {
+            return Err(EvalError {
+                message: format!("Function `{fn_name}` is not exported"),
+            });
+        };
+
+        let (fn_type, _) = unsafe_borrow(self).get_fn_info(fn_index)?;
+        if invoke.args.len() != fn_type.inputs.len() {
+            return Err(EvalError {
+                message: format!(
+                    "Function `{fn_name}` expects {} arguments, got {}",
+                    fn_type.inputs.len(),
+                    invoke.args.len()
+                ),
+            });
+        }
+
+        for (arg, input_type) in invoke.args.iter().zip(&fn_type.inputs) {
+            let value = WasmValue::parse(arg, input_type).ok_or_else(|| EvalError {
+                message: format!("Invalid {input_type:?} argument: {arg}"),
+            })?;
+            self.stack.push(value);
+        }
+
+        self.call_fn(fn_index)?;
+        self.print_results(fn_name, fn_index)?;
 
+        Ok(())
+    }
+
+    // benches run one after another in the same instance, so state carries over
+    fn eval_benches(&mut self) -> Result<(), EvalError> {
+        let mut benches = Vec::new();
+        for export in &self.wasm_module.exports {
+            if export.export_type == WasmExportType::Func
+                && export.export_name.starts_with("bench_")
+            {
+                benches.push((export.export_name.clone(), export.exported_item_index));
+            }
+        }
+        if benches.is_empty() {
+            return Err(EvalError {
+                message: String::from("No `bench_*` functions are exported"),
+            });
+        }
+
+        for (bench_name, fn_index) in benches {
+            let (fn_type, _) = unsafe_borrow(self).get_fn_info(fn_index)?;
+            if !fn_type.inputs.is_empty() {
+                return Err(EvalError {
+                    message: format!("Benchmark `{bench_name}` must not take arguments"),
+                });
+            }
+
+            self.counters = Some(EvalCounters::default());
+            self.call_fn(fn_index)?;
+            let counters = self.counters.take().unwrap(); // safe
+            self.stack
+                .truncate(self.stack.len() - fn_type.outputs.len());
+
+            stdout_write(self.format_bench_report(&bench_name, &counters));
+        }
+
+        Ok(())
+    }
+
+    fn format_bench_report(&self, bench_name: &str, counters: &EvalCounters) -> String {
+        use core::fmt::Write;
+
+        let mut fn_calls = Vec::new();
+        for (fn_index, (calls, _)) in counters.fn_stats.iter().enumerate() {
+            if *calls != 0 {
+                fn_calls.push((*calls, fn_index as u32));
+            }
+        }
+        fn_calls.sort_by(|(lhs_calls, _), (rhs_calls, _)| rhs_calls.cmp(lhs_calls));
+
+        let mut output = format!("{bench_name}: {} instrs\n", counters.instrs);
+        let calls_width = digit_count(fn_calls.first().map_or(0, |(calls, _)| *calls));
+        for (calls, fn_index) in fn_calls {
+            let fn_name = self.get_fn_display_name(fn_index);
+            writeln!(&mut output, "  {calls:>calls_width$} {fn_name}").unwrap();
+        }
+
+        output
+    }
+
+    fn print_results(&mut self, fn_name: &str, fn_index: u32) -> Result<Vec<WasmValue>, EvalError> {
         let (fn_type, _) = self.get_fn_info(fn_index)?;
         let mut values = Vec::new();
         for _ in 0..fn_type.outputs.len() {
@@ -128,73 +527,87 @@ impl WasmEval {
         }
         values.reverse();
 
-        stdout_write(format!("result of `main` is: {}\n", ListDisplay(&values)));
-        Ok(())
+        stdout_write(format!(
+            "result of `{fn_name}` is: {}\n",
+            ListDisplay(&values)
+        ));
+        Ok(values)
     }
 
     fn call_fn(&mut self, fn_index: u32) -> Result<(), EvalError> {
-        if fn_index < self.fn_imports_len as u32 {
-            return call_host_fn(self, fn_index);
-        }
+        let mut fn_index = fn_index;
 
-        let (fn_type, code) = unsafe_borrow(self).get_fn_info(fn_index)?;
+        // tail calls replace the frame instead of nesting
+        loop {
+            if let Some(counters) = &mut self.counters {
+                counters.fn_stats_mut(fn_index).0 += 1;
+            }
 
-        let mut call_frame = CallFrame {
-            fn_index,
-            ..Default::default()
-        };
-        for _ in 0..fn_type.inputs.len() {
-            let value = self.stack.pop().unwrap();
-            call_frame.locals.push(value);
-        }
-        call_frame.locals.reverse();
-        for local in &code.locals {
-            for _ in 0..local.count {
-                let value = WasmValue::default_for_type(&local.value_type);
-                call_frame.locals.push(value);
+            if fn_index < self.fn_imports_len as u32 {
+                return call_host_fn(self, fn_index);
             }
-        }
-        self.call_stack.push(call_frame);
 
-        let jump_table = self.get_jump_table_for_fn(fn_index);
-        self.eval_expr(&code.expr, &jump_table)?;
+            let eval_fn = unsafe_borrow(&self.fns[fn_index as usize - self.fn_imports_len]);
 
-        self.call_stack.pop();
+            let mut locals = Vec::with_capacity(eval_fn.inputs_len + eval_fn.locals.len());
+            locals.extend(self.stack.drain(self.stack.len() - eval_fn.inputs_len..));
+            locals.extend_from_slice(&eval_fn.locals);
+            self.call_stack.push(CallFrame { fn_index, locals });
 
-        Ok(())
+            let tail_callee = self.eval_code(&eval_fn.code)?;
+
+            self.call_stack.pop();
+
+            let Some(tail_callee) = tail_callee else {
+                return Ok(());
+            };
+            fn_index = tail_callee;
+        }
     }
 
-    fn eval_expr(&mut self, expr: &WasmExpr, jump_table: &JumpTable) -> Result<(), EvalError> {
+    // returns index of the fn to tail call after the current frame is dropped
+    fn eval_code(&mut self, code: &EvalCode) -> Result<Option<u32>, EvalError> {
         let mut loc = 0;
-        while loc < expr.instrs.len() {
-            let instr = &expr.instrs[loc];
-
-            match instr {
-                WasmInstr::BlockStart { block_kind, .. } => {
-                    if let WasmBlockKind::If = block_kind {
-                        let cond = self.pop_i32();
-                        if cond == 0 {
-                            loc = jump_table.get_jump_loc(loc);
-                            continue;
-                        }
-                    }
+        while let Some(op) = code.ops.get(loc) {
+            if self.debugger.is_some() {
+                self.debug_pause(loc, op);
+            }
+            if let Some(counters) = &mut self.counters {
+                counters.instrs += 1;
+                // globals are initialized outside of any fn
+                if let Some(frame) = self.call_stack.last() {
+                    counters.fn_stats_mut(frame.fn_index).1 += 1;
                 }
-                WasmInstr::Else => {
-                    loc = jump_table.get_jump_loc(loc);
+            }
+
+            let instr = match op {
+                EvalOp::Jump { to } => {
+                    loc = *to;
                     continue;
                 }
-                WasmInstr::Branch { .. } => {
-                    loc = jump_table.get_jump_loc(loc);
+                EvalOp::JumpUnless { to } => {
+                    let cond = self.pop_i32();
+                    loc = if cond == 0 { *to } else { loc + 1 };
                     continue;
                 }
-                WasmInstr::BlockEnd => {}
+                EvalOp::Return => break,
+                EvalOp::Instr { instr } => instr,
+            };
+
+            match instr {
+                WasmInstr::BlockStart { .. }
+                | WasmInstr::Else
+                | WasmInstr::BlockEnd
+                | WasmInstr::Branch { .. }
+                | WasmInstr::Return => unreachable!(), // decoded into jumps
+                WasmInstr::SourceLoc { .. } => unreachable!(), // skipped when decoding
 
-                WasmInstr::Return => {
-                    break;
-                }
                 WasmInstr::Call { fn_index } => {
                     self.call_fn(*fn_index)?;
                 }
+                WasmInstr::ReturnCall { fn_index } => {
+                    return Ok(Some(*fn_index));
+                }
 
                 WasmInstr::I32Const { value } => {
                     let value = WasmValue::I32 { value: *value };
@@ -223,6 +636,11 @@ impl WasmEval {
                     let frame = self.call_stack.last_mut().unwrap();
                     frame.locals[*local_index as usize] = value;
                 }
+                WasmInstr::LocalTee { local_index } => {
+                    let value = self.stack.last().unwrap().clone();
+                    let frame = self.call_stack.last_mut().unwrap();
+                    frame.locals[*local_index as usize] = value;
+                }
                 WasmInstr::GlobalGet { global_index } => {
                     let value = self.globals[*global_index as usize].clone();
                     self.stack.push(value);
@@ -235,47 +653,182 @@ impl WasmEval {
                     kind,
                     align: _,
                     offset,
-                } => match kind {
-                    WasmLoadKind::I32 => {
-                        let addr = self.pop_i32();
-                        let full_addr = addr as usize + *offset as usize;
-                        let value = self.memory.load_i32(full_addr);
-                        self.stack.push(WasmValue::I32 { value });
+                } => {
+                    let addr = self.pop_i32() as u32 as usize + *offset as usize;
+                    let value = self.memory.load(kind, addr);
+                    let Some(value) = value else {
+                        return Err(
+                            self.err_with_stack(format!("Out of bounds memory access: {addr}"))
+                        );
+                    };
+                    self.stack.push(value);
+                }
+                WasmInstr::Store {
+                    kind,
+                    align: _,
+                    offset,
+                } => {
+                    let value = self.stack.pop().unwrap();
+                    let addr = self.pop_i32() as u32 as usize + *offset as usize;
+                    if let Err(message) = self.memory.store_value(kind, addr, value) {
+                        return Err(self.err_with_stack(message));
+                    }
+                }
+                WasmInstr::AtomicLoad { kind, offset } => {
+                    let access_kind = WasmStoreKind::from_load_kind(kind);
+                    let addr = self.pop_atomic_addr(&access_kind, *offset)?;
+                    let Some(value) = self.memory.load(kind, addr) else {
+                        return Err(
+                            self.err_with_stack(format!("Out of bounds memory access: {addr}"))
+                        );
+                    };
+                    self.stack.push(value);
+                }
+                WasmInstr::AtomicStore { kind, offset } => {
+                    let value = self.stack.pop().unwrap();
+                    let addr = self.pop_atomic_addr(kind, *offset)?;
+                    if let Err(message) = self.memory.store_value(kind, addr, value) {
+                        return Err(self.err_with_stack(message));
                     }
-                    WasmLoadKind::I32U8 => {
-                        let addr = self.pop_i32();
-                        let full_addr = addr as usize + *offset as usize;
-                        let Some(value) = self.memory.bytes.get(full_addr).cloned() else {
+                }
+                WasmInstr::AtomicRmw { op, kind, offset } => {
+                    let operand = self.stack.pop().unwrap();
+                    let addr = self.pop_atomic_addr(kind, *offset)?;
+                    let Some(old_value) =
+                        self.memory.load(&WasmLoadKind::from_store_kind(kind), addr)
+                    else {
+                        return Err(
+                            self.err_with_stack(format!("Out of bounds memory access: {addr}"))
+                        );
+                    };
+
+                    let new_value = match (&old_value, operand) {
+                        (WasmValue::I32 { value: old }, WasmValue::I32 { value }) => {
+                            WasmValue::I32 {
+                                value: apply_atomic_rmw(op, *old as i64, value as i64) as i32,
+                            }
+                        }
+                        (WasmValue::I64 { value: old }, WasmValue::I64 { value }) => {
+                            WasmValue::I64 {
+                                value: apply_atomic_rmw(op, *old, value),
+                            }
+                        }
+                        (_, operand) => {
                             return Err(self.err_with_stack(format!(
-                                "Memory read out of bounds: {full_addr}"
+                                "Invalid operand for atomic {op:?}: {:?}",
+                                operand.get_type()
                             )));
-                        };
+                        }
+                    };
+                    if let Err(message) = self.memory.store_value(kind, addr, new_value) {
+                        return Err(self.err_with_stack(message));
+                    }
+                    self.stack.push(old_value);
+                }
+                WasmInstr::AtomicCmpxchg { kind, offset } => {
+                    let replacement = self.stack.pop().unwrap();
+                    let expected = self.stack.pop().unwrap();
+                    let addr = self.pop_atomic_addr(kind, *offset)?;
+                    let Some(old_value) =
+                        self.memory.load(&WasmLoadKind::from_store_kind(kind), addr)
+                    else {
+                        return Err(
+                            self.err_with_stack(format!("Out of bounds memory access: {addr}"))
+                        );
+                    };
 
-                        self.stack.push(WasmValue::I32 {
-                            value: value as i32,
-                        });
+                    // expected value is wrapped to the access width before comparison
+                    let bits = 8 << kind.natural_align();
+                    let mask = if bits == 64 {
+                        u64::MAX
+                    } else {
+                        (1 << bits) - 1
+                    };
+                    let matches = match (&old_value, &expected) {
+                        (WasmValue::I32 { value: old }, WasmValue::I32 { value }) => {
+                            *old as u32 as u64 == *value as u32 as u64 & mask
+                        }
+                        (WasmValue::I64 { value: old }, WasmValue::I64 { value }) => {
+                            *old as u64 == *value as u64 & mask
+                        }
+                        _ => false,
+                    };
+                    if matches {
+                        if let Err(message) = self.memory.store_value(kind, addr, replacement) {
+                            return Err(self.err_with_stack(message));
+                        }
                     }
-                    _ => todo!("load {kind:?}"),
-                },
-                WasmInstr::Store {
-                    kind,
-                    align: _,
-                    offset,
-                } => match kind {
-                    WasmStoreKind::I32 => {
-                        let value = self.pop_i32();
-                        let addr = self.pop_i32();
-                        let full_addr = addr as usize + *offset as usize;
-                        self.memory.store_i32(full_addr, value);
+                    self.stack.push(old_value);
+                }
+                WasmInstr::MemoryAtomicWait { value_type, offset } => {
+                    let timeout = self.pop_i64();
+                    let expected = self.stack.pop().unwrap();
+                    let access_kind = match value_type {
+                        WasmType::I64 => WasmStoreKind::I64,
+                        _ => WasmStoreKind::I32,
+                    };
+                    let addr = self.pop_atomic_addr(&access_kind, *offset)?;
+
+                    if !self.has_shared_memory() {
+                        return Err(self.err_with_stack("memory.atomic.wait on unshared memory"));
+                    }
+
+                    let value = self
+                        .memory
+                        .load(&WasmLoadKind::from_store_kind(&access_kind), addr);
+                    let Some(value) = value else {
+                        return Err(
+                            self.err_with_stack(format!("Out of bounds memory access: {addr}"))
+                        );
+                    };
+
+                    // there are no other threads to notify the waiter
+                    let equal = match (&value, &expected) {
+                        (WasmValue::I32 { value }, WasmValue::I32 { value: expected }) => {
+                            value == expected
+                        }
+                        (WasmValue::I64 { value }, WasmValue::I64 { value: expected }) => {
+                            value == expected
+                        }
+                        _ => false,
+                    };
+                    if !equal {
+                        self.stack.push(WasmValue::I32 { value: 1 }); // not-equal
+                    } else if timeout >= 0 {
+                        self.stack.push(WasmValue::I32 { value: 2 }); // timed-out
+                    } else {
+                        return Err(self.err_with_stack(
+                            "memory.atomic.wait without timeout would block forever",
+                        ));
                     }
-                    WasmStoreKind::I32U8 => {
-                        let value = self.pop_i32();
-                        let addr = self.pop_i32();
-                        let full_addr = addr as usize + *offset as usize;
-                        self.memory.bytes[full_addr] = value as u8;
+                }
+                WasmInstr::MemoryAtomicNotify { offset } => {
+                    let _waiter_count = self.pop_i32();
+                    let addr = self.pop_atomic_addr(&WasmStoreKind::I32, *offset)?;
+                    if self.memory.load(&WasmLoadKind::I32, addr).is_none() {
+                        return Err(
+                            self.err_with_stack(format!("Out of bounds memory access: {addr}"))
+                        );
                     }
-                    _ => todo!("store {kind:?}"),
-                },
+                    self.stack.push(WasmValue::I32 { value: 0 }); // no waiters were woken
+                }
+                WasmInstr::AtomicFence => {}
+
+                WasmInstr::RefNull { .. } => {
+                    self.stack.push(WasmValue::NullRef);
+                }
+                WasmInstr::RefIsNull => {
+                    let is_null = matches!(self.stack.pop().unwrap(), WasmValue::NullRef);
+                    self.stack.push(WasmValue::from_bool(is_null));
+                }
+
+                // no gc types to reference, see `init_module`
+                WasmInstr::StructNew { .. }
+                | WasmInstr::StructGet { .. }
+                | WasmInstr::ArrayNewFixed { .. }
+                | WasmInstr::ArrayLen => {
+                    return Err(self.err_with_stack("GC instructions are not supported"));
+                }
 
                 WasmInstr::Drop => {
                     let _ = self.stack.pop().unwrap();
@@ -288,16 +841,40 @@ impl WasmEval {
                     self.stack.push(WasmValue::I32 { value: mem_size });
                 }
                 WasmInstr::MemoryCopy => {
-                    let num_bytes = self.pop_i32();
-                    let source = self.pop_i32();
-                    let destination = self.pop_i32();
+                    let num_bytes = self.pop_i32() as u32 as usize;
+                    let source = self.pop_i32() as u32 as usize;
+                    let destination = self.pop_i32() as u32 as usize;
 
-                    self.memory.bytes.copy_within(
-                        source as usize..source as usize + num_bytes as usize,
-                        destination as usize,
-                    );
+                    let mem_len = self.memory.bytes.len();
+                    if source + num_bytes > mem_len || destination + num_bytes > mem_len {
+                        return Err(self.err_with_stack("Out of bounds memory access: memory.copy"));
+                    }
+
+                    self.memory
+                        .bytes
+                        .copy_within(source..source + num_bytes, destination);
+                }
+                WasmInstr::MemoryGrow => {
+                    let delta = self.pop_i32() as u32 as usize;
+
+                    let max_pages = match self.wasm_module.memories.first() {
+                        Some(WasmLimits { max: Some(max), .. }) => *max as usize,
+                        _ => MAX_PAGES,
+                    };
+
+                    let prev_size = self.memory.size_in_pages;
+                    if prev_size + delta > max_pages {
+                        self.stack.push(WasmValue::I32 { value: -1 });
+                    } else {
+                        self.memory.size_in_pages += delta;
+                        self.memory
+                            .bytes
+                            .resize(self.memory.size_in_pages * PAGE_SIZE, 0);
+                        self.stack.push(WasmValue::I32 {
+                            value: prev_size as i32,
+                        });
+                    }
                 }
-                WasmInstr::MemoryGrow => todo!("{instr:?}"),
 
                 WasmInstr::I64ExtendI32u => {
                     let value = self.pop_i32();
@@ -317,185 +894,178 @@ impl WasmEval {
                         value: value as i32,
                     })
                 }
-                WasmInstr::BinaryOp { kind } => match kind {
-                    WasmBinaryOpKind::I32_ADD => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = lhs + rhs;
-                        self.stack.push(WasmValue::I32 { value });
-                    }
-                    WasmBinaryOpKind::I32_SUB => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = lhs - rhs;
-                        self.stack.push(WasmValue::I32 { value });
-                    }
-                    WasmBinaryOpKind::I32_MUL => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = lhs * rhs;
-                        self.stack.push(WasmValue::I32 { value });
-                    }
-                    WasmBinaryOpKind::I32_AND => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = lhs & rhs;
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_OR => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = lhs | rhs;
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_DIV_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = ((lhs as u32) / (rhs as u32)) as i32;
-                        self.stack.push(WasmValue::I32 { value });
-                    }
-                    WasmBinaryOpKind::I32_SHR_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = ((lhs as u32) >> (rhs as u32)) as i32;
-                        self.stack.push(WasmValue::I32 { value });
+                WasmInstr::BinaryOp { kind } => {
+                    let rhs = self.stack.pop().unwrap();
+                    let lhs = self.stack.pop().unwrap();
+                    let value = eval_binary_op(kind, lhs, rhs);
+                    match value {
+                        Ok(value) => self.stack.push(value),
+                        Err(message) => return Err(self.err_with_stack(message)),
                     }
-                    WasmBinaryOpKind::I32_REM_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = ((lhs as u32) % (rhs as u32)) as i32;
-                        self.stack.push(WasmValue::I32 { value });
-                    }
-                    WasmBinaryOpKind::I32_EQ => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if lhs == rhs { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_NE => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if lhs != rhs { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_GT_S => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if lhs > rhs { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_LT_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if (lhs as u32) < (rhs as u32) { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_GT_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if (lhs as u32) > (rhs as u32) { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_GE_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if (lhs as u32) >= (rhs as u32) { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_LE_U => {
-                        let rhs = self.pop_i32();
-                        let lhs = self.pop_i32();
-                        let value = if (lhs as u32) <= (rhs as u32) { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I32_LT_S
-                    | WasmBinaryOpKind::I32_LE_S
-                    | WasmBinaryOpKind::I32_GE_S
-                    | WasmBinaryOpKind::I32_DIV_S
-                    | WasmBinaryOpKind::I32_REM_S
-                    | WasmBinaryOpKind::I32_SHL
-                    | WasmBinaryOpKind::I32_SHR_S => todo!("{kind:?}"),
-
-                    WasmBinaryOpKind::I64_MUL => {
-                        let rhs = self.pop_i64();
-                        let lhs = self.pop_i64();
-                        let value = lhs * rhs;
-                        self.stack.push(WasmValue::I64 { value })
-                    }
-                    WasmBinaryOpKind::I64_EQ => {
-                        let rhs = self.pop_i64();
-                        let lhs = self.pop_i64();
-                        let value = if lhs == rhs { 1 } else { 0 };
-                        self.stack.push(WasmValue::I32 { value })
-                    }
-                    WasmBinaryOpKind::I64_DIV_U => {
-                        let rhs = self.pop_i64();
-                        let lhs = self.pop_i64();
-                        let value = (lhs as u64 / rhs as u64) as i64;
-                        self.stack.push(WasmValue::I64 { value })
-                    }
-                    WasmBinaryOpKind::I64_REM_U => {
-                        let rhs = self.pop_i64();
-                        let lhs = self.pop_i64();
-                        let value = (lhs as u64 % rhs as u64) as i64;
-                        self.stack.push(WasmValue::I64 { value })
+                }
+                WasmInstr::UnaryOp { kind } => {
+                    let operand = self.stack.pop().unwrap();
+                    let value = eval_unary_op(kind, operand);
+                    match value {
+                        Ok(value) => self.stack.push(value),
+                        Err(message) => return Err(self.err_with_stack(message)),
                     }
-                    WasmBinaryOpKind::I64_NE
-                    | WasmBinaryOpKind::I64_LT_S
-                    | WasmBinaryOpKind::I64_LT_U
-                    | WasmBinaryOpKind::I64_GT_S
-                    | WasmBinaryOpKind::I64_GT_U
-                    | WasmBinaryOpKind::I64_LE_S
-                    | WasmBinaryOpKind::I64_LE_U
-                    | WasmBinaryOpKind::I64_GE_S
-                    | WasmBinaryOpKind::I64_GE_U
-                    | WasmBinaryOpKind::I64_ADD
-                    | WasmBinaryOpKind::I64_SUB
-                    | WasmBinaryOpKind::I64_DIV_S
-                    | WasmBinaryOpKind::I64_REM_S
-                    | WasmBinaryOpKind::I64_AND
-                    | WasmBinaryOpKind::I64_OR
-                    | WasmBinaryOpKind::I64_SHL
-                    | WasmBinaryOpKind::I64_SHR_S
-                    | WasmBinaryOpKind::I64_SHR_U => todo!("{kind:?}"),
-
-                    WasmBinaryOpKind::F32_EQ
-                    | WasmBinaryOpKind::F32_NE
-                    | WasmBinaryOpKind::F32_LT
-                    | WasmBinaryOpKind::F32_GT
-                    | WasmBinaryOpKind::F32_LE
-                    | WasmBinaryOpKind::F32_GE
-                    | WasmBinaryOpKind::F32_ADD
-                    | WasmBinaryOpKind::F32_SUB
-                    | WasmBinaryOpKind::F32_MUL
-                    | WasmBinaryOpKind::F32_DIV => todo!("{kind:?}"),
-
-                    WasmBinaryOpKind::F64_EQ
-                    | WasmBinaryOpKind::F64_NE
-                    | WasmBinaryOpKind::F64_LT
-                    | WasmBinaryOpKind::F64_GT
-                    | WasmBinaryOpKind::F64_LE
-                    | WasmBinaryOpKind::F64_GE
-                    | WasmBinaryOpKind::F64_ADD
-                    | WasmBinaryOpKind::F64_SUB
-                    | WasmBinaryOpKind::F64_MUL
-                    | WasmBinaryOpKind::F64_DIV => todo!("{kind:?}"),
-                },
+                }
             }
 
             loc += 1;
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    fn get_jump_table_for_fn(&mut self, fn_index: u32) -> &'static JumpTable {
-        let jt_index = self
-            .jump_tables
-            .binary_search_by_key(&fn_index, |jt| jt.0)
-            .unwrap();
+    fn debug_pause(&mut self, loc: usize, op: &EvalOp) {
+        let Some(debugger) = &self.debugger else {
+            return;
+        };
+
+        // global and data initializers are not stepped through
+        let Some(frame) = self.call_stack.last() else {
+            return;
+        };
+
+        let fn_index = frame.fn_index;
+        let should_pause = match debugger.mode {
+            DebuggerMode::Step => true,
+            DebuggerMode::StepOver { call_depth } => self.call_stack.len() <= call_depth,
+            DebuggerMode::Continue => false,
+        } || debugger.breakpoints.contains(&(fn_index, loc));
+
+        if !should_pause {
+            return;
+        }
+
+        stdout_write(format!(
+            "paused at {}+{loc}: {op}\n",
+            self.get_fn_display_name(fn_index)
+        ));
+
+        loop {
+            stdout_write("(debug) ");
+            let Some(command) = stdin_read_line() else {
+                // <stdin> is exhausted, run to completion
+                self.debugger = None;
+                return;
+            };
+
+            let mut words = command.split_whitespace();
+            let mode = match (words.next(), words.next()) {
+                (None, _) => continue,
+                (Some("s" | "step"), None) => DebuggerMode::Step,
+                (Some("n" | "next"), None) => DebuggerMode::StepOver {
+                    call_depth: self.call_stack.len(),
+                },
+                (Some("f" | "finish"), None) => DebuggerMode::StepOver {
+                    call_depth: self.call_stack.len() - 1,
+                },
+                (Some("c" | "continue"), None) => DebuggerMode::Continue,
+                (Some("b" | "break"), Some(location)) => {
+                    self.debug_add_breakpoint(location);
+                    continue;
+                }
+                (Some("l" | "locals"), None) => {
+                    let frame = self.call_stack.last().unwrap();
+                    let local_names = self.get_local_names(frame.fn_index);
+                    stdout_write(format_locals(&frame.locals, local_names));
+                    continue;
+                }
+                (Some("g" | "globals"), None) => {
+                    stdout_write(format_values(&self.globals));
+                    continue;
+                }
+                (Some("stack"), None) => {
+                    stdout_write(format_values(&self.stack));
+                    continue;
+                }
+                (Some("bt" | "backtrace"), None) => {
+                    for frame in self.call_stack.iter().rev() {
+                        let fn_name = self.get_fn_display_name(frame.fn_index);
+                        stdout_write(format!("  at {fn_name}\n"));
+                    }
+                    continue;
+                }
+                (Some("q" | "quit"), None) => proc_exit(0),
+                _ => {
+                    stdout_write(DEBUGGER_HELP);
+                    continue;
+                }
+            };
 
-        unsafe_borrow(&self.jump_tables[jt_index].1)
+            self.debugger.as_mut().unwrap().mode = mode;
+            return;
+        }
+    }
+
+    // `location` is either a fn name or `<file>:<line>`,
+    //   the latter resolves to the first statement starting at the line
+    fn debug_add_breakpoint(&mut self, location: &str) {
+        let source_location = location
+            .rsplit_once(':')
+            .and_then(|(file_name, line)| Some((file_name, line.parse::<usize>().ok()?)));
+
+        let Some((file_name, line)) = source_location else {
+            let fn_info = self
+                .wasm_module
+                .debug_fn_info
+                .iter()
+                .find(|fn_info| fn_info.fn_name == location);
+            let Some(fn_info) = fn_info else {
+                stdout_write(format!("No function found at {location}\n"));
+                return;
+            };
+
+            stdout_write(format!(
+                "breakpoint set at {} ({})\n",
+                fn_info.fn_name, fn_info.loc
+            ));
+
+            let fn_index = fn_info.fn_index;
+            self.debugger
+                .as_mut()
+                .unwrap()
+                .breakpoints
+                .push((fn_index, 0));
+            return;
+        };
+
+        let mut found: Option<(u32, LoLocation, usize)> = None;
+        for (eval_fn, i) in self.fns.iter().zip(0..) {
+            for (offset, loc) in &eval_fn.code.statement_locs {
+                if !loc.file_name().ends_with(file_name) || loc.pos().line != line {
+                    continue;
+                }
+
+                if let Some((_, found_loc, _)) = found {
+                    if found_loc.pos().col <= loc.pos().col {
+                        continue;
+                    }
+                }
+
+                let fn_index = (self.fn_imports_len + i) as u32;
+                found = Some((fn_index, *loc, *offset));
+            }
+        }
+
+        let Some((fn_index, loc, offset)) = found else {
+            stdout_write(format!("No statement found at {location}\n"));
+            return;
+        };
+
+        stdout_write(format!(
+            "breakpoint set at {}+{offset} ({loc})\n",
+            self.get_fn_display_name(fn_index)
+        ));
+
+        self.debugger
+            .as_mut()
+            .unwrap()
+            .breakpoints
+            .push((fn_index, offset));
     }
 
     fn get_fn_info(&self, fn_index: u32) -> Result<(&WasmFnType, &WasmFn), EvalError> {
@@ -522,25 +1092,69 @@ impl WasmEval {
         let mut message = String::from(message.as_ref());
 
         while let Some(frame) = self.call_stack.pop() {
-            write!(&mut message, "\n  at ").unwrap();
-            if let Some(fn_name) = self.get_fn_name(frame.fn_index) {
-                write!(&mut message, "{fn_name}").unwrap();
-            } else {
-                write!(&mut message, "<unnamed-fn> #{}", frame.fn_index).unwrap();
-            }
+            let fn_name = self.get_fn_display_name(frame.fn_index);
+            write!(&mut message, "\n  at {fn_name}").unwrap();
         }
 
         EvalError { message }
     }
 
+    fn get_fn_display_name(&self, fn_index: u32) -> String {
+        if let Some(fn_name) = self.get_fn_name(fn_index) {
+            return String::from(fn_name);
+        }
+
+        let mut fn_imports = self
+            .wasm_module
+            .imports
+            .iter()
+            .filter(|import| matches!(import.item_desc, WasmImportDesc::Func { .. }));
+        if let Some(import) = fn_imports.nth(fn_index as usize) {
+            return format!("{}::{}", import.module_name, import.item_name);
+        }
+
+        format!("<unnamed-fn> #{fn_index}")
+    }
+
+    fn get_local_names(&self, fn_index: u32) -> &[WasmLocalName] {
+        match self.get_debug_fn_info(fn_index) {
+            Some(fn_info) => &fn_info.local_names,
+            None => &[],
+        }
+    }
+
     fn get_fn_name(&self, fn_index: u32) -> Option<&str> {
-        for fn_info in &self.wasm_module.debug_fn_info {
-            if fn_info.fn_index == fn_index {
-                return Some(&fn_info.fn_name);
-            }
+        Some(&self.get_debug_fn_info(fn_index)?.fn_name)
+    }
+
+    fn get_debug_fn_info(&self, fn_index: u32) -> Option<&WasmDebugFnInfo> {
+        let info_index = (*self.debug_fn_indices.get(fn_index as usize)?)?;
+        Some(&self.wasm_module.debug_fn_info[info_index])
+    }
+
+    fn pop_atomic_addr(&mut self, kind: &WasmStoreKind, offset: u32) -> Result<usize, EvalError> {
+        let addr = self.pop_i32() as u32 as usize + offset as usize;
+        if addr % (1 << kind.natural_align()) != 0 {
+            return Err(self.err_with_stack(format!("Unaligned atomic memory access: {addr}")));
         }
 
-        None
+        Ok(addr)
+    }
+
+    fn has_shared_memory(&self) -> bool {
+        let imported_memory =
+            self.wasm_module
+                .imports
+                .iter()
+                .find_map(|import| match &import.item_desc {
+                    WasmImportDesc::Memory(limits) => Some(limits),
+                    _ => None,
+                });
+
+        match imported_memory.or(self.wasm_module.memories.first()) {
+            Some(limits) => limits.shared,
+            None => false,
+        }
     }
 
     fn pop_i32(&mut self) -> i32 {
@@ -568,49 +1182,581 @@ impl WasmEval {
             proc_exit(1);
         };
 
-        value
+        value
+    }
+}
+
+// values
+
+#[derive(Debug, Clone)]
+pub enum WasmValue {
+    I32 { value: i32 },
+    I64 { value: i64 },
+    F32 { value: f32 },
+    F64 { value: f64 },
+    NullRef, // the evaluator has no host objects to reference
+}
+
+impl WasmValue {
+    fn default_for_type(wasm_type: &WasmType) -> Self {
+        match wasm_type {
+            WasmType::I32 => WasmValue::I32 { value: 0 },
+            WasmType::I64 => WasmValue::I64 { value: 0 },
+            WasmType::F32 => WasmValue::F32 { value: 0.0 },
+            WasmType::F64 => WasmValue::F64 { value: 0.0 },
+            WasmType::ExternRef => WasmValue::NullRef,
+            WasmType::Ref { .. } => unreachable!(), // gc modules are rejected in `init_module`
+        }
+    }
+
+    // integers are accepted in both signed and unsigned ranges
+    fn parse(value: &str, wasm_type: &WasmType) -> Option<Self> {
+        match wasm_type {
+            WasmType::I32 => {
+                let value = value.parse::<i64>().ok()?;
+                if value < i32::MIN as i64 || value > u32::MAX as i64 {
+                    return None;
+                }
+
+                Some(WasmValue::I32 {
+                    value: value as i32,
+                })
+            }
+            WasmType::I64 => {
+                let value = match value.parse::<i64>() {
+                    Ok(value) => value,
+                    Err(_) => value.parse::<u64>().ok()? as i64,
+                };
+
+                Some(WasmValue::I64 { value })
+            }
+            WasmType::F32 => Some(WasmValue::F32 {
+                value: value.parse().ok()?,
+            }),
+            WasmType::F64 => Some(WasmValue::F64 {
+                value: value.parse().ok()?,
+            }),
+            WasmType::ExternRef if value == "null" => Some(WasmValue::NullRef),
+            WasmType::ExternRef | WasmType::Ref { .. } => None,
+        }
+    }
+
+    fn from_bool(value: bool) -> Self {
+        WasmValue::I32 {
+            value: value as i32,
+        }
+    }
+
+    fn get_type(&self) -> WasmType {
+        match self {
+            WasmValue::I32 { .. } => WasmType::I32,
+            WasmValue::I64 { .. } => WasmType::I64,
+            WasmValue::F32 { .. } => WasmType::F32,
+            WasmValue::F64 { .. } => WasmType::F64,
+            WasmValue::NullRef => WasmType::ExternRef,
+        }
+    }
+}
+
+impl core::fmt::Display for WasmValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WasmValue::I32 { value } => write!(f, "{value}"),
+            WasmValue::I64 { value } => write!(f, "{value}"),
+            WasmValue::F32 { value } => write!(f, "{value}"),
+            WasmValue::F64 { value } => write!(f, "{value}"),
+            WasmValue::NullRef => f.write_str("null"),
+        }
+    }
+}
+
+// numeric ops
+
+const ERR_DIVIDE_BY_ZERO: &str = "Integer divide by zero";
+const ERR_INTEGER_OVERFLOW: &str = "Integer overflow";
+const ERR_INVALID_CONVERSION: &str = "Invalid conversion to integer";
+
+pub fn eval_binary_op(
+    kind: &WasmBinaryOpKind,
+    lhs: WasmValue,
+    rhs: WasmValue,
+) -> Result<WasmValue, String> {
+    match (lhs, rhs) {
+        (WasmValue::I32 { value: lhs }, WasmValue::I32 { value: rhs }) => {
+            eval_i32_binary_op(kind, lhs, rhs)
+        }
+        (WasmValue::I64 { value: lhs }, WasmValue::I64 { value: rhs }) => {
+            eval_i64_binary_op(kind, lhs, rhs)
+        }
+        (WasmValue::F32 { value: lhs }, WasmValue::F32 { value: rhs }) => {
+            eval_f32_binary_op(kind, lhs, rhs)
+        }
+        (WasmValue::F64 { value: lhs }, WasmValue::F64 { value: rhs }) => {
+            eval_f64_binary_op(kind, lhs, rhs)
+        }
+        (lhs, rhs) => Err(format!(
+            "Invalid operands for {kind:?}: {:?} and {:?}",
+            lhs.get_type(),
+            rhs.get_type()
+        )),
+    }
+}
+
+fn eval_i32_binary_op(kind: &WasmBinaryOpKind, lhs: i32, rhs: i32) -> Result<WasmValue, String> {
+    let value = match kind {
+        WasmBinaryOpKind::I32_EQ => (lhs == rhs) as i32,
+        WasmBinaryOpKind::I32_NE => (lhs != rhs) as i32,
+        WasmBinaryOpKind::I32_LT_S => (lhs < rhs) as i32,
+        WasmBinaryOpKind::I32_LT_U => ((lhs as u32) < (rhs as u32)) as i32,
+        WasmBinaryOpKind::I32_GT_S => (lhs > rhs) as i32,
+        WasmBinaryOpKind::I32_GT_U => ((lhs as u32) > (rhs as u32)) as i32,
+        WasmBinaryOpKind::I32_LE_S => (lhs <= rhs) as i32,
+        WasmBinaryOpKind::I32_LE_U => ((lhs as u32) <= (rhs as u32)) as i32,
+        WasmBinaryOpKind::I32_GE_S => (lhs >= rhs) as i32,
+        WasmBinaryOpKind::I32_GE_U => ((lhs as u32) >= (rhs as u32)) as i32,
+        WasmBinaryOpKind::I32_ADD => lhs.wrapping_add(rhs),
+        WasmBinaryOpKind::I32_SUB => lhs.wrapping_sub(rhs),
+        WasmBinaryOpKind::I32_MUL => lhs.wrapping_mul(rhs),
+        WasmBinaryOpKind::I32_DIV_S => {
+            if rhs == 0 {
+                return Err(ERR_DIVIDE_BY_ZERO.into());
+            }
+            lhs.checked_div(rhs).ok_or(ERR_INTEGER_OVERFLOW)?
+        }
+        WasmBinaryOpKind::I32_DIV_U => (lhs as u32)
+            .checked_div(rhs as u32)
+            .ok_or(ERR_DIVIDE_BY_ZERO)? as i32,
+        WasmBinaryOpKind::I32_REM_S => {
+            if rhs == 0 {
+                return Err(ERR_DIVIDE_BY_ZERO.into());
+            }
+            lhs.wrapping_rem(rhs)
+        }
+        WasmBinaryOpKind::I32_REM_U => (lhs as u32)
+            .checked_rem(rhs as u32)
+            .ok_or(ERR_DIVIDE_BY_ZERO)? as i32,
+        WasmBinaryOpKind::I32_AND => lhs & rhs,
+        WasmBinaryOpKind::I32_OR => lhs | rhs,
+        WasmBinaryOpKind::I32_XOR => lhs ^ rhs,
+        WasmBinaryOpKind::I32_SHL => lhs.wrapping_shl(rhs as u32),
+        WasmBinaryOpKind::I32_SHR_S => lhs.wrapping_shr(rhs as u32),
+        WasmBinaryOpKind::I32_SHR_U => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        WasmBinaryOpKind::I32_ROTL => lhs.rotate_left(rhs as u32),
+        WasmBinaryOpKind::I32_ROTR => lhs.rotate_right(rhs as u32),
+        _ => return Err(format!("Invalid operands for {kind:?}: I32 and I32")),
+    };
+
+    Ok(WasmValue::I32 { value })
+}
+
+fn eval_i64_binary_op(kind: &WasmBinaryOpKind, lhs: i64, rhs: i64) -> Result<WasmValue, String> {
+    let value = match kind {
+        WasmBinaryOpKind::I64_EQ => return Ok(WasmValue::from_bool(lhs == rhs)),
+        WasmBinaryOpKind::I64_NE => return Ok(WasmValue::from_bool(lhs != rhs)),
+        WasmBinaryOpKind::I64_LT_S => return Ok(WasmValue::from_bool(lhs < rhs)),
+        WasmBinaryOpKind::I64_LT_U => return Ok(WasmValue::from_bool((lhs as u64) < (rhs as u64))),
+        WasmBinaryOpKind::I64_GT_S => return Ok(WasmValue::from_bool(lhs > rhs)),
+        WasmBinaryOpKind::I64_GT_U => return Ok(WasmValue::from_bool((lhs as u64) > (rhs as u64))),
+        WasmBinaryOpKind::I64_LE_S => return Ok(WasmValue::from_bool(lhs <= rhs)),
+        WasmBinaryOpKind::I64_LE_U => {
+            return Ok(WasmValue::from_bool((lhs as u64) <= (rhs as u64)))
+        }
+        WasmBinaryOpKind::I64_GE_S => return Ok(WasmValue::from_bool(lhs >= rhs)),
+        WasmBinaryOpKind::I64_GE_U => {
+            return Ok(WasmValue::from_bool((lhs as u64) >= (rhs as u64)))
+        }
+        WasmBinaryOpKind::I64_ADD => lhs.wrapping_add(rhs),
+        WasmBinaryOpKind::I64_SUB => lhs.wrapping_sub(rhs),
+        WasmBinaryOpKind::I64_MUL => lhs.wrapping_mul(rhs),
+        WasmBinaryOpKind::I64_DIV_S => {
+            if rhs == 0 {
+                return Err(ERR_DIVIDE_BY_ZERO.into());
+            }
+            lhs.checked_div(rhs).ok_or(ERR_INTEGER_OVERFLOW)?
+        }
+        WasmBinaryOpKind::I64_DIV_U => (lhs as u64)
+            .checked_div(rhs as u64)
+            .ok_or(ERR_DIVIDE_BY_ZERO)? as i64,
+        WasmBinaryOpKind::I64_REM_S => {
+            if rhs == 0 {
+                return Err(ERR_DIVIDE_BY_ZERO.into());
+            }
+            lhs.wrapping_rem(rhs)
+        }
+        WasmBinaryOpKind::I64_REM_U => (lhs as u64)
+            .checked_rem(rhs as u64)
+            .ok_or(ERR_DIVIDE_BY_ZERO)? as i64,
+        WasmBinaryOpKind::I64_AND => lhs & rhs,
+        WasmBinaryOpKind::I64_OR => lhs | rhs,
+        WasmBinaryOpKind::I64_XOR => lhs ^ rhs,
+        WasmBinaryOpKind::I64_SHL => lhs.wrapping_shl(rhs as u32),
+        WasmBinaryOpKind::I64_SHR_S => lhs.wrapping_shr(rhs as u32),
+        WasmBinaryOpKind::I64_SHR_U => (lhs as u64).wrapping_shr(rhs as u32) as i64,
+        WasmBinaryOpKind::I64_ROTL => lhs.rotate_left((rhs & 63) as u32),
+        WasmBinaryOpKind::I64_ROTR => lhs.rotate_right((rhs & 63) as u32),
+        _ => return Err(format!("Invalid operands for {kind:?}: I64 and I64")),
+    };
+
+    Ok(WasmValue::I64 { value })
+}
+
+fn eval_f32_binary_op(kind: &WasmBinaryOpKind, lhs: f32, rhs: f32) -> Result<WasmValue, String> {
+    let value = match kind {
+        WasmBinaryOpKind::F32_EQ => return Ok(WasmValue::from_bool(lhs == rhs)),
+        WasmBinaryOpKind::F32_NE => return Ok(WasmValue::from_bool(lhs != rhs)),
+        WasmBinaryOpKind::F32_LT => return Ok(WasmValue::from_bool(lhs < rhs)),
+        WasmBinaryOpKind::F32_GT => return Ok(WasmValue::from_bool(lhs > rhs)),
+        WasmBinaryOpKind::F32_LE => return Ok(WasmValue::from_bool(lhs <= rhs)),
+        WasmBinaryOpKind::F32_GE => return Ok(WasmValue::from_bool(lhs >= rhs)),
+        WasmBinaryOpKind::F32_ADD => lhs + rhs,
+        WasmBinaryOpKind::F32_SUB => lhs - rhs,
+        WasmBinaryOpKind::F32_MUL => lhs * rhs,
+        WasmBinaryOpKind::F32_DIV => lhs / rhs,
+        WasmBinaryOpKind::F32_MIN => {
+            if lhs.is_nan() || rhs.is_nan() {
+                f32::NAN
+            } else if lhs == rhs {
+                // min(-0, +0) is -0
+                f32::from_bits(lhs.to_bits() | rhs.to_bits())
+            } else {
+                lhs.min(rhs)
+            }
+        }
+        WasmBinaryOpKind::F32_MAX => {
+            if lhs.is_nan() || rhs.is_nan() {
+                f32::NAN
+            } else if lhs == rhs {
+                // max(-0, +0) is +0
+                f32::from_bits(lhs.to_bits() & rhs.to_bits())
+            } else {
+                lhs.max(rhs)
+            }
+        }
+        WasmBinaryOpKind::F32_COPYSIGN => {
+            let sign_mask = 1 << 31;
+            f32::from_bits((lhs.to_bits() & !sign_mask) | (rhs.to_bits() & sign_mask))
+        }
+        _ => return Err(format!("Invalid operands for {kind:?}: F32 and F32")),
+    };
+
+    Ok(WasmValue::F32 { value })
+}
+
+fn eval_f64_binary_op(kind: &WasmBinaryOpKind, lhs: f64, rhs: f64) -> Result<WasmValue, String> {
+    let value = match kind {
+        WasmBinaryOpKind::F64_EQ => return Ok(WasmValue::from_bool(lhs == rhs)),
+        WasmBinaryOpKind::F64_NE => return Ok(WasmValue::from_bool(lhs != rhs)),
+        WasmBinaryOpKind::F64_LT => return Ok(WasmValue::from_bool(lhs < rhs)),
+        WasmBinaryOpKind::F64_GT => return Ok(WasmValue::from_bool(lhs > rhs)),
+        WasmBinaryOpKind::F64_LE => return Ok(WasmValue::from_bool(lhs <= rhs)),
+        WasmBinaryOpKind::F64_GE => return Ok(WasmValue::from_bool(lhs >= rhs)),
+        WasmBinaryOpKind::F64_ADD => lhs + rhs,
+        WasmBinaryOpKind::F64_SUB => lhs - rhs,
+        WasmBinaryOpKind::F64_MUL => lhs * rhs,
+        WasmBinaryOpKind::F64_DIV => lhs / rhs,
+        WasmBinaryOpKind::F64_MIN => {
+            if lhs.is_nan() || rhs.is_nan() {
+                f64::NAN
+            } else if lhs == rhs {
+                // min(-0, +0) is -0
+                f64::from_bits(lhs.to_bits() | rhs.to_bits())
+            } else {
+                lhs.min(rhs)
+            }
+        }
+        WasmBinaryOpKind::F64_MAX => {
+            if lhs.is_nan() || rhs.is_nan() {
+                f64::NAN
+            } else if lhs == rhs {
+                // max(-0, +0) is +0
+                f64::from_bits(lhs.to_bits() & rhs.to_bits())
+            } else {
+                lhs.max(rhs)
+            }
+        }
+        WasmBinaryOpKind::F64_COPYSIGN => {
+            let sign_mask = 1 << 63;
+            f64::from_bits((lhs.to_bits() & !sign_mask) | (rhs.to_bits() & sign_mask))
+        }
+        _ => return Err(format!("Invalid operands for {kind:?}: F64 and F64")),
+    };
+
+    Ok(WasmValue::F64 { value })
+}
+
+fn eval_unary_op(kind: &WasmUnaryOpKind, operand: WasmValue) -> Result<WasmValue, String> {
+    use core::intrinsics::*;
+    use WasmUnaryOpKind::*;
+    use WasmValue::*;
+
+    let value = match (kind, operand) {
+        (I32_EQZ, I32 { value }) => WasmValue::from_bool(value == 0),
+        (I64_EQZ, I64 { value }) => WasmValue::from_bool(value == 0),
+
+        (I32_CLZ, I32 { value }) => I32 {
+            value: value.leading_zeros() as i32,
+        },
+        (I32_CTZ, I32 { value }) => I32 {
+            value: value.trailing_zeros() as i32,
+        },
+        (I32_POPCNT, I32 { value }) => I32 {
+            value: value.count_ones() as i32,
+        },
+        (I64_CLZ, I64 { value }) => I64 {
+            value: value.leading_zeros() as i64,
+        },
+        (I64_CTZ, I64 { value }) => I64 {
+            value: value.trailing_zeros() as i64,
+        },
+        (I64_POPCNT, I64 { value }) => I64 {
+            value: value.count_ones() as i64,
+        },
+
+        (F32_ABS, F32 { value }) => F32 {
+            value: f32::from_bits(value.to_bits() & !(1 << 31)),
+        },
+        (F32_NEG, F32 { value }) => F32 {
+            value: f32::from_bits(value.to_bits() ^ (1 << 31)),
+        },
+        (F32_CEIL, F32 { value }) => F32 {
+            value: unsafe { ceilf32(value) },
+        },
+        (F32_FLOOR, F32 { value }) => F32 {
+            value: unsafe { floorf32(value) },
+        },
+        (F32_TRUNC, F32 { value }) => F32 {
+            value: unsafe { truncf32(value) },
+        },
+        (F32_NEAREST, F32 { value }) => F32 {
+            value: unsafe { rintf32(value) },
+        },
+        (F32_SQRT, F32 { value }) => F32 {
+            value: unsafe { sqrtf32(value) },
+        },
+
+        (F64_ABS, F64 { value }) => F64 {
+            value: f64::from_bits(value.to_bits() & !(1 << 63)),
+        },
+        (F64_NEG, F64 { value }) => F64 {
+            value: f64::from_bits(value.to_bits() ^ (1 << 63)),
+        },
+        (F64_CEIL, F64 { value }) => F64 {
+            value: unsafe { ceilf64(value) },
+        },
+        (F64_FLOOR, F64 { value }) => F64 {
+            value: unsafe { floorf64(value) },
+        },
+        (F64_TRUNC, F64 { value }) => F64 {
+            value: unsafe { truncf64(value) },
+        },
+        (F64_NEAREST, F64 { value }) => F64 {
+            value: unsafe { rintf64(value) },
+        },
+        (F64_SQRT, F64 { value }) => F64 {
+            value: unsafe { sqrtf64(value) },
+        },
+
+        (I32_TRUNC_F32_S, F32 { value }) => I32 {
+            value: trunc_to_int(value as f64, -2147483649.0, 2147483648.0)? as i32,
+        },
+        (I32_TRUNC_F32_U, F32 { value }) => I32 {
+            value: trunc_to_int(value as f64, -1.0, 4294967296.0)? as u32 as i32,
+        },
+        (I32_TRUNC_F64_S, F64 { value }) => I32 {
+            value: trunc_to_int(value, -2147483649.0, 2147483648.0)? as i32,
+        },
+        (I32_TRUNC_F64_U, F64 { value }) => I32 {
+            value: trunc_to_int(value, -1.0, 4294967296.0)? as u32 as i32,
+        },
+        (I64_TRUNC_F32_S, F32 { value }) => I64 {
+            value: trunc_to_i64(value as f64)?,
+        },
+        (I64_TRUNC_F32_U, F32 { value }) => I64 {
+            value: trunc_to_u64(value as f64)? as i64,
+        },
+        (I64_TRUNC_F64_S, F64 { value }) => I64 {
+            value: trunc_to_i64(value)?,
+        },
+        (I64_TRUNC_F64_U, F64 { value }) => I64 {
+            value: trunc_to_u64(value)? as i64,
+        },
+
+        (F32_CONVERT_I32_S, I32 { value }) => F32 {
+            value: value as f32,
+        },
+        (F32_CONVERT_I32_U, I32 { value }) => F32 {
+            value: value as u32 as f32,
+        },
+        (F32_CONVERT_I64_S, I64 { value }) => F32 {
+            value: value as f32,
+        },
+        (F32_CONVERT_I64_U, I64 { value }) => F32 {
+            value: value as u64 as f32,
+        },
+        (F32_DEMOTE_F64, F64 { value }) => F32 {
+            value: value as f32,
+        },
+        (F64_CONVERT_I32_S, I32 { value }) => F64 {
+            value: value as f64,
+        },
+        (F64_CONVERT_I32_U, I32 { value }) => F64 {
+            value: value as u32 as f64,
+        },
+        (F64_CONVERT_I64_S, I64 { value }) => F64 {
+            value: value as f64,
+        },
+        (F64_CONVERT_I64_U, I64 { value }) => F64 {
+            value: value as u64 as f64,
+        },
+        (F64_PROMOTE_F32, F32 { value }) => F64 {
+            value: value as f64,
+        },
+
+        (I32_REINTERPRET_F32, F32 { value }) => I32 {
+            value: value.to_bits() as i32,
+        },
+        (I64_REINTERPRET_F64, F64 { value }) => I64 {
+            value: value.to_bits() as i64,
+        },
+        (F32_REINTERPRET_I32, I32 { value }) => F32 {
+            value: f32::from_bits(value as u32),
+        },
+        (F64_REINTERPRET_I64, I64 { value }) => F64 {
+            value: f64::from_bits(value as u64),
+        },
+
+        (kind, operand) => {
+            return Err(format!(
+                "Invalid operand for {kind:?}: {:?}",
+                operand.get_type()
+            ))
+        }
+    };
+
+    Ok(value)
+}
+
+// checks that `value` truncated toward zero fits into (`min`, `max`) exclusive range,
+//   any f32 or i32 is exactly representable as f64 so all the i32/u32 checks are done in f64
+fn trunc_to_int(value: f64, min: f64, max: f64) -> Result<i64, String> {
+    if value.is_nan() {
+        return Err(ERR_INVALID_CONVERSION.into());
+    }
+    if value <= min || value >= max {
+        return Err(ERR_INTEGER_OVERFLOW.into());
+    }
+
+    Ok(value as i64)
+}
+
+fn trunc_to_i64(value: f64) -> Result<i64, String> {
+    if value.is_nan() {
+        return Err(ERR_INVALID_CONVERSION.into());
+    }
+    // -2^63 is the smallest valid value as next f64 below it is -2^63 - 2048
+    if !(-9223372036854775808.0..9223372036854775808.0).contains(&value) {
+        return Err(ERR_INTEGER_OVERFLOW.into());
+    }
+
+    Ok(value as i64)
+}
+
+fn trunc_to_u64(value: f64) -> Result<u64, String> {
+    if value.is_nan() {
+        return Err(ERR_INVALID_CONVERSION.into());
+    }
+    if value <= -1.0 || value >= 18446744073709551616.0 {
+        return Err(ERR_INTEGER_OVERFLOW.into());
     }
-}
 
-// values
+    Ok(value as u64)
+}
 
-#[derive(Debug, Clone)]
-pub enum WasmValue {
-    I32 { value: i32 },
-    I64 { value: i64 },
-    F32 { value: f32 },
-    F64 { value: f64 },
+#[derive(Default)]
+struct EvalCounters {
+    instrs: u64,
+    fn_stats: Vec<(u64, u64)>, // calls and own instrs, indexed by fn index
 }
 
-impl WasmValue {
-    fn default_for_type(wasm_type: &WasmType) -> Self {
-        match wasm_type {
-            WasmType::I32 => WasmValue::I32 { value: 0 },
-            WasmType::I64 => WasmValue::I64 { value: 0 },
-            WasmType::F32 => WasmValue::F32 { value: 0.0 },
-            WasmType::F64 => WasmValue::F64 { value: 0.0 },
+impl EvalCounters {
+    fn fn_stats_mut(&mut self, fn_index: u32) -> &mut (u64, u64) {
+        let fn_index = fn_index as usize;
+        if fn_index >= self.fn_stats.len() {
+            self.fn_stats.resize(fn_index + 1, (0, 0));
         }
+        &mut self.fn_stats[fn_index]
     }
+}
 
-    fn get_type(&self) -> WasmType {
-        match self {
-            WasmValue::I32 { .. } => WasmType::I32,
-            WasmValue::I64 { .. } => WasmType::I64,
-            WasmValue::F32 { .. } => WasmType::F32,
-            WasmValue::F64 { .. } => WasmType::F64,
-        }
+fn digit_count(value: u64) -> usize {
+    value.checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+// debugger
+
+const DEBUGGER_HELP: &str = "\
+commands:
+  s, step              execute next wasm instruction (not source line)
+  n, next              execute next wasm instruction, stepping over calls
+  f, finish            run until current function returns
+  c, continue          run until next breakpoint
+  b, break <location>  break on entry to <fn> or on first statement at <file>:<line>
+  l, locals            print locals of current function
+  g, globals           print globals
+  stack                print value stack
+  bt, backtrace        print call stack
+  q, quit              exit the program
+";
+
+struct Debugger {
+    breakpoints: Vec<(u32, usize)>, // fn index and op offset
+    mode: DebuggerMode,
+}
+
+enum DebuggerMode {
+    Step,
+    StepOver { call_depth: usize },
+    Continue,
+}
+
+fn format_values(values: &[WasmValue]) -> String {
+    use core::fmt::Write;
+
+    let mut output = String::new();
+    for (value, i) in values.iter().zip(0..) {
+        writeln!(&mut output, "  #{i}: {:?} = {value}", value.get_type()).unwrap();
     }
+
+    output
 }
 
-impl core::fmt::Display for WasmValue {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match self {
-            WasmValue::I32 { value } => write!(f, "{value}"),
-            WasmValue::I64 { value } => write!(f, "{value}"),
-            WasmValue::F32 { value } => write!(f, "{value}"),
-            WasmValue::F64 { value } => write!(f, "{value}"),
+fn format_locals(locals: &[WasmValue], local_names: &[WasmLocalName]) -> String {
+    use core::fmt::Write;
+
+    let mut output = String::new();
+    for (value, i) in locals.iter().zip(0..) {
+        let value_type = value.get_type();
+        match local_names.iter().find(|n| n.local_index == i) {
+            Some(local_name) => {
+                let name = &local_name.name;
+                writeln!(&mut output, "  #{i} {name}: {value_type:?} = {value}").unwrap();
+            }
+            None => writeln!(&mut output, "  #{i}: {value_type:?} = {value}").unwrap(),
         }
     }
+
+    output
+}
+
+fn apply_atomic_rmw(op: &WasmAtomicRmwOp, old: i64, operand: i64) -> i64 {
+    match op {
+        WasmAtomicRmwOp::Add => old.wrapping_add(operand),
+        WasmAtomicRmwOp::Sub => old.wrapping_sub(operand),
+        WasmAtomicRmwOp::And => old & operand,
+        WasmAtomicRmwOp::Or => old | operand,
+        WasmAtomicRmwOp::Xor => old ^ operand,
+        WasmAtomicRmwOp::Xchg => operand,
+    }
 }
 
 // state
@@ -622,10 +1768,127 @@ struct LinearMemory {
 }
 
 impl LinearMemory {
+    fn load(&self, kind: &WasmLoadKind, addr: usize) -> Option<WasmValue> {
+        let value = match kind {
+            WasmLoadKind::I32 => WasmValue::I32 {
+                value: i32::from_le_bytes(self.read(addr)?),
+            },
+            WasmLoadKind::I64 => WasmValue::I64 {
+                value: i64::from_le_bytes(self.read(addr)?),
+            },
+            WasmLoadKind::F32 => WasmValue::F32 {
+                value: f32::from_le_bytes(self.read(addr)?),
+            },
+            WasmLoadKind::F64 => WasmValue::F64 {
+                value: f64::from_le_bytes(self.read(addr)?),
+            },
+            WasmLoadKind::I32I8 => WasmValue::I32 {
+                value: i8::from_le_bytes(self.read(addr)?) as i32,
+            },
+            WasmLoadKind::I32U8 => WasmValue::I32 {
+                value: u8::from_le_bytes(self.read(addr)?) as i32,
+            },
+            WasmLoadKind::I32I16 => WasmValue::I32 {
+                value: i16::from_le_bytes(self.read(addr)?) as i32,
+            },
+            WasmLoadKind::I32U16 => WasmValue::I32 {
+                value: u16::from_le_bytes(self.read(addr)?) as i32,
+            },
+            WasmLoadKind::I64I8 => WasmValue::I64 {
+                value: i8::from_le_bytes(self.read(addr)?) as i64,
+            },
+            WasmLoadKind::I64U8 => WasmValue::I64 {
+                value: u8::from_le_bytes(self.read(addr)?) as i64,
+            },
+            WasmLoadKind::I64I16 => WasmValue::I64 {
+                value: i16::from_le_bytes(self.read(addr)?) as i64,
+            },
+            WasmLoadKind::I64U16 => WasmValue::I64 {
+                value: u16::from_le_bytes(self.read(addr)?) as i64,
+            },
+            WasmLoadKind::I64I32 => WasmValue::I64 {
+                value: i32::from_le_bytes(self.read(addr)?) as i64,
+            },
+            WasmLoadKind::I64U32 => WasmValue::I64 {
+                value: u32::from_le_bytes(self.read(addr)?) as i64,
+            },
+        };
+
+        Some(value)
+    }
+
+    fn read<const N: usize>(&self, addr: usize) -> Option<[u8; N]> {
+        self.bytes.get(addr..addr + N)?.try_into().ok()
+    }
+
+    fn store_value(
+        &mut self,
+        kind: &WasmStoreKind,
+        addr: usize,
+        value: WasmValue,
+    ) -> Result<(), String> {
+        let stored = match (kind, value) {
+            (WasmStoreKind::I32, WasmValue::I32 { value }) => {
+                self.store(addr, &value.to_le_bytes())
+            }
+            (WasmStoreKind::I64, WasmValue::I64 { value }) => {
+                self.store(addr, &value.to_le_bytes())
+            }
+            (WasmStoreKind::F32, WasmValue::F32 { value }) => {
+                self.store(addr, &value.to_le_bytes())
+            }
+            (WasmStoreKind::F64, WasmValue::F64 { value }) => {
+                self.store(addr, &value.to_le_bytes())
+            }
+            (WasmStoreKind::I32U8, WasmValue::I32 { value }) => {
+                self.store(addr, &(value as u8).to_le_bytes())
+            }
+            (WasmStoreKind::I32U16, WasmValue::I32 { value }) => {
+                self.store(addr, &(value as u16).to_le_bytes())
+            }
+            (WasmStoreKind::I64U8, WasmValue::I64 { value }) => {
+                self.store(addr, &(value as u8).to_le_bytes())
+            }
+            (WasmStoreKind::I64U16, WasmValue::I64 { value }) => {
+                self.store(addr, &(value as u16).to_le_bytes())
+            }
+            (WasmStoreKind::I64U32, WasmValue::I64 { value }) => {
+                self.store(addr, &(value as u32).to_le_bytes())
+            }
+            (kind, value) => {
+                return Err(format!(
+                    "Cannot store {:?} value as {kind:?}",
+                    value.get_type()
+                ));
+            }
+        };
+
+        if stored.is_none() {
+            return Err(format!("Out of bounds memory access: {addr}"));
+        }
+
+        Ok(())
+    }
+
+    fn store(&mut self, addr: usize, bytes: &[u8]) -> Option<()> {
+        self.bytes
+            .get_mut(addr..addr + bytes.len())?
+            .copy_from_slice(bytes);
+        Some(())
+    }
+
+    // pointers passed to host fns are checked before use, guest gets EFAULT instead of a panic
+    fn contains(&self, addr: i32, len: usize) -> bool {
+        (addr as u32 as usize)
+            .checked_add(len)
+            .is_some_and(|end| end <= self.bytes.len())
+    }
+
     fn load_i32(&self, addr: usize) -> i32 {
         i32::from_le_bytes(self.bytes[addr..addr + 4].try_into().unwrap())
     }
 
+    #[cfg(target_arch = "wasm32")]
     fn store_i16(&mut self, addr: usize, value: i16) {
         self.bytes[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
     }
@@ -637,6 +1900,19 @@ impl LinearMemory {
     fn store_i64(&mut self, addr: usize, value: i64) {
         self.bytes[addr..addr + 8].copy_from_slice(&value.to_le_bytes());
     }
+
+    // makes pointers written by host fns point to guest memory instead of host memory
+    #[cfg(target_arch = "wasm32")]
+    fn relocate_host_ptrs(&mut self, ptrs: *mut *mut u8, count: usize) {
+        let mem_base = self.bytes.as_ptr() as usize;
+
+        for i in 0..count {
+            unsafe {
+                let ptr_i = ptrs.add(i);
+                *ptr_i = (((*ptr_i) as usize) - mem_base) as *mut u8;
+            }
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -645,83 +1921,127 @@ struct CallFrame {
     locals: Vec<WasmValue>,
 }
 
+struct EvalFn {
+    inputs_len: usize,
+    locals: Vec<WasmValue>, // initial values of non-param locals
+    code: EvalCode,
+}
+
+// instructions with block structure flattened into jumps to precomputed locations
 #[derive(Default, Debug)]
-struct JumpTable {
-    jumps: Vec<(usize, usize)>, // (from, to)
+struct EvalCode {
+    ops: Vec<EvalOp>,
+    statement_locs: Vec<(usize, LoLocation)>, // op offsets of `SourceLoc` markers
+}
+
+#[derive(Debug)]
+enum EvalOp {
+    Jump { to: usize },
+    JumpUnless { to: usize },
+    Return,
+    Instr { instr: WasmInstr },
+}
+
+impl core::fmt::Display for EvalOp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EvalOp::Jump { to } => write!(f, "Jump {{ to: {to} }}"),
+            EvalOp::JumpUnless { to } => write!(f, "JumpUnless {{ to: {to} }}"),
+            EvalOp::Return => write!(f, "Return"),
+            EvalOp::Instr { instr } => write!(f, "{instr:?}"),
+        }
+    }
 }
 
-impl JumpTable {
-    fn for_expr(expr: &WasmExpr) -> Self {
+impl EvalCode {
+    fn decode(expr: &WasmExpr) -> Self {
         struct BlockInfo {
-            loc: usize,
             kind: WasmBlockKind,
-            unresolved_branches: Vec<usize>,
-            had_else: bool,
+            start_loc: usize,
+            jump_unless_loc: Option<usize>, // only for `if` without `else` yet
+            unresolved_jumps: Vec<usize>,
         }
 
-        let mut jump_table = JumpTable::default();
+        let mut code = EvalCode::default();
 
         let mut blocks = Vec::<BlockInfo>::new();
-        for (instr, loc) in expr.instrs.iter().zip(0..) {
+        for instr in &expr.instrs {
             match instr {
                 WasmInstr::BlockStart { block_kind, .. } => {
-                    blocks.push(BlockInfo {
-                        loc,
+                    let mut block = BlockInfo {
                         kind: block_kind.clone(),
-                        unresolved_branches: Vec::new(),
-                        had_else: false,
-                    });
+                        start_loc: code.ops.len(),
+                        jump_unless_loc: None,
+                        unresolved_jumps: Vec::new(),
+                    };
+
+                    if let WasmBlockKind::If = block_kind {
+                        block.jump_unless_loc = Some(code.ops.len());
+                        code.ops.push(EvalOp::JumpUnless { to: 0 });
+                    }
+
+                    blocks.push(block);
                 }
                 WasmInstr::Else => {
                     let block = blocks.last_mut().unwrap();
                     assert_eq!(block.kind, WasmBlockKind::If);
-                    jump_table.jumps.push((block.loc, loc + 1));
-                    block.had_else = true;
-                    block.unresolved_branches.push(loc);
+
+                    block.unresolved_jumps.push(code.ops.len());
+                    code.ops.push(EvalOp::Jump { to: 0 });
+
+                    let jump_unless_loc = block.jump_unless_loc.take().unwrap();
+                    code.ops[jump_unless_loc] = EvalOp::JumpUnless { to: code.ops.len() };
                 }
                 WasmInstr::Branch { label_index } => {
+                    let label_index = *label_index as usize;
+
+                    // branching to the fn body's label is a return
+                    if label_index == blocks.len() {
+                        code.ops.push(EvalOp::Return);
+                        continue;
+                    }
+
                     let blocks_len = blocks.len();
-                    let target_block = blocks
-                        .get_mut(blocks_len - 1 - *label_index as usize)
-                        .unwrap();
+                    let target_block = blocks.get_mut(blocks_len - 1 - label_index).unwrap();
 
                     match target_block.kind {
                         WasmBlockKind::Loop => {
-                            jump_table.jumps.push((loc, target_block.loc + 1));
+                            let to = target_block.start_loc;
+                            code.ops.push(EvalOp::Jump { to });
                         }
                         _ => {
-                            target_block.unresolved_branches.push(loc);
+                            target_block.unresolved_jumps.push(code.ops.len());
+                            code.ops.push(EvalOp::Jump { to: 0 });
                         }
                     }
                 }
                 WasmInstr::BlockEnd => {
                     let block = blocks.pop().unwrap();
+                    let end_loc = code.ops.len();
 
-                    for branch_loc in block.unresolved_branches {
-                        jump_table.jumps.push((branch_loc, loc));
+                    for jump_loc in block.unresolved_jumps {
+                        code.ops[jump_loc] = EvalOp::Jump { to: end_loc };
                     }
 
-                    if let WasmBlockKind::If = block.kind {
-                        if !block.had_else {
-                            jump_table.jumps.push((block.loc, loc));
-                        }
+                    if let Some(jump_unless_loc) = block.jump_unless_loc {
+                        code.ops[jump_unless_loc] = EvalOp::JumpUnless { to: end_loc };
                     }
                 }
-                _ => {}
+                WasmInstr::Return => {
+                    code.ops.push(EvalOp::Return);
+                }
+                WasmInstr::SourceLoc { loc } => {
+                    code.statement_locs.push((code.ops.len(), *loc));
+                }
+                _ => {
+                    code.ops.push(EvalOp::Instr {
+                        instr: instr.clone(),
+                    });
+                }
             }
         }
 
-        // jumps from branches are created out of order so need to sort
-        jump_table.jumps.sort_by_key(|&(from, _)| from);
-
-        jump_table
-    }
-
-    fn get_jump_loc(&self, from_loc: usize) -> usize {
-        let jump_index = self
-            .jumps
-            .binary_search_by_key(&from_loc, |(from, _)| *from);
-        self.jumps[jump_index.unwrap()].1
+        code
     }
 }
 
@@ -734,7 +2054,7 @@ struct SupportedHostFn {
     fn_outputs: &'static [WasmType],
 }
 
-static SUPPORTED_HOST_FNS: [SupportedHostFn; 12] = [
+static SUPPORTED_HOST_FNS: [SupportedHostFn; 16] = [
     SupportedHostFn {
         module_name: "utils",
         fn_name: "debug",
@@ -793,6 +2113,30 @@ static SUPPORTED_HOST_FNS: [SupportedHostFn; 12] = [
         fn_inputs: &[WasmType::I32, WasmType::I32],
         fn_outputs: &[WasmType::I32],
     },
+    SupportedHostFn {
+        module_name: "wasi_snapshot_preview1",
+        fn_name: "environ_sizes_get",
+        fn_inputs: &[WasmType::I32, WasmType::I32],
+        fn_outputs: &[WasmType::I32],
+    },
+    SupportedHostFn {
+        module_name: "wasi_snapshot_preview1",
+        fn_name: "environ_get",
+        fn_inputs: &[WasmType::I32, WasmType::I32],
+        fn_outputs: &[WasmType::I32],
+    },
+    SupportedHostFn {
+        module_name: "wasi_snapshot_preview1",
+        fn_name: "clock_res_get",
+        fn_inputs: &[WasmType::I32, WasmType::I32],
+        fn_outputs: &[WasmType::I32],
+    },
+    SupportedHostFn {
+        module_name: "wasi_snapshot_preview1",
+        fn_name: "clock_time_get",
+        fn_inputs: &[WasmType::I32, WasmType::I64, WasmType::I32],
+        fn_outputs: &[WasmType::I32],
+    },
     SupportedHostFn {
         module_name: "wasi_snapshot_preview1",
         fn_name: "proc_exit",
@@ -820,7 +2164,25 @@ static SUPPORTED_HOST_FNS: [SupportedHostFn; 12] = [
 ];
 
 fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
-    let fn_name = &eval.host_fns[fn_index as usize];
+    let fn_name = match unsafe_borrow(&eval.host_fns[fn_index as usize]) {
+        HostFn::Builtin { full_name } => full_name,
+        HostFn::Trap { full_name } => {
+            return Err(eval.err_with_stack(format!("Called trapping import {full_name}")));
+        }
+        HostFn::Noop {
+            inputs_len,
+            outputs,
+        } => {
+            for _ in 0..*inputs_len {
+                eval.stack.pop().unwrap();
+            }
+            for output in outputs {
+                eval.stack.push(WasmValue::default_for_type(output));
+            }
+            return Ok(());
+        }
+    };
+
     match &fn_name[..] {
         "utils::debug" => {
             let value = eval.pop_i32() as u32;
@@ -835,6 +2197,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
             let message = str::from_utf8(message_bytes).unwrap();
             stderr_write(message);
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::fd_prestat_get" => {
             let buf = eval.pop_i32();
             let fd = eval.pop_i32();
@@ -852,6 +2215,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             }
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::fd_prestat_dir_name" => {
             let path_len = eval.pop_i32();
             let path = eval.pop_i32();
@@ -881,6 +2245,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 dealloc(path_buf, layout);
             }
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::fd_fdstat_get" => {
             let fdstat_ptr = eval.pop_i32();
             let fd = eval.pop_i32();
@@ -902,6 +2267,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             }
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::path_open" => {
             let fd_ptr = eval.pop_i32();
             let fdflags = eval.pop_i32();
@@ -936,6 +2302,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             }
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::fd_write" => {
             let nwritten_ptr = eval.pop_i32();
             let iovs_len = eval.pop_i32();
@@ -955,6 +2322,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 });
             }
 
+            stderr_flush();
             match unsafe { wasi::fd_write(fd as u32, &iovs) } {
                 Ok(nwritten) => {
                     eval.stack.push(WasmValue::I32 { value: 0 });
@@ -966,6 +2334,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             };
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::fd_read" => {
             let nread_ptr = eval.pop_i32();
             let iovs_len = eval.pop_i32();
@@ -995,6 +2364,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             };
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::fd_close" => {
             let fd = eval.pop_i32();
 
@@ -1005,6 +2375,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             }
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::args_sizes_get" => {
             let argv_buf_size_ptr = eval.pop_i32();
             let argc_ptr = eval.pop_i32();
@@ -1022,6 +2393,7 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             }
         }
+        #[cfg(target_arch = "wasm32")]
         "wasi_snapshot_preview1::args_get" => {
             let argv_buf_ptr = eval.pop_i32();
             let argv_ptr = eval.pop_i32();
@@ -1031,18 +2403,128 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
 
             match unsafe { wasi::args_get(argv, argv_buf) } {
                 Ok(()) => {
-                    // fix argv pointers to point to guest memory instead of host memory
-                    {
-                        let mem_base = (&eval.memory.bytes).as_ptr() as usize;
+                    let (argc, _) = unsafe { wasi::args_sizes_get() }.unwrap();
+                    eval.memory.relocate_host_ptrs(argv, argc);
 
-                        let (argc, _) = unsafe { wasi::args_sizes_get() }.unwrap();
-                        for i in 0..argc {
-                            unsafe {
-                                let argv_i = argv.add(i);
-                                *argv_i = (((*argv_i) as usize) - mem_base) as *mut u8;
-                            }
-                        }
-                    };
+                    eval.stack.push(WasmValue::I32 { value: 0 });
+                }
+                Err(err) => eval.stack.push(WasmValue::I32 {
+                    value: err.raw() as i32,
+                }),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        "wasi_snapshot_preview1::environ_sizes_get" => {
+            let environ_buf_size_ptr = eval.pop_i32();
+            let environc_ptr = eval.pop_i32();
+
+            if !eval.memory.contains(environc_ptr, 4)
+                || !eval.memory.contains(environ_buf_size_ptr, 4)
+            {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_FAULT.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            match unsafe { wasi::environ_sizes_get() } {
+                Ok((environc, environ_buf_size)) => {
+                    eval.memory
+                        .store_i32(environc_ptr as usize, environc as i32);
+                    eval.memory
+                        .store_i32(environ_buf_size_ptr as usize, environ_buf_size as i32);
+
+                    eval.stack.push(WasmValue::I32 { value: 0 });
+                }
+                Err(err) => eval.stack.push(WasmValue::I32 {
+                    value: err.raw() as i32,
+                }),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        "wasi_snapshot_preview1::environ_get" => {
+            let environ_buf_ptr = eval.pop_i32();
+            let environ_ptr = eval.pop_i32();
+
+            let (environc, environ_buf_size) =
+                unsafe { wasi::environ_sizes_get() }.unwrap_or((0, 0));
+            if !eval.memory.contains(environ_ptr, environc * 4)
+                || !eval.memory.contains(environ_buf_ptr, environ_buf_size)
+            {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_FAULT.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            let environ = &mut eval.memory.bytes[environ_ptr as usize] as *mut u8 as *mut *mut u8;
+            let environ_buf = &mut eval.memory.bytes[environ_buf_ptr as usize] as *mut u8;
+
+            match unsafe { wasi::environ_get(environ, environ_buf) } {
+                Ok(()) => {
+                    eval.memory.relocate_host_ptrs(environ, environc);
+
+                    eval.stack.push(WasmValue::I32 { value: 0 });
+                }
+                Err(err) => eval.stack.push(WasmValue::I32 {
+                    value: err.raw() as i32,
+                }),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        "wasi_snapshot_preview1::clock_res_get" => {
+            let resolution_ptr = eval.pop_i32();
+            let clock_id = eval.pop_i32();
+
+            let Some(clock_id) = get_clock_id(clock_id) else {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_INVAL.raw() as i32,
+                });
+                return Ok(());
+            };
+
+            if !eval.memory.contains(resolution_ptr, 8) {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_FAULT.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            match unsafe { wasi::clock_res_get(clock_id) } {
+                Ok(resolution) => {
+                    eval.memory
+                        .store_i64(resolution_ptr as usize, resolution as i64);
+
+                    eval.stack.push(WasmValue::I32 { value: 0 });
+                }
+                Err(err) => eval.stack.push(WasmValue::I32 {
+                    value: err.raw() as i32,
+                }),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        "wasi_snapshot_preview1::clock_time_get" => {
+            let time_ptr = eval.pop_i32();
+            let precision = eval.pop_i64();
+            let clock_id = eval.pop_i32();
+
+            let Some(clock_id) = get_clock_id(clock_id) else {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_INVAL.raw() as i32,
+                });
+                return Ok(());
+            };
+
+            if !eval.memory.contains(time_ptr, 8) {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_FAULT.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            match unsafe { wasi::clock_time_get(clock_id, precision as u64) } {
+                Ok(time) => {
+                    eval.memory.store_i64(time_ptr as usize, time as i64);
 
                     eval.stack.push(WasmValue::I32 { value: 0 });
                 }
@@ -1051,8 +2533,150 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
                 }),
             }
         }
+        // native builds only pass through <stdin>, <stdout>, <stderr>, args and clocks
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::fd_prestat_get" => {
+            let _buf = eval.pop_i32();
+            let _fd = eval.pop_i32();
+
+            eval.stack.push(WasmValue::I32 {
+                value: wasi::ERRNO_BADF.raw() as i32,
+            });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::fd_write" => {
+            let nwritten_ptr = eval.pop_i32();
+            let iovs_len = eval.pop_i32();
+            let iovs_ptr = eval.pop_i32();
+            let fd = eval.pop_i32() as u32;
+
+            if fd != wasi::FD_STDOUT && fd != wasi::FD_STDERR {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_BADF.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            stderr_flush();
+            let mut nwritten = 0;
+            for i in 0..iovs_len {
+                let iov_base = iovs_ptr as usize + (i as usize * 8);
+                let str_ptr = eval.memory.load_i32(iov_base) as usize;
+                let str_len = eval.memory.load_i32(iov_base + 4) as usize;
+
+                fputs(fd, &eval.memory.bytes[str_ptr..str_ptr + str_len]);
+                nwritten += str_len;
+            }
+
+            eval.memory
+                .store_i32(nwritten_ptr as usize, nwritten as i32);
+            eval.stack.push(WasmValue::I32 { value: 0 });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::fd_read" => {
+            use std::io::Read;
+
+            let nread_ptr = eval.pop_i32();
+            let iovs_len = eval.pop_i32();
+            let iovs_ptr = eval.pop_i32();
+            let fd = eval.pop_i32() as u32;
+
+            if fd != wasi::FD_STDIN {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_BADF.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            let mut nread = 0;
+            for i in 0..iovs_len {
+                let iov_base = iovs_ptr as usize + (i as usize * 8);
+                let buf_ptr = eval.memory.load_i32(iov_base) as usize;
+                let buf_len = eval.memory.load_i32(iov_base + 4) as usize;
+
+                let buf = &mut eval.memory.bytes[buf_ptr..buf_ptr + buf_len];
+                let chunk_len = std::io::stdin().read(buf).unwrap_or(0);
+                nread += chunk_len;
+                if chunk_len < buf_len {
+                    break;
+                }
+            }
+
+            eval.memory.store_i32(nread_ptr as usize, nread as i32);
+            eval.stack.push(WasmValue::I32 { value: 0 });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::args_sizes_get" => {
+            let argv_buf_size_ptr = eval.pop_i32();
+            let argc_ptr = eval.pop_i32();
+
+            let args = std::env::args().collect::<Vec<_>>();
+            let argv_buf_size = args.iter().map(|arg| arg.len() + 1).sum::<usize>();
+            eval.memory.store_i32(argc_ptr as usize, args.len() as i32);
+            eval.memory
+                .store_i32(argv_buf_size_ptr as usize, argv_buf_size as i32);
+
+            eval.stack.push(WasmValue::I32 { value: 0 });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::args_get" => {
+            let argv_buf_ptr = eval.pop_i32();
+            let argv_ptr = eval.pop_i32();
+
+            let mut arg_ptr = argv_buf_ptr as usize;
+            for (i, arg) in std::env::args().enumerate() {
+                eval.memory
+                    .store_i32(argv_ptr as usize + i * 4, arg_ptr as i32);
+                eval.memory.bytes[arg_ptr..arg_ptr + arg.len()].copy_from_slice(arg.as_bytes());
+                eval.memory.bytes[arg_ptr + arg.len()] = 0;
+                arg_ptr += arg.len() + 1;
+            }
+
+            eval.stack.push(WasmValue::I32 { value: 0 });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::environ_sizes_get" => {
+            let environ_buf_size_ptr = eval.pop_i32();
+            let environc_ptr = eval.pop_i32();
+
+            if !eval.memory.contains(environc_ptr, 4)
+                || !eval.memory.contains(environ_buf_size_ptr, 4)
+            {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_FAULT.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            eval.memory.store_i32(environc_ptr as usize, 0);
+            eval.memory.store_i32(environ_buf_size_ptr as usize, 0);
+
+            eval.stack.push(WasmValue::I32 { value: 0 });
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        "wasi_snapshot_preview1::clock_time_get" => {
+            let time_ptr = eval.pop_i32();
+            let _precision = eval.pop_i64();
+            let _clock_id = eval.pop_i32();
+
+            if !eval.memory.contains(time_ptr, 8) {
+                eval.stack.push(WasmValue::I32 {
+                    value: wasi::ERRNO_FAULT.raw() as i32,
+                });
+                return Ok(());
+            }
+
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            eval.memory
+                .store_i64(time_ptr as usize, time.as_nanos() as i64);
+
+            eval.stack.push(WasmValue::I32 { value: 0 });
+        }
         "wasi_snapshot_preview1::proc_exit" => {
             let exit_code = eval.pop_i32();
+            eval.dump_state();
             proc_exit(exit_code as u32);
         }
         _ => {
@@ -1065,6 +2689,17 @@ fn call_host_fn(eval: &mut WasmEval, fn_index: u32) -> Result<(), EvalError> {
     Ok(())
 }
 
+#[cfg(target_arch = "wasm32")]
+fn get_clock_id(clock_id: i32) -> Option<wasi::Clockid> {
+    match clock_id {
+        0 => Some(wasi::CLOCKID_REALTIME),
+        1 => Some(wasi::CLOCKID_MONOTONIC),
+        2 => Some(wasi::CLOCKID_PROCESS_CPUTIME_ID),
+        3 => Some(wasi::CLOCKID_THREAD_CPUTIME_ID),
+        _ => None,
+    }
+}
+
 fn unsafe_borrow<T>(x: &T) -> &'static T {
     unsafe { &*(x as *const T) }
 }
@@ -0,0 +1,214 @@
+use crate::wasm::*;
+use alloc::{format, string::String, vec, vec::Vec};
+
+// rewrites a module so it doesn't depend on the multi-value proposal:
+//   blocks with several results leave them in scratch globals right before their end
+//   and push them back right after it, fn results are expected to be lowered to sret already
+pub fn remove_multivalue(module: &mut WasmModule) -> Result<(), String> {
+    for type_index in module.functions.iter().chain(fn_import_types(module)) {
+        let fn_type = &module.types[*type_index as usize];
+        if fn_type.outputs.len() > 1 {
+            return Err(format!(
+                "Function type #{type_index} has multiple results, \
+                    which are not supported without multi-value"
+            ));
+        }
+    }
+
+    let mut scratch_globals = ScratchGlobals {
+        first_index: module.globals.len() as u32,
+        globals: Vec::new(),
+    };
+    for code in &mut module.codes {
+        let instrs = core::mem::take(&mut code.expr.instrs);
+        code.expr.instrs = lower_blocks(&module.types, &mut scratch_globals, instrs)?;
+    }
+
+    for (value_type, _) in scratch_globals.globals {
+        let zero = match value_type {
+            WasmType::I32 => WasmInstr::I32Const { value: 0 },
+            WasmType::I64 => WasmInstr::I64Const { value: 0 },
+            WasmType::F32 => WasmInstr::F32Const { value: 0.0 },
+            WasmType::F64 => WasmInstr::F64Const { value: 0.0 },
+            WasmType::ExternRef | WasmType::Ref { .. } => WasmInstr::RefNull {
+                ref_type: value_type.clone(),
+            },
+        };
+
+        module.globals.push(WasmGlobal {
+            kind: WasmGlobalKind {
+                value_type,
+                mutable: true,
+            },
+            initial_value: WasmExpr { instrs: vec![zero] },
+        });
+    }
+
+    remove_unused_types(module);
+
+    Ok(())
+}
+
+fn fn_import_types(module: &WasmModule) -> impl Iterator<Item = &u32> {
+    module
+        .imports
+        .iter()
+        .filter_map(|import| match &import.item_desc {
+            WasmImportDesc::Func { type_index } => Some(type_index),
+            _ => None,
+        })
+}
+
+struct ScratchGlobals {
+    first_index: u32,
+    globals: Vec<(WasmType, u32)>, // value type, slot among globals of the same type
+}
+
+impl ScratchGlobals {
+    fn get(&mut self, value_type: &WasmType, slot: u32) -> u32 {
+        let position = self
+            .globals
+            .iter()
+            .position(|(t, s)| t == value_type && *s == slot);
+
+        let position = match position {
+            Some(position) => position,
+            None => {
+                self.globals.push((value_type.clone(), slot));
+                self.globals.len() - 1
+            }
+        };
+
+        self.first_index + position as u32
+    }
+}
+
+fn lower_blocks(
+    types: &[WasmFnType],
+    scratch_globals: &mut ScratchGlobals,
+    instrs: Vec<WasmInstr>,
+) -> Result<Vec<WasmInstr>, String> {
+    let mut out = Vec::with_capacity(instrs.len());
+
+    // results of each open block, empty for blocks that were not rewritten
+    let mut open_blocks = Vec::<Vec<WasmType>>::new();
+
+    for instr in instrs {
+        match instr {
+            WasmInstr::BlockStart {
+                block_kind,
+                block_type: WasmBlockType::InOut { type_index },
+            } => {
+                let block_fn_type = &types[type_index as usize];
+                if !block_fn_type.inputs.is_empty() {
+                    return Err(String::from(
+                        "Blocks with params are not supported without multi-value",
+                    ));
+                }
+
+                if block_fn_type.outputs.len() < 2 {
+                    out.push(WasmInstr::BlockStart {
+                        block_kind,
+                        block_type: WasmBlockType::InOut { type_index },
+                    });
+                    open_blocks.push(Vec::new());
+                    continue;
+                }
+
+                out.push(WasmInstr::BlockStart {
+                    block_kind,
+                    block_type: WasmBlockType::NoOut,
+                });
+                open_blocks.push(block_fn_type.outputs.clone());
+            }
+            WasmInstr::BlockStart { .. } => {
+                out.push(instr);
+                open_blocks.push(Vec::new());
+            }
+            WasmInstr::Else => {
+                let outputs = open_blocks.last().unwrap(); // else is always in a block
+                push_scratch_sets(&mut out, scratch_globals, outputs);
+                out.push(instr);
+            }
+            WasmInstr::BlockEnd => {
+                let Some(outputs) = open_blocks.pop() else {
+                    out.push(instr); // end of the fn body
+                    continue;
+                };
+
+                push_scratch_sets(&mut out, scratch_globals, &outputs);
+                out.push(instr);
+                for (value_type, slot) in outputs.iter().zip(0..) {
+                    out.push(WasmInstr::GlobalGet {
+                        global_index: scratch_globals.get(value_type, slot),
+                    });
+                }
+            }
+            _ => out.push(instr),
+        }
+    }
+
+    Ok(out)
+}
+
+fn push_scratch_sets(
+    out: &mut Vec<WasmInstr>,
+    scratch_globals: &mut ScratchGlobals,
+    outputs: &[WasmType],
+) {
+    // last result is on top of the stack
+    for (slot, value_type) in outputs.iter().enumerate().rev() {
+        out.push(WasmInstr::GlobalSet {
+            global_index: scratch_globals.get(value_type, slot as u32),
+        });
+    }
+}
+
+// multi-value fn types that were only used by blocks would still fail validation
+fn remove_unused_types(module: &mut WasmModule) {
+    let mut used = vec![false; module.types.len()];
+    for type_index in module.functions.iter().chain(fn_import_types(module)) {
+        used[*type_index as usize] = true;
+    }
+    for code in &module.codes {
+        for instr in &code.expr.instrs {
+            if let WasmInstr::BlockStart {
+                block_type: WasmBlockType::InOut { type_index },
+                ..
+            } = instr
+            {
+                used[*type_index as usize] = true;
+            }
+        }
+    }
+
+    let mut new_indices = Vec::with_capacity(module.types.len());
+    let mut new_types = Vec::new();
+    for (fn_type, is_used) in core::mem::take(&mut module.types).into_iter().zip(used) {
+        new_indices.push(new_types.len() as u32);
+        if is_used {
+            new_types.push(fn_type);
+        }
+    }
+    module.types = new_types;
+
+    for type_index in &mut module.functions {
+        *type_index = new_indices[*type_index as usize];
+    }
+    for import in &mut module.imports {
+        if let WasmImportDesc::Func { type_index } = &mut import.item_desc {
+            *type_index = new_indices[*type_index as usize];
+        }
+    }
+    for code in &mut module.codes {
+        for instr in &mut code.expr.instrs {
+            if let WasmInstr::BlockStart {
+                block_type: WasmBlockType::InOut { type_index },
+                ..
+            } = instr
+            {
+                *type_index = new_indices[*type_index as usize];
+            }
+        }
+    }
+}
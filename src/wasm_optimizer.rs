@@ -0,0 +1,313 @@
+use crate::{wasm::*, wasm_eval::*};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+// callees longer than this are never inlined
+const INLINE_MAX_INSTRS: usize = 8;
+
+#[derive(Clone, Copy, Default, PartialEq, PartialOrd)]
+pub enum OptLevel {
+    // direct translation of the source, keeps instrs close to what the debugger shows
+    O0,
+    #[default]
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+type WasmPass = fn(&mut WasmModule);
+
+const O1_PASSES: &[WasmPass] = &[eliminate_dead_code, fold_constants, peephole_optimize];
+const O2_PASSES: &[WasmPass] = &[
+    inline_calls,
+    eliminate_dead_code,
+    fold_constants,
+    peephole_optimize,
+];
+
+pub fn optimize_module(module: &mut WasmModule, level: OptLevel) {
+    let passes = match level {
+        OptLevel::O0 => &[],
+        OptLevel::O1 => O1_PASSES,
+        OptLevel::O2 => O2_PASSES,
+    };
+
+    for pass in passes {
+        pass(module);
+    }
+}
+
+fn for_each_fn(module: &mut WasmModule, pass: fn(Vec<WasmInstr>) -> Vec<WasmInstr>) {
+    for code in &mut module.codes {
+        let instrs = core::mem::take(&mut code.expr.instrs);
+        code.expr.instrs = pass(instrs);
+    }
+}
+
+// removes instrs following `br`, `return` or `unreachable` up to the end of the block
+fn eliminate_dead_code(module: &mut WasmModule) {
+    for_each_fn(module, |instrs| {
+        let mut out = Vec::with_capacity(instrs.len());
+        let mut dead_blocks_depth = None;
+
+        for instr in instrs {
+            if let Some(depth) = &mut dead_blocks_depth {
+                match instr {
+                    WasmInstr::BlockStart { .. } => {
+                        *depth += 1;
+                        continue;
+                    }
+                    WasmInstr::Else | WasmInstr::BlockEnd if *depth == 0 => {
+                        dead_blocks_depth = None;
+                    }
+                    WasmInstr::BlockEnd => {
+                        *depth -= 1;
+                        continue;
+                    }
+                    _ => continue,
+                }
+            }
+
+            let terminates = matches!(
+                instr,
+                WasmInstr::Branch { .. }
+                    | WasmInstr::Return
+                    | WasmInstr::ReturnCall { .. }
+                    | WasmInstr::Unreachable
+            );
+            out.push(instr);
+            if terminates {
+                dead_blocks_depth = Some(0u32);
+            }
+        }
+
+        out
+    });
+}
+
+// evaluates integer binary ops with constant operands,
+//   ops that would trap (like division by zero) are left for the runtime
+fn fold_constants(module: &mut WasmModule) {
+    for_each_fn(module, |instrs| {
+        let mut out: Vec<WasmInstr> = Vec::with_capacity(instrs.len());
+
+        for instr in instrs {
+            if let (WasmInstr::BinaryOp { kind }, [.., lhs, rhs]) = (&instr, out.as_slice()) {
+                if let (Some(lhs), Some(rhs)) = (get_int_const(lhs), get_int_const(rhs)) {
+                    if let Ok(value) = eval_binary_op(kind, lhs, rhs) {
+                        out.truncate(out.len() - 2);
+                        out.push(match value {
+                            WasmValue::I32 { value } => WasmInstr::I32Const { value },
+                            WasmValue::I64 { value } => WasmInstr::I64Const { value },
+                            _ => unreachable!(),
+                        });
+                        continue;
+                    }
+                }
+            }
+
+            out.push(instr);
+        }
+
+        out
+    });
+}
+
+fn get_int_const(instr: &WasmInstr) -> Option<WasmValue> {
+    match instr {
+        WasmInstr::I32Const { value } => Some(WasmValue::I32 { value: *value }),
+        WasmInstr::I64Const { value } => Some(WasmValue::I64 { value: *value }),
+        _ => None,
+    }
+}
+
+// replaces calls to small straight-line fns with their bodies,
+//   callee params become locals of the caller which are set from the call args
+fn inline_calls(module: &mut WasmModule) {
+    let fn_imports_len = module
+        .imports
+        .iter()
+        .filter(|import| matches!(import.item_desc, WasmImportDesc::Func { .. }))
+        .count() as u32;
+
+    let mut inlinable_bodies = BTreeMap::<u32, Vec<WasmInstr>>::new();
+    for (code_index, code) in module.codes.iter().enumerate() {
+        if let Some(body) = get_inlinable_body(code) {
+            inlinable_bodies.insert(fn_imports_len + code_index as u32, body);
+        }
+    }
+
+    for (code_index, code) in module.codes.iter_mut().enumerate() {
+        let fn_type = &module.types[module.functions[code_index] as usize];
+        let mut locals_len = fn_type.inputs.len() as u32;
+        for locals in &code.locals {
+            locals_len += locals.count;
+        }
+
+        // every call site of the same callee reuses its params locals
+        let mut params_locals = BTreeMap::<u32, u32>::new();
+
+        let instrs = core::mem::take(&mut code.expr.instrs);
+        for instr in instrs {
+            let WasmInstr::Call { fn_index } = instr else {
+                code.expr.instrs.push(instr);
+                continue;
+            };
+            let Some(body) = inlinable_bodies.get(&fn_index) else {
+                code.expr.instrs.push(instr);
+                continue;
+            };
+
+            let callee_type_index = module.functions[(fn_index - fn_imports_len) as usize];
+            let callee_inputs = &module.types[callee_type_index as usize].inputs;
+
+            let first_local = *params_locals.entry(fn_index).or_insert_with(|| {
+                for input in callee_inputs {
+                    code.locals.push(WasmLocals {
+                        count: 1,
+                        value_type: input.clone(),
+                    });
+                }
+                locals_len += callee_inputs.len() as u32;
+                locals_len - callee_inputs.len() as u32
+            });
+
+            for param_index in (0..callee_inputs.len() as u32).rev() {
+                code.expr.instrs.push(WasmInstr::LocalSet {
+                    local_index: first_local + param_index,
+                });
+            }
+
+            for body_instr in body {
+                code.expr.instrs.push(match body_instr {
+                    WasmInstr::LocalGet { local_index } => WasmInstr::LocalGet {
+                        local_index: first_local + local_index,
+                    },
+                    WasmInstr::LocalSet { local_index } => WasmInstr::LocalSet {
+                        local_index: first_local + local_index,
+                    },
+                    WasmInstr::LocalTee { local_index } => WasmInstr::LocalTee {
+                        local_index: first_local + local_index,
+                    },
+                    other => other.clone(),
+                });
+            }
+        }
+    }
+}
+
+// fns without own locals, blocks or calls can be pasted in place of the call,
+//   a trailing `return` is dropped since the values are already on the stack,
+//   `SourceLoc` markers are dropped too so the code maps to the call statement
+fn get_inlinable_body(code: &WasmFn) -> Option<Vec<WasmInstr>> {
+    if !code.locals.is_empty() {
+        return None;
+    }
+
+    let instrs = code
+        .expr
+        .instrs
+        .iter()
+        .filter(|instr| !matches!(instr, WasmInstr::SourceLoc { .. }))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut body = instrs.as_slice();
+    if let [rest @ .., WasmInstr::Return] = body {
+        body = rest;
+    }
+
+    if body.len() > INLINE_MAX_INSTRS {
+        return None;
+    }
+
+    for instr in body {
+        match instr {
+            WasmInstr::BlockStart { .. }
+            | WasmInstr::Else
+            | WasmInstr::BlockEnd
+            | WasmInstr::Branch { .. }
+            | WasmInstr::Return
+            | WasmInstr::Call { .. }
+            | WasmInstr::ReturnCall { .. } => return None,
+            _ => {}
+        }
+    }
+
+    Some(body.to_vec())
+}
+
+// cleans up patterns produced by naive lowering of `LoInstr`s,
+//   each instr is pushed to the output and then the tail is reduced
+//   until no pattern matches, so rewrites can enable further ones
+fn peephole_optimize(module: &mut WasmModule) {
+    for_each_fn(module, |instrs| {
+        let mut out = Vec::with_capacity(instrs.len());
+
+        for instr in instrs {
+            out.push(instr);
+            while reduce_tail(&mut out) {}
+        }
+
+        out
+    });
+}
+
+fn reduce_tail(out: &mut Vec<WasmInstr>) -> bool {
+    let [.., prev, last] = out.as_slice() else {
+        return false;
+    };
+
+    let replacement = match (prev, last) {
+        // value is computed with no side effects and then thrown away
+        (
+            WasmInstr::LocalGet { .. }
+            | WasmInstr::GlobalGet { .. }
+            | WasmInstr::I32Const { .. }
+            | WasmInstr::I64Const { .. }
+            | WasmInstr::F32Const { .. }
+            | WasmInstr::F64Const { .. },
+            WasmInstr::Drop,
+        ) => None,
+        (WasmInstr::LocalSet { local_index: a }, WasmInstr::LocalGet { local_index: b })
+            if a == b =>
+        {
+            Some(WasmInstr::LocalTee { local_index: *a })
+        }
+        (WasmInstr::LocalTee { local_index }, WasmInstr::Drop) => Some(WasmInstr::LocalSet {
+            local_index: *local_index,
+        }),
+        (WasmInstr::LocalGet { local_index: a }, WasmInstr::LocalSet { local_index: b })
+            if a == b =>
+        {
+            None
+        }
+        (WasmInstr::I64ExtendI32s | WasmInstr::I64ExtendI32u, WasmInstr::I32WrapI64) => None,
+        (WasmInstr::I32Const { value }, WasmInstr::I64ExtendI32s) => Some(WasmInstr::I64Const {
+            value: *value as i64,
+        }),
+        (WasmInstr::I32Const { value }, WasmInstr::I64ExtendI32u) => Some(WasmInstr::I64Const {
+            value: *value as u32 as i64,
+        }),
+        (WasmInstr::I64Const { value }, WasmInstr::I32WrapI64) => Some(WasmInstr::I32Const {
+            value: *value as i32,
+        }),
+        _ => return false,
+    };
+
+    out.truncate(out.len() - 2);
+    if let Some(replacement) = replacement {
+        out.push(replacement);
+    }
+
+    true
+}
@@ -0,0 +1,121 @@
+use crate::wasm::*;
+use alloc::{collections::BTreeSet, format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+// sizes are of encoded fn bodies, an export (or the start fn, or a table) retains
+//   every own fn reachable from it through direct calls, `call_indirect` is not followed
+pub fn generate_report(wasm_module: &WasmModule, string_bytes_saved: u32) -> String {
+    let mut out = String::new();
+
+    let mut fn_names = Vec::new();
+    for import in &wasm_module.imports {
+        if let WasmImportDesc::Func { .. } = import.item_desc {
+            fn_names.push(format!("{}::{}", import.module_name, import.item_name));
+        }
+    }
+    let imported_fns_count = fn_names.len() as u32;
+    for i in 0..wasm_module.codes.len() as u32 {
+        fn_names.push(format!("#{}", imported_fns_count + i));
+    }
+    for debug_info in &wasm_module.debug_fn_info {
+        fn_names[debug_info.fn_index as usize] = debug_info.fn_name.clone();
+    }
+
+    let fn_body_sizes = wasm_module.fn_body_sizes();
+    let callees: Vec<BTreeSet<u32>> = wasm_module
+        .codes
+        .iter()
+        .map(|fn_code| {
+            let mut callees = BTreeSet::new();
+            for instr in &fn_code.expr.instrs {
+                if let WasmInstr::Call { fn_index } | WasmInstr::ReturnCall { fn_index } = instr {
+                    callees.insert(*fn_index);
+                }
+            }
+            callees
+        })
+        .collect();
+
+    let mut fns_by_size: Vec<_> = (0..fn_body_sizes.len()).collect();
+    fns_by_size.sort_by_key(|i| core::cmp::Reverse(fn_body_sizes[*i]));
+    writeln!(
+        &mut out,
+        "fn bodies: {} bytes in {} fns",
+        fn_body_sizes.iter().sum::<u32>(),
+        fn_body_sizes.len()
+    )
+    .unwrap();
+    for i in fns_by_size {
+        let fn_name = &fn_names[imported_fns_count as usize + i];
+        writeln!(&mut out, "  {:>8}  {fn_name}", fn_body_sizes[i]).unwrap();
+    }
+
+    let data_bytes = wasm_module.datas.iter().fold(0, |size, data| {
+        let WasmData::Active { bytes, .. } = data;
+        size + bytes.len()
+    });
+    writeln!(
+        &mut out,
+        "data section: {data_bytes} bytes in {} segments, \
+            {string_bytes_saved} bytes saved by string pooling",
+        wasm_module.datas.len(),
+    )
+    .unwrap();
+
+    out.push_str("call graph:\n");
+    for (fn_callees, i) in callees.iter().zip(imported_fns_count..) {
+        write!(&mut out, "  {}", fn_names[i as usize]).unwrap();
+        for (callee, j) in fn_callees.iter().zip(0..) {
+            let separator = if j == 0 { " -> " } else { ", " };
+            write!(&mut out, "{separator}{}", fn_names[*callee as usize]).unwrap();
+        }
+        out.push('\n');
+    }
+
+    let mut roots = Vec::new();
+    for export in &wasm_module.exports {
+        if export.export_type == WasmExportType::Func {
+            roots.push((
+                format!("export {}", export.export_name),
+                vec![export.exported_item_index],
+            ));
+        }
+    }
+    if let Some(start) = wasm_module.start {
+        roots.push((String::from("start"), vec![start]));
+    }
+    for element in &wasm_module.elements {
+        let WasmElement::Active {
+            table_index,
+            fn_indices,
+            ..
+        } = element;
+        roots.push((format!("table {table_index}"), fn_indices.clone()));
+    }
+
+    out.push_str("retained by:\n");
+    for (root_name, root_fns) in roots {
+        let mut retained = BTreeSet::new();
+        let mut queue = root_fns;
+        while let Some(fn_index) = queue.pop() {
+            if fn_index < imported_fns_count || !retained.insert(fn_index) {
+                continue;
+            }
+            let own_index = (fn_index - imported_fns_count) as usize;
+            queue.extend(&callees[own_index]);
+        }
+
+        let retained_bytes: u32 = retained
+            .iter()
+            .map(|fn_index| fn_body_sizes[(fn_index - imported_fns_count) as usize])
+            .sum();
+        writeln!(
+            &mut out,
+            "  {root_name}: {retained_bytes} bytes in {} fns",
+            retained.len()
+        )
+        .unwrap();
+    }
+
+    out
+}
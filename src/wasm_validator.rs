@@ -0,0 +1,957 @@
+use crate::wasm::*;
+use alloc::{format, string::String, vec, vec::Vec};
+
+pub const MAX_PAGES: u32 = 65_536;
+
+#[derive(Debug)]
+pub struct ValidationError {
+    pub message: String,
+}
+
+// checks that the module would be accepted by a wasm engine,
+//   so codegen bugs are reported before the module is dumped or evaluated
+pub struct WasmValidator<'a> {
+    module: &'a WasmModule,
+    features: WasmFeatures,
+    fn_imports_len: usize,
+    tables_len: usize,
+    memories_len: usize,
+
+    // state of the fn being validated
+    locals: Vec<WasmType>,
+    stack: Vec<Option<WasmType>>, // `None` is a value of unknown type in unreachable code
+    frames: Vec<ControlFrame>,
+}
+
+struct ControlFrame {
+    kind: Option<WasmBlockKind>, // `None` is the fn body
+    inputs: Vec<WasmType>,
+    outputs: Vec<WasmType>,
+    stack_height: usize,
+    unreachable: bool,
+}
+
+impl ControlFrame {
+    // branching to a loop jumps to its start
+    fn label_types(&self) -> &Vec<WasmType> {
+        match self.kind {
+            Some(WasmBlockKind::Loop) => &self.inputs,
+            _ => &self.outputs,
+        }
+    }
+}
+
+impl<'a> WasmValidator<'a> {
+    pub fn validate(module: &'a WasmModule, features: WasmFeatures) -> Result<(), ValidationError> {
+        let mut validator = WasmValidator {
+            module,
+            features,
+            fn_imports_len: 0,
+            tables_len: 0,
+            memories_len: 0,
+            locals: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+        };
+
+        validator.validate_module()
+    }
+
+    fn validate_module(&mut self) -> Result<(), ValidationError> {
+        let module = self.module;
+
+        if !module.gc_types.is_empty() {
+            self.features
+                .require(WasmFeature::Gc, "GC types")
+                .map_err(|message| error("module", message))?;
+        }
+        for (gc_type, i) in module.gc_types.iter().zip(0..) {
+            let fields = match gc_type {
+                WasmGcType::Struct { fields } => fields.as_slice(),
+                WasmGcType::Array { item } => core::slice::from_ref(item),
+            };
+            for field in fields {
+                if let WasmStorageType::Value(value_type) = &field.storage_type {
+                    self.validate_value_type(value_type)
+                        .map_err(|message| error(&format!("gc type #{i}"), message))?;
+                }
+            }
+        }
+
+        for (fn_type, i) in module.types.iter().zip(0..) {
+            for value_type in fn_type.inputs.iter().chain(&fn_type.outputs) {
+                self.validate_value_type(value_type)
+                    .map_err(|message| error(&format!("type #{i}"), message))?;
+            }
+        }
+
+        for import in &module.imports {
+            let import_name = format!("import {}::{}", import.module_name, import.item_name);
+
+            match &import.item_desc {
+                WasmImportDesc::Func { type_index } => {
+                    self.get_fn_type(*type_index)
+                        .and_then(|fn_type| self.check_results_len(fn_type))
+                        .map_err(|message| error(&import_name, message))?;
+                    self.fn_imports_len += 1;
+                }
+                WasmImportDesc::Table(limits) => {
+                    validate_table_limits(limits)
+                        .map_err(|message| error(&import_name, message))?;
+                    self.tables_len += 1;
+                }
+                WasmImportDesc::Memory(limits) => {
+                    self.validate_limits(limits)
+                        .map_err(|message| error(&import_name, message))?;
+                    self.memories_len += 1;
+                }
+            }
+        }
+
+        for (limits, i) in module.tables.iter().zip(0..) {
+            validate_table_limits(limits)
+                .map_err(|message| error(&format!("table #{i}"), message))?;
+            self.tables_len += 1;
+        }
+        if self.tables_len > 1 {
+            self.features
+                .require(WasmFeature::ReferenceTypes, "Multiple tables")
+                .map_err(|message| error("module", message))?;
+        }
+
+        for limits in &module.memories {
+            self.validate_limits(limits)
+                .map_err(|message| error("memory", message))?;
+            self.memories_len += 1;
+        }
+        if self.memories_len > 1 {
+            return Err(error("module", "Multiple memories are not supported"));
+        }
+
+        for (global, i) in module.globals.iter().zip(0..) {
+            self.validate_value_type(&global.kind.value_type)
+                .and_then(|_| {
+                    self.validate_const_expr(&global.initial_value, &global.kind.value_type)
+                })
+                .map_err(|message| error(&format!("global #{i}"), message))?;
+        }
+
+        for (export, i) in module.exports.iter().zip(0..) {
+            let export_name = format!("export `{}`", export.export_name);
+
+            let exported_items_len = match export.export_type {
+                WasmExportType::Func => self.fn_imports_len + module.functions.len(),
+                WasmExportType::Table => self.tables_len,
+                WasmExportType::Mem => self.memories_len,
+                WasmExportType::Global => module.globals.len(),
+            };
+            if export.exported_item_index as usize >= exported_items_len {
+                return Err(error(
+                    &export_name,
+                    format!("Unknown item #{}", export.exported_item_index),
+                ));
+            }
+
+            for other_export in &module.exports[..i] {
+                if other_export.export_name == export.export_name {
+                    return Err(error(&export_name, "Duplicate export name"));
+                }
+            }
+        }
+
+        if let Some(fn_index) = module.start {
+            let fn_type = self
+                .get_fn_type_by_fn_index(fn_index)
+                .map_err(|message| error("start", message))?;
+            if !fn_type.inputs.is_empty() || !fn_type.outputs.is_empty() {
+                return Err(error("start", "Start function must have type [] -> []"));
+            }
+        }
+
+        for (element, i) in module.elements.iter().zip(0..) {
+            let element_name = format!("element #{i}");
+
+            let WasmElement::Active {
+                table_index,
+                offset,
+                fn_indices,
+            } = element;
+            if *table_index as usize >= self.tables_len {
+                return Err(error(
+                    &element_name,
+                    format!("Unknown table #{table_index}"),
+                ));
+            }
+            self.validate_const_expr(offset, &WasmType::I32)
+                .map_err(|message| error(&element_name, message))?;
+            for fn_index in fn_indices {
+                if *fn_index as usize >= self.fn_imports_len + module.functions.len() {
+                    return Err(error(
+                        &element_name,
+                        format!("Unknown function #{fn_index}"),
+                    ));
+                }
+            }
+        }
+
+        for (data, i) in module.datas.iter().zip(0..) {
+            let data_name = format!("data #{i}");
+
+            if self.memories_len == 0 {
+                return Err(error(&data_name, "Module has no memory"));
+            }
+
+            match data {
+                WasmData::Active { offset, bytes: _ } => {
+                    self.validate_const_expr(offset, &WasmType::I32)
+                        .map_err(|message| error(&data_name, message))?;
+                }
+            }
+        }
+
+        if module.functions.len() != module.codes.len() {
+            return Err(error(
+                "module",
+                format!(
+                    "Function count ({}) doesn't match code count ({})",
+                    module.functions.len(),
+                    module.codes.len()
+                ),
+            ));
+        }
+
+        for (fn_info, i) in module.debug_fn_info.iter().zip(0..) {
+            if fn_info.fn_index as usize >= self.fn_imports_len + module.functions.len() {
+                return Err(error(
+                    &format!("debug fn info #{i}"),
+                    format!("Unknown function #{}", fn_info.fn_index),
+                ));
+            }
+        }
+
+        for ((type_index, code), i) in module.functions.iter().zip(&module.codes).zip(0..) {
+            let fn_index = (self.fn_imports_len + i) as u32;
+            self.validate_fn(fn_index, *type_index, code)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_const_expr(&self, expr: &WasmExpr, expected: &WasmType) -> Result<(), String> {
+        let actual = match &expr.instrs[..] {
+            [WasmInstr::I32Const { .. }] => WasmType::I32,
+            [WasmInstr::I64Const { .. }] => WasmType::I64,
+            [WasmInstr::F32Const { .. }] => WasmType::F32,
+            [WasmInstr::F64Const { .. }] => WasmType::F64,
+            [WasmInstr::RefNull { ref_type }] => ref_type.clone(),
+            _ => {
+                return Err(format!(
+                    "Expected a single constant, got: {:?}",
+                    expr.instrs
+                ))
+            }
+        };
+
+        if actual != *expected {
+            return Err(format!("Expected {expected:?} constant, got {actual:?}"));
+        }
+
+        Ok(())
+    }
+
+    fn validate_fn(
+        &mut self,
+        fn_index: u32,
+        type_index: u32,
+        code: &WasmFn,
+    ) -> Result<(), ValidationError> {
+        let fn_name = match self.get_fn_name(fn_index) {
+            Some(fn_name) => format!("fn {fn_name}"),
+            None => format!("fn #{fn_index}"),
+        };
+
+        let fn_type = self
+            .get_fn_type(type_index)
+            .map_err(|message| error(&fn_name, message))?;
+        self.check_results_len(fn_type)
+            .map_err(|message| error(&fn_name, message))?;
+
+        self.locals = fn_type.inputs.clone();
+        for local in &code.locals {
+            self.validate_value_type(&local.value_type)
+                .map_err(|message| error(&fn_name, message))?;
+            for _ in 0..local.count {
+                self.locals.push(local.value_type.clone());
+            }
+        }
+
+        self.stack = Vec::new();
+        self.frames = vec![ControlFrame {
+            kind: None,
+            inputs: Vec::new(),
+            outputs: fn_type.outputs.clone(),
+            stack_height: 0,
+            unreachable: false,
+        }];
+
+        for (instr, i) in code.expr.instrs.iter().zip(0..) {
+            self.validate_instr(instr)
+                .map_err(|message| error(&format!("{fn_name}, instr #{i} {instr:?}"), message))?;
+        }
+
+        // implicit `end` of the fn body
+        self.end_frame()
+            .map_err(|message| error(&format!("{fn_name}, end"), message))?;
+        if !self.frames.is_empty() {
+            return Err(error(&fn_name, "Unclosed blocks at fn body end"));
+        }
+
+        Ok(())
+    }
+
+    fn validate_instr(&mut self, instr: &WasmInstr) -> Result<(), String> {
+        match instr {
+            WasmInstr::Unreachable => self.mark_unreachable(),
+            WasmInstr::Drop => {
+                self.pop_any()?;
+            }
+            WasmInstr::BinaryOp { kind } => {
+                let (operand_type, result_type) = get_binary_op_types(kind);
+                self.pop_expected(&operand_type)?;
+                self.pop_expected(&operand_type)?;
+                self.push(result_type);
+            }
+            WasmInstr::UnaryOp { kind } => {
+                let (operand_type, result_type) = get_unary_op_types(kind);
+                self.pop_expected(&operand_type)?;
+                self.push(result_type);
+            }
+            WasmInstr::MemorySize => {
+                self.expect_memory()?;
+                self.push(WasmType::I32);
+            }
+            WasmInstr::MemoryGrow => {
+                self.expect_memory()?;
+                self.pop_expected(&WasmType::I32)?;
+                self.push(WasmType::I32);
+            }
+            WasmInstr::MemoryCopy => {
+                self.features
+                    .require(WasmFeature::BulkMemory, "memory.copy")?;
+                self.expect_memory()?;
+                self.pop_expected(&WasmType::I32)?;
+                self.pop_expected(&WasmType::I32)?;
+                self.pop_expected(&WasmType::I32)?;
+            }
+            WasmInstr::AtomicLoad { kind, offset: _ } => {
+                let (value_type, _) = get_load_kind_info(kind);
+                self.expect_atomic_access(&WasmStoreKind::from_load_kind(kind))?;
+                if matches!(
+                    kind,
+                    WasmLoadKind::I32I8
+                        | WasmLoadKind::I32I16
+                        | WasmLoadKind::I64I8
+                        | WasmLoadKind::I64I16
+                        | WasmLoadKind::I64I32
+                ) {
+                    return Err(format!("Atomic load of {kind:?} is not supported"));
+                }
+                self.pop_expected(&WasmType::I32)?;
+                self.push(value_type);
+            }
+            WasmInstr::AtomicStore { kind, offset: _ } => {
+                self.expect_atomic_access(kind)?;
+                let (value_type, _) = get_store_kind_info(kind);
+                self.pop_expected(&value_type)?;
+                self.pop_expected(&WasmType::I32)?;
+            }
+            WasmInstr::AtomicRmw { kind, .. } => {
+                self.expect_atomic_access(kind)?;
+                let (value_type, _) = get_store_kind_info(kind);
+                self.pop_expected(&value_type)?;
+                self.pop_expected(&WasmType::I32)?;
+                self.push(value_type);
+            }
+            WasmInstr::AtomicCmpxchg { kind, offset: _ } => {
+                self.expect_atomic_access(kind)?;
+                let (value_type, _) = get_store_kind_info(kind);
+                self.pop_expected(&value_type)?;
+                self.pop_expected(&value_type)?;
+                self.pop_expected(&WasmType::I32)?;
+                self.push(value_type);
+            }
+            WasmInstr::MemoryAtomicWait {
+                value_type,
+                offset: _,
+            } => {
+                self.expect_atomic_access(&WasmStoreKind::I32)?;
+                if *value_type != WasmType::I32 && *value_type != WasmType::I64 {
+                    return Err(format!("Cannot wait on {value_type:?} value"));
+                }
+                self.pop_expected(&WasmType::I64)?; // timeout
+                self.pop_expected(value_type)?;
+                self.pop_expected(&WasmType::I32)?;
+                self.push(WasmType::I32);
+            }
+            WasmInstr::MemoryAtomicNotify { offset: _ } => {
+                self.expect_atomic_access(&WasmStoreKind::I32)?;
+                self.pop_expected(&WasmType::I32)?; // waiter count
+                self.pop_expected(&WasmType::I32)?;
+                self.push(WasmType::I32);
+            }
+            WasmInstr::AtomicFence => {
+                self.features
+                    .require(WasmFeature::Threads, "atomic.fence")?;
+            }
+            WasmInstr::I32Const { .. } => self.push(WasmType::I32),
+            WasmInstr::I64Const { .. } => self.push(WasmType::I64),
+            WasmInstr::F32Const { .. } => self.push(WasmType::F32),
+            WasmInstr::F64Const { .. } => self.push(WasmType::F64),
+            WasmInstr::I64ExtendI32u | WasmInstr::I64ExtendI32s => {
+                self.pop_expected(&WasmType::I32)?;
+                self.push(WasmType::I64);
+            }
+            WasmInstr::I32WrapI64 => {
+                self.pop_expected(&WasmType::I64)?;
+                self.push(WasmType::I32);
+            }
+            WasmInstr::LocalGet { local_index } => {
+                let local_type = self.get_local_type(*local_index)?;
+                self.push(local_type);
+            }
+            WasmInstr::LocalSet { local_index } => {
+                let local_type = self.get_local_type(*local_index)?;
+                self.pop_expected(&local_type)?;
+            }
+            WasmInstr::SourceLoc { .. } => {}
+            WasmInstr::LocalTee { local_index } => {
+                let local_type = self.get_local_type(*local_index)?;
+                self.pop_expected(&local_type)?;
+                self.push(local_type);
+            }
+            WasmInstr::GlobalGet { global_index } => {
+                let global = self.get_global(*global_index)?;
+                self.push(global.kind.value_type.clone());
+            }
+            WasmInstr::GlobalSet { global_index } => {
+                let global = self.get_global(*global_index)?;
+                if !global.kind.mutable {
+                    return Err(format!("Global #{global_index} is immutable"));
+                }
+                self.pop_expected(&global.kind.value_type)?;
+            }
+            WasmInstr::Load {
+                kind,
+                align,
+                offset: _,
+            } => {
+                self.expect_memory()?;
+                let (value_type, natural_size) = get_load_kind_info(kind);
+                validate_align(*align, natural_size)?;
+                self.pop_expected(&WasmType::I32)?;
+                self.push(value_type);
+            }
+            WasmInstr::Store {
+                kind,
+                align,
+                offset: _,
+            } => {
+                self.expect_memory()?;
+                let (value_type, natural_size) = get_store_kind_info(kind);
+                validate_align(*align, natural_size)?;
+                self.pop_expected(&value_type)?;
+                self.pop_expected(&WasmType::I32)?;
+            }
+            WasmInstr::Return => {
+                let outputs = self.frames[0].outputs.clone();
+                self.pop_all_expected(&outputs)?;
+                self.mark_unreachable();
+            }
+            WasmInstr::BlockStart {
+                block_kind,
+                block_type,
+            } => {
+                let (inputs, outputs) = match block_type {
+                    WasmBlockType::NoOut => (Vec::new(), Vec::new()),
+                    WasmBlockType::SingleOut { wasm_type } => (Vec::new(), vec![wasm_type.clone()]),
+                    WasmBlockType::InOut { type_index } => {
+                        let fn_type = self.get_fn_type(*type_index)?;
+                        if !fn_type.inputs.is_empty() {
+                            self.features
+                                .require(WasmFeature::MultiValue, "Block with params")?;
+                        }
+                        self.check_results_len(fn_type)?;
+                        (fn_type.inputs.clone(), fn_type.outputs.clone())
+                    }
+                };
+
+                if let WasmBlockKind::If = block_kind {
+                    self.pop_expected(&WasmType::I32)?;
+                }
+                self.pop_all_expected(&inputs)?;
+
+                self.frames.push(ControlFrame {
+                    kind: Some(block_kind.clone()),
+                    stack_height: self.stack.len(),
+                    inputs: inputs.clone(),
+                    outputs,
+                    unreachable: false,
+                });
+                for input in inputs {
+                    self.push(input);
+                }
+            }
+            WasmInstr::Else => {
+                let frame = self.frames.last().unwrap();
+                if frame.kind != Some(WasmBlockKind::If) {
+                    return Err(String::from("`else` outside of `if` block"));
+                }
+
+                let outputs = frame.outputs.clone();
+                self.pop_all_expected(&outputs)?;
+                self.expect_frame_stack_empty()?;
+
+                let frame = self.frames.last_mut().unwrap();
+                // `else` branch is treated as a block with no `else` of its own
+                frame.kind = Some(WasmBlockKind::Block);
+                frame.unreachable = false;
+                for input in frame.inputs.clone() {
+                    self.push(input);
+                }
+            }
+            WasmInstr::BlockEnd => {
+                if self.frames.len() == 1 {
+                    return Err(String::from("`end` without a matching block"));
+                }
+
+                let frame = self.frames.last().unwrap();
+                if frame.kind == Some(WasmBlockKind::If) && frame.inputs != frame.outputs {
+                    return Err(String::from(
+                        "`if` without `else` must have matching inputs and outputs",
+                    ));
+                }
+
+                self.end_frame()?;
+            }
+            WasmInstr::Branch { label_index } => {
+                let label_index = *label_index as usize;
+                if label_index >= self.frames.len() {
+                    return Err(format!("Unknown label {label_index}"));
+                }
+
+                let frame = &self.frames[self.frames.len() - 1 - label_index];
+                let label_types = frame.label_types().clone();
+                self.pop_all_expected(&label_types)?;
+                self.mark_unreachable();
+            }
+            WasmInstr::Call { fn_index } => {
+                let fn_type = self.get_fn_type_by_fn_index(*fn_index)?;
+                self.pop_all_expected(&fn_type.inputs)?;
+                for output in &fn_type.outputs {
+                    self.push(output.clone());
+                }
+            }
+            WasmInstr::ReturnCall { fn_index } => {
+                self.features
+                    .require(WasmFeature::TailCall, "return_call")?;
+                let fn_type = self.get_fn_type_by_fn_index(*fn_index)?;
+                if fn_type.outputs != self.frames[0].outputs {
+                    return Err(format!(
+                        "Callee results {:?} don't match fn results {:?}",
+                        fn_type.outputs, self.frames[0].outputs
+                    ));
+                }
+                self.pop_all_expected(&fn_type.inputs)?;
+                self.mark_unreachable();
+            }
+            WasmInstr::RefNull { ref_type } => {
+                if !is_ref_type(ref_type) {
+                    return Err(format!("{ref_type:?} is not a reference type"));
+                }
+                self.validate_value_type(ref_type)?;
+                self.push(ref_type.clone());
+            }
+            WasmInstr::RefIsNull => {
+                self.features
+                    .require(WasmFeature::ReferenceTypes, "ref.is_null")?;
+                if let Some(actual) = self.pop_any()? {
+                    if !is_ref_type(&actual) {
+                        return Err(format!("Expected reference on the stack, got {actual:?}"));
+                    }
+                }
+                self.push(WasmType::I32);
+            }
+            WasmInstr::StructNew { type_index } => {
+                let fields = self.get_struct_fields(*type_index)?;
+                for field in fields.iter().rev() {
+                    self.pop_expected(&field.storage_type.unpacked())?;
+                }
+                self.push(WasmType::Ref {
+                    type_index: *type_index,
+                });
+            }
+            WasmInstr::StructGet {
+                type_index,
+                field_index,
+            } => {
+                let fields = self.get_struct_fields(*type_index)?;
+                let Some(field) = fields.get(*field_index as usize) else {
+                    return Err(format!("Unknown field #{field_index}"));
+                };
+                let WasmStorageType::Value(value_type) = &field.storage_type else {
+                    return Err(format!("Field #{field_index} is packed"));
+                };
+                self.pop_expected(&WasmType::Ref {
+                    type_index: *type_index,
+                })?;
+                self.push(value_type.clone());
+            }
+            WasmInstr::ArrayNewFixed { type_index, len } => {
+                let WasmGcType::Array { item } = self.get_gc_type(*type_index)? else {
+                    return Err(format!("Type #{type_index} is not an array"));
+                };
+                for _ in 0..*len {
+                    self.pop_expected(&item.storage_type.unpacked())?;
+                }
+                self.push(WasmType::Ref {
+                    type_index: *type_index,
+                });
+            }
+            WasmInstr::ArrayLen => {
+                self.features.require(WasmFeature::Gc, "array.len")?;
+                match self.pop_any()? {
+                    None => {}
+                    Some(WasmType::Ref { type_index })
+                        if matches!(self.get_gc_type(type_index)?, WasmGcType::Array { .. }) => {}
+                    Some(actual) => {
+                        return Err(format!("Expected array reference, got {actual:?}"));
+                    }
+                }
+                self.push(WasmType::I32);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> Result<(), String> {
+        let outputs = self.frames.last().unwrap().outputs.clone();
+        self.pop_all_expected(&outputs)?;
+        self.expect_frame_stack_empty()?;
+
+        self.frames.pop();
+        for output in outputs {
+            self.push(output);
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, value_type: WasmType) {
+        self.stack.push(Some(value_type));
+    }
+
+    fn pop_any(&mut self) -> Result<Option<WasmType>, String> {
+        let frame = self.frames.last().unwrap();
+
+        if self.stack.len() == frame.stack_height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+
+            return Err(String::from("Stack underflow"));
+        }
+
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn pop_expected(&mut self, expected: &WasmType) -> Result<(), String> {
+        match self.pop_any()? {
+            Some(actual) if actual != *expected => Err(format!(
+                "Expected {expected:?} on the stack, got {actual:?}"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn pop_all_expected(&mut self, expected: &[WasmType]) -> Result<(), String> {
+        for expected_type in expected.iter().rev() {
+            self.pop_expected(expected_type)?;
+        }
+
+        Ok(())
+    }
+
+    fn expect_frame_stack_empty(&self) -> Result<(), String> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() != frame.stack_height {
+            let extra_values = &self.stack[frame.stack_height..];
+            return Err(format!("Extra values left on the stack: {extra_values:?}"));
+        }
+
+        Ok(())
+    }
+
+    fn mark_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.stack_height);
+        frame.unreachable = true;
+    }
+
+    fn expect_memory(&self) -> Result<(), String> {
+        if self.memories_len == 0 {
+            return Err(String::from("Module has no memory"));
+        }
+
+        Ok(())
+    }
+
+    fn expect_atomic_access(&self, kind: &WasmStoreKind) -> Result<(), String> {
+        self.features
+            .require(WasmFeature::Threads, "Atomic memory access")?;
+        self.expect_memory()?;
+        if kind.atomic_index().is_none() {
+            return Err(format!("Atomic access of {kind:?} is not supported"));
+        }
+
+        Ok(())
+    }
+
+    fn validate_limits(&self, limits: &WasmLimits) -> Result<(), String> {
+        validate_limits(limits)?;
+        if limits.shared {
+            self.features
+                .require(WasmFeature::Threads, "Shared memory")?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_value_type(&self, value_type: &WasmType) -> Result<(), String> {
+        match value_type {
+            WasmType::ExternRef => self
+                .features
+                .require(WasmFeature::ReferenceTypes, "externref"),
+            WasmType::Ref { type_index } => self.get_gc_type(*type_index).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    fn get_gc_type(&self, type_index: u32) -> Result<&'a WasmGcType, String> {
+        self.features
+            .require(WasmFeature::Gc, "Reference to a GC type")?;
+        self.module
+            .gc_types
+            .get(type_index as usize)
+            .ok_or_else(|| format!("Unknown GC type #{type_index}"))
+    }
+
+    fn get_struct_fields(&self, type_index: u32) -> Result<&'a [WasmFieldType], String> {
+        let WasmGcType::Struct { fields } = self.get_gc_type(type_index)? else {
+            return Err(format!("Type #{type_index} is not a struct"));
+        };
+
+        Ok(fields)
+    }
+
+    fn get_local_type(&self, local_index: u32) -> Result<WasmType, String> {
+        self.locals
+            .get(local_index as usize)
+            .cloned()
+            .ok_or_else(|| format!("Unknown local #{local_index}"))
+    }
+
+    fn get_global(&self, global_index: u32) -> Result<&'a WasmGlobal, String> {
+        self.module
+            .globals
+            .get(global_index as usize)
+            .ok_or_else(|| format!("Unknown global #{global_index}"))
+    }
+
+    fn check_results_len(&self, fn_type: &WasmFnType) -> Result<(), String> {
+        if fn_type.outputs.len() > 1 {
+            return self
+                .features
+                .require(WasmFeature::MultiValue, "Multiple results");
+        }
+
+        Ok(())
+    }
+
+    fn get_fn_type(&self, type_index: u32) -> Result<&'a WasmFnType, String> {
+        self.module
+            .types
+            .get(type_index as usize)
+            .ok_or_else(|| format!("Unknown type #{type_index}"))
+    }
+
+    fn get_fn_type_by_fn_index(&self, fn_index: u32) -> Result<&'a WasmFnType, String> {
+        let mut fn_imports_seen = 0;
+        for import in &self.module.imports {
+            if let WasmImportDesc::Func { type_index } = import.item_desc {
+                if fn_imports_seen == fn_index {
+                    return self.get_fn_type(type_index);
+                }
+                fn_imports_seen += 1;
+            }
+        }
+
+        let Some(type_index) = self
+            .module
+            .functions
+            .get((fn_index - fn_imports_seen) as usize)
+        else {
+            return Err(format!("Unknown function #{fn_index}"));
+        };
+
+        self.get_fn_type(*type_index)
+    }
+
+    fn get_fn_name(&self, fn_index: u32) -> Option<&'a str> {
+        for fn_info in &self.module.debug_fn_info {
+            if fn_info.fn_index == fn_index {
+                return Some(&fn_info.fn_name);
+            }
+        }
+
+        None
+    }
+}
+
+fn error(item: &str, message: impl AsRef<str>) -> ValidationError {
+    ValidationError {
+        message: format!("Invalid wasm module, {item}: {}", message.as_ref()),
+    }
+}
+
+fn validate_limits(limits: &WasmLimits) -> Result<(), String> {
+    if limits.min > MAX_PAGES {
+        return Err(format!("Memory min size is larger than {MAX_PAGES} pages"));
+    }
+
+    if let Some(max) = limits.max {
+        if max > MAX_PAGES {
+            return Err(format!("Memory max size is larger than {MAX_PAGES} pages"));
+        }
+        if max < limits.min {
+            return Err(String::from("Memory max size is less than min size"));
+        }
+    } else if limits.shared {
+        return Err(String::from("Shared memory must have max size"));
+    }
+
+    Ok(())
+}
+
+fn validate_table_limits(limits: &WasmLimits) -> Result<(), String> {
+    if limits.max.is_some_and(|max| max < limits.min) {
+        return Err(String::from("Table max size is less than min size"));
+    }
+
+    Ok(())
+}
+
+fn is_ref_type(value_type: &WasmType) -> bool {
+    matches!(value_type, WasmType::ExternRef | WasmType::Ref { .. })
+}
+
+fn validate_align(align: u32, natural_size: u32) -> Result<(), String> {
+    if align >= 32 || 1 << align > natural_size {
+        return Err(format!(
+            "Alignment 2^{align} is larger than natural alignment {natural_size}"
+        ));
+    }
+
+    Ok(())
+}
+
+// returns (value type, natural size)
+fn get_load_kind_info(kind: &WasmLoadKind) -> (WasmType, u32) {
+    match kind {
+        WasmLoadKind::I32 => (WasmType::I32, 4),
+        WasmLoadKind::I64 => (WasmType::I64, 8),
+        WasmLoadKind::F32 => (WasmType::F32, 4),
+        WasmLoadKind::F64 => (WasmType::F64, 8),
+        WasmLoadKind::I32I8 | WasmLoadKind::I32U8 => (WasmType::I32, 1),
+        WasmLoadKind::I32I16 | WasmLoadKind::I32U16 => (WasmType::I32, 2),
+        WasmLoadKind::I64I8 | WasmLoadKind::I64U8 => (WasmType::I64, 1),
+        WasmLoadKind::I64I16 | WasmLoadKind::I64U16 => (WasmType::I64, 2),
+        WasmLoadKind::I64I32 | WasmLoadKind::I64U32 => (WasmType::I64, 4),
+    }
+}
+
+// returns (value type, natural size)
+fn get_store_kind_info(kind: &WasmStoreKind) -> (WasmType, u32) {
+    match kind {
+        WasmStoreKind::I32 => (WasmType::I32, 4),
+        WasmStoreKind::I64 => (WasmType::I64, 8),
+        WasmStoreKind::F32 => (WasmType::F32, 4),
+        WasmStoreKind::F64 => (WasmType::F64, 8),
+        WasmStoreKind::I32U8 => (WasmType::I32, 1),
+        WasmStoreKind::I32U16 => (WasmType::I32, 2),
+        WasmStoreKind::I64U8 => (WasmType::I64, 1),
+        WasmStoreKind::I64U16 => (WasmType::I64, 2),
+        WasmStoreKind::I64U32 => (WasmType::I64, 4),
+    }
+}
+
+// returns (operand type, result type)
+fn get_binary_op_types(kind: &WasmBinaryOpKind) -> (WasmType, WasmType) {
+    use WasmBinaryOpKind::*;
+
+    match kind {
+        I32_EQ | I32_NE | I32_LT_S | I32_LT_U | I32_GT_S | I32_GT_U | I32_LE_S | I32_LE_U
+        | I32_GE_S | I32_GE_U => (WasmType::I32, WasmType::I32),
+        I64_EQ | I64_NE | I64_LT_S | I64_LT_U | I64_GT_S | I64_GT_U | I64_LE_S | I64_LE_U
+        | I64_GE_S | I64_GE_U => (WasmType::I64, WasmType::I32),
+        F32_EQ | F32_NE | F32_LT | F32_GT | F32_LE | F32_GE => (WasmType::F32, WasmType::I32),
+        F64_EQ | F64_NE | F64_LT | F64_GT | F64_LE | F64_GE => (WasmType::F64, WasmType::I32),
+
+        I32_ADD | I32_SUB | I32_MUL | I32_DIV_S | I32_DIV_U | I32_REM_S | I32_REM_U | I32_AND
+        | I32_OR | I32_XOR | I32_SHL | I32_SHR_S | I32_SHR_U | I32_ROTL | I32_ROTR => {
+            (WasmType::I32, WasmType::I32)
+        }
+        I64_ADD | I64_SUB | I64_MUL | I64_DIV_S | I64_DIV_U | I64_REM_S | I64_REM_U | I64_AND
+        | I64_OR | I64_XOR | I64_SHL | I64_SHR_S | I64_SHR_U | I64_ROTL | I64_ROTR => {
+            (WasmType::I64, WasmType::I64)
+        }
+        F32_ADD | F32_SUB | F32_MUL | F32_DIV | F32_MIN | F32_MAX | F32_COPYSIGN => {
+            (WasmType::F32, WasmType::F32)
+        }
+        F64_ADD | F64_SUB | F64_MUL | F64_DIV | F64_MIN | F64_MAX | F64_COPYSIGN => {
+            (WasmType::F64, WasmType::F64)
+        }
+    }
+}
+
+// returns (operand type, result type)
+fn get_unary_op_types(kind: &WasmUnaryOpKind) -> (WasmType, WasmType) {
+    use WasmUnaryOpKind::*;
+
+    match kind {
+        I32_EQZ | I32_CLZ | I32_CTZ | I32_POPCNT => (WasmType::I32, WasmType::I32),
+        I64_EQZ => (WasmType::I64, WasmType::I32),
+        I64_CLZ | I64_CTZ | I64_POPCNT => (WasmType::I64, WasmType::I64),
+        F32_ABS | F32_NEG | F32_CEIL | F32_FLOOR | F32_TRUNC | F32_NEAREST | F32_SQRT => {
+            (WasmType::F32, WasmType::F32)
+        }
+        F64_ABS | F64_NEG | F64_CEIL | F64_FLOOR | F64_TRUNC | F64_NEAREST | F64_SQRT => {
+            (WasmType::F64, WasmType::F64)
+        }
+
+        I32_TRUNC_F32_S | I32_TRUNC_F32_U | I32_REINTERPRET_F32 => (WasmType::F32, WasmType::I32),
+        I32_TRUNC_F64_S | I32_TRUNC_F64_U => (WasmType::F64, WasmType::I32),
+        I64_TRUNC_F32_S | I64_TRUNC_F32_U => (WasmType::F32, WasmType::I64),
+        I64_TRUNC_F64_S | I64_TRUNC_F64_U | I64_REINTERPRET_F64 => (WasmType::F64, WasmType::I64),
+        F32_CONVERT_I32_S | F32_CONVERT_I32_U | F32_REINTERPRET_I32 => {
+            (WasmType::I32, WasmType::F32)
+        }
+        F32_CONVERT_I64_S | F32_CONVERT_I64_U => (WasmType::I64, WasmType::F32),
+        F32_DEMOTE_F64 => (WasmType::F64, WasmType::F32),
+        F64_CONVERT_I32_S | F64_CONVERT_I32_U => (WasmType::I32, WasmType::F64),
+        F64_CONVERT_I64_S | F64_CONVERT_I64_U | F64_REINTERPRET_I64 => {
+            (WasmType::I64, WasmType::F64)
+        }
+        F64_PROMOTE_F32 => (WasmType::F32, WasmType::F64),
+    }
+}
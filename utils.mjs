@@ -141,7 +141,9 @@ async function debugWasiCommand(args) {
     });
 }
 
-async function testCommand() {
+/** @param {string[]} args */
+async function testCommand(args) {
+    const updateSnapshots = args.includes("--update-snapshots");
     const v1 = await loadCompilerWithWasiAPI(await fs.readFile(COMPILER_PATH));
     const v2 = await loadCompilerWithWasiAPI(await fs.readFile(COMPILER_PATH), {
         buildArgs: (fileName) => ["lo", fileName ?? "-i", "--compile-v2"],
@@ -445,6 +447,42 @@ async function testCommand() {
         assert.strictEqual(output, "14\n");
     });
 
+    testCompilers("compiles sret.lo", { v1 }, async (compile) => {
+        const program = await compile("./examples/test/sret.lo");
+
+        const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+            await runWASI(program, { stdout: stdout.fd });
+            return fs.readFile(stdoutFile, { encoding: "utf-8" });
+        });
+
+        assert.strictEqual(output, "25\n13\n16\n6\n1\n");
+    });
+
+    testCompilers("compiles stack-locals.lo", { v1 }, async (compile) => {
+        const program = await compile("./examples/test/stack-locals.lo");
+
+        const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+            await runWASI(program, { stdout: stdout.fd });
+            return fs.readFile(stdoutFile, { encoding: "utf-8" });
+        });
+
+        assert.strictEqual(output, "2 1\n15 25\n55\n");
+    });
+
+    testCompilers("compiles string-overlap.lo", { v1 }, async (compile) => {
+        const program = await compile("./examples/test/string-overlap.lo");
+
+        const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+            await runWASI(program, { stdout: stdout.fd });
+            return fs.readFile(stdoutFile, { encoding: "utf-8" });
+        });
+
+        assert.strictEqual(
+            output,
+            "Hello World!\nWorld!\n\nGoodbye!\nGoodbye23\n"
+        );
+    });
+
     testCompilers("compiles tracing.lo", { v1 }, async (compile) => {
         const program = await compile("./examples/test/tracing.lo");
 
@@ -481,14 +519,32 @@ async function testCommand() {
         assert.strictEqual(
             output,
             m`
-            p1 = 1048597
-            p2 = 1048597
-            p3 = 1048606
+            p1 = 1048600
+            p2 = 1048600
+            p3 = 1048612
 
             `
         );
     });
 
+    testCompilers("compiles alloc-intrinsics.lo", { v1 }, async (compile) => {
+        const output = await compile("./examples/test/alloc-intrinsics.lo");
+
+        const program = await loadWasm(output);
+        const first = program.alloc(3, 1);
+        const aligned = program.alloc(8, 8);
+
+        assert.notStrictEqual(first, 0);
+        assert.strictEqual(aligned % 8, 0);
+        assert.ok(aligned >= first + 3);
+        assert.strictEqual(program.alloc_pair(), 11);
+
+        // requests that don't fit grow the memory
+        const big = program.alloc(200_000, 16);
+        assert.ok(program.memory.buffer.byteLength >= big + 200_000);
+        program.free(big);
+    });
+
     testCompilers("compiles defer.lo", { v1 }, async (compile) => {
         const program = await compile("./examples/test/defer.lo");
 
@@ -530,6 +586,21 @@ async function testCommand() {
         );
     });
 
+    testCompilers("compiles custom-section.lo", { v1 }, async (compile) => {
+        const output = await compile("./examples/test/custom-section.lo");
+
+        const mod = await WebAssembly.compile(output);
+        const getSection = (/** @type {string} */ name) =>
+            Buffer.from(WebAssembly.Module.customSections(mod, name)[0]);
+
+        assert.strictEqual(getSection("lo.version").toString(), "0.1.0");
+        assert.deepEqual([...getSection("lo.bytes")], [1, 2, 3]);
+        assert.deepEqual(
+            getSection("lo.abi"),
+            await fs.readFile("./examples/test/custom-section.json")
+        );
+    });
+
     describe("<stdin> input", async () => {
         const v1 = await loadCompilerWithWasiAPI(
             await fs.readFile(COMPILER_PATH),
@@ -732,6 +803,33 @@ async function testCommand() {
         );
     });
 
+    describe("snapshots", async () => {
+        // every `<name>.expected` next to `<name>.lo` opts the program in
+        const snapshotFiles = (
+            await fs.readdir("examples/test", { recursive: true })
+        )
+            .filter((fileName) => fileName.endsWith(".expected"))
+            .sort();
+
+        for (const snapshotFile of snapshotFiles) {
+            const fileName = `examples/test/${snapshotFile.replace(
+                /\.expected$/,
+                ".lo"
+            )}`;
+
+            test(`matches snapshot of ${fileName}`, async () => {
+                const program = await v1(fileName);
+
+                const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+                    await runWASI(program, { stdout: stdout.fd });
+                    return fs.readFile(stdoutFile, { encoding: "utf-8" });
+                });
+
+                await assertSnapshot(fileName, output, updateSnapshots);
+            });
+        }
+    });
+
     describe("formatter", async () => {
         const format = await loadCompilerWithWasiAPI(
             await fs.readFile(COMPILER_PATH),
@@ -764,9 +862,22 @@ async function testCommand() {
             "examples/lo.lo",
             "examples/test/42.lo",
             "examples/test/add.lo",
+            "examples/test/alloc-intrinsics.lo",
             "examples/test/args.test.lo",
+            "examples/test/atomics.lo",
+            "examples/test/bench.lo",
+            "examples/test/bindings.lo",
+            "examples/test/bitcast.lo",
+            "examples/test/bools.lo",
+            "examples/test/catch-destructuring.lo",
+            "examples/test/const-groups.lo",
+            "examples/test/custom-section.lo",
+            "examples/test/data-alignment.lo",
+            "examples/test/data-segments.lo",
             "examples/test/decl-nesting.lo",
+            "examples/test/defer-with.lo",
             "examples/test/defer.lo",
+            "examples/test/div-by-zero.lo",
             "examples/test/demos/aoc2020/1-part2.lo",
             "examples/test/demos/aoc2020/1.lo",
             "examples/test/demos/aoc2020/2-part2.lo",
@@ -788,29 +899,67 @@ async function testCommand() {
             "examples/test/demos/wasm4/src/dark-maze.lo",
             "examples/test/demos/wasm4/src/lib/wasm4.lo",
             "examples/test/demos/wasm4/src/slasher.lo",
+            "examples/test/derive.lo",
+            "examples/test/distinct-types.lo",
+            "examples/test/doc-comments.lo",
             "examples/test/else-if.lo",
+            "examples/test/error-unions.lo",
             "examples/test/errors.lo",
+            "examples/test/eval-imports.lo",
+            "examples/test/externref.lo",
             "examples/test/factorial.lo",
+            "examples/test/fizzbuzz.lo",
+            "examples/test/flags.lo",
             "examples/test/for-loop.lo",
+            "examples/test/gc-structs.lo",
             "examples/test/globals.lo",
             "examples/test/heap-alloc.lo",
+            "examples/test/heap-base.lo",
             "examples/test/hex-and-shifts.lo",
             "examples/test/import.lo",
             "examples/test/include.lo",
+            "examples/test/inline-structs.lo",
+            "examples/test/int-overflow.lo",
+            "examples/test/int16.lo",
+            "examples/test/int64-literals.lo",
             "examples/test/lexer.test.lo",
+            "examples/test/literal-inference.lo",
+            "examples/test/literal-suffixes.lo",
             "examples/test/locals.lo",
             "examples/test/loop.lo",
             "examples/test/macro.lo",
+            "examples/test/memory-dump.lo",
             "examples/test/methods.lo",
             "examples/test/multiple-compiler-errors.lo",
             "examples/test/nested-if-break.lo",
+            "examples/test/nested-macros.lo",
+            "examples/test/numeric-ops.lo",
+            "examples/test/private-fields.lo",
+            "examples/test/private-fields/counter.lo",
+            "examples/test/readonly-pointers.lo",
+            "examples/test/reflection.lo",
+            "examples/test/result-methods.lo",
+            "examples/test/script.lo",
+            "examples/test/slice-bounds.lo",
+            "examples/test/slices.lo",
+            "examples/test/sret.lo",
+            "examples/test/stack-locals.lo",
             "examples/test/std.test.lo",
+            "examples/test/str-ops.lo",
+            "examples/test/string-overlap.lo",
             "examples/test/string-pooling.lo",
             "examples/test/struct-in-struct.lo",
+            "examples/test/struct-layout.lo",
             "examples/test/struct-ref.lo",
             "examples/test/struct.lo",
+            "examples/test/tables.lo",
+            "examples/test/tail-calls.lo",
             "examples/test/tracing.lo",
+            "examples/test/unions.lo",
+            "examples/test/unwrap-error.lo",
             "examples/test/vec.test.lo",
+            "examples/test/wasi-bad-pointers.lo",
+            "examples/test/wasi-env-clock.lo",
         ];
 
         for (const fileName of formattedFiles) {
@@ -826,200 +975,2753 @@ async function testCommand() {
         }
     });
 
-    describe("interpreter", async () => {
-        const interpret = await loadCompilerWithWasiAPI(
-            await fs.readFile(COMPILER_PATH),
-            { buildArgs: (fileName) => ["lo", fileName ?? "-i", "--eval"] }
+    test("generates docs for doc-comments.lo", async () => {
+        const output = await compileFile(
+            "examples/test/doc-comments.lo",
+            "--doc"
         );
 
-        test("interprets 42.lo", async () => {
-            const res = await interpret("examples/test/42.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 42\n"
-            );
-        });
+        assert.strictEqual(
+            output.toString("utf-8"),
+            m`
+            ## const MAX_NAME_LEN
 
-        test("interprets include.lo", async () => {
-            const res = await interpret("examples/test/include.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 120\n"
-            );
-        });
+            \`\`\`lo
+            const MAX_NAME_LEN = 32;
+            \`\`\`
 
-        test("interprets else-if.lo", async () => {
-            const res = await interpret("examples/test/else-if.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 13\n"
-            );
-        });
+            Maximum length of a name
 
-        test("interprets globals.lo", async () => {
-            const res = await interpret("examples/test/globals.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 69\n"
-            );
+            ## struct Point
+
+            \`\`\`lo
+            struct Point {
+                x: u32,
+                y: u32,
+            };
+            \`\`\`
+
+            A point on a 2D plane
+
+            ## fn add
+
+            \`\`\`lo
+            export fn add(a: u32, b: u32): u32;
+            \`\`\`
+
+            Adds two numbers.
+            Wraps on overflow.
+
+            ## fn undocumented
+
+            \`\`\`lo
+            export fn undocumented(): u32;
+            \`\`\`
+
+
+            `
+        );
+    });
+
+    test("expands macros in macro.lo", async () => {
+        const output = await compileFile(
+            "examples/test/macro.lo",
+            "--expand-macros"
+        );
+
+        assert.strictEqual(
+            output.toString("utf-8"),
+            m`
+            export fn main(): u32 {
+                let ptr = {
+                    // expanded from heap::alloc_many!<u32>(4), defined at examples/test/macro.lo:8:1
+                    heap::alloc(4 * sizeof u32) as *&u32;
+                };
+                return ptr as u32;
+            };
+
+            struct heap {};
+
+            macro heap::alloc_many!<T>(count: u32): *&T {
+                heap::alloc(count * sizeof T) as *&T;
+            };
+
+            fn heap::alloc(bytes: u32): &void {
+                // stub
+                return bytes as &void;
+            };
+            ` + "\n"
+        );
+
+        const nested = await compileFile(
+            "examples/test/nested-macros.lo",
+            "--expand-macros"
+        );
+        assert.match(
+            nested.toString("utf-8"),
+            /\/\/ expanded from quad!<u64>\(5 as u64\), .*\n.*let doubled = \{\n.*\/\/ expanded from twice!<u64>/
+        );
+    });
+
+    test("emits IR of add.lo", async () => {
+        const output = await compileFile("examples/test/add.lo", "--emit-ir");
+
+        assert.strictEqual(
+            output.toString("utf-8"),
+            m`
+            fn add(a: u32, b: u32): u32 @ examples/test/add.lo:1:11
+              source_loc examples/test/add.lo:2:5: void
+              return: never
+                casted: u32
+                  binary_op I32_ADD: u32
+                    local_get 0: u32
+                    local_get 1: u32
+            ` + "\n\n"
+        );
+    });
+
+    test("emits AST of add.lo as JSON", async () => {
+        const ast = JSON.parse(
+            (await compileFile("examples/test/add.lo", "--emit-ast")).toString(
+                "utf-8"
+            )
+        );
+        const [fnDef] = ast.exprs;
+        assert.strictEqual(fnDef.kind, "FnDef");
+        assert.strictEqual(fnDef.exported, true);
+        assert.strictEqual(fnDef.decl.fn_name.repr, "add");
+        assert.deepEqual(
+            fnDef.decl.fn_params.map((param) => param.param_name),
+            ["a", "b"]
+        );
+
+        const [returnExpr] = fnDef.body.exprs;
+        assert.strictEqual(returnExpr.expr.kind, "InfixOp");
+        assert.strictEqual(returnExpr.expr.op, "+");
+        assert.deepEqual(returnExpr.expr.rhs.loc, {
+            file: "examples/test/add.lo",
+            offset: 52,
+            end_offset: 53,
+            line: 2,
+            col: 16,
+            end_line: 2,
+            end_col: 17,
         });
 
-        test("interprets hex-and-shifts.lo", async () => {
-            const res = await interpret("examples/test/hex-and-shifts.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 31\n"
-            );
+        const withComments = await compileSource(
+            "/// doc\nconst A = 1; // trailing\n",
+            "--emit-ast"
+        );
+        const { exprs, comments } = JSON.parse(withComments.toString("utf-8"));
+        assert.strictEqual(exprs[0].doc, "doc");
+        assert.deepEqual(
+            comments.map((c) => [c.content, c.is_doc]),
+            [
+                ["/// doc", true],
+                ["// trailing", false],
+            ]
+        );
+    });
+
+    describe("validator", async () => {
+        /** @param {string} fileName */
+        const validate = (fileName) => compileFile(fileName, "--validate");
+
+        test("accepts valid modules", async () => {
+            await validate("./examples/test/demos/hello-world.lo");
+            await validate("./examples/test/demos/wasm4/src/blink.lo");
+            await validate("./examples/lo.lo");
         });
 
-        test("interprets loop.lo", async () => {
-            const res = await interpret("./examples/test/loop.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 120\n"
-            );
+        test("rejects memory access without memory", async () => {
+            await assert.rejects(validate("./examples/lib/std.lo"), {
+                message:
+                    "Invalid wasm module, fn abort, instr #4 " +
+                    "Store { kind: I32, align: 0, offset: 0 }: " +
+                    "Module has no memory\n",
+            });
         });
+    });
 
-        test("interprets for-loop.lo", async () => {
-            const res = await interpret("./examples/test/for-loop.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 138\n"
+    describe("optimizer", async () => {
+        for (const level of ["-O0", "-O1", "-O2"]) {
+            test(`compiles valid modules at ${level}`, async () => {
+                const factorial = await loadWasm(
+                    await compileFile(
+                        "./examples/test/factorial.lo",
+                        "--validate",
+                        level
+                    )
+                );
+                assert.strictEqual(factorial.factorial(5), 120);
+
+                const globals = await loadWasm(
+                    await compileFile(
+                        "./examples/test/globals.lo",
+                        "--validate",
+                        level
+                    )
+                );
+                assert.strictEqual(globals.main(), 69);
+            });
+        }
+
+        test("self-hosts at -O2", async () => {
+            const compileSelfHosted = await loadCompilerWithWasiAPI(
+                await compileFile("./examples/lo.lo", "--validate", "-O2")
             );
+
+            const output = await compileSelfHosted("./examples/test/42.lo");
+            assert.strictEqual((await loadWasm(output)).main(), 42);
         });
 
-        test("interprets methods.lo", async () => {
-            const res = await interpret("./examples/test/methods.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 1\n"
-            );
+        test("removes code after tail calls", async () => {
+            const source =
+                "#pragma allow unreachable_code\n" +
+                "export fn f(x: u32): u32 { return f(x); return 1; };";
+            const args = ["--features", "tail-call", "--validate"];
+
+            const full = await compileSource(source, ...args, "-O0");
+            const optimized = await compileSource(source, ...args, "-O1");
+            // `i32.const 1` and `return`
+            assert.strictEqual(optimized.length, full.length - 3);
         });
 
-        test("interprets decl-nesting.lo", async () => {
-            const res = await interpret("./examples/test/decl-nesting.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 16\n"
+        test("maps inlined code to the call statement", async () => {
+            const sourceMapPath = `${TMP_DIR}/inlined.wasm.map`;
+            await compileSource(
+                "fn twice(x: u32): u32 {\n" +
+                    "    return x * 2;\n" +
+                    "};\n" +
+                    "export fn main(): u32 {\n" +
+                    "    return twice(21);\n" +
+                    "};\n",
+                "-O2",
+                "--source-map",
+                sourceMapPath
             );
-        });
 
-        test("interprets struct.lo", async () => {
-            const res = await interpret("./examples/test/struct.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 13\n"
+            const sourceMap = JSON.parse(
+                await fs.readFile(sourceMapPath, "utf-8")
             );
+            await fs.unlink(sourceMapPath);
+            // `return x * 2` of `twice`, `return twice(21)` of `main`
+            assert.strictEqual(sourceMap.mappings, "iDACI,WAGA");
         });
 
-        test("interprets nested-if-break.lo", async () => {
-            const res = await interpret("./examples/test/nested-if-break.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 1\n"
+        test("strips debug sections", async () => {
+            const full = await v1("./examples/lo.lo");
+            const stripped = await compileFile(
+                "./examples/lo.lo",
+                "--validate",
+                "--strip"
             );
+
+            const getNameSections = async (/** @type {Buffer} */ binary) =>
+                WebAssembly.Module.customSections(
+                    await WebAssembly.compile(binary),
+                    "name"
+                );
+            assert.strictEqual((await getNameSections(full)).length, 1);
+            assert.strictEqual((await getNameSections(stripped)).length, 0);
+            assert.ok(stripped.length < full.length);
         });
+    });
 
-        test("interprets struct-ref.lo", async () => {
-            const res = await interpret("./examples/test/struct-ref.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 3\n"
+    describe("source maps", async () => {
+        test("maps statements of factorial.lo", async () => {
+            const sourceMapPath = `${TMP_DIR}/factorial.wasm.map`;
+            const output = await compileFile(
+                "./examples/test/factorial.lo",
+                "--source-map",
+                sourceMapPath
+            );
+            const [sourceMapURL] = WebAssembly.Module.customSections(
+                await WebAssembly.compile(output),
+                "sourceMappingURL"
+            );
+            // section contains the length-prefixed URL
+            assert.ok(
+                Buffer.from(sourceMapURL).toString().endsWith(sourceMapPath)
             );
-        });
 
-        test("interprets macro.lo", async () => {
-            const res = await interpret("./examples/test/macro.lo");
+            const sourceMap = JSON.parse(
+                await fs.readFile(sourceMapPath, "utf-8")
+            );
+            assert.deepEqual(sourceMap.sources, ["examples/test/factorial.lo"]);
             assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: 16\n"
+                sourceMap.sourcesContent[0],
+                await fs.readFile("./examples/test/factorial.lo", "utf-8")
             );
+            // `if x < 2`, `return x`, `return x * factorial(x - 1)`
+            assert.strictEqual(sourceMap.mappings, "iDACI,OACI,IAEJ");
         });
+    });
 
-        test("interprets import.lo", async () => {
-            const res = await interpret("./examples/test/import.lo");
-            // NOTE: can't really see debug output because it's on stderr and that is ignored on exit = 0
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "result of `main` is: \n"
+    describe("data layout globals", async () => {
+        test("exports __heap_base and __data_end", async () => {
+            const program = await loadWasm(
+                await compileFile(
+                    "examples/test/heap-base.lo",
+                    "--export-heap-base"
+                )
             );
+
+            assert.strictEqual(program.__data_end.value, 14);
+            assert.strictEqual(program.__heap_base.value, 16);
+            assert.strictEqual(program.heap_start(), 16);
         });
 
-        // wasi
+        test("creates __heap_base only when used", async () => {
+            const program = await loadWasm(await v1("examples/test/heap-base.lo"));
 
-        test("interprets hello-world-raw.lo", async () => {
-            const res = await interpret(
-                "./examples/test/demos/hello-world-raw.lo"
-            );
-            assert.strictEqual(res.toString("utf-8"), "Hello World!\n");
+            assert.strictEqual(program.__heap_base, undefined);
+            assert.strictEqual(program.heap_start(), 16);
         });
+    });
 
-        test("interprets hello-world.lo", async () => {
-            const res = await interpret("./examples/test/demos/hello-world.lo");
-            assert.strictEqual(res.toString("utf-8"), "Hello World!\n");
-        });
+    describe("tables", async () => {
+        test("exports a table of tables.lo", async () => {
+            const program = await loadWasm(await v1("examples/test/tables.lo"));
 
-        test("interprets echo.lo", async () => {
-            const res = await interpret("./examples/test/demos/echo.lo");
-            // NOTE: no stdin provided so no output
-            assert.strictEqual(res.toString("utf-8"), "");
+            assert.strictEqual(program.ops.length, 3);
+            assert.strictEqual(program.ops.get(0)(3, 4), 7);
+            assert.strictEqual(program.ops.get(1)(3, 4), 12);
         });
 
-        test("interprets args.test.lo", async () => {
-            const res = await interpret("./examples/test/args.test.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                m`
-                lo
-                ./examples/test/args.test.lo
-                --eval
-
-                `
+        test("imports a table before defined ones", async () => {
+            const wasm = await compileSource(
+                'import from "env" { table funcs: funcref; }; fn one(): u32 { return 1; }; export table own = [one];',
+                "--features",
+                "reference-types"
             );
-        });
 
-        // TODO: provide a way to pass own args to --eval
-        test("interprets cat.lo", async () => {
-            try {
-                await interpret("./examples/test/demos/cat.lo");
-            } catch (err) {
-                assert.strictEqual(err.message, "Usage cat.lo <file>");
-            }
-        });
+            const module = new WebAssembly.Module(wasm);
+            assert.deepEqual(WebAssembly.Module.imports(module), [
+                { module: "env", name: "funcs", kind: "table" },
+            ]);
 
-        test("interprets string-pooling.lo", async () => {
-            const res = await interpret("./examples/test/string-pooling.lo");
-            assert.strictEqual(res.toString("utf-8"), "14\n");
+            const funcs = new WebAssembly.Table({
+                initial: 0,
+                element: "anyfunc",
+            });
+            const program = await loadWasm(wasm, { env: { funcs } });
+            assert.strictEqual(program.own.get(0)(), 1);
         });
 
-        test("interprets tracing.lo", async () => {
-            const res = await interpret("./examples/test/tracing.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                "examples/test/tracing.lo:4:10 - hello there\n"
+        const rejections = [
+            [
+                "table t = [f];",
+                "<stdin>:1:12 - Cannot put unknown function f into table t\n",
+            ],
+            [
+                'import from "env" { table t: externref; };',
+                "<stdin>:1:30 - Unsupported table type: externref, expected funcref\n",
+            ],
+            [
+                'import from "env" { table t: funcref; }; table u = [];',
+                "<stdin>:1:48 - Multiple tables requires wasm feature `reference-types`, enable it with `--features reference-types`\n",
+            ],
+            [
+                "fn f() {}; table t = [f]; table u = [f];",
+                "<stdin>:1:33 - Multiple tables requires wasm feature `reference-types`, enable it with `--features reference-types`\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("data segments", async () => {
+        test("places repeated bytes after the string pool", async () => {
+            const program = await loadWasm(
+                await compileSource(
+                    'export memory { min_pages: 1 }; const S = "abc"; const Z = [7u8; 4]; export fn z(): u32 { return Z as u32; };'
+                )
             );
-        });
 
-        test("interprets struct-in-struct.lo", async () => {
-            const res = await interpret("./examples/test/struct-in-struct.lo");
-            assert.strictEqual(res.toString("utf-8"), "3\n3\n3\n3\n3\n3\n3\n");
+            assert.strictEqual(program.z(), 3);
+            assert.deepEqual(
+                [...new Uint8Array(program.memory.buffer, 0, 8)],
+                [97, 98, 99, 7, 7, 7, 7, 0]
+            );
         });
 
-        test("interprets heap-alloc.lo", async () => {
-            const res = await interpret("./examples/test/heap-alloc.lo");
-            assert.strictEqual(
-                res.toString("utf-8"),
-                m`
-                p1 = 1048597
-                p2 = 1048597
-                p3 = 1048606
+        const rejections = [
+            [
+                "const A = [300u8; 2];",
+                "<stdin>:1:12 - Integer literal 300 is out of range for u8, allowed range is 0..=255\n",
+            ],
+            [
+                "const A = [1u8, 2];",
+                "<stdin>:1:15 - Unexpected token ',', wanted ';'\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
 
-                `
-            );
-        });
+    describe("struct layout", async () => {
+        const rejections = [
+            [
+                "@align(3) struct A { a: u32 };",
+                "<stdin>:1:8 - Struct alignment must be a power of two, got 3\n",
+            ],
+            [
+                "@inline struct A { a: u32 };",
+                "<stdin>:1:2 - Unknown attribute: @inline\n",
+            ],
+            [
+                "@packed fn a() {};",
+                "<stdin>:1:9 - Layout attributes are only supported on structs\n",
+            ],
+            [
+                "@packed @align(4) struct A { a: u32 };",
+                "<stdin>:1:10 - Struct layout is already specified\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
 
-        test("interprets defer.lo", async () => {
-            const res = await interpret("./examples/test/defer.lo");
+    describe("bools", async () => {
+        const rejections = [
+            [
+                "fn a(x: u32): bool { return !x; };",
+                "<stdin>:1:29 - Operator `!` is only supported on bool, got u32\n",
+            ],
+            [
+                "fn a(x: bool): bool { return x + x; };",
+                "<stdin>:1:32 - Operator `+` is incompatible with operands of type bool\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("slices", async () => {
+        const rejections = [
+            [
+                "fn a(x: u32): u32 { return x[0]; };",
+                "<stdin>:1:29 - Trying to index non slice: u32\n",
+            ],
+            [
+                "fn a(x: &[u8]): u8 { return x[1 as u8]; };",
+                "<stdin>:1:30 - Slice index must be u32, got u8\n",
+            ],
+            [
+                "fn a(x: &[u8]): u32 { return x.count(); };",
+                "<stdin>:1:32 - Unknown slice method: count\n",
+            ],
+            [
+                "struct str { data: *&u8, size: u32 }; " +
+                    "fn a(x: str, y: str): bool { return x == y; };",
+                "<stdin>:1:77 - Operator `==` on str requires a " +
+                    "`str::equals(self, other: str): bool` fn\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("defer with captures", async () => {
+        const rejections = [
+            [
+                "fn a() { defer with (x) a(); };",
+                "<stdin>:1:22 - Only locals can be captured by defer: x\n",
+            ],
+            [
+                "fn a(x: u32) { defer with (x, x) a(x); };",
+                "<stdin>:1:31 - Duplicate defer capture: x\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("nested macros", async () => {
+        const rejections = [
+            [
+                "macro m!(): u32 { x; }; fn a(): u32 { let x = 1; return m!(); };",
+                "<stdin>:1:19 - Reading unknown variable: x\n",
+            ],
+            [
+                "macro i!(): u32 { x; }; macro o!(x: u32): u32 { i!(); }; fn a(): u32 { return o!(1); };",
+                "<stdin>:1:19 - Reading unknown variable: x\n",
+            ],
+            [
+                "macro m!(x: u32): u32 { m!(x); }; fn a(): u32 { return m!(1); };",
+                "<stdin>:1:25 - Macro expansion depth limit (64) exceeded while expanding m!\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("nesting limits", async () => {
+        // `return` and the literal are nesting levels too
+        /** @param {number} depth */
+        const nestedParens = (depth) =>
+            "export fn f(): u32 { return " +
+            "(".repeat(depth - 2) +
+            "1" +
+            ")".repeat(depth - 2) +
+            "; };";
+        // the condition of the innermost `if` is a nesting level too
+        /** @param {number} depth */
+        const nestedIfs = (depth) =>
+            "export fn f() { " + "if true { ".repeat(depth - 1) + "}; ".repeat(depth - 1) + "};";
+        /** @param {string} loc */
+        const tooDeep = (loc) =>
+            `<stdin>:${loc} - Expression is too deeply nested, nesting depth limit (256) exceeded\n`;
+
+        test("compiles exprs nested exactly up to the limit", async () => {
+            await compileSource(nestedParens(256));
+            await compileSource(nestedIfs(256));
+            await compileSource(nestedParens(256), "--pretty-print");
+        });
+
+        test("rejects exprs nested one level past the limit", async () => {
+            await expectRejection(nestedParens(257), tooDeep("1:284"));
+            await expectRejection(nestedIfs(257), tooDeep("1:2570"));
+            await expectRejection(nestedParens(257), tooDeep("1:284"), "--pretty-print");
+        });
+
+        test("rejects deeply nested parens", async () => {
+            await expectRejection(nestedParens(10_000), tooDeep("1:284"));
+        });
+
+        test("rejects deeply nested blocks", async () => {
+            await expectRejection(nestedIfs(10_000), tooDeep("1:2570"));
+        });
+
+        test("rejects deeply nested parens when pretty printing", async () => {
+            await expectRejection(
+                nestedParens(10_000),
+                tooDeep("1:284"),
+                "--pretty-print"
+            );
+        });
+
+        test("stops a file including itself under growing paths", async () => {
+            // empty path segments are kept, so every include names a new file
+            const fileName = `${TMP_DIR}/self-include.lo`;
+            await fs.writeFile(fileName, `include ".//self-include.lo";\n`);
+
+            /** @param {number} slashes */
+            const path = (slashes) => TMP_DIR + "/".repeat(slashes) + "self-include.lo";
+            try {
+                await assert.rejects(v1(fileName), {
+                    message:
+                        `${path(128)}:1:9 - Include depth limit (128) exceeded ` +
+                        `while including ${path(129)}\n`,
+                });
+            } finally {
+                await fs.unlink(fileName);
+            }
+        });
+
+        test("includes a file including itself once", async () => {
+            const fileName = `${TMP_DIR}/self-include-once.lo`;
+            await fs.writeFile(
+                fileName,
+                `include "./self-include-once.lo";\nexport fn f(): u32 { return 1; };\n`
+            );
+
+            try {
+                const program = await loadWasm(await v1(fileName));
+                assert.strictEqual(program.f(), 1);
+            } finally {
+                await fs.unlink(fileName);
+            }
+        });
+    });
+
+    describe("reflection builtins", async () => {
+        const rejections = [
+            [
+                "fn a(): u32 { return @field_count(u32); };",
+                "<stdin>:1:23 - @field_count expects a struct type, got u32\n",
+            ],
+            [
+                "fn a() { @nope(u32); };",
+                "<stdin>:1:11 - Unknown builtin: @nope\n",
+            ],
+            [
+                "struct P { x: u32 }; fn a(): u32 { return @field_offset(P, 1); };",
+                "<stdin>:1:60 - Field index out of range for P: 1\n",
+            ],
+            [
+                "fn a() { let _ = @type_name(u32); };",
+                "<stdin>:1:19 - Cannot use @type_name with no memories defined\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("derive", async () => {
+        const rejections = [
+            [
+                "@derive(Equals) fn a() {};",
+                "<stdin>:1:17 - Derive attributes are only supported on structs\n",
+            ],
+            [
+                "@derive(Hash) struct A { x: u32 };",
+                "<stdin>:1:9 - Unknown derive: Hash, expected one of: Equals, ToStr, Default\n",
+            ],
+            [
+                "@derive(ToStr) struct A { x: i32 };",
+                "<stdin>:1:9 - Cannot derive ToStr for A, field `x` has unsupported type i32\n",
+            ],
+            [
+                "@derive(Equals) struct A { x: u32 }; fn A::equals(self, other: A): bool { return true; };",
+                "<stdin>:1:41 - Cannot redefine function: A::equals\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("const groups", async () => {
+        test("keeps `group` and `flags` usable as const names", async () => {
+            await compileSource("const group = 1; const flags = 2;");
+        });
+
+        const rejections = [
+            [
+                "const group C { A, B = 0 };",
+                "<stdin>:1:20 - Duplicate value in const group C: A and B are both 0\n",
+            ],
+            [
+                "const group C { A, B = 2 as u8 };",
+                "<stdin>:1:24 - Const group values must be u32 constants, got u8\n",
+            ],
+            [
+                "const flags F { A = 0x80000000, B };",
+                "<stdin>:1:33 - Value of F::B overflows u32\n",
+            ],
+            [
+                "const group C { A, A };",
+                "<stdin>:1:20 - Duplicate constant: C::A\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("timings", async () => {
+        test("prints time and allocations of each phase", async () => {
+            const stderr = (
+                await compileFileWith("examples/test/42.lo", ["--timings"], {
+                    readStderr: true,
+                })
+            ).toString();
+
+            const phases = ["lex", "parse", "finalize", "optimize", "emit"];
+            for (const phase of [...phases, "total"]) {
+                assert.match(
+                    stderr,
+                    new RegExp(`^${phase} +\\d+\\.\\d{3}ms +\\d+ +\\d+$`, "m")
+                );
+            }
+            assert.match(stderr, /^peak memory: \d+ bytes$/m);
+        });
+    });
+
+    describe("environment variables", async () => {
+        /**
+         * @param {string} source
+         * @param {Record<string, string>} env
+         */
+        const compileSourceWithEnv = (source, env) =>
+            compileSourceWith(source, [], { readStderr: true, env });
+
+        test("resolves includes using LO_INCLUDE_PATH", async () => {
+            const stderr = await compileSourceWithEnv(
+                'include "std.lo"; export fn f(): u32 { return 1; };',
+                { LO_INCLUDE_PATH: "examples/missing:examples/lib" }
+            );
+
+            assert.strictEqual(stderr.toString(), "");
+        });
+
+        test("highlights diagnostics with LO_COLOR=always", async () => {
+            const stderr = await compileSourceWithEnv(
+                "export fn f(): u32 { return 1; let x = 2; return x; };",
+                { LO_COLOR: "always" }
+            );
+
+            assert.strictEqual(
+                stderr.toString(),
+                "\x1b[1m<stdin>:1:32\x1b[0m - " +
+                    "\x1b[33mWarning: Unreachable code\x1b[0m\n"
+            );
+        });
+    });
+
+    describe("output file", async () => {
+        test("writes the wasm module to the -o file", async () => {
+            await runWithTmpFile(async (_, outputFile) => {
+                const stdout = await compileFile(
+                    "examples/test/42.lo",
+                    "-o",
+                    outputFile
+                );
+
+                assert.strictEqual(stdout.length, 0);
+                assert.deepEqual(
+                    await fs.readFile(outputFile),
+                    await v1("examples/test/42.lo")
+                );
+            });
+        });
+    });
+
+    describe("stderr buffering", async () => {
+        test("writes diagnostics before the wasm binary", async () => {
+            const output = await compileSourceWith(
+                "export fn f(): u32 { return 1; let x = 2; return x; }; " +
+                    "export fn g(): u32 { return 2; let y = 3; return y; };",
+                [],
+                { mergeStderr: true }
+            );
+
+            const warnings =
+                "<stdin>:1:32 - Warning: Unreachable code\n" +
+                "<stdin>:1:87 - Warning: Unreachable code\n";
+            assert.strictEqual(
+                output.subarray(0, warnings.length).toString(),
+                warnings
+            );
+            assert.deepEqual(
+                [...output.subarray(warnings.length, warnings.length + 4)],
+                [0x00, 0x61, 0x73, 0x6d]
+            );
+        });
+    });
+
+    describe("argument parsing", async () => {
+        /** @param {string} args space separated arguments */
+        const compileWithArgs = (args) => runCompiler(...args.split(" "));
+
+        test("accepts options in any order", async () => {
+            const expected = await compileWithArgs(
+                "examples/test/42.lo --emit-hash -O2"
+            );
+
+            assert.deepEqual(
+                await compileWithArgs("-O2 --emit-hash examples/test/42.lo"),
+                expected
+            );
+            assert.deepEqual(
+                await compileWithArgs("--emit-hash -O2 -- examples/test/42.lo"),
+                expected
+            );
+        });
+
+        test("prints the usage with --help", async () => {
+            const usage = (await compileWithArgs("--help")).toString();
+            assert.ok(usage.startsWith("Usage: lo <file> [mode] [options]\n"));
+        });
+
+        test("points to --help instead of printing the usage on errors", async () => {
+            await assert.rejects(compileWithArgs("examples/test/42.lo --zzz"), {
+                message: "Unknown option: --zzz\nRun with --help for usage\n",
+            });
+            await assert.rejects(compileWithArgs("--eval"), {
+                message: "Missing input file\nRun with --help for usage\n",
+            });
+        });
+
+        // errors are followed by a hint to use `--help`
+        const rejections = [
+            [
+                "examples/test/42.lo --valdate",
+                "Unknown option: --valdate, did you mean --validate?\n",
+            ],
+            ["examples/test/42.lo --zzz", "Unknown option: --zzz\n"],
+            [
+                "examples/test/42.lo --inspect --eval",
+                "Conflicting compiler modes: --inspect and --eval\n",
+            ],
+            [
+                "examples/test/42.lo --bench",
+                "Option --bench is only available with --eval\n",
+            ],
+            [
+                "examples/test/42.lo --source-map",
+                "Missing source map file after --source-map\n",
+            ],
+            [
+                "examples/test/42.lo -- --eval",
+                "Unexpected argument: --eval, " +
+                    "input file is already examples/test/42.lo\n",
+            ],
+            [
+                "examples/test/42.lo --inspect-range examples/test/42.lo",
+                "Option --inspect-range is only available with --inspect\n",
+            ],
+            [
+                "examples/test/42.lo --inspect --inspect-range 42.lo:3-1",
+                "Invalid inspect range: 42.lo:3-1, " +
+                    "expected <file>[:<first line>-<last line>]\n",
+            ],
+            [
+                "examples/test/42.lo --columns bytes",
+                "Unknown column unit: bytes, expected `chars` or `utf16`",
+            ],
+            [
+                "examples/test/42.lo --columns utf16 --tab-width 2",
+                "--tab-width only applies to `--columns chars`\n",
+            ],
+        ];
+        for (const [args, message] of rejections) {
+            test(`rejects ${args}`, async () => {
+                await assert.rejects(
+                    compileWithArgs(args),
+                    (/** @type {Error} */ err) =>
+                        err.message.startsWith(message)
+                );
+            });
+        }
+    });
+
+    describe("inspect range", async () => {
+        /** @param {string} [range] */
+        const inspectLocs = async (range) => {
+            const args = range ? ["--inspect-range", range] : [];
+            const output = await compileFile(
+                "examples/test/include.lo",
+                "--inspect",
+                ...args
+            );
+            return JSON.parse(output.toString("utf-8"))
+                .filter((item) => item.type === "info")
+                .map((item) => item.loc);
+        };
+
+        test("compiles all fn bodies without a range", async () => {
+            const locs = await inspectLocs();
+
+            assert.ok(locs.includes("1/2:8-2:9"));
+            assert.ok(locs.includes("0/4:12-4:21"));
+        });
+
+        test("skips fn bodies of other files", async () => {
+            assert.deepEqual(await inspectLocs("examples/test/include.lo"), [
+                "0/1:9-1:25",
+                "0/4:12-4:21",
+            ]);
+        });
+
+        test("skips fn bodies outside of the lines", async () => {
+            assert.deepEqual(
+                await inspectLocs("./examples/test/include.lo:1-2"),
+                ["0/1:9-1:25"]
+            );
+            assert.deepEqual(
+                await inspectLocs("examples/test/include.lo:4-4"),
+                ["0/1:9-1:25", "0/4:12-4:21"]
+            );
+        });
+    });
+
+    describe("catch destructuring", async () => {
+        const prelude =
+            "struct E { code: u32, size: u32 }; fn r(): Result<u32, E> { return Ok(1); }; ";
+        const rejections = [
+            [
+                "fn g(): Result<u32, u32> { return Ok(1); }; " +
+                    "export fn f(): u32 { return g() catch { code } { return 0; }; };",
+                "<stdin>:1:83 - Cannot destructure error of type u32, it is not a struct\n",
+            ],
+            [
+                prelude + "export fn f(): u32 { return r() catch { cause } { return 0; }; };",
+                "<stdin>:1:118 - Unknown field cause in struct E\n",
+            ],
+            [
+                prelude + "export fn f(): u32 { return r() catch { code, code } { return 0; }; };",
+                "<stdin>:1:124 - Duplicate local definition: code\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("error unions", async () => {
+        const prelude =
+            "struct A { x: u32 }; struct B { y: u32 }; " +
+            "fn r(): Result<u32, A | B> { return Ok(1); }; ";
+        const rejections = [
+            [
+                "fn f(): Result<u32, u64 | u32> { return Ok(1); };",
+                "<stdin>:1:21 - Error union variant u64 must have at most one 32-bit integer component\n",
+            ],
+            [
+                "fn f(): Result<u32, u32 | u32> { return Ok(1); };",
+                "<stdin>:1:27 - Duplicate error union variant: u32\n",
+            ],
+            [
+                "fn g(): Result<u32, u32> { return Ok(1); }; " +
+                    "export fn f(): u32 { return g() catch e: u32 { return 0; }; };",
+                "<stdin>:1:83 - Cannot catch by error type, u32 is not an error union\n",
+            ],
+            [
+                prelude + "export fn f(): u32 { return r() catch e: u32 { return 0; }; };",
+                "<stdin>:1:130 - Error type u32 is not a variant of A | B\n",
+            ],
+            [
+                prelude +
+                    "export fn f(): u32 { return r() catch e: A { return 0; } catch e: A { return 1; }; };",
+                "<stdin>:1:155 - Error type A is already caught\n",
+            ],
+            [
+                prelude + "export fn f(): u32 { return r() catch e: A { return 0; }; };",
+                "<stdin>:1:127 - Unhandled error types: B\n",
+            ],
+            [
+                prelude + "fn g(): Result<u32, A> { return Ok(r()?); }; export fn f() { let _ = g(); };",
+                "<stdin>:1:127 - Invalid throw type, expected A, got A | B\n",
+            ],
+            [
+                prelude + "fn g(): Result<u32, A | B> { return Err(1); }; export fn f() { let _ = g(); };",
+                "<stdin>:1:125 - Invalid Err type: u32, expected: A | B\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("unhandled results", async () => {
+        const prelude = "fn g(): Result<void, u32> { return Ok(); }; ";
+        /** @param {string} loc */
+        const unhandledAt = (loc) =>
+            `<stdin>:${loc} - Unhandled Result<void, u32>, ` +
+            "`catch` the error, propagate it with `?` or discard it with `let _ =`\n";
+
+        test("accepts handled results", async () => {
+            await compileSource(
+                prelude +
+                    "export fn f(): Result<void, u32> { " +
+                    "g()?; let _ = g(); defer let _ = g(); " +
+                    "g() catch e { return Err(e); }; return g(); };"
+            );
+        });
+
+        const rejections = [
+            [prelude + "export fn f() { g(); };", unhandledAt("1:61")],
+            [prelude + "export fn f(): u32 { g(); return 1; };", unhandledAt("1:66")],
+            [prelude + "export fn f(c: bool) { if c { g(); }; };", unhandledAt("1:75")],
+            [prelude + "export fn f() { defer g(); };", unhandledAt("1:67")],
+            [prelude + "export fn f(x: u32) { defer with (x) g(); };", unhandledAt("1:82")],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("result methods", async () => {
+        test("panics on unwrapped error in unwrap-error.lo", async () => {
+            await assert.rejects(compileFile("examples/test/unwrap-error.lo", "--eval"), {
+                message: "examples/test/unwrap-error.lo:8:22 - Unwrapped an error\n",
+            });
+        });
+
+        const prelude = "fn r(): Result<u32, u32> { return Ok(1); }; ";
+        const rejections = [
+            [
+                prelude + "export fn f(): u32 { return r().expect(); };",
+                "<stdin>:1:77 - Unknown Result method: expect\n",
+            ],
+            [
+                prelude + "export fn f(): u32 { return r().unwrap_or(1 as u8); };",
+                "<stdin>:1:77 - Invalid arguments for `Result<u32, u32>::unwrap_or` call: [u8], expected: [u32]\n",
+            ],
+            [
+                prelude + "export fn f(): bool { return r().is_ok(1); };",
+                "<stdin>:1:78 - Invalid arguments for `Result<u32, u32>::is_ok` call: [u32], expected: []\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("unreachable code warnings", async () => {
+        const warnings = [
+            [
+                "export fn f(): u32 { return 1; let x = 2; return x; };",
+                "<stdin>:1:32 - Warning: Unreachable code\n",
+            ],
+            [
+                "export fn f() { loop { break; let x = 2; }; };",
+                "<stdin>:1:31 - Warning: Unreachable code\n",
+            ],
+            [
+                "export fn f(c: bool) { if c { return; } else { unreachable; }; f(c); };",
+                "<stdin>:1:64 - Warning: Unreachable code\n",
+            ],
+            [
+                "export fn f(c: bool) { if c { return; f(c); }; f(c); };",
+                "<stdin>:1:39 - Warning: Unreachable code\n",
+            ],
+            [
+                "export fn f() { loop {}; f(); };",
+                "<stdin>:1:26 - Warning: Unreachable code\n",
+            ],
+            ["export fn f(c: bool) { if c { return; }; f(c); };", ""],
+            ["export fn f(): u32 { return 1; };", ""],
+            [
+                "#pragma allow unreachable_code\n" +
+                    "export fn f(): u32 { return 1; let x = 2; return x; };",
+                "",
+            ],
+        ];
+        for (const [source, warning] of warnings) {
+            test(`warns about ${source}`, async () => {
+                assert.strictEqual(await stderrOf(source), warning);
+            });
+        }
+    });
+
+    describe("unused warnings", async () => {
+        const warnings = [
+            [
+                "export fn f(a: u32, b: u32): u32 { return a; };",
+                "<stdin>:1:21 - Warning: Unused parameter: b\n",
+            ],
+            ["export fn f(a: u32, _b: u32): u32 { return a; };", ""],
+            ["export fn u32::f(self): u32 { return 1; };", ""],
+            [
+                "fn g(): u32 { return 1; }; export fn f() { defer g(); };",
+                "<stdin>:1:50 - Warning: Unused result of type u32, discard it with `let _ = ...`\n",
+            ],
+            [
+                "fn g(): u32 { return 1; }; export fn f(x: u32) { defer with (x) g(); };",
+                "<stdin>:1:65 - Warning: Unused result of type u32, discard it with `let _ = ...`\n",
+            ],
+            ["fn g(): u32 { return 1; }; export fn f() { defer let _ = g(); };", ""],
+            [
+                "#pragma allow unused_param\n" +
+                    "export fn f(a: u32, b: u32): u32 { return a; };",
+                "",
+            ],
+            [
+                "#pragma allow unused_result\n" +
+                    "fn g(): u32 { return 1; }; export fn f() { defer g(); };",
+                "",
+            ],
+        ];
+        for (const [source, warning] of warnings) {
+            test(`warns about ${source}`, async () => {
+                assert.strictEqual(await stderrOf(source), warning);
+            });
+        }
+    });
+
+    describe("wasi abi warnings", async () => {
+        const warnings = [
+            [
+                'import from "wasi_snapshot_preview1" { fn fd_write(fd: u32, iovs: u32, len: u32): u32; };',
+                "<stdin>:1:43 - Warning: Signature of fd_write doesn't match the WASI ABI, " +
+                    "expected (i32, i32, i32, i32) -> i32, got (i32, i32, i32) -> i32\n",
+            ],
+            [
+                'import from "wasi_snapshot_preview1" { fn fd_seek(fd: u32, offset: u32, whence: u8, out: &u64): u32; };',
+                "<stdin>:1:43 - Warning: Signature of fd_seek doesn't match the WASI ABI, " +
+                    "expected (i32, i64, i32, i32) -> i32, got (i32, i32, i32, i32) -> i32\n",
+            ],
+            [
+                'import from "wasi_snapshot_preview1" { fn proc_exit(code: u32): u32; };',
+                "<stdin>:1:43 - Warning: Signature of proc_exit doesn't match the WASI ABI, " +
+                    "expected (i32) -> (), got (i32) -> i32\n",
+            ],
+            [
+                'import from "wasi_snapshot_preview1" { fn fd_wirte(fd: u32): u32; };',
+                "<stdin>:1:43 - Warning: Unknown WASI function: fd_wirte\n",
+            ],
+            [
+                'import from "wasi_snapshot_preview1" { fn clock_time_get(id: u32, precision: u64, time: &u64): u32; };',
+                "",
+            ],
+            [
+                "struct wasi {};\n" +
+                    'import from "wasi_snapshot_preview1" { fn wasi::proc_exit(code: u32): never; };',
+                "",
+            ],
+            ['import from "env" { fn fd_write(fd: u32): u32; };', ""],
+            [
+                "#pragma allow wasi_abi\n" +
+                    'import from "wasi_snapshot_preview1" { fn fd_close(fd: u64): u32; };',
+                "",
+            ],
+        ];
+        for (const [source, warning] of warnings) {
+            test(`warns about ${source}`, async () => {
+                assert.strictEqual(await stderrOf(source), warning);
+            });
+        }
+    });
+
+    describe("shebang and pragmas", async () => {
+        test("skips the shebang line", async () => {
+            const warnings = await stderrOf(
+                "#!/usr/bin/env -S lo --eval\nexport fn f(): u32 { return 1; };"
+            );
+            assert.strictEqual(warnings, "");
+        });
+
+        const rejections = [
+            [
+                "export fn f() {};\n#!/usr/bin/env lo",
+                "<stdin>:2:1 - Unexpected char: #\n",
+            ],
+            [
+                "#pragma deny unreachable_code",
+                "<stdin>:1:1 - Unknown pragma: deny\n",
+            ],
+            [
+                "#pragma allow unused",
+                "<stdin>:1:1 - Unknown warning: unused\n",
+            ],
+            [
+                "#pragma allow",
+                "<stdin>:1:1 - Expected warning names after `#pragma allow`\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("unicode identifiers", async () => {
+        test("normalizes identifiers to NFC", async () => {
+            const warnings = await stderrOf(
+                "export fn f(): u32 { let caf\u00e9 = 1; return cafe\u0301; };"
+            );
+            assert.strictEqual(warnings, "");
+        });
+
+        const rejections = [
+            [
+                "export fn f(): u32 { let \u03b1\u03b2 = 1; return \u03b1\u03b2 + y; };",
+                "<stdin>:1:46 - Reading unknown variable: y\n",
+            ],
+            [
+                "export fn f(): u32 { let a\u20ac = 1; };",
+                "<stdin>:1:27 - Unexpected char: \u20ac\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("int literals", async () => {
+        const rejections = [
+            [
+                "export fn f(): u8 { return 256u8; };",
+                "<stdin>:1:28 - Integer literal 256 is out of range for u8, allowed range is 0..=255\n",
+            ],
+            [
+                "export fn f(): i16 { return 32768i16; };",
+                "<stdin>:1:29 - Integer literal 32768 is out of range for i16, allowed range is -32768..=32767\n",
+            ],
+            [
+                "export fn f(): i8 { return 128i8; };",
+                "<stdin>:1:28 - Integer literal 128 is out of range for i8, allowed range is -128..=127\n",
+            ],
+            [
+                "export fn f(): u32 { return 0x100000000; };",
+                "<stdin>:1:22 - Integer literal 4294967296 is out of range for u32, allowed range is 0..=4294967295\n",
+            ],
+            [
+                "export fn f(): u64 { return 18446744073709551616u64; };",
+                "<stdin>:1:29 - Integer literal does not fit into 64 bits: 18446744073709551616\n",
+            ],
+            [
+                "export fn f(): u8 { return 300 as u8; };",
+                "<stdin>:1:32 - Integer literal 300 is out of range for u8, allowed range is 0..=255\n",
+            ],
+            [
+                "export fn f(): i16 { return (-40000) as i16; };",
+                "<stdin>:1:38 - Integer literal -40000 is out of range for i16, allowed range is -32768..=32767\n",
+            ],
+            [
+                "type B = distinct u8; export fn f(): B { return 256 as B; };",
+                "<stdin>:1:53 - Integer literal 256 is out of range for u8, allowed range is 0..=255\n",
+            ],
+            [
+                "export fn f(): u8 { return 256; };",
+                "<stdin>:1:21 - Integer literal 256 is out of range for u8, allowed range is 0..=255\n",
+            ],
+            [
+                "fn f(x: u16) {}; export fn g() { f(70000); };",
+                "<stdin>:1:34 - Integer literal 70000 is out of range for u16, allowed range is 0..=65535\n",
+            ],
+            [
+                "export fn f(x: i8): i8 { return x + 200; };",
+                "<stdin>:1:35 - Integer literal 200 is out of range for i8, allowed range is -128..=127\n",
+            ],
+            [
+                "export fn f(): i64 { return -9223372036854775809i64; };",
+                "<stdin>:1:30 - Integer literal -9223372036854775809 is out of range for i64, allowed range is -9223372036854775808..=9223372036854775807\n",
+            ],
+            [
+                "export fn f(): i8 { return -129i8; };",
+                "<stdin>:1:29 - Integer literal -129 is out of range for i8, allowed range is -128..=127\n",
+            ],
+            [
+                "export fn f(): i32 { return -2147483649; };",
+                "<stdin>:1:22 - Integer literal -2147483649 is out of range for i32, allowed range is -2147483648..=2147483647\n",
+            ],
+            [
+                "fn f(x: u8) {}; export fn g() { f(200 + 100); };",
+                "<stdin>:1:33 - Integer literal 300 is out of range for u8, allowed range is 0..=255\n",
+            ],
+            [
+                "export fn f(): u32 { return -1; };",
+                "<stdin>:1:22 - Integer literal -1 is out of range for u32, allowed range is 0..=4294967295\n",
+            ],
+            [
+                "export fn f(): u64 { let y = 18446744073709551615 + 1; return y; };",
+                "<stdin>:1:51 - Integer literal arithmetic overflows\n",
+            ],
+            [
+                "fn f(x: i64) {}; export fn g() { f(-9223372036854775807 - 2); };",
+                "<stdin>:1:57 - Integer literal arithmetic overflows\n",
+            ],
+            [
+                "export fn f(): u64 { return 4294967296 * 4294967296; };",
+                "<stdin>:1:40 - Integer literal arithmetic overflows\n",
+            ],
+            [
+                "export fn f(): u32 { return 1 / 0; };",
+                "<stdin>:1:31 - Division by zero in constant expression\n",
+            ],
+            [
+                "export fn f(): u32 { return 5 % 0; };",
+                "<stdin>:1:31 - Division by zero in constant expression\n",
+            ],
+            [
+                "fn f(x: i64) {}; export fn g() { f(5u32); };",
+                "<stdin>:1:34 - Invalid arguments for `f` call: [u32], expected: [i64]\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("columns", async () => {
+        // tab, then a char outside of the BMP (two UTF-16 code units)
+        const source =
+            "export fn f(): u32 {\n\tlet \u{1d431} = 1; return y;\n};";
+        const columns = [
+            [[], "<stdin>:2:23 - Reading unknown variable: y\n"],
+            [
+                ["--tab-width", "8"],
+                "<stdin>:2:27 - Reading unknown variable: y\n",
+            ],
+            [
+                ["--columns", "utf16"],
+                "<stdin>:2:21 - Reading unknown variable: y\n",
+            ],
+        ];
+        for (const [args, message] of columns) {
+            test(`counts columns with [${args.join(" ")}]`, async () => {
+                await expectRejection(source, message, ...args);
+            });
+        }
+    });
+
+    describe("block comments", async () => {
+        test("skips nested block comments", async () => {
+            const warnings = await stderrOf(
+                "export fn f(): u32 { /* outer /* inner */ return 2; */ return 1; };"
+            );
+            assert.strictEqual(warnings, "");
+        });
+
+        test("rejects unterminated block comments", async () => {
+            await expectRejection(
+                "export fn f() {};\n/* outer /* inner */",
+                "<stdin>:2:1 - Unterminated block comment\n"
+            );
+        });
+    });
+
+    describe("never returning fns", async () => {
+        const accepted = [
+            "export fn f(): never { loop {}; };",
+            "export fn f(): never { loop { loop { break; }; }; };",
+            "export fn f(c: bool): never { if c { unreachable; } else { loop {}; }; };",
+            "fn g(): never { unreachable; }; export fn f(): u32 { g(); };",
+            "export fn f(c: bool): u32 { if c { return 1; } else { return 2; }; };",
+        ];
+        for (const source of accepted) {
+            test(`accepts ${source}`, async () => {
+                await compileSource(source);
+            });
+        }
+
+        const rejections = [
+            [
+                "export fn f(): never { loop { break; }; };",
+                "<stdin>:1:11 - This function terminates but is marked as `never`\n",
+            ],
+            [
+                "export fn f(c: bool): never { loop { if c { break; }; }; };",
+                "<stdin>:1:11 - This function terminates but is marked as `never`\n",
+            ],
+            [
+                "export fn f(c: bool): never { if c { unreachable; }; };",
+                "<stdin>:1:11 - This function terminates but is marked as `never`\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("readonly pointers", async () => {
+        const prelude = "struct P { x: u32 }; fn P::set(&self) {}; ";
+        const rejections = [
+            [
+                "export fn f(p: &readonly P) { p.x = 1; };",
+                "<stdin>:1:77 - Cannot write through read-only pointer `&readonly P`\n",
+            ],
+            [
+                "export fn f(p: &readonly u32) { *p += 1; };",
+                "<stdin>:1:78 - Cannot write through read-only pointer `&readonly u32`\n",
+            ],
+            [
+                "export fn f(p: &readonly P) { p.set(); };",
+                "<stdin>:1:75 - Invalid arguments for `P::set` call: [&readonly P], expected: [&P]\n",
+            ],
+            [
+                "export fn f(p: &readonly P): &P { return p; };",
+                "<stdin>:1:77 - Invalid return type, expected &P, got &readonly P\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await assert.rejects(compileSource(prelude + source), { message });
+            });
+        }
+    });
+
+    describe("pointer casts", async () => {
+        test("allows casts between pointers and u32", async () => {
+            await compileSource(
+                "export fn f(p: &u8): u32 { let q = (p as u32 + 1) as &u32; return q as u32; };"
+            );
+        });
+
+        const rejections = [
+            [
+                "export fn f(p: &u8): i32 { return p as i32; };",
+                "<stdin>:1:37 - `&u8` cannot be casted to `i32`, pointers can only be casted from and to `u32`\n",
+            ],
+            [
+                "export fn f(x: u8): &u8 { return x as &u8; };",
+                "<stdin>:1:36 - `u8` cannot be casted to `&u8`, pointers can only be casted from and to `u32`\n",
+            ],
+            [
+                "export fn f(p: &u8): bool { return p as bool; };",
+                "<stdin>:1:38 - `&u8` cannot be casted to `bool`, pointers can only be casted from and to `u32`\n",
+            ],
+            [
+                "export fn f(p: &u8) { if p {}; };",
+                "<stdin>:1:26 - Pointer `&u8` cannot be used as a condition, cast it to `u32` to check the address\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("bitcast", async () => {
+        const rejections = [
+            [
+                "fn f() { let x = __bitcast<u64, u32>(1 as u64); };",
+                "<stdin>:1:18 - Cannot bitcast `u64` to `u32`: sizes differ (8 and 4 bytes)\n",
+            ],
+            [
+                "fn f() { let x = __bitcast<u8, u32>(1 as u8); };",
+                "<stdin>:1:18 - Cannot bitcast `u8` to `u32`: sizes differ (1 and 4 bytes)\n",
+            ],
+            [
+                "fn f(x: f32) { let y = __bitcast<f32, u32>(x); };",
+                "<stdin>:1:24 - Cannot bitcast `f32` to `u32`: wasm components differ\n",
+            ],
+            [
+                "fn f() { let x = __bitcast<u32, i32>(1 as u8); };",
+                "<stdin>:1:18 - Invalid arguments for __bitcast, got [u8], expected [u32]\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("unions", async () => {
+        test("allows unions behind pointers and in sizeof", async () => {
+            await compileSource(
+                "union U { a: u32, b: u8 }; " +
+                    "export fn f(p: &U): u32 { return sizeof U + p.b as u32 + p.a; };"
+            );
+        });
+
+        const rejections = [
+            [
+                "union U { a: u32 }; fn f(u: U) {};",
+                "<stdin>:1:29 - Union U can only be used through a pointer\n",
+            ],
+            [
+                "union U { a: u32 }; fn f() { let u = .U { a: 1 }; };",
+                "<stdin>:1:39 - Union U can only be used through a pointer\n",
+            ],
+            [
+                "union U { a: u32 }; fn f(p: &U) { let u = *p; };",
+                "<stdin>:1:43 - Union U can only be used through a pointer\n",
+            ],
+            [
+                "union U { a: u32 }; fn U::get(self) {};",
+                "<stdin>:1:31 - Union U can only be used through a pointer\n",
+            ],
+            [
+                "union U { a: u32 }; fn f(s: &[U]) {};",
+                "<stdin>:1:31 - Union U can only be used through a pointer\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("inline structs", async () => {
+        const rejections = [
+            [
+                "struct A::b { x: u32 }; struct A { b: struct { y: u32 } };",
+                "<stdin>:1:36 - Cannot redefine type A::b\n",
+            ],
+            [
+                "struct A { b: struct { a: A } };",
+                "<stdin>:1:24 - Recursive type needs indirection: field `a` of struct A::b contains A by value\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("recursive structs", async () => {
+        test("allows self references through pointers", async () => {
+            await compileSource(
+                "struct A { x: u32, p: &A, s: &[A], r: &Result<u32, A> };"
+            );
+        });
+
+        const rejections = [
+            [
+                "struct A { x: u32, a: A };",
+                "<stdin>:1:20 - Recursive type needs indirection: field `a` of struct A contains A by value\n",
+            ],
+            [
+                "struct A { r: Result<u32, A> };",
+                "<stdin>:1:12 - Recursive type needs indirection: field `r` of struct A contains A by value\n",
+            ],
+            [
+                "struct A { x: u32, t: (A) };",
+                "<stdin>:1:20 - Recursive type needs indirection: field `t` of struct A contains A by value\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("private fields", async () => {
+        const include = `include "./examples/test/private-fields/counter.lo";`;
+        const rejections = [
+            [
+                `${include} fn f(c: Counter): u32 { return c.value; };`,
+                "<stdin>:1:87 - Field `value` of struct Counter is private, use methods of Counter instead\n",
+            ],
+            [
+                `${include} fn f(c: &Counter) { c.value = 5; };`,
+                "<stdin>:1:76 - Field `value` of struct Counter is private, use methods of Counter instead\n",
+            ],
+            [
+                `${include} fn f(): Counter { return .Counter { value: 0, limit: 1 }; };`,
+                "<stdin>:1:90 - Field `value` of struct Counter is private, use methods of Counter instead\n",
+            ],
+            [
+                `${include} type Counterfeit = u32; fn Counterfeit::get(c: Counter): u32 { return c.value; };`,
+                "<stdin>:1:126 - Field `value` of struct Counter is private, use methods of Counter instead\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("distinct types", async () => {
+        test("keeps plain type aliases transparent", async () => {
+            await compileSource("type A = u32; fn f(x: A): u32 { return x + 1; };");
+        });
+
+        const rejections = [
+            [
+                "type A = distinct u32; fn f(x: A): u32 { return x; };",
+                "<stdin>:1:42 - Invalid return type, expected u32, got A\n",
+            ],
+            [
+                "type A = distinct u32; fn f(x: A): A { return x + 1; };",
+                "<stdin>:1:49 - Operands of `+` have incompatible types: A and u32\n",
+            ],
+            [
+                "type A = distinct u32; type B = distinct u32; fn f(a: A, b: B): bool { return a == b; };",
+                "<stdin>:1:81 - Operands of `==` have incompatible types: A and B\n",
+            ],
+            [
+                "type A = distinct u32; fn f(x: A) {}; fn g() { f(1); };",
+                "<stdin>:1:48 - Invalid arguments for `f` call: [u32], expected: [A]\n",
+            ],
+            [
+                "type A = distinct u32; fn f(): f32 { return 1 as A as f32; };",
+                "<stdin>:1:52 - `A` cannot be casted to `f32`\n",
+            ],
+            [
+                "type A = u32; type A = distinct u32;",
+                "<stdin>:1:20 - Cannot redefine type: A\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("flags", async () => {
+        const rejections = [
+            [
+                "flags A { X }; flags B { Y }; fn f(): A { return A::X | B::Y; };",
+                "<stdin>:1:55 - Operands of `|` have incompatible types: A and B\n",
+            ],
+            [
+                "flags A { X }; fn f(): A { return A::X & 1; };",
+                "<stdin>:1:40 - Operands of `&` have incompatible types: A and u32\n",
+            ],
+            [
+                "flags A { X }; flags B { Y }; fn f(): bool { return A::X.has(B::Y); };",
+                "<stdin>:1:58 - Invalid arguments for `A::has` call: [B], expected: [A]\n",
+            ],
+            [
+                "flags A { X }; fn f(): A { return A::X + A::X; };",
+                "<stdin>:1:40 - Operator `+` is incompatible with operands of type A\n",
+            ],
+            [
+                "flags A { X = 2 as u8 };",
+                "<stdin>:1:15 - Flags values must be u32 constants, got u8\n",
+            ],
+            [
+                "flags A { X, Y = 1 };",
+                "<stdin>:1:14 - Duplicate value in flags A: X and Y are both 1\n",
+            ],
+            [
+                "struct A {}; flags A { X };",
+                "<stdin>:1:20 - Cannot redefine type A\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("alloc intrinsics", async () => {
+        test("calls the @alloc fn", async () => {
+            const output = await compileSource(`
+                export memory { min_pages: 1 };
+                @alloc fn alloc(size: u32, align: u32): &void {
+                    return (size * 100 + align) as &void;
+                };
+                export fn main(): u32 { return __alloc(4, 8) as u32; };
+            `);
+
+            const program = await loadWasm(output);
+            assert.strictEqual(program.main(), 408);
+        });
+
+        const rejections = [
+            [
+                "fn a(): u32 { return __alloc(4, 4) as u32; };",
+                "<stdin>:1:22 - __alloc requires a memory\n",
+            ],
+            [
+                "memory { min_pages: 1 }; @free fn f(ptr: &void) {}; " +
+                    "fn a(): u32 { return __alloc(4, 4) as u32; };",
+                "<stdin>:1:74 - __alloc requires a `@alloc` fn\n",
+            ],
+            [
+                "@alloc fn f(size: u32): &void { return 0 as &void; };",
+                "<stdin>:1:11 - `@alloc` fn must have signature " +
+                    "`fn(size: u32, align: u32): &void`\n",
+            ],
+            [
+                "@alloc struct A { a: u32 };",
+                "<stdin>:1:8 - Allocator attributes are only supported on fns\n",
+            ],
+            [
+                "@alloc @free fn f(ptr: &void) {};",
+                "<stdin>:1:9 - Allocator role is already specified\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("start fn", async () => {
+        test("calls the @start fn on instantiation", async () => {
+            const output = await compileSource(`
+                global value = 0;
+                @start fn init() { value = 42; };
+                export fn get(): u32 { return value; };
+            `);
+
+            const module = new WebAssembly.Module(output);
+            assert.deepEqual(
+                WebAssembly.Module.exports(module).map((e) => e.name),
+                ["get"]
+            );
+
+            const program = await loadWasm(output);
+            assert.strictEqual(program.get(), 42);
+        });
+
+        test("makes repeated @start fn calls a no-op", async () => {
+            const output = await compileSource(`
+                global count = 0;
+                @start fn init() { count += 1; };
+                export fn reinit(): u32 { init(); init(); return count; };
+            `);
+
+            const program = await loadWasm(output);
+            assert.strictEqual(program.reinit(), 1);
+        });
+
+        const rejections = [
+            [
+                "@start fn init(x: u32) {};",
+                "<stdin>:1:11 - `@start` fn must have signature `fn()`\n",
+            ],
+            [
+                "@start fn a() {}; @start fn b() {};",
+                "<stdin>:1:29 - `@start` fn is already defined: a\n",
+            ],
+            [
+                "@start struct A { a: u32 };",
+                "<stdin>:1:8 - Start attribute is only supported on fns\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+    });
+
+    describe("symbol mangling", async () => {
+        const source = `
+            struct Size { w: u32, h: u32 };
+            export fn Size::area(w: u32, h: u32): u32 { return w * h; };
+            export fn u32::double(self): u32 { return self * 2; };
+            export fn _Lx(): u32 { return 1; };
+            export fn main(): u32 { return Size::area(1, 2.double()); };
+        `;
+        /** @param {string[]} args */
+        const exportNamesWith = async (...args) => {
+            const output = await compileSource(source, ...args);
+            const module = new WebAssembly.Module(output);
+            return WebAssembly.Module.exports(module).map((e) => e.name);
+        };
+
+        test("mangles nested export names", async () => {
+            assert.deepEqual(await exportNamesWith(), [
+                "_L4Size4area",
+                "_L3u326double",
+                "_L3_Lx",
+                "main",
+            ]);
+        });
+
+        test("keeps source names with --unmangled-exports", async () => {
+            assert.deepEqual(await exportNamesWith("--unmangled-exports"), [
+                "Size::area",
+                "u32::double",
+                "_Lx",
+                "main",
+            ]);
+        });
+    });
+
+    describe("export names", async () => {
+        const rejections = [
+            [
+                "export fn f() {}; export existing fn f as \"f\";",
+                "<stdin>:1:43 - Duplicate export name: f, " +
+                    "already exported at <stdin>:1:11\n",
+            ],
+            [
+                "fn f() {}; export existing fn f as \"g\"; " +
+                    "export existing fn f as \"g\";",
+                "<stdin>:1:65 - Duplicate export name: g, " +
+                    "already exported at <stdin>:1:36\n",
+            ],
+            [
+                "export fn t() {}; export table t = [t];",
+                "<stdin>:1:32 - Duplicate export name: t, " +
+                    "already exported at <stdin>:1:11\n",
+            ],
+            [
+                "fn f() {}; export existing fn f as \"\";",
+                "<stdin>:1:36 - Export name cannot be empty\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message);
+            });
+        }
+
+        test("rejects fns named like heap base exports", async () => {
+            await expectRejection(
+                "export fn __data_end() {};",
+                "<stdin>:1:11 - Duplicate export name: __data_end, " +
+                    "also exported by --export-heap-base\n",
+                "--export-heap-base"
+            );
+        });
+
+        test("rejects unmangled names colliding with others", async () => {
+            await expectRejection(
+                "fn f() {}; export existing fn f as \"u32::f\"; " +
+                    "export fn u32::f(self) {};",
+                "<stdin>:1:56 - Duplicate export name: u32::f, " +
+                    "already exported at <stdin>:1:36\n",
+                "--unmangled-exports"
+            );
+        });
+    });
+
+    describe("size report", async () => {
+        test("reports fn sizes, call graph and retained bytes", async () => {
+            const report = await stderrOf(
+                `
+                import from "env" { fn log(x: u32); };
+                fn helper(x: u32): u32 { log(x); return x * 2; };
+                fn unused_by_main(): u32 { return helper(3); };
+                export fn main(): u32 { return helper(21); };
+                export fn other(): u32 { return unused_by_main() + helper(1); };
+                `,
+                "--report"
+            );
+
+            assert.strictEqual(
+                report,
+                "fn bodies: 36 bytes in 4 fns\n" +
+                    "        12  helper\n" +
+                    "        10  other\n" +
+                    "         7  unused_by_main\n" +
+                    "         7  main\n" +
+                    "data section: 0 bytes in 0 segments, 0 bytes saved by string pooling\n" +
+                    "call graph:\n" +
+                    "  helper -> env::log\n" +
+                    "  unused_by_main -> helper\n" +
+                    "  main -> helper\n" +
+                    "  other -> helper, unused_by_main\n" +
+                    "retained by:\n" +
+                    "  export main: 19 bytes in 2 fns\n" +
+                    "  export other: 29 bytes in 3 fns\n"
+            );
+        });
+    });
+
+    describe("runtime checks", async () => {
+        /** @param {string} fileName */
+        const evalWithChecks = (fileName) =>
+            compileFile(fileName, "--eval", "--checks", "div-by-zero,bounds");
+
+        test("panics on division by zero in div-by-zero.lo", async () => {
+            await assert.rejects(evalWithChecks("examples/test/div-by-zero.lo"), {
+                message: "examples/test/div-by-zero.lo:4:14 - Division by zero\n",
+            });
+        });
+
+        test("panics on reversed slice range in slice-bounds.lo", async () => {
+            await assert.rejects(evalWithChecks("examples/test/slice-bounds.lo"), {
+                message: "examples/test/slice-bounds.lo:20:25 - Slice range out of bounds\n",
+            });
+        });
+
+        test("panics on slice range past the end", async () => {
+            const source = m`
+                include "./examples/lib/cli.lo";
+                fn main() {
+                    let items = slice!<u32>(alloc_many!<u32>(0, 3), 3);
+                    let tail = items.slice(1, 4);
+                };
+            `;
+            await assert.rejects(compileSource(source, "--eval", "--checks", "bounds"), {
+                message: "<stdin>:4:22 - Slice range out of bounds\n",
+            });
+        });
+
+        test("panics on index past the end", async () => {
+            const source = m`
+                include "./examples/lib/cli.lo";
+                fn main() {
+                    let items = slice!<u32>(alloc_many!<u32>(0, 3), 3);
+                    items[3] = 1;
+                };
+            `;
+            await assert.rejects(compileSource(source, "--eval", "--checks", "bounds"), {
+                message: "<stdin>:4:10 - Index out of bounds\n",
+            });
+        });
+
+        test("requires a panic fn", async () => {
+            await expectRejection(
+                "fn a(x: u32): u32 { return 1 / x; };",
+                "<stdin>:1:30 - Runtime checks require a `panic(message: str): never` fn\n",
+                "--checks",
+                "div-by-zero"
+            );
+        });
+    });
+
+    describe("no multivalue", async () => {
+        /** @param {string} fileName */
+        const compileSingleValue = (fileName) =>
+            compileFile(fileName, "--no-multivalue");
+
+        test("returns multi-component values through memory", async () => {
+            const program = await compileSingleValue("./examples/test/sret.lo");
+
+            const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+                await runWASI(program, { stdout: stdout.fd });
+                return fs.readFile(stdoutFile, { encoding: "utf-8" });
+            });
+
+            assert.strictEqual(output, "25\n13\n16\n6\n1\n");
+        });
+
+        test("compiles lo.lo into a working single-value compiler", async () => {
+            const singleValueCompiler = await loadCompilerWithWasiAPI(
+                await compileSingleValue("./examples/lo.lo")
+            );
+            const multiValueCompiler = await loadCompilerWithWasiAPI(
+                await v1("./examples/lo.lo")
+            );
+
+            assert.deepStrictEqual(
+                await singleValueCompiler("./examples/test/42.lo"),
+                await multiValueCompiler("./examples/test/42.lo")
+            );
+        });
+    });
+
+    describe("bindings", async () => {
+        for (const args of [[], ["--no-multivalue"]]) {
+            test(`loads bindings.lo via js loader ${args}`, async () => {
+                const fileName = "./examples/test/bindings.lo";
+
+                const loaderPath = `${TMP_DIR}/${crypto.randomUUID()}.mjs`;
+                await fs.writeFile(
+                    loaderPath,
+                    await compileFile(fileName, ...args, "--emit-bindings", "js")
+                );
+                try {
+                    const { load, LoError } = await import(`./${loaderPath}`);
+                    const program = await load(
+                        await compileFile(fileName, ...args)
+                    );
+
+                    assert.strictEqual(
+                        program["add-numbers"](2 ** 31, 1),
+                        2 ** 31 + 1
+                    );
+                    assert.strictEqual(program.is_even(4), true);
+                    assert.strictEqual(
+                        program.scale(3n, 2n ** 40n),
+                        3n * 2n ** 40n
+                    );
+                    assert.strictEqual(program.greeting(true), "Good day");
+                    assert.strictEqual(
+                        program.first_word("héllo world"),
+                        "héllo"
+                    );
+                    assert.deepStrictEqual(
+                        program.make_point(4_000_000_000, -5),
+                        { x: 4_000_000_000, y: -5 }
+                    );
+                    assert.deepStrictEqual(
+                        program.span({ x: 1, y: -2 }, { x: 3, y: 4 }),
+                        {
+                            start: { x: 1, y: -2 },
+                            end: { x: 3, y: 4 },
+                            label: "span",
+                        }
+                    );
+                    assert.strictEqual(program.parse_digit("7"), 7);
+                    assert.throws(
+                        () => program.parse_digit("x"),
+                        (err) => err instanceof LoError && err.code === 2
+                    );
+                } finally {
+                    await fs.unlink(loaderPath);
+                }
+            });
+        }
+
+        test("emits typescript declarations for bindings.lo", async () => {
+            const declarations = (
+                await compileFile(
+                    "./examples/test/bindings.lo",
+                    "--emit-bindings",
+                    "dts"
+                )
+            ).toString();
+
+            assert.ok(
+                declarations.includes(
+                    "export interface Span {\n" +
+                        "    start: Point;\n" +
+                        "    end: Point;\n" +
+                        "    label: string;\n" +
+                        "}\n"
+                )
+            );
+            assert.ok(
+                declarations.includes(
+                    '    "add-numbers"(a: number, b: number): number;\n'
+                )
+            );
+            assert.ok(
+                declarations.includes(
+                    "    scale(n: bigint, factor: bigint): bigint;\n"
+                )
+            );
+            assert.ok(
+                declarations.includes(
+                    "    parse_digit(text: string): number;\n"
+                )
+            );
+        });
+    });
+
+    describe("memory definitions", async () => {
+        const threads = ["--features", "threads"];
+
+        test("accepts bare shared flag", async () => {
+            assert.deepStrictEqual(
+                await compileSource(
+                    "export memory { max_pages: 2, shared, min_pages: 1 };",
+                    ...threads
+                ),
+                await compileSource(
+                    "export memory { min_pages: 1, max_pages: 2, shared: true };",
+                    ...threads
+                )
+            );
+        });
+
+        const rejections = [
+            [
+                "memory { min_page: 1 };",
+                "<stdin>:1:10 - Unknown memory property: min_page, " +
+                    "did you mean min_pages?\n",
+            ],
+            [
+                "memory { ayo: 1 };",
+                "<stdin>:1:10 - Unknown memory property: ayo, expected one of: " +
+                    "min_pages, max_pages, data_start, shared\n",
+            ],
+            [
+                "memory { min_pages: 1, min_pages: 2 };",
+                "<stdin>:1:24 - Duplicate memory property: min_pages\n",
+            ],
+            [
+                "memory { min_pages: 2, max_pages: 1 };",
+                "<stdin>:1:8 - Memory min_pages (2) is larger than max_pages (1)\n",
+            ],
+            [
+                "memory { max_pages: 65537 };",
+                "<stdin>:1:8 - Memory max_pages is larger than 65536\n",
+            ],
+            [
+                "memory { min_pages: 1, shared };",
+                "<stdin>:1:8 - Shared memory must have max_pages\n",
+            ],
+        ];
+        for (const [source, message] of rejections) {
+            test(`rejects ${source}`, async () => {
+                await expectRejection(source, message, ...threads);
+            });
+        }
+    });
+
+    describe("wasm features", async () => {
+        /**
+         * @param {string} fileName
+         * @param {string} features
+         */
+        const compileWithFeatures = (fileName, features) =>
+            compileFile(fileName, "--features", features);
+
+        test("rejects instructions of disabled features", async () => {
+            await assert.rejects(compileWithFeatures("./examples/test/sret.lo", "-bulk-memory"), {
+                message:
+                    "examples/lib/std.lo:96:5 - __memory_copy requires wasm feature " +
+                    "`bulk-memory`, enable it with `--features bulk-memory`\n",
+            });
+        });
+
+        test("rejects unknown features", async () => {
+            await assert.rejects(compileWithFeatures("./examples/test/42.lo", "simd,wasi"), {
+                message:
+                    "Unknown wasm feature: wasi, expected one of: bulk-memory, " +
+                    "multi-value, reference-types, simd, tail-call, threads, gc\n",
+            });
+        });
+
+        test("compiles atomics.lo with threads", async () => {
+            const program = await compileWithFeatures("./examples/test/atomics.lo", "threads");
+
+            const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+                await runWASI(program, { stdout: stdout.fd });
+                return fs.readFile(stdoutFile, { encoding: "utf-8" });
+            });
+
+            assert.strictEqual(output, "40 42\n10 11\n999 7\n0 1 1\n1 2 0\n");
+        });
+
+        test("compiles tail-calls.lo with tail-call", async () => {
+            const program = await compileWithFeatures("./examples/test/tail-calls.lo", "tail-call");
+
+            const output = await runWithTmpFile(async (stdout, stdoutFile) => {
+                await runWASI(program, { stdout: stdout.fd });
+                return fs.readFile(stdoutFile, { encoding: "utf-8" });
+            });
+
+            assert.strictEqual(output, "1 0\n500000500000\n1000000\n");
+        });
+
+        test("compiles externref.lo with reference-types", async () => {
+            const output = await compileWithFeatures("./examples/test/externref.lo", "reference-types");
+
+            const descriptions = [];
+            const program = await loadWasm(output, {
+                host: {
+                    describe: (handle, visits) =>
+                        descriptions.push([handle, visits]),
+                },
+            });
+
+            const handle = { name: "handle" };
+            assert.strictEqual(program.has_handle(), 0);
+            assert.strictEqual(program.recall(), null);
+            program.remember(handle);
+            assert.strictEqual(program.has_handle(), 1);
+            assert.strictEqual(program.recall(), handle);
+            assert.deepStrictEqual(descriptions, [[handle, 1]]);
+            assert.strictEqual(program.first_set(null, handle), handle);
+            assert.strictEqual(program.first_set("first", handle), "first");
+        });
+
+        test("rejects externref without reference-types", async () => {
+            await assert.rejects(v1("./examples/test/externref.lo"), {
+                message:
+                    "examples/test/externref.lo:2:25 - externref requires wasm feature " +
+                    "`reference-types`, enable it with `--features reference-types`\n",
+            });
+        });
+
+        test("rejects shared memory without threads", async () => {
+            await assert.rejects(v1("./examples/test/atomics.lo"), {
+                message:
+                    "examples/test/atomics.lo:8:5 - Shared memory requires wasm feature " +
+                    "`threads`, enable it with `--features threads`\n",
+            });
+        });
+
+        test("disables multi-value like --no-multivalue", async () => {
+            assert.deepStrictEqual(
+                await compileWithFeatures(
+                    "./examples/test/sret.lo",
+                    "simd,-multi-value"
+                ),
+                await compileFile("./examples/test/sret.lo", "--no-multivalue")
+            );
+        });
+    });
+
+    describe("gc target", async () => {
+        /** @param {string} fileName */
+        const gc = (fileName) =>
+            compileFile(fileName, "--compile-gc", "--validate");
+        /** @param {string} fileName */
+        const v2 = (fileName) =>
+            compileFile(fileName, "--compile-v2", "--validate");
+        /**
+         * @param {string} fileName
+         * @param {string} backend
+         */
+        const compileWithBackend = (fileName, backend) =>
+            compileFile(
+                fileName,
+                "--compile-v2",
+                "--backend",
+                backend,
+                "--validate"
+            );
+
+        // node doesn't support the final gc encoding yet, so only validation is checked
+        test("compiles gc-structs.lo", async () => {
+            const program = await gc("./examples/test/gc-structs.lo");
+
+            assert.deepStrictEqual(
+                [...program.subarray(0, 4)],
+                [0x00, 0x61, 0x73, 0x6d]
+            );
+        });
+
+        test("emits the same code as v2 without gc types", async () => {
+            assert.deepStrictEqual(
+                await gc("./examples/test/factorial.lo"),
+                await v2("./examples/test/factorial.lo")
+            );
+        });
+
+        test("rejects structs in linear memory mode", async () => {
+            await assert.rejects(v2("./examples/test/gc-structs.lo"), {
+                message:
+                    "examples/test/gc-structs.lo:1:1 - Struct Point is only " +
+                    "supported in `--compile-gc` mode for now\n",
+            });
+        });
+
+        test("selects the gc backend by name", async () => {
+            assert.deepStrictEqual(
+                await compileWithBackend(
+                    "./examples/test/gc-structs.lo",
+                    "wasm-gc"
+                ),
+                await gc("./examples/test/gc-structs.lo")
+            );
+        });
+
+        test("prints the IR with the text backend", async () => {
+            const output = await compileWithBackend(
+                "./examples/test/factorial.lo",
+                "ir"
+            );
+
+            assert.strictEqual(
+                output.toString("utf-8"),
+                m`
+                export fn factorial(x: u32): u32 @ examples/test/factorial.lo:1:1
+                  if: void
+                    casted: bool
+                      binary_op I32_LT_U: u32
+                        var_load x: u32
+                        u32_const 2: u32
+                    then
+                      return: never
+                        var_load x: u32
+                  return: never
+                    binary_op I32_MUL: u32
+                      var_load x: u32
+                      call factorial: u32
+                        binary_op I32_SUB: u32
+                          var_load x: u32
+                          u32_const 1: u32
+                ` + "\n\n"
+            );
+        });
+
+        test("rejects unknown backends", async () => {
+            await assert.rejects(
+                compileWithBackend("./examples/test/factorial.lo", "c"),
+                {
+                    message:
+                        "Unknown backend: c, expected one of: wasm, wasm-gc, ir\n",
+                }
+            );
+        });
+    });
+
+    describe("reproducible builds", async () => {
+        /** @param {string} fileName */
+        const hash = (fileName) => compileFile(fileName, "--emit-hash");
+
+        for (const fileName of ["examples/test/42.lo", "examples/lo.lo"]) {
+            test(`emits hash of ${fileName}`, async () => {
+                const expectedHash = crypto
+                    .createHash("sha256")
+                    .update(await v1(fileName))
+                    .digest("hex");
+
+                const firstHash = (await hash(fileName)).toString();
+                const secondHash = (await hash(fileName)).toString();
+
+                assert.strictEqual(firstHash, expectedHash + "\n");
+                assert.strictEqual(secondHash, firstHash);
+            });
+        }
+    });
+
+    describe("interpreter", async () => {
+        // exit code is the result of `main` which is checked via stdout
+        const interpret = await loadCompilerWithWasiAPI(
+            await fs.readFile(COMPILER_PATH),
+            {
+                ignoreExitCode: true,
+                buildArgs: (fileName) => ["lo", fileName ?? "-i", "--eval"],
+            }
+        );
+        /**
+         * @param {string} fileName
+         * @param {string[]} args
+         */
+        const interpretWith = (fileName, ...args) =>
+            compileFileWith(fileName, ["--eval", ...args], {
+                ignoreExitCode: true,
+            });
+        /**
+         * @param {string} fileName
+         * @param {string[]} args fn name and its arguments
+         */
+        const invoke = (fileName, ...args) =>
+            compileFile(fileName, "--eval", "--invoke", ...args);
+
+        test("uses result of `main` as exit code", async () => {
+            await assert.rejects(compileFile("examples/test/42.lo", "--eval"), {
+                message: "Exited with code 42",
+            });
+        });
+
+        test("dumps memory and globals", async () => {
+            await assert.rejects(
+                compileFile(
+                    "examples/test/memory-dump.lo",
+                    "--eval",
+                    "--dump-globals",
+                    "--dump-memory",
+                    "0x0:20"
+                ),
+                {
+                    message: m`
+                        globals:
+                          #0: I32 = 3
+                        memory 0x00000000..0x00000014:
+                          00000000: 48 65 6c 6c 6f 21 0a 00 00 00 00 00 00 00 00 00  Hello!..........
+                          00000010: 00 00 00 00                                      ....
+                    ` + "\n",
+                }
+            );
+        });
+
+        test("prints profile of include.lo", async () => {
+            // exit code is the result of `main`, so <stderr> ends up in the error
+            await assert.rejects(
+                compileFile("examples/test/include.lo", "--eval", "--profile"),
+                {
+                    message: m`
+                        profile: 53 instrs
+                          50  94.3% 5 calls  factorial at examples/test/factorial.lo:1:11
+                           3   5.7% 1 calls  main at examples/test/include.lo:3:11
+                    ` + "\n",
+                }
+            );
+        });
+
+        /**
+         * @param {string} fileName
+         * @param {string} stdin debugger commands
+         */
+        const debug = (fileName, stdin) =>
+            compileFileWith(fileName, ["--eval", "--debug"], { stdin });
+
+        test("steps through globals.lo with --debug", async () => {
+            const output = await debug(
+                "examples/test/globals.lo",
+                "b alloc\nc\nlocals\nbt\nfinish\nglobals\nq\n"
+            );
+            assert.strictEqual(
+                output.toString(),
+                "paused at main+0: I32Const { value: 45 }\n" +
+                    "(debug) breakpoint set at alloc (examples/test/globals.lo:9:4)\n" +
+                    "(debug) paused at alloc+0: GlobalGet { global_index: 0 }\n" +
+                    "(debug)   #0 size: I32 = 45\n" +
+                    "  #1 ptr: I32 = 0\n" +
+                    "(debug)   at alloc\n" +
+                    "  at main\n" +
+                    "(debug) paused at main+2: Drop\n" +
+                    "(debug)   #0: I32 = 45\n" +
+                    "(debug) "
+            );
+        });
+
+        test("breaks on a line inside a fn with --debug", async () => {
+            const output = await debug(
+                "examples/test/globals.lo",
+                "b globals.lo:11\nc\nc\nlocals\nq\n"
+            );
+            assert.strictEqual(
+                output.toString(),
+                "paused at main+0: I32Const { value: 45 }\n" +
+                    "(debug) breakpoint set at alloc+2 (examples/test/globals.lo:11:5)\n" +
+                    "(debug) paused at alloc+2: GlobalGet { global_index: 0 }\n" +
+                    "(debug) paused at alloc+2: GlobalGet { global_index: 0 }\n" +
+                    "(debug)   #0 size: I32 = 24\n" +
+                    "  #1 ptr: I32 = 45\n" +
+                    "(debug) "
+            );
+        });
+
+        test("interprets 42.lo", async () => {
+            const res = await interpret("examples/test/42.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 42\n"
+            );
+        });
+
+        test("interprets include.lo", async () => {
+            const res = await interpret("examples/test/include.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 120\n"
+            );
+        });
+
+        test("interprets else-if.lo", async () => {
+            const res = await interpret("examples/test/else-if.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 13\n"
+            );
+        });
+
+        test("interprets globals.lo", async () => {
+            const res = await interpret("examples/test/globals.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 69\n"
+            );
+        });
+
+        test("interprets hex-and-shifts.lo", async () => {
+            const res = await interpret("examples/test/hex-and-shifts.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 31\n"
+            );
+        });
+
+        test("interprets loop.lo", async () => {
+            const res = await interpret("./examples/test/loop.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 120\n"
+            );
+        });
+
+        test("interprets for-loop.lo", async () => {
+            const res = await interpret("./examples/test/for-loop.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 138\n"
+            );
+        });
+
+        test("interprets methods.lo", async () => {
+            const res = await interpret("./examples/test/methods.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 1\n"
+            );
+        });
+
+        test("interprets decl-nesting.lo", async () => {
+            const res = await interpret("./examples/test/decl-nesting.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 16\n"
+            );
+        });
+
+        test("interprets struct.lo", async () => {
+            const res = await interpret("./examples/test/struct.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 13\n"
+            );
+        });
+
+        test("interprets nested-if-break.lo", async () => {
+            const res = await interpret("./examples/test/nested-if-break.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 1\n"
+            );
+        });
+
+        test("interprets struct-ref.lo", async () => {
+            const res = await interpret("./examples/test/struct-ref.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 3\n"
+            );
+        });
+
+        test("interprets macro.lo", async () => {
+            const res = await interpret("./examples/test/macro.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 16\n"
+            );
+        });
+
+        test("interprets import.lo", async () => {
+            const res = await interpret("./examples/test/import.lo");
+            // NOTE: can't really see debug output because it's on stderr and that is ignored on exit = 0
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: \n"
+            );
+        });
+
+        // wasi
+
+        test("interprets hello-world-raw.lo", async () => {
+            const res = await interpret(
+                "./examples/test/demos/hello-world-raw.lo"
+            );
+            assert.strictEqual(res.toString("utf-8"), "Hello World!\n");
+        });
+
+        test("interprets hello-world.lo", async () => {
+            const res = await interpret("./examples/test/demos/hello-world.lo");
+            assert.strictEqual(res.toString("utf-8"), "Hello World!\n");
+        });
+
+        test("interprets echo.lo", async () => {
+            const res = await interpret("./examples/test/demos/echo.lo");
+            // NOTE: no stdin provided so no output
+            assert.strictEqual(res.toString("utf-8"), "");
+        });
+
+        test("interprets args.test.lo", async () => {
+            const res = await interpret("./examples/test/args.test.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                m`
+                lo
+                ./examples/test/args.test.lo
+                --eval
+
+                `
+            );
+        });
+
+        test("interprets wasi-env-clock.lo", async () => {
+            const res = await interpret("./examples/test/wasi-env-clock.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                m`
+                environ: ok
+                clock: ok
+
+                `
+            );
+        });
+
+        test("evaluates slice before its range in slice-bounds.lo", async () => {
+            const res = await interpret("./examples/test/slice-bounds.lo");
+            assert.strictEqual(res.toString("utf-8"), "items at at 2\n0\n");
+        });
+
+        test("returns EFAULT for pointers outside of memory", async () => {
+            const res = await interpret("./examples/test/wasi-bad-pointers.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                m`
+                environ: fault
+                clock: fault
+
+                `
+            );
+        });
+
+        // TODO: provide a way to pass own args to --eval
+        test("interprets cat.lo", async () => {
+            try {
+                await interpret("./examples/test/demos/cat.lo");
+            } catch (err) {
+                assert.strictEqual(err.message, "Usage cat.lo <file>");
+            }
+        });
+
+        test("interprets eval-imports.lo", async () => {
+            const res = await interpretWith(
+                "./examples/test/eval-imports.lo",
+                "--import",
+                "env::log=utils::debug",
+                "--import",
+                "env::*=noop"
+            );
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "result of `main` is: 42\n"
+            );
+        });
+
+        test("interprets add.lo with --invoke", async () => {
+            const res = await invoke("./examples/test/add.lo", "add", "2", "3");
+            assert.strictEqual(res.toString("utf-8"), "result of `add` is: 5\n");
+        });
+
+        test("counts instructions and calls of bench.lo", async () => {
+            const res = await compileFile(
+                "./examples/test/bench.lo",
+                "--eval",
+                "--bench"
+            );
+            assert.strictEqual(
+                res.toString("utf-8"),
+                m`
+                bench_fib: 1769 instrs
+                  177 fib
+                    1 bench_fib
+                bench_sum: 16562 instrs
+                  2 sum_to
+                  1 bench_sum
+
+                `
+            );
+        });
+
+        test("interprets numeric-ops.lo", async () => {
+            /** @param {string[]} invokeArgs */
+            const invokeNumericOp = async (...invokeArgs) =>
+                (
+                    await invoke("./examples/test/numeric-ops.lo", ...invokeArgs)
+                ).toString("utf-8");
+
+            const results = [
+                ["div_s", "-7", "2", "-3"],
+                ["rem_s", "-7", "2", "-1"],
+                ["shl", "1", "33", "2"],
+                ["shr_s", "-8", "1", "-4"],
+                ["lt_s", "-1", "1", "1"],
+                ["add_i64", "9223372036854775807", "1", "-9223372036854775808"],
+                ["div_u64", "18446744073709551615", "2", "9223372036854775807"],
+                ["rem_i64", "-9223372036854775808", "-1", "0"],
+                ["add_f64", "0.1", "0.2", "0.30000000000000004"],
+                ["div_f32", "1", "0", "inf"],
+                ["lt_f64", "NaN", "1", "0"],
+            ];
+            for (const [fnName, lhs, rhs, result] of results) {
+                assert.strictEqual(
+                    await invokeNumericOp(fnName, lhs, rhs),
+                    `result of \`${fnName}\` is: ${result}\n`
+                );
+            }
+
+            await assert.rejects(invokeNumericOp("div_s", "-2147483648", "-1"), {
+                message: "Integer overflow\n  at div_s\n",
+            });
+            await assert.rejects(invokeNumericOp("div_u64", "1", "0"), {
+                message: "Integer divide by zero\n  at div_u64\n",
+            });
+        });
+
+        test("interprets int64-literals.lo", async () => {
+
+            const results = [
+                ["i64_edges", "16381"],
+                ["i64_min", "-9223372036854775808"],
+                ["i64_max", "9223372036854775807"],
+                ["u64_max", "-1"],
+                ["above_i32", "2147483648"],
+                ["below_i32", "-2147483649"],
+                ["i32_min", "-2147483648"],
+                ["u32_max", "-1"],
+            ];
+            for (const [fnName, result] of results) {
+                assert.strictEqual(
+                    (
+                        await invoke("./examples/test/int64-literals.lo", fnName)
+                    ).toString("utf-8"),
+                    `result of \`${fnName}\` is: ${result}\n`
+                );
+            }
+        });
+
+        test("rejects negated literals not fitting into 64 bits", async () => {
+            await expectRejection(
+                "export fn f(): i64 { return -9223372036854775809; };",
+                "<stdin>:1:30 - Integer literal -9223372036854775809 is out of range for i64, " +
+                    "allowed range is -9223372036854775808..=9223372036854775807\n"
+            );
+        });
+
+        test("aligns data-alignment.lo and moves it with --data-start", async () => {
+            const res = await interpret("./examples/test/data-alignment.lo");
+            assert.strictEqual(res.toString("utf-8"), "12\naligned\n");
+
+            const moved = await compileFile(
+                "./examples/test/data-alignment.lo",
+                "--eval",
+                "--data-start",
+                "100"
+            );
+            assert.strictEqual(moved.toString("utf-8"), "112\naligned\n");
+        });
+
+        test("interprets sret.lo", async () => {
+            const res = await interpret("./examples/test/sret.lo");
+            assert.strictEqual(res.toString("utf-8"), "25\n13\n16\n6\n1\n");
+        });
+
+        test("interprets atomics.lo", async () => {
+            const res = await interpretWith(
+                "./examples/test/atomics.lo",
+                "--features",
+                "threads"
+            );
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "40 42\n10 11\n999 7\n0 1 1\n1 2 0\n"
+            );
+        });
+
+        test("interprets tail-calls.lo", async () => {
+            const res = await interpretWith(
+                "./examples/test/tail-calls.lo",
+                "--features",
+                "tail-call"
+            );
+            assert.strictEqual(res.toString("utf-8"), "1 0\n500000500000\n1000000\n");
+        });
+
+        test("interprets stack-locals.lo", async () => {
+            const res = await interpret("./examples/test/stack-locals.lo");
+            assert.strictEqual(res.toString("utf-8"), "2 1\n15 25\n55\n");
+        });
+
+        test("interprets string-pooling.lo", async () => {
+            const res = await interpret("./examples/test/string-pooling.lo");
+            assert.strictEqual(res.toString("utf-8"), "14\n");
+        });
+
+        test("interprets tracing.lo", async () => {
+            const res = await interpret("./examples/test/tracing.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                "examples/test/tracing.lo:4:10 - hello there\n"
+            );
+        });
+
+        test("interprets struct-in-struct.lo", async () => {
+            const res = await interpret("./examples/test/struct-in-struct.lo");
+            assert.strictEqual(res.toString("utf-8"), "3\n3\n3\n3\n3\n3\n3\n");
+        });
+
+        test("interprets heap-alloc.lo", async () => {
+            const res = await interpret("./examples/test/heap-alloc.lo");
+            assert.strictEqual(
+                res.toString("utf-8"),
+                m`
+                p1 = 1048600
+                p2 = 1048600
+                p3 = 1048612
+
+                `
+            );
+        });
+
+        test("interprets defer.lo", async () => {
+            const res = await interpret("./examples/test/defer.lo");
             assert.strictEqual(
                 res.toString("utf-8"),
                 m`
@@ -1123,6 +3825,114 @@ async function testCommand() {
         }
     }
 
+    /** @typedef {{ readStderr?: boolean, mergeStderr?: boolean, ignoreExitCode?: boolean, stdin?: string, env?: Record<string, string> }} CompileOptions */
+
+    /** @type {Map<string, Promise<Compile>>} */
+    const compilers = new Map();
+
+    /**
+     * Loads the compiler once per `args` and `options`, `args` go after the input file
+     *
+     * @param {string[]} args
+     * @param {CompileOptions & { mockStdin?: boolean }} options
+     */
+    function getCompiler(args, options) {
+        const key = JSON.stringify([args, options]);
+        let compiler = compilers.get(key);
+        if (compiler === undefined) {
+            compiler = fs.readFile(COMPILER_PATH).then((binary) =>
+                loadCompilerWithWasiAPI(binary, {
+                    ...options,
+                    buildArgs: (fileName) => ["lo", fileName ?? "-i", ...args],
+                })
+            );
+            compilers.set(key, compiler);
+        }
+        return compiler;
+    }
+
+    /**
+     * @param {string} fileName
+     * @param {string[]} args
+     * @param {CompileOptions} [options]
+     */
+    async function compileFileWith(fileName, args, options = {}) {
+        const compile = await getCompiler(args, options);
+        return compile(fileName);
+    }
+
+    /**
+     * @param {string} fileName
+     * @param {string[]} args
+     */
+    function compileFile(fileName, ...args) {
+        return compileFileWith(fileName, args);
+    }
+
+    /** @type {Promise<Compile> | undefined} */
+    let commandLineCompiler;
+
+    /**
+     * Runs the compiler with `args` as the whole command line
+     *
+     * @param {string[]} args
+     */
+    async function runCompiler(...args) {
+        commandLineCompiler ??= fs.readFile(COMPILER_PATH).then((binary) =>
+            loadCompilerWithWasiAPI(binary, {
+                buildArgs: (args) => ["lo", ...JSON.parse(args ?? "[]")],
+            })
+        );
+        const compile = await commandLineCompiler;
+        return compile(JSON.stringify(args));
+    }
+
+    /**
+     * Compiles `source` from a tmp file, `args` are appended to the command line
+     *
+     * @param {string} source
+     * @param {string[]} args
+     * @param {CompileOptions} [options]
+     */
+    async function compileSourceWith(source, args, options = {}) {
+        const compile = await getCompiler(args, { ...options, mockStdin: true });
+
+        return runWithTmpFile(async (_, sourceFile) => {
+            await fs.writeFile(sourceFile, source);
+            return compile(sourceFile);
+        });
+    }
+
+    /**
+     * @param {string} source
+     * @param {string[]} args
+     */
+    function compileSource(source, ...args) {
+        return compileSourceWith(source, args);
+    }
+
+    /**
+     * Returns stderr (warnings, reports) of a successful compilation
+     *
+     * @param {string} source
+     * @param {string[]} args
+     */
+    async function stderrOf(source, ...args) {
+        const stderr = await compileSourceWith(source, args, {
+            readStderr: true,
+        });
+        return stderr.toString();
+    }
+
+    /**
+     * @param {string} source
+     * @param {string} message
+     * @param {string[]} args
+     */
+    async function expectRejection(source, message, ...args) {
+        await assert.rejects(compileSource(source, ...args), { message });
+    }
+
     /** @typedef {(sourcePath: string) => Promise<Buffer>} Compile */
 
     /**
@@ -1133,6 +3943,11 @@ async function testCommand() {
         compilerWasmBinary,
         {
             mockStdin = false,
+            stdin = /** @type {string | undefined} */ (undefined),
+            ignoreExitCode = false,
+            readStderr = false,
+            mergeStderr = false,
+            env = /** @type {Record<string, string>} */ ({}),
             buildArgs = (fileName) => ["lo", fileName ?? "-i"],
         } = {}
     ) {
@@ -1149,8 +3964,9 @@ async function testCommand() {
                         version: "preview1",
                         stdin: stdinFd,
                         stdout: stdout.fd,
-                        stderr: stderr.fd,
+                        stderr: mergeStderr ? stdout.fd : stderr.fd,
                         args: buildArgs(fileName),
+                        env,
                         preopens: { ".": "." },
                     });
 
@@ -1165,13 +3981,16 @@ async function testCommand() {
                             wasi.start(instance)
                         );
 
-                        if (exitCode ?? 0 !== 0) {
+                        if (!ignoreExitCode && (exitCode ?? 0 !== 0)) {
                             throw new Error(
-                                await fs.readFile(stderrFile, "utf-8")
+                                (await fs.readFile(stderrFile, "utf-8")) ||
+                                    `Exited with code ${exitCode}`
                             );
                         }
 
-                        return fs.readFile(stdoutFile);
+                        return fs.readFile(
+                            readStderr ? stderrFile : stdoutFile
+                        );
                     } catch (err) {
                         const errorMessage = await fs.readFile(
                             stderrFile,
@@ -1197,6 +4016,14 @@ async function testCommand() {
          * @param {string} sourcePath
          */
         return async (sourcePath) => {
+            if (stdin !== undefined) {
+                return runWithTmpFile(async (stdinHandle, stdinFile) => {
+                    await fs.writeFile(stdinFile, stdin);
+
+                    return compile(sourcePath, stdinHandle.fd);
+                });
+            }
+
             if (!mockStdin) {
                 return compile(sourcePath);
             }
@@ -1258,6 +4085,29 @@ async function runWASI(data, wasiOptions, additionalImports = {}) {
     }
 }
 
+/**
+ * Compares test output with `.expected` file next to the source,
+ * `./utils.mjs test --update-snapshots` overwrites the file instead
+ * @param {string} sourceFile
+ * @param {string} output
+ * @param {boolean} update
+ */
+async function assertSnapshot(sourceFile, output, update) {
+    const snapshotFile = sourceFile.replace(/\.lo$/, ".expected");
+    if (update) {
+        await fs.writeFile(snapshotFile, output);
+        return;
+    }
+
+    const expected = await fs.readFile(snapshotFile, "utf-8");
+    assert.strictEqual(
+        output,
+        expected,
+        `Output differs from ${snapshotFile}, ` +
+            "run `./utils.mjs test --update-snapshots` if the change is expected"
+    );
+}
+
 /**
  * @template T
  * @param {(file: import("node:fs/promises").FileHandle, fileName: string) => T} run
@@ -44,7 +44,15 @@ export async function activate(context: vscode.ExtensionContext) {
         const compilerResult = await wasi.runWasiProgram({
             processName: "lo",
             cwdUri: workspaceUri,
-            args: [vscode.workspace.asRelativePath(document.uri), "--inspect"],
+            args: [
+                vscode.workspace.asRelativePath(document.uri),
+                "--inspect",
+                // bodies of included fns don't affect hovers and links of the document
+                "--inspect-range",
+                vscode.workspace.asRelativePath(document.uri),
+                "--columns",
+                "utf16",
+            ],
             module: ctx.compilerModule,
         });
         inspectLatency.measureAndLog(logChannel);